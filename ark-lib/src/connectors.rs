@@ -3,7 +3,7 @@
 use std::iter;
 
 use bitcoin::{
-	Address, Amount, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+	Address, Amount, FeeRate, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
 	Weight, Witness,
 };
 use bitcoin::secp256k1::{Keypair, PublicKey};
@@ -18,15 +18,25 @@ const TX_WEIGHT: Weight = Weight::from_vb_unchecked(154);
 pub const INPUT_WEIGHT: Weight = Weight::from_wu(66);
 
 
+/// The minimum value of a connector output so that it's still economical
+/// to spend it at the given feerate.
+///
+/// This is the p2tr dust value plus the fee for its input weight.
+pub fn min_connector_value(fee_rate: FeeRate) -> Amount {
+	fee::DUST + fee_rate * INPUT_WEIGHT
+}
+
+
 /// A chain of connector outputs.
 ///
 /// Each connector is a p2tr keyspend output for the provided key.
-/// Each connector has the p2tr dust value.
+/// Each connector has the same value, which should be at least the p2tr dust value.
 #[derive(Debug)]
 pub struct ConnectorChain {
 	len: usize,
 	spk: ScriptBuf,
 	utxo: OutPoint,
+	connector_value: Amount,
 }
 
 impl ConnectorChain {
@@ -37,13 +47,13 @@ impl ConnectorChain {
 	}
 
 	/// The budget needed for a chain of length [len] to pay for
-	/// - dust on 2 outputs per tx
+	/// - the connector value on 2 outputs per tx
 	/// - minrelayfee per tx
-	pub fn required_budget(len: usize) -> Amount {
+	pub fn required_budget(len: usize, connector_value: Amount) -> Amount {
 		assert_ne!(len, 0);
 
-		// We need n times dust for connectors.
-		fee::DUST * len as u64
+		// We need n times the connector value for connectors.
+		connector_value * len as u64
 		// Then we need minrelayfee to make sure we can pay for every tx in chain.
 		+ fee::RELAY_FEERATE * Self::total_weight(len)
 	}
@@ -59,10 +69,10 @@ impl ConnectorChain {
 	}
 
 	/// Create a connector output.
-	pub fn output(len: usize, pubkey: PublicKey) -> TxOut {
+	pub fn output(len: usize, pubkey: PublicKey, connector_value: Amount) -> TxOut {
 		TxOut {
 			script_pubkey: Self::output_script(pubkey),
-			value: Self::required_budget(len),
+			value: Self::required_budget(len, connector_value),
 		}
 	}
 
@@ -72,22 +82,33 @@ impl ConnectorChain {
 	/// as specified by [ConnectorChain::output_script] or [ConnectorChain::address].
 	/// The amount in this output is expected to be exaclty equal to
 	/// [ConnectorChain::required_budget].
-	pub fn new(len: usize, utxo: OutPoint, pubkey: PublicKey) -> ConnectorChain {
+	pub fn new(
+		len: usize,
+		utxo: OutPoint,
+		pubkey: PublicKey,
+		connector_value: Amount,
+	) -> ConnectorChain {
 		assert_ne!(len, 0);
 		let spk = Self::output_script(pubkey);
 
-		ConnectorChain { len, spk, utxo }
+		ConnectorChain { len, spk, utxo, connector_value }
 	}
 
 	pub fn len(&self) -> usize {
 		self.len
 	}
 
+	/// The value of each connector output.
+	pub fn connector_value(&self) -> Amount {
+		self.connector_value
+	}
+
 	/// Iterator over the signed transactions in this chain.
 	pub fn iter_signed_txs<'a>(&'a self, sign_key: &'a Keypair) -> ConnectorTxIter<'a> {
 		ConnectorTxIter {
 			len: self.len,
 			spk: &self.spk,
+			connector_value: self.connector_value,
 			sign_key: Some(sign_key.for_keyspend()),
 			prev: self.utxo,
 			idx: 0,
@@ -99,6 +120,7 @@ impl ConnectorChain {
 		ConnectorTxIter {
 			len: self.len,
 			spk: &self.spk,
+			connector_value: self.connector_value,
 			sign_key: None,
 			prev: self.utxo,
 			idx: 0,
@@ -117,6 +139,7 @@ impl ConnectorChain {
 pub struct ConnectorTxIter<'a> {
 	len: usize,
 	spk: &'a Script,
+	connector_value: Amount,
 	sign_key: Option<Keypair>,
 
 	prev: OutPoint,
@@ -143,11 +166,13 @@ impl<'a> iter::Iterator for ConnectorTxIter<'a> {
 			output: vec![
 				TxOut {
 					script_pubkey: self.spk.to_owned(),
-					value: ConnectorChain::required_budget(self.len - self.idx - 1),
+					value: ConnectorChain::required_budget(
+						self.len - self.idx - 1, self.connector_value,
+					),
 				},
 				TxOut {
 					script_pubkey: self.spk.to_owned(),
-					value: fee::DUST,
+					value: self.connector_value,
 				},
 			],
 		};
@@ -155,7 +180,7 @@ impl<'a> iter::Iterator for ConnectorTxIter<'a> {
 		if let Some(keypair) = self.sign_key {
 			let prevout = TxOut {
 				script_pubkey: self.spk.to_owned(),
-				value: ConnectorChain::required_budget(self.len - self.idx),
+				value: ConnectorChain::required_budget(self.len - self.idx, self.connector_value),
 			};
 			let mut shc = SighashCache::new(&ret);
 			let sighash = shc.taproot_key_spend_signature_hash(
@@ -218,20 +243,21 @@ mod test {
 	fn test_budget() {
 		let key = Keypair::new(&util::SECP, &mut rand::thread_rng());
 		let utxo = OutPoint::new(Txid::all_zeros(), 0);
+		let value = Amount::from_sat(1_000);
 
-		let chain = ConnectorChain::new(1, utxo, key.public_key());
+		let chain = ConnectorChain::new(1, utxo, key.public_key(), value);
 		assert_eq!(chain.connectors().count(), 1);
 		assert_eq!(chain.iter_unsigned_txs().count(), 0);
 		assert_eq!(chain.connectors().next().unwrap(), utxo);
 
-		let chain = ConnectorChain::new(2, utxo, key.public_key());
+		let chain = ConnectorChain::new(2, utxo, key.public_key(), value);
 		assert_eq!(chain.connectors().count(), 2);
 		assert_eq!(chain.iter_unsigned_txs().count(), 1);
 		assert_eq!(chain.iter_signed_txs(&key).count(), 1);
 		let tx = chain.iter_signed_txs(&key).next().unwrap();
 		assert_eq!(TX_WEIGHT, tx.weight());
 
-		let chain = ConnectorChain::new(100, utxo, key.public_key());
+		let chain = ConnectorChain::new(100, utxo, key.public_key(), value);
 		assert_eq!(chain.connectors().count(), 100);
 		assert_eq!(chain.iter_unsigned_txs().count(), 99);
 		assert_eq!(chain.iter_signed_txs(&key).count(), 99);
@@ -241,18 +267,26 @@ mod test {
 		}
 		let weight = chain.iter_signed_txs(&key).map(|t| t.weight()).sum::<Weight>();
 		assert_eq!(weight, ConnectorChain::total_weight(100));
-		chain.iter_unsigned_txs().for_each(|t| assert_eq!(t.output[1].value, fee::DUST));
-		assert_eq!(fee::DUST, chain.iter_unsigned_txs().last().unwrap().output[0].value);
+		chain.iter_unsigned_txs().for_each(|t| assert_eq!(t.output[1].value, value));
+		assert_eq!(value, chain.iter_unsigned_txs().last().unwrap().output[0].value);
 
 		let total_value = chain.iter_unsigned_txs().map(|t| t.output[1].value).sum::<Amount>()
 			+ chain.iter_unsigned_txs().last().unwrap().output[0].value
 			+ fee::RELAY_FEERATE * weight;
-		assert_eq!(ConnectorChain::required_budget(100), total_value);
+		assert_eq!(ConnectorChain::required_budget(100, value), total_value);
 
 		// random checks
 		let mut txs = chain.iter_unsigned_txs();
-		assert_eq!(txs.next().unwrap().output[0].value, ConnectorChain::required_budget(99));
-		assert_eq!(txs.next().unwrap().output[0].value, ConnectorChain::required_budget(98));
+		assert_eq!(txs.next().unwrap().output[0].value, ConnectorChain::required_budget(99, value));
+		assert_eq!(txs.next().unwrap().output[0].value, ConnectorChain::required_budget(98, value));
+	}
+
+	#[test]
+	fn test_min_connector_value() {
+		assert_eq!(min_connector_value(FeeRate::ZERO), fee::DUST);
+		// 66 WU at 10 sat/vb is 165 sat
+		let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+		assert_eq!(min_connector_value(fee_rate), fee::DUST + Amount::from_sat(165));
 	}
 
 	#[test]
@@ -262,11 +296,11 @@ mod test {
 		let spk = ConnectorChain::output_script(key.public_key());
 
 		let mut n = 10;
-		let chain = ConnectorChain::new(n, utxo, key.public_key());
+		let chain = ConnectorChain::new(n, utxo, key.public_key(), fee::DUST);
 		for tx in chain.iter_signed_txs(&key) {
 			bitcoinconsensus::verify(
 				spk.as_bytes(),
-				ConnectorChain::required_budget(n).to_sat(),
+				ConnectorChain::required_budget(n, fee::DUST).to_sat(),
 				&bitcoin::consensus::serialize(&tx),
 				0,
 			).expect("verification failed");
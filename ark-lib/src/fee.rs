@@ -1,5 +1,5 @@
 
-use bitcoin::{opcodes, Amount, FeeRate, ScriptBuf, TxOut, Witness};
+use bitcoin::{opcodes, Amount, FeeRate, ScriptBuf, TxOut, Weight, Witness};
 
 /// The minimum feerate for transaction relay.
 ///
@@ -17,6 +17,18 @@ pub const DUST: Amount = Amount::from_sat(330);
 /// The size in bytes of a dust fee anchor created with [dust_anchor].
 pub const DUST_ANCHOR_SIZE: usize = 43;
 
+/// The satisfaction weight of a dust fee anchor input.
+///
+/// The witness is the single-byte OP_TRUE witness script: one byte for the
+/// number of items, one for the length of the script and the script itself.
+pub const DUST_ANCHOR_SATISFACTION_WEIGHT: Weight = Weight::from_wu(3);
+
+/// Dust value of 240 satoshis for pay-to-anchor outputs.
+pub const P2A_DUST: Amount = Amount::from_sat(240);
+
+/// The witness program of pay-to-anchor outputs.
+const P2A_PROGRAM: [u8; 2] = [0x4e, 0x73];
+
 /// The Script that holds only the OP_TRUE opcode.
 fn op_true_script() -> ScriptBuf {
 	ScriptBuf::from_bytes(vec![opcodes::OP_TRUE.to_u8()])
@@ -49,6 +61,24 @@ pub fn dust_anchor_witness() -> Witness {
 	DUST_ANCHOR_WITNESS.clone()
 }
 
+/// A keyless pay-to-anchor (P2A) output with the P2A dust amount.
+///
+/// These are standard to spend with an empty witness since Bitcoin Core 28.
+pub fn p2a_anchor() -> TxOut {
+	lazy_static! {
+		static ref P2A_ANCHOR: TxOut = TxOut {
+			script_pubkey: {
+				let mut spk = vec![opcodes::all::OP_PUSHNUM_1.to_u8(), P2A_PROGRAM.len() as u8];
+				spk.extend_from_slice(&P2A_PROGRAM);
+				ScriptBuf::from_bytes(spk)
+			},
+			value: P2A_DUST,
+		};
+	}
+
+	P2A_ANCHOR.clone()
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -57,4 +87,15 @@ mod test {
 	fn test_dust_fee_anchor_size() {
 		assert_eq!(DUST_ANCHOR_SIZE, bitcoin::consensus::serialize(&dust_anchor()).len());
 	}
+
+	#[test]
+	fn test_dust_anchor_satisfaction_weight() {
+		let weight = Weight::from_wu(dust_anchor_witness().size() as u64);
+		assert_eq!(DUST_ANCHOR_SATISFACTION_WEIGHT, weight);
+	}
+
+	#[test]
+	fn test_p2a_anchor_script() {
+		assert_eq!(p2a_anchor().script_pubkey.as_bytes(), &[0x51, 0x02, 0x4e, 0x73]);
+	}
 }
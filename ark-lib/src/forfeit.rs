@@ -1,9 +1,9 @@
 
 
-use bitcoin::{OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness};
 use bitcoin::sighash::{self, SighashCache, TapSighash, TapSighashType};
 
-use crate::{fee, util, Vtxo};
+use crate::{fee, Vtxo};
 use crate::connectors::ConnectorChain;
 
 
@@ -46,14 +46,20 @@ pub fn create_forfeit_tx(vtxo: &Vtxo, connector: OutPoint) -> Transaction {
 		output: vec![
 			TxOut {
 				value: leftover,
-				script_pubkey: ScriptBuf::new_p2tr(&util::SECP, vtxo.spec().combined_pubkey(), None),
+				script_pubkey: vtxo.spec().script_type.forfeit_spk(vtxo.spec().combined_pubkey()),
 			},
 			fee::dust_anchor(),
 		],
 	}
 }
 
-pub fn forfeit_sighash(vtxo: &Vtxo, connector: OutPoint) -> (TapSighash, Transaction) {
+/// The sighash of the forfeit tx of the given vtxo, for a connector of the
+/// given value.
+pub fn forfeit_sighash(
+	vtxo: &Vtxo,
+	connector: OutPoint,
+	connector_value: Amount,
+) -> (TapSighash, Transaction) {
 	let spec = vtxo.spec();
 	let exit_spk = spec.exit_spk();
 	let exit_prevout = TxOut {
@@ -62,7 +68,7 @@ pub fn forfeit_sighash(vtxo: &Vtxo, connector: OutPoint) -> (TapSighash, Transac
 	};
 	let connector_prevout = TxOut {
 		script_pubkey: ConnectorChain::output_script(spec.asp_pubkey),
-		value: fee::DUST,
+		value: connector_value,
 	};
 	let tx = create_forfeit_tx(vtxo, connector);
 	let sighash = SighashCache::new(&tx).taproot_key_spend_signature_hash(
@@ -9,6 +9,9 @@ pub mod lightning;
 pub mod musig;
 pub mod onboard;
 pub mod oor;
+pub mod psbtext;
+#[doc(hidden)]
+pub mod test_util;
 pub mod tree;
 pub mod util;
 #[cfg(test)]
@@ -94,12 +97,26 @@ impl OffboardRequest {
 		Some(fee_rate * Weight::from_vb(vb).expect("no overflow"))
 	}
 
-	/// Validate that the offboard has a valid script.
-	pub fn validate(&self) -> Result<(), &'static str> {
-		if Self::calculate_fee(&self.script_pubkey, FeeRate::ZERO).is_none() {
-			Err("invalid script")
+	/// The minimum amount of an offboard to the given script, so that its
+	/// output is not dust.
+	///
+	/// Returns [None] for non-standard scripts.
+	pub fn dust_limit(script: &Script) -> Option<Amount> {
+		if script.is_op_return() {
+			// OP_RETURN outputs are never dust.
+			Some(Amount::ZERO)
 		} else {
-			Ok(())
+			Self::calculate_fee(script, FeeRate::from_sat_per_vb_unchecked(3))
+		}
+	}
+
+	/// Validate that the offboard has a standard script and an amount
+	/// that's not dust.
+	pub fn validate(&self) -> Result<(), &'static str> {
+		match Self::dust_limit(&self.script_pubkey) {
+			None => Err("invalid script"),
+			Some(dust) if self.amount < dust => Err("amount is below the dust limit"),
+			Some(_) => Ok(()),
 		}
 	}
 
@@ -213,30 +230,205 @@ impl<'de> serde::Deserialize<'de> for VtxoId {
 	}
 }
 
+/// The kind of timelock used in the exit clause of vtxos.
+///
+/// The ASP and users need to agree on the type, the exit clause script
+/// is different for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitTimelockType {
+	/// A relative timelock (CSV) of the exit delta, starting when the vtxo
+	/// output confirms.
+	Relative,
+	/// An absolute timelock (CLTV) at the vtxo's expiry height plus the exit
+	/// delta, regardless of when the vtxo output confirms.
+	Absolute,
+}
+
+impl ExitTimelockType {
+	/// The exit timelock for a vtxo with the given exit delta and expiry height.
+	pub fn timelock(self, exit_delta: u16, expiry_height: u32) -> ExitTimelock {
+		match self {
+			ExitTimelockType::Relative => ExitTimelock::Relative(exit_delta),
+			ExitTimelockType::Absolute => {
+				ExitTimelock::Absolute(expiry_height + exit_delta as u32)
+			},
+		}
+	}
+}
+
+impl Default for ExitTimelockType {
+	fn default() -> Self {
+		ExitTimelockType::Relative
+	}
+}
+
+impl fmt::Display for ExitTimelockType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ExitTimelockType::Relative => f.write_str("relative"),
+			ExitTimelockType::Absolute => f.write_str("absolute"),
+		}
+	}
+}
+
+impl FromStr for ExitTimelockType {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"relative" => Ok(ExitTimelockType::Relative),
+			"absolute" => Ok(ExitTimelockType::Absolute),
+			_ => Err(format!("unknown exit timelock type: {}", s)),
+		}
+	}
+}
+
+/// The type of the output scripts of vtxos.
+///
+/// This determines how the exit, expiry and forfeit scripts of vtxos are
+/// built, so the ASP and users need to agree on the type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VtxoScriptType {
+	/// Taproot outputs with the musig-combined key of the owners as internal
+	/// key and the exit or expiry clause as the only script.
+	#[default]
+	Taproot,
+}
+
+impl VtxoScriptType {
+	/// The clause with which the user can claim a vtxo in a unilateral exit.
+	pub fn exit_clause(self, user_pubkey: PublicKey, timelock: ExitTimelock) -> ScriptBuf {
+		match self {
+			VtxoScriptType::Taproot => exit_clause(user_pubkey, timelock),
+		}
+	}
+
+	/// The taproot of a vtxo output with the exit clause of the user.
+	pub fn exit_taproot(
+		self,
+		user_pubkey: PublicKey,
+		asp_pubkey: PublicKey,
+		timelock: ExitTimelock,
+	) -> taproot::TaprootSpendInfo {
+		match self {
+			VtxoScriptType::Taproot => exit_taproot(user_pubkey, asp_pubkey, timelock),
+		}
+	}
+
+	/// The scriptPubkey of a vtxo output with the exit clause of the user.
+	pub fn exit_spk(
+		self,
+		user_pubkey: PublicKey,
+		asp_pubkey: PublicKey,
+		timelock: ExitTimelock,
+	) -> ScriptBuf {
+		match self {
+			VtxoScriptType::Taproot => exit_spk(user_pubkey, asp_pubkey, timelock),
+		}
+	}
+
+	/// The clause with which the ASP can sweep an output after it expired.
+	pub fn expiry_clause(self, asp_pubkey: PublicKey, expiry_height: u32) -> ScriptBuf {
+		match self {
+			VtxoScriptType::Taproot => {
+				util::timelock_sign(expiry_height, asp_pubkey.x_only_public_key().0)
+			},
+		}
+	}
+
+	/// The taproot of an output of the given key that the ASP can sweep
+	/// after it expired, like the onboard and vtxo tree outputs.
+	pub fn expiry_taproot(
+		self,
+		internal_key: XOnlyPublicKey,
+		asp_pubkey: PublicKey,
+		expiry_height: u32,
+	) -> taproot::TaprootSpendInfo {
+		match self {
+			VtxoScriptType::Taproot => bitcoin::taproot::TaprootBuilder::new()
+				.add_leaf(0, self.expiry_clause(asp_pubkey, expiry_height)).unwrap()
+				.finalize(&util::SECP, internal_key).unwrap(),
+		}
+	}
+
+	/// The scriptPubkey of the output of a forfeit tx of a vtxo with the
+	/// given combined key.
+	pub fn forfeit_spk(self, combined_pubkey: XOnlyPublicKey) -> ScriptBuf {
+		match self {
+			VtxoScriptType::Taproot => ScriptBuf::new_p2tr(&util::SECP, combined_pubkey, None),
+		}
+	}
+}
+
+impl fmt::Display for VtxoScriptType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			VtxoScriptType::Taproot => f.write_str("taproot"),
+		}
+	}
+}
+
+impl FromStr for VtxoScriptType {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"taproot" => Ok(VtxoScriptType::Taproot),
+			_ => Err(format!("unknown vtxo script type: {}", s)),
+		}
+	}
+}
+
+/// The timelock in the exit clause of a vtxo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExitTimelock {
+	/// Claimable this number of blocks after the vtxo output confirmed.
+	Relative(u16),
+	/// Claimable from this block height on.
+	Absolute(u32),
+}
+
+impl ExitTimelock {
+	/// The height from which the exit can be claimed, given the height at
+	/// which the vtxo output confirmed.
+	pub fn claimable_height(&self, confirmed_height: u32) -> u32 {
+		match self {
+			ExitTimelock::Relative(delta) => confirmed_height + *delta as u32,
+			ExitTimelock::Absolute(height) => *height,
+		}
+	}
+}
+
 pub fn exit_clause(
 	user_pubkey: PublicKey,
-	exit_delta: u16,
+	timelock: ExitTimelock,
 ) -> ScriptBuf {
-	util::delayed_sign(exit_delta, user_pubkey.x_only_public_key().0)
+	let pk = user_pubkey.x_only_public_key().0;
+	match timelock {
+		ExitTimelock::Relative(delta) => util::delayed_sign(delta, pk),
+		ExitTimelock::Absolute(height) => util::timelock_sign(height, pk),
+	}
 }
 
 pub fn exit_taproot(
 	user_pubkey: PublicKey,
 	asp_pubkey: PublicKey,
-	exit_delta: u16,
+	timelock: ExitTimelock,
 ) -> taproot::TaprootSpendInfo {
 	let combined_pk = musig::combine_keys([user_pubkey, asp_pubkey]);
 	bitcoin::taproot::TaprootBuilder::new()
-		.add_leaf(0, exit_clause(user_pubkey, exit_delta)).unwrap()
+		.add_leaf(0, exit_clause(user_pubkey, timelock)).unwrap()
 		.finalize(&util::SECP, combined_pk).unwrap()
 }
 
 pub fn exit_spk(
 	user_pubkey: PublicKey,
 	asp_pubkey: PublicKey,
-	exit_delta: u16,
+	timelock: ExitTimelock,
 ) -> ScriptBuf {
-	let taproot = exit_taproot(user_pubkey, asp_pubkey, exit_delta);
+	let taproot = exit_taproot(user_pubkey, asp_pubkey, timelock);
 	ScriptBuf::new_p2tr_tweaked(taproot.output_key())
 }
 
@@ -252,6 +444,10 @@ pub struct VtxoSpec {
 	/// be added.
 	#[serde(with = "bitcoin::amount::serde::as_sat")]
 	pub amount: Amount,
+	#[serde(default)]
+	pub exit_timelock_type: ExitTimelockType,
+	#[serde(default)]
+	pub script_type: VtxoScriptType,
 }
 
 impl VtxoSpec {
@@ -260,20 +456,29 @@ impl VtxoSpec {
 		musig::combine_keys([self.user_pubkey, self.asp_pubkey])
 	}
 
+	pub fn exit_timelock(&self) -> ExitTimelock {
+		self.exit_timelock_type.timelock(self.exit_delta, self.expiry_height)
+	}
+
 	pub fn exit_clause(&self) -> ScriptBuf {
-		exit_clause(self.user_pubkey, self.exit_delta)
+		self.script_type.exit_clause(self.user_pubkey, self.exit_timelock())
 	}
 
 	pub fn exit_taproot(&self) -> taproot::TaprootSpendInfo {
-		exit_taproot(self.user_pubkey, self.asp_pubkey, self.exit_delta)
+		self.script_type.exit_taproot(self.user_pubkey, self.asp_pubkey, self.exit_timelock())
 	}
 
 	pub fn exit_taptweak(&self) -> taproot::TapTweakHash {
-		exit_taproot(self.user_pubkey, self.asp_pubkey, self.exit_delta).tap_tweak()
+		self.exit_taproot().tap_tweak()
 	}
 
 	pub fn exit_spk(&self) -> ScriptBuf {
-		exit_spk(self.user_pubkey, self.asp_pubkey, self.exit_delta)
+		self.script_type.exit_spk(self.user_pubkey, self.asp_pubkey, self.exit_timelock())
+	}
+
+	/// The clause with which the ASP can sweep the vtxo after it expired.
+	pub fn expiry_clause(&self) -> ScriptBuf {
+		self.script_type.expiry_clause(self.asp_pubkey, self.expiry_height)
 	}
 }
 
@@ -411,6 +616,63 @@ impl Vtxo {
 		}
 	}
 
+	/// Check that the exit path of this vtxo is complete and consistent.
+	///
+	/// All txs from the onchain utxo up to the vtxo output have to be present,
+	/// each spending the one before, and signed. The signatures themselves
+	/// are not validated.
+	pub fn validate_exit_path(&self) -> Result<(), &'static str> {
+		fn signed(tx: &Transaction) -> bool {
+			tx.input.iter().all(|i| !i.witness.is_empty())
+		}
+
+		match self {
+			// The reveal tx is built from the spec and spends the onboard utxo.
+			Vtxo::Onboard { .. } => {},
+			Vtxo::Round { base, exit_branch, .. } => {
+				let first = exit_branch.first().ok_or("empty exit branch")?;
+				if first.input.len() != 1 || first.input[0].previous_output != base.utxo {
+					return Err("exit branch doesn't start at the round utxo");
+				}
+				for pair in exit_branch.windows(2) {
+					let (parent, child) = (&pair[0], &pair[1]);
+					let linked = child.input.len() == 1 && {
+						let prev = child.input[0].previous_output;
+						prev.txid == parent.compute_txid() && (prev.vout as usize) < parent.output.len()
+					};
+					if !linked {
+						return Err("exit branch is missing a tx");
+					}
+				}
+				if !exit_branch.iter().all(signed) {
+					return Err("exit branch has an unsigned tx");
+				}
+				if exit_branch.last().unwrap().output.first() != Some(&self.txout()) {
+					return Err("exit branch doesn't end in the vtxo");
+				}
+			},
+			Vtxo::Oor { inputs, oor_tx: tx, final_point, .. }
+				| Vtxo::Bolt11Change { inputs, htlc_tx: tx, final_point, .. } =>
+			{
+				for input in inputs {
+					input.validate_exit_path()?;
+				}
+				if tx.input.len() != inputs.len() || !inputs.iter().all(|input| {
+					tx.input.iter().any(|i| i.previous_output == input.point())
+				}) {
+					return Err("tx doesn't spend the input vtxos");
+				}
+				if !signed(tx) {
+					return Err("tx is unsigned");
+				}
+				if final_point.txid != tx.compute_txid() || final_point.vout as usize >= tx.output.len() {
+					return Err("tx doesn't create the vtxo");
+				}
+			},
+		}
+		Ok(())
+	}
+
 	pub fn is_onboard(&self) -> bool {
 		match self {
 			Vtxo::Onboard { .. } => true,
@@ -458,6 +720,24 @@ mod test {
 	use super::*;
 	use bitcoin::hashes::hex::FromHex;
 
+	#[test]
+	fn offboard_validation() {
+		let offb = |script_pubkey: ScriptBuf, sat: u64| OffboardRequest {
+			script_pubkey, amount: Amount::from_sat(sat),
+		};
+		let p2wpkh = ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+		let p2wsh = ScriptBuf::new_p2wsh(&bitcoin::WScriptHash::all_zeros());
+
+		assert_eq!(OffboardRequest::dust_limit(&p2wpkh), Some(P2WPKH_DUST));
+		offb(p2wpkh.clone(), P2WPKH_DUST_SAT).validate().unwrap();
+		offb(p2wpkh, P2WPKH_DUST_SAT - 1).validate().unwrap_err();
+		offb(p2wsh.clone(), 330).validate().unwrap();
+		offb(p2wsh, 329).validate().unwrap_err();
+		offb(ScriptBuf::new_op_return(&[1, 2, 3]), 0).validate().unwrap();
+		// Non-standard scripts are refused whatever the amount.
+		offb(ScriptBuf::from_bytes(vec![0x51]), 100_000).validate().unwrap_err();
+	}
+
 	#[test]
 	fn vtxo_roundtrip() {
 		let pk = "034b56997a369b627dae1621c603bbf2466b8369b37724cc902c5f1b434fc6a38a".parse().unwrap();
@@ -473,6 +753,8 @@ mod test {
 					expiry_height: 15,
 					exit_delta: 7,
 					amount: Amount::from_sat(5),
+					exit_timelock_type: ExitTimelockType::Relative,
+					script_type: VtxoScriptType::Taproot,
 				},
 				utxo: point,
 			},
@@ -488,6 +770,8 @@ mod test {
 					expiry_height: 15,
 					exit_delta: 7,
 					amount: Amount::from_sat(5),
+					exit_timelock_type: ExitTimelockType::Relative,
+					script_type: VtxoScriptType::Taproot,
 				},
 				utxo: point,
 			},
@@ -507,6 +791,8 @@ mod test {
 				expiry_height: 15,
 				exit_delta: 7,
 				amount: Amount::from_sat(5),
+				exit_timelock_type: ExitTimelockType::Absolute,
+				script_type: VtxoScriptType::Taproot,
 			},
 			oor_tx: tx.clone(),
 			final_point: point,
@@ -525,10 +811,113 @@ mod test {
 				expiry_height: 15,
 				exit_delta: 7,
 				amount: Amount::from_sat(5),
+				exit_timelock_type: ExitTimelockType::Absolute,
+				script_type: VtxoScriptType::Taproot,
 			},
 			oor_tx: tx.clone(),
 			final_point: point,
 		};
 		assert_eq!(oor_recursive, Vtxo::decode(&oor_recursive.encode()).unwrap());
 	}
+
+	#[test]
+	fn exit_timelock_types() {
+		let pk = "034b56997a369b627dae1621c603bbf2466b8369b37724cc902c5f1b434fc6a38a".parse().unwrap();
+		let mut spec = VtxoSpec {
+			user_pubkey: pk,
+			asp_pubkey: pk,
+			expiry_height: 1_000,
+			exit_delta: 12,
+			amount: Amount::from_sat(5),
+			exit_timelock_type: ExitTimelockType::Relative,
+			script_type: VtxoScriptType::Taproot,
+		};
+		assert_eq!(spec.exit_timelock(), ExitTimelock::Relative(12));
+		assert_eq!(spec.exit_timelock().claimable_height(500), 512);
+		let relative_spk = spec.exit_spk();
+
+		spec.exit_timelock_type = ExitTimelockType::Absolute;
+		assert_eq!(spec.exit_timelock(), ExitTimelock::Absolute(1_012));
+		assert_eq!(spec.exit_timelock().claimable_height(500), 1_012);
+		assert_ne!(relative_spk, spec.exit_spk());
+
+		for t in [ExitTimelockType::Relative, ExitTimelockType::Absolute] {
+			assert_eq!(t, t.to_string().parse::<ExitTimelockType>().unwrap());
+		}
+	}
+
+	#[test]
+	fn exit_path_validation() {
+		use bitcoin::secp256k1::{rand, Keypair};
+		use crate::tree::signed::{OutputKeyPolicy, SignedVtxoTree, VtxoTreeSpec};
+
+		let asp_key = Keypair::new(&util::SECP, &mut rand::thread_rng());
+		let user_key = Keypair::new(&util::SECP, &mut rand::thread_rng());
+		let vtxos = (1..=20).map(|i| VtxoRequest {
+			pubkey: user_key.public_key(),
+			amount: Amount::from_sat(10_000 * i),
+		}).collect::<Vec<_>>();
+		let spec = VtxoTreeSpec::new(
+			vtxos.clone(),
+			musig::combine_keys([asp_key.public_key(), user_key.public_key()]),
+			asp_key.public_key(),
+			100_000,
+			2016,
+			false,
+			OutputKeyPolicy::MerkleRootTweak,
+			ExitTimelockType::Relative,
+			VtxoScriptType::Taproot,
+		);
+		let utxo = "0000000000000000000000000000000000000000000000000000000000000001:0".parse().unwrap();
+		// The signatures aren't validated, any will do.
+		let sig = schnorr::Signature::from_slice(&[1; 64]).unwrap();
+		let tree = SignedVtxoTree::new(spec.clone(), utxo, vec![sig; spec.nb_nodes()]);
+
+		let round_vtxo = |exit_branch: Vec<Transaction>| Vtxo::Round {
+			base: BaseVtxo {
+				spec: VtxoSpec {
+					user_pubkey: user_key.public_key(),
+					asp_pubkey: asp_key.public_key(),
+					expiry_height: 100_000,
+					exit_delta: 2016,
+					amount: vtxos[0].amount,
+					exit_timelock_type: ExitTimelockType::Relative,
+					script_type: VtxoScriptType::Taproot,
+				},
+				utxo,
+			},
+			leaf_idx: 0,
+			exit_branch,
+		};
+		let branch = tree.exit_branch(0).unwrap();
+		assert!(branch.len() > 2);
+		let vtxo = round_vtxo(branch.clone());
+		vtxo.validate_exit_path().unwrap();
+
+		// A branch missing its root or an intermediate tx can't be exited.
+		round_vtxo(branch[1..].to_vec()).validate_exit_path().unwrap_err();
+		let mut missing = branch.clone();
+		missing.remove(1);
+		assert_eq!(round_vtxo(missing).validate_exit_path(), Err("exit branch is missing a tx"));
+		// Neither can a branch with an unsigned tx.
+		let mut unsigned = branch.clone();
+		unsigned[1].input[0].witness = bitcoin::Witness::new();
+		assert_eq!(round_vtxo(unsigned).validate_exit_path(), Err("exit branch has an unsigned tx"));
+		// Or the branch of another leaf.
+		round_vtxo(tree.exit_branch(1).unwrap()).validate_exit_path().unwrap_err();
+
+		// An OOR vtxo can only be exited if its inputs can.
+		let oor = |input: Vtxo| {
+			let mut oor_tx = branch[0].clone();
+			oor_tx.input[0].previous_output = input.point();
+			Vtxo::Oor {
+				final_point: OutPoint::new(oor_tx.compute_txid(), 0),
+				inputs: vec![Box::new(input.clone())],
+				pseudo_spec: input.spec().clone(),
+				oor_tx,
+			}
+		};
+		oor(vtxo).validate_exit_path().unwrap();
+		oor(round_vtxo(branch[1..].to_vec())).validate_exit_path().unwrap_err();
+	}
 }
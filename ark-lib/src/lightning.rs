@@ -7,7 +7,7 @@ use bitcoin::secp256k1::{self, schnorr, Keypair, PublicKey, XOnlyPublicKey};
 use bitcoin::taproot::TaprootSpendInfo;
 use lightning_invoice::Bolt11Invoice;
 
-use crate::{fee, musig, util, Vtxo, VtxoSpec, P2TR_DUST_SAT};
+use crate::{fee, musig, util, ExitTimelockType, Vtxo, VtxoScriptType, VtxoSpec, P2TR_DUST_SAT};
 
 
 /// The minimum fee we consider for an HTLC transaction.
@@ -28,6 +28,10 @@ pub struct Bolt11Payment {
 	/// The expiration-height of the HTLC granted from client to ASP
 	pub htlc_expiry: u32,
 	pub exit_delta: u16,
+	#[serde(default)]
+	pub exit_timelock_type: ExitTimelockType,
+	#[serde(default)]
+	pub script_type: VtxoScriptType,
 }
 
 impl Bolt11Payment {
@@ -55,8 +59,14 @@ impl Bolt11Payment {
 		ScriptBuf::new_p2tr_tweaked(taproot.output_key())
 	}
 
+	/// The expiry height of the change vtxo, the soonest of all inputs.
+	pub fn change_expiry_height(&self) -> u32 {
+		self.inputs.iter().map(|i| i.spec().expiry_height).min().unwrap()
+	}
+
 	fn change_output(&self) -> TxOut {
-		let spk = crate::exit_spk(self.user_pubkey, self.asp_pubkey, self.exit_delta);
+		let timelock = self.exit_timelock_type.timelock(self.exit_delta, self.change_expiry_height());
+		let spk = self.script_type.exit_spk(self.user_pubkey, self.asp_pubkey, timelock);
 		TxOut {
 			value: self.change_amount(),
 			script_pubkey: spk,
@@ -201,14 +211,16 @@ impl Bolt11Payment {
 		(pub_nonces, part_sigs)
 	}
 
+	/// Sign the inputs with the user key of each input in `keypairs`.
 	pub fn sign_finalize_user(
 		self,
-		keypair: &Keypair,
+		keypairs: &[Keypair],
 		our_sec_nonces: Vec<musig::MusigSecNonce>,
 		our_pub_nonces: &[musig::MusigPubNonce],
 		asp_nonces: &[musig::MusigPubNonce],
 		asp_part_sigs: &[musig::MusigPartialSignature],
 	) -> SignedBolt11Payment {
+		assert_eq!(self.inputs.len(), keypairs.len());
 		assert_eq!(self.inputs.len(), our_sec_nonces.len());
 		assert_eq!(self.inputs.len(), our_pub_nonces.len());
 		assert_eq!(self.inputs.len(), asp_nonces.len());
@@ -217,6 +229,7 @@ impl Bolt11Payment {
 
 		let mut sigs = Vec::with_capacity(self.inputs.len());
 		for (idx, (input, sec_nonce)) in self.inputs.iter().zip(our_sec_nonces.into_iter()).enumerate() {
+			let keypair = &keypairs[idx];
 			assert_eq!(keypair.public_key(), input.spec().user_pubkey);
 			let agg_nonce = musig::nonce_agg([our_pub_nonces[idx], asp_nonces[idx]]);
 			let (_part_sig, final_sig) = musig::partial_sign(
@@ -287,7 +300,7 @@ impl SignedBolt11Payment {
 
 	pub fn change_vtxo(&self) -> Vtxo {
 		let tx = self.signed_transaction();
-		let expiry_height = self.payment.inputs.iter().map(|i| i.spec().expiry_height).min().unwrap();
+		let expiry_height = self.payment.change_expiry_height();
 		Vtxo::Bolt11Change {
 			inputs: self.payment.inputs.iter().map(|i| Box::new(i.clone())).collect(),
 			pseudo_spec: VtxoSpec {
@@ -296,6 +309,8 @@ impl SignedBolt11Payment {
 				expiry_height: expiry_height,
 				asp_pubkey: self.payment.asp_pubkey,
 				user_pubkey: self.payment.user_pubkey,
+				exit_timelock_type: self.payment.exit_timelock_type,
+				script_type: self.payment.script_type,
 			},
 			final_point: OutPoint::new(tx.compute_txid(), 1),
 			htlc_tx: tx,
@@ -2,16 +2,20 @@
 //! Onboard flow:
 //!
 //! * User starts by using the [new_user] function that crates the user's parts.
-//! * ASP does a deterministic sign and sends ASP part using [new_asp].
+//! * ASP does a deterministic sign and sends ASP part using [new_asp], or
+//!   signs with a nonce it generated beforehand using [new_asp_with_nonce].
+//! * User checks the ASP part using [verify_asp].
 //! * User also signs and combines sigs using [finish] and stores vtxo.
 
+use std::io;
+
 use bitcoin::{
 	taproot, Amount, OutPoint, Sequence, ScriptBuf, Transaction, TxIn, TxOut, Weight,
 	Witness,
 };
 use bitcoin::blockdata::locktime::absolute::LockTime;
 use bitcoin::hashes::Hash;
-use bitcoin::secp256k1::{schnorr, Keypair};
+use bitcoin::secp256k1::{schnorr, Keypair, PublicKey};
 use bitcoin::sighash::{self, SighashCache, TapSighash};
 
 use crate::{fee, musig, util, BaseVtxo, Vtxo, VtxoSpec};
@@ -20,11 +24,13 @@ use crate::{fee, musig, util, BaseVtxo, Vtxo, VtxoSpec};
 /// The total signed tx weight of a reveal tx.
 const REVEAL_TX_WEIGHT: Weight = Weight::from_vb_unchecked(154);
 
+/// The maximum number of onboards the ASP cosigns in a single batch request.
+pub const MAX_BATCH_SIZE: usize = 32;
+
 fn onboard_taproot(spec: &VtxoSpec) -> taproot::TaprootSpendInfo {
-	let expiry = util::timelock_sign(spec.expiry_height, spec.asp_pubkey.x_only_public_key().0);
-	let ret = taproot::TaprootBuilder::new()
-		.add_leaf(0, expiry).unwrap()
-		.finalize(&util::SECP, spec.combined_pubkey()).unwrap();
+	let ret = spec.script_type.expiry_taproot(
+		spec.combined_pubkey(), spec.asp_pubkey, spec.expiry_height,
+	);
 	debug_assert_eq!(
 		ret.output_key().to_inner(),
 		musig::tweaked_key_agg(
@@ -56,6 +62,18 @@ pub struct UserPart {
 	pub nonce: musig::MusigPubNonce,
 }
 
+impl UserPart {
+	pub fn encode(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(self, &mut buf).unwrap();
+		buf
+	}
+
+	pub fn decode(bytes: &[u8]) -> Result<Self, ciborium::de::Error<io::Error>> {
+		ciborium::from_reader(bytes)
+	}
+}
+
 #[derive(Debug)]
 pub struct PrivateUserPart {
 	pub sec_nonce: musig::MusigSecNonce,
@@ -100,6 +118,60 @@ pub fn new_asp(user: &UserPart, key: &Keypair) -> AspPart {
 	}
 }
 
+/// Like [new_asp], but signing with a nonce pair the ASP generated before
+/// it received the user part, e.g. using [musig::nonce_pair].
+///
+/// The secret nonce is consumed, it must never be used for another signature.
+pub fn new_asp_with_nonce(
+	user: &UserPart,
+	key: &Keypair,
+	sec_nonce: musig::MusigSecNonce,
+	pub_nonce: musig::MusigPubNonce,
+) -> AspPart {
+	let (reveal_sighash, _reveal_tx) = reveal_tx_sighash(&user.spec, user.utxo);
+	let agg_nonce = musig::nonce_agg([user.nonce, pub_nonce]);
+	let (sig, _) = musig::partial_sign(
+		[user.spec.user_pubkey, key.public_key()],
+		agg_nonce,
+		key,
+		sec_nonce,
+		reveal_sighash.to_byte_array(),
+		Some(onboard_taptweak(&user.spec).to_byte_array()),
+		None,
+	);
+	AspPart {
+		nonce: pub_nonce,
+		signature: sig,
+	}
+}
+
+/// Check that the ASP part holds a valid partial signature by the ASP
+/// for the reveal tx of the user part.
+pub fn verify_asp(user: &UserPart, asp: &AspPart, asp_pubkey: PublicKey) -> bool {
+	if user.spec.asp_pubkey != asp_pubkey {
+		return false;
+	}
+
+	let (reveal_sighash, _reveal_tx) = reveal_tx_sighash(&user.spec, user.utxo);
+	let (agg, _) = musig::tweaked_key_agg(
+		[user.spec.user_pubkey, asp_pubkey], onboard_taptweak(&user.spec).to_byte_array(),
+	);
+	let agg_nonce = musig::nonce_agg([user.nonce, asp.nonce]);
+	let session = musig::MusigSession::new(
+		&musig::SECP,
+		&agg,
+		agg_nonce,
+		musig::zkp::Message::from_digest(reveal_sighash.to_byte_array()),
+	);
+	session.partial_verify(
+		&musig::SECP,
+		&agg,
+		asp.signature.clone(),
+		asp.nonce,
+		musig::pubkey_to(asp_pubkey),
+	)
+}
+
 pub fn create_reveal_tx(
 	spec: &VtxoSpec,
 	utxo: OutPoint,
@@ -191,6 +263,8 @@ pub fn signed_reveal_tx(vtxo: &Vtxo) -> Option<Transaction> {
 mod test {
 	use super::*;
 
+	use crate::{ExitTimelockType, VtxoScriptType};
+
 	#[test]
 	fn test_flow_assertions() {
 		//! Passes through the entire flow so that all assertions
@@ -204,10 +278,68 @@ mod test {
 			expiry_height: 100_000,
 			exit_delta: 2016,
 			amount: Amount::from_btc(1.5).unwrap(),
+			exit_timelock_type: ExitTimelockType::Relative,
+			script_type: VtxoScriptType::Taproot,
 		};
 		let (user, upriv) = new_user(spec, utxo);
 		let asp = new_asp(&user, &key);
+		assert!(verify_asp(&user, &asp, key.public_key()));
 		let vtxo = finish(user, asp, upriv, &key);
 		let _reveal_tx = signed_reveal_tx(&vtxo).unwrap();
 	}
+
+	#[test]
+	fn test_verify_tampered_asp_part() {
+		let user_key = Keypair::new(&util::SECP, &mut rand::thread_rng());
+		let asp_key = Keypair::new(&util::SECP, &mut rand::thread_rng());
+		let utxo = "0000000000000000000000000000000000000000000000000000000000000001:1".parse().unwrap();
+		let spec = VtxoSpec {
+			user_pubkey: user_key.public_key(),
+			asp_pubkey: asp_key.public_key(),
+			expiry_height: 100_000,
+			exit_delta: 2016,
+			amount: Amount::from_btc(1.5).unwrap(),
+			exit_timelock_type: ExitTimelockType::Relative,
+			script_type: VtxoScriptType::Taproot,
+		};
+		let (user, _upriv) = new_user(spec.clone(), utxo);
+		let asp = new_asp(&user, &asp_key);
+		assert!(verify_asp(&user, &asp, asp_key.public_key()));
+
+		// A signature for a different reveal tx.
+		let (other_user, _) = new_user(spec.clone(), OutPoint::new(utxo.txid, 2));
+		let other_asp = new_asp(&other_user, &asp_key);
+		let tampered = AspPart { nonce: asp.nonce, signature: other_asp.signature };
+		assert!(!verify_asp(&user, &tampered, asp_key.public_key()));
+		let tampered = AspPart { nonce: other_asp.nonce, signature: asp.signature };
+		assert!(!verify_asp(&user, &tampered, asp_key.public_key()));
+
+		// A signature by someone other than the ASP.
+		let fake = new_asp(&user, &user_key);
+		assert!(!verify_asp(&user, &fake, asp_key.public_key()));
+		assert!(!verify_asp(&user, &asp, user_key.public_key()));
+	}
+
+	#[test]
+	fn test_flow_pregenerated_nonce() {
+		let user_key = Keypair::new(&util::SECP, &mut rand::thread_rng());
+		let asp_key = Keypair::new(&util::SECP, &mut rand::thread_rng());
+		let utxo = "0000000000000000000000000000000000000000000000000000000000000001:1".parse().unwrap();
+		let spec = VtxoSpec {
+			user_pubkey: user_key.public_key(),
+			asp_pubkey: asp_key.public_key(),
+			expiry_height: 100_000,
+			exit_delta: 2016,
+			amount: Amount::from_btc(1.5).unwrap(),
+			exit_timelock_type: ExitTimelockType::Relative,
+			script_type: VtxoScriptType::Taproot,
+		};
+		let (sec_nonce, pub_nonce) = musig::nonce_pair(&asp_key);
+		let (user, upriv) = new_user(spec, utxo);
+		let asp = new_asp_with_nonce(&user, &asp_key, sec_nonce, pub_nonce);
+		assert_eq!(asp.nonce.serialize(), pub_nonce.serialize());
+		assert!(verify_asp(&user, &asp, asp_key.public_key()));
+		let vtxo = finish(user, asp, upriv, &user_key);
+		let _reveal_tx = signed_reveal_tx(&vtxo).unwrap();
+	}
 }
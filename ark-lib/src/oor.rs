@@ -10,31 +10,66 @@ use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{schnorr, Keypair, PublicKey};
 use bitcoin::sighash::{self, SighashCache, TapSighash, TapSighashType};
 
-use crate::{fee, musig, util, Vtxo, VtxoRequest, VtxoSpec};
+use crate::{fee, musig, util, ExitTimelockType, Vtxo, VtxoRequest, VtxoScriptType, VtxoSpec};
 
 
 /// The minimum fee we consider for an oor transaction.
 pub const OOR_MIN_FEE: Amount = crate::P2TR_DUST;
 
+/// An estimate of the weight of the input claiming an exited OOR vtxo:
+/// the base input plus a script spend of the exit clause.
+const EXIT_CLAIM_INPUT_WEIGHT: Weight = Weight::from_wu(4 * 41 + 142);
+
+/// An estimate of the weight of an OOR tx with one input, a payment and a
+/// change output and the fee anchor, with the signed input.
+const EXIT_OOR_TX_WEIGHT: Weight = Weight::from_wu(
+	4 * (11 + 41 + 2 * 43 + fee::DUST_ANCHOR_SIZE as u64) + crate::TAPROOT_KEYSPEND_WEIGHT as u64
+);
+
+/// An estimate of the weight of the CPFP input spending the fee anchor.
+const EXIT_ANCHOR_INPUT_WEIGHT: Weight = Weight::from_wu(
+	4 * 41 + fee::DUST_ANCHOR_SATISFACTION_WEIGHT.to_wu()
+);
+
+/// The smallest OOR output worth exiting: the dust value plus the fee to
+/// get the OOR tx confirmed through its anchor and to claim the exit output,
+/// all at the relay feerate.
+pub fn min_exitable_amount() -> Amount {
+	let weight = EXIT_OOR_TX_WEIGHT + EXIT_ANCHOR_INPUT_WEIGHT + EXIT_CLAIM_INPUT_WEIGHT;
+	fee::DUST + fee::RELAY_FEERATE * weight
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OorPayment {
 	pub asp_pubkey: PublicKey,
 	pub exit_delta: u16,
 	pub inputs: Vec<Vtxo>,
 	pub outputs: Vec<VtxoRequest>,
+	#[serde(default)]
+	pub exit_timelock_type: ExitTimelockType,
+	#[serde(default)]
+	pub script_type: VtxoScriptType,
 }
 
 impl OorPayment {
 	pub fn new(
 		asp_pubkey: PublicKey,
 		exit_delta: u16,
+		exit_timelock_type: ExitTimelockType,
+		script_type: VtxoScriptType,
 		inputs: Vec<Vtxo>,
 		outputs: Vec<VtxoRequest>,
 	) -> OorPayment {
-		OorPayment { asp_pubkey, exit_delta, inputs, outputs }
+		OorPayment { asp_pubkey, exit_delta, inputs, outputs, exit_timelock_type, script_type }
+	}
+
+	/// The expiry height of the output vtxos, the soonest of all inputs.
+	pub fn expiry_height(&self) -> u32 {
+		self.inputs.iter().map(|i| i.spec().expiry_height).min().unwrap()
 	}
 
 	pub fn unsigned_transaction(&self) -> Transaction {
+		let timelock = self.exit_timelock_type.timelock(self.exit_delta, self.expiry_height());
 		Transaction {
 			version: bitcoin::transaction::Version::TWO,
 			lock_time: bitcoin::absolute::LockTime::ZERO,
@@ -47,7 +82,7 @@ impl OorPayment {
 				}
 			}).collect(),
 			output: self.outputs.iter().map(|output| {
-				let spk = crate::exit_spk(output.pubkey, self.asp_pubkey, self.exit_delta);
+				let spk = self.script_type.exit_spk(output.pubkey, self.asp_pubkey, timelock);
 				TxOut {
 					value: output.amount,
 					script_pubkey: spk,
@@ -123,14 +158,16 @@ impl OorPayment {
 		(pub_nonces, part_sigs)
 	}
 
+	/// Sign and finalize the user side, `keypairs` holds the key for each input.
 	pub fn sign_finalize_user(
 		self,
-		keypair: &Keypair,
+		keypairs: &[Keypair],
 		our_sec_nonces: Vec<musig::MusigSecNonce>,
 		our_pub_nonces: &[musig::MusigPubNonce],
 		asp_nonces: &[musig::MusigPubNonce],
 		asp_part_sigs: &[musig::MusigPartialSignature],
 	) -> SignedOorPayment {
+		assert_eq!(self.inputs.len(), keypairs.len());
 		assert_eq!(self.inputs.len(), our_sec_nonces.len());
 		assert_eq!(self.inputs.len(), our_pub_nonces.len());
 		assert_eq!(self.inputs.len(), asp_nonces.len());
@@ -139,6 +176,7 @@ impl OorPayment {
 
 		let mut sigs = Vec::with_capacity(self.inputs.len());
 		for (idx, (input, sec_nonce)) in self.inputs.iter().zip(our_sec_nonces.into_iter()).enumerate() {
+			let keypair = &keypairs[idx];
 			assert_eq!(keypair.public_key(), input.spec().user_pubkey);
 			let agg_nonce = musig::nonce_agg([our_pub_nonces[idx], asp_nonces[idx]]);
 			let (_part_sig, final_sig) = musig::partial_sign(
@@ -198,7 +236,7 @@ impl SignedOorPayment {
 			.map(|input| Box::new(input.clone()))
 			.collect::<Vec<_>>();
 
-		let expiry_height = self.payment.inputs.iter().map(|i| i.spec().expiry_height).min().unwrap();
+		let expiry_height = self.payment.expiry_height();
 		let oor_tx = self.signed_transaction();
 		let oor_txid = oor_tx.compute_txid();
 		self.payment.outputs.iter().enumerate().map(|(idx, output)| {
@@ -210,6 +248,8 @@ impl SignedOorPayment {
 					expiry_height,
 					asp_pubkey,
 					user_pubkey: output.pubkey,
+					exit_timelock_type: self.payment.exit_timelock_type,
+					script_type: self.payment.script_type,
 				},
 				oor_tx: oor_tx.clone(),
 				final_point: OutPoint::new(oor_txid, idx as u32),
@@ -0,0 +1,87 @@
+//! Helpers for proprietary PSBT fields.
+//!
+//! The Ark daemons and wallets keep their metadata in BIP-174 proprietary
+//! fields with a prefix of their own, see their `psbtext` modules for the
+//! fields they define.
+//!
+//! Next to its fields, each of them writes the version of its field layout
+//! as a global proprietary field with subtype [PROP_SUBTYPE_VERSION] and an
+//! empty key, so that external tooling can tell which layout a PSBT has.
+//! New fields only get new subtypes, the version changes when an existing
+//! field changes meaning or encoding.
+
+use bitcoin::psbt;
+
+
+/// The subtype of the global field holding the version of the field layout.
+pub const PROP_SUBTYPE_VERSION: u8 = 0;
+
+/// The proprietary key with the given prefix, subtype and key data.
+pub fn prop_key(prefix: &[u8], subtype: u8, key: Vec<u8>) -> psbt::raw::ProprietaryKey {
+	psbt::raw::ProprietaryKey { prefix: prefix.to_vec(), subtype, key }
+}
+
+/// Whether the proprietary key has the given prefix.
+pub fn is_ours(prefix: &[u8], key: &psbt::raw::ProprietaryKey) -> bool {
+	key.prefix == prefix
+}
+
+/// Write the version of the field layout with the given prefix.
+pub fn set_version(psbt: &mut psbt::Psbt, prefix: &[u8], version: u8) {
+	psbt.proprietary.insert(prop_key(prefix, PROP_SUBTYPE_VERSION, Vec::new()), vec![version]);
+}
+
+/// The version of the field layout with the given prefix.
+///
+/// Returns [None] if the PSBT has no version field for the prefix and
+/// `Err` with the raw value if the value isn't a single byte.
+pub fn get_version(psbt: &psbt::Psbt, prefix: &[u8]) -> Result<Option<u8>, Vec<u8>> {
+	match psbt.proprietary.get(&prop_key(prefix, PROP_SUBTYPE_VERSION, Vec::new())) {
+		Some(val) if val.len() == 1 => Ok(Some(val[0])),
+		Some(val) => Err(val.clone()),
+		None => Ok(None),
+	}
+}
+
+/// Remove all proprietary fields with the given prefix from the PSBT,
+/// returning how many were removed. Proprietary fields of others are kept.
+pub fn strip_proprietary(psbt: &mut psbt::Psbt, prefix: &[u8]) -> usize {
+	let maps = Some(&mut psbt.proprietary).into_iter()
+		.chain(psbt.inputs.iter_mut().map(|i| &mut i.proprietary))
+		.chain(psbt.outputs.iter_mut().map(|o| &mut o.proprietary));
+	let mut removed = 0;
+	for map in maps {
+		let before = map.len();
+		map.retain(|key, _| !is_ours(prefix, key));
+		removed += before - map.len();
+	}
+	removed
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use bitcoin::OutPoint;
+
+	use crate::test_util::dummy_psbt;
+
+	#[test]
+	fn version_and_strip() {
+		let mut psbt = dummy_psbt([OutPoint::null()]);
+		assert_eq!(get_version(&psbt, b"ours"), Ok(None));
+		set_version(&mut psbt, b"ours", 3);
+		set_version(&mut psbt, b"other", 1);
+		psbt.inputs[0].proprietary.insert(prop_key(b"ours", 1, vec![1]), vec![]);
+
+		let mut decoded = psbt::Psbt::deserialize(&psbt.serialize()).unwrap();
+		assert_eq!(get_version(&decoded, b"ours"), Ok(Some(3)));
+		assert_eq!(get_version(&decoded, b"other"), Ok(Some(1)));
+		assert_eq!(strip_proprietary(&mut decoded, b"ours"), 2);
+		assert_eq!(get_version(&decoded, b"ours"), Ok(None));
+		assert_eq!(get_version(&decoded, b"other"), Ok(Some(1)));
+
+		decoded.proprietary.insert(prop_key(b"ours", PROP_SUBTYPE_VERSION, Vec::new()), vec![1, 2]);
+		assert_eq!(get_version(&decoded, b"ours"), Err(vec![1, 2]));
+	}
+}
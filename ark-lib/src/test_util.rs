@@ -0,0 +1,46 @@
+//! Fixtures shared by the tests and benchmarks of the Ark crates.
+//!
+//! This is not part of the public API and can change at any time.
+
+use std::str::FromStr;
+
+use bitcoin::{absolute, psbt, transaction, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut};
+use bitcoin::secp256k1::PublicKey;
+
+use crate::{onboard, ExitTimelockType, VtxoScriptType, VtxoSpec};
+
+
+/// A PSBT spending the given outpoints into a single output of 1000 sats.
+pub fn dummy_psbt(inputs: impl IntoIterator<Item = OutPoint>) -> psbt::Psbt {
+	let tx = Transaction {
+		version: transaction::Version::TWO,
+		lock_time: absolute::LockTime::ZERO,
+		input: inputs.into_iter().map(|previous_output| TxIn {
+			previous_output,
+			..Default::default()
+		}).collect(),
+		output: vec![TxOut {
+			value: Amount::from_sat(1000),
+			script_pubkey: ScriptBuf::new(),
+		}],
+	};
+	psbt::Psbt::from_unsigned_tx(tx).unwrap()
+}
+
+/// The user part of an onboard of 100k sats in the given output of a dummy
+/// onboard tx.
+pub fn onboard_user_part(user: PublicKey, asp: PublicKey, vout: u32) -> onboard::UserPart {
+	let spec = VtxoSpec {
+		user_pubkey: user,
+		asp_pubkey: asp,
+		expiry_height: 100_000,
+		exit_delta: 2016,
+		amount: Amount::from_sat(100_000),
+		exit_timelock_type: ExitTimelockType::Relative,
+		script_type: VtxoScriptType::Taproot,
+	};
+	let utxo = OutPoint::from_str(
+		"0000000000000000000000000000000000000000000000000000000000000001:0",
+	).unwrap();
+	onboard::new_user(spec, OutPoint::new(utxo.txid, vout)).0
+}
@@ -1,15 +1,18 @@
 
 
-use std::{cmp, io};
+use std::{cmp, fmt, io};
+use std::collections::HashMap;
+use std::str::FromStr;
 
 use bitcoin::{
 	taproot, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness,
 };
+use bitcoin::key::TweakedPublicKey;
 use bitcoin::secp256k1::{schnorr, PublicKey, XOnlyPublicKey};
 use bitcoin::sighash::{self, SighashCache, TapSighash, TapSighashType};
-use bitcoin::taproot::{ControlBlock, LeafVersion, TapNodeHash, TaprootBuilder};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapNodeHash};
 
-use crate::{fee, util, VtxoSpec, VtxoRequest};
+use crate::{fee, util, BaseVtxo, ExitTimelockType, Vtxo, VtxoScriptType, VtxoSpec, VtxoRequest};
 use crate::tree::Tree;
 
 
@@ -36,6 +39,50 @@ const NODE4_TX_WEIGHT_ANCHOR: Weight = Weight::from_vb_unchecked(283);
 pub const NODE_SPEND_WEIGHT: Weight = Weight::from_wu(140);
 
 
+/// How the output key of the outputs in the vtxo tree is constructed.
+///
+/// The ASP and all cosigners need to agree on the policy, signatures made
+/// with the wrong policy are not valid for the tree outputs.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKeyPolicy {
+	/// The cosign aggregate key tweaked with the merkle root of the taptree
+	/// holding the expiry clause, so that the ASP can sweep after expiry.
+	MerkleRootTweak,
+	/// The plain cosign aggregate key without any tweak.
+	///
+	/// NB There is no script path, so the outputs can only be spent
+	/// by all cosigners together and can't be swept after expiry.
+	PlainAggregate,
+}
+
+impl Default for OutputKeyPolicy {
+	fn default() -> Self {
+		OutputKeyPolicy::MerkleRootTweak
+	}
+}
+
+impl fmt::Display for OutputKeyPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			OutputKeyPolicy::MerkleRootTweak => f.write_str("merkle_root_tweak"),
+			OutputKeyPolicy::PlainAggregate => f.write_str("plain_aggregate"),
+		}
+	}
+}
+
+impl FromStr for OutputKeyPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"merkle_root_tweak" => Ok(OutputKeyPolicy::MerkleRootTweak),
+			"plain_aggregate" => Ok(OutputKeyPolicy::PlainAggregate),
+			_ => Err(format!("unknown output key policy: {}", s)),
+		}
+	}
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct VtxoTreeSpec {
 	pub vtxos: Vec<VtxoRequest>,
@@ -46,6 +93,15 @@ pub struct VtxoTreeSpec {
 	/// Whether or not to place fee anchors on each node in the tree.
 	/// NB Fee anchors are always placed on the leaves regardless of this field.
 	pub node_anchors: bool,
+	/// How the output key of the tree outputs is constructed.
+	#[serde(default)]
+	pub output_key_policy: OutputKeyPolicy,
+	/// The timelock type of the exit clause of the vtxos.
+	#[serde(default)]
+	pub exit_timelock_type: ExitTimelockType,
+	/// The type of the output scripts of the tree and the vtxos.
+	#[serde(default)]
+	pub script_type: VtxoScriptType,
 }
 
 impl VtxoTreeSpec {
@@ -56,8 +112,14 @@ impl VtxoTreeSpec {
 		expiry_height: u32,
 		exit_delta: u16,
 		node_anchors: bool,
+		output_key_policy: OutputKeyPolicy,
+		exit_timelock_type: ExitTimelockType,
+		script_type: VtxoScriptType,
 	) -> VtxoTreeSpec {
-		VtxoTreeSpec { vtxos, cosign_agg_pk, asp_key, expiry_height, exit_delta, node_anchors }
+		VtxoTreeSpec {
+			vtxos, cosign_agg_pk, asp_key, expiry_height, exit_delta, node_anchors, output_key_policy,
+			exit_timelock_type, script_type,
+		}
 	}
 
 	pub fn encode(&self) -> Vec<u8> {
@@ -132,34 +194,78 @@ impl VtxoTreeSpec {
 
 	/// The expiry clause hidden in the node taproot as only script.
 	fn expiry_clause(&self) -> ScriptBuf {
-		let pk = self.asp_key.x_only_public_key().0;
-		util::timelock_sign(self.expiry_height, pk)
+		self.script_type.expiry_clause(self.asp_key, self.expiry_height)
 	}
 
 	/// The taproot scriptspend info for the expiry clause.
-	pub fn expiry_scriptspend(&self) -> (ControlBlock, ScriptBuf, LeafVersion, TapNodeHash) {
+	///
+	/// Returns [None] if the output key policy doesn't have a script path.
+	pub fn expiry_scriptspend(&self) -> Option<(ControlBlock, ScriptBuf, LeafVersion, TapNodeHash)> {
+		if self.output_key_policy == OutputKeyPolicy::PlainAggregate {
+			return None;
+		}
 		let taproot = self.cosign_taproot();
 		let script = self.expiry_clause();
 		let cb = taproot.control_block(&(script.clone(), LeafVersion::TapScript))
 			.expect("expiry script should be in cosign taproot");
-		(cb, script, LeafVersion::TapScript, taproot.merkle_root().unwrap())
+		Some((cb, script, LeafVersion::TapScript, taproot.merkle_root().unwrap()))
 	}
 
 	pub fn cosign_taproot(&self) -> taproot::TaprootSpendInfo {
-		TaprootBuilder::new()
-			.add_leaf(0, self.expiry_clause()).unwrap()
-			.finalize(&util::SECP, self.cosign_agg_pk).unwrap()
+		self.script_type.expiry_taproot(self.cosign_agg_pk, self.asp_key, self.expiry_height)
 	}
 
-	pub fn cosign_taptweak(&self) -> taproot::TapTweakHash {
-		self.cosign_taproot().tap_tweak()
+	/// The tweak to apply to the cosign aggregate key when signing, if any.
+	pub fn cosign_taptweak(&self) -> Option<taproot::TapTweakHash> {
+		match self.output_key_policy {
+			OutputKeyPolicy::MerkleRootTweak => Some(self.cosign_taproot().tap_tweak()),
+			OutputKeyPolicy::PlainAggregate => None,
+		}
+	}
+
+	/// The output key of all the tree outputs.
+	pub fn cosign_output_key(&self) -> TweakedPublicKey {
+		match self.output_key_policy {
+			OutputKeyPolicy::MerkleRootTweak => self.cosign_taproot().output_key(),
+			OutputKeyPolicy::PlainAggregate => {
+				TweakedPublicKey::dangerous_assume_tweaked(self.cosign_agg_pk)
+			},
+		}
 	}
 
 	pub fn cosign_spk(&self) -> ScriptBuf {
-		ScriptBuf::new_p2tr_tweaked(self.cosign_taproot().output_key())
+		ScriptBuf::new_p2tr_tweaked(self.cosign_output_key())
 	}
 
-	fn node_tx(&self, children: &[&Transaction]) -> Transaction {
+	/// The output of a node tx that funds the given child tx.
+	fn child_output(&self, cosign_spk: &ScriptBuf, child: &TxSummary) -> TxOut {
+		let weight = if child.is_leaf {
+			LEAF_TX_WEIGHT
+		} else {
+			if self.node_anchors {
+				match child.nb_outputs {
+					3 => NODE2_TX_WEIGHT_ANCHOR,
+					4 => NODE3_TX_WEIGHT_ANCHOR,
+					5 => NODE4_TX_WEIGHT_ANCHOR,
+					n => unreachable!("node tx with {} children", n),
+				}
+			} else {
+				match child.nb_outputs {
+					2 => NODE2_TX_WEIGHT,
+					3 => NODE3_TX_WEIGHT,
+					4 => NODE4_TX_WEIGHT,
+					n => unreachable!("node tx with {} children", n),
+				}
+			}
+		};
+		let fee_budget = fee::RELAY_FEERATE * weight;
+		TxOut {
+			script_pubkey: cosign_spk.clone(),
+			value: child.value + fee_budget,
+		}
+	}
+
+	fn node_tx(&self, cosign_spk: &ScriptBuf, children: &[&TxSummary]) -> Transaction {
 		Transaction {
 			version: bitcoin::transaction::Version::TWO,
 			lock_time: bitcoin::absolute::LockTime::ZERO,
@@ -170,31 +276,7 @@ impl VtxoTreeSpec {
 				witness: Witness::new(),
 			}],
 			output: children.iter().map(|child| {
-				let is_leaf = child.output.len() == 2 && child.output[1] == fee::dust_anchor();
-				let weight = if is_leaf {
-					LEAF_TX_WEIGHT
-				} else {
-					if self.node_anchors {
-						match child.output.len() {
-							3 => NODE2_TX_WEIGHT_ANCHOR,
-							4 => NODE3_TX_WEIGHT_ANCHOR,
-							5 => NODE4_TX_WEIGHT_ANCHOR,
-							n => unreachable!("node tx with {} children", n),
-						}
-					} else {
-						match child.output.len() {
-							2 => NODE2_TX_WEIGHT,
-							3 => NODE3_TX_WEIGHT,
-							4 => NODE4_TX_WEIGHT,
-							n => unreachable!("node tx with {} children", n),
-						}
-					}
-				};
-				let fee_budget = fee::RELAY_FEERATE * weight;
-				TxOut {
-					script_pubkey: self.cosign_spk(),
-					value: child.output.iter().map(|o| o.value).sum::<Amount>() + fee_budget,
-				}
+				self.child_output(cosign_spk, child)
 			}).chain(if self.node_anchors {
 				Some(fee::dust_anchor())
 			} else {
@@ -210,6 +292,8 @@ impl VtxoTreeSpec {
 			expiry_height: self.expiry_height,
 			exit_delta: self.exit_delta,
 			amount: vtxo.amount,
+			exit_timelock_type: self.exit_timelock_type,
+			script_type: self.script_type,
 		}
 	}
 
@@ -234,9 +318,78 @@ impl VtxoTreeSpec {
 		}
 	}
 
+	/// The number of txs in the tree.
+	pub fn nb_nodes(&self) -> usize {
+		// Cfr the way [Tree::new] combines nodes.
+		let mut left = self.vtxos.len();
+		let mut nb_nodes = left;
+		while left > 1 {
+			left -= cmp::min(left, 4) - 1;
+			nb_nodes += 1;
+		}
+		nb_nodes
+	}
+
+	/// The shape of the tree with a summary of each tx, which is all we need
+	/// to build any of the txs once we know its prevout.
+	fn skeleton(&self) -> Tree<TxSummary> {
+		let leaves = self.vtxos.iter().map(|vtxo| TxSummary {
+			is_leaf: true,
+			nb_outputs: 2,
+			value: vtxo.amount + fee::dust_anchor().value,
+		});
+		let cosign_spk = self.cosign_spk();
+		Tree::new(leaves, |children| {
+			let outputs = children.iter().map(|c| self.child_output(&cosign_spk, c).value);
+			let anchor = if self.node_anchors { fee::dust_anchor().value } else { Amount::ZERO };
+			TxSummary {
+				is_leaf: false,
+				nb_outputs: children.len() + self.node_anchors as usize,
+				value: outputs.sum::<Amount>() + anchor,
+			}
+		})
+	}
+
+	/// Build the tx at the given index of the tree, spending the given prevout.
+	fn build_tx(
+		&self,
+		skeleton: &Tree<TxSummary>,
+		cosign_spk: &ScriptBuf,
+		idx: usize,
+		prevout: OutPoint,
+	) -> Transaction {
+		let mut tx = if idx < skeleton.nb_leaves() {
+			self.leaf_tx(&self.vtxos[idx])
+		} else {
+			let nb_children = skeleton.nb_children_of(idx).unwrap();
+			let children = (0..nb_children).map(|i| skeleton.child_of(idx, i).unwrap())
+				.collect::<Vec<_>>();
+			self.node_tx(cosign_spk, &children)
+		};
+		tx.input[0].previous_output = prevout;
+		tx
+	}
+
+	/// The output spent by the tx at the given index of the tree.
+	fn tx_prevout(&self, skeleton: &Tree<TxSummary>, cosign_spk: &ScriptBuf, idx: usize) -> TxOut {
+		if skeleton.parent_idx_of(idx).is_some() {
+			self.child_output(cosign_spk, skeleton.element_at(idx).unwrap())
+		} else {
+			// this is the root
+			TxOut {
+				script_pubkey: cosign_spk.clone(),
+				value: self.total_required_value(),
+			}
+		}
+	}
+
 	pub fn build_unsigned_tree(&self, utxo: OutPoint) -> Tree<Transaction> {
+		let cosign_spk = self.cosign_spk();
 		let leaves = self.vtxos.iter().map(|dest| self.leaf_tx(dest));
-		let mut tree = Tree::new(leaves, |children| self.node_tx(children));
+		let mut tree = Tree::new(leaves, |children| {
+			let summaries = children.iter().map(|c| TxSummary::of(c)).collect::<Vec<_>>();
+			self.node_tx(&cosign_spk, &summaries.iter().collect::<Vec<_>>())
+		});
 
 		// Iterate over all nodes in reverse order and set the prevouts.
 		let mut cursor = tree.nb_nodes() - 1;
@@ -255,25 +408,110 @@ impl VtxoTreeSpec {
 		tree
 	}
 
+	/// Build the unsigned txs of the tree from the root down to the leaves,
+	/// passing them to `f` in chunks of at most `chunk_size` txs.
+	///
+	/// The txs are the same as those of [VtxoTreeSpec::build_unsigned_tree],
+	/// but only a chunk of txs and the txids of the nodes whose children
+	/// are not built yet are kept in memory, instead of the entire tree.
+	pub fn build_unsigned_tree_chunked(
+		&self,
+		utxo: OutPoint,
+		chunk_size: usize,
+		mut f: impl FnMut(&[UnsignedTreeTx]),
+	) {
+		assert_ne!(chunk_size, 0, "chunk size can't be zero");
+		let skeleton = self.skeleton();
+		let cosign_spk = self.cosign_spk();
+
+		let mut parent_txids = HashMap::new();
+		let mut chunk = Vec::with_capacity(cmp::min(chunk_size, skeleton.nb_nodes()));
+		// Parents always come after their children in the tree, so we
+		// build each tx after the tx it spends.
+		for idx in (0..skeleton.nb_nodes()).rev() {
+			let prevout = match skeleton.parent_idx_of(idx) {
+				Some(parent) => {
+					let child_idx = (0..4).position(|i| skeleton.child_idx_of(parent, i) == Some(idx))
+						.expect("broken tree");
+					// Siblings are built from the last to the first, so we
+					// no longer need the parent after its first child.
+					let txid = if child_idx == 0 {
+						parent_txids.remove(&parent)
+					} else {
+						parent_txids.get(&parent).copied()
+					}.expect("parent built before its children");
+					OutPoint::new(txid, child_idx as u32)
+				},
+				None => utxo,
+			};
+
+			let tx = self.build_tx(&skeleton, &cosign_spk, idx, prevout);
+			if idx >= skeleton.nb_leaves() {
+				parent_txids.insert(idx, tx.compute_txid());
+			}
+			let prevout = self.tx_prevout(&skeleton, &cosign_spk, idx);
+			chunk.push(UnsignedTreeTx { idx, tx, prevout });
+
+			if chunk.len() == chunk_size {
+				f(&chunk);
+				chunk.clear();
+			}
+		}
+		if !chunk.is_empty() {
+			f(&chunk);
+		}
+	}
+
 	/// Return all sighashes ordered from the root down to the leaves.
 	pub fn sighashes(&self, utxo: OutPoint) -> Vec<TapSighash> {
-		let tree = self.build_unsigned_tree(utxo);
+		let mut ret = Vec::with_capacity(self.nb_nodes());
+		self.build_unsigned_tree_chunked(utxo, DEFAULT_CHUNK_SIZE, |chunk| {
+			ret.extend(chunk.iter().map(|t| t.sighash()));
+		});
+		ret
+	}
+}
 
-		(0..tree.nb_nodes()).rev().map(|idx| {
-			let prev = if let Some((parent, child_idx)) = tree.parent_of_with_idx(idx) {
-				parent.output[child_idx].clone()
-			} else {
-				// this is the root
-				TxOut {
-					script_pubkey: self.cosign_spk(),
-					value: self.total_required_value(),
-				}
-			};
-			let el = tree.element_at(idx).unwrap();
-			SighashCache::new(el).taproot_key_spend_signature_hash(
-				0, &sighash::Prevouts::All(&[prev]), TapSighashType::Default,
-			).expect("sighash error")
-		}).collect()
+/// The default number of txs [VtxoTreeSpec::build_unsigned_tree_chunked]
+/// keeps in memory when we build the tree internally.
+pub const DEFAULT_CHUNK_SIZE: usize = 1024;
+
+/// What a node tx needs to know about a child tx to fund it.
+#[derive(Debug, Clone, Copy)]
+struct TxSummary {
+	is_leaf: bool,
+	nb_outputs: usize,
+	/// The total value of the outputs.
+	value: Amount,
+}
+
+impl TxSummary {
+	fn of(tx: &Transaction) -> TxSummary {
+		TxSummary {
+			is_leaf: tx.output.len() == 2 && tx.output[1] == fee::dust_anchor(),
+			nb_outputs: tx.output.len(),
+			value: tx.output.iter().map(|o| o.value).sum(),
+		}
+	}
+}
+
+/// An unsigned tx of the vtxo tree built by
+/// [VtxoTreeSpec::build_unsigned_tree_chunked].
+#[derive(Debug, Clone)]
+pub struct UnsignedTreeTx {
+	/// The index of the tx in the tree.
+	pub idx: usize,
+	pub tx: Transaction,
+	/// The output spent by the tx.
+	pub prevout: TxOut,
+}
+
+impl UnsignedTreeTx {
+	/// The sighash the cosigners sign for this tx.
+	pub fn sighash(&self) -> TapSighash {
+		SighashCache::new(&self.tx).taproot_key_spend_signature_hash(
+			0, &sighash::Prevouts::All(&[&self.prevout]), TapSighashType::Default,
+		).expect("sighash error")
 	}
 }
 
@@ -310,39 +548,82 @@ impl SignedVtxoTree {
 
 	/// Validate the signatures.
 	pub fn validate_signatures(&self) -> Result<(), String> {
-		let pk = self.spec.cosign_taproot().output_key().to_inner();
-		let sighashes = self.spec.sighashes(self.utxo);
-		for (i, (sighash, sig)) in sighashes.into_iter().rev().zip(self.signatures.iter()).enumerate() {
-			util::SECP.verify_schnorr(sig, &sighash.into(), &pk)
-				.map_err(|e| format!("failed signature {}: sh {}; sig {}: {}", i, sighash, sig, e))?;
+		if self.signatures.len() != self.spec.nb_nodes() {
+			return Err(format!("expected {} signatures, got {}",
+				self.spec.nb_nodes(), self.signatures.len(),
+			));
 		}
-		Ok(())
+		let pk = self.spec.cosign_output_key().to_inner();
+		let mut res = Ok(());
+		self.spec.build_unsigned_tree_chunked(self.utxo, DEFAULT_CHUNK_SIZE, |chunk| {
+			if res.is_err() {
+				return;
+			}
+			for t in chunk {
+				let sighash = t.sighash();
+				let sig = &self.signatures[t.idx];
+				if let Err(e) = util::SECP.verify_schnorr(sig, &sighash.into(), &pk) {
+					res = Err(format!("failed signature {}: sh {}; sig {}: {}", t.idx, sighash, sig, e));
+					return;
+				}
+			}
+		});
+		res
 	}
 
 	/// Construct the exit branch starting from the root ending in the leaf.
+	///
+	/// Only the txs of the branch are built.
 	pub fn exit_branch(&self, leaf_idx: usize) -> Option<Vec<Transaction>> {
-		let tree = self.spec.build_unsigned_tree(self.utxo);
-		if leaf_idx >= tree.nb_leaves {
+		let skeleton = self.spec.skeleton();
+		if leaf_idx >= skeleton.nb_leaves() {
 			return None;
 		}
 
-		let mut branch = Vec::new();
-		let mut cursor = leaf_idx;
-		loop {
-			let mut tx = tree.element_at(cursor).unwrap().clone();
-			SignedVtxoTree::finalize_tx(&mut tx, &self.signatures[cursor]);
-			branch.push(tx);
-			if let Some(p) = tree.parent_idx_of(cursor) {
-				cursor = p;
-			} else {
-				break;
+		let mut path = vec![leaf_idx];
+		while let Some(p) = skeleton.parent_idx_of(*path.last().unwrap()) {
+			path.push(p);
+		}
+
+		let cosign_spk = self.spec.cosign_spk();
+		let mut branch = Vec::with_capacity(path.len());
+		let mut prevout = self.utxo;
+		for (i, idx) in path.iter().copied().enumerate().rev() {
+			let mut tx = self.spec.build_tx(&skeleton, &cosign_spk, idx, prevout);
+			if i > 0 {
+				let child = path[i - 1];
+				let child_idx = (0..4).position(|c| skeleton.child_idx_of(idx, c) == Some(child))
+					.expect("broken tree");
+				prevout = OutPoint::new(tx.compute_txid(), child_idx as u32);
 			}
+			SignedVtxoTree::finalize_tx(&mut tx, &self.signatures[idx]);
+			branch.push(tx);
 		}
-		branch.reverse();
 
 		Some(branch)
 	}
 
+	/// The round vtxo of the given leaf, [None] if there is no such leaf.
+	pub fn vtxo(&self, leaf_idx: usize) -> Option<Vtxo> {
+		let dest = self.spec.vtxos.get(leaf_idx)?;
+		Some(Vtxo::Round {
+			base: BaseVtxo {
+				spec: VtxoSpec {
+					user_pubkey: dest.pubkey,
+					asp_pubkey: self.spec.asp_key,
+					expiry_height: self.spec.expiry_height,
+					exit_delta: self.spec.exit_delta,
+					amount: dest.amount,
+					exit_timelock_type: self.spec.exit_timelock_type,
+					script_type: self.spec.script_type,
+				},
+				utxo: self.utxo,
+			},
+			leaf_idx,
+			exit_branch: self.exit_branch(leaf_idx)?,
+		})
+	}
+
 	/// Get all signed txs in this tree, starting with the leaves, towards the root.
 	pub fn all_signed_txs(&self) -> Vec<Transaction> {
 		let mut ret = self.spec.build_unsigned_tree(self.utxo).into_vec();
@@ -363,9 +644,10 @@ mod test {
 	use bitcoin::hashes::{siphash24, sha256, Hash, HashEngine};
 	use bitcoin::secp256k1::{self, rand, Keypair};
 	use bitcoin::FeeRate;
+	use bitcoin::absolute::LockTime;
 	use rand::SeedableRng;
 
-	use crate::musig;
+	use crate::{musig, ExitTimelock};
 
 	#[test]
 	fn vtxo_tree_spec() {
@@ -388,6 +670,9 @@ mod test {
 				100_000,
 				2016,
 				false,
+				OutputKeyPolicy::MerkleRootTweak,
+				ExitTimelockType::Relative,
+				VtxoScriptType::Taproot,
 			);
 			assert_eq!(spec.total_required_value().to_sat(), 2755270);
 			let sighashes_hash = {
@@ -405,6 +690,9 @@ mod test {
 				101_000,
 				2016,
 				true,
+				OutputKeyPolicy::MerkleRootTweak,
+				ExitTimelockType::Relative,
+				VtxoScriptType::Taproot,
 			);
 			assert_eq!(spec.total_required_value().to_sat(), 2861894);
 			let sighashes_hash = {
@@ -416,6 +704,155 @@ mod test {
 		}
 	}
 
+	#[test]
+	fn vtxo_scripts_match_client() {
+		let secp = secp256k1::Secp256k1::new();
+		let mut rand = rand::rngs::SmallRng::seed_from_u64(42);
+		let asp_key = Keypair::new(&secp, &mut rand);
+		let cosign_key = Keypair::new(&secp, &mut rand);
+		let dests = (1..=5).map(|i| VtxoRequest {
+			pubkey: Keypair::new(&secp, &mut rand).public_key(),
+			amount: Amount::from_sat(10_000 * i),
+		}).collect::<Vec<_>>();
+		let point = "0000000000000000000000000000000000000000000000000000000000000001:1".parse().unwrap();
+
+		for exit_timelock_type in [ExitTimelockType::Relative, ExitTimelockType::Absolute] {
+			let script_type = VtxoScriptType::Taproot;
+			let spec = VtxoTreeSpec::new(
+				dests.clone(),
+				musig::combine_keys([asp_key.public_key(), cosign_key.public_key()]),
+				asp_key.public_key(),
+				100_000,
+				2016,
+				true,
+				OutputKeyPolicy::MerkleRootTweak,
+				exit_timelock_type,
+				script_type,
+			);
+			let tree = spec.build_unsigned_tree(point);
+
+			for (idx, dest) in dests.iter().enumerate() {
+				// This is how the client knows its vtxo from the ark info.
+				let vtxo_spec = VtxoSpec {
+					user_pubkey: dest.pubkey,
+					asp_pubkey: asp_key.public_key(),
+					expiry_height: 100_000,
+					exit_delta: 2016,
+					amount: dest.amount,
+					exit_timelock_type,
+					script_type,
+				};
+				let leaf = tree.element_at(idx).unwrap();
+				assert_eq!(leaf.output[0].script_pubkey, vtxo_spec.exit_spk());
+
+				// And the client can claim it with the exit clause.
+				let taproot = vtxo_spec.exit_taproot();
+				let exit_clause = vtxo_spec.exit_clause();
+				let cb = taproot.control_block(&(exit_clause.clone(), LeafVersion::TapScript))
+					.expect("exit clause in taproot");
+				assert!(cb.verify_taproot_commitment(
+					&secp, taproot.output_key().to_inner(), &exit_clause,
+				));
+			}
+
+			// The ASP can sweep all tree outputs with the expiry clause.
+			let (cb, expiry_clause, _, _) = spec.expiry_scriptspend().unwrap();
+			assert!(cb.verify_taproot_commitment(
+				&secp, spec.cosign_output_key().to_inner(), &expiry_clause,
+			));
+			for node in tree.iter().skip(tree.nb_leaves()) {
+				let tx = node.element;
+				let nb_children = if spec.node_anchors { tx.output.len() - 1 } else { tx.output.len() };
+				for out in &tx.output[..nb_children] {
+					assert_eq!(out.script_pubkey, spec.cosign_spk());
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn vtxo_exit_path_spend() {
+		let secp = secp256k1::Secp256k1::new();
+		let mut rand = rand::rngs::SmallRng::seed_from_u64(42);
+		let asp_key = Keypair::new(&secp, &mut rand);
+		let cosign_key = Keypair::new(&secp, &mut rand);
+		let user_key = Keypair::new(&secp, &mut rand);
+		let dest = VtxoRequest { pubkey: user_key.public_key(), amount: Amount::from_sat(50_000) };
+		let point = "0000000000000000000000000000000000000000000000000000000000000001:1".parse().unwrap();
+
+		for exit_timelock_type in [ExitTimelockType::Relative, ExitTimelockType::Absolute] {
+			let spec = VtxoTreeSpec::new(
+				vec![dest.clone()],
+				musig::combine_keys([asp_key.public_key(), cosign_key.public_key()]),
+				asp_key.public_key(),
+				100_000,
+				2016,
+				true,
+				OutputKeyPolicy::MerkleRootTweak,
+				exit_timelock_type,
+				VtxoScriptType::Taproot,
+			);
+			let tree = spec.build_unsigned_tree(point);
+			let leaf = tree.element_at(0).unwrap();
+			let vtxo_spec = spec.vtxo_spec(&dest);
+			let prevout = leaf.output[0].clone();
+
+			// The claim tx the user builds to exit with the vtxo.
+			let (sequence, lock_time) = match vtxo_spec.exit_timelock() {
+				ExitTimelock::Relative(delta) => (Sequence::from_height(delta), LockTime::ZERO),
+				ExitTimelock::Absolute(height) => {
+					(Sequence::ENABLE_LOCKTIME_NO_RBF, LockTime::from_height(height).unwrap())
+				},
+			};
+			let mut claim = Transaction {
+				version: bitcoin::transaction::Version::TWO,
+				lock_time,
+				input: vec![TxIn {
+					previous_output: OutPoint::new(leaf.compute_txid(), 0),
+					script_sig: ScriptBuf::new(),
+					sequence,
+					witness: Witness::new(),
+				}],
+				output: vec![TxOut {
+					script_pubkey: ScriptBuf::new_p2tr(&secp, user_key.x_only_public_key().0, None),
+					value: prevout.value - Amount::from_sat(500),
+				}],
+			};
+
+			let exit_clause = vtxo_spec.exit_clause();
+			let leaf_hash = taproot::TapLeafHash::from_script(&exit_clause, LeafVersion::TapScript);
+			let sighash = SighashCache::new(&claim).taproot_script_spend_signature_hash(
+				0, &sighash::Prevouts::All(&[&prevout]), leaf_hash, TapSighashType::Default,
+			).unwrap();
+			let sig = secp.sign_schnorr(&sighash.into(), &user_key);
+			let cb = vtxo_spec.exit_taproot()
+				.control_block(&(exit_clause.clone(), LeafVersion::TapScript))
+				.unwrap();
+			claim.input[0].witness = Witness::from_slice(
+				&[&sig[..], exit_clause.as_bytes(), &cb.serialize()],
+			);
+
+			// The witness opens the leaf output through the exit clause.
+			let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..]).unwrap();
+			assert!(cb.verify_taproot_commitment(&secp, output_key, &exit_clause));
+			secp.verify_schnorr(&sig, &sighash.into(), &user_key.x_only_public_key().0).unwrap();
+
+			// And the claim tx satisfies the timelock of the exit clause.
+			let expected_clause = match exit_timelock_type {
+				ExitTimelockType::Relative => {
+					assert_eq!(claim.input[0].sequence, Sequence::from_height(2016));
+					util::delayed_sign(2016, user_key.x_only_public_key().0)
+				},
+				ExitTimelockType::Absolute => {
+					assert!(!claim.input[0].sequence.is_final());
+					assert_eq!(claim.lock_time, LockTime::from_height(100_000 + 2016).unwrap());
+					util::timelock_sign(100_000 + 2016, user_key.x_only_public_key().0)
+				},
+			};
+			assert_eq!(exit_clause, expected_clause);
+		}
+	}
+
 	fn test_tree_amounts(
 		tree: &SignedVtxoTree,
 		root_value: Amount,
@@ -473,6 +910,9 @@ mod test {
 				100_000,
 				2016,
 				false,
+				OutputKeyPolicy::MerkleRootTweak,
+				ExitTimelockType::Relative,
+				VtxoScriptType::Taproot,
 			);
 			let root_value = spec.total_required_value();
 			let unsigned = spec.build_unsigned_tree(point);
@@ -532,6 +972,9 @@ mod test {
 				100_000,
 				2016,
 				true,
+				OutputKeyPolicy::MerkleRootTweak,
+				ExitTimelockType::Relative,
+				VtxoScriptType::Taproot,
 			);
 			let root_value = spec.total_required_value();
 			let unsigned = spec.build_unsigned_tree(point);
@@ -579,4 +1022,150 @@ mod test {
 		}
 		assert!(had2 && had3 && had4);
 	}
+
+	/// Sign all txs in the tree with musig using the given tweak.
+	fn musig_sign_tree(
+		spec: &VtxoTreeSpec,
+		keys: &[Keypair],
+		utxo: OutPoint,
+		tweak: Option<[u8; 32]>,
+	) -> Vec<schnorr::Signature> {
+		let pubkeys = keys.iter().map(|k| k.public_key()).collect::<Vec<_>>();
+		spec.sighashes(utxo).into_iter().map(|sighash| {
+			let (sec_nonces, pub_nonces) = keys.iter()
+				.map(|k| musig::nonce_pair(k))
+				.unzip::<_, _, Vec<_>, Vec<_>>();
+			let agg_nonce = musig::nonce_agg(pub_nonces);
+			let mut iter = keys.iter().zip(sec_nonces);
+			let (last_key, last_sec) = iter.next_back().unwrap();
+			let partials = iter.map(|(key, sec)| {
+				musig::partial_sign(
+					pubkeys.iter().copied(), agg_nonce, key, sec, sighash.to_byte_array(), tweak, None,
+				).0
+			}).collect::<Vec<_>>();
+			musig::partial_sign(
+				pubkeys.iter().copied(),
+				agg_nonce,
+				last_key,
+				last_sec,
+				sighash.to_byte_array(),
+				tweak,
+				Some(&partials),
+			).1.unwrap()
+		}).collect()
+	}
+
+	#[test]
+	fn chunked_tree_matches_full_tree() {
+		let secp = secp256k1::Secp256k1::new();
+		let mut rand = rand::rngs::SmallRng::seed_from_u64(42);
+		let asp = Keypair::new(&secp, &mut rand);
+		let user = Keypair::new(&secp, &mut rand);
+		let sig = secp.sign_schnorr(&secp256k1::Message::from_digest([1; 32]), &asp);
+		let point = "0000000000000000000000000000000000000000000000000000000000000001:1".parse().unwrap();
+
+		for n in [1, 2, 5, 16, 17, 70] {
+			for node_anchors in [false, true] {
+				let spec = VtxoTreeSpec::new(
+					(0..n).map(|i| VtxoRequest {
+						pubkey: user.public_key(),
+						amount: Amount::from_sat(10_000 + i),
+					}).collect(),
+					musig::combine_keys([asp.public_key(), user.public_key()]),
+					asp.public_key(),
+					100_000,
+					2016,
+					node_anchors,
+					OutputKeyPolicy::MerkleRootTweak,
+					ExitTimelockType::Relative,
+					VtxoScriptType::Taproot,
+				);
+				let full = spec.build_unsigned_tree(point);
+				assert_eq!(spec.nb_nodes(), full.nb_nodes());
+
+				for chunk_size in [1, 3, 1000] {
+					let mut txs = Vec::new();
+					spec.build_unsigned_tree_chunked(point, chunk_size, |chunk| {
+						assert!(chunk.len() <= chunk_size);
+						txs.extend(chunk.iter().cloned());
+					});
+					assert_eq!(txs.len(), full.nb_nodes());
+					for (i, t) in txs.iter().enumerate() {
+						assert_eq!(t.idx, full.nb_nodes() - 1 - i);
+						assert_eq!(&t.tx, full.element_at(t.idx).unwrap());
+					}
+				}
+
+				let signed = SignedVtxoTree::new(spec.clone(), point, vec![sig; full.nb_nodes()]);
+				let all = full.into_vec();
+				for leaf in 0..n as usize {
+					let branch = signed.exit_branch(leaf).unwrap();
+					let leaf_tx = branch.last().unwrap();
+					assert_eq!(leaf_tx.compute_txid(), all[leaf].compute_txid());
+					assert_eq!(branch[0].input[0].previous_output, point);
+				}
+				assert!(signed.exit_branch(n as usize).is_none());
+			}
+		}
+	}
+
+	#[test]
+	fn output_key_policy_roundtrip() {
+		let secp = secp256k1::Secp256k1::new();
+		let mut rand = rand::rngs::SmallRng::seed_from_u64(42);
+		let asp = Keypair::new(&secp, &mut rand);
+		let user = Keypair::new(&secp, &mut rand);
+		let keys = [asp, user];
+		let dest = VtxoRequest {
+			pubkey: user.public_key(),
+			amount: Amount::from_sat(100_000),
+		};
+		let point = "0000000000000000000000000000000000000000000000000000000000000001:1".parse().unwrap();
+
+		for policy in [OutputKeyPolicy::MerkleRootTweak, OutputKeyPolicy::PlainAggregate] {
+			let spec = VtxoTreeSpec::new(
+				vec![dest.clone(); 3],
+				musig::combine_keys(keys.iter().map(|k| k.public_key())),
+				asp.public_key(),
+				100_000,
+				2016,
+				true,
+				policy,
+				ExitTimelockType::Relative,
+				VtxoScriptType::Taproot,
+			);
+			assert_eq!(spec.expiry_scriptspend().is_some(), policy == OutputKeyPolicy::MerkleRootTweak);
+
+			// Signatures made following the policy are valid for the key in the output.
+			let spk = spec.cosign_spk();
+			assert!(spk.is_p2tr());
+			let output_key = XOnlyPublicKey::from_slice(&spk.as_bytes()[2..]).unwrap();
+			let tweak = spec.cosign_taptweak().map(|t| t.to_byte_array());
+			let sigs = musig_sign_tree(&spec, &keys, point, tweak);
+			for (sighash, sig) in spec.sighashes(point).into_iter().zip(sigs.iter()) {
+				secp.verify_schnorr(sig, &sighash.into(), &output_key).expect("invalid signature");
+			}
+			SignedVtxoTree::new(spec.clone(), point, sigs).validate_signatures().unwrap();
+
+			// Signatures made following the other policy are not.
+			let other = VtxoTreeSpec {
+				output_key_policy: match policy {
+					OutputKeyPolicy::MerkleRootTweak => OutputKeyPolicy::PlainAggregate,
+					OutputKeyPolicy::PlainAggregate => OutputKeyPolicy::MerkleRootTweak,
+				},
+				..spec.clone()
+			};
+			let tweak = other.cosign_taptweak().map(|t| t.to_byte_array());
+			let sigs = musig_sign_tree(&spec, &keys, point, tweak);
+			assert!(SignedVtxoTree::new(spec, point, sigs).validate_signatures().is_err());
+		}
+	}
+
+	#[test]
+	fn output_key_policy_from_str() {
+		for policy in [OutputKeyPolicy::MerkleRootTweak, OutputKeyPolicy::PlainAggregate] {
+			assert_eq!(policy.to_string().parse::<OutputKeyPolicy>().unwrap(), policy);
+		}
+		assert!("tweaked".parse::<OutputKeyPolicy>().is_err());
+	}
 }
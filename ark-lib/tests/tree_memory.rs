@@ -0,0 +1,87 @@
+
+//! Compares the peak memory usage of building the unsigned vtxo tree of a
+//! large round at once with building it in chunks.
+//!
+//! Run with `--nocapture` to see the numbers.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bitcoin::{Amount, OutPoint};
+use bitcoin::secp256k1::{rand, Keypair};
+use rand::SeedableRng;
+
+use ark::{musig, ExitTimelockType, VtxoRequest, VtxoScriptType};
+use ark::tree::signed::{OutputKeyPolicy, VtxoTreeSpec};
+
+/// An allocator keeping track of the current and peak heap usage.
+struct PeakAlloc {
+	current: AtomicUsize,
+	peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let ret = System.alloc(layout);
+		if !ret.is_null() {
+			let current = self.current.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+			self.peak.fetch_max(current, Ordering::SeqCst);
+		}
+		ret
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		System.dealloc(ptr, layout);
+		self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+	}
+}
+
+#[global_allocator]
+static ALLOC: PeakAlloc = PeakAlloc {
+	current: AtomicUsize::new(0),
+	peak: AtomicUsize::new(0),
+};
+
+/// The peak heap usage while running `f`, above the usage before.
+fn peak_usage(f: impl FnOnce()) -> usize {
+	let start = ALLOC.current.load(Ordering::SeqCst);
+	ALLOC.peak.store(start, Ordering::SeqCst);
+	f();
+	ALLOC.peak.load(Ordering::SeqCst) - start
+}
+
+#[test]
+fn tree_construction_memory() {
+	let mut rand = rand::rngs::SmallRng::seed_from_u64(42);
+	let asp = Keypair::new(&ark::util::SECP, &mut rand);
+	let user = Keypair::new(&ark::util::SECP, &mut rand);
+	let spec = VtxoTreeSpec::new(
+		(0..10_000).map(|i| VtxoRequest {
+			pubkey: user.public_key(),
+			amount: Amount::from_sat(10_000 + i),
+		}).collect(),
+		musig::combine_keys([asp.public_key(), user.public_key()]),
+		asp.public_key(),
+		100_000,
+		2016,
+		true,
+		OutputKeyPolicy::MerkleRootTweak,
+		ExitTimelockType::Relative,
+		VtxoScriptType::Taproot,
+	);
+	let utxo = OutPoint::null();
+
+	let full = peak_usage(|| {
+		let tree = spec.build_unsigned_tree(utxo);
+		assert_eq!(tree.nb_nodes(), spec.nb_nodes());
+	});
+	let chunked = peak_usage(|| {
+		let mut nb_txs = 0;
+		spec.build_unsigned_tree_chunked(utxo, 256, |chunk| nb_txs += chunk.len());
+		assert_eq!(nb_txs, spec.nb_nodes());
+	});
+	println!("peak heap usage for {} txs: full tree {} KiB, chunked {} KiB",
+		spec.nb_nodes(), full / 1024, chunked / 1024,
+	);
+	assert!(chunked * 2 < full, "chunked: {}, full: {}", chunked, full);
+}
@@ -1,27 +1,94 @@
 
 use std::{env, fmt};
+use std::collections::VecDeque;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::str::FromStr;
 use std::time::Duration;
 
 use bitcoin::address::{Address, NetworkUnchecked};
-use bitcoin::{Amount, Network};
+use bitcoin::{Amount, FeeRate, Network, Txid};
 use serde_json;
 use tokio::fs;
 use tokio::io::AsyncReadExt;
-use tokio::process::Command as TokioCommand;
+use tokio::process::{Child, Command as TokioCommand};
 
 use bark_json::cli as json;
 
-use crate::constants::env::BARK_EXEC;
+use crate::Bitcoind;
+use crate::constants::env::{BARK_CMD_RETENTION, BARK_EXEC, CRASH_BEFORE_ONBOARD_BROADCAST_ENV};
 use crate::util::resolve_path;
 
+/// A bark command that exited unsuccessfully.
+#[derive(Debug)]
+pub struct CommandFailed {
+	pub command: String,
+	/// The exit code of the process, [None] if it was killed by a signal.
+	pub exit_code: Option<i32>,
+}
+
+impl CommandFailed {
+	/// The error category bark reported through its exit code.
+	pub fn error_code(&self) -> Option<json::ExitCode> {
+		self.exit_code.and_then(json::ExitCode::from_code)
+	}
+}
+
+impl fmt::Display for CommandFailed {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Failed to execute {}", self.command)?;
+		if let Some(code) = self.exit_code {
+			write!(f, " (exit code {})", code)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// Which of the per-command debug folders in `<datadir>/cmd` to keep.
+///
+/// The folders of failed commands are always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmdRetention {
+	/// Keep all folders.
+	All,
+	/// Keep the folders of the last N successful commands.
+	Last(usize),
+	/// Only keep the folders of failed commands.
+	FailedOnly,
+}
+
+impl CmdRetention {
+	/// The retention set in the environment, [CmdRetention::All] if unset.
+	fn from_env() -> CmdRetention {
+		match env::var(BARK_CMD_RETENTION) {
+			Err(_) => CmdRetention::All,
+			Ok(v) => v.parse().unwrap_or_else(|e| panic!("invalid {}: {}", BARK_CMD_RETENTION, e)),
+		}
+	}
+}
+
+impl FromStr for CmdRetention {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"all" => Ok(CmdRetention::All),
+			"failed" => Ok(CmdRetention::FailedOnly),
+			n => n.parse().map(CmdRetention::Last)
+				.map_err(|_| format!("expected \"all\", \"failed\" or a number, got \"{}\"", s)),
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct BarkConfig {
 	pub datadir: PathBuf,
 	pub asp_url: String,
+	/// The certificate to verify the ASP's TLS certificate with.
+	pub asp_cert: Option<PathBuf>,
 	pub network: String,
 	pub bitcoind_url: String,
 	pub bitcoind_cookie: PathBuf
@@ -33,6 +100,12 @@ pub struct Bark {
 	config: BarkConfig,
 	counter: AtomicUsize,
 	timeout: Duration,
+	cmd_retention: CmdRetention,
+	/// The numbers of the successful commands whose folders we still keep,
+	/// oldest first.
+	succeeded_cmds: Mutex<VecDeque<usize>>,
+	/// The running `bark daemon` process, if any, and its command folder.
+	daemon: Mutex<Option<(Child, PathBuf)>>,
 }
 
 impl Bark {
@@ -47,7 +120,39 @@ impl Bark {
 	}
 
 	pub async fn try_new(name: impl AsRef<str>, cfg: BarkConfig) -> anyhow::Result<Bark> {
-		let output = Bark::cmd()
+		Ok(Self::try_create(name, cfg, false, None, None).await?.0)
+	}
+
+	/// Create a new wallet and return its generated mnemonic.
+	pub async fn new_with_mnemonic(name: impl AsRef<str>, cfg: BarkConfig) -> (Bark, String) {
+		let (bark, mnemonic) = Self::try_create(name, cfg, true, None, None).await.unwrap();
+		(bark, mnemonic.expect("mnemonic was requested"))
+	}
+
+	/// Create a new wallet from the given mnemonic.
+	pub async fn new_from_mnemonic(name: impl AsRef<str>, cfg: BarkConfig, mnemonic: &str) -> Bark {
+		Self::try_create(name, cfg, false, Some(mnemonic), None).await.unwrap().0
+	}
+
+	/// Create a watch-only wallet from a watchtower bundle file.
+	pub async fn new_watch_only(name: impl AsRef<str>, cfg: BarkConfig, bundle: &Path) -> Bark {
+		Self::try_create(name, cfg, false, None, Some(("--watch", bundle))).await.unwrap().0
+	}
+
+	/// Create a watch-only wallet from a watch key file.
+	pub async fn new_watch_only_with_key(name: impl AsRef<str>, cfg: BarkConfig, key: &Path) -> Bark {
+		Self::try_create(name, cfg, false, None, Some(("--watch-key", key))).await.unwrap().0
+	}
+
+	async fn try_create(
+		name: impl AsRef<str>,
+		cfg: BarkConfig,
+		print_mnemonic: bool,
+		mnemonic: Option<&str>,
+		watch: Option<(&str, &Path)>,
+	) -> anyhow::Result<(Bark, Option<String>)> {
+		let mut cmd = Bark::cmd();
+		cmd
 			.arg("create")
 			.arg("--datadir")
 			.arg(&cfg.datadir)
@@ -58,9 +163,20 @@ impl Bark {
 			.arg("--bitcoind-cookie")
 			.arg(&cfg.bitcoind_cookie)
 			.arg("--bitcoind")
-			.arg(&cfg.bitcoind_url)
-			.output()
-			.await?;
+			.arg(&cfg.bitcoind_url);
+		if let Some(ref cert) = cfg.asp_cert {
+			cmd.arg("--asp-cert").arg(cert);
+		}
+		if print_mnemonic {
+			cmd.arg("--print-mnemonic");
+		}
+		if let Some(mnemonic) = mnemonic {
+			cmd.arg("--mnemonic").arg(mnemonic);
+		}
+		if let Some((flag, file)) = watch {
+			cmd.arg(flag).arg(file);
+		}
+		let output = cmd.output().await?;
 
 		info!("Ran command");
 		if !output.status.success() {
@@ -74,27 +190,117 @@ impl Bark {
 			bail!("Failed to create {}", name.as_ref());
 		}
 
-		Ok(Bark {
+		let mnemonic = if print_mnemonic {
+			Some(String::from_utf8(output.stdout)?.trim().to_string())
+		} else {
+			None
+		};
+
+		let bark = Bark {
 			name: name.as_ref().to_string(),
 			config: cfg,
 			counter: AtomicUsize::new(0),
 			timeout: Duration::from_millis(10_000),
-		})
+			cmd_retention: CmdRetention::from_env(),
+			succeeded_cmds: Mutex::new(VecDeque::new()),
+			daemon: Mutex::new(None),
+		};
+		Ok((bark, mnemonic))
 	}
 
 	pub fn name(&self) -> &str {
 		&self.name
 	}
 
+	pub fn datadir(&self) -> &Path {
+		&self.config.datadir
+	}
+
+	/// Set which of the per-command debug folders to keep.
+	pub fn set_cmd_retention(&mut self, retention: CmdRetention) {
+		self.cmd_retention = retention;
+	}
+
+	fn cmd_folder(&self, count: usize) -> PathBuf {
+		self.config.datadir.join("cmd").join(count.to_string())
+	}
+
+	/// Remove the folders of successful commands our retention doesn't keep.
+	async fn prune_cmd_folders(&self, succeeded: usize) {
+		let keep = match self.cmd_retention {
+			CmdRetention::All => return,
+			CmdRetention::Last(n) => n,
+			CmdRetention::FailedOnly => 0,
+		};
+		let prune = {
+			let mut cmds = self.succeeded_cmds.lock().unwrap();
+			cmds.push_back(succeeded);
+			let excess = cmds.len().saturating_sub(keep);
+			cmds.drain(..excess).collect::<Vec<_>>()
+		};
+		for count in prune {
+			let folder = self.cmd_folder(count);
+			if let Err(e) = fs::remove_dir_all(&folder).await {
+				warn!("Failed to remove command folder {}: {}", folder.display(), e);
+			}
+		}
+	}
+
+	/// Create a copy of this wallet with its current state in a new datadir.
+	///
+	/// This can be used to simulate a user restoring an outdated backup.
+	pub async fn clone_to(&self, name: impl AsRef<str>, datadir: PathBuf) -> Bark {
+		let status = TokioCommand::new("cp")
+			.arg("-r")
+			.arg(&self.config.datadir)
+			.arg(&datadir)
+			.status()
+			.await
+			.expect("failed to run cp");
+		assert!(status.success(), "failed to copy datadir of {}", self.name);
+
+		Bark {
+			name: name.as_ref().to_string(),
+			config: BarkConfig {
+				datadir,
+				asp_url: self.config.asp_url.clone(),
+				asp_cert: self.config.asp_cert.clone(),
+				network: self.config.network.clone(),
+				bitcoind_url: self.config.bitcoind_url.clone(),
+				bitcoind_cookie: self.config.bitcoind_cookie.clone(),
+			},
+			counter: AtomicUsize::new(self.counter.load(Ordering::Relaxed)),
+			timeout: self.timeout,
+			cmd_retention: self.cmd_retention,
+			succeeded_cmds: Mutex::new(self.succeeded_cmds.lock().unwrap().clone()),
+			daemon: Mutex::new(None),
+		}
+	}
+
 	pub async fn onchain_balance(&self) -> Amount {
 		self.run(["onchain", "balance"]).await.parse().unwrap()
 	}
 
+	pub async fn balance(&self) -> json::Balance {
+		let json = self.run(["balance", "--json"]).await;
+		serde_json::from_str::<json::Balance>(&json).unwrap()
+	}
+
 	pub async fn offchain_balance(&self) -> Amount {
 		let json = self.run(["balance", "--json"]).await;
 		serde_json::from_str::<json::Balance>(&json).unwrap().offchain
 	}
 
+	pub async fn onchain_reserve(&self) -> Amount {
+		let json = self.run(["balance", "--json"]).await;
+		serde_json::from_str::<json::Balance>(&json).unwrap().onchain_reserve
+	}
+
+	pub async fn status(&self) -> json::Status {
+		let json = self.run(["status", "--json"]).await;
+		serde_json::from_str::<json::Status>(&json).unwrap()
+	}
+
 	pub async fn get_onchain_address(&self) -> Address {
 		let address_string = self.run(["onchain", "address"]).await.trim().to_string();
 		Address::<NetworkUnchecked>::from_str(&address_string).unwrap()
@@ -110,16 +316,68 @@ impl Bark {
 		self.run(["vtxo-pubkey"]).await
 	}
 
+	pub async fn fresh_vtxo_pubkey(&self) -> String {
+		self.run(["vtxo-pubkey", "--fresh"]).await
+	}
+
 	pub async fn send_round(&self, destination: impl fmt::Display, amount: Amount) {
 		let destination = destination.to_string();
 		let amount = amount.to_string();
 		self.run(["send-round", &destination, &amount, "--verbose"]).await;
 	}
 
+	/// Send a round payment delivered as one vtxo per split amount.
+	pub async fn send_round_split(&self, destination: impl fmt::Display, splits: &[Amount]) {
+		let amount = splits.iter().copied().sum::<Amount>();
+		info!("{}: Send {} to {} in round, split into {:?}", self.name, amount, destination, splits);
+		let mut args = vec!["send-round".to_string(), destination.to_string(), amount.to_string()];
+		for split in splits {
+			args.push("--split".into());
+			args.push(split.to_string());
+		}
+		self.run(args).await;
+	}
+
+	pub async fn try_send_oor(&self, destination: impl fmt::Display, amount: Amount) -> anyhow::Result<()> {
+		let destination = destination.to_string();
+		let amount = amount.to_string();
+		self.try_run(["send", &destination, &amount, "--verbose"]).await?;
+		Ok(())
+	}
+
 	pub async fn send_oor(&self, destination: impl fmt::Display, amount: Amount) {
+		self.try_send_oor(destination, amount).await.unwrap();
+	}
+
+	/// Send an OOR payment that labels our change vtxo.
+	pub async fn send_oor_with_label(&self, destination: impl fmt::Display, amount: Amount, label: &str) {
+		let destination = destination.to_string();
+		let amount = amount.to_string();
+		self.run(["send", &destination, &amount, "--label", label, "--verbose"]).await;
+	}
+
+	/// Pay an on-chain address from our off-chain balance in a round.
+	pub async fn send_onchain(&self, destination: impl fmt::Display, amount: Amount) {
+		info!("{}: Send {} to on-chain address {}", self.name, amount, destination);
 		let destination = destination.to_string();
 		let amount = amount.to_string();
-		self.run(["send", &destination, &amount, "--verbose"]).await;
+		self.run(["send", "--onchain", &destination, &amount, "--verbose"]).await;
+	}
+
+	/// Preview an arkoor payment without sending it.
+	pub async fn simulate_send(&self, destination: impl fmt::Display, amount: Amount) -> json::SendPreview {
+		let destination = destination.to_string();
+		let amount = amount.to_string();
+		let res = self.run(["send", &destination, &amount, "--simulate", "--json"]).await;
+		serde_json::from_str(&res).expect("json error")
+	}
+
+	/// Preview a round payment without sending it.
+	pub async fn simulate_send_round(&self, destination: impl fmt::Display, amount: Amount) -> json::SendPreview {
+		let destination = destination.to_string();
+		let amount = amount.to_string();
+		let res = self.run(["send-round", &destination, &amount, "--simulate", "--json"]).await;
+		serde_json::from_str(&res).expect("json error")
 	}
 
 	pub async fn try_send_bolt11(&self, destination :impl fmt::Display, amount: Option<Amount>)-> anyhow::Result<()> {
@@ -142,8 +400,58 @@ impl Bark {
 		self.run(["onboard", &amount.to_string()]).await;
 	}
 
+	/// Onboard and label the new vtxo.
+	pub async fn onboard_with_label(&self, amount: Amount, label: &str) {
+		info!("{}: Onboard {} with label {}", self.name, amount, label);
+		self.run(["onboard", &amount.to_string(), "--label", label]).await;
+	}
+
+	/// Onboard a separate vtxo for each amount, all in a single onboard tx.
+	pub async fn onboard_many(&self, amounts: &[Amount]) {
+		info!("{}: Onboard {:?}", self.name, amounts);
+		let mut args = vec!["onboard".to_string()];
+		args.extend(amounts.iter().map(|a| a.to_string()));
+		self.run(args).await;
+	}
+
+	/// Onboard and wait until the onboard tx is confirmed.
+	///
+	/// Blocks are generated on the given bitcoind until the onboard tx confirms.
+	pub async fn onboard_and_confirm(&self, amount: Amount, bitcoind: &Bitcoind) {
+		info!("{}: Onboard {} and wait for confirmation", self.name, amount);
+		let amount = amount.to_string();
+		let onboard = self.run(["onboard", &amount, "--wait"]);
+		tokio::pin!(onboard);
+		loop {
+			tokio::select! {
+				_ = &mut onboard => break,
+				_ = tokio::time::sleep(Duration::from_millis(500)) => bitcoind.generate(1).await,
+			}
+		}
+	}
+
+	pub async fn try_refresh_all(&self) -> anyhow::Result<()> {
+		self.try_run(["refresh", "--all"]).await?;
+		Ok(())
+	}
+
 	pub async fn refresh_all(&self) {
-		self.run(["refresh", "--all"]).await;
+		self.try_refresh_all().await.unwrap();
+	}
+
+	/// Refresh all vtxos and add a label to the refreshed vtxo.
+	pub async fn refresh_all_with_label(&self, label: &str) {
+		self.run(["refresh", "--all", "--label", label]).await;
+	}
+
+	/// Drop all vtxos from the wallet database.
+	pub async fn drop_vtxos(&self) {
+		self.run(["drop-vtxos"]).await;
+	}
+
+	/// Fetch all round vtxos of the wallet from the ASP.
+	pub async fn restore_vtxos(&self) {
+		self.run(["restore-vtxos"]).await;
 	}
 
 	pub async fn exit(&self) -> json::ExitStatus {
@@ -151,8 +459,145 @@ impl Bark {
 		serde_json::from_str::<json::ExitStatus>(&res).expect("invalid json from exit")
 	}
 
+	/// Exit and claim only the given vtxos.
+	pub async fn exit_vtxos(&self, vtxos: &[impl fmt::Display]) -> json::ExitStatus {
+		let mut args = vec!["exit".to_string(), "--json".to_string()];
+		for vtxo in vtxos {
+			args.push("--vtxo".into());
+			args.push(vtxo.to_string());
+		}
+		let res = self.run(args).await;
+		serde_json::from_str::<json::ExitStatus>(&res).expect("invalid json from exit")
+	}
+
+	pub async fn exit_with_fee_rate(&self, fee_rate: FeeRate) -> json::ExitStatus {
+		let fee_rate = fee_rate.to_sat_per_vb_ceil().to_string();
+		let res = self.run(["exit", "--json", "--feerate", &fee_rate]).await;
+		serde_json::from_str::<json::ExitStatus>(&res).expect("invalid json from exit")
+	}
+
+	/// Check whether the exit claim tx at the given fee rate would be accepted.
+	pub async fn verify_exit_with_fee_rate(&self, fee_rate: FeeRate) -> json::ExitVerification {
+		let fee_rate = fee_rate.to_sat_per_vb_ceil().to_string();
+		let res = self.run(["exit", "--json", "--verify", "--feerate", &fee_rate]).await;
+		serde_json::from_str::<json::ExitVerification>(&res).expect("invalid json from exit")
+	}
+
+	/// Export the tree txs of a vtxo to a file in the datadir and return its path.
+	pub async fn export_tree_txs(&self, vtxo: impl fmt::Display) -> PathBuf {
+		let path = self.config.datadir.join("tree_txs.hex");
+		self.run(["export-tree-txs", &vtxo.to_string(), "--file", path.to_str().unwrap()]).await;
+		path
+	}
+
+	/// Broadcast the tree txs in the file and return the txids that were broadcast.
+	pub async fn broadcast_tree(&self, file: &Path) -> Vec<Txid> {
+		let res = self.run(["broadcast-tree", "--json", file.to_str().unwrap()]).await;
+		serde_json::from_str::<Vec<Txid>>(&res).expect("invalid json from broadcast-tree")
+	}
+
+	/// Export a watchtower bundle to a file in the datadir and return its path.
+	pub async fn export_watchtower(&self) -> PathBuf {
+		let path = self.config.datadir.join("watchtower.hex");
+		self.run(["export-watchtower", "--file", path.to_str().unwrap()]).await;
+		path
+	}
+
+	/// Export a watch key to a file in the datadir and return its path.
+	pub async fn export_watch_key(&self) -> PathBuf {
+		let path = self.config.datadir.join("watch_key.hex");
+		self.run(["export-watch-key", "--file", path.to_str().unwrap()]).await;
+		path
+	}
+
+	pub async fn import_watchtower(&self, file: &Path) -> Vec<json::WatchtowerVtxoInfo> {
+		let res = self.run(["import-watchtower", file.to_str().unwrap(), "--json"]).await;
+		serde_json::from_str(&res).expect("invalid json from import-watchtower")
+	}
+
+	/// Start an onboard, but have bark crash right before broadcasting it.
+	///
+	/// This needs a bark built with the `test_hooks` feature.
+	pub async fn onboard_crash_before_broadcast(&self, amount: Amount) -> CommandFailed {
+		info!("{}: Onboard {}, crashing before broadcast", self.name, amount);
+		let err = self.try_run_with_env(
+			["onboard", &amount.to_string()],
+			[(CRASH_BEFORE_ONBOARD_BROADCAST_ENV, "1")],
+		).await.expect_err("onboard should crash");
+		err.downcast::<CommandFailed>().expect("bark should have crashed")
+	}
+
+	/// Give up on an onboard that can't be broadcast anymore.
+	pub async fn abandon_onboard(&self) {
+		info!("{}: Abandon onboard", self.name);
+		self.run(["abandon-onboard"]).await;
+	}
+
+	/// Start `bark daemon` in the background to refresh expiring VTXOs.
+	///
+	/// The output of the daemon is written to a command folder like the
+	/// output of all other commands.
+	pub async fn start_daemon(&self) {
+		let mut daemon = self.daemon.lock().unwrap();
+		assert!(daemon.is_none(), "daemon of {} is already running", self.name);
+
+		let mut command = Bark::cmd();
+		command.args(&[
+			"--verbose",
+			"--datadir",
+			&self.config.datadir.as_os_str().to_str().unwrap(),
+			"daemon",
+		]);
+		let count = self.counter.fetch_add(1, Ordering::Relaxed);
+		let folder = self.cmd_folder(count);
+		std::fs::create_dir_all(&folder).unwrap();
+		std::fs::write(folder.join("cmd"), format!("{:?}", command.as_std())).unwrap();
+		command.stderr(std::fs::File::create(folder.join("stderr.log")).unwrap());
+		command.stdout(std::fs::File::create(folder.join("stdout.log")).unwrap());
+		command.kill_on_drop(true);
+		*daemon = Some((command.spawn().expect("failed to start bark daemon"), folder));
+	}
+
+	/// The log output of the running daemon so far.
+	pub fn daemon_log(&self) -> String {
+		let daemon = self.daemon.lock().unwrap();
+		let (_, folder) = daemon.as_ref()
+			.unwrap_or_else(|| panic!("daemon of {} isn't running", self.name));
+		std::fs::read_to_string(folder.join("stderr.log")).unwrap()
+	}
+
+	/// Stop the daemon started with [Bark::start_daemon] and wait for it to exit.
+	pub async fn stop_daemon(&self) {
+		let (mut child, _) = self.daemon.lock().unwrap().take()
+			.unwrap_or_else(|| panic!("daemon of {} isn't running", self.name));
+		let pid = child.id().expect("daemon already exited");
+		let status = TokioCommand::new("kill")
+			.arg("-TERM")
+			.arg(pid.to_string())
+			.status()
+			.await
+			.expect("failed to run kill");
+		assert!(status.success(), "failed to signal daemon of {}", self.name);
+		let exit = tokio::time::timeout(self.timeout, child.wait()).await
+			.expect("daemon didn't shut down in time")
+			.expect("failed to wait for daemon");
+		assert!(exit.success(), "daemon of {} exited with {}", self.name, exit);
+	}
+
 	pub async fn try_run<I,S>(&self, args: I) -> anyhow::Result<String>
 		where I: IntoIterator<Item = S>, S : AsRef<str>
+	{
+		self.try_run_with_env(args, std::iter::empty::<(&str, &str)>()).await
+	}
+
+	/// Run a command with extra environment variables set.
+	pub async fn try_run_with_env<I, S, E, K, V>(&self, args: I, envs: E) -> anyhow::Result<String>
+	where
+		I: IntoIterator<Item = S>,
+		S: AsRef<str>,
+		E: IntoIterator<Item = (K, V)>,
+		K: AsRef<std::ffi::OsStr>,
+		V: AsRef<std::ffi::OsStr>,
 	{
 		let args: Vec<String>  = args.into_iter().map(|x| x.as_ref().to_string()).collect();
 
@@ -163,11 +608,12 @@ impl Bark {
 			&self.config.datadir.as_os_str().to_str().unwrap(),
 		]);
 		command.args(args);
+		command.envs(envs);
 		let command_str = format!("{:?}", command.as_std());
 
 		// Create a folder for each command
 		let count = self.counter.fetch_add(1, Ordering::Relaxed);
-		let folder = self.config.datadir.join("cmd").join(count.to_string());
+		let folder = self.cmd_folder(count);
 		fs::create_dir_all(&folder).await?;
 		fs::write(folder.join("cmd"), &command_str).await?;
 
@@ -197,10 +643,11 @@ impl Bark {
 			);
 		}
 		if exit.success() {
+			self.prune_cmd_folders(count).await;
 			Ok(out.trim().to_string())
 		}
 		else {
-			bail!("Failed to execute {:?}", command)
+			bail!(CommandFailed { command: command_str, exit_code: exit.code() })
 		}
 	}
 
@@ -5,4 +5,9 @@ pub mod env {
 	pub const ASPD_EXEC: &str = "ASPD_EXEC";
 	pub const LIGHTNINGD_EXEC: &str = "LIGHTNINGD_EXEC";
 	pub const LIGHTNINGD_PLUGINS: &str = "LIGHTNINGD_PLUGINS";
+	/// Makes bark abort right before broadcasting an onboard tx.
+	pub const CRASH_BEFORE_ONBOARD_BROADCAST_ENV: &str = "BARK_CRASH_BEFORE_ONBOARD_BROADCAST";
+	/// The default [CmdRetention](crate::bark::CmdRetention) of barks:
+	/// "all", "failed" or the number of successful commands to keep.
+	pub const BARK_CMD_RETENTION: &str = "BARK_CMD_RETENTION";
 }
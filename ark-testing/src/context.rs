@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use bitcoin::Network;
@@ -74,6 +74,34 @@ impl TestContext {
 			round_submit_time: Duration::from_millis(500),
 			round_sign_time: Duration::from_millis(500),
 			nb_round_nonces: 100,
+			max_round_inputs: None,
+			nb_round_asp_cosigners: None,
+			vtxo_expiry_delta: None,
+			vtxo_exit_timelock: None,
+			round_tx_feerate: None,
+			round_tx_bump_after: None,
+			round_tx_bump_feerate: None,
+			min_feerate: None,
+			round_tx_precheck: None,
+			round_change: None,
+			round_change_vtxo_max: None,
+			round_tx_version: None,
+			fee_scheme: None,
+			public_rpc_tls_cert_path: None,
+			public_rpc_tls_key_path: None,
+			wallet_rotate_addresses: None,
+			onboard_confirmations: None,
+			onboard_max_confirmations: None,
+			onboard_nonce_pool_size: None,
+			max_vtxo_lifetime_blocks: None,
+			sweep_batch_max_inputs: None,
+			sweep_mode: None,
+			sweep_interval: None,
+			sweep_grace_blocks: None,
+			oor_min_amount: None,
+			admin_rpc_token: None,
+			mnemonic: None,
+			birthday: None,
 			cln_grpc_uri: None,
 			cln_grpc_server_cert_path: None,
 			cln_grpc_client_cert_path: None,
@@ -92,24 +120,73 @@ impl TestContext {
 		self.aspd_with_cfg(name, self.aspd_default_cfg(name, bitcoind, lightningd).await).await
 	}
 
-	pub async fn try_bark(&self, name: impl AsRef<str>, bitcoind: &Bitcoind, aspd: &Aspd) -> anyhow::Result<Bark> {
-		let datadir = self.datadir.join(name.as_ref());
-		let asp_url = aspd.asp_url();
-
-		let cfg = BarkConfig {
-			datadir,
-			asp_url,
+	pub fn bark_cfg(&self, name: impl AsRef<str>, bitcoind: &Bitcoind, aspd: &Aspd) -> BarkConfig {
+		BarkConfig {
+			datadir: self.datadir.join(name.as_ref()),
+			asp_url: aspd.asp_url(),
+			asp_cert: aspd.tls_cert().cloned(),
 			bitcoind_url: bitcoind.rpc_url(),
 			bitcoind_cookie: bitcoind.rpc_cookie(),
 			network: String::from("regtest"),
-		};
+		}
+	}
+
+	pub async fn try_bark(&self, name: impl AsRef<str>, bitcoind: &Bitcoind, aspd: &Aspd) -> anyhow::Result<Bark> {
+		let cfg = self.bark_cfg(name.as_ref(), bitcoind, aspd);
 		Bark::try_new(name, cfg).await
 	}
 
+	/// Create a new bark and return the generated mnemonic.
+	pub async fn bark_with_mnemonic(
+		&self,
+		name: impl AsRef<str>,
+		bitcoind: &Bitcoind,
+		aspd: &Aspd,
+	) -> (Bark, String) {
+		let cfg = self.bark_cfg(name.as_ref(), bitcoind, aspd);
+		Bark::new_with_mnemonic(name, cfg).await
+	}
+
+	/// Create a new bark that restores the wallet with the given mnemonic.
+	pub async fn bark_from_mnemonic(
+		&self,
+		name: impl AsRef<str>,
+		bitcoind: &Bitcoind,
+		aspd: &Aspd,
+		mnemonic: &str,
+	) -> Bark {
+		let cfg = self.bark_cfg(name.as_ref(), bitcoind, aspd);
+		Bark::new_from_mnemonic(name, cfg, mnemonic).await
+	}
+
 	pub async fn bark(&self, name: impl AsRef<str>, bitcoind: &Bitcoind, aspd: &Aspd) -> Bark {
 		self.try_bark(name, &bitcoind, &aspd).await.unwrap()
 	}
 
+	/// Create a watch-only bark for the vtxos in the watchtower bundle file.
+	pub async fn bark_watch_only(
+		&self,
+		name: impl AsRef<str>,
+		bitcoind: &Bitcoind,
+		aspd: &Aspd,
+		bundle: &Path,
+	) -> Bark {
+		let cfg = self.bark_cfg(name.as_ref(), bitcoind, aspd);
+		Bark::new_watch_only(name, cfg, bundle).await
+	}
+
+	/// Create a watch-only bark for the vtxos of the watch key file.
+	pub async fn bark_watch_key(
+		&self,
+		name: impl AsRef<str>,
+		bitcoind: &Bitcoind,
+		aspd: &Aspd,
+		key: &Path,
+	) -> Bark {
+		let cfg = self.bark_cfg(name.as_ref(), bitcoind, aspd);
+		Bark::new_watch_only_with_key(name, cfg, key).await
+	}
+
 	pub async fn lightningd(&self, name: impl AsRef<str>, bitcoind: &Bitcoind) -> Lightningd {
 		let datadir = self.datadir.join(name.as_ref());
 
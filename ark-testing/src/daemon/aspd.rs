@@ -4,11 +4,11 @@ use std::time::Duration;
 use std::path::PathBuf;
 use std::process::Command;
 
-use bitcoin::Network;
+use bitcoin::{Amount, FeeRate, Network};
 use bitcoin::address::{Address, NetworkUnchecked};
 
 use aspd_rpc_client::{AdminServiceClient, ArkServiceClient};
-use aspd_rpc_client::Empty;
+use aspd_rpc_client::{Empty, TriggerRoundRequest, ADMIN_TOKEN_HEADER};
 
 use crate::{Daemon, DaemonHelper, Lightningd};
 use crate::constants::env::ASPD_EXEC;
@@ -24,6 +24,8 @@ pub struct AspdHelper {
 	name : String,
 	state: AspdState,
 	config: AspdConfig,
+	/// `ARKD_*` config overrides aspd is started with.
+	env_overrides: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +37,41 @@ pub struct AspdConfig {
 	pub round_submit_time: Duration,
 	pub round_sign_time: Duration,
 	pub nb_round_nonces: usize,
+	pub max_round_inputs: Option<usize>,
+	pub nb_round_asp_cosigners: Option<usize>,
+	pub vtxo_expiry_delta: Option<u16>,
+	/// Either "relative" or "absolute".
+	pub vtxo_exit_timelock: Option<String>,
+	pub round_tx_feerate: Option<FeeRate>,
+	pub round_tx_bump_after: Option<u32>,
+	pub round_tx_bump_feerate: Option<FeeRate>,
+	pub min_feerate: Option<FeeRate>,
+	pub round_tx_precheck: Option<bool>,
+	/// Either "onchain" or "vtxo".
+	pub round_change: Option<String>,
+	pub round_change_vtxo_max: Option<Amount>,
+	pub round_tx_version: Option<i32>,
+	/// Either "keyless_anchor", "p2a_anchor" or "cpfp_change".
+	pub fee_scheme: Option<String>,
+	/// Serve the public gRPC service over TLS for "localhost".
+	pub public_rpc_tls_cert_path: Option<PathBuf>,
+	pub public_rpc_tls_key_path: Option<PathBuf>,
+	pub wallet_rotate_addresses: Option<bool>,
+	pub onboard_confirmations: Option<u32>,
+	pub onboard_max_confirmations: Option<u32>,
+	pub onboard_nonce_pool_size: Option<usize>,
+	pub max_vtxo_lifetime_blocks: Option<u32>,
+	pub sweep_batch_max_inputs: Option<usize>,
+	/// Either "auto" or "manual".
+	pub sweep_mode: Option<String>,
+	pub sweep_interval: Option<Duration>,
+	pub sweep_grace_blocks: Option<u32>,
+	pub oor_min_amount: Option<Amount>,
+	pub admin_rpc_token: Option<String>,
+	/// Restore from this mnemonic instead of generating a new one.
+	pub mnemonic: Option<String>,
+	/// The height from which the wallet of a restored mnemonic is scanned.
+	pub birthday: Option<u32>,
 	pub cln_grpc_uri: Option<String>,
 	pub cln_grpc_server_cert_path: Option<PathBuf>,
 	pub cln_grpc_client_cert_path: Option<PathBuf>,
@@ -59,6 +96,7 @@ impl Aspd {
 			name: name.as_ref().to_string(),
 			config,
 			state: AspdState::default(),
+			env_overrides: Vec::new(),
 		};
 
 		Daemon::wrap(helper)
@@ -68,6 +106,17 @@ impl Aspd {
 		self.inner.asp_url()
 	}
 
+	/// Override a config field with an `ARKD_*` environment variable when
+	/// aspd is (re)started from now on.
+	pub fn set_config_override(&mut self, key: &str, value: impl ToString) {
+		self.inner.env_overrides.push((key.to_string(), value.to_string()));
+	}
+
+	/// The TLS certificate of the public gRPC service, if it uses TLS.
+	pub fn tls_cert(&self) -> Option<&PathBuf> {
+		self.inner.config.public_rpc_tls_cert_path.as_ref()
+	}
+
 	pub async fn get_admin_client(&self) -> AdminClient {
 		self.inner.connect_admin_client().await.unwrap()
 	}
@@ -84,7 +133,17 @@ impl Aspd {
 	}
 
 	pub async fn trigger_round(&self) {
-		self.get_admin_client().await.trigger_round(Empty {}).await.unwrap();
+		self.get_admin_client().await.trigger_round(TriggerRoundRequest::default()).await.unwrap();
+	}
+
+	/// Request a graceful shutdown using the configured admin token.
+	pub async fn shutdown(&self) -> Result<(), tonic::Status> {
+		let mut req = tonic::Request::new(Empty {});
+		if let Some(ref token) = self.inner.config.admin_rpc_token {
+			req.metadata_mut().insert(ADMIN_TOKEN_HEADER, token.parse().unwrap());
+		}
+		self.get_admin_client().await.shutdown(req).await?;
+		Ok(())
 	}
 }
 
@@ -140,6 +199,27 @@ impl DaemonHelper for AspdHelper {
 			let round_submit_time = cfg.round_submit_time.as_millis().to_string();
 			let round_sign_time = cfg.round_sign_time.as_millis().to_string();
 			let nb_round_nonces = cfg.nb_round_nonces.to_string();
+			let vtxo_expiry_delta = cfg.vtxo_expiry_delta.map(|d| d.to_string());
+			let round_tx_feerate = cfg.round_tx_feerate.map(|f| (f.to_sat_per_kwu() * 4).to_string());
+			let round_tx_bump_after = cfg.round_tx_bump_after.map(|b| b.to_string());
+			let round_tx_bump_feerate = cfg.round_tx_bump_feerate
+				.map(|f| (f.to_sat_per_kwu() * 4).to_string());
+			let min_feerate = cfg.min_feerate.map(|f| (f.to_sat_per_kwu() * 4).to_string());
+			let max_round_inputs = cfg.max_round_inputs.map(|m| m.to_string());
+			let nb_round_asp_cosigners = cfg.nb_round_asp_cosigners.map(|n| n.to_string());
+			let round_tx_precheck = cfg.round_tx_precheck.map(|p| p.to_string());
+			let wallet_rotate_addresses = cfg.wallet_rotate_addresses.map(|r| r.to_string());
+			let onboard_confirmations = cfg.onboard_confirmations.map(|c| c.to_string());
+			let onboard_max_confirmations = cfg.onboard_max_confirmations.map(|c| c.to_string());
+			let onboard_nonce_pool_size = cfg.onboard_nonce_pool_size.map(|s| s.to_string());
+			let max_vtxo_lifetime_blocks = cfg.max_vtxo_lifetime_blocks.map(|b| b.to_string());
+			let sweep_batch_max_inputs = cfg.sweep_batch_max_inputs.map(|m| m.to_string());
+			let sweep_interval = cfg.sweep_interval.map(|i| i.as_millis().to_string());
+			let sweep_grace_blocks = cfg.sweep_grace_blocks.map(|b| b.to_string());
+			let oor_min_amount = cfg.oor_min_amount.map(|a| a.to_sat().to_string());
+			let round_change_vtxo_max = cfg.round_change_vtxo_max.map(|a| a.to_sat().to_string());
+			let birthday = cfg.birthday.map(|b| b.to_string());
+			let round_tx_version = cfg.round_tx_version.map(|v| v.to_string());
 
 			let mut args = vec![
 				"create",
@@ -153,6 +233,92 @@ impl DaemonHelper for AspdHelper {
 				"--nb-round-nonces", &nb_round_nonces
 			];
 
+			if let Some(ref v) = max_round_inputs {
+				args.extend(["--max-round-inputs", v]);
+			}
+			if let Some(ref v) = nb_round_asp_cosigners {
+				args.extend(["--nb-round-asp-cosigners", v]);
+			}
+			if let Some(ref v) = vtxo_expiry_delta {
+				args.extend(["--vtxo-expiry-delta", v]);
+			}
+			if let Some(ref v) = cfg.vtxo_exit_timelock {
+				args.extend(["--vtxo-exit-timelock", v]);
+			}
+			if let Some(ref v) = round_tx_feerate {
+				args.extend(["--round-tx-feerate-sat-per-kvb", v]);
+			}
+			if let Some(ref v) = round_tx_bump_after {
+				args.extend(["--round-tx-bump-after", v]);
+			}
+			if let Some(ref v) = round_tx_bump_feerate {
+				args.extend(["--round-tx-bump-feerate-sat-per-kvb", v]);
+			}
+			if let Some(ref v) = min_feerate {
+				args.extend(["--min-feerate-sat-per-kvb", v]);
+			}
+			if let Some(ref v) = round_tx_precheck {
+				args.extend(["--round-tx-precheck", v]);
+			}
+			if let Some(ref v) = cfg.round_change {
+				args.extend(["--round-change", v]);
+			}
+			if let Some(ref v) = round_change_vtxo_max {
+				args.extend(["--round-change-vtxo-max-sat", v]);
+			}
+			if let Some(ref v) = round_tx_version {
+				args.extend(["--round-tx-version", v]);
+			}
+			if let Some(ref v) = cfg.fee_scheme {
+				args.extend(["--fee-scheme", v]);
+			}
+			let tls_cert = cfg.public_rpc_tls_cert_path.as_ref().map(|p| p.display().to_string());
+			if let Some(ref v) = tls_cert {
+				args.extend(["--public-rpc-tls-cert-path", v]);
+			}
+			let tls_key = cfg.public_rpc_tls_key_path.as_ref().map(|p| p.display().to_string());
+			if let Some(ref v) = tls_key {
+				args.extend(["--public-rpc-tls-key-path", v]);
+			}
+			if let Some(ref v) = wallet_rotate_addresses {
+				args.extend(["--wallet-rotate-addresses", v]);
+			}
+			if let Some(ref v) = onboard_confirmations {
+				args.extend(["--onboard-confirmations", v]);
+			}
+			if let Some(ref v) = onboard_max_confirmations {
+				args.extend(["--onboard-max-confirmations", v]);
+			}
+			if let Some(ref v) = onboard_nonce_pool_size {
+				args.extend(["--onboard-nonce-pool-size", v]);
+			}
+			if let Some(ref v) = max_vtxo_lifetime_blocks {
+				args.extend(["--max-vtxo-lifetime-blocks", v]);
+			}
+			if let Some(ref v) = sweep_batch_max_inputs {
+				args.extend(["--sweep-batch-max-inputs", v]);
+			}
+			if let Some(ref v) = cfg.sweep_mode {
+				args.extend(["--sweep-mode", v]);
+			}
+			if let Some(ref v) = sweep_interval {
+				args.extend(["--sweep-interval", v]);
+			}
+			if let Some(ref v) = sweep_grace_blocks {
+				args.extend(["--sweep-grace-blocks", v]);
+			}
+			if let Some(ref v) = oor_min_amount {
+				args.extend(["--oor-min-amount-sat", v]);
+			}
+			if let Some(ref v) = cfg.admin_rpc_token {
+				args.extend(["--admin-rpc-token", v]);
+			}
+			if let Some(ref v) = cfg.mnemonic {
+				args.extend(["--mnemonic", v]);
+			}
+			if let Some(ref v) = birthday {
+				args.extend(["--birthday", v]);
+			}
 
 			if cfg.cln_grpc_uri.is_some() {
 				args.extend(["--cln-grpc-uri", cfg.cln_grpc_uri.as_ref().unwrap()]);
@@ -191,7 +357,8 @@ impl DaemonHelper for AspdHelper {
 		base_cmd
 			.arg("--datadir")
 			.arg(&self.config.datadir)
-			.arg("start");
+			.arg("start")
+			.envs(self.env_overrides.iter().map(|(k, v)| (k, v)));
 
 		Ok(base_cmd)
 	}
@@ -226,7 +393,14 @@ impl AspdHelper {
 	}
 
 	pub fn asp_url(&self) -> String {
-		format!("http://{}", self.state.public_grpc_address.clone().expect("asp not running"))
+		let addr = self.state.public_grpc_address.clone().expect("asp not running");
+		if self.config.public_rpc_tls_cert_path.is_some() {
+			// The certificate is for localhost, not for the address we bind to.
+			let port = addr.rsplit(':').next().unwrap();
+			format!("https://localhost:{}", port)
+		} else {
+			format!("http://{}", addr)
+		}
 	}
 
 	pub fn admin_url(&self) -> String {
@@ -234,7 +408,16 @@ impl AspdHelper {
 	}
 
 	pub async fn connect_public_client(&self) -> Result<ArkClient, tonic::transport::Error> {
-		ArkClient::connect(self.asp_url()).await
+		let mut endpoint = tonic::transport::Channel::from_shared(self.asp_url())
+			.expect("valid url");
+		if let Some(ref path) = self.config.public_rpc_tls_cert_path {
+			let pem = std::fs::read(path).expect("failed to read TLS certificate");
+			endpoint = endpoint.tls_config(tonic::transport::ClientTlsConfig::new()
+				.domain_name("localhost")
+				.ca_certificate(tonic::transport::Certificate::from_pem(pem)),
+			)?;
+		}
+		Ok(ArkClient::new(endpoint.connect().await?))
 	}
 
 	pub async fn connect_admin_client(&self) -> Result<AdminClient, tonic::transport::Error> {
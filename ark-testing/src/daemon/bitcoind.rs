@@ -24,6 +24,8 @@ pub struct BitcoindConfig {
 	pub network: Network,
 	pub fallback_fee: FeeRate,
 	pub relay_fee: Option<FeeRate>,
+	/// Don't mine txs that pay less than this fee rate.
+	pub block_min_fee: Option<FeeRate>,
 }
 
 impl Default for BitcoindConfig {
@@ -34,6 +36,7 @@ impl Default for BitcoindConfig {
 			network: Network::Regtest,
 			fallback_fee: FeeRate::from_sat_per_vb(1).unwrap(),
 			relay_fee: None,
+			block_min_fee: None,
 		}
 	}
 }
@@ -109,6 +112,12 @@ impl Bitcoind {
 		).unwrap();
 	}
 
+	/// Generate blocks paying their coinbase to the aspd's wallet.
+	pub async fn generate_to_aspd(&self, aspd: &Aspd, block_num: u64) {
+		let address = aspd.get_funding_address().await;
+		self.sync_client().generate_to_address(block_num, &address).unwrap();
+	}
+
 	pub async fn fund_bark(&self, bark: &Bark, amount: Amount) -> Txid {
 		info!("Fund {} {}", bark.name(), amount);
 		let address = bark.get_onchain_address().await;
@@ -201,6 +210,9 @@ impl DaemonHelper for BitcoindHelper {
 		if let Some(fr) = self.config.relay_fee {
 			cmd.arg(format!("-minrelaytxfee={}", fr.to_btc_per_kvb()));
 		}
+		if let Some(fr) = self.config.block_min_fee {
+			cmd.arg(format!("-blockmintxfee={}", fr.to_btc_per_kvb()));
+		}
 
 		Ok(cmd)
 	}
@@ -9,10 +9,13 @@ use std::path::PathBuf;
 use std::process::{Child, Command, Stdio};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::util::is_running;
 
+/// How long we wait for a daemon to exit after asking it to stop.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub enum DaemonState {
 	Init,
 	Starting,
@@ -98,14 +101,45 @@ impl<T> Daemon<T>
 
 	pub async fn try_start(&mut self) -> anyhow::Result<()> {
 		self.inner.make_reservations().await?;
+		self.spawn().await
+	}
 
+	/// Start the daemon again after it was stopped or killed.
+	///
+	/// The daemon keeps its datadir and reservations, so clients can
+	/// reconnect to it at the same address.
+	pub async fn restart(&mut self) -> anyhow::Result<()> {
+		if self.child.is_some() {
+			self.stop().await?;
+		}
+
+		info!("Restarting {}", self.inner.name());
+		self.daemon_state = DaemonState::Starting;
+		if let Some(jh) = self.stdout_jh.take() {
+			let _ = jh.join();
+		}
+		if let Err(e) = self.spawn().await {
+			error!("Failed to restart {}: {}", self.inner.name(), e);
+			self.daemon_state = DaemonState::Error;
+			return Err(e);
+		}
+		info!("Restarted {}", self.inner.name());
+		self.daemon_state = DaemonState::Running;
+		Ok(())
+	}
+
+	async fn spawn(&mut self) -> anyhow::Result<()> {
 		let mut cmd = self
 			.inner
 			.get_command()
 			.await?;
 
 		cmd.stdout(Stdio::piped());
-		cmd.stderr(std::fs::File::create(self.inner.datadir().join("stderr.log"))?);
+		let stderr = std::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(self.inner.datadir().join("stderr.log"))?;
+		cmd.stderr(stderr);
 
 		trace!("{}: Trying to spawn {:?}", self.inner.name(), cmd);
 		let mut child = cmd.spawn()?;
@@ -146,28 +180,82 @@ impl<T> Daemon<T>
 		}
 	}
 
+	/// Stop the daemon with SIGTERM and wait for it to exit.
+	///
+	/// If it doesn't exit within [STOP_TIMEOUT], it is killed.
 	pub async fn stop(&mut self) -> anyhow::Result<()> {
 		trace!("Stopping {}", self.inner.name());
 		self.daemon_state = DaemonState::Stopping;
 
-		match self.child.take() {
-			Some(mut child) => tokio::task::spawn_blocking(move || child.kill()).await?,
+		let mut child = match self.child.take() {
+			Some(child) => child,
 			None => anyhow::bail!("Failed to stop daemon because there is no child. Was it running?")
-		}?;
-
+		};
+		// SAFETY: the child wasn't waited for yet, so the pid is still ours.
+		if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+			anyhow::bail!("Failed to send SIGTERM to {}", self.inner.name());
+		}
+		let exited = tokio::task::spawn_blocking(move || {
+			let deadline = Instant::now() + STOP_TIMEOUT;
+			while Instant::now() < deadline {
+				if child.try_wait()?.is_some() {
+					return Ok(true);
+				}
+				std::thread::sleep(Duration::from_millis(100));
+			}
+			child.kill()?;
+			child.wait().map(|_| false)
+		}).await??;
+		if !exited {
+			warn!("{} didn't stop in time, killed it", self.inner.name());
+		}
 
 		info!("Stopped {}", self.inner.name());
 		self.daemon_state = DaemonState::Stopped;
 		Ok(())
 	}
 
-	pub async fn join(&mut self) -> anyhow::Result<()> {
+	/// Send the given signal to the running daemon.
+	pub fn signal(&self, signal: libc::c_int) -> anyhow::Result<()> {
+		let child = match self.child {
+			Some(ref child) => child,
+			None => anyhow::bail!("Failed to signal daemon because there is no child. Was it running?")
+		};
+		// SAFETY: the child wasn't waited for yet, so the pid is still ours.
+		if unsafe { libc::kill(child.id() as libc::pid_t, signal) } != 0 {
+			anyhow::bail!("Failed to send signal {} to {}", signal, self.inner.name());
+		}
+		Ok(())
+	}
+
+	/// Simulate a crash by killing the daemon with SIGKILL.
+	pub async fn kill(&mut self) -> anyhow::Result<()> {
+		trace!("Killing {}", self.inner.name());
+		self.daemon_state = DaemonState::Stopping;
 
 		match self.child.take() {
+			Some(mut child) => tokio::task::spawn_blocking(move || {
+				child.kill()?;
+				child.wait()
+			}).await??,
+			None => anyhow::bail!("Failed to kill daemon because there is no child. Was it running?")
+		};
+
+		info!("Killed {}", self.inner.name());
+		self.daemon_state = DaemonState::Stopped;
+		Ok(())
+	}
+
+	/// Wait for the daemon to exit, errors if it didn't exit successfully.
+	pub async fn join(&mut self) -> anyhow::Result<()> {
+
+		let status = match self.child.take() {
 			Some(mut child) => { tokio::task::spawn_blocking(move || child.wait())}.await?,
 			None => anyhow::bail!("Failed to wait for daemon to complete. Was it running?")
 		}?;
+		anyhow::ensure!(status.success(), "{} exited with {}", self.inner.name(), status);
 
+		self.daemon_state = DaemonState::Stopped;
 		Ok(())
 	}
 
@@ -14,4 +14,4 @@ pub use daemon::{Daemon, DaemonHelper};
 pub use daemon::bitcoind::{Bitcoind, BitcoindConfig};
 pub use daemon::aspd::{Aspd, AspdConfig};
 pub use daemon::lightningd::{Lightningd, LightningdConfig};
-pub use bark::{Bark, BarkConfig};
+pub use bark::{Bark, BarkConfig, CmdRetention, CommandFailed};
@@ -81,6 +81,29 @@ fn get_cargo_workspace() -> PathBuf {
 		Path::new(&cargo_path.trim()).parent().unwrap().to_path_buf()
 }
 
+/// Generate a self-signed TLS certificate for localhost in the given dir.
+///
+/// Returns the paths of the PEM certificate and private key.
+pub async fn generate_tls_cert(dir: impl AsRef<Path>) -> anyhow::Result<(PathBuf, PathBuf)> {
+	let cert = dir.as_ref().join("tls_cert.pem");
+	let key = dir.as_ref().join("tls_key.pem");
+	fs::create_dir_all(dir.as_ref()).await?;
+	let output = tokio::process::Command::new("openssl")
+		.args(["req", "-x509", "-nodes", "-days", "1"])
+		.args(["-newkey", "ec", "-pkeyopt", "ec_paramgen_curve:prime256v1"])
+		.args(["-subj", "/CN=localhost"])
+		.args(["-addext", "subjectAltName=DNS:localhost"])
+		.args(["-addext", "basicConstraints=critical,CA:FALSE"])
+		.arg("-keyout").arg(&key)
+		.arg("-out").arg(&cert)
+		.output().await
+		.context("failed to run openssl")?;
+	if !output.status.success() {
+		bail!("openssl failed: {}", String::from_utf8_lossy(&output.stderr));
+	}
+	Ok((cert, key))
+}
+
 pub fn is_running(child: &mut Child) -> bool {
 	match child.try_wait() {
 		Ok(None) => true,
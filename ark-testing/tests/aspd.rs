@@ -1,12 +1,30 @@
 extern crate tokio;
 
-use std::time::Duration;
+use std::collections::HashSet;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use ark_testing::TestContext;
-use ark_testing::daemon::aspd::Aspd;
-use aspd_rpc_client::Empty;
+use ark_testing::{AspdConfig, Bark, Bitcoind, BitcoindConfig, CommandFailed, TestContext};
+use ark_testing::daemon::aspd::{Aspd, AdminClient, ArkClient};
+use ark_testing::util::generate_tls_cert;
+use aspd_rpc_client::{
+	payment, round_event, BumpRoundTxRequest, CancelPaymentRequest, Empty, FreshRoundsRequest,
+	OnboardCosignRequest, OorCosignRequest, Payment, RoundConnectorsRequest, RoundEvent, RoundFailureKind, RoundId,
+	RoundStart, SubmitPaymentRequest, SubmitRejectReason,
+	SweepExpiredRoundsRequest, SweepRoundRequest, TriggerRoundRequest, VtxoStatus,
+	VtxoStatusRequest, VtxosForPubkeyRequest, WatchDelegation,
+};
+use bark_json::cli::ExitCode;
 
+use bitcoin::{Address, FeeRate, Network, OutPoint, Txid};
 use bitcoin::amount::Amount;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{rand, Keypair, PublicKey, Secp256k1};
+use bitcoincore_rpc::RpcApi;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tonic::codec::CompressionEncoding;
 
 #[test]
 fn check_aspd_version() {
@@ -39,3 +57,1723 @@ async fn fund_asp() {
 	let response  = admin_client.wallet_status(Empty {}).await.expect("Get response").into_inner();
 	assert!(response.balance > 0);
 }
+
+#[tokio::test]
+async fn rotate_funding_addresses() {
+	let ctx = TestContext::new("aspd/rotate_funding_addresses").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	bitcoind.generate(106).await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		wallet_rotate_addresses: Some(true),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+
+	// Every request results in a new address.
+	let addr1 = aspd.get_funding_address().await;
+	let addr2 = aspd.get_funding_address().await;
+	let addr3 = aspd.get_funding_address().await;
+	assert_ne!(addr1, addr2);
+	assert_ne!(addr2, addr3);
+	assert_ne!(addr1, addr3);
+
+	// Funds sent to all of them are found by the wallet.
+	let client = bitcoind.sync_client();
+	for addr in [&addr1, &addr2, &addr3] {
+		client.send_to_address(addr, Amount::from_sat(1_000_000), None, None, None, None, None, None)
+			.unwrap();
+	}
+	bitcoind.generate(1).await;
+
+	let mut admin_client = aspd.get_admin_client().await;
+	let response = admin_client.wallet_status(Empty {}).await.unwrap().into_inner();
+	assert_eq!(response.balance, 3_000_000);
+}
+
+#[tokio::test]
+async fn bump_stuck_round_tx() {
+	let ctx = TestContext::new("aspd/bump_stuck_round_tx").await;
+	// Our bitcoind won't mine txs paying less than 5 sat/vb.
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		fallback_fee: FeeRate::from_sat_per_vb(10).unwrap(),
+		block_min_fee: Some(FeeRate::from_sat_per_vb(5).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	bitcoind.generate(106).await;
+
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(2).unwrap()),
+		round_tx_bump_after: Some(2),
+		round_tx_bump_feerate: Some(FeeRate::from_sat_per_vb(20).unwrap()),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	// The round tx is the only tx left in the mempool.
+	bark.refresh_all().await;
+	let client = bitcoind.sync_client();
+	let mempool = client.get_raw_mempool().unwrap();
+	assert_eq!(mempool.len(), 1);
+	let round_txid = mempool[0];
+
+	// It doesn't pay enough fees to get mined.
+	bitcoind.generate(2).await;
+	assert!(client.get_mempool_entry(&round_txid).is_ok());
+
+	// The aspd should bump it using its fee anchor at the next round.
+	let mut bumped = false;
+	for _ in 0..20 {
+		if !client.get_mempool_entry(&round_txid).unwrap().spent_by.is_empty() {
+			bumped = true;
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	assert!(bumped, "round tx was not fee bumped");
+
+	bitcoind.generate(1).await;
+	assert!(client.get_mempool_entry(&round_txid).is_err());
+	let info = client.get_raw_transaction_info(&round_txid, None).unwrap();
+	assert_eq!(info.confirmations, Some(1));
+}
+
+#[tokio::test]
+async fn bump_round_tx_fee() {
+	let ctx = TestContext::new("aspd/bump_round_tx_fee").await;
+	// Our bitcoind won't mine txs paying less than 5 sat/vb.
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		fallback_fee: FeeRate::from_sat_per_vb(10).unwrap(),
+		block_min_fee: Some(FeeRate::from_sat_per_vb(5).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	bitcoind.generate(106).await;
+
+	// The aspd shouldn't bump the round tx by itself.
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(2).unwrap()),
+		round_tx_bump_after: Some(1_000),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	bark.refresh_all().await;
+	let client = bitcoind.sync_client();
+	let mempool = client.get_raw_mempool().unwrap();
+	assert_eq!(mempool.len(), 1);
+	let round_txid = mempool[0];
+
+	// The first bump doesn't pay enough to get the round tx mined.
+	let mut admin = aspd.get_admin_client().await;
+	let req = BumpRoundTxRequest { round_txid: round_txid[..].to_vec(), fee_rate: 750 };
+	let first = admin.bump_round_tx(req).await.unwrap().into_inner().bump_txid;
+	let entry = client.get_mempool_entry(&round_txid).unwrap();
+	assert_eq!(entry.spent_by.len(), 1);
+	assert_eq!(entry.spent_by[0][..], first[..]);
+	bitcoind.generate(1).await;
+	assert!(client.get_mempool_entry(&round_txid).is_ok());
+
+	// Bumping it again at a lower feerate can't replace the first bump.
+	let req = BumpRoundTxRequest { round_txid: round_txid[..].to_vec(), fee_rate: 500 };
+	admin.bump_round_tx(req).await.unwrap_err();
+
+	// The second bump replaces the first one, the round tx stays the same.
+	let req = BumpRoundTxRequest { round_txid: round_txid[..].to_vec(), fee_rate: 2_500 };
+	let second = admin.bump_round_tx(req).await.unwrap().into_inner().bump_txid;
+	assert_ne!(first, second);
+	let mempool = client.get_raw_mempool().unwrap();
+	assert!(!mempool.iter().any(|txid| txid[..] == first[..]));
+	assert!(mempool.iter().any(|txid| txid[..] == second[..]));
+	let entry = client.get_mempool_entry(&round_txid).unwrap();
+	assert_eq!(entry.spent_by.len(), 1);
+	assert_eq!(entry.spent_by[0][..], second[..]);
+
+	bitcoind.generate(1).await;
+	let info = client.get_raw_transaction_info(&round_txid, None).unwrap();
+	assert_eq!(info.confirmations, Some(1));
+
+}
+
+#[tokio::test]
+async fn retry_round_tx_rejected_for_low_fee() {
+	let ctx = TestContext::new("aspd/retry_round_tx_rejected_for_low_fee").await;
+	// Our bitcoind won't accept txs paying less than 8 sat/vb.
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		relay_fee: Some(FeeRate::from_sat_per_vb(8).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	bitcoind.generate(106).await;
+
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(4).unwrap()),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	// The first round tx is rejected, the aspd retries at a higher fee rate.
+	bark.refresh_all().await;
+	let client = bitcoind.sync_client();
+	let mempool = client.get_raw_mempool().unwrap();
+	assert_eq!(mempool.len(), 1);
+	let entry = client.get_mempool_entry(&mempool[0]).unwrap();
+	let feerate = entry.fees.base.to_sat() / entry.vsize;
+	assert!(feerate >= 8, "round tx pays only {} sat/vb", feerate);
+}
+
+#[tokio::test]
+async fn min_feerate_floor() {
+	let ctx = TestContext::new("aspd/min_feerate_floor").await;
+	// Our bitcoind won't accept txs paying less than 8 sat/vb.
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		relay_fee: Some(FeeRate::from_sat_per_vb(8).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	bitcoind.generate(106).await;
+
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(2).unwrap()),
+		min_feerate: Some(FeeRate::from_sat_per_vb(10).unwrap()),
+		vtxo_expiry_delta: Some(20),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	// Retrying after a rejection wouldn't get the round tx to 10 sat/vb,
+	// only the floor does.
+	bark.refresh_all().await;
+	let client = bitcoind.sync_client();
+	let mempool = client.get_raw_mempool().unwrap();
+	assert_eq!(mempool.len(), 1);
+	let entry = client.get_mempool_entry(&mempool[0]).unwrap();
+	let feerate = entry.fees.base.to_sat() / entry.vsize;
+	assert!(feerate >= 10, "round tx pays only {} sat/vb", feerate);
+
+	// A sweep requested at 1 sat/vb is built at the floor.
+	bitcoind.generate(21).await;
+	let mut admin = aspd.get_admin_client().await;
+	let req = SweepRoundRequest { round_txid: mempool[0][..].to_vec(), fee_rate: 250 };
+	let sweep_txid = admin.sweep_round(req).await.unwrap().into_inner().sweep_txid;
+	let sweep_txid = Txid::from_slice(&sweep_txid).unwrap();
+	let entry = client.get_mempool_entry(&sweep_txid).unwrap();
+	let feerate = entry.fees.base.to_sat() / entry.vsize;
+	assert!(feerate >= 10, "sweep tx pays only {} sat/vb", feerate);
+}
+
+#[tokio::test]
+async fn precheck_catches_low_fee_round_tx() {
+	let ctx = TestContext::new("aspd/precheck_catches_low_fee_round_tx").await;
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		relay_fee: Some(FeeRate::from_sat_per_vb(8).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	bitcoind.generate(106).await;
+
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(4).unwrap()),
+		round_tx_precheck: Some(true),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	// The low-fee round tx is caught before broadcasting and retried.
+	bark.refresh_all().await;
+	let log = tokio::fs::read_to_string(ctx.datadir.join("aspd").join("stdout.log")).await.unwrap();
+	assert!(log.contains("failed mempool pre-check"));
+	assert!(log.contains("min relay fee not met"));
+
+	let client = bitcoind.sync_client();
+	let mempool = client.get_raw_mempool().unwrap();
+	assert_eq!(mempool.len(), 1);
+	let entry = client.get_mempool_entry(&mempool[0]).unwrap();
+	assert!(entry.fees.base.to_sat() / entry.vsize >= 8);
+}
+
+#[tokio::test]
+async fn claim_forfeit_of_exited_vtxo() {
+	let ctx = TestContext::new("aspd/claim_forfeit_of_exited_vtxo").await;
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		relay_fee: Some(FeeRate::from_sat_per_vb(8).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	bitcoind.generate(106).await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	// Keep a copy of the wallet that still has the vtxo and then forfeit it.
+	let old_bark = bark.clone_to("old_bark", ctx.datadir.join("old_bark")).await;
+	let vtxo = old_bark.vtxos().await[0].utxo;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	// The old wallet exits the forfeited vtxo until the vtxo output appears.
+	let client = bitcoind.sync_client();
+	for _ in 0..10 {
+		if client.get_tx_out(&vtxo.txid, vtxo.vout, Some(true)).unwrap().is_some() {
+			break;
+		}
+		old_bark.exit().await;
+		bitcoind.generate(1).await;
+	}
+	assert!(client.get_tx_out(&vtxo.txid, vtxo.vout, Some(true)).unwrap().is_some());
+
+	// The aspd should claim it using the forfeit tx at the next round.
+	let mut claimed = false;
+	for _ in 0..20 {
+		if client.get_tx_out(&vtxo.txid, vtxo.vout, Some(true)).unwrap().is_none() {
+			claimed = true;
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	assert!(claimed, "forfeited vtxo was not claimed");
+
+	// The forfeit tx and its cpfp were submitted together and confirm together.
+	bitcoind.generate(1).await;
+	assert!(client.get_raw_mempool().unwrap().is_empty());
+}
+
+async fn get_vtxo_status(client: &mut ArkClient, vtxo_id: Vec<u8>) -> VtxoStatus {
+	let res = client.get_vtxo_status(VtxoStatusRequest { vtxo_id, vtxo: None }).await.unwrap().into_inner();
+	VtxoStatus::try_from(res.status).unwrap()
+}
+
+#[tokio::test]
+async fn vtxo_status() {
+	let ctx = TestContext::new("aspd/vtxo_status").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	bitcoind.generate(106).await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	let mut client = aspd.get_public_client().await;
+	let vtxo = bark.vtxos().await[0].id;
+	assert_eq!(VtxoStatus::Active, get_vtxo_status(&mut client, vtxo.bytes().to_vec()).await);
+	assert_eq!(VtxoStatus::Unknown, get_vtxo_status(&mut client, vec![0; 36]).await);
+
+	bark.refresh_all().await;
+	assert_eq!(VtxoStatus::Forfeited, get_vtxo_status(&mut client, vtxo.bytes().to_vec()).await);
+
+	let err = client.get_vtxo_status(VtxoStatusRequest { vtxo_id: vec![0; 3], vtxo: None }).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::InvalidArgument);
+}
+
+async fn asp_pubkey(client: &mut ArkClient) -> PublicKey {
+	let info = client.get_ark_info(Empty {}).await.unwrap().into_inner();
+	PublicKey::from_slice(&info.pubkey).unwrap()
+}
+
+fn vtxos_for_pubkey_request(
+	asp_pubkey: PublicKey,
+	key: &Keypair,
+	signer: &Keypair,
+	timestamp_ms: u64,
+) -> VtxosForPubkeyRequest {
+	let msg = aspd_rpc_client::vtxos_for_pubkey_message(asp_pubkey, key.public_key(), timestamp_ms);
+	VtxosForPubkeyRequest {
+		pubkey: key.public_key().serialize().to_vec(),
+		timestamp_ms,
+		signature: Secp256k1::new().sign_schnorr(&msg, signer).serialize().to_vec(),
+		delegation: None,
+	}
+}
+
+#[tokio::test]
+async fn restore_vtxos_for_pubkey() {
+	let ctx = TestContext::new("aspd/restore_vtxos_for_pubkey").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd_cfg = AspdConfig {
+		round_interval: Duration::from_millis(2_000),
+		round_submit_time: Duration::from_millis(3_000),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	};
+	let aspd = ctx.aspd_with_cfg("aspd", aspd_cfg).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+	bark2.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(12).await;
+
+	// Both users pay to our key in the same round.
+	let key = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+	let pk = key.public_key().to_string();
+	tokio::join!(
+		bark1.send_round(&pk, Amount::from_sat(100_000)),
+		bark2.send_round(&pk, Amount::from_sat(200_000)),
+	);
+	bitcoind.generate(1).await;
+
+	let mut client = aspd.get_public_client().await;
+	let asp_pubkey = asp_pubkey(&mut client).await;
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+	let res = client.get_vtxos_for_pubkey(vtxos_for_pubkey_request(asp_pubkey, &key, &key, now)).await
+		.unwrap().into_inner();
+	assert_eq!(res.vtxos.len(), 2);
+
+	// A signature of another key, for another ASP or an outdated request
+	// is rejected.
+	let other = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+	let err = client.get_vtxos_for_pubkey(vtxos_for_pubkey_request(asp_pubkey, &key, &other, now)).await
+		.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::Unauthenticated);
+	let req = vtxos_for_pubkey_request(other.public_key(), &key, &key, now);
+	let err = client.get_vtxos_for_pubkey(req).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::Unauthenticated);
+	let old = now - 60 * 60 * 1000;
+	let err = client.get_vtxos_for_pubkey(vtxos_for_pubkey_request(asp_pubkey, &key, &key, old)).await
+		.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+	// Another key only gets its own vtxos, which are none.
+	let res = client.get_vtxos_for_pubkey(vtxos_for_pubkey_request(asp_pubkey, &other, &other, now)).await
+		.unwrap().into_inner();
+	assert!(res.vtxos.is_empty());
+
+	// A watch key can list the vtxos, but only with a delegation by the key.
+	let watch = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+	let delegation = |signer: &Keypair| {
+		let msg = aspd_rpc_client::watch_delegation_message(key.public_key(), watch.public_key());
+		WatchDelegation {
+			watch_pubkey: watch.public_key().serialize().to_vec(),
+			signature: Secp256k1::new().sign_schnorr(&msg, signer).serialize().to_vec(),
+		}
+	};
+	let res = client.get_vtxos_for_pubkey(VtxosForPubkeyRequest {
+		delegation: Some(delegation(&key)),
+		..vtxos_for_pubkey_request(asp_pubkey, &key, &watch, now)
+	}).await.unwrap().into_inner();
+	assert_eq!(res.vtxos.len(), 2);
+	let err = client.get_vtxos_for_pubkey(VtxosForPubkeyRequest {
+		delegation: Some(delegation(&other)),
+		..vtxos_for_pubkey_request(asp_pubkey, &key, &watch, now)
+	}).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+	// A wallet that lost its vtxos restores its change vtxos from the round.
+	let vtxos = bark1.vtxos().await;
+	assert!(!vtxos.is_empty());
+	bark1.drop_vtxos().await;
+	bark1.restore_vtxos().await;
+	let restored = bark1.vtxos().await;
+	assert_eq!(
+		vtxos.iter().map(|v| v.id).collect::<HashSet<_>>(),
+		restored.iter().map(|v| v.id).collect::<HashSet<_>>(),
+	);
+}
+
+#[tokio::test]
+async fn restore_vtxos_of_derived_keys() {
+	let ctx = TestContext::new("aspd/restore_vtxos_of_derived_keys").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd_cfg = AspdConfig {
+		round_interval: Duration::from_millis(2_000),
+		round_submit_time: Duration::from_millis(3_000),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	};
+	let aspd = ctx.aspd_with_cfg("aspd", aspd_cfg).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let (bark1, mnemonic) = ctx.bark_with_mnemonic("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+	bark2.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(12).await;
+
+	// bark1 receives on fresh keys, the second one beyond the first.
+	let pk1 = bark1.fresh_vtxo_pubkey().await;
+	let pk2 = bark1.fresh_vtxo_pubkey().await;
+	bark2.send_round(&pk1, Amount::from_sat(100_000)).await;
+	bark2.send_round(&pk2, Amount::from_sat(200_000)).await;
+	bitcoind.generate(1).await;
+	let vtxos = bark1.vtxos().await;
+	assert_eq!(vtxos.len(), 2);
+
+	// A new wallet from the same mnemonic finds the vtxos of both keys.
+	let bark3 = ctx.bark_from_mnemonic("bark3", &bitcoind, &aspd, &mnemonic).await;
+	bark3.restore_vtxos().await;
+	let restored = bark3.vtxos().await;
+	assert_eq!(
+		vtxos.iter().map(|v| v.id).collect::<HashSet<_>>(),
+		restored.iter().map(|v| v.id).collect::<HashSet<_>>(),
+	);
+}
+
+#[tokio::test]
+async fn refresh_beyond_max_vtxo_lifetime() {
+	let ctx = TestContext::new("aspd/refresh_beyond_max_vtxo_lifetime").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		max_vtxo_lifetime_blocks: Some(30),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard(Amount::from_sat(800_000)).await;
+
+	// Refreshing right away stays within the lifetime.
+	bark.refresh_all().await;
+	bitcoind.generate(15).await;
+
+	// The refreshed vtxo inherits the creation height of the onboard,
+	// so refreshing it again would exceed the maximum lifetime.
+	let vtxos = bark.vtxos().await;
+	assert!(bark.try_refresh_all().await.is_err());
+	assert_eq!(vtxos.len(), bark.vtxos().await.len());
+	assert_eq!(vtxos[0].id, bark.vtxos().await[0].id);
+}
+
+#[tokio::test]
+async fn forfeit_with_round_connectors() {
+	let ctx = TestContext::new("aspd/forfeit_with_round_connectors").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	bitcoind.generate(106).await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	// Multiple inputs make for a connector chain with multiple txs.
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard(Amount::from_sat(200_000)).await;
+	bark.onboard(Amount::from_sat(200_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(200_000), &bitcoind).await;
+	let vtxos = bark.vtxos().await;
+	assert_eq!(3, vtxos.len());
+
+	// The forfeits are signed using the connectors provided by the ASP.
+	bark.refresh_all().await;
+	let mut client = aspd.get_public_client().await;
+	for vtxo in vtxos {
+		assert_eq!(VtxoStatus::Forfeited, get_vtxo_status(&mut client, vtxo.id.bytes().to_vec()).await);
+	}
+	assert_eq!(1, bark.vtxos().await.len());
+
+	let err = client.get_round_connectors(RoundConnectorsRequest { round_id: u64::MAX })
+		.await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn sweep_expired_round() {
+	let ctx = TestContext::new("aspd/sweep_expired_round").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	let mut client = aspd.get_public_client().await;
+	let round_txid = client.get_fresh_rounds(FreshRoundsRequest { start_height: 0 }).await.unwrap()
+		.into_inner().txids.pop().expect("round created");
+
+	// The round hasn't expired yet.
+	let mut admin = aspd.get_admin_client().await;
+	let req = SweepRoundRequest { round_txid: round_txid.clone(), fee_rate: 1_000 };
+	let err = admin.sweep_round(req.clone()).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+	bitcoind.generate(20).await;
+	let res = admin.sweep_round(req.clone()).await.unwrap().into_inner();
+	let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+	assert!(mempool.iter().any(|txid| txid[..] == res.sweep_txid[..]));
+
+	// The round can't be swept twice.
+	let err = admin.sweep_round(req).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn sweep_expired_rounds_in_batches() {
+	let ctx = TestContext::new("aspd/sweep_expired_rounds_in_batches").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		// Only a single round fits in each sweep.
+		sweep_batch_max_inputs: Some(3),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	// Every refresh creates a new round.
+	for _ in 0..3 {
+		bark.refresh_all().await;
+		bitcoind.generate(1).await;
+	}
+
+	let mut client = aspd.get_public_client().await;
+	let rounds = client.get_fresh_rounds(FreshRoundsRequest { start_height: 0 }).await.unwrap()
+		.into_inner().txids;
+	assert_eq!(rounds.len(), 3);
+
+	// Nothing expired yet.
+	let mut admin = aspd.get_admin_client().await;
+	let req = SweepExpiredRoundsRequest { fee_rate: 1_000 };
+	let res = admin.sweep_expired_rounds(req.clone()).await.unwrap().into_inner();
+	assert!(res.sweep_txids.is_empty());
+
+	bitcoind.generate(20).await;
+	let res = admin.sweep_expired_rounds(req.clone()).await.unwrap().into_inner();
+	assert_eq!(res.sweep_txids.len(), 3);
+	let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+	for sweep in &res.sweep_txids {
+		assert!(mempool.iter().any(|txid| txid[..] == sweep[..]));
+	}
+
+	// The swept rounds are forgotten.
+	let res = admin.sweep_expired_rounds(req).await.unwrap().into_inner();
+	assert!(res.sweep_txids.is_empty());
+}
+
+async fn nb_fresh_rounds(client: &mut ArkClient) -> usize {
+	client.get_fresh_rounds(FreshRoundsRequest { start_height: 0 }).await.unwrap()
+		.into_inner().txids.len()
+}
+
+#[tokio::test]
+async fn auto_sweep_expired_rounds() {
+	let ctx = TestContext::new("aspd/auto_sweep_expired_rounds").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		sweep_mode: Some("auto".into()),
+		sweep_interval: Some(Duration::from_millis(1_000)),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	let mut client = aspd.get_public_client().await;
+	assert_eq!(nb_fresh_rounds(&mut client).await, 1);
+
+	// Once the round expired, it's swept without any admin action.
+	bitcoind.generate(20).await;
+	let mut swept = false;
+	for _ in 0..20 {
+		if nb_fresh_rounds(&mut client).await == 0 {
+			swept = true;
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	assert!(swept, "expired round was not swept");
+	assert!(!bitcoind.sync_client().get_raw_mempool().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn sweep_grace_period() {
+	let ctx = TestContext::new("aspd/sweep_grace_period").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		sweep_mode: Some("auto".into()),
+		sweep_interval: Some(Duration::from_millis(1_000)),
+		sweep_grace_blocks: Some(10),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	// A just-expired round is left alone, also when asked to sweep it.
+	bitcoind.generate(20).await;
+	tokio::time::sleep(Duration::from_millis(3_000)).await;
+	let mut client = aspd.get_public_client().await;
+	assert_eq!(nb_fresh_rounds(&mut client).await, 1);
+	let mut admin = aspd.get_admin_client().await;
+	let res = admin.sweep_expired_rounds(SweepExpiredRoundsRequest { fee_rate: 1_000 }).await
+		.unwrap().into_inner();
+	assert!(res.sweep_txids.is_empty());
+
+	// Once the grace period passed, it's swept.
+	bitcoind.generate(10).await;
+	let mut swept = false;
+	for _ in 0..20 {
+		if nb_fresh_rounds(&mut client).await == 0 {
+			swept = true;
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	assert!(swept, "expired round was not swept after the grace period");
+}
+
+#[tokio::test]
+async fn manual_sweep_expired_rounds() {
+	let ctx = TestContext::new("aspd/manual_sweep_expired_rounds").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		sweep_mode: Some("manual".into()),
+		sweep_interval: Some(Duration::from_millis(1_000)),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	// The expired round stays around, also when new rounds happen.
+	bitcoind.generate(20).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+	bark2.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark2.refresh_all().await;
+	tokio::time::sleep(Duration::from_millis(3_000)).await;
+	let mut client = aspd.get_public_client().await;
+	assert_eq!(nb_fresh_rounds(&mut client).await, 2);
+
+	// Until the admin sweeps it.
+	let mut admin = aspd.get_admin_client().await;
+	let res = admin.sweep_expired_rounds(SweepExpiredRoundsRequest { fee_rate: 1_000 }).await
+		.unwrap().into_inner();
+	assert_eq!(res.sweep_txids.len(), 1);
+	assert_eq!(nb_fresh_rounds(&mut client).await, 1);
+}
+
+#[tokio::test]
+async fn round_with_vtxo_and_onchain_outputs() {
+	let ctx = TestContext::new("aspd/round_with_vtxo_and_onchain_outputs").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd_cfg = AspdConfig {
+		round_interval: Duration::from_millis(2_000),
+		round_submit_time: Duration::from_millis(3_000),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	};
+	let aspd = ctx.aspd_with_cfg("aspd", aspd_cfg).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	let bark3 = ctx.bark("bark3", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+	bark2.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(12).await;
+
+	// One user pays a vtxo, the other an on-chain address, in the same round.
+	let pk3 = bark3.vtxo_pubkey().await;
+	let addr3 = bark3.get_onchain_address().await;
+	tokio::join!(
+		bark1.send_round(&pk3, Amount::from_sat(200_000)),
+		bark2.send_onchain(&addr3, Amount::from_sat(300_000)),
+	);
+
+	let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+	assert_eq!(1, mempool.len(), "both payments should be in a single round");
+	let round_tx = bitcoind.sync_client().get_raw_transaction(&mempool[0], None).unwrap();
+	assert!(round_tx.output.iter().any(|o| {
+		o.script_pubkey.as_bytes() == addr3.script_pubkey().as_bytes() && o.value.to_sat() == 300_000
+	}), "round tx should pay the on-chain address directly");
+
+	bitcoind.generate(1).await;
+	assert_eq!(Amount::from_sat(300_000), bark3.onchain_balance().await);
+	assert_eq!(Amount::from_sat(200_000), bark3.offchain_balance().await);
+}
+
+#[tokio::test]
+async fn round_with_multiple_asp_cosigners() {
+	let ctx = TestContext::new("aspd/round_with_multiple_asp_cosigners").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		nb_round_asp_cosigners: Some(2),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+	bark2.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(12).await;
+
+	tokio::join!(bark1.refresh_all(), bark2.refresh_all());
+	bitcoind.generate(1).await;
+	assert_eq!(bark1.vtxos().await.len(), 1);
+
+	// The tree cosigned by both our keys and those of the users is valid,
+	// so its txs are accepted when exiting.
+	bark1.exit().await;
+	assert!(!bitcoind.sync_client().get_raw_mempool().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn round_change_destination() {
+	let ctx = TestContext::new("aspd/round_change_destination").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	bitcoind.generate(106).await;
+
+	// With a keyless anchor, the round tx has the vtxo tree, connector
+	// and anchor outputs, plus the onchain change if there is any. A
+	// capped change vtxo leaves the rest of the change onchain.
+	let cap = Amount::from_sat(100_000);
+	let cases = [
+		("onchain", None, 4),
+		("vtxo", Some(Amount::MAX_MONEY), 3),
+		("vtxo", Some(cap), 4),
+	];
+	for (i, (round_change, vtxo_max, nb_outputs)) in cases.into_iter().enumerate() {
+		let name = format!("aspd_{}", i);
+		let aspd = ctx.aspd_with_cfg(&name, AspdConfig {
+			round_change: Some(round_change.into()),
+			round_change_vtxo_max: vtxo_max,
+			..ctx.aspd_default_cfg(&name, &bitcoind, None).await
+		}).await;
+		bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+		let bark = ctx.bark(format!("bark_{}", i), &bitcoind, &aspd).await;
+		bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+		bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+		bark.refresh_all().await;
+
+		let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+		assert_eq!(1, mempool.len());
+		let round_tx = bitcoind.sync_client().get_raw_transaction(&mempool[0], None).unwrap();
+		assert_eq!(round_tx.output.len(), nb_outputs, "round change {}", round_change);
+		bitcoind.generate(1).await;
+
+		let status = aspd.get_admin_client().await.wallet_status(Empty {}).await.unwrap().into_inner();
+		let onchain = Amount::from_sat(status.balance);
+		let vtxo = Amount::from_sat(status.vtxo_balance);
+		if vtxo_max == Some(cap) {
+			assert!(onchain > Amount::from_int_btc(9), "onchain balance {}", onchain);
+			assert_eq!(vtxo, cap);
+		} else if round_change == "vtxo" {
+			assert!(onchain < Amount::from_int_btc(1), "onchain balance {}", onchain);
+			assert!(vtxo > Amount::from_int_btc(9), "vtxo balance {}", vtxo);
+		} else {
+			assert!(onchain > Amount::from_int_btc(9), "onchain balance {}", onchain);
+			assert_eq!(vtxo, Amount::ZERO);
+		}
+	}
+}
+
+#[tokio::test]
+async fn round_fee_schemes() {
+	let ctx = TestContext::new("aspd/round_fee_schemes").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	bitcoind.generate(106).await;
+
+	// The round tx has the vtxo tree and connector outputs, then the fee
+	// anchor, if any, and then our change.
+	for scheme in ["keyless_anchor", "p2a_anchor", "cpfp_change"] {
+		let name = format!("aspd_{}", scheme);
+		let aspd = ctx.aspd_with_cfg(&name, AspdConfig {
+			fee_scheme: Some(scheme.into()),
+			round_tx_version: Some(3),
+			..ctx.aspd_default_cfg(&name, &bitcoind, None).await
+		}).await;
+		bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+		bitcoind.generate(1).await;
+
+		let bark = ctx.bark(format!("bark_{}", scheme), &bitcoind, &aspd).await;
+		bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+		bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+		bark.refresh_all().await;
+
+		let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+		assert_eq!(1, mempool.len());
+		let round_tx = bitcoind.sync_client().get_raw_transaction(&mempool[0], None).unwrap();
+		assert_eq!(round_tx.version.0, 3);
+		match scheme {
+			"keyless_anchor" => {
+				assert_eq!(round_tx.output.len(), 4);
+				assert_eq!(round_tx.output[2], ark::fee::dust_anchor());
+			},
+			"p2a_anchor" => {
+				assert_eq!(round_tx.output.len(), 4);
+				assert_eq!(round_tx.output[2], ark::fee::p2a_anchor());
+			},
+			_ => {
+				assert_eq!(round_tx.output.len(), 3);
+				assert!(round_tx.output.iter().all(|o| {
+					o != &ark::fee::dust_anchor() && o != &ark::fee::p2a_anchor()
+				}));
+			},
+		}
+		bitcoind.generate(1).await;
+	}
+}
+
+#[tokio::test]
+async fn wallet_descriptor_rpc() {
+	let ctx = TestContext::new("aspd/wallet_descriptor_rpc").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+
+	let res = aspd.get_admin_client().await.wallet_descriptor(Empty {}).await.unwrap().into_inner();
+	assert!(res.descriptor.contains("tpub"), "{}", res.descriptor);
+	assert!(!res.descriptor.contains("prv"), "{}", res.descriptor);
+	let address = aspd.get_funding_address().await.to_string();
+	assert_eq!(res.address, address);
+
+	// Another wallet derives the same address from the descriptor.
+	let derived = bitcoind.sync_client().derive_addresses(&res.descriptor, Some([0, 0])).unwrap();
+	assert_eq!(derived[0].clone().assume_checked().to_string(), address);
+}
+
+#[tokio::test]
+async fn public_rpc_tls() {
+	let ctx = TestContext::new("aspd/public_rpc_tls").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let (cert, key) = generate_tls_cert(ctx.datadir.join("tls")).await.unwrap();
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		public_rpc_tls_cert_path: Some(cert),
+		public_rpc_tls_key_path: Some(key),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	aspd.get_public_client().await.get_ark_info(Empty {}).await.unwrap();
+
+	// Plaintext connections are refused.
+	let plaintext = aspd.asp_url().replace("https://", "http://");
+	let res = match ArkClient::connect(plaintext).await {
+		Ok(mut c) => c.get_ark_info(Empty {}).await.map(|_| ()).map_err(|e| e.to_string()),
+		Err(e) => Err(e.to_string()),
+	};
+	assert!(res.is_err(), "plaintext connection should fail");
+
+	// Bark can use the certificate to connect over TLS.
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	assert_eq!(bark.offchain_balance().await, Amount::from_sat(800_000));
+
+	let log = ctx.datadir.join("aspd").join("stdout.log");
+	let wait_for_log = |msg: &'static str| {
+		let log = log.clone();
+		async move {
+			for _ in 0..100 {
+				if std::fs::read_to_string(&log).unwrap_or_default().contains(msg) {
+					return;
+				}
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+			panic!("aspd didn't log \"{}\"", msg);
+		}
+	};
+
+	// A broken certificate on reload keeps the old one in use.
+	let cert = aspd.tls_cert().unwrap().clone();
+	let pem = std::fs::read(&cert).unwrap();
+	std::fs::write(&cert, "not a certificate").unwrap();
+	aspd.signal(libc::SIGHUP).unwrap();
+	wait_for_log("Failed to reload TLS certificate").await;
+	std::fs::write(&cert, pem).unwrap();
+	aspd.get_public_client().await.get_ark_info(Empty {}).await.unwrap();
+	assert_eq!(bark.offchain_balance().await, Amount::from_sat(800_000));
+
+	// A valid certificate is picked up by new connections.
+	aspd.signal(libc::SIGHUP).unwrap();
+	wait_for_log("Reloaded TLS certificate").await;
+	aspd.get_public_client().await.get_ark_info(Empty {}).await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_admin_rpc() {
+	let ctx = TestContext::new("aspd/shutdown_admin_rpc").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let mut aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		admin_rpc_token: Some("secret".into()),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+
+	// Without the token, the request is refused.
+	let err = aspd.get_admin_client().await.shutdown(Empty {}).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+	aspd.shutdown().await.unwrap();
+	tokio::time::timeout(Duration::from_secs(30), aspd.join()).await
+		.expect("aspd didn't shut down in time")
+		.unwrap();
+}
+
+#[tokio::test]
+async fn restart_aspd() {
+	let ctx = TestContext::new("aspd/restart_aspd").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let mut aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+
+	let mut client = aspd.get_public_client().await;
+	let pubkey = client.get_ark_info(Empty {}).await.unwrap().into_inner().pubkey;
+	let rounds = client.get_fresh_rounds(FreshRoundsRequest { start_height: 0 }).await.unwrap()
+		.into_inner().txids;
+	assert_eq!(rounds.len(), 1);
+	let url = aspd.asp_url();
+
+	// A graceful stop and a crash both keep the datadir and the address.
+	aspd.stop().await.unwrap();
+	aspd.restart().await.unwrap();
+	assert_eq!(aspd.asp_url(), url);
+	aspd.kill().await.unwrap();
+	aspd.restart().await.unwrap();
+	assert_eq!(aspd.asp_url(), url);
+
+	let mut client = aspd.get_public_client().await;
+	let info = client.get_ark_info(Empty {}).await.unwrap().into_inner();
+	assert_eq!(info.pubkey, pubkey);
+	let after = client.get_fresh_rounds(FreshRoundsRequest { start_height: 0 }).await.unwrap()
+		.into_inner().txids;
+	assert_eq!(after, rounds);
+
+	// The restarted aspd runs rounds again.
+	bark.refresh_all().await;
+	assert_eq!(bark.vtxos().await.len(), 1);
+	let after = client.get_fresh_rounds(FreshRoundsRequest { start_height: 0 }).await.unwrap()
+		.into_inner().txids;
+	assert_eq!(after.len(), 2);
+
+	// Round sequence numbers keep increasing across restarts.
+	let new_round = after.into_iter().find(|txid| *txid != rounds[0]).unwrap();
+	let before_seq = client.get_round(RoundId { txid: rounds[0].clone() }).await.unwrap()
+		.into_inner().round_seq.unwrap();
+	let after_seq = client.get_round(RoundId { txid: new_round }).await.unwrap()
+		.into_inner().round_seq.unwrap();
+	assert!(after_seq > before_seq, "{} <= {}", after_seq, before_seq);
+}
+
+#[tokio::test]
+async fn restore_with_wallet_birthday() {
+	let ctx = TestContext::new("aspd/restore_with_wallet_birthday").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	bitcoind.generate(106).await;
+	let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon \
+		abandon abandon abandon about";
+
+	let birthday = bitcoind.get_block_count().await as u32;
+	let cfg = AspdConfig {
+		mnemonic: Some(mnemonic.into()),
+		birthday: Some(birthday),
+		..ctx.aspd_default_cfg("aspd1", &bitcoind, None).await
+	};
+	let mut aspd1 = ctx.aspd_with_cfg("aspd1", cfg).await;
+	bitcoind.fund_aspd(&aspd1, Amount::from_int_btc(1)).await;
+	bitcoind.generate(1).await;
+	let balance = aspd1.get_admin_client().await.wallet_status(Empty {}).await.unwrap()
+		.into_inner().balance;
+	assert_eq!(balance, Amount::from_int_btc(1).to_sat());
+	aspd1.stop().await.unwrap();
+
+	// Restoring from the birthday finds the funds confirmed after it.
+	let cfg = AspdConfig {
+		mnemonic: Some(mnemonic.into()),
+		birthday: Some(birthday),
+		..ctx.aspd_default_cfg("aspd2", &bitcoind, None).await
+	};
+	let mut aspd2 = ctx.aspd_with_cfg("aspd2", cfg).await;
+	let status = aspd2.get_admin_client().await.wallet_status(Empty {}).await.unwrap().into_inner();
+	assert_eq!(status.balance, balance);
+	aspd2.stop().await.unwrap();
+
+	// The funds are confirmed in the block after it, a birthday at that
+	// block still finds them.
+	let cfg = AspdConfig {
+		mnemonic: Some(mnemonic.into()),
+		birthday: Some(birthday + 1),
+		..ctx.aspd_default_cfg("aspd3", &bitcoind, None).await
+	};
+	let mut aspd3 = ctx.aspd_with_cfg("aspd3", cfg).await;
+	let status = aspd3.get_admin_client().await.wallet_status(Empty {}).await.unwrap().into_inner();
+	assert_eq!(status.balance, balance);
+	aspd3.stop().await.unwrap();
+
+	// Funds confirmed before the birthday are not found.
+	let cfg = AspdConfig {
+		mnemonic: Some(mnemonic.into()),
+		birthday: Some(birthday + 2),
+		..ctx.aspd_default_cfg("aspd4", &bitcoind, None).await
+	};
+	let aspd4 = ctx.aspd_with_cfg("aspd4", cfg).await;
+	let status = aspd4.get_admin_client().await.wallet_status(Empty {}).await.unwrap().into_inner();
+	assert_eq!(status.balance, 0);
+}
+
+#[tokio::test]
+async fn round_metrics_rpc() {
+	let ctx = TestContext::new("aspd/round_metrics_rpc").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let mut admin = aspd.get_admin_client().await;
+	let res = admin.round_metrics(Empty {}).await.unwrap().into_inner();
+	assert!(res.phases.iter().all(|p| p.count == 0));
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+
+	let res = admin.round_metrics(Empty {}).await.unwrap().into_inner();
+	assert_eq!(res.phases.len(), 6);
+	for phase in res.phases {
+		assert_eq!(phase.count, 1, "phase {}", phase.phase);
+		assert_eq!(phase.bucket_counts.len(), phase.bucket_bounds_ms.len() + 1);
+		assert_eq!(phase.bucket_counts.iter().sum::<u64>(), 1);
+	}
+}
+
+#[tokio::test]
+async fn onboard_with_nonce_pool() {
+	let ctx = TestContext::new("aspd/onboard_with_nonce_pool").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		onboard_nonce_pool_size: Some(4),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+
+	let mut admin = aspd.get_admin_client().await;
+	let res = admin.round_metrics(Empty {}).await.unwrap().into_inner();
+	assert_eq!(res.onboard_nonce_pool_size, Some(4));
+	assert_eq!(res.onboard_nonce_pool_occupancy, 4);
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	assert_eq!(bark.offchain_balance().await, Amount::from_sat(600_000));
+
+	// The pool is refilled in the background.
+	let res = admin.round_metrics(Empty {}).await.unwrap().into_inner();
+	assert_eq!(res.onboard_nonce_pool_occupancy, 4);
+	assert_eq!(res.onboard_nonce_pool_exhausted, 0);
+}
+
+/// Send funds to an onboard output of a fresh key, without asking the ASP to
+/// cosign first, and return the user part for it.
+async fn unsigned_onboard(
+	client: &mut ArkClient,
+	bitcoind: &Bitcoind,
+	amount: Amount,
+) -> ark::onboard::UserPart {
+	let info = client.get_ark_info(Empty {}).await.unwrap().into_inner();
+	let key = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+	let spec = ark::VtxoSpec {
+		user_pubkey: key.public_key(),
+		asp_pubkey: PublicKey::from_slice(&info.pubkey).unwrap(),
+		expiry_height: bitcoind.get_block_count().await as u32 + info.vtxo_expiry_delta,
+		exit_delta: info.vtxo_exit_delta as u16,
+		amount,
+		exit_timelock_type: Default::default(),
+		script_type: Default::default(),
+	};
+	let spk = ark::onboard::onboard_spk(&spec);
+	let addr = Address::from_script(&spk, Network::Regtest).unwrap();
+	let rpc = bitcoind.sync_client();
+	let txid = rpc.send_to_address(
+		&addr, amount + ark::onboard::onboard_surplus(), None, None, None, None, None, None,
+	).unwrap();
+	let tx = rpc.get_raw_transaction(&txid, None).unwrap();
+	let vout = tx.output.iter().position(|o| o.script_pubkey == spk).unwrap();
+	ark::onboard::new_user(spec, OutPoint::new(txid, vout as u32)).0
+}
+
+#[tokio::test]
+async fn reject_deeply_confirmed_onboard_utxo() {
+	let ctx = TestContext::new("aspd/reject_deeply_confirmed_onboard_utxo").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let mut aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		onboard_max_confirmations: Some(3),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	let mut client = aspd.get_public_client().await;
+
+	// Utxos up to the maximum depth are cosigned.
+	let fresh = unsigned_onboard(&mut client, &bitcoind, Amount::from_sat(100_000)).await;
+	client.request_onboard_cosign(OnboardCosignRequest { user_part: fresh.encode() })
+		.await.unwrap();
+	let shallow = unsigned_onboard(&mut client, &bitcoind, Amount::from_sat(100_000)).await;
+	bitcoind.generate(3).await;
+	client.request_onboard_cosign(OnboardCosignRequest { user_part: shallow.encode() })
+		.await.unwrap();
+
+	// Deeper ones are refused.
+	let deep = unsigned_onboard(&mut client, &bitcoind, Amount::from_sat(100_000)).await;
+	bitcoind.generate(4).await;
+	let err = client.request_onboard_cosign(OnboardCosignRequest { user_part: deep.encode() })
+		.await.unwrap_err();
+	assert!(err.message().contains("at most 3 allowed"), "unexpected error: {}", err);
+
+	// Without a maximum, the same utxo is fine.
+	aspd.set_config_override("ARKD_ONBOARD_MAX_CONFIRMATIONS", "");
+	aspd.restart().await.unwrap();
+	let mut client = aspd.get_public_client().await;
+	client.request_onboard_cosign(OnboardCosignRequest { user_part: deep.encode() })
+		.await.unwrap();
+}
+
+#[tokio::test]
+async fn skip_round_with_insufficient_asp_funds() {
+	let ctx = TestContext::new("aspd/skip_round_with_insufficient_asp_funds").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	// Far too little to fund a refresh of the bark's vtxo.
+	bitcoind.fund_aspd(&aspd, Amount::from_sat(100_000)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	let mut client = aspd.get_public_client().await;
+	let mut events = client.subscribe_rounds(Empty {}).await.unwrap().into_inner();
+	let err = bark.try_refresh_all().await.unwrap_err();
+	let err = err.downcast::<CommandFailed>().unwrap();
+	assert_eq!(err.error_code(), Some(ExitCode::RoundFailed));
+	let failed = loop {
+		match events.message().await.unwrap().unwrap().event.unwrap() {
+			round_event::Event::Failed(f) => break f,
+			_ => {},
+		}
+	};
+	assert_eq!(failed.kind, RoundFailureKind::InsufficientAspFunds as i32);
+	assert!(failed.reason.contains("insufficient ASP funds"), "{}", failed.reason);
+	// The vtxo wasn't spent.
+	assert_eq!(bark.offchain_balance().await, Amount::from_sat(800_000));
+
+	let mut admin = aspd.get_admin_client().await;
+	let res = admin.round_metrics(Empty {}).await.unwrap().into_inner();
+	assert!(res.insufficient_funds_rounds >= 1);
+	assert!(res.phases.iter().all(|p| p.count == 0));
+	// We back off after the failed round, the round interval is 500ms.
+	assert!(res.round_backoff_level >= 1);
+	assert!(res.round_backoff_interval_ms >= 2 * 500, "{}", res.round_backoff_interval_ms);
+}
+
+#[tokio::test]
+async fn round_waits_for_coinbase_maturity() {
+	let ctx = TestContext::new("aspd/round_waits_for_coinbase_maturity").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	// Our only funds are a fresh coinbase output.
+	bitcoind.generate_to_aspd(&aspd, 1).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	let mut admin = aspd.get_admin_client().await;
+	let status = admin.wallet_status(Empty {}).await.unwrap().into_inner();
+	assert!(status.immature_balance > 0);
+	assert_eq!(status.balance, status.immature_balance);
+
+	let mut client = aspd.get_public_client().await;
+	let mut events = client.subscribe_rounds(Empty {}).await.unwrap().into_inner();
+	let err = bark.try_refresh_all().await.unwrap_err();
+	let err = err.downcast::<CommandFailed>().unwrap();
+	assert_eq!(err.error_code(), Some(ExitCode::RoundFailed));
+	let failed = loop {
+		match events.message().await.unwrap().unwrap().event.unwrap() {
+			round_event::Event::Failed(f) => break f,
+			_ => {},
+		}
+	};
+	assert_eq!(failed.kind, RoundFailureKind::InsufficientAspFunds as i32);
+	assert_eq!(bark.offchain_balance().await, Amount::from_sat(800_000));
+
+	// Once the coinbase matured, it funds the round.
+	bitcoind.generate(100).await;
+	let status = admin.wallet_status(Empty {}).await.unwrap().into_inner();
+	assert_eq!(status.immature_balance, 0);
+	bark.refresh_all().await;
+	assert_eq!(bark.vtxos().await.len(), 1);
+}
+
+#[tokio::test]
+async fn round_with_explicit_expiry_height() {
+	let ctx = TestContext::new("aspd/round_with_explicit_expiry_height").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		// Only start rounds when we trigger them.
+		round_interval: Duration::from_secs(3600),
+		vtxo_expiry_delta: Some(100),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	let tip = bitcoind.get_block_count().await as u32;
+	let mut admin = aspd.get_admin_client().await;
+
+	// An expiry beyond the expiry delta is refused.
+	let err = admin.trigger_round(TriggerRoundRequest {
+		expiry_height: Some(tip + 101),
+	}).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+	// Keep triggering until bark joined a round.
+	let expiry = tip + 50;
+	let refresh = bark.refresh_all();
+	tokio::pin!(refresh);
+	loop {
+		admin.trigger_round(TriggerRoundRequest { expiry_height: Some(expiry) }).await.unwrap();
+		tokio::select! {
+			() = &mut refresh => break,
+			() = tokio::time::sleep(Duration::from_secs(1)) => {},
+		}
+	}
+
+	let vtxos = bark.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_eq!(vtxos[0].expiry_height, expiry);
+}
+
+/// Trigger a round and return the epoch of its start.
+async fn trigger_round_start(
+	admin: &mut AdminClient,
+	events: &mut tonic::Streaming<RoundEvent>,
+) -> u64 {
+	admin.trigger_round(TriggerRoundRequest::default()).await.unwrap();
+	loop {
+		match events.message().await.unwrap().unwrap().event.unwrap() {
+			round_event::Event::Start(RoundStart { round_epoch, .. }) => return round_epoch,
+			_ => {},
+		}
+	}
+}
+
+#[tokio::test]
+async fn reject_payment_with_stale_round_epoch() {
+	let ctx = TestContext::new("aspd/reject_payment_with_stale_round_epoch").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		// Only start rounds when we trigger them.
+		round_interval: Duration::from_secs(3600),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	let mut client = aspd.get_public_client().await;
+	let mut admin = aspd.get_admin_client().await;
+	let mut events = client.subscribe_rounds(Empty {}).await.unwrap().into_inner();
+	let stale_epoch = trigger_round_start(&mut admin, &mut events).await;
+	let fresh_epoch = trigger_round_start(&mut admin, &mut events).await;
+	assert_ne!(stale_epoch, fresh_epoch);
+
+	// A submission for an earlier round start is refused.
+	let res = client.submit_payment(SubmitPaymentRequest {
+		round_epoch: stale_epoch,
+		..Default::default()
+	}).await.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::StaleEpoch as i32);
+	assert!(res.reject_message.contains("stale round epoch"), "{}", res.reject_message);
+
+	// Dust outputs are refused with their own reason.
+	let pubkey = bitcoin::secp256k1::PublicKey::from_str(
+		"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+	).unwrap();
+	let res = client.submit_payment(SubmitPaymentRequest {
+		round_epoch: fresh_epoch,
+		payments: vec![Payment {
+			amount: 1,
+			destination: Some(payment::Destination::VtxoPublicKey(pubkey.serialize().to_vec())),
+			..Default::default()
+		}],
+		..Default::default()
+	}).await.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::Dust as i32);
+
+	// bark echoes the epoch of the current round start.
+	let refresh = bark.refresh_all();
+	tokio::pin!(refresh);
+	loop {
+		admin.trigger_round(TriggerRoundRequest::default()).await.unwrap();
+		tokio::select! {
+			() = &mut refresh => break,
+			() = tokio::time::sleep(Duration::from_secs(1)) => {},
+		}
+	}
+	assert_eq!(bark.vtxos().await.len(), 1);
+}
+
+fn cancel_payment_request(
+	vtxo: &ark::Vtxo,
+	cosign_pubkey: PublicKey,
+	signer: &Keypair,
+	round_epoch: u64,
+) -> CancelPaymentRequest {
+	let msg = aspd_rpc_client::cancel_payment_message(vtxo.id(), round_epoch, cosign_pubkey);
+	CancelPaymentRequest {
+		input_vtxo: vtxo.encode(),
+		round_epoch,
+		signature: Secp256k1::new().sign_schnorr(&msg, signer).serialize().to_vec(),
+		cosign_pubkey: cosign_pubkey.serialize().to_vec(),
+	}
+}
+
+/// Have bark send a round payment to a fresh key and return the key and the
+/// round vtxo, so that we can spend it ourselves.
+async fn own_round_vtxo(
+	bark: &Bark,
+	aspd: &Aspd,
+	bitcoind: &Bitcoind,
+	amount: Amount,
+) -> (Keypair, ark::Vtxo) {
+	let mut client = aspd.get_public_client().await;
+	let mut admin = aspd.get_admin_client().await;
+	let key = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+	let send = bark.send_round(key.public_key(), amount);
+	tokio::pin!(send);
+	loop {
+		admin.trigger_round(TriggerRoundRequest::default()).await.unwrap();
+		tokio::select! {
+			() = &mut send => break,
+			() = tokio::time::sleep(Duration::from_secs(1)) => {},
+		}
+	}
+	bitcoind.generate(1).await;
+	let asp_pubkey = asp_pubkey(&mut client).await;
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+	let res = client.get_vtxos_for_pubkey(vtxos_for_pubkey_request(asp_pubkey, &key, &key, now)).await
+		.unwrap().into_inner();
+	(key, ark::Vtxo::decode(&res.vtxos[0]).unwrap())
+}
+
+#[tokio::test]
+async fn cancel_round_payment() {
+	let ctx = TestContext::new("aspd/cancel_round_payment").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		// Only start rounds when we trigger them.
+		round_interval: Duration::from_secs(3600),
+		round_submit_time: Duration::from_secs(10),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	// Get a round vtxo of our own key, so that we can submit it ourselves.
+	let mut client = aspd.get_public_client().await;
+	let mut admin = aspd.get_admin_client().await;
+	let (key, vtxo) = own_round_vtxo(&bark, &aspd, &bitcoind, Amount::from_sat(100_000)).await;
+	// Let a round started by a leftover trigger sit out its submit window.
+	tokio::time::sleep(Duration::from_secs(11)).await;
+
+	let nb_nonces = client.get_ark_info(Empty {}).await.unwrap().into_inner().nb_round_nonces;
+	let submit = |client: &ArkClient, round_epoch: u64| {
+		let cosign_key = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+		let req = SubmitPaymentRequest {
+			input_vtxos: vec![vtxo.encode()],
+			payments: vec![Payment {
+				amount: vtxo.amount().to_sat(),
+				destination: Some(payment::Destination::VtxoPublicKey(
+					key.public_key().serialize().to_vec(),
+				)),
+				..Default::default()
+			}],
+			cosign_pubkey: cosign_key.public_key().serialize().to_vec(),
+			public_nonces: (0..nb_nonces).map(|_| {
+				ark::musig::nonce_pair(&cosign_key).1.serialize().to_vec()
+			}).collect(),
+			round_epoch,
+		};
+		let mut client = client.clone();
+		async move {
+			(client.submit_payment(req).await.unwrap().into_inner(), cosign_key.public_key())
+		}
+	};
+
+	let mut events = client.subscribe_rounds(Empty {}).await.unwrap().into_inner();
+	let epoch = trigger_round_start(&mut admin, &mut events).await;
+	let (res, cosign) = submit(&client, epoch).await;
+	assert_eq!(res.reject_reason, SubmitRejectReason::Accepted as i32, "{}", res.reject_message);
+	let pending = admin.pending_payments(Empty {}).await.unwrap().into_inner().payments;
+	assert_eq!(pending.len(), 1);
+	assert_eq!(pending[0].input_vtxo_ids, vec![vtxo.id().bytes().to_vec()]);
+	assert_eq!(pending[0].output_amount, vtxo.amount().to_sat());
+	// The vtxo is taken by the pending payment.
+	let (res, _) = submit(&client, epoch).await;
+	assert_eq!(res.reject_reason, SubmitRejectReason::DoubleSpend as i32, "{}", res.reject_message);
+
+	// Only the owner of the vtxo can cancel the payment.
+	let other = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+	let err = client.cancel_payment(cancel_payment_request(&vtxo, cosign, &other, epoch)).await
+		.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::Unauthenticated);
+	let cancel = cancel_payment_request(&vtxo, cosign, &key, epoch);
+	let res = client.cancel_payment(cancel.clone()).await.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::Accepted as i32, "{}", res.reject_message);
+	assert!(admin.pending_payments(Empty {}).await.unwrap().into_inner().payments.is_empty());
+	let res = client.cancel_payment(cancel.clone()).await.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::UnknownPayment as i32, "{}", res.reject_message);
+
+	// The vtxo is free again, and replaying the earlier cancellation
+	// doesn't cancel the new payment.
+	let (res, cosign) = submit(&client, epoch).await;
+	assert_eq!(res.reject_reason, SubmitRejectReason::Accepted as i32, "{}", res.reject_message);
+	let res = client.cancel_payment(cancel).await.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::UnknownPayment as i32, "{}", res.reject_message);
+	let res = client.cancel_payment(cancel_payment_request(&vtxo, cosign, &key, epoch)).await
+		.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::Accepted as i32, "{}", res.reject_message);
+
+	// Once the submit window closed, there is nothing to cancel anymore.
+	tokio::time::sleep(Duration::from_secs(11)).await;
+	let res = client.cancel_payment(cancel_payment_request(&vtxo, cosign, &key, epoch)).await
+		.unwrap().into_inner();
+	assert_eq!(res.reject_reason, SubmitRejectReason::PastDeadline as i32, "{}", res.reject_message);
+
+	// And the vtxo can be used in the next round.
+	let epoch = trigger_round_start(&mut admin, &mut events).await;
+	let (res, _) = submit(&client, epoch).await;
+	assert_eq!(res.reject_reason, SubmitRejectReason::Accepted as i32, "{}", res.reject_message);
+}
+
+/// Read round events until the end of a round and return those of that round.
+async fn round_events(events: &mut tonic::Streaming<RoundEvent>) -> Vec<RoundEvent> {
+	let mut ret = Vec::new();
+	loop {
+		let event = events.message().await.unwrap().unwrap();
+		match event.event.as_ref().unwrap() {
+			round_event::Event::Start(_) => ret.clear(),
+			round_event::Event::Finished(_) => {
+				ret.push(event);
+				return ret;
+			},
+			_ => {},
+		}
+		ret.push(event);
+	}
+}
+
+#[tokio::test]
+async fn compressed_round_events() {
+	let ctx = TestContext::new("aspd/compressed_round_events").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let barks = futures::future::join_all((0..4).map(|i| {
+		ctx.bark(format!("bark{}", i), &bitcoind, &aspd)
+	})).await;
+	for bark in &barks {
+		bitcoind.fund_bark(bark, Amount::from_sat(1_000_000)).await;
+		bark.onboard(Amount::from_sat(800_000)).await;
+	}
+	bitcoind.generate(12).await;
+
+	let mut plain = aspd.get_public_client().await;
+	let mut gzip = aspd.get_public_client().await.accept_compressed(CompressionEncoding::Gzip);
+	let res = plain.subscribe_rounds(Empty {}).await.unwrap();
+	assert!(res.metadata().get("grpc-encoding").is_none());
+	let mut plain_events = res.into_inner();
+	let res = gzip.subscribe_rounds(Empty {}).await.unwrap();
+	assert_eq!(res.metadata().get("grpc-encoding").unwrap(), "gzip");
+	let mut gzip_events = res.into_inner();
+
+	let (_, plain_round, gzip_round) = tokio::join!(
+		futures::future::join_all(barks.iter().map(|b| b.refresh_all())),
+		round_events(&mut plain_events),
+		round_events(&mut gzip_events),
+	);
+	// The decompressed events are the same as the plain ones.
+	assert_eq!(plain_round, gzip_round);
+
+	// Measure what compression saves on the events with the vtxo tree.
+	let mut nb_proposals = 0;
+	for event in &plain_round {
+		let name = match event.event.as_ref().unwrap() {
+			round_event::Event::VtxoProposal(_) => "vtxo proposal",
+			round_event::Event::RoundProposal(_) => "round proposal",
+			_ => continue,
+		};
+		let raw = prost::Message::encode_to_vec(event);
+		let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+		encoder.write_all(&raw).unwrap();
+		let compressed = encoder.finish().unwrap();
+		println!("{} of {} bytes, {} bytes with gzip ({:.0}%)",
+			name, raw.len(), compressed.len(), compressed.len() as f64 * 100.0 / raw.len() as f64,
+		);
+		nb_proposals += 1;
+	}
+	assert_eq!(nb_proposals, 2);
+}
+
+#[tokio::test]
+async fn reject_payment_in_full_round() {
+	let ctx = TestContext::new("aspd/reject_payment_in_full_round").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		max_round_inputs: Some(1),
+		// Without the limit, the round would wait for more payments.
+		round_interval: Duration::from_secs(2),
+		round_submit_time: Duration::from_secs(10),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	let mut client = aspd.get_public_client().await;
+	let mut events = client.subscribe_rounds(Empty {}).await.unwrap().into_inner();
+	let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+	// The payment of bark fills the round, so it proceeds right away and
+	// a later payment for the same round start is turned away.
+	let overflow = async {
+		let mut epoch = None;
+		loop {
+			match events.message().await.unwrap().unwrap().event.unwrap() {
+				round_event::Event::Start(RoundStart { round_epoch, .. }) => epoch = Some(round_epoch),
+				round_event::Event::VtxoProposal(_) => break,
+				_ => {},
+			}
+		}
+		client.submit_payment(SubmitPaymentRequest {
+			round_epoch: epoch.unwrap(),
+			..Default::default()
+		}).await.unwrap().into_inner()
+	};
+	let ((), res) = tokio::join!(bark.refresh_all(), overflow);
+	assert_eq!(res.reject_reason, SubmitRejectReason::RoundFull as i32, "{}", res.reject_message);
+	assert!(res.reject_message.contains("round is full"), "{}", res.reject_message);
+	// The next round is a round interval after the start of this one.
+	assert!(res.next_round_start_ms > before, "{} <= {}", res.next_round_start_ms, before);
+	assert!(res.next_round_start_ms < before + 60_000);
+	assert_eq!(bark.vtxos().await.len(), 1);
+}
+
+#[tokio::test]
+async fn reject_oor_below_minimum() {
+	let ctx = TestContext::new("aspd/reject_oor_below_minimum").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_interval: Duration::from_secs(3600),
+		oor_min_amount: Some(Amount::from_sat(15_000)),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	let (key, vtxo) = own_round_vtxo(&bark, &aspd, &bitcoind, Amount::from_sat(100_000)).await;
+
+	let mut client = aspd.get_public_client().await;
+	let dest = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng()).public_key();
+	let request = |amount: Amount| {
+		let change = vtxo.amount() - amount - Amount::from_sat(1_000);
+		let payment = ark::oor::OorPayment::new(
+			vtxo.spec().asp_pubkey,
+			vtxo.spec().exit_delta,
+			vtxo.spec().exit_timelock_type,
+			vtxo.spec().script_type,
+			vec![vtxo.clone()],
+			vec![
+				ark::VtxoRequest { pubkey: dest, amount },
+				ark::VtxoRequest { pubkey: key.public_key(), amount: change },
+			],
+		);
+		OorCosignRequest {
+			payment: payment.encode(),
+			pub_nonces: vec![ark::musig::nonce_pair(&key).1.serialize().to_vec()],
+		}
+	};
+
+	let err = client.request_oor_cosign(request(Amount::from_sat(10_000))).await.unwrap_err();
+	assert_eq!(err.code(), tonic::Code::InvalidArgument);
+	assert!(err.message().contains("below the minimum of"), "{}", err.message());
+
+	// The rejected request didn't mark the vtxo as cosigned.
+	let res = client.request_oor_cosign(request(Amount::from_sat(20_000))).await
+		.unwrap().into_inner();
+	assert_eq!(res.partial_sigs.len(), 1);
+}
@@ -3,9 +3,15 @@ extern crate log;
 
 use std::time::Duration;
 
+use bitcoincore_rpc::RpcApi;
+use bitcoincore_rpc::bitcoin::FeeRate;
 use bitcoincore_rpc::bitcoin::amount::Amount;
 
-use ark_testing::{TestContext, AspdConfig};
+use aspd_rpc_client::{FreshRoundsRequest, VtxoStatus, VtxoStatusRequest};
+use bark_json::cli::{Balance, ExitCode, VtxoKind};
+
+use ark_testing::{TestContext, AspdConfig, Bark, BarkConfig, CmdRetention, CommandFailed};
+use ark_testing::util::generate_tls_cert;
 
 #[tokio::test]
 async fn bark_version() {
@@ -45,6 +51,18 @@ async fn bark_create_is_atomic() {
 	assert!(!std::path::Path::is_dir(ctx.datadir.join("bark_fails").as_path()));
 }
 
+#[tokio::test]
+async fn bark_create_print_mnemonic() {
+	let ctx = TestContext::new("bark/create-print-mnemonic").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	let (_bark, mnemonic) = ctx.bark_with_mnemonic("bark", &bitcoind, &aspd).await;
+	assert_eq!(12, mnemonic.split_whitespace().count());
+	let stored = tokio::fs::read_to_string(ctx.datadir.join("bark").join("mnemonic")).await.unwrap();
+	assert_eq!(stored.trim(), mnemonic);
+}
+
 #[tokio::test]
 async fn onboard_bark() {
 	let ctx = TestContext::new("bark/onboard_bark").await;
@@ -57,13 +75,176 @@ async fn onboard_bark() {
 
 	// Get the bark-address and fund it
 	bitcoind.fund_bark(&bark, Amount::from_sat(100_000)).await;
-	bark.onboard(Amount::from_sat(90_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(90_000), &bitcoind).await;
 
 	// TODO: Verify the onboarded balance
 	// The current cli only provides logs in stdout. This is to annoying
 	let _ = bark.run(["balance"]).await;
 }
 
+#[tokio::test]
+async fn bark_status() {
+	let ctx = TestContext::new("bark/bark_status").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.generate(101).await;
+
+	let status = bark.status().await;
+	assert_eq!(status.nb_vtxos, 0);
+	assert_eq!(status.offchain_balance, Amount::ZERO);
+	assert_eq!(status.nearest_expiry_height, None);
+
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark.onboard_and_confirm(Amount::from_sat(200_000), &bitcoind).await;
+
+	let status = bark.status().await;
+	let vtxos = bark.vtxos().await;
+	assert_eq!(status.nb_vtxos, 2);
+	assert_eq!(status.offchain_balance, Amount::from_sat(500_000));
+	assert!(status.onchain_balance > Amount::ZERO);
+	let first_expiry = vtxos.iter().map(|v| v.expiry_height).min();
+	assert_eq!(status.nearest_expiry_height, first_expiry);
+	assert!(status.pending_round_txids.is_empty());
+	assert_eq!(status.asp_pubkey, vtxos[0].asp_pubkey);
+	let tip = bitcoind.sync_client().get_block_count().unwrap() as u32;
+	assert_eq!(status.chain_tip, tip);
+	assert_eq!(status.ark_sync_height, tip);
+}
+
+#[tokio::test]
+async fn broadcast_tree_without_asp() {
+	let ctx = TestContext::new("bark/broadcast_tree_without_asp").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let mut aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark-1".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	let vtxos = bark.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	let file = bark.export_tree_txs(vtxos[0].id).await;
+	let nb_txs = std::fs::read_to_string(&file).unwrap().lines().count();
+	assert!(nb_txs > 0);
+
+	// The ASP disappears, we can still put the tree branch onchain.
+	aspd.stop().await.unwrap();
+	let txids = bark.broadcast_tree(&file).await;
+	assert_eq!(txids.len(), nb_txs);
+	let client = bitcoind.sync_client();
+	let mempool = client.get_raw_mempool().unwrap();
+	for txid in &txids {
+		let txid = txid.to_string().parse().unwrap();
+		assert!(mempool.contains(&txid), "tree tx {} not in mempool", txid);
+	}
+	// The tree txs with a fee anchor, at least the leaf, come with a CPFP
+	// from our onchain wallet.
+	assert!(mempool.len() > nb_txs, "no CPFP in mempool");
+
+	// Broadcasting again while they are in the mempool is a no-op.
+	assert!(bark.broadcast_tree(&file).await.is_empty());
+
+	// Once confirmed, broadcasting again is a no-op.
+	bitcoind.generate(1).await;
+	assert!(bark.broadcast_tree(&file).await.is_empty());
+}
+
+#[tokio::test]
+async fn export_import_watchtower() {
+	let ctx = TestContext::new("bark/export_import_watchtower").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+	let bark1 = ctx.bark("bark-1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark-2".to_string(), &bitcoind, &aspd).await;
+
+	bitcoind.generate(101).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark1.onboard_and_confirm(Amount::from_sat(200_000), &bitcoind).await;
+	bark1.refresh_all().await;
+
+	let vtxos = bark1.vtxos().await;
+	let file = bark1.export_watchtower().await;
+
+	// Another party can read the bundle without having the wallet's keys.
+	let imported = bark2.import_watchtower(&file).await;
+	assert_eq!(imported.len(), vtxos.len());
+	for vtxo in &vtxos {
+		let w = imported.iter().find(|w| w.id == vtxo.id).expect("vtxo missing from bundle");
+		assert_eq!(w.amount, vtxo.amount);
+		assert_eq!(w.expiry_height, vtxo.expiry_height);
+		assert!(!w.exit_txids.is_empty());
+	}
+}
+
+#[tokio::test]
+async fn asp_tls() {
+	let ctx = TestContext::new("bark/asp_tls").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let (cert, key) = generate_tls_cert(ctx.datadir.join("tls")).await.unwrap();
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		public_rpc_tls_cert_path: Some(cert),
+		public_rpc_tls_key_path: Some(key),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	// The self-signed certificate is only trusted when it's pinned.
+	let cfg = BarkConfig { asp_cert: None, ..ctx.bark_cfg("bark_nocert", &bitcoind, &aspd) };
+	Bark::try_new("bark_nocert", cfg).await.unwrap_err();
+
+	// A pinned certificate is refused for a plaintext ASP address.
+	let cfg = BarkConfig {
+		asp_url: aspd.asp_url().replace("https://", "http://"),
+		..ctx.bark_cfg("bark_http", &bitcoind, &aspd)
+	};
+	Bark::try_new("bark_http", cfg).await.unwrap_err();
+
+	// With the pinned certificate, all of bark's ASP traffic goes over TLS.
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.refresh_all().await;
+	assert_eq!(1, bark.vtxos().await.len());
+}
+
+#[tokio::test]
+async fn onboard_requires_confirmations() {
+	let ctx = TestContext::new("bark/onboard_requires_confirmations").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		onboard_confirmations: Some(2),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bitcoind.generate(1).await;
+
+	// The onboard vtxo can't be used as long as the onboard tx is unconfirmed.
+	bark.onboard(Amount::from_sat(800_000)).await;
+	assert!(bark.try_run(["refresh", "--all"]).await.is_err());
+
+	// Nor with a single confirmation.
+	bitcoind.generate(1).await;
+	assert!(bark.try_run(["refresh", "--all"]).await.is_err());
+
+	bitcoind.generate(1).await;
+	bark.refresh_all().await;
+	assert_eq!(1, bark.vtxos().await.len());
+}
+
 #[tokio::test]
 async fn multiple_round_payments() {
 	#[cfg(not(feature = "slow_test"))]
@@ -145,6 +326,478 @@ async fn oor() {
 	assert_eq!(20_000, bark2.offchain_balance().await.to_sat());
 }
 
+#[tokio::test]
+async fn oor_below_minimum() {
+	let ctx = TestContext::new("bark/oor_below_minimum").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd_with_cfg("aspd-1", AspdConfig {
+		oor_min_amount: Some(Amount::from_sat(15_000)),
+		..ctx.aspd_default_cfg("aspd-1", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(90_000)).await;
+	bark1.onboard(Amount::from_sat(80_000)).await;
+	let pk2 = bark2.vtxo_pubkey().await;
+
+	let err = bark1.try_send_oor(&pk2, Amount::from_sat(10_000)).await.unwrap_err();
+	assert!(err.to_string().contains("below the ASP's minimum of"), "{}", err);
+	assert_eq!(bark1.offchain_balance().await, Amount::from_sat(80_000));
+	assert_eq!(bark2.offchain_balance().await, Amount::ZERO);
+
+	bark1.send_oor(&pk2, Amount::from_sat(20_000)).await;
+	assert_eq!(bark2.offchain_balance().await, Amount::from_sat(20_000));
+}
+
+#[tokio::test]
+async fn vtxo_kinds() {
+	let ctx = TestContext::new("bark/vtxo_kinds").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	let bark3 = ctx.bark("bark3".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	assert_eq!(bark1.vtxos().await[0].kind, VtxoKind::Round);
+
+	bark1.send_round(bark2.vtxo_pubkey().await, Amount::from_sat(100_000)).await;
+	bark1.send_oor(bark3.vtxo_pubkey().await, Amount::from_sat(50_000)).await;
+
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_eq!(vtxos[0].kind, VtxoKind::Round);
+	let balance = bark2.balance().await;
+	assert_eq!(balance.offchain_round, Some(Amount::from_sat(100_000)));
+	assert_eq!(balance.offchain_oor, Some(Amount::ZERO));
+
+	let vtxos = bark3.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_eq!(vtxos[0].kind, VtxoKind::Oor);
+	let balance = bark3.balance().await;
+	assert_eq!(balance.offchain_round, Some(Amount::ZERO));
+	assert_eq!(balance.offchain_oor, Some(Amount::from_sat(50_000)));
+	let status = bark3.status().await;
+	assert_eq!(status.nb_oor_vtxos, 1);
+	assert_eq!(status.offchain_oor_balance, Amount::from_sat(50_000));
+
+	// The OOR change of the sender is an OOR vtxo too.
+	let vtxos = bark1.vtxos().await;
+	assert!(vtxos.iter().all(|v| v.kind == VtxoKind::Oor), "{:?}", vtxos);
+}
+
+#[tokio::test]
+async fn reused_key_refresh() {
+	let ctx = TestContext::new("bark/reused_key_refresh").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+	let pk2 = bark2.vtxo_pubkey().await;
+
+	bark1.send_oor(&pk2, Amount::from_sat(20_000)).await;
+	assert_eq!(bark2.status().await.nb_reused_key_vtxos, 0);
+	assert!(!bark2.vtxos().await[0].reused_key);
+
+	// The second payment to the same key flags both vtxos.
+	bark1.send_oor(&pk2, Amount::from_sat(30_000)).await;
+	assert_eq!(bark2.status().await.nb_reused_key_vtxos, 2);
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(vtxos.len(), 2);
+	assert!(vtxos.iter().all(|v| v.reused_key));
+
+	// With the policy on, the daemon moves them to a fresh key.
+	bark2.run(["config", "--refresh-reused-keys", "true", "--daemon-interval", "1"]).await;
+	let mut client = aspd.get_public_client().await;
+	let req = FreshRoundsRequest { start_height: 0 };
+	assert!(client.get_fresh_rounds(req.clone()).await.unwrap().into_inner().txids.is_empty());
+	bark2.start_daemon().await;
+	let mut refreshed = false;
+	for _ in 0..60 {
+		if !client.get_fresh_rounds(req.clone()).await.unwrap().into_inner().txids.is_empty() {
+			refreshed = true;
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	assert!(refreshed, "daemon didn't refresh the vtxos on the reused key");
+	bark2.stop_daemon().await;
+
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_ne!(vtxos[0].user_pubkey.to_string(), pk2);
+	assert!(!vtxos[0].reused_key);
+	assert_eq!(vtxos[0].amount, Amount::from_sat(50_000));
+	assert_eq!(bark2.status().await.nb_reused_key_vtxos, 0);
+}
+
+#[tokio::test]
+async fn receive_on_fresh_keys() {
+	let ctx = TestContext::new("bark/receive_on_fresh_keys").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+
+	let pk1 = bark2.fresh_vtxo_pubkey().await;
+	let pk2 = bark2.fresh_vtxo_pubkey().await;
+	assert_ne!(pk1, pk2);
+	assert_ne!(pk1, bark2.vtxo_pubkey().await);
+
+	// Payments to different fresh keys are not key reuse.
+	bark1.send_oor(&pk1, Amount::from_sat(20_000)).await;
+	bark1.send_oor(&pk2, Amount::from_sat(30_000)).await;
+	assert_eq!(bark2.offchain_balance().await, Amount::from_sat(50_000));
+	assert_eq!(bark2.status().await.nb_reused_key_vtxos, 0);
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(vtxos.len(), 2);
+	assert!(vtxos.iter().all(|v| !v.reused_key));
+}
+
+#[tokio::test]
+async fn vtxo_labels() {
+	let ctx = TestContext::new("bark/vtxo_labels").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard_with_label(Amount::from_sat(300_000), "savings").await;
+	bark1.onboard(Amount::from_sat(300_000)).await;
+	bitcoind.generate(1).await;
+
+	let mut labels = bark1.vtxos().await.into_iter().map(|v| v.labels).collect::<Vec<_>>();
+	labels.sort();
+	assert_eq!(labels, vec![vec![], vec!["savings".to_string()]]);
+
+	// Consolidating keeps the labels of the inputs.
+	bark1.refresh_all_with_label("consolidated").await;
+	let vtxos = bark1.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_eq!(vtxos[0].labels, vec!["savings".to_string(), "consolidated".to_string()]);
+
+	// The change of a payment keeps them too, the receiver doesn't get them.
+	let pk2 = bark2.vtxo_pubkey().await;
+	bark1.send_oor_with_label(&pk2, Amount::from_sat(100_000), "rent").await;
+	let vtxos = bark1.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_eq!(vtxos[0].labels, vec!["savings", "consolidated", "rent"]);
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert!(vtxos[0].labels.is_empty());
+}
+
+#[tokio::test]
+async fn fresh_change_keys() {
+	let ctx = TestContext::new("bark/fresh_change_keys").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bark1.run(["config", "--fresh-change-keys", "true"]).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(90_000)).await;
+	bark1.onboard(Amount::from_sat(80_000)).await;
+	let pk1 = bark1.vtxo_pubkey().await;
+	let pk2 = bark2.vtxo_pubkey().await;
+
+	// The change of an OOR payment goes to a fresh key.
+	bark1.send_oor(&pk2, Amount::from_sat(20_000)).await;
+	assert_eq!(58_035, bark1.offchain_balance().await.to_sat());
+	let vtxos = bark1.vtxos().await;
+	assert_eq!(1, vtxos.len());
+	let change_key1 = vtxos[0].user_pubkey.to_string();
+	assert_ne!(pk1, change_key1);
+
+	// We can spend the change and get change on yet another key.
+	let preview = bark1.simulate_send(&pk2, Amount::from_sat(10_000)).await;
+	bark1.send_oor(&pk2, Amount::from_sat(10_000)).await;
+	assert_eq!(preview.balance_after, bark1.offchain_balance().await);
+	let vtxos = bark1.vtxos().await;
+	assert_eq!(1, vtxos.len());
+	let change_key2 = vtxos[0].user_pubkey.to_string();
+	assert_ne!(pk1, change_key2);
+	assert_ne!(change_key1, change_key2);
+
+	// Round change is recognized as ours as well.
+	let before = bark1.offchain_balance().await;
+	bark1.send_round(&pk2, Amount::from_sat(5_000)).await;
+	assert_eq!(before - Amount::from_sat(5_000), bark1.offchain_balance().await);
+	let vtxos = bark1.vtxos().await;
+	assert_eq!(1, vtxos.len());
+	let change_key3 = vtxos[0].user_pubkey.to_string();
+	assert!(![&pk1, &change_key1, &change_key2].contains(&&change_key3));
+
+	assert_eq!(35_000, bark2.offchain_balance().await.to_sat());
+}
+
+#[tokio::test]
+async fn simulate_send() {
+	let ctx = TestContext::new("bark/simulate_send").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(90_000)).await;
+	bark1.onboard(Amount::from_sat(80_000)).await;
+	let pk2 = bark2.vtxo_pubkey().await;
+
+	// A simulated payment doesn't touch the wallet.
+	let before = bark1.offchain_balance().await;
+	let preview = bark1.simulate_send(&pk2, Amount::from_sat(20_000)).await;
+	assert_eq!(before, bark1.offchain_balance().await);
+	assert_eq!(1, preview.inputs.len());
+	assert_eq!(before - Amount::from_sat(20_000) - preview.fee, preview.balance_after);
+
+	let round_preview = bark1.simulate_send_round(&pk2, Amount::from_sat(20_000)).await;
+	assert_eq!(Amount::ZERO, round_preview.fee);
+	assert_eq!(before - Amount::from_sat(20_000), round_preview.balance_after);
+
+	// The preview matches the actual payment.
+	bark1.send_oor(&pk2, Amount::from_sat(20_000)).await;
+	assert_eq!(preview.balance_after, bark1.offchain_balance().await);
+}
+
+#[tokio::test]
+async fn exit_codes() {
+	let ctx = TestContext::new("bark/exit_codes").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let mut aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(90_000)).await;
+	bark1.onboard(Amount::from_sat(80_000)).await;
+	let pk2 = bark2.vtxo_pubkey().await;
+
+	let code = |e: anyhow::Error| e.downcast_ref::<CommandFailed>().unwrap().error_code();
+
+	let err = bark1.try_run(["send", &pk2.to_string(), "1000000 sat"]).await.unwrap_err();
+	assert_eq!(Some(ExitCode::InsufficientFunds), code(err));
+
+	let err = bark1.try_run(["send", "not-a-destination", "1000 sat"]).await.unwrap_err();
+	assert_eq!(Some(ExitCode::InvalidArgument), code(err));
+
+	aspd.stop().await.unwrap();
+	let err = bark1.try_run(["send", &pk2.to_string(), "1000 sat"]).await.unwrap_err();
+	assert_eq!(Some(ExitCode::AspUnreachable), code(err));
+}
+
+#[tokio::test]
+async fn onchain_fee_reserve() {
+	let ctx = TestContext::new("bark/onchain_fee_reserve").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	bitcoind.generate(106).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(100_000)).await;
+	bitcoind.generate(1).await;
+	bark1.run(["config", "--reserve", "50000 sat"]).await;
+
+	assert_eq!(Amount::from_sat(50_000), bark1.onchain_reserve().await);
+
+	// Both sending and onboarding into the reserve are refused by default.
+	let addr = bark2.get_onchain_address().await.to_string();
+	let err = bark1.try_run(["onchain", "send", &addr, "80000 sat"]).await.unwrap_err();
+	let code = |e: anyhow::Error| e.downcast_ref::<CommandFailed>().unwrap().error_code();
+	assert_eq!(Some(ExitCode::InsufficientFunds), code(err));
+	let err = bark1.try_run(["onboard", "80000 sat"]).await.unwrap_err();
+	assert_eq!(Some(ExitCode::InsufficientFunds), code(err));
+
+	// Spending above the reserve is fine.
+	bark1.run(["onchain", "send", &addr, "20000 sat"]).await;
+
+	// And the user can insist.
+	bark1.run(["onchain", "send", &addr, "60000 sat", "--allow-below-reserve"]).await;
+	bitcoind.generate(1).await;
+	assert_eq!(Amount::from_sat(80_000), bark2.onchain_balance().await);
+}
+
+#[tokio::test]
+async fn onboard_many() {
+	let ctx = TestContext::new("bark/onboard_many").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	let amounts = [Amount::from_sat(100_000), Amount::from_sat(200_000), Amount::from_sat(300_000)];
+	bark.onboard_many(&amounts).await;
+
+	let vtxos = bark.vtxos().await;
+	assert_eq!(3, vtxos.len());
+	let mut got = vtxos.iter().map(|v| v.amount).collect::<Vec<_>>();
+	got.sort();
+	assert_eq!(&amounts[..], &got[..]);
+	assert_eq!(Amount::from_sat(600_000), bark.offchain_balance().await);
+
+	// The onboarded vtxos can be refreshed like any other.
+	bitcoind.generate(12).await;
+	bark.refresh_all().await;
+	assert_eq!(1, bark.vtxos().await.len());
+}
+
+#[tokio::test]
+async fn onboard_resumes_after_crash() {
+	let ctx = TestContext::new("bark/onboard_resumes_after_crash").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	bitcoind.generate(106).await;
+	let bark = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bitcoind.generate(1).await;
+
+	let err = bark.onboard_crash_before_broadcast(Amount::from_sat(300_000)).await;
+	assert_eq!(err.exit_code, None, "bark should be killed by a signal");
+	let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+	assert!(mempool.is_empty(), "onboard tx shouldn't be broadcast yet");
+
+	// Opening the wallet again finishes the onboard.
+	assert_eq!(Amount::from_sat(300_000), bark.offchain_balance().await);
+	let mempool = bitcoind.sync_client().get_raw_mempool().unwrap();
+	assert_eq!(1, mempool.len());
+	bitcoind.generate(1).await;
+
+	// A new onboard doesn't conflict with the resumed one.
+	bark.onboard(Amount::from_sat(200_000)).await;
+	bitcoind.generate(1).await;
+	assert_eq!(2, bark.vtxos().await.len());
+	assert_eq!(Amount::from_sat(500_000), bark.offchain_balance().await);
+	assert!(bark.onchain_balance().await < Amount::from_sat(500_000));
+}
+
+#[tokio::test]
+async fn abandon_onboard_after_crash() {
+	let ctx = TestContext::new("bark/abandon_onboard_after_crash").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	bitcoind.generate(106).await;
+	let bark = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let funding_txid = bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+
+	let err = bark.onboard_crash_before_broadcast(Amount::from_sat(300_000)).await;
+	assert_eq!(err.exit_code, None, "bark should be killed by a signal");
+
+	// Replacing the funding tx makes the signed onboard tx invalid.
+	bitcoind.sync_client().bump_fee(&funding_txid, None).unwrap();
+	bitcoind.generate(1).await;
+	bark.abandon_onboard().await;
+	assert!(bark.vtxos().await.is_empty());
+	assert_eq!(Amount::ZERO, bark.offchain_balance().await);
+
+	// The wallet can onboard again.
+	bark.onboard(Amount::from_sat(200_000)).await;
+	bitcoind.generate(1).await;
+	assert_eq!(1, bark.vtxos().await.len());
+	assert_eq!(Amount::from_sat(200_000), bark.offchain_balance().await);
+}
+
+#[tokio::test]
+async fn send_round_split() {
+	let ctx = TestContext::new("bark/send_round_split").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(12).await;
+
+	// Each split becomes its own leaf in the vtxo tree.
+	let pk2 = bark2.vtxo_pubkey().await;
+	let splits = [Amount::from_sat(10_000), Amount::from_sat(20_000), Amount::from_sat(30_000)];
+	bark1.send_round_split(&pk2, &splits).await;
+
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(3, vtxos.len());
+	let mut got = vtxos.iter().map(|v| v.amount).collect::<Vec<_>>();
+	got.sort();
+	assert_eq!(&splits[..], &got[..]);
+
+	// Splits that don't add up to the amount are refused.
+	let err = bark1.try_run(
+		["send-round", &pk2, "50000 sat", "--split", "10000 sat", "--split", "20000 sat"],
+	).await.unwrap_err();
+	let code = err.downcast_ref::<CommandFailed>().unwrap().error_code();
+	assert_eq!(Some(ExitCode::InvalidArgument), code);
+}
+
+#[tokio::test]
+async fn send_onchain_in_round() {
+	let ctx = TestContext::new("bark/send_onchain_in_round").await;
+	let bitcoind = ctx.bitcoind("bitcoind-1").await;
+	let aspd = ctx.aspd("aspd-1", &bitcoind, None).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(12).await;
+
+	let addr = bark2.get_onchain_address().await;
+	bark1.send_onchain(&addr, Amount::from_sat(300_000)).await;
+	bitcoind.generate(1).await;
+	assert_eq!(Amount::from_sat(300_000), bark2.onchain_balance().await);
+	assert!(bark1.offchain_balance().await < Amount::from_sat(500_000));
+
+	// Addresses for other networks are refused.
+	let err = bark1.try_run([
+		"send", "--onchain", "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", "10000 sat",
+	]).await.unwrap_err();
+	let code = err.downcast_ref::<CommandFailed>().unwrap().error_code();
+	assert_eq!(Some(ExitCode::InvalidArgument), code);
+}
+
 #[tokio::test]
 async fn refresh() {
 	// Initialize the test
@@ -177,3 +830,293 @@ async fn refresh() {
 	assert_eq!(1, bark2.vtxos().await.len());
 }
 
+
+#[tokio::test]
+async fn detect_asp_sweep() {
+	let ctx = TestContext::new("bark/detect_asp_sweep").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(20),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+
+	// Fund the asp
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1".to_string(), &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+
+	// Give bark1 a round vtxo.
+	bark1.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark1.refresh_all().await;
+	bitcoind.generate(1).await;
+	assert_eq!(1, bark1.vtxos().await.len());
+	assert!(bark1.offchain_balance().await > Amount::ZERO);
+
+	// Let the vtxo expire, the asp will sweep it in the next round.
+	bitcoind.generate(20).await;
+	bark2.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark2.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	assert_eq!(Amount::ZERO, bark1.offchain_balance().await);
+	assert_eq!(0, bark1.vtxos().await.len());
+}
+
+#[tokio::test]
+async fn cmd_folder_retention() {
+	let ctx = TestContext::new("bark/cmd_folder_retention").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	let mut bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bark.set_cmd_retention(CmdRetention::Last(2));
+
+	for _ in 0..4 {
+		bark.vtxo_pubkey().await;
+	}
+	bark.try_run(["no-such-command"]).await.unwrap_err();
+	bark.vtxo_pubkey().await;
+
+	// The last two successful commands and the failed one are kept.
+	let mut folders = std::fs::read_dir(bark.datadir().join("cmd")).unwrap()
+		.map(|e| e.unwrap().file_name().into_string().unwrap().parse::<usize>().unwrap())
+		.collect::<Vec<_>>();
+	folders.sort();
+	assert_eq!(folders, vec![3, 4, 5]);
+}
+
+#[tokio::test]
+async fn watch_only_wallet() {
+	let ctx = TestContext::new("bark/watch_only_wallet").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+
+	bitcoind.generate(101).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark1.refresh_all().await;
+
+	let file = bark1.export_watchtower().await;
+	let watch = ctx.bark_watch_only("watch", &bitcoind, &aspd, &file).await;
+	assert_eq!(watch.offchain_balance().await, bark1.offchain_balance().await);
+
+	// The watch-only wallet has no keys to spend with.
+	let pk2 = bark2.vtxo_pubkey().await;
+	let err = watch.try_run(["send-round", &pk2, "10000 sat"]).await.unwrap_err();
+	let err = err.downcast::<CommandFailed>().unwrap();
+	assert_eq!(err.error_code(), Some(ExitCode::InvalidArgument));
+
+	// Once the wallet spends the watched vtxo, it's no longer counted.
+	bark1.send_round(&pk2, Amount::from_sat(100_000)).await;
+	assert_eq!(watch.offchain_balance().await, Amount::ZERO);
+}
+
+#[tokio::test]
+async fn watch_only_wallet_with_watch_key() {
+	let ctx = TestContext::new("bark/watch_only_wallet_with_watch_key").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+
+	bitcoind.generate(101).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark1.refresh_all().await;
+
+	let file = bark1.export_watch_key().await;
+	let watch = ctx.bark_watch_key("watch", &bitcoind, &aspd, &file).await;
+	assert_eq!(watch.offchain_balance().await, bark1.offchain_balance().await);
+
+	// The vtxos the wallet gets in later rounds are watched too.
+	bark1.refresh_all().await;
+	assert_eq!(watch.offchain_balance().await, bark1.offchain_balance().await);
+	let pk2 = bark2.vtxo_pubkey().await;
+	bark1.send_round(&pk2, Amount::from_sat(100_000)).await;
+	assert_eq!(watch.offchain_balance().await, bark1.offchain_balance().await);
+
+	let err = watch.try_run(["send-round", &pk2, "10000 sat"]).await.unwrap_err();
+	let err = err.downcast::<CommandFailed>().unwrap();
+	assert_eq!(err.error_code(), Some(ExitCode::InvalidArgument));
+}
+
+#[tokio::test]
+async fn daemon_refreshes_expiring_vtxos() {
+	let ctx = TestContext::new("bark/daemon_refreshes_expiring_vtxos").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_expiry_delta: Some(50),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	bark.run(["config", "--refresh-threshold", "10", "--daemon-interval", "1"]).await;
+	let onboard = bark.vtxos().await.pop().unwrap();
+
+	let mut client = aspd.get_public_client().await;
+	let req = FreshRoundsRequest { start_height: 0 };
+
+	bark.start_daemon().await;
+
+	// The vtxo isn't close to expiry yet, so the daemon leaves it alone.
+	tokio::time::sleep(Duration::from_secs(3)).await;
+	assert!(client.get_fresh_rounds(req.clone()).await.unwrap().into_inner().txids.is_empty());
+
+	// The daemon only opens the wallet when a round starts, so we can use
+	// it in between.
+	let mut balance = None;
+	for _ in 0..20 {
+		if let Ok(json) = bark.try_run(["balance", "--json"]).await {
+			balance = Some(serde_json::from_str::<Balance>(&json).unwrap().offchain);
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
+	}
+	assert_eq!(balance, Some(Amount::from_sat(800_000)), "wallet is locked by the daemon");
+
+	// Once it gets close, the daemon refreshes it in a round.
+	let tip = bitcoind.get_block_count().await as u32;
+	bitcoind.generate((onboard.expiry_height - tip - 5) as u64).await;
+	let mut refreshed = false;
+	for _ in 0..60 {
+		if !client.get_fresh_rounds(req.clone()).await.unwrap().into_inner().txids.is_empty() {
+			refreshed = true;
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	assert!(refreshed, "daemon didn't refresh the expiring vtxo");
+	bark.stop_daemon().await;
+
+	let vtxos = bark.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	assert_ne!(vtxos[0].id, onboard.id);
+	assert!(vtxos[0].expiry_height > onboard.expiry_height);
+}
+
+/// Wait until the daemon log of the wallet contains `pattern` `count` times.
+async fn wait_for_daemon_log(bark: &ark_testing::Bark, pattern: &str, count: usize) {
+	for _ in 0..120 {
+		if bark.daemon_log().matches(pattern).count() >= count {
+			return;
+		}
+		tokio::time::sleep(Duration::from_millis(500)).await;
+	}
+	panic!("daemon log doesn't contain \"{}\" {} time(s)", pattern, count);
+}
+
+#[tokio::test]
+async fn daemon_resubscribes_after_aspd_restart() {
+	let ctx = TestContext::new("bark/daemon_resubscribes_after_aspd_restart").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let mut aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(1_000_000)).await;
+	bark1.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+
+	const SUBSCRIBED: &str = "Subscribed to the round events of the ASP";
+	bark2.start_daemon().await;
+	wait_for_daemon_log(&bark2, SUBSCRIBED, 1).await;
+
+	// The subscription breaks when aspd goes away.
+	aspd.stop().await.unwrap();
+	wait_for_daemon_log(&bark2, "reconnecting", 1).await;
+
+	// Once aspd is back, the daemon subscribes again and receives the events
+	// of new rounds.
+	aspd.restart().await.unwrap();
+	wait_for_daemon_log(&bark2, SUBSCRIBED, 2).await;
+	bark1.refresh_all().await;
+	wait_for_daemon_log(&bark2, "finished, syncing", 1).await;
+	bark2.stop_daemon().await;
+}
+
+#[tokio::test]
+async fn failed_round_keeps_forfeited_inputs_locked() {
+	let ctx = TestContext::new("bark/failed_round_keeps_forfeited_inputs_locked").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	// bitcoind refuses round txs paying this much fees as absurd, so the
+	// round fails after we provided our forfeit signatures.
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(20_000).unwrap()),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(800_000), &bitcoind).await;
+	let vtxo = bark.vtxos().await[0].id;
+
+	bark.try_refresh_all().await.expect_err("round should fail");
+
+	// The ASP didn't forfeit our vtxo...
+	let mut client = aspd.get_public_client().await;
+	let status = client.get_vtxo_status(VtxoStatusRequest { vtxo_id: vtxo.bytes().to_vec(), vtxo: None })
+		.await.unwrap().into_inner().status;
+	assert_ne!(status, VtxoStatus::Forfeited as i32);
+
+	// ...but it still holds our forfeit signature and could broadcast the
+	// round tx, so we don't spend the vtxo again until the round tx can't
+	// confirm anymore.
+	assert!(bark.vtxos().await.is_empty());
+	assert_eq!(bark.offchain_balance().await, Amount::ZERO);
+}
+
+#[tokio::test]
+async fn conflicted_round_restores_forfeited_inputs() {
+	let ctx = TestContext::new("bark/conflicted_round_restores_forfeited_inputs").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	// The first round fails after we provided our forfeit signatures, see
+	// failed_round_keeps_forfeited_inputs_locked.
+	let mut aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		round_tx_feerate: Some(FeeRate::from_sat_per_vb(20_000).unwrap()),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	let locked = bark.vtxos().await[0].id;
+	bark.try_refresh_all().await.expect_err("round should fail");
+	assert!(bark.vtxos().await.is_empty());
+	assert_eq!(bark.status().await.pending_round_txids.len(), 1);
+
+	// With a sane fee rate, the ASP's next round tx spends the same wallet
+	// utxo as the failed one.
+	aspd.set_config_override("ARKD_ROUND_TX_FEERATE", 10_000);
+	aspd.restart().await.unwrap();
+
+	// While the outcome of the failed round is unknown, we can still take
+	// part in rounds with our other vtxos.
+	bark.onboard_and_confirm(Amount::from_sat(200_000), &bitcoind).await;
+	let other = bark.vtxos().await[0].id;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	// Now the failed round tx can't confirm anymore, so our forfeited vtxo
+	// is ours again.
+	let status = bark.status().await;
+	assert!(status.pending_round_txids.is_empty());
+	let vtxos = bark.vtxos().await;
+	assert_eq!(vtxos.len(), 2);
+	assert!(vtxos.iter().any(|v| v.id == locked));
+	assert!(!vtxos.iter().any(|v| v.id == other));
+	assert_eq!(bark.offchain_balance().await, Amount::from_sat(500_000));
+}
@@ -1,6 +1,6 @@
 
 use ark_testing::daemon::bitcoind::BitcoindConfig;
-use ark_testing::{context::TestContext, Bark, Bitcoind};
+use ark_testing::{context::TestContext, AspdConfig, Bark, Bitcoind};
 
 use bitcoin::FeeRate;
 use bitcoincore_rpc::bitcoin::amount::Amount;
@@ -9,10 +9,23 @@ use bitcoincore_rpc::RpcApi;
 async fn progress_exit(
 	bitcoind: &Bitcoind,
 	w: &Bark,
+) {
+	progress_exit_vtxos(bitcoind, w, &[] as &[String]).await;
+}
+
+/// Progress the exit until the given vtxos, or all if empty, are claimed.
+async fn progress_exit_vtxos(
+	bitcoind: &Bitcoind,
+	w: &Bark,
+	vtxos: &[impl std::fmt::Display],
 ) {
 	let mut flip = false;
 	for _ in 0..20 {
-		let res = w.exit().await;
+		let res = if vtxos.is_empty() {
+			w.exit().await
+		} else {
+			w.exit_vtxos(vtxos).await
+		};
 		if res.done {
 			return;
 		}
@@ -98,3 +111,205 @@ async fn unilateral_exit() {
 	progress_exit(&bitcoind, &bark4).await;
 	assert_eq!(34_996_095, bark4.onchain_balance().await.to_sat());
 }
+
+#[tokio::test]
+async fn unilateral_exit_absolute_timelock() {
+	let ctx = TestContext::new("unilateral_exit_absolute_timelock").await;
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		relay_fee: Some(FeeRate::from_sat_per_vb(8).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	let aspd = ctx.aspd_with_cfg("aspd", AspdConfig {
+		vtxo_exit_timelock: Some("absolute".into()),
+		..ctx.aspd_default_cfg("aspd", &bitcoind, None).await
+	}).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+	bark.refresh_all().await;
+	bitcoind.generate(1).await;
+
+	let vtxos = bark.vtxos().await;
+	assert_eq!(vtxos.len(), 1);
+	let claimable_height = vtxos[0].expiry_height + vtxos[0].exit_delta as u32;
+
+	// The exit can't be claimed before the absolute timelock.
+	let mut waited_for_timelock = false;
+	for _ in 0..20 {
+		let res = bark.exit().await;
+		if res.done {
+			break;
+		}
+		if let Some(height) = res.height {
+			assert_eq!(height, claimable_height);
+			waited_for_timelock = true;
+			let current = bitcoind.sync_client().get_block_count().unwrap();
+			bitcoind.generate(height as u64 - current).await;
+		} else {
+			bitcoind.generate(1).await;
+		}
+	}
+	assert!(waited_for_timelock);
+	let balance = bark.onchain_balance().await;
+	assert!(balance > Amount::from_sat(900_000), "exit not claimed, balance: {}", balance);
+}
+
+#[tokio::test]
+async fn round_tx_version_allows_csv_exit() {
+	let ctx = TestContext::new("round_tx_version_allows_csv_exit").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard(Amount::from_sat(800_000)).await;
+	bitcoind.generate(1).await;
+
+	// The round tx is version 2 with an anti-fee-sniping locktime.
+	let client = bitcoind.sync_client();
+	let height = client.get_block_count().unwrap();
+	bark.refresh_all().await;
+	let mempool = client.get_raw_mempool().unwrap();
+	assert_eq!(mempool.len(), 1);
+	let round_tx = client.get_raw_transaction(&mempool[0], None).unwrap();
+	assert_eq!(round_tx.version, bitcoin::transaction::Version::TWO);
+	assert_eq!(round_tx.lock_time.to_consensus_u32(), height as u32);
+
+	// And the relative timelocks in the exit of its vtxo are enforced.
+	bitcoind.generate(1).await;
+	let onchain_before = bark.onchain_balance().await;
+	progress_exit(&bitcoind, &bark).await;
+	assert!(bark.onchain_balance().await > onchain_before);
+}
+
+#[tokio::test]
+async fn exit_with_fee_rate() {
+	let ctx = TestContext::new("exit_with_fee_rate").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+
+	let fee_rate = FeeRate::from_sat_per_vb(30).unwrap();
+	let mut claim_txid = None;
+	for _ in 0..20 {
+		let res = bark.exit_with_fee_rate(fee_rate).await;
+		if let Some(txid) = res.claim_txid {
+			claim_txid = Some(txid);
+			break;
+		}
+		if let Some(height) = res.height {
+			let current = bitcoind.sync_client().get_block_count().unwrap();
+			bitcoind.generate(height as u64 - current).await;
+		} else {
+			bitcoind.generate(1).await;
+		}
+	}
+	let claim_txid = claim_txid.expect("exit wasn't claimed");
+
+	// The claim tx pays the fee rate we asked for.
+	let txid = claim_txid.to_string().parse().unwrap();
+	let entry = bitcoind.sync_client().get_mempool_entry(&txid).unwrap();
+	let fee = entry.fees.base.to_sat();
+	assert!(fee >= 30 * entry.vsize && fee < 31 * entry.vsize,
+		"claim tx pays {} sat for {} vbytes", fee, entry.vsize,
+	);
+}
+
+#[tokio::test]
+async fn verify_underfunded_exit_claim() {
+	let ctx = TestContext::new("verify_underfunded_exit_claim").await;
+	let bitcoind = ctx.bitcoind_with_cfg("bitcoind", BitcoindConfig {
+		relay_fee: Some(FeeRate::from_sat_per_vb(8).unwrap()),
+		..ctx.bitcoind_default_cfg("bitcoind")
+	}).await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark = ctx.bark("bark".to_string(), &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark, Amount::from_sat(1_000_000)).await;
+	bark.onboard_and_confirm(Amount::from_sat(300_000), &bitcoind).await;
+
+	// Confirm all exit txs until the exit is claimable.
+	let fee_rate = FeeRate::from_sat_per_vb(10).unwrap();
+	let mut claimable = false;
+	for _ in 0..20 {
+		let res = bark.exit_with_fee_rate(fee_rate).await;
+		assert!(res.claim_txid.is_none());
+		if let Some(height) = res.height {
+			let current = bitcoind.sync_client().get_block_count().unwrap();
+			bitcoind.generate(height as u64 - current).await;
+			claimable = true;
+			break;
+		}
+		bitcoind.generate(1).await;
+	}
+	assert!(claimable, "exit txs didn't confirm");
+
+	// A claim below the relay fee is flagged before we broadcast it.
+	let res = bark.verify_exit_with_fee_rate(FeeRate::from_sat_per_vb(2).unwrap()).await;
+	assert!(!res.accepted);
+	let reason = res.reject_reason.expect("no reject reason");
+	assert!(reason.contains("min relay fee not met"), "reject reason: {}", reason);
+
+	// Progressing the exit with that fee rate doesn't broadcast the claim either.
+	bark.try_run(["exit", "--only-progress", "--feerate", "2"]).await.unwrap_err();
+	assert!(bitcoind.sync_client().get_raw_mempool().unwrap().is_empty());
+
+	// With enough fee it would be accepted, verifying still broadcasts nothing.
+	let res = bark.verify_exit_with_fee_rate(fee_rate).await;
+	assert!(res.accepted, "claim rejected: {:?}", res.reject_reason);
+	assert!(res.fee.unwrap() >= Amount::from_sat(10 * res.vsize.unwrap()));
+	assert!(bitcoind.sync_client().get_raw_mempool().unwrap().is_empty());
+
+	let res = bark.exit_with_fee_rate(fee_rate).await;
+	assert!(res.claim_txid.is_some());
+}
+
+#[tokio::test]
+async fn exit_selected_vtxos() {
+	let ctx = TestContext::new("exit/exit_selected_vtxos").await;
+	let bitcoind = ctx.bitcoind("bitcoind").await;
+	let aspd = ctx.aspd("aspd", &bitcoind, None).await;
+	bitcoind.generate(106).await;
+	bitcoind.fund_aspd(&aspd, Amount::from_int_btc(10)).await;
+
+	let bark1 = ctx.bark("bark1", &bitcoind, &aspd).await;
+	let bark2 = ctx.bark("bark2", &bitcoind, &aspd).await;
+	bitcoind.fund_bark(&bark1, Amount::from_sat(2_000_000)).await;
+	bitcoind.fund_bark(&bark2, Amount::from_sat(1_000_000)).await;
+	bark1.onboard(Amount::from_sat(1_500_000)).await;
+	bitcoind.generate(12).await;
+
+	// All vtxos are leaves of the same tree, so they share part of their branch.
+	let pk2 = bark2.vtxo_pubkey().await;
+	let splits = [Amount::from_sat(200_000), Amount::from_sat(300_000), Amount::from_sat(400_000)];
+	bark1.send_round_split(&pk2, &splits).await;
+	let vtxos = bark2.vtxos().await;
+	assert_eq!(vtxos.len(), 3);
+	let exited = vtxos.iter().find(|v| v.amount == splits[2]).unwrap().id;
+	let onchain = bark2.onchain_balance().await;
+
+	bitcoind.generate(1).await;
+	progress_exit_vtxos(&bitcoind, &bark2, &[exited]).await;
+	assert!(bark2.onchain_balance().await > onchain + Amount::from_sat(300_000));
+
+	// The other vtxos were left untouched and can still be refreshed.
+	let mut left = bark2.vtxos().await.into_iter().map(|v| v.amount).collect::<Vec<_>>();
+	left.sort();
+	assert_eq!(&left[..], &splits[..2]);
+	bark2.refresh_all().await;
+	let refreshed = bark2.vtxos().await;
+	assert_eq!(refreshed.len(), 1);
+	assert!(refreshed[0].amount <= splits[0] + splits[1]);
+}
@@ -13,6 +13,20 @@ pub struct ArkInfo {
     pub vtxo_exit_delta: u32,
     #[prost(uint32, tag = "6")]
     pub vtxo_expiry_delta: u32,
+    #[prost(enumeration = "VtxoOutputKeyPolicy", tag = "7")]
+    pub vtxo_output_key_policy: i32,
+    #[prost(uint32, tag = "8")]
+    pub onboard_confirmations: u32,
+    #[prost(enumeration = "VtxoExitTimelock", tag = "9")]
+    pub vtxo_exit_timelock: i32,
+    #[prost(enumeration = "VtxoScriptType", tag = "10")]
+    pub vtxo_script_type: i32,
+    /// / The minimum amount of each output of an OOR payment.
+    #[prost(uint64, tag = "11")]
+    pub oor_min_amount_sat: u64,
+    /// / The time of the ASP when answering, in milliseconds since the unix epoch.
+    #[prost(uint64, tag = "12")]
+    pub timestamp_ms: u64,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct FreshRoundsRequest {
@@ -35,6 +49,55 @@ pub struct RoundInfo {
     pub round_tx: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", tag = "2")]
     pub signed_vtxos: ::prost::alloc::vec::Vec<u8>,
+    /// / The sequence number of the round, not set for old rounds.
+    #[prost(uint64, optional, tag = "3")]
+    pub round_seq: ::core::option::Option<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VtxoStatusRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub vtxo_id: ::prost::alloc::vec::Vec<u8>,
+    /// / The encoded vtxo itself. Needed for vtxos that were not created in
+    /// / a round, their status is determined by their inputs or onboard utxo.
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub vtxo: ::core::option::Option<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct VtxoStatusResponse {
+    #[prost(enumeration = "VtxoStatus", tag = "1")]
+    pub status: i32,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VtxosForPubkeyRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub pubkey: ::prost::alloc::vec::Vec<u8>,
+    /// / The time of the request, in milliseconds since the unix epoch.
+    #[prost(uint64, tag = "2")]
+    pub timestamp_ms: u64,
+    /// / BIP-340 signature with the pubkey over the request, see
+    /// / `aspd_rpc_client::vtxos_for_pubkey_message`. With a delegation,
+    /// / the signature is made with its watch key instead.
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+    /// / Set by watch-only wallets, which don't have the key of the pubkey.
+    #[prost(message, optional, tag = "4")]
+    pub delegation: ::core::option::Option<WatchDelegation>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WatchDelegation {
+    /// / The key the owner of the pubkey allows to list its vtxos.
+    #[prost(bytes = "vec", tag = "1")]
+    pub watch_pubkey: ::prost::alloc::vec::Vec<u8>,
+    /// / BIP-340 signature with the pubkey, see
+    /// / `aspd_rpc_client::watch_delegation_message`.
+    #[prost(bytes = "vec", tag = "2")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VtxosForPubkeyResponse {
+    /// / Serialized unspent round `Vtxo`s of the pubkey.
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub vtxos: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct OnboardCosignRequest {
@@ -48,6 +111,18 @@ pub struct OnboardCosignResponse {
     pub asp_part: ::prost::alloc::vec::Vec<u8>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OnboardCosignBatchRequest {
+    /// / Serialized `UserPart`s
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub user_parts: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OnboardCosignBatchResponse {
+    /// / Serialized `AspPart`s, in the order of the requested user parts.
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub asp_parts: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct OorCosignRequest {
     #[prost(bytes = "vec", tag = "1")]
     pub payment: ::prost::alloc::vec::Vec<u8>,
@@ -127,6 +202,14 @@ pub struct RoundStart {
     pub round_id: u64,
     #[prost(uint64, tag = "2")]
     pub offboard_feerate_sat_vkb: u64,
+    /// / A fresh random value for every round (attempt) start that has to be
+    /// / echoed in payment submissions.
+    #[prost(uint64, tag = "3")]
+    pub round_epoch: u64,
+    /// / The sequence number of the round, it increases by one for every round
+    /// / the ASP starts.
+    #[prost(uint64, tag = "4")]
+    pub round_seq: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ForfeitNonces {
@@ -148,6 +231,8 @@ pub struct VtxoProposal {
     pub vtxos_signers: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
     #[prost(bytes = "vec", repeated, tag = "5")]
     pub vtxos_agg_nonces: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    #[prost(uint64, tag = "6")]
+    pub round_seq: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RoundProposal {
@@ -161,6 +246,8 @@ pub struct RoundProposal {
     pub round_tx: ::prost::alloc::vec::Vec<u8>,
     #[prost(message, repeated, tag = "6")]
     pub forfeit_nonces: ::prost::alloc::vec::Vec<ForfeitNonces>,
+    #[prost(uint64, tag = "7")]
+    pub round_seq: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RoundFinished {
@@ -172,10 +259,24 @@ pub struct RoundFinished {
     /// / The signed round tx.
     #[prost(bytes = "vec", tag = "3")]
     pub round_tx: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "4")]
+    pub round_seq: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RoundFailed {
+    #[prost(uint64, tag = "1")]
+    pub round_id: u64,
+    /// / Why the ASP gave up on the round.
+    #[prost(string, tag = "2")]
+    pub reason: ::prost::alloc::string::String,
+    #[prost(enumeration = "RoundFailureKind", tag = "3")]
+    pub kind: i32,
+    #[prost(uint64, tag = "4")]
+    pub round_seq: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct RoundEvent {
-    #[prost(oneof = "round_event::Event", tags = "1, 2, 3, 4")]
+    #[prost(oneof = "round_event::Event", tags = "1, 2, 3, 4, 5")]
     pub event: ::core::option::Option<round_event::Event>,
 }
 /// Nested message and enum types in `RoundEvent`.
@@ -190,6 +291,8 @@ pub mod round_event {
         RoundProposal(super::RoundProposal),
         #[prost(message, tag = "4")]
         Finished(super::RoundFinished),
+        #[prost(message, tag = "5")]
+        Failed(super::RoundFailed),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -197,7 +300,11 @@ pub struct Payment {
     /// amount in sats
     #[prost(uint64, tag = "1")]
     pub amount: u64,
-    #[prost(oneof = "payment::Destination", tags = "2, 3")]
+    /// / Split a vtxo payment into multiple vtxos with these amounts.
+    /// / They should sum to the payment amount.
+    #[prost(uint64, repeated, tag = "4")]
+    pub split_amounts: ::prost::alloc::vec::Vec<u64>,
+    #[prost(oneof = "payment::Destination", tags = "2, 3, 5")]
     pub destination: ::core::option::Option<payment::Destination>,
 }
 /// Nested message and enum types in `Payment`.
@@ -208,6 +315,10 @@ pub mod payment {
         VtxoPublicKey(::prost::alloc::vec::Vec<u8>),
         #[prost(bytes, tag = "3")]
         OffboardSpk(::prost::alloc::vec::Vec<u8>),
+        /// / An on-chain address to pay in the round tx, the ASP checks
+        /// / that it's for its network.
+        #[prost(string, tag = "5")]
+        OffboardAddress(::prost::alloc::string::String),
     }
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -221,6 +332,36 @@ pub struct SubmitPaymentRequest {
     pub cosign_pubkey: ::prost::alloc::vec::Vec<u8>,
     #[prost(bytes = "vec", repeated, tag = "4")]
     pub public_nonces: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// / The epoch of the round start this payment is for.
+    #[prost(uint64, tag = "5")]
+    pub round_epoch: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CancelPaymentRequest {
+    /// / One of the input vtxos of the payment, the whole payment is cancelled.
+    #[prost(bytes = "vec", tag = "1")]
+    pub input_vtxo: ::prost::alloc::vec::Vec<u8>,
+    /// / The epoch of the round start the payment was submitted for.
+    #[prost(uint64, tag = "2")]
+    pub round_epoch: u64,
+    /// / A signature with the key of the input vtxo, to prove that it's ours.
+    #[prost(bytes = "vec", tag = "3")]
+    pub signature: ::prost::alloc::vec::Vec<u8>,
+    /// / The cosign pubkey the payment was submitted with.
+    #[prost(bytes = "vec", tag = "4")]
+    pub cosign_pubkey: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubmitResponse {
+    #[prost(enumeration = "SubmitRejectReason", tag = "1")]
+    pub reject_reason: i32,
+    /// / Details on why the submission was rejected.
+    #[prost(string, tag = "2")]
+    pub reject_message: ::prost::alloc::string::String,
+    /// / The expected start of the next round in milliseconds since the unix
+    /// / epoch, set when the submission didn't fit in this round.
+    #[prost(uint64, tag = "3")]
+    pub next_round_start_ms: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ForfeitSignatures {
@@ -236,6 +377,32 @@ pub struct ForfeitSignaturesRequest {
     #[prost(message, repeated, tag = "1")]
     pub signatures: ::prost::alloc::vec::Vec<ForfeitSignatures>,
 }
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct RoundConnectorsRequest {
+    #[prost(uint64, tag = "1")]
+    pub round_id: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Connector {
+    /// / The consensus-encoded outpoint of the connector.
+    #[prost(bytes = "vec", tag = "1")]
+    pub outpoint: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub script_pubkey: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub amount: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RoundConnectors {
+    #[prost(uint64, tag = "1")]
+    pub round_id: u64,
+    /// / The connectors in the order they have to be used for forfeit signatures.
+    #[prost(message, repeated, tag = "2")]
+    pub connectors: ::prost::alloc::vec::Vec<Connector>,
+    /// / The weight of a signed connector input, in weight units.
+    #[prost(uint64, tag = "3")]
+    pub input_weight: u64,
+}
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct VtxoSignaturesRequest {
     /// / The cosign pubkey these signatures are for.
@@ -250,12 +417,337 @@ pub struct WalletStatusResponse {
     pub address: ::prost::alloc::string::String,
     #[prost(uint64, tag = "2")]
     pub balance: u64,
+    /// / The value of our own vtxos in rounds we didn't sweep yet.
+    #[prost(uint64, tag = "3")]
+    pub vtxo_balance: u64,
+    /// / The part of the balance in coinbase outputs that can't be spent yet.
+    #[prost(uint64, tag = "4")]
+    pub immature_balance: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WalletDescriptorResponse {
+    /// / The public descriptor of the onchain wallet, it's used for both
+    /// / receive and change addresses.
+    #[prost(string, tag = "1")]
+    pub descriptor: ::prost::alloc::string::String,
+    /// / The current receive address.
+    #[prost(string, tag = "2")]
+    pub address: ::prost::alloc::string::String,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct TriggerRoundRequest {
+    /// / The absolute expiry height of the round's vtxos. If not set, the
+    /// / round expires after the configured vtxo expiry delta.
+    #[prost(uint32, optional, tag = "1")]
+    pub expiry_height: ::core::option::Option<u32>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SweepRoundRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub round_txid: ::prost::alloc::vec::Vec<u8>,
+    /// / The feerate of the sweep tx in sat/kwu.
+    #[prost(uint64, tag = "2")]
+    pub fee_rate: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SweepRoundResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub sweep_txid: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SweepExpiredRoundsRequest {
+    /// / The feerate of the sweep txs in sat/kwu.
+    #[prost(uint64, tag = "1")]
+    pub fee_rate: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SweepExpiredRoundsResponse {
+    /// / The sweep txs, each spends at most the configured sweep batch max
+    /// / inputs.
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub sweep_txids: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BumpRoundTxRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub round_txid: ::prost::alloc::vec::Vec<u8>,
+    /// / The feerate in sat/kwu the round tx and its bump tx should pay together.
+    #[prost(uint64, tag = "2")]
+    pub fee_rate: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BumpRoundTxResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub bump_txid: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PhaseHistogram {
+    /// / The name of the round phase.
+    #[prost(string, tag = "1")]
+    pub phase: ::prost::alloc::string::String,
+    /// / The upper bounds of the buckets in milliseconds. There is one more
+    /// / bucket than bounds for everything above the last bound.
+    #[prost(uint64, repeated, tag = "2")]
+    pub bucket_bounds_ms: ::prost::alloc::vec::Vec<u64>,
+    /// / The number of rounds in each bucket, not cumulative.
+    #[prost(uint64, repeated, tag = "3")]
+    pub bucket_counts: ::prost::alloc::vec::Vec<u64>,
+    #[prost(uint64, tag = "4")]
+    pub count: u64,
+    #[prost(uint64, tag = "5")]
+    pub sum_ms: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RoundMetricsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub phases: ::prost::alloc::vec::Vec<PhaseHistogram>,
+    /// / The number of rounds skipped because our wallet couldn't fund them.
+    #[prost(uint64, tag = "2")]
+    pub insufficient_funds_rounds: u64,
+    /// / The size of the onboard nonce pool, not set if there is no pool.
+    #[prost(uint64, optional, tag = "3")]
+    pub onboard_nonce_pool_size: ::core::option::Option<u64>,
+    /// / The number of nonces currently in the onboard nonce pool.
+    #[prost(uint64, tag = "4")]
+    pub onboard_nonce_pool_occupancy: u64,
+    /// / The number of onboards rejected because the nonce pool was empty.
+    #[prost(uint64, tag = "5")]
+    pub onboard_nonce_pool_exhausted: u64,
+    /// / The number of consecutive failed rounds we're backing off for.
+    #[prost(uint64, tag = "6")]
+    pub round_backoff_level: u64,
+    /// / The current time between rounds in milliseconds, including backoff.
+    #[prost(uint64, tag = "7")]
+    pub round_backoff_interval_ms: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PendingPayment {
+    #[prost(bytes = "vec", tag = "1")]
+    pub cosign_pubkey: ::prost::alloc::vec::Vec<u8>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub input_vtxo_ids: ::prost::alloc::vec::Vec<::prost::alloc::vec::Vec<u8>>,
+    /// / The total amount of the vtxo outputs.
+    #[prost(uint64, tag = "3")]
+    pub output_amount: u64,
+    /// / The total amount of the offboards.
+    #[prost(uint64, tag = "4")]
+    pub offboard_amount: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PendingPaymentsResponse {
+    /// / Empty if no round is collecting payments at the moment.
+    #[prost(message, repeated, tag = "1")]
+    pub payments: ::prost::alloc::vec::Vec<PendingPayment>,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct Empty {}
+/// / The kind of failure of a round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum RoundFailureKind {
+    /// / Any failure without a more specific kind.
+    RoundFailureOther = 0,
+    /// / The ASP wallet doesn't have enough funds to fund the round tx.
+    InsufficientAspFunds = 1,
+}
+impl RoundFailureKind {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            RoundFailureKind::RoundFailureOther => "ROUND_FAILURE_OTHER",
+            RoundFailureKind::InsufficientAspFunds => "INSUFFICIENT_ASP_FUNDS",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ROUND_FAILURE_OTHER" => Some(Self::RoundFailureOther),
+            "INSUFFICIENT_ASP_FUNDS" => Some(Self::InsufficientAspFunds),
+            _ => None,
+        }
+    }
+}
+/// / Why the ASP rejected a submission to a round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum SubmitRejectReason {
+    /// / The submission was accepted.
+    Accepted = 0,
+    /// / The submission was for an earlier round start, wait for the next one.
+    StaleEpoch = 1,
+    /// / An input vtxo is already used by another payment in the round.
+    DoubleSpend = 2,
+    /// / An output is below the dust limit.
+    Dust = 3,
+    /// / The round already stopped accepting this kind of submission.
+    PastDeadline = 4,
+    /// / The round has no room left for the outputs, try the next round.
+    OverCapacity = 5,
+    /// / A forfeit signature is invalid.
+    BadForfeitSignature = 6,
+    /// / An input vtxo is not accepted in rounds.
+    InvalidInput = 7,
+    /// / The payment is invalid, f.e. it spends more than its inputs.
+    InvalidPayment = 8,
+    /// / The round reached its maximum number of participants, try the next round.
+    RoundFull = 9,
+    /// / There is no payment with the input in the round.
+    UnknownPayment = 10,
+}
+impl SubmitRejectReason {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            SubmitRejectReason::Accepted => "ACCEPTED",
+            SubmitRejectReason::StaleEpoch => "STALE_EPOCH",
+            SubmitRejectReason::DoubleSpend => "DOUBLE_SPEND",
+            SubmitRejectReason::Dust => "DUST",
+            SubmitRejectReason::PastDeadline => "PAST_DEADLINE",
+            SubmitRejectReason::OverCapacity => "OVER_CAPACITY",
+            SubmitRejectReason::BadForfeitSignature => "BAD_FORFEIT_SIGNATURE",
+            SubmitRejectReason::InvalidInput => "INVALID_INPUT",
+            SubmitRejectReason::InvalidPayment => "INVALID_PAYMENT",
+            SubmitRejectReason::RoundFull => "ROUND_FULL",
+            SubmitRejectReason::UnknownPayment => "UNKNOWN_PAYMENT",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ACCEPTED" => Some(Self::Accepted),
+            "STALE_EPOCH" => Some(Self::StaleEpoch),
+            "DOUBLE_SPEND" => Some(Self::DoubleSpend),
+            "DUST" => Some(Self::Dust),
+            "PAST_DEADLINE" => Some(Self::PastDeadline),
+            "OVER_CAPACITY" => Some(Self::OverCapacity),
+            "BAD_FORFEIT_SIGNATURE" => Some(Self::BadForfeitSignature),
+            "INVALID_INPUT" => Some(Self::InvalidInput),
+            "INVALID_PAYMENT" => Some(Self::InvalidPayment),
+            "ROUND_FULL" => Some(Self::RoundFull),
+            "UNKNOWN_PAYMENT" => Some(Self::UnknownPayment),
+            _ => None,
+        }
+    }
+}
 /// / Primitives
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
+pub enum VtxoOutputKeyPolicy {
+    MerkleRootTweak = 0,
+    PlainAggregate = 1,
+}
+impl VtxoOutputKeyPolicy {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            VtxoOutputKeyPolicy::MerkleRootTweak => "MERKLE_ROOT_TWEAK",
+            VtxoOutputKeyPolicy::PlainAggregate => "PLAIN_AGGREGATE",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "MERKLE_ROOT_TWEAK" => Some(Self::MerkleRootTweak),
+            "PLAIN_AGGREGATE" => Some(Self::PlainAggregate),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum VtxoExitTimelock {
+    Relative = 0,
+    Absolute = 1,
+}
+impl VtxoExitTimelock {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            VtxoExitTimelock::Relative => "RELATIVE",
+            VtxoExitTimelock::Absolute => "ABSOLUTE",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "RELATIVE" => Some(Self::Relative),
+            "ABSOLUTE" => Some(Self::Absolute),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum VtxoScriptType {
+    Taproot = 0,
+}
+impl VtxoScriptType {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            VtxoScriptType::Taproot => "TAPROOT",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "TAPROOT" => Some(Self::Taproot),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum VtxoStatus {
+    Unknown = 0,
+    Active = 1,
+    Forfeited = 2,
+    Swept = 3,
+    Expired = 4,
+}
+impl VtxoStatus {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            VtxoStatus::Unknown => "UNKNOWN",
+            VtxoStatus::Active => "ACTIVE",
+            VtxoStatus::Forfeited => "FORFEITED",
+            VtxoStatus::Swept => "SWEPT",
+            VtxoStatus::Expired => "EXPIRED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "UNKNOWN" => Some(Self::Unknown),
+            "ACTIVE" => Some(Self::Active),
+            "FORFEITED" => Some(Self::Forfeited),
+            "SWEPT" => Some(Self::Swept),
+            "EXPIRED" => Some(Self::Expired),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
 pub enum PaymentStatus {
     Pending = 0,
     Failed = 1,
@@ -432,6 +924,58 @@ pub mod ark_service_client {
             req.extensions_mut().insert(GrpcMethod::new("aspd.ArkService", "GetRound"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn get_vtxo_status(
+            &mut self,
+            request: impl tonic::IntoRequest<super::VtxoStatusRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::VtxoStatusResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.ArkService/GetVtxoStatus",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.ArkService", "GetVtxoStatus"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// / The round vtxos of a pubkey, to restore a wallet. Requires a signature
+        /// / of the pubkey to prove ownership, or of a watch key it delegated to.
+        pub async fn get_vtxos_for_pubkey(
+            &mut self,
+            request: impl tonic::IntoRequest<super::VtxosForPubkeyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::VtxosForPubkeyResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.ArkService/GetVtxosForPubkey",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.ArkService", "GetVtxosForPubkey"));
+            self.inner.unary(req, path, codec).await
+        }
         /// * ONBOARDING *
         pub async fn request_onboard_cosign(
             &mut self,
@@ -458,6 +1002,31 @@ pub mod ark_service_client {
                 .insert(GrpcMethod::new("aspd.ArkService", "RequestOnboardCosign"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn request_onboard_cosign_batch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::OnboardCosignBatchRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::OnboardCosignBatchResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.ArkService/RequestOnboardCosignBatch",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.ArkService", "RequestOnboardCosignBatch"));
+            self.inner.unary(req, path, codec).await
+        }
         /// * OOR PAYMENTS*
         pub async fn request_oor_cosign(
             &mut self,
@@ -611,7 +1180,7 @@ pub mod ark_service_client {
         pub async fn submit_payment(
             &mut self,
             request: impl tonic::IntoRequest<super::SubmitPaymentRequest>,
-        ) -> std::result::Result<tonic::Response<super::Empty>, tonic::Status> {
+        ) -> std::result::Result<tonic::Response<super::SubmitResponse>, tonic::Status> {
             self.inner
                 .ready()
                 .await
@@ -630,6 +1199,29 @@ pub mod ark_service_client {
                 .insert(GrpcMethod::new("aspd.ArkService", "SubmitPayment"));
             self.inner.unary(req, path, codec).await
         }
+        /// / Withdraw a payment from the round before the submit window closes.
+        pub async fn cancel_payment(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CancelPaymentRequest>,
+        ) -> std::result::Result<tonic::Response<super::SubmitResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.ArkService/CancelPayment",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.ArkService", "CancelPayment"));
+            self.inner.unary(req, path, codec).await
+        }
         pub async fn provide_vtxo_signatures(
             &mut self,
             request: impl tonic::IntoRequest<super::VtxoSignaturesRequest>,
@@ -655,7 +1247,7 @@ pub mod ark_service_client {
         pub async fn provide_forfeit_signatures(
             &mut self,
             request: impl tonic::IntoRequest<super::ForfeitSignaturesRequest>,
-        ) -> std::result::Result<tonic::Response<super::Empty>, tonic::Status> {
+        ) -> std::result::Result<tonic::Response<super::SubmitResponse>, tonic::Status> {
             self.inner
                 .ready()
                 .await
@@ -674,6 +1266,28 @@ pub mod ark_service_client {
                 .insert(GrpcMethod::new("aspd.ArkService", "ProvideForfeitSignatures"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn get_round_connectors(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RoundConnectorsRequest>,
+        ) -> std::result::Result<tonic::Response<super::RoundConnectors>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.ArkService/GetRoundConnectors",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.ArkService", "GetRoundConnectors"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated client implementations.
@@ -787,9 +1401,34 @@ pub mod admin_service_client {
                 .insert(GrpcMethod::new("aspd.AdminService", "WalletStatus"));
             self.inner.unary(req, path, codec).await
         }
-        pub async fn trigger_round(
+        /// / The public descriptor of our onchain wallet, to import it as watch-only
+        /// / elsewhere. All private key material is left out.
+        pub async fn wallet_descriptor(
             &mut self,
             request: impl tonic::IntoRequest<super::Empty>,
+        ) -> std::result::Result<tonic::Response<super::WalletDescriptorResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/WalletDescriptor",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "WalletDescriptor"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Start a round right away.
+        pub async fn trigger_round(
+            &mut self,
+            request: impl tonic::IntoRequest<super::TriggerRoundRequest>,
         ) -> std::result::Result<tonic::Response<super::Empty>, tonic::Status> {
             self.inner
                 .ready()
@@ -809,6 +1448,75 @@ pub mod admin_service_client {
                 .insert(GrpcMethod::new("aspd.AdminService", "TriggerRound"));
             self.inner.unary(req, path, codec).await
         }
+        /// / Sweep the outputs of an expired round right away.
+        pub async fn sweep_round(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SweepRoundRequest>,
+        ) -> std::result::Result<tonic::Response<super::SweepRoundResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/SweepRound",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "SweepRound"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Sweep the outputs of all expired rounds right away.
+        pub async fn sweep_expired_rounds(
+            &mut self,
+            request: impl tonic::IntoRequest<super::SweepExpiredRoundsRequest>,
+        ) -> std::result::Result<tonic::Response<super::SweepExpiredRoundsResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/SweepExpiredRounds",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "SweepExpiredRounds"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// / Bump the fee of an unconfirmed round tx, replacing an earlier bump tx.
+        pub async fn bump_round_tx(
+            &mut self,
+            request: impl tonic::IntoRequest<super::BumpRoundTxRequest>,
+        ) -> std::result::Result<tonic::Response<super::BumpRoundTxResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/BumpRoundTx",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "BumpRoundTx"));
+            self.inner.unary(req, path, codec).await
+        }
         pub async fn stop(
             &mut self,
             request: impl tonic::IntoRequest<super::Empty>,
@@ -828,5 +1536,74 @@ pub mod admin_service_client {
             req.extensions_mut().insert(GrpcMethod::new("aspd.AdminService", "Stop"));
             self.inner.unary(req, path, codec).await
         }
+        /// / Shut down gracefully: finish the round in progress and stop all
+        /// / services. Requires the admin token.
+        pub async fn shutdown(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Empty>,
+        ) -> std::result::Result<tonic::Response<super::Empty>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/Shutdown",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "Shutdown"));
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn round_metrics(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Empty>,
+        ) -> std::result::Result<tonic::Response<super::RoundMetricsResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/RoundMetrics",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "RoundMetrics"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// / The payments submitted to the round that is collecting payments.
+        pub async fn pending_payments(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Empty>,
+        ) -> std::result::Result<tonic::Response<super::PendingPaymentsResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/aspd.AdminService/PendingPayments",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("aspd.AdminService", "PendingPayments"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
@@ -1,4 +1,6 @@
+use ark::{ExitTimelockType, VtxoScriptType};
 use ark::lightning::PaymentStatus;
+use ark::tree::signed::OutputKeyPolicy;
 
 impl From<crate::PaymentStatus> for PaymentStatus {
 	fn from(value: crate::PaymentStatus) -> Self {
@@ -9,3 +11,29 @@ impl From<crate::PaymentStatus> for PaymentStatus {
 		}
 	}
 }
+
+impl From<crate::VtxoOutputKeyPolicy> for OutputKeyPolicy {
+	fn from(value: crate::VtxoOutputKeyPolicy) -> Self {
+		match value {
+			crate::VtxoOutputKeyPolicy::MerkleRootTweak => Self::MerkleRootTweak,
+			crate::VtxoOutputKeyPolicy::PlainAggregate => Self::PlainAggregate,
+		}
+	}
+}
+
+impl From<crate::VtxoExitTimelock> for ExitTimelockType {
+	fn from(value: crate::VtxoExitTimelock) -> Self {
+		match value {
+			crate::VtxoExitTimelock::Relative => Self::Relative,
+			crate::VtxoExitTimelock::Absolute => Self::Absolute,
+		}
+	}
+}
+
+impl From<crate::VtxoScriptType> for VtxoScriptType {
+	fn from(value: crate::VtxoScriptType) -> Self {
+		match value {
+			crate::VtxoScriptType::Taproot => Self::Taproot,
+		}
+	}
+}
@@ -2,7 +2,65 @@
 
 mod aspd;
 
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, PublicKey};
+
 pub use aspd::*;
 pub use aspd::ark_service_client::ArkServiceClient;
 pub use aspd::admin_service_client::AdminServiceClient;
 pub mod convert;
+
+/// The request metadata key that carries the admin token.
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+const VTXOS_FOR_PUBKEY_TAG: &[u8] = b"aspd/vtxos_for_pubkey";
+
+/// The message a client signs with the key of the pubkey for a
+/// [VtxosForPubkeyRequest], to prove that it owns the pubkey.
+///
+/// It commits to the pubkey of the ASP, so that a request for one ASP
+/// can't be replayed to another one.
+pub fn vtxos_for_pubkey_message(
+	asp_pubkey: PublicKey,
+	pubkey: PublicKey,
+	timestamp_ms: u64,
+) -> secp256k1::Message {
+	let mut engine = sha256::Hash::engine();
+	engine.input(VTXOS_FOR_PUBKEY_TAG);
+	engine.input(&asp_pubkey.serialize());
+	engine.input(&pubkey.serialize());
+	engine.input(&timestamp_ms.to_be_bytes());
+	secp256k1::Message::from_digest(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+const WATCH_DELEGATION_TAG: &[u8] = b"aspd/watch_delegation";
+
+/// The message the owner of a pubkey signs for a [WatchDelegation], to
+/// allow the watch key to list the vtxos of the pubkey.
+pub fn watch_delegation_message(pubkey: PublicKey, watch_pubkey: PublicKey) -> secp256k1::Message {
+	let mut engine = sha256::Hash::engine();
+	engine.input(WATCH_DELEGATION_TAG);
+	engine.input(&pubkey.serialize());
+	engine.input(&watch_pubkey.serialize());
+	secp256k1::Message::from_digest(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+const CANCEL_PAYMENT_TAG: &[u8] = b"aspd/cancel_payment";
+
+/// The message a client signs with the key of an input vtxo for a
+/// [CancelPaymentRequest], to prove that it owns the vtxo.
+///
+/// It commits to the cosign pubkey of the payment, so that the cancellation
+/// doesn't apply to a later payment with the same vtxo in the same round.
+pub fn cancel_payment_message(
+	vtxo_id: ark::VtxoId,
+	round_epoch: u64,
+	cosign_pubkey: PublicKey,
+) -> secp256k1::Message {
+	let mut engine = sha256::Hash::engine();
+	engine.input(CANCEL_PAYMENT_TAG);
+	engine.input(&vtxo_id.bytes());
+	engine.input(&round_epoch.to_be_bytes());
+	engine.input(&cosign_pubkey.serialize());
+	secp256k1::Message::from_digest(sha256::Hash::from_engine(engine).to_byte_array())
+}
@@ -0,0 +1,43 @@
+//! Compares the time to cosign a burst of onboards with a warm nonce pool
+//! to deterministic cosigning.
+
+use std::time::Instant;
+
+use bitcoin::secp256k1::{rand, Keypair};
+
+use ark::onboard;
+use ark::test_util::onboard_user_part;
+use aspd::NoncePool;
+
+const BURST: u32 = 200;
+const NB_RUNS: u32 = 10;
+
+fn main() {
+	let key = Keypair::new(&ark::util::SECP, &mut rand::thread_rng());
+	let user = Keypair::new(&ark::util::SECP, &mut rand::thread_rng());
+	let parts = (0..BURST).map(|i| {
+		onboard_user_part(user.public_key(), key.public_key(), i)
+	}).collect::<Vec<_>>();
+
+	for _ in 0..NB_RUNS {
+		let start = Instant::now();
+		for part in &parts {
+			onboard::new_asp(part, &key);
+		}
+		let deterministic = start.elapsed();
+
+		let pool = NoncePool::new(BURST as usize);
+		pool.refill(&key);
+		let start = Instant::now();
+		for part in &parts {
+			let (sec, public) = pool.take().unwrap();
+			onboard::new_asp_with_nonce(part, &key, sec, public);
+		}
+		let pooled = start.elapsed();
+
+		println!("cosigning {} onboards: deterministic {:?} ({:?} per request), \
+			warm pool {:?} ({:?} per request)",
+			BURST, deterministic, deterministic / BURST, pooled, pooled / BURST,
+		);
+	}
+}
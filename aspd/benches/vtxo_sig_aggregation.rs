@@ -0,0 +1,53 @@
+//! Measures the time to aggregate the vtxo tree signatures of a round
+//! for different batch sizes.
+
+use std::time::Instant;
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{rand, Keypair};
+use bitcoin::sighash::TapSighash;
+
+use ark::musig;
+
+const NB_COSIGNERS: usize = 32;
+const NB_NODES: usize = 127;
+
+fn main() {
+	let keys = (0..NB_COSIGNERS)
+		.map(|_| Keypair::new(&ark::util::SECP, &mut rand::thread_rng()))
+		.collect::<Vec<_>>();
+	let key_agg = musig::key_agg(keys.iter().map(|k| k.public_key()));
+	let sighashes = (0..NB_NODES)
+		.map(|_| TapSighash::from_byte_array(rand::random()))
+		.collect::<Vec<_>>();
+
+	let mut agg_nonces = Vec::with_capacity(NB_NODES);
+	let mut partial_sigs = Vec::with_capacity(NB_NODES);
+	for sighash in &sighashes {
+		let nonces = keys.iter().map(|k| musig::nonce_pair(k)).collect::<Vec<_>>();
+		let agg_nonce = musig::nonce_agg(nonces.iter().map(|n| n.1));
+		let sigs = keys.iter().zip(nonces).map(|(key, (sec, _))| {
+			musig::partial_sign(
+				keys.iter().map(|k| k.public_key()),
+				agg_nonce,
+				key,
+				sec,
+				sighash.to_byte_array(),
+				None,
+				None,
+			).0
+		}).collect::<Vec<_>>();
+		agg_nonces.push(agg_nonce);
+		partial_sigs.push(sigs);
+	}
+
+	for batch_size in [1, 10, 32, NB_NODES] {
+		let start = Instant::now();
+		aspd::aggregate_vtxo_sigs(
+			&key_agg, &agg_nonces, &sighashes, &partial_sigs, batch_size, |_| {},
+		);
+		println!("aggregating {} nodes of {} cosigners in batches of {}: {:?}",
+			NB_NODES, NB_COSIGNERS, batch_size, start.elapsed(),
+		);
+	}
+}
@@ -0,0 +1,276 @@
+use anyhow::Context;
+use bdk_bitcoind_rpc::bitcoincore_rpc::{self, RpcApi};
+use bdk_esplora::{esplora_client, EsploraAsyncExt};
+use bitcoin::{FeeRate, Transaction, Txid};
+
+use crate::database::Db;
+
+/// bitcoind RPC error code for txs rejected by mempool policy.
+pub const RPC_VERIFY_REJECTED: i32 = -26;
+/// bitcoind RPC error code for txs that are already confirmed.
+pub const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+
+/// Number of blocks after which we store the wallet changes during a sync.
+const SYNC_COMMIT_INTERVAL: u32 = 10_000;
+
+/// The stop gap and number of parallel requests for esplora syncs.
+const ESPLORA_STOP_GAP: usize = 50;
+const ESPLORA_PARALLEL_REQS: usize = 4;
+
+/// The configuration of the chain source we sync our onchain wallet from.
+pub enum ChainSourceConfig {
+	Bitcoind {
+		url: String,
+		auth: bitcoincore_rpc::Auth,
+	},
+	Esplora {
+		url: String,
+	},
+}
+
+impl ChainSourceConfig {
+	/// Connect to the configured chain source.
+	pub fn connect(self) -> anyhow::Result<Box<dyn ChainSource>> {
+		Ok(match self {
+			ChainSourceConfig::Bitcoind { url, auth } => Box::new(BitcoindChainSource(
+				bitcoincore_rpc::Client::new(&url, auth)
+					.context("failed to create bitcoind rpc client")?
+			)),
+			ChainSourceConfig::Esplora { url } => Box::new(EsploraChainSource(
+				esplora_client::Builder::new(&url).build_async()
+					.with_context(|| format!("failed to create esplora client for url {}", url))?
+			)),
+		})
+	}
+}
+
+/// Where a tx is, as far as the chain source knows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+	/// The tx is confirmed in the block at the given height.
+	Confirmed(u32),
+	/// The tx is in the mempool.
+	Mempool,
+	/// The chain source doesn't know about the tx.
+	Unknown,
+}
+
+/// The backend we sync our onchain wallet from and broadcast our txs to.
+#[tonic::async_trait]
+pub trait ChainSource: Send + Sync {
+	/// Sync the wallet with the chain and the mempool.
+	///
+	/// The changes to the wallet are stored in the db.
+	async fn sync_wallet(&self, wallet: &mut bdk_wallet::Wallet, db: &Db) -> anyhow::Result<()>;
+
+	/// Broadcast the tx. Txs that are already confirmed are not an error.
+	async fn broadcast_tx(&self, tx: &Transaction) -> anyhow::Result<()>;
+
+	/// The fee rate needed to confirm within the given number of blocks,
+	/// [None] if the chain source has no estimate.
+	async fn estimate_fee(&self, conf_target: u16) -> anyhow::Result<Option<FeeRate>>;
+
+	/// Look up where the given tx is.
+	async fn get_tx_status(&self, txid: Txid) -> anyhow::Result<TxStatus>;
+}
+
+pub struct BitcoindChainSource(bitcoincore_rpc::Client);
+
+#[tonic::async_trait]
+impl ChainSource for BitcoindChainSource {
+	async fn sync_wallet(&self, wallet: &mut bdk_wallet::Wallet, db: &Db) -> anyhow::Result<()> {
+		let prev_tip = wallet.latest_checkpoint();
+		debug!("Starting onchain sync at block height {}", prev_tip.height());
+		let mut emitter = bdk_bitcoind_rpc::Emitter::new(
+			&self.0, prev_tip.clone(), prev_tip.height(),
+		);
+		while let Some(em) = emitter.next_block()? {
+			wallet.apply_block_connected_to(&em.block, em.block_height(), em.connected_to())?;
+
+			if em.block_height() % SYNC_COMMIT_INTERVAL == 0 {
+				debug!("Synced until block {}, committing...", em.block_height());
+				if let Some(change) = wallet.take_staged() {
+					db.store_changeset(&change).await?;
+				}
+			}
+		}
+
+		// mempool
+		let mempool = emitter.mempool()?;
+		wallet.apply_unconfirmed_txs(mempool.iter().map(|(tx, time)| (tx.clone(), *time)));
+
+		if let Some(change) = wallet.take_staged() {
+			db.store_changeset(&change).await?;
+		}
+		Ok(())
+	}
+
+	async fn broadcast_tx(&self, tx: &Transaction) -> anyhow::Result<()> {
+		match self.0.send_raw_transaction(tx) {
+			Ok(_) => Ok(()),
+			Err(bitcoincore_rpc::Error::JsonRpc(
+				bitcoincore_rpc::jsonrpc::Error::Rpc(e))
+			) if e.code == RPC_VERIFY_ALREADY_IN_CHAIN => Ok(()),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	async fn estimate_fee(&self, conf_target: u16) -> anyhow::Result<Option<FeeRate>> {
+		let res = self.0.estimate_smart_fee(conf_target, None)?;
+		if let Some(ref errors) = res.errors {
+			debug!("bitcoind has no fee estimate for target {}: {:?}", conf_target, errors);
+		}
+		Ok(res.fee_rate.map(|per_kvb| FeeRate::from_sat_per_kwu(per_kvb.to_sat() / 4)))
+	}
+
+	async fn get_tx_status(&self, txid: Txid) -> anyhow::Result<TxStatus> {
+		if self.0.get_mempool_entry(&txid).is_ok() {
+			return Ok(TxStatus::Mempool);
+		}
+		// Without txindex, bitcoind only knows confirmed txs of its own wallet.
+		let info = match self.0.get_raw_transaction_info(&txid, None) {
+			Ok(info) => info,
+			Err(_) => return Ok(TxStatus::Unknown),
+		};
+		if let Some(hash) = info.blockhash {
+			let block = self.0.get_block_header_info(&hash)?;
+			if block.confirmations > 0 {
+				return Ok(TxStatus::Confirmed(block.height as u32));
+			}
+		}
+		Ok(TxStatus::Unknown)
+	}
+}
+
+pub struct EsploraChainSource(esplora_client::AsyncClient);
+
+#[tonic::async_trait]
+impl ChainSource for EsploraChainSource {
+	async fn sync_wallet(&self, wallet: &mut bdk_wallet::Wallet, db: &Db) -> anyhow::Result<()> {
+		debug!("Starting onchain sync with esplora");
+		let request = wallet.start_full_scan();
+		let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+		let update = self.0.full_scan(request, ESPLORA_STOP_GAP, ESPLORA_PARALLEL_REQS).await?;
+		wallet.apply_update_at(update, Some(now))?;
+
+		if let Some(change) = wallet.take_staged() {
+			db.store_changeset(&change).await?;
+		}
+		Ok(())
+	}
+
+	async fn broadcast_tx(&self, tx: &Transaction) -> anyhow::Result<()> {
+		self.0.broadcast(tx).await?;
+		Ok(())
+	}
+
+	async fn estimate_fee(&self, conf_target: u16) -> anyhow::Result<Option<FeeRate>> {
+		// Esplora gives estimates for a range of targets, take the one of
+		// the first target that is at least as far out as the requested one.
+		let estimates = self.0.get_fee_estimates().await?;
+		let estimate = estimates.into_iter()
+			.filter(|(target, _)| *target >= conf_target)
+			.min_by_key(|(target, _)| *target);
+		Ok(estimate.map(|(_, sat_per_vb)| {
+			FeeRate::from_sat_per_kwu((sat_per_vb * 250.0).ceil() as u64)
+		}))
+	}
+
+	async fn get_tx_status(&self, txid: Txid) -> anyhow::Result<TxStatus> {
+		if self.0.get_tx(&txid).await?.is_none() {
+			return Ok(TxStatus::Unknown);
+		}
+		let status = self.0.get_tx_status(&txid).await?;
+		Ok(match status.block_height {
+			Some(height) if status.confirmed => TxStatus::Confirmed(height),
+			_ => TxStatus::Mempool,
+		})
+	}
+}
+
+/// Broadcast the given txs again, unless the chain source already has them.
+///
+/// Returns the number of txs we broadcast.
+pub async fn rebroadcast_txs(
+	chain: &dyn ChainSource,
+	txs: impl IntoIterator<Item = Transaction>,
+) -> usize {
+	let mut ret = 0;
+	for tx in txs {
+		let txid = tx.compute_txid();
+		match chain.get_tx_status(txid).await {
+			Ok(TxStatus::Unknown) => {},
+			Ok(_) => continue,
+			Err(e) => warn!("Error looking up pending tx {}: {:#}", txid, e),
+		}
+		match chain.broadcast_tx(&tx).await {
+			Ok(()) => ret += 1,
+			Err(e) => warn!("Error broadcasting pending tx {}: {:#}", txid, e),
+		}
+	}
+	ret
+}
+
+#[cfg(test)]
+pub mod test {
+	use super::*;
+
+	use std::collections::HashMap;
+	use std::sync::Mutex;
+
+	use bitcoin::absolute::LockTime;
+	use bitcoin::transaction::Version;
+	use bitcoin::{Amount, ScriptBuf, TxOut};
+
+	/// A chain source that serves canned answers and records broadcasts.
+	#[derive(Default)]
+	pub struct MockChainSource {
+		pub fee_estimates: HashMap<u16, FeeRate>,
+		pub tx_status: HashMap<Txid, TxStatus>,
+		pub broadcast: Mutex<Vec<Transaction>>,
+	}
+
+	#[tonic::async_trait]
+	impl ChainSource for MockChainSource {
+		async fn sync_wallet(&self, _: &mut bdk_wallet::Wallet, _: &Db) -> anyhow::Result<()> {
+			Ok(())
+		}
+
+		async fn broadcast_tx(&self, tx: &Transaction) -> anyhow::Result<()> {
+			self.broadcast.lock().unwrap().push(tx.clone());
+			Ok(())
+		}
+
+		async fn estimate_fee(&self, conf_target: u16) -> anyhow::Result<Option<FeeRate>> {
+			Ok(self.fee_estimates.get(&conf_target).copied())
+		}
+
+		async fn get_tx_status(&self, txid: Txid) -> anyhow::Result<TxStatus> {
+			Ok(self.tx_status.get(&txid).copied().unwrap_or(TxStatus::Unknown))
+		}
+	}
+
+	fn tx(tag: u8) -> Transaction {
+		Transaction {
+			version: Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: vec![],
+			output: vec![TxOut {
+				script_pubkey: ScriptBuf::new_op_return(&[tag]),
+				value: Amount::ZERO,
+			}],
+		}
+	}
+
+	#[tokio::test]
+	async fn rebroadcast_only_unknown_txs() {
+		let (confirmed, mempool, lost) = (tx(1), tx(2), tx(3));
+		let mut chain = MockChainSource::default();
+		chain.tx_status.insert(confirmed.compute_txid(), TxStatus::Confirmed(100));
+		chain.tx_status.insert(mempool.compute_txid(), TxStatus::Mempool);
+
+		let nb = rebroadcast_txs(&chain, [confirmed, mempool, lost.clone()]).await;
+		assert_eq!(nb, 1);
+		assert_eq!(*chain.broadcast.lock().unwrap(), vec![lost]);
+	}
+}
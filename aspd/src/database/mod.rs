@@ -1,4 +1,25 @@
 
+//! The aspd database.
+//!
+//! # Concurrency
+//!
+//! A single [Db] is shared between the RPC server, the round scheduler and
+//! the sweeping logic, so all methods take `&self` and are safe to call
+//! concurrently from multiple threads.
+//!
+//! - Single-key reads and writes rely on RocksDB's own thread-safety.
+//! - Writes that touch multiple keys, like [Db::store_round], are done in
+//!   a single transaction, so readers either see all of it or nothing.
+//! - Read-modify-write operations, like [Db::atomic_check_mark_oors_cosigned]
+//!   and [Db::pull_oors], use optimistic transactions that are retried
+//!   when they conflict with a concurrent writer.
+//! - Methods that read multiple keys, like iterating an index and then
+//!   fetching the rounds it points to, don't read from a common snapshot.
+//!   Callers should be prepared for entries that are removed in between.
+//!
+//! We don't drop or create column families after opening the db, because
+//! concurrent users could then fail to find them.
+
 mod wallet;
 
 use std::io;
@@ -6,7 +27,7 @@ use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, Context};
-use bitcoin::{Amount, Transaction, Txid};
+use bitcoin::{Amount, BlockHash, OutPoint, Transaction, Txid};
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{schnorr, PublicKey};
 use rocksdb::{
@@ -27,24 +48,53 @@ use self::wallet::{CF_BDK_CHANGESETS, ChangeSetDbState};
 const CF_FORFEIT_VTXO: &str = "forfeited_vtxos";
 /// mapping Txid -> serialized StoredRound
 const CF_ROUND: &str = "rounds";
+/// mapping VtxoId -> round Txid
+const CF_VTXO_ROUND: &str = "vtxo_rounds";
 /// set [expiry][txid]
 const CF_ROUND_EXPIRY: &str = "rounds_by_expiry";
 /// set [outpoint]
 const CF_OOR_COSIGNED: &str = "oor_cosign";
 /// set [pubkey][vtxo]
 const CF_OOR_MAILBOX: &str = "oor_mailbox";
+/// set [pubkey][round txid]
+///
+/// Only rounds stored after this index was introduced are in it.
+const CF_PUBKEY_ROUND: &str = "pubkey_rounds";
+/// set [VtxoId]
+///
+/// Forfeited vtxos that the chain monitor saw exited onchain.
+const CF_CLAIMABLE_FORFEIT: &str = "claimable_forfeits";
 
 // ROOT ENTRY KEYS
 
 const MASTER_SEED: &str = "master_seed";
 const MASTER_MNEMONIC: &str = "master_mnemonic";
+/// The last block scanned for round tx confirmations, see [MonitorTip].
+const MONITOR_TIP: &str = "monitor_tip";
+/// The sequence number of the last round we started, as big-endian u64.
+const ROUND_SEQ: &str = "round_seq";
 
 
 /// A vtxo that has been forfeited and is now ours.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ForfeitVtxo {
 	pub vtxo: Vtxo,
+	/// One signature for each connector of the round the vtxo was forfeited in.
 	pub forfeit_sigs: Vec<schnorr::Signature>,
+	/// The txid of the round the vtxo was forfeited in.
+	///
+	/// This is [None] for vtxos stored before we tracked it.
+	#[serde(default)]
+	pub round_txid: Option<Txid>,
+	/// The value of the connectors of the round the vtxo was forfeited in.
+	///
+	/// Vtxos stored before this was configurable used dust connectors.
+	#[serde(default = "default_forfeit_connector_value", with = "bitcoin::amount::serde::as_sat")]
+	pub connector_value: Amount,
+}
+
+fn default_forfeit_connector_value() -> Amount {
+	ark::fee::DUST
 }
 
 impl ForfeitVtxo {
@@ -102,6 +152,34 @@ impl RoundExpiryKey {
 pub struct StoredRound {
 	pub tx: Transaction,
 	pub signed_tree: SignedVtxoTree,
+	/// The output of the round tx used for fee bumping.
+	///
+	/// Depending on the fee scheme, this is either a fee anchor or our own
+	/// change output. Rounds created before we added fee anchors, or
+	/// without change to bump with, don't have one.
+	#[serde(default)]
+	pub anchor: Option<OutPoint>,
+	/// For each vtxo in the tree, the height at which the chain of
+	/// refreshes leading to it started.
+	///
+	/// Empty for rounds created before we tracked this.
+	#[serde(default)]
+	pub vtxo_origin_heights: Vec<u32>,
+	/// The height of the block the round tx confirmed in, if any.
+	///
+	/// This is tracked by the chain monitor and is reset on reorgs.
+	#[serde(default)]
+	pub confirmed_height: Option<u32>,
+	/// The ASP keys in the cosign group of the vtxo tree.
+	///
+	/// Empty for rounds created before we tracked this.
+	#[serde(default)]
+	pub asp_cosign_pubkeys: Vec<PublicKey>,
+	/// The sequence number of the round, see [Db::next_round_seq].
+	///
+	/// Not set for rounds created before we numbered them.
+	#[serde(default)]
+	pub seq: Option<u64>,
 }
 
 impl StoredRound {
@@ -120,6 +198,37 @@ impl StoredRound {
 	}
 }
 
+/// The last block the chain monitor scanned.
+///
+/// This is tracked separately from the checkpoint of the onchain wallet,
+/// which only cares about our own wallet txs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorTip {
+	pub height: u32,
+	pub hash: BlockHash,
+}
+
+impl MonitorTip {
+	fn encode(&self) -> [u8; 36] {
+		let mut ret = [0u8; 36];
+		ret[0..4].copy_from_slice(&self.height.to_le_bytes());
+		ret[4..].copy_from_slice(&self.hash[..]);
+		ret
+	}
+
+	fn decode(b: &[u8]) -> Self {
+		assert_eq!(b.len(), 36, "corrupt monitor tip");
+		Self {
+			height: {
+				let mut buf = [0u8; 4];
+				buf[..].copy_from_slice(&b[0..4]);
+				u32::from_le_bytes(buf)
+			},
+			hash: BlockHash::from_slice(&b[4..]).unwrap(),
+		}
+	}
+}
+
 /// Type alias for the underlying RocksDB type.
 type RocksDb = rocksdb::OptimisticTransactionDB<rocksdb::MultiThreaded>;
 
@@ -137,9 +246,12 @@ impl Db {
 		let cfs = [
 			CF_FORFEIT_VTXO,
 			CF_ROUND,
+			CF_VTXO_ROUND,
 			CF_ROUND_EXPIRY,
 			CF_OOR_COSIGNED,
 			CF_OOR_MAILBOX,
+			CF_PUBKEY_ROUND,
+			CF_CLAIMABLE_FORFEIT,
 			CF_BDK_CHANGESETS,
 		];
 		let db = rocksdb::OptimisticTransactionDB::open_cf(&opts, path, cfs)
@@ -156,6 +268,10 @@ impl Db {
 		self.db.cf_handle(CF_ROUND).expect("db missing round cf")
 	}
 
+	fn cf_vtxo_round<'a>(&'a self) -> Arc<BoundColumnFamily<'a>> {
+		self.db.cf_handle(CF_VTXO_ROUND).expect("db missing vtxo round cf")
+	}
+
 	fn cf_round_expiry<'a>(&'a self) -> Arc<BoundColumnFamily<'a>> {
 		self.db.cf_handle(CF_ROUND_EXPIRY).expect("db missing round expiry cf")
 	}
@@ -168,6 +284,14 @@ impl Db {
 		self.db.cf_handle(CF_OOR_MAILBOX).expect("db missing oor mailbox cf")
 	}
 
+	fn cf_pubkey_round<'a>(&'a self) -> Arc<BoundColumnFamily<'a>> {
+		self.db.cf_handle(CF_PUBKEY_ROUND).expect("db missing pubkey round cf")
+	}
+
+	fn cf_claimable_forfeit<'a>(&'a self) -> Arc<BoundColumnFamily<'a>> {
+		self.db.cf_handle(CF_CLAIMABLE_FORFEIT).expect("db missing claimable forfeit cf")
+	}
+
 	pub fn store_master_mnemonic_and_seed(&self, mnemonic: &bip39::Mnemonic) -> anyhow::Result<()> {
 		let mut b = WriteBatchWithTransaction::<true>::default();
 		b.put(MASTER_MNEMONIC, mnemonic.to_string().as_bytes());
@@ -186,14 +310,62 @@ impl Db {
 		Ok(self.db.get(MASTER_MNEMONIC)?.map(|b| String::from_utf8(b)).transpose()?)
 	}
 
-	pub fn store_round(&self, round_tx: Transaction, vtxos: SignedVtxoTree) -> anyhow::Result<()> {
+	/// Take the sequence number for a new round.
+	///
+	/// Sequence numbers start at 1 and increase by one for every round we
+	/// start, also across restarts. Rounds that don't complete leave gaps.
+	pub fn next_round_seq(&self) -> anyhow::Result<u64> {
+		let mut opts = WriteOptions::default();
+		opts.set_sync(true);
+		let oopts = OptimisticTransactionOptions::new();
+
+		loop {
+			let tx = self.db.transaction_opt(&opts, &oopts);
+			let last = match tx.get_for_update(ROUND_SEQ, true)? {
+				Some(b) => u64::from_be_bytes(b[..].try_into().context("corrupt round seq")?),
+				None => 0,
+			};
+			let seq = last + 1;
+			tx.put(ROUND_SEQ, seq.to_be_bytes())?;
+
+			match tx.commit() {
+				Ok(()) => return Ok(seq),
+				Err(e) if e.kind() == rocksdb::ErrorKind::TryAgain => continue,
+				Err(e) if e.kind() == rocksdb::ErrorKind::Busy => continue,
+				Err(e) => bail!("failed to commit db tx: {}", e),
+			}
+		}
+	}
+
+	pub fn store_round(
+		&self,
+		seq: u64,
+		round_tx: Transaction,
+		vtxos: SignedVtxoTree,
+		anchor: Option<OutPoint>,
+		vtxo_origin_heights: Vec<u32>,
+		asp_cosign_pubkeys: Vec<PublicKey>,
+	) -> anyhow::Result<()> {
 		let round = StoredRound {
 			tx: round_tx,
 			signed_tree: vtxos,
+			anchor,
+			vtxo_origin_heights,
+			confirmed_height: None,
+			asp_cosign_pubkeys,
+			seq: Some(seq),
 		};
 		let id = round.id();
 		let encoded_round = round.encode();
 		let expiry_key = RoundExpiryKey::new(round.signed_tree.spec.expiry_height, id);
+		let vtxo_ids = {
+			let spec = &round.signed_tree.spec;
+			let tree = spec.build_unsigned_tree(round.signed_tree.utxo).into_vec();
+			tree.into_iter().take(spec.vtxos.len())
+				.map(|leaf| VtxoId::from(OutPoint::new(leaf.compute_txid(), 0)))
+				.collect::<Vec<_>>()
+		};
+		let pubkey_keys = pubkey_round_keys(&round.signed_tree, id);
 
 		let mut opts = WriteOptions::default();
 		opts.set_sync(true);
@@ -205,6 +377,12 @@ impl Db {
 			let tx = self.db.transaction_opt(&opts, &oopts);
 			tx.put_cf(&self.cf_round(), id, &encoded_round)?;
 			tx.put_cf(&self.cf_round_expiry(), expiry_key.encode(), [])?;
+			for vtxo_id in &vtxo_ids {
+				tx.put_cf(&self.cf_vtxo_round(), vtxo_id, id)?;
+			}
+			for key in &pubkey_keys {
+				tx.put_cf(&self.cf_pubkey_round(), key, [])?;
+			}
 
 			match tx.commit() {
 				Ok(()) => break,
@@ -217,18 +395,32 @@ impl Db {
 		let mut opts = FlushOptions::default();
 		opts.set_wait(true); //TODO(stevenroose) is this needed?
 		self.db.flush_cfs_opt(
-			&[&self.cf_round(), &self.cf_forfeit_vtxo(), &self.cf_round_expiry()], &opts,
+			&[
+				&self.cf_round(), &self.cf_forfeit_vtxo(), &self.cf_round_expiry(),
+				&self.cf_vtxo_round(), &self.cf_pubkey_round(),
+			],
+			&opts,
 		).context("error flushing db")?;
 
 		Ok(())
 	}
 
+	/// Get the id of the round that created the vtxo.
+	///
+	/// NB This mapping is kept after the round itself is removed.
+	pub fn get_vtxo_round(&self, id: VtxoId) -> anyhow::Result<Option<Txid>> {
+		Ok(self.db.get_pinned_cf(&self.cf_vtxo_round(), id)?.map(|b| {
+			Txid::from_slice(&b).expect("corrupt db: invalid txid")
+		}))
+	}
+
 	pub fn remove_round(&self, id: Txid) -> anyhow::Result<()> {
 		let round = match self.get_round(id)? {
 			Some(r) => r,
 			None => return Ok(()),
 		};
 		let expiry_key = RoundExpiryKey::new(round.signed_tree.spec.expiry_height, id);
+		let pubkey_keys = pubkey_round_keys(&round.signed_tree, id);
 
 		let opts = WriteOptions::default();
 		let oopts = OptimisticTransactionOptions::new();
@@ -238,6 +430,9 @@ impl Db {
 			let tx = self.db.transaction_opt(&opts, &oopts);
 			tx.delete_cf(&self.cf_round(), id)?;
 			tx.delete_cf(&self.cf_round_expiry(), expiry_key.encode())?;
+			for key in &pubkey_keys {
+				tx.delete_cf(&self.cf_pubkey_round(), key)?;
+			}
 
 			match tx.commit() {
 				Ok(()) => break,
@@ -255,6 +450,40 @@ impl Db {
 		}))
 	}
 
+	/// Set or clear the height at which the round tx confirmed.
+	pub fn set_round_confirmed_height(&self, id: Txid, height: Option<u32>) -> anyhow::Result<()> {
+		let opts = WriteOptions::default();
+		let oopts = OptimisticTransactionOptions::new();
+
+		loop {
+			let tx = self.db.transaction_opt(&opts, &oopts);
+			let mut round = match tx.get_for_update_cf(&self.cf_round(), id, true)? {
+				Some(b) => StoredRound::decode(&b).expect("corrupt db"),
+				// the round might have been removed in the meantime
+				None => return Ok(()),
+			};
+			round.confirmed_height = height;
+			tx.put_cf(&self.cf_round(), id, round.encode())?;
+
+			match tx.commit() {
+				Ok(()) => break,
+				Err(e) if e.kind() == rocksdb::ErrorKind::TryAgain => continue,
+				Err(e) if e.kind() == rocksdb::ErrorKind::Busy => continue,
+				Err(e) => bail!("failed to commit db tx: {}", e),
+			}
+		}
+		Ok(())
+	}
+
+	pub fn get_monitor_tip(&self) -> anyhow::Result<Option<MonitorTip>> {
+		Ok(self.db.get_pinned(MONITOR_TIP)?.map(|b| MonitorTip::decode(&b)))
+	}
+
+	pub fn store_monitor_tip(&self, tip: MonitorTip) -> anyhow::Result<()> {
+		self.db.put(MONITOR_TIP, tip.encode())?;
+		Ok(())
+	}
+
 	/// Get all round IDs of rounds that expired before or on [height].
 	pub fn get_expired_rounds(&self, height: u32) -> anyhow::Result<Vec<Txid>> {
 		let mut ret = Vec::new();
@@ -296,11 +525,91 @@ impl Db {
 		Ok(ret)
 	}
 
+	/// Get the ids of all stored rounds that have a vtxo for the given pubkey.
+	pub fn get_pubkey_rounds(&self, pubkey: PublicKey) -> anyhow::Result<Vec<Txid>> {
+		let pk = pubkey.serialize();
+
+		let mut ret = Vec::new();
+		let mut iter = self.db.raw_iterator_cf(&self.cf_pubkey_round());
+		iter.seek(&pk);
+		while iter.valid() {
+			if let Some(key) = iter.key() {
+				if key[0..33] != pk {
+					break;
+				}
+				ret.push(Txid::from_slice(&key[33..]).expect("corrupt db: invalid txid"));
+				iter.next();
+			} else {
+				break;
+			}
+		}
+		iter.status().context("pubkey round iterator error")?;
+
+		Ok(ret)
+	}
+
 	pub fn store_forfeit_vtxo(&self, vtxo: ForfeitVtxo) -> anyhow::Result<()> {
 		self.db.put_cf(&self.cf_forfeit_vtxo(), vtxo.id(), vtxo.encode())?;
 		Ok(())
 	}
 
+	/// Whether the vtxo was spent, either by forfeiting it in a round or
+	/// by cosigning an OOR tx spending it.
+	pub fn is_vtxo_spent(&self, id: VtxoId) -> anyhow::Result<bool> {
+		Ok(self.db.get_pinned_cf(&self.cf_forfeit_vtxo(), id)?.is_some()
+			|| self.db.get_pinned_cf(&self.cf_oor_cosigned(), id)?.is_some())
+	}
+
+	pub fn get_forfeit_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<ForfeitVtxo>> {
+		Ok(self.db.get_pinned_cf(&self.cf_forfeit_vtxo(), id)?.map(|b| {
+			ForfeitVtxo::decode(&b).expect("corrupt db: invalid forfeit vtxo")
+		}))
+	}
+
+	pub fn get_all_forfeit_vtxos(&self) -> anyhow::Result<Vec<ForfeitVtxo>> {
+		let mut ret = Vec::new();
+
+		let mut iter = self.db.raw_iterator_cf(&self.cf_forfeit_vtxo());
+		iter.seek_to_first();
+		while iter.valid() {
+			if let Some(value) = iter.value() {
+				ret.push(ForfeitVtxo::decode(value).expect("corrupt db: invalid forfeit vtxo"));
+				iter.next();
+			} else {
+				break;
+			}
+		}
+		iter.status().context("forfeit vtxo iterator error")?;
+
+		Ok(ret)
+	}
+
+	/// Mark the forfeited vtxo as exited, so that we try to claim it.
+	pub fn store_claimable_forfeit(&self, id: VtxoId) -> anyhow::Result<()> {
+		self.db.put_cf(&self.cf_claimable_forfeit(), id, [])?;
+		Ok(())
+	}
+
+	pub fn remove_claimable_forfeit(&self, id: VtxoId) -> anyhow::Result<()> {
+		self.db.delete_cf(&self.cf_claimable_forfeit(), id)?;
+		Ok(())
+	}
+
+	/// The forfeited vtxos that were exited and that we didn't claim yet.
+	pub fn get_claimable_forfeits(&self) -> anyhow::Result<Vec<VtxoId>> {
+		let mut ret = Vec::new();
+
+		let mut iter = self.db.raw_iterator_cf(&self.cf_claimable_forfeit());
+		iter.seek_to_first();
+		while let Some(key) = iter.key() {
+			ret.push(VtxoId::from_slice(key).expect("corrupt db: invalid vtxo id"));
+			iter.next();
+		}
+		iter.status().context("claimable forfeit iterator error")?;
+
+		Ok(ret)
+	}
+
 	/// Returns [None] if all the ids were not previously marked as signed
 	/// and are now correctly marked as such.
 	/// Returns [Some] for the first vtxo that was already signed.
@@ -319,11 +628,13 @@ impl Db {
 			let tx = self.db.transaction_opt(&opts, &oopts);
 
 			for id in ids.clone() {
-				if tx.get_cf(&self.cf_oor_cosigned(), id)?.is_some() {
+				// NB we need get_for_update so that concurrent cosign
+				// requests for the same vtxo conflict.
+				if tx.get_for_update_cf(&self.cf_oor_cosigned(), id, true)?.is_some() {
 					tx.rollback()?;
 					return Ok(Some(id));
 				}
-				tx.put_cf(&self.cf_round(), id, [])?;
+				tx.put_cf(&self.cf_oor_cosigned(), id, [])?;
 			}
 
 			match tx.commit() {
@@ -337,12 +648,16 @@ impl Db {
 	}
 
 	pub fn clear_oor_cosigned(&self) -> anyhow::Result<()> {
-		self.db.drop_cf(CF_OOR_COSIGNED)?;
-
-		let mut opts = rocksdb::Options::default();
-		opts.create_if_missing(true);
-		opts.create_missing_column_families(true);
-		self.db.create_cf(CF_OOR_COSIGNED, &opts)?;
+		let cf = self.cf_oor_cosigned();
+		let mut b = WriteBatchWithTransaction::<true>::default();
+		let mut iter = self.db.raw_iterator_cf(&cf);
+		iter.seek_to_first();
+		while let Some(key) = iter.key() {
+			b.delete_cf(&cf, key);
+			iter.next();
+		}
+		iter.status().context("oor cosigned iterator error")?;
+		self.db.write(b)?;
 		Ok(())
 	}
 
@@ -354,18 +669,20 @@ impl Db {
 		Ok(())
 	}
 
+	/// Take all OOR vtxos for the given pubkey out of the mailbox.
+	///
+	/// When called concurrently, each vtxo is only returned once.
 	pub fn pull_oors(&self, pubkey: PublicKey) -> anyhow::Result<Vec<Vtxo>> {
 		let pk = pubkey.serialize();
 		assert_eq!(33, pk.len());
 
-		let mut ret = Vec::new();
+		let mut keys = Vec::new();
 		let mut iter = self.db.raw_iterator_cf(&self.cf_oor_mailbox());
 		iter.seek(&pk);
 		while iter.valid() {
 			if let Some(item) = iter.key() {
 				if item[0..33] == pk {
-					ret.push(Vtxo::decode(&item[33..]).expect("corrupt db: invalid vtxo"));
-					self.db.delete_cf(&self.cf_oor_mailbox(), &item)?;
+					keys.push(item.to_vec());
 				} else {
 					break;
 				}
@@ -374,10 +691,199 @@ impl Db {
 				break;
 			}
 		}
-		iter.status().context("round expiry iterator error")?;
-		Ok(ret)
+		iter.status().context("oor mailbox iterator error")?;
+
+		let opts = WriteOptions::default();
+		let oopts = OptimisticTransactionOptions::new();
+		loop {
+			let tx = self.db.transaction_opt(&opts, &oopts);
+			let mut ret = Vec::with_capacity(keys.len());
+			for key in &keys {
+				// Skip the ones that someone else pulled meanwhile.
+				if tx.get_for_update_cf(&self.cf_oor_mailbox(), key, true)?.is_some() {
+					ret.push(Vtxo::decode(&key[33..]).expect("corrupt db: invalid vtxo"));
+					tx.delete_cf(&self.cf_oor_mailbox(), key)?;
+				}
+			}
+
+			match tx.commit() {
+				Ok(()) => return Ok(ret),
+				Err(e) if e.kind() == rocksdb::ErrorKind::TryAgain => continue,
+				Err(e) if e.kind() == rocksdb::ErrorKind::Busy => continue,
+				Err(e) => bail!("failed to commit db tx: {}", e),
+			}
+		}
 	}
 }
 
+/// The keys in [CF_PUBKEY_ROUND] for all distinct vtxo pubkeys in the tree.
+fn pubkey_round_keys(tree: &SignedVtxoTree, id: Txid) -> Vec<[u8; 65]> {
+	let mut keys = tree.spec.vtxos.iter().map(|vtxo| {
+		let mut key = [0u8; 65];
+		key[0..33].copy_from_slice(&vtxo.pubkey.serialize());
+		key[33..].copy_from_slice(id.as_byte_array());
+		key
+	}).collect::<Vec<_>>();
+	keys.sort_unstable();
+	keys.dedup();
+	keys
+}
+
 //TODO(stevenroose) write test to make sure the iterator in get_fresh_round_ids doesn't skip
 //any rounds on the same height.
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use std::{env, fs, thread};
+	use std::collections::HashSet;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	use bitcoin::absolute::LockTime;
+	use bitcoin::secp256k1::{rand, Keypair};
+	use ark::{ExitTimelockType, VtxoRequest, VtxoScriptType};
+	use ark::tree::signed::{OutputKeyPolicy, VtxoTreeSpec};
+
+	use crate::SECP;
+
+	fn temp_db(name: &str) -> (Db, std::path::PathBuf) {
+		let dir = env::temp_dir().join(format!("aspd-db-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		(Db::open(&dir).unwrap(), dir)
+	}
+
+	fn dummy_round(n: u32, key: &Keypair) -> (Transaction, SignedVtxoTree) {
+		let tx = Transaction {
+			version: bitcoin::transaction::Version::TWO,
+			lock_time: LockTime::from_consensus(n),
+			input: vec![],
+			output: vec![],
+		};
+		let dest = VtxoRequest { pubkey: key.public_key(), amount: Amount::from_sat(10_000) };
+		let spec = VtxoTreeSpec::new(
+			vec![dest; 2],
+			key.x_only_public_key().0,
+			key.public_key(),
+			1000 + n,
+			144,
+			false,
+			OutputKeyPolicy::MerkleRootTweak,
+			ExitTimelockType::Relative,
+			VtxoScriptType::Taproot,
+		);
+		let tree = SignedVtxoTree::new(spec, OutPoint::new(tx.compute_txid(), 0), vec![]);
+		(tx, tree)
+	}
+
+	#[test]
+	fn concurrent_reads_during_round_writes() {
+		let (db, dir) = temp_db("rounds");
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		const NB_ROUNDS: u32 = 200;
+
+		let done = AtomicBool::new(false);
+		thread::scope(|s| {
+			for _ in 0..4 {
+				s.spawn(|| {
+					while !done.load(Ordering::Relaxed) {
+						// Every round in the index must be fully stored.
+						for id in db.get_fresh_round_ids(0).unwrap() {
+							let round = db.get_round(id).unwrap().expect("indexed round missing");
+							let spec = &round.signed_tree.spec;
+							let tree = spec.build_unsigned_tree(round.signed_tree.utxo).into_vec();
+							for leaf in tree.iter().take(spec.vtxos.len()) {
+								let vtxo_id = VtxoId::from(OutPoint::new(leaf.compute_txid(), 0));
+								assert_eq!(db.get_vtxo_round(vtxo_id).unwrap(), Some(id));
+							}
+						}
+					}
+				});
+			}
+
+			for n in 0..NB_ROUNDS {
+				let (tx, tree) = dummy_round(n, &key);
+				let anchor = OutPoint::new(tx.compute_txid(), 2);
+				db.store_round(n as u64, tx, tree, Some(anchor), vec![n; 2], vec![]).unwrap();
+			}
+			done.store(true, Ordering::Relaxed);
+		});
+
+		assert_eq!(db.get_fresh_round_ids(0).unwrap().len(), NB_ROUNDS as usize);
+		drop(db);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn monitor_state_survives_restart() {
+		let (db, dir) = temp_db("monitor");
+		assert_eq!(db.get_monitor_tip().unwrap(), None);
+
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let (tx, tree) = dummy_round(1, &key);
+		let id = tx.compute_txid();
+		db.store_round(1, tx, tree, Some(OutPoint::new(id, 2)), vec![1; 2], vec![]).unwrap();
+		db.set_round_confirmed_height(id, Some(120)).unwrap();
+		let tip = MonitorTip { height: 125, hash: BlockHash::from_byte_array([3; 32]) };
+		db.store_monitor_tip(tip).unwrap();
+
+		drop(db);
+		let db = Db::open(&dir).unwrap();
+		assert_eq!(db.get_monitor_tip().unwrap(), Some(tip));
+		assert_eq!(db.get_round(id).unwrap().unwrap().confirmed_height, Some(120));
+
+		db.set_round_confirmed_height(id, None).unwrap();
+		assert_eq!(db.get_round(id).unwrap().unwrap().confirmed_height, None);
+		drop(db);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn round_seq_survives_restart() {
+		let (db, dir) = temp_db("round_seq");
+		assert_eq!(db.next_round_seq().unwrap(), 1);
+		assert_eq!(db.next_round_seq().unwrap(), 2);
+
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let (tx, tree) = dummy_round(1, &key);
+		let id = tx.compute_txid();
+		db.store_round(2, tx, tree, None, vec![1; 2], vec![key.public_key()]).unwrap();
+
+		drop(db);
+		let db = Db::open(&dir).unwrap();
+		let round = db.get_round(id).unwrap().unwrap();
+		assert_eq!(round.seq, Some(2));
+		assert_eq!(round.asp_cosign_pubkeys, vec![key.public_key()]);
+		// Concurrent rounds never get the same number.
+		let seqs = thread::scope(|s| {
+			let handles = (0..8).map(|_| s.spawn(|| db.next_round_seq().unwrap()))
+				.collect::<Vec<_>>();
+			handles.into_iter().map(|h| h.join().unwrap()).collect::<HashSet<_>>()
+		});
+		assert_eq!(seqs, (3..11).collect());
+		assert_eq!(db.next_round_seq().unwrap(), 11);
+		drop(db);
+		let _ = fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn concurrent_oor_cosign_marks() {
+		let (db, dir) = temp_db("oor");
+		let id = VtxoId::from(OutPoint::new(Txid::all_zeros(), 1));
+
+		// Only one of the concurrent attempts can mark the vtxo.
+		let nb_marked = thread::scope(|s| {
+			let handles = (0..8).map(|_| s.spawn(|| {
+				db.atomic_check_mark_oors_cosigned([id].into_iter()).unwrap().is_none()
+			})).collect::<Vec<_>>();
+			handles.into_iter().map(|h| h.join().unwrap()).filter(|ok| *ok).count()
+		});
+		assert_eq!(nb_marked, 1);
+		assert!(db.is_vtxo_spent(id).unwrap());
+
+		db.clear_oor_cosigned().unwrap();
+		assert!(!db.is_vtxo_spent(id).unwrap());
+		drop(db);
+		let _ = fs::remove_dir_all(&dir);
+	}
+}
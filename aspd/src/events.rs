@@ -0,0 +1,298 @@
+
+//! Machine-readable event log.
+//!
+//! Next to the human-readable logs, aspd can write a stream of structured
+//! events to a file or a unix socket. Every event is written as a single line
+//! of JSON, so the stream can be consumed as newline-delimited JSON.
+//!
+//! Every record carries the [EVENT_SCHEMA_VERSION] under the `version` key,
+//! a `timestamp` in milliseconds since the unix epoch and the event type
+//! under the `type` key. The remaining keys depend on the event type.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use bitcoin::{OutPoint, Txid};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+
+/// The version of the event schema.
+///
+/// This should be bumped on every backwards-incompatible change to [Event].
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// Prefix used in the string representation of [EventSinkConfig::UnixSocket].
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Where to write the event log.
+///
+/// In the config file, this takes the same string form as on the command
+/// line: either `unix:<path>` for a unix socket or a plain file path.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum EventSinkConfig {
+	/// Append events to the file at the given path.
+	File(PathBuf),
+	/// Write events to the unix socket at the given path.
+	UnixSocket(PathBuf),
+}
+
+impl FromStr for EventSinkConfig {
+	type Err = anyhow::Error;
+
+	/// Parse either `unix:<path>` for a unix socket or a plain file path.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if let Some(path) = s.strip_prefix(UNIX_SOCKET_PREFIX) {
+			ensure!(!path.is_empty(), "empty unix socket path");
+			Ok(EventSinkConfig::UnixSocket(path.into()))
+		} else {
+			ensure!(!s.is_empty(), "empty event log file path");
+			Ok(EventSinkConfig::File(s.into()))
+		}
+	}
+}
+
+impl TryFrom<String> for EventSinkConfig {
+	type Error = anyhow::Error;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+impl From<EventSinkConfig> for String {
+	fn from(c: EventSinkConfig) -> String {
+		c.to_string()
+	}
+}
+
+impl fmt::Display for EventSinkConfig {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			EventSinkConfig::File(p) => write!(f, "{}", p.display()),
+			EventSinkConfig::UnixSocket(p) => write!(f, "{}{}", UNIX_SOCKET_PREFIX, p.display()),
+		}
+	}
+}
+
+/// The events emitted by aspd.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+	RoundStarted {
+		round_id: u64,
+	},
+	/// A round attempt failed and the round will be retried.
+	RoundFailed {
+		round_id: u64,
+		reason: String,
+	},
+	RoundFinished {
+		round_id: u64,
+		round_txid: Txid,
+		nb_input_vtxos: usize,
+		nb_output_vtxos: usize,
+		nb_offboards: usize,
+	},
+	/// How long each phase of the final attempt of a finished round took.
+	RoundTimings {
+		round_id: u64,
+		submit_collection_ms: u64,
+		tree_construction_ms: u64,
+		nonce_aggregation_ms: u64,
+		vtxo_signing_ms: u64,
+		forfeit_signing_ms: u64,
+		broadcast_ms: u64,
+	},
+	/// The outputs of expired rounds were swept, either in a round tx or
+	/// in a separate sweep tx.
+	ExpiredRoundsSwept {
+		/// The tx that spends the swept outputs.
+		sweep_txid: Txid,
+		/// The expired rounds that were swept.
+		round_txids: Vec<Txid>,
+		nb_utxos: usize,
+		#[serde(with = "bitcoin::amount::serde::as_sat")]
+		total_value: bitcoin::Amount,
+	},
+	/// The chain monitor saw a round tx confirm.
+	RoundTxConfirmed {
+		round_txid: Txid,
+		height: u32,
+	},
+	OnboardCosigned {
+		utxo: OutPoint,
+	},
+	/// A user unilaterally exited a forfeited vtxo and we claimed it.
+	ForfeitClaimed {
+		vtxo: OutPoint,
+		forfeit_txid: Txid,
+	},
+	WalletSynced {
+		height: u32,
+		#[serde(with = "bitcoin::amount::serde::as_sat")]
+		balance: bitcoin::Amount,
+	},
+}
+
+#[derive(Serialize)]
+struct EventRecord<'a> {
+	version: u32,
+	timestamp: u64,
+	#[serde(flatten)]
+	event: &'a Event,
+}
+
+/// How long the writer task waits before reopening a sink that failed.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+async fn open_writer(
+	config: &EventSinkConfig,
+) -> anyhow::Result<Box<dyn AsyncWrite + Send + Unpin>> {
+	Ok(match config {
+		EventSinkConfig::File(path) => {
+			let file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await
+				.with_context(|| format!("failed to open event log file {}", path.display()))?;
+			Box::new(file)
+		},
+		EventSinkConfig::UnixSocket(path) => {
+			let socket = UnixStream::connect(path).await
+				.with_context(|| format!("failed to connect to event socket {}", path.display()))?;
+			Box::new(socket)
+		},
+	})
+}
+
+/// Writes [Event]s to the configured sink.
+///
+/// Events are handed over to a background task that does the actual
+/// writing, so emitting an event never blocks the caller. When a write
+/// fails, the task reopens the sink and retries the event.
+pub struct EventSink {
+	tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl EventSink {
+	/// Open the sink and start the writer task.
+	///
+	/// The sink has to be available on startup, later failures are retried.
+	pub async fn open(config: &EventSinkConfig) -> anyhow::Result<EventSink> {
+		let writer = open_writer(config).await?;
+		let (tx, rx) = mpsc::unbounded_channel();
+		tokio::spawn(run_writer(config.clone(), writer, rx));
+		Ok(EventSink { tx })
+	}
+
+	/// Write the event to the sink.
+	///
+	/// Failing to write an event is not fatal, errors are only logged.
+	pub fn emit(&self, event: &Event) {
+		let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+		let record = EventRecord { version: EVENT_SCHEMA_VERSION, timestamp, event };
+		let mut line = serde_json::to_vec(&record).expect("serialization can't error");
+		line.push(b'\n');
+
+		if self.tx.send(line).is_err() {
+			warn!("Event sink writer is gone, dropping event");
+		}
+	}
+}
+
+async fn run_writer(
+	config: EventSinkConfig,
+	writer: Box<dyn AsyncWrite + Send + Unpin>,
+	mut rx: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+	let mut writer = Some(writer);
+	while let Some(line) = rx.recv().await {
+		loop {
+			let w = match writer {
+				Some(ref mut w) => w,
+				None => match open_writer(&config).await {
+					Ok(w) => {
+						info!("Reopened event sink {}", config);
+						writer.insert(w)
+					},
+					Err(e) => {
+						warn!("Failed to reopen event sink: {:#}", e);
+						tokio::time::sleep(RECONNECT_INTERVAL).await;
+						continue;
+					},
+				},
+			};
+			match w.write_all(&line).await {
+				Ok(()) => match w.flush().await {
+					Ok(()) => break,
+					Err(e) => warn!("Failed to flush event sink {}: {}", config, e),
+				},
+				Err(e) => warn!("Failed to write event to event sink {}: {}", config, e),
+			}
+			writer = None;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn sink_config_from_str() {
+		assert_eq!(
+			"/tmp/events.log".parse::<EventSinkConfig>().unwrap(),
+			EventSinkConfig::File("/tmp/events.log".into()),
+		);
+		assert_eq!(
+			"unix:/tmp/events.sock".parse::<EventSinkConfig>().unwrap(),
+			EventSinkConfig::UnixSocket("/tmp/events.sock".into()),
+		);
+		assert!("unix:".parse::<EventSinkConfig>().is_err());
+
+		for s in ["/tmp/events.log", "unix:/tmp/events.sock"] {
+			assert_eq!(s.parse::<EventSinkConfig>().unwrap().to_string(), s);
+			let json = serde_json::to_value(s.parse::<EventSinkConfig>().unwrap()).unwrap();
+			assert_eq!(json, serde_json::json!(s));
+			assert_eq!(serde_json::from_value::<EventSinkConfig>(json).unwrap().to_string(), s);
+		}
+	}
+
+	#[tokio::test]
+	async fn sink_writes_in_background() {
+		let path = std::env::temp_dir()
+			.join(format!("aspd-events-{}.log", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let sink = EventSink::open(&EventSinkConfig::File(path.clone())).await.unwrap();
+		sink.emit(&Event::RoundStarted { round_id: 1 });
+		sink.emit(&Event::RoundStarted { round_id: 2 });
+
+		let lines = async {
+			loop {
+				let content = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+				if content.lines().count() == 2 {
+					break content;
+				}
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+		};
+		let content = tokio::time::timeout(Duration::from_secs(5), lines).await.unwrap();
+		assert!(content.lines().all(|l| l.contains("round_started")));
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn event_record_schema() {
+		let event = Event::RoundStarted { round_id: 42 };
+		let record = EventRecord { version: EVENT_SCHEMA_VERSION, timestamp: 1, event: &event };
+		let json = serde_json::to_value(&record).unwrap();
+		assert_eq!(json, serde_json::json!({
+			"version": EVENT_SCHEMA_VERSION,
+			"timestamp": 1,
+			"type": "round_started",
+			"round_id": 42,
+		}));
+	}
+}
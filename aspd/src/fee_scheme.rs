@@ -0,0 +1,262 @@
+
+//! The schemes round txs can use to pay for and bump their fees.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+use bitcoin::{psbt, OutPoint, ScriptBuf, Transaction, TxOut, Weight, Witness};
+use bitcoin::transaction::Version;
+
+/// The maximum virtual size of a TRUC tx, see BIP-431.
+pub const TRUC_MAX_VSIZE: usize = 10_000;
+/// The maximum virtual size of a TRUC tx that has an unconfirmed TRUC parent.
+pub const TRUC_CHILD_MAX_VSIZE: usize = 1_000;
+
+/// How round txs make room for fee bumping.
+///
+/// Round txs always pay [Config::round_tx_feerate](crate::Config::round_tx_feerate)
+/// themselves, the scheme decides which output we spend to bump them
+/// with CPFP when they get stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundFeeScheme {
+	/// A p2wsh OP_TRUE dust output that anyone can spend.
+	KeylessAnchor,
+	/// A pay-to-anchor (P2A) output that anyone can spend.
+	///
+	/// Unlike an ephemeral anchor, the output carries the P2A dust amount
+	/// and the round tx pays its own fee, so it relays on its own. Only
+	/// supported for version 3 round txs, so that the CPFP is relayed as a
+	/// TRUC package.
+	///
+	/// This used to be called "ephemeral_anchor", which is still accepted.
+	#[serde(alias = "ephemeral_anchor")]
+	P2aAnchor,
+	/// No dedicated output, we bump using the change output of our wallet.
+	///
+	/// Round txs that don't have change can't be bumped.
+	CpfpChange,
+}
+
+impl RoundFeeScheme {
+	/// The fee anchor output to add to the round tx, if any.
+	pub fn anchor_output(&self) -> Option<TxOut> {
+		match self {
+			RoundFeeScheme::KeylessAnchor => Some(ark::fee::dust_anchor()),
+			RoundFeeScheme::P2aAnchor => Some(ark::fee::p2a_anchor()),
+			RoundFeeScheme::CpfpChange => None,
+		}
+	}
+
+	/// Check that the scheme can be used for round txs of the given version.
+	pub fn check_compatible(&self, round_tx_version: i32) -> anyhow::Result<()> {
+		if *self == RoundFeeScheme::P2aAnchor {
+			ensure!(round_tx_version == 3,
+				"fee scheme {} requires round tx version 3, not {}", self, round_tx_version,
+			);
+		}
+		Ok(())
+	}
+
+	/// Find the output of the round tx to use for fee bumping.
+	///
+	/// The `is_mine` closure should tell whether a script belongs to our wallet.
+	pub fn round_bump_output(
+		&self,
+		round_tx: &Transaction,
+		is_mine: impl Fn(&ScriptBuf) -> bool,
+	) -> Option<OutPoint> {
+		let txid = round_tx.compute_txid();
+		let idx = match self.anchor_output() {
+			Some(anchor) => round_tx.output.iter().position(|o| *o == anchor),
+			None => round_tx.output.iter().position(|o| is_mine(&o.script_pubkey)),
+		}?;
+		Some(OutPoint::new(txid, idx as u32))
+	}
+}
+
+impl FromStr for RoundFeeScheme {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"keyless_anchor" => Ok(RoundFeeScheme::KeylessAnchor),
+			"p2a_anchor" | "ephemeral_anchor" => Ok(RoundFeeScheme::P2aAnchor),
+			"cpfp_change" => Ok(RoundFeeScheme::CpfpChange),
+			_ => bail!("unknown fee scheme: {}", s),
+		}
+	}
+}
+
+impl fmt::Display for RoundFeeScheme {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			RoundFeeScheme::KeylessAnchor => "keyless_anchor",
+			RoundFeeScheme::P2aAnchor => "p2a_anchor",
+			RoundFeeScheme::CpfpChange => "cpfp_change",
+		})
+	}
+}
+
+/// The output of a tx we spend to bump its fee.
+#[derive(Debug, Clone)]
+pub enum BumpOutput {
+	/// A keyless anchor, spent as a foreign utxo.
+	Anchor {
+		point: OutPoint,
+		input: psbt::Input,
+		/// The satisfaction weight of the anchor input.
+		weight: Weight,
+	},
+	/// An output of our own wallet.
+	Wallet(OutPoint),
+}
+
+impl BumpOutput {
+	/// Determine how to spend the output of the given tx.
+	///
+	/// We look at the output itself instead of the configured scheme,
+	/// because the tx might have been created with an earlier config.
+	pub fn new(tx: &Transaction, point: OutPoint) -> anyhow::Result<BumpOutput> {
+		let txout = tx.output.get(point.vout as usize)
+			.with_context(|| format!("tx {} has no output {}", tx.compute_txid(), point.vout))?;
+		let (witness, weight) = if *txout == ark::fee::dust_anchor() {
+			(ark::fee::dust_anchor_witness(), ark::fee::DUST_ANCHOR_SATISFACTION_WEIGHT)
+		} else if *txout == ark::fee::p2a_anchor() {
+			(Witness::new(), Weight::from_wu(1))
+		} else {
+			return Ok(BumpOutput::Wallet(point));
+		};
+		Ok(BumpOutput::Anchor {
+			point,
+			input: psbt::Input {
+				witness_utxo: Some(txout.clone()),
+				non_witness_utxo: Some(tx.clone()),
+				final_script_witness: Some(witness),
+				..Default::default()
+			},
+			weight,
+		})
+	}
+}
+
+/// Check the parts of the TRUC (v3) policy of BIP-431 that only depend on
+/// the tx itself. Txs of other versions always pass.
+///
+/// The remaining rules limit the unconfirmed ancestors of a TRUC tx, so
+/// TRUC txs should only spend confirmed wallet outputs.
+pub fn check_truc_policy(tx: &Transaction, has_unconfirmed_parent: bool) -> anyhow::Result<()> {
+	if tx.version != Version(3) {
+		return Ok(());
+	}
+	let max = if has_unconfirmed_parent { TRUC_CHILD_MAX_VSIZE } else { TRUC_MAX_VSIZE };
+	ensure!(tx.vsize() <= max, "TRUC tx {} is {} vbytes, more than the maximum of {}",
+		tx.compute_txid(), tx.vsize(), max,
+	);
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use bitcoin::absolute::LockTime;
+	use bitcoin::Amount;
+
+	const SCHEMES: [RoundFeeScheme; 3] = [
+		RoundFeeScheme::KeylessAnchor,
+		RoundFeeScheme::P2aAnchor,
+		RoundFeeScheme::CpfpChange,
+	];
+
+	/// Build a round tx with the output layout of the round coordinator:
+	/// vtxo tree and connector output, the fee anchor, then the change.
+	///
+	/// The `round_fee_schemes` integration test checks that actual round
+	/// txs have this layout.
+	fn round_tx(scheme: RoundFeeScheme, change_spk: &ScriptBuf) -> Transaction {
+		let mut output = vec![
+			TxOut { script_pubkey: ScriptBuf::new_op_return(&[1]), value: Amount::from_sat(10_000) },
+			TxOut { script_pubkey: ScriptBuf::new_op_return(&[2]), value: Amount::from_sat(1_000) },
+		];
+		output.extend(scheme.anchor_output());
+		output.push(TxOut { script_pubkey: change_spk.clone(), value: Amount::from_sat(50_000) });
+		Transaction {
+			version: Version(3),
+			lock_time: LockTime::ZERO,
+			input: vec![],
+			output,
+		}
+	}
+
+	#[test]
+	fn round_tx_fee_outputs() {
+		let change_spk = ScriptBuf::new_op_return(&[3]);
+		let is_mine = |spk: &ScriptBuf| *spk == change_spk;
+
+		for scheme in SCHEMES {
+			let tx = round_tx(scheme, &change_spk);
+			let point = scheme.round_bump_output(&tx, is_mine).unwrap();
+			assert_eq!(point.txid, tx.compute_txid());
+			let bump = BumpOutput::new(&tx, point).unwrap();
+			match scheme {
+				RoundFeeScheme::KeylessAnchor => {
+					assert_eq!(tx.output.len(), 4);
+					assert_eq!(point.vout, crate::round::ROUND_TX_ANCHOR_VOUT);
+					assert_eq!(tx.output[2], ark::fee::dust_anchor());
+					assert!(matches!(bump, BumpOutput::Anchor { .. }));
+				},
+				RoundFeeScheme::P2aAnchor => {
+					assert_eq!(tx.output.len(), 4);
+					assert_eq!(point.vout, crate::round::ROUND_TX_ANCHOR_VOUT);
+					assert_eq!(tx.output[2], ark::fee::p2a_anchor());
+					match bump {
+						BumpOutput::Anchor { input, .. } => {
+							assert!(input.final_script_witness.unwrap().is_empty());
+						},
+						BumpOutput::Wallet(_) => panic!("p2a anchor is not a wallet output"),
+					}
+				},
+				RoundFeeScheme::CpfpChange => {
+					assert_eq!(tx.output.len(), 3);
+					assert_eq!(point.vout, 2);
+					assert!(matches!(bump, BumpOutput::Wallet(p) if p == point));
+				},
+			}
+		}
+
+		// Without change, there's nothing to bump with.
+		let mut tx = round_tx(RoundFeeScheme::CpfpChange, &change_spk);
+		tx.output.pop();
+		assert_eq!(RoundFeeScheme::CpfpChange.round_bump_output(&tx, is_mine), None);
+	}
+
+	#[test]
+	fn scheme_compatibility() {
+		for scheme in SCHEMES {
+			scheme.check_compatible(3).unwrap();
+			assert_eq!(scheme.to_string().parse::<RoundFeeScheme>().unwrap(), scheme);
+		}
+		RoundFeeScheme::KeylessAnchor.check_compatible(2).unwrap();
+		RoundFeeScheme::CpfpChange.check_compatible(2).unwrap();
+		assert!(RoundFeeScheme::P2aAnchor.check_compatible(2).is_err());
+	}
+
+	#[test]
+	fn truc_policy() {
+		let spk = ScriptBuf::new_op_return(&[]);
+		let mut tx = round_tx(RoundFeeScheme::KeylessAnchor, &spk);
+		check_truc_policy(&tx, true).unwrap();
+
+		// Only TRUC txs have size limits.
+		let out = TxOut { value: Amount::ZERO, script_pubkey: spk };
+		tx.output.extend(std::iter::repeat(out).take(200));
+		assert!(tx.vsize() > TRUC_CHILD_MAX_VSIZE && tx.vsize() < TRUC_MAX_VSIZE);
+		check_truc_policy(&tx, false).unwrap();
+		tx.version = Version::TWO;
+		check_truc_policy(&tx, true).unwrap();
+		tx.version = Version(3);
+		check_truc_policy(&tx, true).unwrap_err();
+	}
+}
@@ -5,18 +5,29 @@
 #[macro_use] extern crate serde;
 
 
+mod chain;
 mod database;
+mod events;
+mod fee_scheme;
 mod lightning;
+mod metrics;
+mod nonce_pool;
 mod psbtext;
 mod serde_util;
 mod rpc;
 mod rpcserver;
 mod round;
+#[cfg(feature = "selftest")]
+mod selftest;
+mod signer;
 
-use std::fs;
+use std::{cmp, fmt, fs};
+use std::collections::HashSet;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -24,24 +35,45 @@ use anyhow::Context;
 use ark::lightning::Bolt11Payment;
 use bark_cln::subscribe_sendpay::SendpaySubscriptionItem;
 use bdk_bitcoind_rpc::bitcoincore_rpc::RpcApi;
+use bdk_wallet::chain::ChainPosition;
 use bitcoin::{
-	bip32, psbt, sighash, taproot, Address, Amount, FeeRate, Network, OutPoint, Transaction,
-	Weight, Witness,
+	bip32, psbt, sighash, taproot, Address, Amount, FeeRate, Network, OutPoint, ScriptBuf,
+	Sequence, Transaction, TxIn, TxOut, Txid, Weight, Witness,
 };
-use bitcoin::hashes::{sha256, Hash};
+use bitcoin::absolute::LockTime;
+use bitcoin::constants::COINBASE_MATURITY;
+use bitcoin::hashes::{sha256, sha256d, Hash};
+use bitcoin::p2p::Magic;
+use bitcoin::policy::MAX_STANDARD_TX_WEIGHT;
 use bitcoin::secp256k1::{self, Keypair, PublicKey};
 use lightning_invoice::Bolt11Invoice;
 
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::time::MissedTickBehavior;
-use tokio::sync::{Mutex, broadcast};
+use tokio::sync::{Mutex, broadcast, watch};
 use tokio_stream::{StreamExt, Stream};
 use tokio_stream::wrappers::{BroadcastStream, IntervalStream};
 
-use ark::util::KeypairExt;
-use ark::{musig, Vtxo};
-
-use crate::psbtext::{PsbtInputExt, RoundMeta};
-use crate::round::{RoundEvent, RoundInput};
+use ark::tree::signed::OutputKeyPolicy;
+use ark::connectors::{self, ConnectorChain};
+use ark::util::{KeypairExt, TransactionExt};
+use ark::{musig, ExitTimelockType, Vtxo, VtxoId, VtxoScriptType};
+
+use crate::chain::{ChainSource, ChainSourceConfig};
+use crate::database::{MonitorTip, StoredRound};
+use crate::events::{Event, EventSink};
+use crate::metrics::RoundMetrics;
+use crate::fee_scheme::{check_truc_policy, BumpOutput};
+use crate::round::{ProposedConnectors, RoundEvent, RoundInput};
+
+pub use crate::events::EventSinkConfig;
+pub use crate::fee_scheme::RoundFeeScheme;
+pub use crate::psbtext::{PsbtExt, PsbtExtError, PsbtInputExt, RoundMeta, PSBT_EXT_VERSION};
+pub use crate::nonce_pool::{NoncePool, NoncePoolExhausted};
+pub use crate::round::{aggregate_vtxo_sigs, RoundChange, RoundOutputOrdering};
+#[cfg(feature = "selftest")]
+pub use crate::selftest::{run_self_test, SelfTestStep};
+pub use crate::signer::{KeypairSigner, Signer};
 
 lazy_static::lazy_static! {
 	/// Global secp context.
@@ -52,40 +84,351 @@ lazy_static::lazy_static! {
 /// happening negligible.
 const DEEPLY_CONFIRMED: u64 = 100;
 
+/// The longest sweep grace period we allow, about a year of blocks.
+const MAX_SWEEP_GRACE_BLOCKS: u32 = 52_560;
+
+/// The prefix of environment variables that override config fields.
+pub const CONFIG_ENV_PREFIX: &str = "ARKD_";
+
+/// The challenge of the default signet.
+const DEFAULT_SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+/// How the outputs of expired rounds are swept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepMode {
+	/// Sweep expired rounds in the round txs and in sweep txs every
+	/// [Config::sweep_interval].
+	Auto,
+	/// Only sweep when asked to through the admin RPC.
+	Manual,
+}
+
+impl FromStr for SweepMode {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"auto" => Ok(SweepMode::Auto),
+			"manual" => Ok(SweepMode::Manual),
+			_ => bail!("unknown sweep mode: {}", s),
+		}
+	}
+}
+
+impl fmt::Display for SweepMode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			SweepMode::Auto => "auto",
+			SweepMode::Manual => "manual",
+		})
+	}
+}
+
 //TODO(stevenroose) sanity check deltas
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
 	pub network: bitcoin::Network,
+	/// The challenge script of a custom signet.
+	///
+	/// Only allowed for network signet, leave unset for the default signet.
+	/// Custom signets share the address encoding of the default signet, but
+	/// have a different network magic.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub signet_challenge: Option<ScriptBuf>,
 	pub public_rpc_address: SocketAddr,
+	/// The PEM certificate (chain) to serve the public gRPC service with TLS.
+	///
+	/// Without it, the public service uses plaintext. The certificate is
+	/// reloaded on SIGHUP.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub public_rpc_tls_cert_path: Option<PathBuf>,
+	/// The PEM private key for [Config::public_rpc_tls_cert_path].
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub public_rpc_tls_key_path: Option<PathBuf>,
+	/// Whether the public gRPC service gzips its responses to clients that
+	/// accept it, most notably the round events that carry the vtxo trees.
+	///
+	/// Clients that don't ask for compression always get plain responses.
+	#[serde(default = "default_public_rpc_compression")]
+	pub public_rpc_compression: bool,
 	pub admin_rpc_address: Option<SocketAddr>,
+	/// The token admin clients have to provide for sensitive admin RPCs,
+	/// like `shutdown`. These RPCs are refused when no token is set.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub admin_rpc_token: Option<String>,
 	pub bitcoind_url: String,
 	pub bitcoind_cookie: String,
+	/// Sync the onchain wallet from the Esplora server at this URL instead
+	/// of from bitcoind. bitcoind is still used for everything else.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub esplora_url: Option<String>,
 
 	// vtxo spec
 	pub vtxo_expiry_delta: u16,
 	pub vtxo_exit_delta: u16,
 	/// Add fee anchors on all VTXO tree intermediate txs.
 	pub vtxo_node_anchors: bool,
+	/// How the output key of the VTXO tree outputs is constructed.
+	pub vtxo_output_key_policy: OutputKeyPolicy,
+	/// The type of timelock used in the exit clause of VTXOs.
+	pub vtxo_exit_timelock: ExitTimelockType,
+	/// The type of the output scripts of VTXOs.
+	#[serde(default)]
+	pub vtxo_script_type: VtxoScriptType,
 	// ln
 	pub htlc_delta: u16,
 	pub htlc_expiry_delta: u16,
 
+	#[serde(with = "serde_util::duration")]
 	pub round_interval: Duration,
+	/// The maximum time between rounds when backing off after failures.
+	///
+	/// After every consecutive failed round, the time until the next round
+	/// doubles, up to this value. It's reset to the round interval after
+	/// the first successful round.
+	#[serde(with = "serde_util::duration", default = "default_max_round_interval")]
+	pub max_round_interval: Duration,
+	#[serde(with = "serde_util::duration")]
 	pub round_submit_time: Duration,
+	#[serde(with = "serde_util::duration")]
 	pub round_sign_time: Duration,
 	pub nb_round_nonces: usize,
+	/// The number of ASP keys in the cosign group of the vtxo tree.
+	///
+	/// The cosign key of the tree aggregates the keys of the users with
+	/// this many one-time keys of ours. All of them are ours, so more than
+	/// one adds no security, it only exercises cosign groups with several
+	/// ASP members.
+	#[serde(default = "default_nb_round_asp_cosigners")]
+	pub nb_round_asp_cosigners: usize,
+	/// The maximum number of input vtxos in a round.
+	///
+	/// Payments that would go over it are rejected as round full, with the
+	/// expected start of the next round. When not set, only the number of
+	/// outputs is limited, by the number of round nonces.
+	#[serde(default)]
+	pub max_round_inputs: Option<usize>,
+	/// The number of vtxo tree nodes whose signatures are aggregated
+	/// in one batch, progress is logged after every batch.
+	///
+	/// When not set, all nodes are aggregated at once.
+	#[serde(default)]
+	pub round_cosign_batch_size: Option<usize>,
 	//TODO(stevenroose) get these from a fee estimator service
 	/// Fee rate used for the round tx.
 	pub round_tx_feerate: FeeRate,
+	/// Number of blocks a round tx can stay unconfirmed before we bump it.
+	pub round_tx_bump_after: u32,
+	/// Fee rate used when bumping a stuck round tx using its fee anchor.
+	///
+	/// When the chain source estimates a higher fee rate for the next
+	/// block, we bump at that rate instead.
+	pub round_tx_bump_feerate: FeeRate,
+	/// The lowest fee rate we build txs at. It's applied as a floor to the
+	/// fee rates of round txs, sweep txs and CPFP txs.
+	///
+	/// Set this to at least the minimum relay fee rate of the network.
+	#[serde(default = "default_min_feerate")]
+	pub min_feerate: FeeRate,
+	/// The number of confirmations coinbase outputs of our wallet need
+	/// before we use them to fund round txs and fee bumps.
+	///
+	/// This can't be lower than the consensus coinbase maturity of 100.
+	#[serde(default = "default_coinbase_maturity")]
+	pub coinbase_maturity: u32,
+	/// Check round txs with testmempoolaccept before broadcasting them.
+	///
+	/// Disable this for backends that don't support testmempoolaccept.
+	#[serde(default = "default_round_tx_precheck")]
+	pub round_tx_precheck: bool,
+	/// The version of round txs.
+	///
+	/// Relative timelocks are only enforced for version 2 and up, so only
+	/// versions 2 and 3 are allowed.
+	#[serde(default = "default_round_tx_version")]
+	pub round_tx_version: i32,
+	/// Set the locktime of round txs to the current height to discourage
+	/// fee sniping. If unset, the locktime is zero.
+	#[serde(default = "default_round_tx_anti_fee_sniping")]
+	pub round_tx_anti_fee_sniping: bool,
+	/// Which output of round txs is used to bump their fee.
+	#[serde(default = "default_fee_scheme")]
+	pub fee_scheme: RoundFeeScheme,
+	/// Where our wallet change of round txs goes.
+	#[serde(default = "default_round_change")]
+	pub round_change: RoundChange,
+	/// The maximum value of the vtxo that holds our round change when
+	/// [Config::round_change] is [RoundChange::Vtxo].
+	///
+	/// Change above this amount goes to our onchain wallet as usual, so
+	/// that our liquidity isn't locked up in vtxos until they expire.
+	#[serde(default = "default_round_change_vtxo_max", with = "bitcoin::amount::serde::as_sat")]
+	pub round_change_vtxo_max: Amount,
+	/// How the vtxos and offboards of round txs are ordered.
+	///
+	/// Shuffling or BIP-69 ordering hides in which order participants
+	/// joined the round.
+	#[serde(default = "default_round_output_ordering")]
+	pub round_output_ordering: RoundOutputOrdering,
+	/// The value of each connector output created by round txs.
+	///
+	/// It has to cover the p2tr dust value plus the fee to spend the connector
+	/// at [Config::round_tx_bump_feerate].
+	#[serde(default = "default_connector_value", with = "bitcoin::amount::serde::as_sat")]
+	pub connector_value: Amount,
+	/// The maximum number of expired round utxos that are spent by a
+	/// single tx, be it a round tx or a sweep tx.
+	///
+	/// When more utxos are expired, they are spread over multiple sweeps.
+	/// The utxos of a round are only split over different sweeps when the
+	/// round has more utxos than fit in a single one.
+	#[serde(default = "default_sweep_batch_max_inputs")]
+	pub sweep_batch_max_inputs: usize,
+	/// Whether expired rounds are swept automatically or only through the
+	/// admin RPC.
+	#[serde(default = "default_sweep_mode")]
+	pub sweep_mode: SweepMode,
+	/// How often expired rounds are swept in [SweepMode::Auto].
+	///
+	/// Sweep txs pay the round tx feerate. Sweeps are checked before starting
+	/// a round, so they happen at most once per round interval.
+	#[serde(with = "serde_util::duration", default = "default_sweep_interval")]
+	pub sweep_interval: Duration,
+	/// The number of blocks we wait after a round expired before we sweep
+	/// it, so that users who were offline when their vtxos expired still
+	/// get a chance to refresh or exit them.
+	#[serde(default)]
+	pub sweep_grace_blocks: u32,
+	/// The maximum weight of round txs.
+	///
+	/// Payments with offboards that would make the round tx heavier are
+	/// rejected for the next round, expired round utxos are swept later.
+	#[serde(default = "default_round_tx_max_weight")]
+	pub round_tx_max_weight: u64,
+
+	// onchain wallet
+	/// Hand out a new address on every funding address request instead of
+	/// the first unused one.
+	pub wallet_rotate_addresses: bool,
+	/// Number of addresses beyond the last revealed one that are watched
+	/// when syncing the wallet.
+	pub wallet_gap_limit: u32,
 
 	// limits
 	#[serde(with = "bitcoin::amount::serde::as_sat::opt")]
 	pub max_onboard_value: Option<Amount>,
+	/// Number of confirmations an onboard tx needs before its vtxo can
+	/// be used in a round.
+	pub onboard_confirmations: u32,
+	/// Maximum number of confirmations an onboard utxo can already have
+	/// when we are asked to cosign its onboard.
+	///
+	/// Onboard txs are normally broadcast after we cosign them, this rejects
+	/// clients that try to onboard old, already confirmed outputs.
+	#[serde(default)]
+	pub onboard_max_confirmations: Option<u32>,
+	/// Number of nonces we generate ahead of time for cosigning onboards.
+	///
+	/// When set, onboard requests are signed with a nonce from this pool,
+	/// which is refilled in the background, and rejected while it's empty.
+	/// When not set, onboards are signed with a deterministic nonce.
+	#[serde(default)]
+	pub onboard_nonce_pool_size: Option<usize>,
+	/// Maximum number of blocks a vtxo can live, counted from the creation
+	/// of the vtxo it was originally refreshed from.
+	#[serde(default)]
+	pub max_vtxo_lifetime_blocks: Option<u32>,
+
+	/// The minimum amount of each output of an OOR payment.
+	///
+	/// Smaller vtxos would cost more to exit than they are worth.
+	#[serde(default = "default_oor_min_amount", with = "bitcoin::amount::serde::as_sat")]
+	pub oor_min_amount: Amount,
 
 	// lightning
 	#[serde(skip_serializing_if = "Option::is_none")]
 	#[serde(default)]
-	pub cln_config: Option<ClnConfig>
+	pub cln_config: Option<ClnConfig>,
+
+	/// Where to write the machine-readable event log, if anywhere.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub event_sink: Option<EventSinkConfig>,
+}
+
+fn default_nb_round_asp_cosigners() -> usize {
+	1
+}
+
+fn default_public_rpc_compression() -> bool {
+	true
+}
+
+fn default_round_tx_precheck() -> bool {
+	true
+}
+
+fn default_min_feerate() -> FeeRate {
+	FeeRate::BROADCAST_MIN
+}
+
+fn default_coinbase_maturity() -> u32 {
+	COINBASE_MATURITY
+}
+
+fn default_max_round_interval() -> Duration {
+	Duration::from_secs(5 * 60)
+}
+
+fn default_round_tx_version() -> i32 {
+	2
+}
+
+fn default_round_tx_anti_fee_sniping() -> bool {
+	true
+}
+
+fn default_fee_scheme() -> RoundFeeScheme {
+	RoundFeeScheme::KeylessAnchor
+}
+
+fn default_round_change() -> RoundChange {
+	RoundChange::Onchain
+}
+
+fn default_round_output_ordering() -> RoundOutputOrdering {
+	RoundOutputOrdering::Submission
+}
+
+fn default_connector_value() -> Amount {
+	Amount::from_sat(1_000)
+}
+
+fn default_sweep_batch_max_inputs() -> usize {
+	100
+}
+
+fn default_sweep_mode() -> SweepMode {
+	SweepMode::Auto
+}
+
+fn default_sweep_interval() -> Duration {
+	Duration::from_secs(10 * 60)
+}
+
+fn default_round_tx_max_weight() -> u64 {
+	// Leave room below the standardness limit for our wallet inputs.
+	MAX_STANDARD_TX_WEIGHT as u64 * 3 / 4
+}
+
+fn default_round_change_vtxo_max() -> Amount {
+	Amount::from_sat(1_000_000)
+}
+
+fn default_oor_min_amount() -> Amount {
+	ark::oor::min_exitable_amount()
 }
 
 // NB some random defaults to have something
@@ -93,22 +436,60 @@ impl Default for Config {
 	fn default() -> Config {
 		Config {
 			network: bitcoin::Network::Regtest,
+			signet_challenge: None,
 			public_rpc_address: "0.0.0.0:3535".parse().unwrap(),
+			public_rpc_tls_cert_path: None,
+			public_rpc_tls_key_path: None,
+			public_rpc_compression: default_public_rpc_compression(),
 			admin_rpc_address: Some("127.0.0.1:3536".parse().unwrap()),
+			admin_rpc_token: None,
 			bitcoind_url: "http://127.0.0.1:38332".into(),
 			bitcoind_cookie: "~/.bitcoin/signet/.cookie".into(),
+			esplora_url: None,
 			vtxo_expiry_delta: 1 * 24 * 6, // 1 day
 			vtxo_exit_delta: 2 * 6, // 2 hrs
 			vtxo_node_anchors: true,
+			vtxo_output_key_policy: OutputKeyPolicy::MerkleRootTweak,
+			vtxo_exit_timelock: ExitTimelockType::Relative,
+			vtxo_script_type: VtxoScriptType::Taproot,
 			htlc_delta: 1 * 6, // 1 hr
 			htlc_expiry_delta: 1 * 6, // 1 hr
 			round_interval: Duration::from_secs(10),
+			max_round_interval: default_max_round_interval(),
 			round_submit_time: Duration::from_secs(2),
 			round_sign_time: Duration::from_secs(2),
 			nb_round_nonces: 100,
+			nb_round_asp_cosigners: default_nb_round_asp_cosigners(),
+			max_round_inputs: None,
+			round_cosign_batch_size: None,
 			round_tx_feerate: FeeRate::from_sat_per_vb(10).unwrap(),
+			round_tx_bump_after: 3,
+			round_tx_bump_feerate: FeeRate::from_sat_per_vb(25).unwrap(),
+			min_feerate: default_min_feerate(),
+			coinbase_maturity: default_coinbase_maturity(),
+			round_tx_precheck: default_round_tx_precheck(),
+			round_tx_version: default_round_tx_version(),
+			round_tx_anti_fee_sniping: default_round_tx_anti_fee_sniping(),
+			fee_scheme: default_fee_scheme(),
+			round_change: default_round_change(),
+			round_change_vtxo_max: default_round_change_vtxo_max(),
+			round_output_ordering: default_round_output_ordering(),
+			connector_value: default_connector_value(),
+			sweep_batch_max_inputs: default_sweep_batch_max_inputs(),
+			sweep_mode: default_sweep_mode(),
+			sweep_interval: default_sweep_interval(),
+			sweep_grace_blocks: 0,
+			round_tx_max_weight: default_round_tx_max_weight(),
+			wallet_rotate_addresses: false,
+			wallet_gap_limit: 25,
 			max_onboard_value: None,
+			onboard_confirmations: 0,
+			onboard_max_confirmations: None,
+			onboard_nonce_pool_size: None,
+			max_vtxo_lifetime_blocks: None,
+			oor_min_amount: default_oor_min_amount(),
 			cln_config: None,
+			event_sink: None,
 		}
 	}
 }
@@ -132,6 +513,427 @@ impl Config {
 		serde_json::from_slice::<Self>(&bytes).context("invalid config file")
 	}
 
+	/// Parse a config file, filling in defaults for fields it doesn't have.
+	///
+	/// Returns the config and the names of the fields that were added.
+	/// The network is never defaulted, old files always have it.
+	pub fn upgrade_json(bytes: &[u8]) -> anyhow::Result<(Self, Vec<String>)> {
+		let value = serde_json::from_slice::<serde_json::Value>(bytes)
+			.context("invalid config file")?;
+		let fields = value.as_object().context("config file is not a json object")?;
+		ensure!(fields.contains_key("network"), "config file has no network");
+
+		let config = serde_json::from_value::<Self>(value.clone())
+			.context("invalid config file")?;
+		let complete = serde_json::to_value(&config)?;
+		let added = complete.as_object().expect("config serializes as object").keys()
+			.filter(|k| !fields.contains_key(k.as_str()))
+			.cloned()
+			.collect();
+		Ok((config, added))
+	}
+
+	/// Upgrade the config file in the datadir to the current format.
+	///
+	/// Missing fields get their default value and the complete config is
+	/// written back, after making a back-up of the old file.
+	pub fn upgrade_in_datadir<P: AsRef<Path>>(datadir: P) -> anyhow::Result<Self> {
+		let path = datadir.as_ref().join("config.json");
+		let bytes = fs::read(&path)
+			.with_context(|| format!("failed to read config file: {}", path.display()))?;
+
+		let (config, added) = Self::upgrade_json(&bytes)?;
+		if !added.is_empty() {
+			for field in &added {
+				info!("Config upgrade: added field {} with its default value", field);
+			}
+			Self::create_backup_in_datadir(&datadir)?;
+			config.write_to_datadir(&datadir)?;
+			info!("Upgraded config file {} with {} new fields", path.display(), added.len());
+		}
+		Ok(config)
+	}
+
+	/// Check that the config values are sane.
+	pub fn validate(&self) -> anyhow::Result<()> {
+		ensure!(self.round_tx_version == 2 || self.round_tx_version == 3,
+			"round tx version must be 2 or 3, not {}", self.round_tx_version,
+		);
+		self.fee_scheme.check_compatible(self.round_tx_version)?;
+		ensure!(self.round_change != RoundChange::Vtxo || self.fee_scheme != RoundFeeScheme::CpfpChange,
+			"round change can't be kept in a vtxo with fee scheme {}", self.fee_scheme,
+		);
+		ensure!(self.round_change != RoundChange::Vtxo || self.round_change_vtxo_max >= ark::fee::DUST,
+			"round change vtxo max {} is below the dust limit", self.round_change_vtxo_max,
+		);
+		if let Some(ref challenge) = self.signet_challenge {
+			ensure!(self.network == Network::Signet,
+				"a signet challenge can only be set for network signet, not {}", self.network,
+			);
+			ensure!(!challenge.is_empty(), "the signet challenge can't be empty");
+		}
+		if let Some(ref token) = self.admin_rpc_token {
+			ensure!(!token.is_empty(), "the admin rpc token can't be empty");
+		}
+		ensure!(self.public_rpc_tls_cert_path.is_some() == self.public_rpc_tls_key_path.is_some(),
+			"the public rpc TLS certificate and key have to be set together",
+		);
+		if let Some(max) = self.onboard_max_confirmations {
+			ensure!(max >= self.onboard_confirmations,
+				"onboard max confirmations ({}) can't be lower than the onboard confirmations ({})",
+				max, self.onboard_confirmations,
+			);
+		}
+		ensure!(self.max_round_interval >= self.round_interval,
+			"the max round interval ({:?}) can't be lower than the round interval ({:?})",
+			self.max_round_interval, self.round_interval,
+		);
+		ensure!(self.coinbase_maturity >= COINBASE_MATURITY,
+			"the coinbase maturity can't be lower than the consensus maturity of {}",
+			COINBASE_MATURITY,
+		);
+		ensure!(self.nb_round_asp_cosigners > 0, "the number of round ASP cosigners can't be zero");
+		if let Some(max) = self.max_round_inputs {
+			ensure!(max > 0, "the max round inputs can't be zero");
+		}
+		if let Some(size) = self.round_cosign_batch_size {
+			ensure!(size > 0, "the round cosign batch size can't be zero");
+		}
+		if let Some(size) = self.onboard_nonce_pool_size {
+			ensure!(size > 0, "the onboard nonce pool size can't be zero");
+		}
+		ensure!(self.sweep_batch_max_inputs > 0, "the sweep batch max inputs can't be zero");
+		ensure!(!self.sweep_interval.is_zero(), "the sweep interval can't be zero");
+		ensure!(self.sweep_grace_blocks <= MAX_SWEEP_GRACE_BLOCKS,
+			"the sweep grace blocks can't exceed {}", MAX_SWEEP_GRACE_BLOCKS,
+		);
+		ensure!(self.round_tx_max_weight <= MAX_STANDARD_TX_WEIGHT as u64,
+			"the round tx max weight can't exceed the standard limit of {}", MAX_STANDARD_TX_WEIGHT,
+		);
+		let base_weight = round::round_tx_base_weight(self);
+		ensure!(self.round_tx_max_weight > base_weight.to_wu(),
+			"the round tx max weight has to be more than the base round tx weight of {}",
+			base_weight.to_wu(),
+		);
+		ensure!(self.oor_min_amount >= ark::P2TR_DUST,
+			"the OOR min amount can't be lower than the dust value of {}", ark::P2TR_DUST,
+		);
+		let min_connector_value = connectors::min_connector_value(self.round_tx_bump_feerate);
+		ensure!(self.connector_value >= min_connector_value,
+			"connector value of {} is too low to spend at the round tx bump feerate, \
+			it has to be at least {}", self.connector_value, min_connector_value,
+		);
+		Ok(())
+	}
+
+	/// Check whether an onboard utxo with the given number of confirmations
+	/// can be cosigned.
+	///
+	/// Utxos that are not confirmed yet are fine, the minimum number of
+	/// confirmations is enforced when the vtxo is used.
+	pub fn check_onboard_utxo_depth(
+		&self,
+		utxo: OutPoint,
+		confirmations: Option<u32>,
+	) -> anyhow::Result<()> {
+		if let (Some(confs), Some(max)) = (confirmations, self.onboard_max_confirmations) {
+			ensure!(confs <= max,
+				"onboard utxo {} already has {} confirmations, at most {} allowed", utxo, confs, max,
+			);
+		}
+		Ok(())
+	}
+
+	/// Check that all outputs of an OOR payment are at least
+	/// [Config::oor_min_amount].
+	pub fn check_oor_outputs(&self, outputs: &[ark::VtxoRequest]) -> anyhow::Result<()> {
+		if let Some(o) = outputs.iter().find(|o| o.amount < self.oor_min_amount) {
+			bail!("OOR output of {} is below the minimum of {}", o.amount, self.oor_min_amount);
+		}
+		Ok(())
+	}
+
+	/// The chain source to sync the onchain wallet from.
+	fn chain_source(&self) -> ChainSourceConfig {
+		match self.esplora_url {
+			Some(ref url) => ChainSourceConfig::Esplora { url: url.clone() },
+			None => ChainSourceConfig::Bitcoind {
+				url: self.bitcoind_url.clone(),
+				auth: bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(
+					self.bitcoind_cookie.as_str().into(),
+				),
+			},
+		}
+	}
+
+	/// The network magic of the chain we run on.
+	pub fn network_magic(&self) -> Magic {
+		match self.signet_challenge {
+			// Like Core, we take the first 4 bytes of the hash of the challenge.
+			Some(ref challenge) => {
+				let hash = sha256d::Hash::hash(&bitcoin::consensus::serialize(challenge));
+				Magic::from_bytes(hash.to_byte_array()[..4].try_into().unwrap())
+			},
+			None => self.network.magic(),
+		}
+	}
+
+	/// Check the `getblockchaininfo` response of bitcoind against our network.
+	///
+	/// Older versions of bitcoind don't report their signet challenge, in which
+	/// case we can't tell a custom signet from the default one.
+	fn check_chain_info(&self, info: &serde_json::Value) -> anyhow::Result<()> {
+		let chain = info["chain"].as_str().context("no chain in blockchain info")?;
+		let network = Network::from_core_arg(chain)
+			.with_context(|| format!("bitcoind is running unknown chain {}", chain))?;
+		ensure!(network == self.network,
+			"bitcoind is running on {}, but we are configured for {}", network, self.network,
+		);
+
+		if self.network == Network::Signet {
+			let expected = match self.signet_challenge {
+				Some(ref c) => c.clone(),
+				None => ScriptBuf::from_hex(DEFAULT_SIGNET_CHALLENGE).unwrap(),
+			};
+			match info["signet_challenge"].as_str() {
+				Some(hex) => {
+					let challenge = ScriptBuf::from_hex(hex)
+						.context("bitcoind reported invalid signet challenge")?;
+					ensure!(challenge == expected,
+						"bitcoind is running on signet with challenge {}, but we are configured for {}",
+						challenge.to_hex_string(), expected.to_hex_string(),
+					);
+				},
+				None => warn!("bitcoind doesn't report its signet challenge, can't check it"),
+			}
+		}
+		Ok(())
+	}
+
+	/// Check that bitcoind runs on the chain we are configured for.
+	fn check_bitcoind_chain(
+		&self,
+		bitcoind: &bdk_bitcoind_rpc::bitcoincore_rpc::Client,
+	) -> anyhow::Result<()> {
+		let info = bitcoind.call::<serde_json::Value>("getblockchaininfo", &[])
+			.context("failed to fetch blockchain info from bitcoind")?;
+		self.check_chain_info(&info)
+	}
+
+	/// Override config fields with values from `ARKD_*` environment variables.
+	///
+	/// Environment variables take precedence over the values in the config
+	/// file. See [Config::apply_overrides] for the format.
+	pub fn apply_env_overrides(&mut self) -> anyhow::Result<()> {
+		let mut vars = Vec::new();
+		for (key, value) in std::env::vars_os() {
+			// Other variables are none of our business, even if they're not unicode.
+			if !key.as_encoded_bytes().starts_with(CONFIG_ENV_PREFIX.as_bytes()) {
+				continue;
+			}
+			let key = key.into_string().map_err(|k| {
+				anyhow!("environment variable {} is not valid unicode", k.to_string_lossy())
+			})?;
+			let value = value.into_string().map_err(|_| {
+				anyhow!("value of environment variable {} is not valid unicode", key)
+			})?;
+			vars.push((key, value));
+		}
+		self.apply_overrides(vars)
+	}
+
+	/// Override config fields with the given `ARKD_*` variables.
+	///
+	/// Every variable is named after the config field in upper case, f.e.
+	/// `ARKD_BITCOIND_URL` for the `bitcoind_url` field. The fields of the
+	/// cln config use `ARKD_CLN_` followed by the name of the field, f.e.
+	/// `ARKD_CLN_GRPC_URI`, and can only be used if the config file already
+	/// has a cln config.
+	///
+	/// Durations are given like in the config file, f.e. `10s` or `2500ms`,
+	/// with a plain number taken as milliseconds. Amounts are given in sats
+	/// and the round tx feerates in sats per kvb. An empty value unsets an
+	/// optional field.
+	///
+	/// Variables without the `ARKD_` prefix are ignored, unknown variables
+	/// with the prefix result in an error.
+	pub fn apply_overrides(
+		&mut self,
+		vars: impl IntoIterator<Item = (String, String)>,
+	) -> anyhow::Result<()> {
+		fn opt(v: String) -> Option<String> {
+			if v.is_empty() { None } else { Some(v) }
+		}
+		fn parse_kvb(v: &str) -> anyhow::Result<FeeRate> {
+			let kvb = v.parse::<u64>()?;
+			Ok(FeeRate::from_sat_per_kwu((kvb.checked_sub(1).context("feerate can't be 0")? / 4) + 1))
+		}
+		fn parse_duration(v: &str) -> anyhow::Result<Duration> {
+			// Like in the config file, a plain number is in milliseconds.
+			match v.parse::<u64>() {
+				Ok(ms) => Ok(Duration::from_millis(ms)),
+				Err(_) => serde_util::duration::from_str(v).map_err(|e| anyhow!("{}", e)),
+			}
+		}
+
+		for (key, value) in vars {
+			let field = match key.strip_prefix(CONFIG_ENV_PREFIX) {
+				Some(f) => f,
+				None => continue,
+			};
+			trace!("Overriding config field from environment variable {}", key);
+			let ctx = || format!("invalid value for environment variable {}", key);
+
+			match field {
+				"NETWORK" => self.network = value.parse().with_context(ctx)?,
+				"SIGNET_CHALLENGE" => {
+					self.signet_challenge = opt(value).map(|v| ScriptBuf::from_hex(&v))
+						.transpose().with_context(ctx)?;
+				},
+				"PUBLIC_RPC_ADDRESS" => {
+					self.public_rpc_address = value.parse().with_context(ctx)?;
+				},
+				"ADMIN_RPC_ADDRESS" => {
+					self.admin_rpc_address = opt(value).map(|v| v.parse())
+						.transpose().with_context(ctx)?;
+				},
+				"ADMIN_RPC_TOKEN" => self.admin_rpc_token = opt(value),
+				"PUBLIC_RPC_TLS_CERT_PATH" => {
+					self.public_rpc_tls_cert_path = opt(value).map(PathBuf::from);
+				},
+				"PUBLIC_RPC_TLS_KEY_PATH" => {
+					self.public_rpc_tls_key_path = opt(value).map(PathBuf::from);
+				},
+				"PUBLIC_RPC_COMPRESSION" => {
+					self.public_rpc_compression = value.parse().with_context(ctx)?;
+				},
+				"BITCOIND_URL" => self.bitcoind_url = value,
+				"BITCOIND_COOKIE" => self.bitcoind_cookie = value,
+				"ESPLORA_URL" => self.esplora_url = opt(value),
+				"VTXO_EXPIRY_DELTA" => self.vtxo_expiry_delta = value.parse().with_context(ctx)?,
+				"VTXO_EXIT_DELTA" => self.vtxo_exit_delta = value.parse().with_context(ctx)?,
+				"VTXO_NODE_ANCHORS" => self.vtxo_node_anchors = value.parse().with_context(ctx)?,
+				"VTXO_OUTPUT_KEY_POLICY" => {
+					self.vtxo_output_key_policy = value.parse().map_err(|e| anyhow!("{}", e))
+						.with_context(ctx)?;
+				},
+				"VTXO_EXIT_TIMELOCK" => {
+					self.vtxo_exit_timelock = value.parse().map_err(|e| anyhow!("{}", e))
+						.with_context(ctx)?;
+				},
+				"VTXO_SCRIPT_TYPE" => {
+					self.vtxo_script_type = value.parse().map_err(|e| anyhow!("{}", e))
+						.with_context(ctx)?;
+				},
+				"HTLC_DELTA" => self.htlc_delta = value.parse().with_context(ctx)?,
+				"HTLC_EXPIRY_DELTA" => self.htlc_expiry_delta = value.parse().with_context(ctx)?,
+				"ROUND_INTERVAL" => {
+					self.round_interval = parse_duration(&value).with_context(ctx)?;
+				},
+				"MAX_ROUND_INTERVAL" => {
+					self.max_round_interval = parse_duration(&value).with_context(ctx)?;
+				},
+				"ROUND_SUBMIT_TIME" => {
+					self.round_submit_time = parse_duration(&value).with_context(ctx)?;
+				},
+				"ROUND_SIGN_TIME" => {
+					self.round_sign_time = parse_duration(&value).with_context(ctx)?;
+				},
+				"NB_ROUND_NONCES" => self.nb_round_nonces = value.parse().with_context(ctx)?,
+				"NB_ROUND_ASP_COSIGNERS" => {
+					self.nb_round_asp_cosigners = value.parse().with_context(ctx)?;
+				},
+				"MAX_ROUND_INPUTS" => {
+					self.max_round_inputs = opt(value).map(|v| v.parse())
+						.transpose().with_context(ctx)?;
+				},
+				"ROUND_COSIGN_BATCH_SIZE" => {
+					self.round_cosign_batch_size = opt(value).map(|v| v.parse())
+						.transpose().with_context(ctx)?;
+				},
+				"ROUND_TX_FEERATE" => {
+					self.round_tx_feerate = parse_kvb(&value).with_context(ctx)?;
+				},
+				"ROUND_TX_BUMP_AFTER" => self.round_tx_bump_after = value.parse().with_context(ctx)?,
+				"ROUND_TX_BUMP_FEERATE" => {
+					self.round_tx_bump_feerate = parse_kvb(&value).with_context(ctx)?;
+				},
+				"MIN_FEERATE" => self.min_feerate = parse_kvb(&value).with_context(ctx)?,
+				"COINBASE_MATURITY" => self.coinbase_maturity = value.parse().with_context(ctx)?,
+				"ROUND_TX_PRECHECK" => self.round_tx_precheck = value.parse().with_context(ctx)?,
+				"ROUND_TX_VERSION" => self.round_tx_version = value.parse().with_context(ctx)?,
+				"FEE_SCHEME" => self.fee_scheme = value.parse().with_context(ctx)?,
+				"ROUND_CHANGE" => self.round_change = value.parse().with_context(ctx)?,
+				"ROUND_CHANGE_VTXO_MAX" => {
+					self.round_change_vtxo_max = Amount::from_sat(value.parse().with_context(ctx)?);
+				},
+				"ROUND_OUTPUT_ORDERING" => {
+					self.round_output_ordering = value.parse().with_context(ctx)?;
+				},
+				"CONNECTOR_VALUE" => {
+					self.connector_value = Amount::from_sat(value.parse().with_context(ctx)?);
+				},
+				"ROUND_TX_MAX_WEIGHT" => {
+					self.round_tx_max_weight = value.parse().with_context(ctx)?;
+				},
+				"SWEEP_BATCH_MAX_INPUTS" => {
+					self.sweep_batch_max_inputs = value.parse().with_context(ctx)?;
+				},
+				"SWEEP_MODE" => self.sweep_mode = value.parse().with_context(ctx)?,
+				"SWEEP_INTERVAL" => {
+					self.sweep_interval = parse_duration(&value).with_context(ctx)?;
+				},
+				"SWEEP_GRACE_BLOCKS" => self.sweep_grace_blocks = value.parse().with_context(ctx)?,
+				"ROUND_TX_ANTI_FEE_SNIPING" => {
+					self.round_tx_anti_fee_sniping = value.parse().with_context(ctx)?;
+				},
+				"WALLET_ROTATE_ADDRESSES" => {
+					self.wallet_rotate_addresses = value.parse().with_context(ctx)?;
+				},
+				"WALLET_GAP_LIMIT" => self.wallet_gap_limit = value.parse().with_context(ctx)?,
+				"MAX_ONBOARD_VALUE" => {
+					self.max_onboard_value = opt(value).map(|v| v.parse().map(Amount::from_sat))
+						.transpose().with_context(ctx)?;
+				},
+				"ONBOARD_CONFIRMATIONS" => {
+					self.onboard_confirmations = value.parse().with_context(ctx)?;
+				},
+				"ONBOARD_MAX_CONFIRMATIONS" => {
+					self.onboard_max_confirmations = opt(value).map(|v| v.parse())
+						.transpose().with_context(ctx)?;
+				},
+				"ONBOARD_NONCE_POOL_SIZE" => {
+					self.onboard_nonce_pool_size = opt(value).map(|v| v.parse())
+						.transpose().with_context(ctx)?;
+				},
+				"OOR_MIN_AMOUNT" => {
+					self.oor_min_amount = Amount::from_sat(value.parse().with_context(ctx)?);
+				},
+				"MAX_VTXO_LIFETIME_BLOCKS" => {
+					self.max_vtxo_lifetime_blocks = opt(value).map(|v| v.parse())
+						.transpose().with_context(ctx)?;
+				},
+				"EVENT_SINK" => {
+					self.event_sink = opt(value).map(|v| v.parse()).transpose().with_context(ctx)?;
+				},
+				f if f.starts_with("CLN_") => {
+					let cln = self.cln_config.as_mut()
+						.with_context(|| format!("can't set {} without a cln config", key))?;
+					match &f[4..] {
+						"GRPC_URI" => cln.grpc_uri = value.parse().with_context(ctx)?,
+						"GRPC_SERVER_CERT_PATH" => cln.grpc_server_cert_path = value.into(),
+						"GRPC_CLIENT_CERT_PATH" => cln.grpc_client_cert_path = value.into(),
+						"GRPC_CLIENT_KEY_PATH" => cln.grpc_client_key_path = value.into(),
+						_ => bail!("unknown config environment variable: {}", key),
+					}
+				},
+				_ => bail!("unknown config environment variable: {}", key),
+			}
+		}
+
+		Ok(())
+	}
+
 	pub fn write_to_datadir<P: AsRef<Path>>(&self, datadir: P) -> anyhow::Result<()> {
 		let path = datadir.as_ref().join("config.json");
 		trace!("Dumping configuration from file {}", path.display());
@@ -162,23 +964,167 @@ impl Config {
 	}
 }
 
+/// Fill a new datadir using `init` and only move it in place when that succeeds.
+///
+/// `init` gets a temporary dir next to the datadir, which is removed again
+/// when `init` fails. This way a failed create doesn't leave a partial
+/// datadir behind that makes a retry fail.
+async fn create_datadir<F, Fut>(datadir: &Path, init: F) -> anyhow::Result<()>
+where
+	F: FnOnce(PathBuf) -> Fut,
+	Fut: Future<Output = anyhow::Result<()>>,
+{
+	if datadir.exists() && fs::read_dir(datadir).context("can't read dir")?.next().is_some() {
+		bail!("dir is not empty: {}. Use an empty or new dir to create an aspd", datadir.display());
+	}
+
+	let tmp = tmp_datadir(datadir)?;
+	// A previous create could have been killed before it cleaned up.
+	if tmp.exists() {
+		fs::remove_dir_all(&tmp).context("can't remove stale temporary dir")?;
+	}
+	fs::create_dir_all(&tmp).context("can't create dir")?;
+	if let Err(e) = init(tmp.clone()).await {
+		if let Err(e) = fs::remove_dir_all(&tmp) {
+			warn!("Failed to remove temporary dir {}: {}", tmp.display(), e);
+		}
+		return Err(e);
+	}
+
+	// Not all platforms can rename over an empty dir.
+	if datadir.exists() {
+		fs::remove_dir(datadir).context("can't remove empty dir")?;
+	}
+	fs::rename(&tmp, datadir).context("can't move new datadir in place")?;
+	Ok(())
+}
+
+/// The derivation path of the account of our onchain wallet,
+/// relative to the master key.
+const WALLET_ACCOUNT_PATH: &str = "84'/0'/0'";
+
+/// The descriptor of our onchain wallet, given the key expression of the
+/// wallet account, see [WALLET_ACCOUNT_PATH].
+fn wallet_descriptor(account_key: impl fmt::Display) -> String {
+	format!("tr({}/0/*)", account_key)
+}
+
+/// The fee rate to bump stuck round txs to: the configured bump fee rate,
+/// or the next-block estimate of the chain source if that's higher.
+async fn round_bump_feerate(chain: &dyn ChainSource, configured: FeeRate) -> FeeRate {
+	match chain.estimate_fee(1).await {
+		Ok(Some(estimate)) => cmp::max(configured, estimate),
+		Ok(None) => configured,
+		Err(e) => {
+			warn!("Error estimating fee rate, bumping at {}: {:#}", configured, e);
+			configured
+		},
+	}
+}
+
+/// The temporary dir we create the datadir in.
+fn tmp_datadir(datadir: &Path) -> anyhow::Result<PathBuf> {
+	let name = datadir.file_name().context("invalid datadir")?;
+	Ok(datadir.with_file_name(format!(".{}.creating", name.to_string_lossy())))
+}
+
 pub struct RoundHandle {
 	round_event_tx: tokio::sync::broadcast::Sender<RoundEvent>,
 	round_input_tx: tokio::sync::mpsc::UnboundedSender<RoundInput>,
-	round_trigger_tx: tokio::sync::mpsc::Sender<()>,
+	round_trigger_tx: tokio::sync::mpsc::Sender<round::RoundTrigger>,
+	/// The connectors of the latest round proposal.
+	proposed_connectors: Mutex<Option<ProposedConnectors>>,
+	/// The epoch of the latest round start, payments have to carry it.
+	round_epoch: AtomicU64,
+	/// Whether the latest round start stopped taking payments because it's full.
+	round_full: AtomicBool,
+	/// The expected start of the next round, in ms since the unix epoch.
+	next_round_start: AtomicU64,
+}
+
+/// A handle to request a graceful shutdown of a running [App].
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl ShutdownHandle {
+	/// Request a graceful shutdown, see [App::shutdown].
+	pub fn shutdown(&self) {
+		info!("Shutdown requested");
+		self.0.send_replace(true);
+	}
 }
 
 pub struct SendpayHandle {
 	sendpay_rx: tokio::sync::broadcast::Receiver<SendpaySubscriptionItem>
 }
 
+/// The status of a vtxo as far as we know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VtxoStatus {
+	/// The round or onboard the vtxo came from has not expired yet.
+	Active,
+	/// The vtxo was spent, either forfeited in a round or spent out-of-round.
+	Forfeited,
+	/// The round of the vtxo expired and we swept its outputs.
+	Swept,
+	/// The round of the vtxo expired, but we didn't sweep it yet.
+	Expired,
+	/// We don't know about this vtxo.
+	Unknown,
+}
+
+/// The error returned when a round can't be swept in its current state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SweepRejected(String);
+
+impl fmt::Display for SweepRejected {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for SweepRejected {}
+
+/// The public key material of the ASP.
+///
+/// This is enough to start aspd in descriptor-only mode when the seed is
+/// lost, see [App::open_descriptor_only].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescriptorBackup {
+	/// The public descriptor of the onchain wallet.
+	pub descriptor: String,
+	/// The ASP's public key used in vtxos and connectors.
+	pub asp_pubkey: PublicKey,
+}
+
 pub struct App {
 	config: Config,
 	db: database::Db,
-	master_xpriv: bip32::Xpriv,
-	master_key: Keypair,
+	/// [None] when running in descriptor-only mode.
+	master_xpriv: Option<bip32::Xpriv>,
+	/// [None] when running in descriptor-only mode.
+	master_key: Option<Keypair>,
+	/// Signs round utxo inputs and onboards with the master key, the other
+	/// signatures are made with [App::master_key].
+	///
+	/// [None] when running in descriptor-only mode.
+	signer: Option<Box<dyn Signer>>,
+	asp_pubkey: PublicKey,
 	wallet: Mutex<bdk_wallet::Wallet>,
 	bitcoind: bdk_bitcoind_rpc::bitcoincore_rpc::Client,
+	/// Where we sync our onchain wallet from.
+	chain_source: Box<dyn ChainSource>,
+	events: Option<EventSink>,
+	round_metrics: RoundMetrics,
+	/// Rounds we already warned about because we can't sweep them.
+	unsweepable_rounds: std::sync::Mutex<HashSet<Txid>>,
+	/// Held while spending expired round utxos, so that the admin sweeps
+	/// and the round coordinator don't spend the same utxos.
+	sweep_lock: Mutex<()>,
+	/// Pre-generated nonces for onboard cosigning, if configured.
+	onboard_nonces: Option<NoncePool>,
+	/// Set to true to request a graceful shutdown.
+	shutdown: ShutdownHandle,
 
 	rounds: Option<RoundHandle>,
 	sendpay_updates: Option<SendpayHandle>
@@ -188,14 +1134,13 @@ impl App {
 	fn wallet_from_seed(
 		network: Network,
 		seed: &[u8],
+		gap_limit: u32,
 		state: Option<bdk_wallet::ChangeSet>,
 	) -> anyhow::Result<(Keypair, bip32::Xpriv, bdk_wallet::Wallet)> {
 		let (master_key, xpriv, edesc) = {
-			let seed_xpriv = bip32::Xpriv::new_master(network, &seed).unwrap();
-			let path = bip32::DerivationPath::from_str("m/0").unwrap();
-			let xpriv = seed_xpriv.derive_priv(&SECP, &path).unwrap();
+			let xpriv = Self::master_xpriv_from_seed(network, seed);
 			let keypair = Keypair::from_secret_key(&SECP, &xpriv.private_key);
-			let edesc = format!("tr({}/84'/0'/0'/0/*)", xpriv);
+			let edesc = wallet_descriptor(format!("{}/{}", xpriv, WALLET_ACCOUNT_PATH));
 
 			(keypair, xpriv, edesc)
 		};
@@ -205,6 +1150,7 @@ impl App {
 				let wallet = bdk_wallet::Wallet::load()
 					.descriptor(bdk_wallet::KeychainKind::External, Some(edesc.clone()))
 					.check_network(network)
+					.lookahead(gap_limit)
 					.extract_keys()
 					.load_wallet_no_persist(changeset)?;
 				wallet.expect("wallet should be loaded")
@@ -212,6 +1158,7 @@ impl App {
 			None => {
 				bdk_wallet::Wallet::create_single(edesc)
 					.network(network)
+					.lookahead(gap_limit)
 					.create_wallet_no_persist()?
 			},
 		};
@@ -219,27 +1166,136 @@ impl App {
 		Ok((master_key, xpriv, wallet))
 	}
 
+	/// The master key of the ASP, all our keys are derived from it.
+	fn master_xpriv_from_seed(network: Network, seed: &[u8]) -> bip32::Xpriv {
+		let seed_xpriv = bip32::Xpriv::new_master(network, &seed).unwrap();
+		let path = bip32::DerivationPath::from_str("m/0").unwrap();
+		seed_xpriv.derive_priv(&SECP, &path).unwrap()
+	}
+
+	fn descriptor_backup_from_seed(network: Network, seed: &[u8]) -> DescriptorBackup {
+		let xpriv = Self::master_xpriv_from_seed(network, seed);
+		let account_path = bip32::DerivationPath::from_str(&format!("m/{}", WALLET_ACCOUNT_PATH))
+			.unwrap();
+		let account = bip32::Xpub::from_priv(&SECP, &xpriv.derive_priv(&SECP, &account_path).unwrap());
+		let origin = format!("[{}/{}]", xpriv.fingerprint(&SECP), WALLET_ACCOUNT_PATH);
+		DescriptorBackup {
+			descriptor: wallet_descriptor(format!("{}{}", origin, account)),
+			asp_pubkey: xpriv.private_key.public_key(&SECP),
+		}
+	}
+
+	fn wallet_from_descriptor(
+		network: Network,
+		descriptor: &str,
+		gap_limit: u32,
+		state: Option<bdk_wallet::ChangeSet>,
+	) -> anyhow::Result<bdk_wallet::Wallet> {
+		let desc = descriptor.to_owned();
+		Ok(match state {
+			Some(changeset) => {
+				let wallet = bdk_wallet::Wallet::load()
+					.descriptor(bdk_wallet::KeychainKind::External, Some(desc))
+					.check_network(network)
+					.lookahead(gap_limit)
+					.load_wallet_no_persist(changeset)?;
+				wallet.expect("wallet should be loaded")
+			},
+			None => {
+				bdk_wallet::Wallet::create_single(desc)
+					.network(network)
+					.lookahead(gap_limit)
+					.create_wallet_no_persist()?
+			},
+		})
+	}
+
+	/// Create a new aspd in the given datadir.
+	///
+	/// The datadir is either created or has to be empty. If creation fails,
+	/// nothing is left behind, so it can simply be retried.
 	pub async fn create(datadir: &Path, config: Config) -> anyhow::Result<()> {
+		Self::create_inner(datadir, config, None).await
+	}
+
+	/// Create a new aspd in the given datadir with an existing mnemonic.
+	///
+	/// The onchain wallet is scanned starting from the `birthday` height,
+	/// the height at which the seed was first used. Without a birthday,
+	/// the wallet is scanned from genesis.
+	pub async fn create_with_mnemonic(
+		datadir: &Path,
+		config: Config,
+		mnemonic: bip39::Mnemonic,
+		birthday: Option<u32>,
+	) -> anyhow::Result<()> {
+		Self::create_inner(datadir, config, Some((mnemonic, birthday))).await
+	}
+
+	async fn create_inner(
+		datadir: &Path,
+		config: Config,
+		import: Option<(bip39::Mnemonic, Option<u32>)>,
+	) -> anyhow::Result<()> {
 		info!("Creating aspd server at {}", datadir.display());
 		trace!("Config: {:?}", config);
+		config.validate().context("invalid config")?;
+
+		create_datadir(datadir, |dir| async move {
+			Self::init_datadir(&dir, config, import).await
+		}).await
+	}
 
-		// create dir if not exit, but check that it's empty
-		fs::create_dir_all(&datadir).context("can't create dir")?;
-		if fs::read_dir(&datadir).context("can't read dir")?.next().is_some() {
-			bail!("dir is not empty");
+	/// The initial wallet state with the given block as its first checkpoint.
+	///
+	/// Syncing a wallet with this state starts at the block after the
+	/// checkpoint, so funds confirmed earlier are not found.
+	fn wallet_birthday_changeset(
+		network: Network,
+		seed: &[u8],
+		gap_limit: u32,
+		checkpoint: Option<bdk_wallet::chain::BlockId>,
+	) -> anyhow::Result<bdk_wallet::ChangeSet> {
+		let (_, _, mut wallet) = Self::wallet_from_seed(network, seed, gap_limit, None)
+			.expect("shouldn't fail on empty state");
+		if let Some(block) = checkpoint.filter(|b| b.height > 0) {
+			wallet.insert_checkpoint(block).context("invalid wallet birthday")?;
 		}
+		Ok(wallet.take_staged().expect("new wallet has staged changes"))
+	}
 
+	async fn init_datadir(
+		datadir: &Path,
+		config: Config,
+		import: Option<(bip39::Mnemonic, Option<u32>)>,
+	) -> anyhow::Result<()> {
 		let bitcoind = bdk_bitcoind_rpc::bitcoincore_rpc::Client::new(
 			&config.bitcoind_url,
 			bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(config.bitcoind_cookie.as_str().into()),
 		).context("failed to create bitcoind rpc client")?;
-		let deep_tip = (|| {
-			let tip = bitcoind.get_block_count()?;
-			let deep = tip.saturating_sub(DEEPLY_CONFIRMED);
-			let hash = bitcoind.get_block_hash(deep)?;
-			let header = bitcoind.get_block_header_info(&hash)?;
-			Ok::<_, anyhow::Error>(header)
-		})().context("failed to fetch deep tip from bitcoind")?;
+		config.check_bitcoind_chain(&bitcoind)?;
+		let tip = bitcoind.get_block_count().context("failed to fetch tip from bitcoind")?;
+		let checkpoint = match import {
+			// A fresh seed can't have been used before, so there's no need
+			// to scan any blocks before a deeply confirmed one.
+			None => Some(tip.saturating_sub(DEEPLY_CONFIRMED)),
+			Some((_, Some(height))) => {
+				ensure!(height as u64 <= tip,
+					"wallet birthday {} is beyond the chain tip {}", height, tip,
+				);
+				// The funds may be confirmed in the birthday block itself,
+				// so the sync has to start there.
+				Some((height as u64).saturating_sub(1))
+			},
+			Some((_, None)) => {
+				warn!("No wallet birthday given, the onchain wallet will be scanned from genesis");
+				None
+			},
+		};
+		let checkpoint = checkpoint.map(|height| {
+			let hash = bitcoind.get_block_hash(height)?;
+			Ok::<_, anyhow::Error>(bdk_wallet::chain::BlockId { height: height as u32, hash })
+		}).transpose().context("failed to fetch birthday block from bitcoind")?;
 
 		// write the config to disk
 		let config_str = serde_json::to_string_pretty(&config)
@@ -253,19 +1309,18 @@ impl App {
 		let db = database::Db::open(&db_path).context("failed to open db")?;
 
 		// Initiate key material.
-		let mnemonic = bip39::Mnemonic::generate(12).expect("12 is valid");
+		let mnemonic = match import {
+			Some((mnemonic, _)) => mnemonic,
+			None => bip39::Mnemonic::generate(12).expect("12 is valid"),
+		};
 		db.store_master_mnemonic_and_seed(&mnemonic)
 			.context("failed to store mnemonic")?;
 
 		// Store initial wallet state to avoid full chain sync.
 		let seed = mnemonic.to_seed("");
-		let (_, _, mut wallet) = Self::wallet_from_seed(config.network, &seed, None)
-			.expect("shouldn't fail on empty state");
-		wallet.insert_checkpoint(bdk_wallet::chain::BlockId {
-			height: deep_tip.height as u32,
-			hash: deep_tip.hash,
-		}).expect("should work, might fail if tip is genesis");
-		let cs = wallet.take_staged().expect("should have stored tip");
+		let cs = Self::wallet_birthday_changeset(
+			config.network, &seed, config.wallet_gap_limit, checkpoint,
+		)?;
 		ensure!(db.read_aggregate_changeset().await.context("db error")?.is_none(), "db not empty");
 		db.store_changeset(&cs).await.context("error storing initial wallet state")?;
 
@@ -273,34 +1328,97 @@ impl App {
 	}
 
 	pub async fn open(datadir: &Path) -> anyhow::Result<Arc<Self>> {
+		Self::open_inner(datadir, None).await
+	}
+
+	/// Open the ASP without its seed, using only the public [DescriptorBackup].
+	///
+	/// This is a recovery mode for operators that lost their seed. aspd can
+	/// sync its wallet and serve information about existing rounds, but it
+	/// can't run rounds, cosign onboards or OOR payments or sweep expired
+	/// rounds.
+	pub async fn open_descriptor_only(
+		datadir: &Path,
+		backup: DescriptorBackup,
+	) -> anyhow::Result<Arc<Self>> {
+		Self::open_inner(datadir, Some(backup)).await
+	}
+
+	async fn open_inner(
+		datadir: &Path,
+		descriptor_only: Option<DescriptorBackup>,
+	) -> anyhow::Result<Arc<Self>> {
 		info!("Starting aspd at {}", datadir.display());
 
-		let config = Config::read_from_datadir(datadir)?;
+		let mut config = Config::upgrade_in_datadir(datadir)?;
+		config.apply_env_overrides().context("invalid config from environment")?;
 		trace!("Config: {:?}", config);
+		config.validate().context("invalid config")?;
 
 		let db_path = datadir.join("aspd_db");
 		info!("Loading db at {}", db_path.display());
 		let db = database::Db::open(&db_path).context("failed to open db")?;
 
-		let seed = db.get_master_seed()
-			.context("db error")?
-			.context("db doesn't contain seed")?;
 		let init = db.read_aggregate_changeset().await?;
-		let (master_key, xpriv, wallet) = Self::wallet_from_seed(config.network, &seed, init)
-			.context("error loading wallet")?;
+		let (master_key, xpriv, asp_pubkey, wallet) = if let Some(backup) = descriptor_only {
+			warn!("Running in descriptor-only mode: aspd can't sign sweeps, rounds, \
+				onboards or OOR payments");
+			if init.is_none() {
+				warn!("No wallet state found in the db, the onchain wallet will be empty");
+			}
+			let wallet = Self::wallet_from_descriptor(
+				config.network, &backup.descriptor, config.wallet_gap_limit, init,
+			).context("error loading wallet from descriptor")?;
+			(None, None, backup.asp_pubkey, wallet)
+		} else {
+			let seed = db.get_master_seed()
+				.context("db error")?
+				.context("db doesn't contain seed")?;
+			let (master_key, xpriv, wallet) = Self::wallet_from_seed(
+				config.network, &seed, config.wallet_gap_limit, init,
+			)
+				.context("error loading wallet")?;
+			(Some(master_key), Some(xpriv), master_key.public_key(), wallet)
+		};
 
 		let bitcoind = bdk_bitcoind_rpc::bitcoincore_rpc::Client::new(
 			&config.bitcoind_url,
 			bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(config.bitcoind_cookie.as_str().into()),
 		).context("failed to create bitcoind rpc client")?;
+		config.check_bitcoind_chain(&bitcoind)?;
+		let chain_source = config.chain_source().connect()?;
+		if let Some(ref url) = config.esplora_url {
+			info!("Syncing onchain wallet from esplora at {}", url);
+		}
+		if config.signet_challenge.is_some() {
+			info!("Running on custom signet with network magic {}", config.network_magic());
+		}
+
+		let events = match config.event_sink {
+			Some(ref cfg) => {
+				info!("Writing events to event sink {}", cfg);
+				Some(EventSink::open(cfg).await.context("failed to open event sink")?)
+			},
+			None => None,
+		};
+		let onboard_nonces = config.onboard_nonce_pool_size.map(NoncePool::new);
 
 		Ok(Arc::new(App {
 			config,
 			db,
 			master_xpriv: xpriv,
 			master_key,
+			signer: master_key.map(|k| Box::new(KeypairSigner::new(k)) as Box<dyn Signer>),
+			asp_pubkey,
 			wallet: Mutex::new(wallet),
 			bitcoind,
+			chain_source,
+			events,
+			round_metrics: RoundMetrics::new(),
+			unsweepable_rounds: std::sync::Mutex::new(HashSet::new()),
+			sweep_lock: Mutex::new(()),
+			onboard_nonces,
+			shutdown: ShutdownHandle(Arc::new(watch::channel(false).0)),
 			rounds: None,
 			sendpay_updates: None
 		}))
@@ -314,25 +1432,64 @@ impl App {
 		let (round_trigger_tx, round_trigger_rx) = tokio::sync::mpsc::channel(1);
 		let (sendpay_tx, sendpay_rx) = broadcast::channel(1024);
 
-		mut_self.rounds = Some(RoundHandle { round_event_tx, round_input_tx, round_trigger_tx });
+		// Without keys we can't run rounds, so don't expose a round handle.
+		if mut_self.master_key.is_some() {
+			mut_self.rounds = Some(RoundHandle {
+				round_event_tx,
+				round_input_tx,
+				round_trigger_tx,
+				proposed_connectors: Mutex::new(None),
+				round_epoch: AtomicU64::new(0),
+				round_full: AtomicBool::new(false),
+				next_round_start: AtomicU64::new(0),
+			});
+		}
 		mut_self.sendpay_updates = Some(SendpayHandle{ sendpay_rx });
 
+		// A SIGTERM requests a graceful shutdown.
+		let mut terminate = signal(SignalKind::terminate()).context("failed to listen for SIGTERM")?;
 		let app = self.clone();
-		let jh_rpc_public = tokio::spawn(async move {
-			rpcserver::run_public_rpc_server(app.clone())
-				.await.context("error running public gRPC server")
+		tokio::spawn(async move {
+			if terminate.recv().await.is_some() {
+				info!("Received SIGTERM");
+				app.shutdown();
+			}
 		});
 
 		let app = self.clone();
-		let jh_round_coord = tokio::spawn(async move {
-			round::run_round_coordinator(app.clone(), round_input_rx, round_trigger_rx)
-				.await.context("error from round scheduler")
+		let jh_rpc_public = tokio::spawn(async move {
+			rpcserver::run_public_rpc_server(app.clone())
+				.await.context("error running public gRPC server")
 		});
 
 		// The tasks that always run
-		let mut jhs = vec![jh_rpc_public, jh_round_coord];
+		let mut jhs = vec![jh_rpc_public];
 
-		// These tasks do only run if the config is provided
+		if let (Some(pool), Some(key)) = (&self.onboard_nonces, self.master_key) {
+			let nb = pool.refill(&key);
+			info!("Pre-generated {} onboard cosign nonces", nb);
+			let app = self.clone();
+			let jh_nonce_pool = tokio::spawn(async move {
+				let pool = app.onboard_nonces.as_ref().expect("checked above");
+				tokio::select! {
+					() = pool.run_refill(&key) => {},
+					() = app.shutdown_signal() => {},
+				}
+				Ok(())
+			});
+			jhs.push(jh_nonce_pool);
+		}
+
+		if self.master_key.is_some() {
+			let app = self.clone();
+			let jh_round_coord = tokio::spawn(async move {
+				round::run_round_coordinator(app.clone(), round_input_rx, round_trigger_rx)
+					.await.context("error from round scheduler")
+			});
+			jhs.push(jh_round_coord);
+		}
+
+		// These tasks do only run if the config is provided
 		if self.config.admin_rpc_address.is_some() {
 			let app = self.clone();
 			let jh_rpc_admin = tokio::spawn(async move {
@@ -344,76 +1501,165 @@ impl App {
 
 		if self.config.cln_config.is_some() {
 			let cln_config = self.config.cln_config.clone().unwrap();
+			let app = self.clone();
 			let jh_sendpay = tokio::spawn(async move {
-				lightning::run_process_sendpay_updates(&cln_config, sendpay_tx)
-					.await.context("error processing sendpays")
+				tokio::select! {
+					res = lightning::run_process_sendpay_updates(&cln_config, sendpay_tx) => {
+						res.context("error processing sendpays")
+					},
+					() = app.shutdown_signal() => Ok(()),
+				}
 			});
 			jhs.push(jh_sendpay)
 		}
 
-		// Wait until the first task finishes
+		// Wait until all tasks finished, or the first one errors
 		futures::future::try_join_all(jhs).await
 			.context("one of our background processes errored")?;
+		info!("aspd shut down");
 		Ok(())
 	}
 
+	/// Request a graceful shutdown.
+	///
+	/// The RPC servers stop accepting new requests, the round in progress
+	/// is finished and then [App::start] returns.
+	pub fn shutdown(&self) {
+		self.shutdown.shutdown();
+	}
+
+	/// A handle to request a shutdown after the app was started.
+	pub fn shutdown_handle(&self) -> ShutdownHandle {
+		self.shutdown.clone()
+	}
+
+	/// Resolves once a shutdown was requested.
+	async fn shutdown_signal(&self) {
+		let mut rx = self.shutdown.0.subscribe();
+		let _ = rx.wait_for(|shutdown| *shutdown).await;
+	}
+
 	pub fn try_rounds(&self) -> anyhow::Result<&RoundHandle> {
+		if self.master_key.is_none() {
+			bail!("aspd is running in descriptor-only mode and doesn't hold rounds");
+		}
 		self.rounds.as_ref().context("no round scheduler started yet")
 	}
 
+	/// The ASP's master keypair, errors when running in descriptor-only mode.
+	fn master_key(&self) -> anyhow::Result<&Keypair> {
+		self.master_key.as_ref().context("aspd is running in descriptor-only mode and can't sign")
+	}
+
+	/// The signer for the master key, errors when running in descriptor-only mode.
+	fn signer(&self) -> anyhow::Result<&dyn Signer> {
+		self.signer.as_deref().context("aspd is running in descriptor-only mode and can't sign")
+	}
+
+	/// Sign round utxo inputs and onboards with the given signer instead of
+	/// the in-memory master key, f.e. to use a key kept in an HSM.
+	///
+	/// All other signing still uses the in-memory master key, so this can't
+	/// be used in descriptor-only mode. Has to be called before [App::start].
+	pub fn set_signer(self: &mut Arc<Self>, signer: Box<dyn Signer>) -> anyhow::Result<()> {
+		let mut_self = Arc::get_mut(self).context("can only set signer if we are unique Arc")?;
+		ensure!(mut_self.signer.is_some(), "aspd is running in descriptor-only mode");
+		ensure!(signer.pubkey() == mut_self.asp_pubkey,
+			"signer key {} doesn't match the ASP pubkey {}", signer.pubkey(), mut_self.asp_pubkey,
+		);
+		mut_self.signer = Some(signer);
+		Ok(())
+	}
+
+	/// The public key material needed to start in descriptor-only mode.
+	pub fn descriptor_backup(&self) -> anyhow::Result<DescriptorBackup> {
+		let seed = self.db.get_master_seed()
+			.context("db error")?
+			.context("db doesn't contain seed")?;
+		Ok(Self::descriptor_backup_from_seed(self.config.network, &seed))
+	}
+
 	pub fn rounds(&self) -> &RoundHandle {
 		self.try_rounds().expect("should only call this in round scheduler code")
 	}
 
-	pub async fn onchain_address(&self) -> anyhow::Result<Address> {
-		let mut wallet = self.wallet.lock().await;
-		let ret = wallet.next_unused_address(bdk_wallet::KeychainKind::Internal).address;
-		// should always return the same address
-		debug_assert_eq!(ret, wallet.next_unused_address(bdk_wallet::KeychainKind::Internal).address);
-		Ok(ret)
+	/// Emit an event to the event sink, if one is configured.
+	fn emit_event(&self, event: Event) {
+		if let Some(ref sink) = self.events {
+			sink.emit(&event);
+		}
 	}
 
-	pub async fn sync_onchain_wallet(&self) -> anyhow::Result<Amount> {
+	/// The public descriptor of our onchain wallet.
+	///
+	/// This only contains xpubs, so it's safe to share for watch-only use.
+	/// The wallet has a single descriptor for both receive and change.
+	pub async fn wallet_descriptor(&self) -> String {
+		let wallet = self.wallet.lock().await;
+		wallet.public_descriptor(bdk_wallet::KeychainKind::External).to_string()
+	}
+
+	/// Get an address to fund our wallet.
+	///
+	/// If [Config::wallet_rotate_addresses] is set, this reveals a new address
+	/// on every call, otherwise the first unused address is returned.
+	pub async fn onchain_address(&self) -> anyhow::Result<Address> {
 		let mut wallet = self.wallet.lock().await;
-		let prev_tip = wallet.latest_checkpoint();
-		// let keychain_spks = self.wallet.spks_of_all_keychains();
-
-		debug!("Starting onchain sync at block height {}", prev_tip.height());
-		let mut emitter = bdk_bitcoind_rpc::Emitter::new(&self.bitcoind, prev_tip.clone(), prev_tip.height());
-		while let Some(em) = emitter.next_block()? {
-			wallet.apply_block_connected_to(&em.block, em.block_height(), em.connected_to())?;
-
-			if em.block_height() % 10_000 == 0 {
-				debug!("Synced until block {}, committing...", em.block_height());
-				if let Some(change) = wallet.take_staged() {
-					self.db.store_changeset(&change).await?;
-				}
+		if self.config.wallet_rotate_addresses {
+			let ret = wallet.reveal_next_address(bdk_wallet::KeychainKind::Internal).address;
+			if let Some(change) = wallet.take_staged() {
+				self.db.store_changeset(&change).await?;
 			}
+			Ok(ret)
+		} else {
+			let ret = wallet.next_unused_address(bdk_wallet::KeychainKind::Internal).address;
+			// should always return the same address
+			debug_assert_eq!(ret, wallet.next_unused_address(bdk_wallet::KeychainKind::Internal).address);
+			Ok(ret)
 		}
+	}
 
-		// mempool
-		let mempool = emitter.mempool()?;
-		wallet.apply_unconfirmed_txs(mempool.iter().map(|(tx, time)| (tx.clone(), *time)));
-		if let Some(change) = wallet.take_staged() {
-			self.db.store_changeset(&change).await?;
-		}
+	pub async fn sync_onchain_wallet(&self) -> anyhow::Result<Amount> {
+		let mut wallet = self.wallet.lock().await;
+		self.chain_source.sync_wallet(&mut wallet, &self.db).await?;
 
 		// rebroadcast unconfirmed txs
 		// NB during some round failures we commit a tx but fail to broadcast it,
 		// so this ensures we still broadcast them afterwards
-		for tx in wallet.transactions() {
-			if !tx.chain_position.is_confirmed() {
-				let bc = self.bitcoind.send_raw_transaction(&*tx.tx_node.tx);
-				if let Err(e) = bc {
-					warn!("Error broadcasting pending tx: {}", e);
-				}
-			}
-		}
+		let unconfirmed = wallet.transactions()
+			.filter(|tx| !tx.chain_position.is_confirmed())
+			.map(|tx| (*tx.tx_node.tx).clone())
+			.collect::<Vec<_>>();
+		chain::rebroadcast_txs(&*self.chain_source, unconfirmed).await;
 
 		let balance = wallet.balance();
+		self.emit_event(Event::WalletSynced {
+			height: wallet.latest_checkpoint().height(),
+			balance: balance.total(),
+		});
 		Ok(balance.total())
 	}
 
+	/// The total value of our own vtxos in rounds that we didn't sweep yet.
+	///
+	/// These hold our round change if we keep it in vtxos, see
+	/// [Config::round_change]. Their value returns to the onchain wallet
+	/// when we sweep the rounds after they expire.
+	pub fn round_change_vtxo_balance(&self) -> anyhow::Result<Amount> {
+		let mut ret = Amount::ZERO;
+		for round_txid in self.db.get_fresh_round_ids(0)? {
+			let round = match self.db.get_round(round_txid)? {
+				Some(r) => r,
+				None => continue,
+			};
+			ret += round.signed_tree.spec.iter_vtxos()
+				.filter(|v| v.pubkey == self.asp_pubkey)
+				.map(|v| v.amount)
+				.sum::<Amount>();
+		}
+		Ok(ret)
+	}
+
 	pub async fn drain(
 		&self,
 		address: Address<bitcoin::address::NetworkUnchecked>,
@@ -423,9 +1669,11 @@ impl App {
 		let addr = address.require_network(self.config.network)?;
 
 		let mut wallet = self.wallet.lock().await;
+		let immature = self.immature_coinbase(&wallet);
 		let mut b = wallet.build_tx();
 		b.drain_to(addr.script_pubkey());
 		b.drain_wallet();
+		b.unspendable(immature);
 		let mut psbt = b.finish().context("error building tx")?;
 		let finalized = wallet.sign(&mut psbt, bdk_wallet::SignOptions::default())?;
 		assert!(finalized);
@@ -443,9 +1691,672 @@ impl App {
 		Ok(tx)
 	}
 
-	pub fn cosign_onboard(&self, user_part: ark::onboard::UserPart) -> ark::onboard::AspPart {
+	/// Scan the blocks since the last monitor tip for round tx confirmations.
+	///
+	/// The monitor tip is persisted, so after a restart we continue where we
+	/// left off. On the very first run, or when our tip was reorged out, we
+	/// start [DEEPLY_CONFIRMED] blocks below the tip instead of scanning the
+	/// whole chain.
+	pub async fn sync_monitor(&self) -> anyhow::Result<()> {
+		let tip = self.bitcoind.get_block_count()? as u32;
+		let mut height = match self.db.get_monitor_tip()? {
+			// If our tip was reorged out, we don't know where the fork is, so
+			// rescan the blocks that could have been affected.
+			Some(t) if t.height > tip || self.bitcoind.get_block_hash(t.height as u64)? != t.hash => {
+				warn!("Chain monitor tip {} at height {} was reorged out", t.hash, t.height);
+				cmp::min(t.height, tip).saturating_sub(DEEPLY_CONFIRMED as u32)
+			},
+			Some(t) => t.height,
+			None => tip.saturating_sub(DEEPLY_CONFIRMED as u32),
+		};
+
+		let fresh_rounds = self.db.get_fresh_round_ids(height)?;
+		let mut pending = HashSet::with_capacity(fresh_rounds.len());
+		for round_txid in fresh_rounds {
+			let round = match self.db.get_round(round_txid)? {
+				Some(r) => r,
+				None => continue,
+			};
+			match round.confirmed_height {
+				// Any confirmation after our starting point might have been reorged out.
+				Some(h) if h > height => {
+					debug!("Round tx {} confirmation at height {} was reorged out", round_txid, h);
+					self.db.set_round_confirmed_height(round_txid, None)?;
+					pending.insert(round_txid);
+				},
+				Some(_) => {},
+				None => { pending.insert(round_txid); },
+			}
+		}
+
+		debug!("Starting chain monitor sync at block height {}", height);
+		while height < tip {
+			height += 1;
+			let hash = self.bitcoind.get_block_hash(height as u64)?;
+			let block = self.bitcoind.get_block(&hash)?;
+			for tx in &block.txdata {
+				let txid = tx.compute_txid();
+				if pending.remove(&txid) {
+					info!("Round tx {} confirmed at height {}", txid, height);
+					self.db.set_round_confirmed_height(txid, Some(height))?;
+					self.emit_event(Event::RoundTxConfirmed { round_txid: txid, height });
+				}
+
+				// A user exiting a vtxo they forfeited creates its output onchain.
+				for vout in 0..tx.output.len() as u32 {
+					let id = VtxoId::from(OutPoint::new(txid, vout));
+					if self.db.get_forfeit_vtxo(id)?.is_some() {
+						warn!("Forfeited vtxo {} was exited unilaterally at height {}",
+							id, height,
+						);
+						self.db.store_claimable_forfeit(id)?;
+					}
+				}
+			}
+			self.db.store_monitor_tip(MonitorTip { height, hash })?;
+		}
+		Ok(())
+	}
+
+	/// The coinbase outputs of our wallet that don't have the confirmations
+	/// of [Config::coinbase_maturity] yet at the tip the wallet is synced to.
+	///
+	/// These must never be used to fund a tx.
+	pub fn immature_coinbase(&self, wallet: &bdk_wallet::Wallet) -> Vec<OutPoint> {
+		let tip = wallet.latest_checkpoint().height();
+		wallet.list_unspent().filter(|utxo| {
+			let coinbase = wallet.get_tx(utxo.outpoint.txid)
+				.map(|tx| tx.tx_node.tx.is_coinbase())
+				.unwrap_or(false);
+			coinbase && match utxo.chain_position {
+				ChainPosition::Confirmed(anchor) => {
+					(tip + 1).saturating_sub(anchor.block_id.height) < self.config.coinbase_maturity
+				},
+				ChainPosition::Unconfirmed(_) => true,
+			}
+		}).map(|utxo| utxo.outpoint).collect()
+	}
+
+	/// The outputs of our wallet that are not confirmed yet.
+	pub fn unconfirmed_utxos(&self, wallet: &bdk_wallet::Wallet) -> Vec<OutPoint> {
+		wallet.list_unspent().filter(|u| !u.chain_position.is_confirmed())
+			.map(|u| u.outpoint).collect()
+	}
+
+	/// The funds of our wallet, see [WalletFunds].
+	pub fn wallet_funds(&self, wallet: &bdk_wallet::Wallet) -> WalletFunds {
+		let immature = self.immature_coinbase(wallet).into_iter().collect::<HashSet<_>>();
+		let mut ret = WalletFunds { spendable: Amount::ZERO, immature: Amount::ZERO };
+		for utxo in wallet.list_unspent() {
+			if immature.contains(&utxo.outpoint) {
+				ret.immature += utxo.txout.value;
+			} else if utxo.chain_position.is_confirmed() {
+				ret.spendable += utxo.txout.value;
+			}
+		}
+		ret
+	}
+
+	/// Apply the [Config::min_feerate] floor to the given fee rate.
+	pub fn floor_feerate(&self, fee_rate: FeeRate) -> FeeRate {
+		cmp::max(fee_rate, self.config.min_feerate)
+	}
+
+	/// Bump the fees of round txs that are stuck in the mempool.
+	///
+	/// A round tx is considered stuck when it has been in the mempool for
+	/// [Config::round_tx_bump_after] blocks. We then spend the output
+	/// selected by the round's fee scheme, see [Config::fee_scheme], with
+	/// a CPFP tx from our wallet at [Config::round_tx_bump_feerate].
+	pub async fn bump_stuck_round_txs(&self) -> anyhow::Result<()> {
+		let tip = self.bitcoind.get_block_count()? as u32;
+		for round_txid in self.db.get_fresh_round_ids(tip)? {
+			let round = self.db.get_round(round_txid)?.expect("db has round");
+			if round.confirmed_height.is_some() {
+				continue;
+			}
+			let anchor = match round.anchor {
+				Some(a) => a,
+				None => continue,
+			};
+
+			// If the tx is not in our mempool, it's either confirmed or we lost it.
+			let entry = match self.bitcoind.get_mempool_entry(&round_txid) {
+				Ok(e) => e,
+				Err(_) => continue,
+			};
+			if (tip as u64) < entry.height + self.config.round_tx_bump_after as u64 {
+				continue;
+			}
+
+			// If the anchor is already spent in the mempool, we bumped before.
+			if self.bitcoind.get_tx_out(&anchor.txid, anchor.vout, Some(true))?.is_none() {
+				trace!("Fee bump output of stuck round tx {} already spent", round_txid);
+				continue;
+			}
+
+			let fee_rate = round_bump_feerate(
+				&*self.chain_source, self.config.round_tx_bump_feerate,
+			).await;
+			let bump = BumpOutput::new(&round.tx, anchor)?;
+			let cpfp = self.create_cpfp(&round.tx, bump, entry.fees.base, fee_rate).await
+				.with_context(|| format!("failed to create cpfp for round tx {}", round_txid))?;
+			info!("Bumping fee of round tx {} with cpfp tx {}", round_txid, cpfp.compute_txid());
+			if let Err(e) = self.bitcoind.send_raw_transaction(&cpfp) {
+				error!("Error broadcasting cpfp tx for round tx {}: {}", round_txid, e);
+			}
+		}
+		Ok(())
+	}
+
+	/// Bump the fee of the unconfirmed round tx with the given txid so that
+	/// the round tx and its bump tx together pay the given fee rate.
+	///
+	/// We can't replace the round tx itself. Any change to its inputs or
+	/// outputs changes its txid, and the vtxo tree, the connector chain and
+	/// the forfeit txs all spend outputs of that txid, so all users of the
+	/// round would have to sign them again. Instead, we replace the CPFP tx
+	/// spending the fee bump output of the round, see [Config::fee_scheme],
+	/// with one paying more fee. The round txid stays the same, so the stored
+	/// round remains valid. If the round tx wasn't bumped before, we create
+	/// its first CPFP tx.
+	pub async fn bump_round_tx_fee(&self, round_txid: Txid, fee_rate: FeeRate) -> anyhow::Result<Txid> {
+		let round = self.db.get_round(round_txid)?
+			.with_context(|| format!("no round with txid {}", round_txid))?;
+		ensure!(round.confirmed_height.is_none(), "round tx {} is already confirmed", round_txid);
+		let anchor = round.anchor.context("round tx has no fee bump output")?;
+		let entry = self.bitcoind.get_mempool_entry(&round_txid)
+			.with_context(|| format!("round tx {} is not in the mempool", round_txid))?;
+
+		let mut prev_cpfp = None;
+		for txid in &entry.spent_by {
+			let tx = self.bitcoind.get_raw_transaction(txid, None)?;
+			if tx.input.iter().any(|i| i.previous_output == anchor) {
+				prev_cpfp = Some(tx);
+				break;
+			}
+		}
+
+		let bump = BumpOutput::new(&round.tx, anchor)?;
+		let cpfp = if let Some(prev) = prev_cpfp {
+			self.replace_cpfp(&round.tx, &prev, bump, entry.fees.base, fee_rate).await
+		} else {
+			self.create_cpfp(&round.tx, bump, entry.fees.base, fee_rate).await
+		}.with_context(|| format!("failed to create cpfp for round tx {}", round_txid))?;
+		let txid = cpfp.compute_txid();
+		info!("Bumping fee of round tx {} with cpfp tx {}", round_txid, txid);
+		self.bitcoind.send_raw_transaction(&cpfp).context("failed to broadcast cpfp tx")?;
+		Ok(txid)
+	}
+
+	/// Claim the forfeited vtxos that the chain monitor saw exited,
+	/// see [App::sync_monitor].
+	pub async fn claim_exited_forfeits(&self) -> anyhow::Result<()> {
+		for id in self.db.get_claimable_forfeits()? {
+			// The vtxo output disappears again once it is spent by either us
+			// or the user, or when its exit was reorged out.
+			let point = id.utxo();
+			if self.bitcoind.get_tx_out(&point.txid, point.vout, Some(true))?.is_none() {
+				debug!("Exited forfeited vtxo {} is gone, no longer claiming it", point);
+				self.db.remove_claimable_forfeit(id)?;
+				continue;
+			}
+
+			info!("Claiming exited forfeited vtxo {}", point);
+			match self.claim_forfeit(point).await {
+				Ok(_) => self.db.remove_claimable_forfeit(id)?,
+				Err(e) => error!("Failed to claim forfeited vtxo {}: {:#}", point, e),
+			}
+		}
+		Ok(())
+	}
+
+	/// Claim the exited forfeited vtxo with the given outpoint.
+	///
+	/// We pick the first connector of the round the vtxo was forfeited in
+	/// that is still available, broadcasting the connector txs leading up
+	/// to it if necessary. The forfeit tx is then bumped using its fee anchor
+	/// at [Config::round_tx_bump_feerate] to make sure it confirms before
+	/// the user's exit timelock expires.
+	pub async fn claim_forfeit(&self, outpoint: OutPoint) -> anyhow::Result<Txid> {
+		let forfeit = self.db.get_forfeit_vtxo(outpoint.into())?
+			.with_context(|| format!("no forfeited vtxo with outpoint {}", outpoint))?;
+		let round_txid = forfeit.round_txid
+			.context("forfeited vtxo was stored without its round txid")?;
+
+		let asp_pubkey = self.asp_pubkey;
+		let connector_utxo = OutPoint::new(round_txid, 1);
+		let chain = ConnectorChain::new(
+			forfeit.forfeit_sigs.len(), connector_utxo, asp_pubkey, forfeit.connector_value,
+		);
+
+		// Each connector but the last one is created by the connector tx
+		// of the same index, so we walk both in lockstep.
+		let mut connector_txs = chain.iter_signed_txs(self.master_key()?);
+		let mut selected = None;
+		for (idx, connector) in chain.connectors().enumerate() {
+			let connector_tx = connector_txs.next();
+			if self.bitcoind.get_tx_out(&connector.txid, connector.vout, Some(true))?.is_some() {
+				selected = Some((idx, connector));
+				break;
+			}
+
+			// If the connector tx is not yet broadcast, its input is still unspent.
+			if let Some(tx) = connector_tx {
+				let prev = tx.input[0].previous_output;
+				if self.bitcoind.get_tx_out(&prev.txid, prev.vout, Some(true))?.is_some() {
+					debug!("Broadcasting connector tx {}", tx.compute_txid());
+					self.bitcoind.send_raw_transaction(&tx)
+						.context("failed to broadcast connector tx")?;
+					selected = Some((idx, connector));
+					break;
+				}
+			}
+		}
+		let (idx, connector) = selected.context("all connectors of the round are spent")?;
+
+		let vtxo = &forfeit.vtxo;
+		let mut tx = ark::forfeit::create_forfeit_tx(vtxo, connector);
+		let prevouts = [
+			TxOut {
+				script_pubkey: vtxo.spec().exit_spk(),
+				value: vtxo.amount(),
+			},
+			TxOut {
+				script_pubkey: ConnectorChain::output_script(asp_pubkey),
+				value: forfeit.connector_value,
+			},
+			ark::fee::dust_anchor(),
+		];
+		let connector_sighash = sighash::SighashCache::new(&tx).taproot_key_spend_signature_hash(
+			1,
+			&sighash::Prevouts::All(&prevouts),
+			sighash::TapSighashType::Default,
+		).expect("all prevouts provided");
+		let connector_sig = SECP.sign_schnorr(&connector_sighash.into(), &self.master_key()?.for_keyspend());
+		tx.input[0].witness = Witness::from_slice(&[&forfeit.forfeit_sigs[idx][..]]);
+		tx.input[1].witness = Witness::from_slice(&[&connector_sig[..]]);
+
+		let txid = tx.compute_txid();
+		let input_value = prevouts.iter().map(|o| o.value).sum::<Amount>();
+		let output_value = tx.output.iter().map(|o| o.value).sum::<Amount>();
+		let anchor = tx.fee_anchor().expect("forfeit tx has fee anchor");
+		let fee_rate = self.config.round_tx_bump_feerate;
+		let bump = BumpOutput::new(&tx, anchor)?;
+		let cpfp = self.create_cpfp(&tx, bump, input_value - output_value, fee_rate).await
+			.with_context(|| format!("failed to create cpfp for forfeit tx {}", txid))?;
+
+		// The forfeit tx doesn't pay any fee itself, so it can only be relayed
+		// together with its cpfp.
+		info!("Broadcasting forfeit tx {} claiming vtxo {} with cpfp tx {}",
+			txid, outpoint, cpfp.compute_txid(),
+		);
+		self.submit_package(&[&tx, &cpfp]).context("failed to broadcast forfeit tx package")?;
+
+		self.emit_event(Event::ForfeitClaimed { vtxo: outpoint, forfeit_txid: txid });
+		Ok(txid)
+	}
+
+	/// Submit the txs as a package to bitcoind, parents first.
+	fn submit_package(&self, txs: &[&Transaction]) -> anyhow::Result<()> {
+		let hex = txs.iter().map(|t| bitcoin::consensus::encode::serialize_hex(*t))
+			.collect::<Vec<_>>();
+		let res = self.bitcoind.call::<serde_json::Value>("submitpackage", &[hex.into()])
+			.context("submitpackage failed")?;
+		let msg = res.get("package_msg").and_then(|m| m.as_str())
+			.context("invalid submitpackage response")?;
+		ensure!(msg == "success", "package rejected: {}: {}", msg, res["tx-results"]);
+		Ok(())
+	}
+
+	/// Create a tx spending the given output of the given tx so that the
+	/// package pays the given fee rate.
+	async fn create_cpfp(
+		&self,
+		tx: &Transaction,
+		bump: BumpOutput,
+		existing_fee: Amount,
+		fee_rate: FeeRate,
+	) -> anyhow::Result<Transaction> {
+		let fee_rate = self.floor_feerate(fee_rate);
+		self.sync_onchain_wallet().await.context("error syncing wallet")?;
+		let mut wallet = self.wallet.lock().await;
+		let drain_spk = wallet.next_unused_address(bdk_wallet::KeychainKind::Internal)
+			.address.script_pubkey();
+		// The child of a TRUC tx has to be TRUC itself.
+		let version = cmp::max(tx.version, bitcoin::transaction::Version::TWO);
+		let mut unspendable = self.immature_coinbase(&wallet);
+		// A TRUC child can't have other unconfirmed parents.
+		if version.0 == 3 {
+			unspendable.extend(self.unconfirmed_utxos(&wallet));
+		}
+
+		fn add_bump_input<Cs>(b: &mut bdk_wallet::TxBuilder<Cs>, bump: &BumpOutput) -> anyhow::Result<()>
+		where
+			Cs: bdk_wallet::coin_selection::CoinSelectionAlgorithm,
+		{
+			match bump {
+				BumpOutput::Anchor { point, input, weight } => {
+					b.add_foreign_utxo(*point, input.clone(), *weight)
+						.expect("bdk rejected fee anchor");
+				},
+				BumpOutput::Wallet(point) => {
+					b.add_utxo(*point).context("bump output not in our wallet")?;
+				},
+			}
+			Ok(())
+		}
+		// Since BDK doesn't support adding extra weight for fees, we first
+		// build a template tx to learn the weight of the anchor spend tx.
+		let package_weight = tx.weight();
+		let extra_fee_needed = (fee_rate * package_weight).checked_sub(existing_fee)
+			.context("tx already pays bump feerate")?;
+		let template_weight = {
+			let mut b = wallet.build_tx();
+			b.version(version.0);
+			b.unspendable(unspendable.clone());
+			add_bump_input(&mut b, &bump)?;
+			b.add_recipient(drain_spk.clone(), extra_fee_needed + ark::P2TR_DUST);
+			b.fee_rate(fee_rate);
+			let mut psbt = b.finish().context("error building anchor spend template")?;
+			let opts = bdk_wallet::SignOptions {
+				trust_witness_utxo: true,
+				..Default::default()
+			};
+			let finalized = wallet.sign(&mut psbt, opts)?;
+			assert!(finalized);
+			psbt.extract_tx()?.weight()
+		};
+
+		let total_fee = fee_rate * (package_weight + template_weight);
+		let mut b = wallet.build_tx();
+		b.version(version.0);
+		b.unspendable(unspendable);
+		add_bump_input(&mut b, &bump)?;
+		b.drain_to(drain_spk);
+		b.fee_absolute(total_fee - existing_fee);
+		let mut psbt = b.finish().context("error building anchor spend tx")?;
+		let opts = bdk_wallet::SignOptions {
+			trust_witness_utxo: true,
+			..Default::default()
+		};
+		let finalized = wallet.sign(&mut psbt, opts)?;
+		assert!(finalized);
+		let cpfp = psbt.extract_tx()?;
+		check_truc_policy(&cpfp, true)?;
+		if let Some(change) = wallet.take_staged() {
+			self.db.store_changeset(&change).await?;
+		}
+		Ok(cpfp)
+	}
+
+	/// Create a tx replacing the given CPFP tx of the given tx so that the
+	/// package pays the given fee rate.
+	///
+	/// The replacement spends the same inputs and takes the extra fee from
+	/// the change. As BIP125 requires, it pays at least the fee of the
+	/// replaced tx plus the incremental relay fee for its own size.
+	async fn replace_cpfp(
+		&self,
+		tx: &Transaction,
+		prev_cpfp: &Transaction,
+		bump: BumpOutput,
+		existing_fee: Amount,
+		fee_rate: FeeRate,
+	) -> anyhow::Result<Transaction> {
+		let fee_rate = self.floor_feerate(fee_rate);
+		self.sync_onchain_wallet().await.context("error syncing wallet")?;
+		let prev_txid = prev_cpfp.compute_txid();
+		let prev_fee = self.bitcoind.get_mempool_entry(&prev_txid)
+			.with_context(|| format!("cpfp tx {} is not in the mempool", prev_txid))?
+			.fees.base;
+
+		// The replacement has the same inputs and outputs, so roughly the same weight.
+		let fee = (fee_rate * (tx.weight() + prev_cpfp.weight())).checked_sub(existing_fee)
+			.context("tx already pays bump feerate")?;
+		// We assume bitcoind's default incremental relay feerate.
+		let min_fee = prev_fee + FeeRate::BROADCAST_MIN * prev_cpfp.weight();
+		ensure!(fee >= min_fee,
+			"replacing cpfp tx {} requires a fee of at least {}, feerate only gives {}",
+			prev_txid, min_fee, fee,
+		);
+
+		let mut wallet = self.wallet.lock().await;
+		let mut b = wallet.build_fee_bump(prev_txid)
+			.with_context(|| format!("can't replace cpfp tx {}", prev_txid))?;
+		b.fee_absolute(fee);
+		let mut psbt = b.finish().context("error building replacement cpfp tx")?;
+		// BDK doesn't know how to finalize a keyless anchor.
+		if let BumpOutput::Anchor { point, input, .. } = bump {
+			let idx = psbt.unsigned_tx.input.iter().position(|i| i.previous_output == point)
+				.context("replacement doesn't spend the fee anchor")?;
+			psbt.inputs[idx] = input;
+		}
+		let opts = bdk_wallet::SignOptions {
+			trust_witness_utxo: true,
+			..Default::default()
+		};
+		let finalized = wallet.sign(&mut psbt, opts)?;
+		assert!(finalized);
+		let cpfp = psbt.extract_tx()?;
+		check_truc_policy(&cpfp, true)?;
+		if let Some(change) = wallet.take_staged() {
+			self.db.store_changeset(&change).await?;
+		}
+		Ok(cpfp)
+	}
+
+	/// Get the status of the vtxo with the given id.
+	///
+	/// This uses the same database indices as the round scheduler to decide
+	/// whether a vtxo is spent. Vtxos that are not part of a round can only
+	/// be resolved when the vtxo itself is given.
+	pub fn vtxo_status(&self, id: VtxoId, vtxo: Option<&Vtxo>) -> anyhow::Result<VtxoStatus> {
+		if self.db.is_vtxo_spent(id)? {
+			return Ok(VtxoStatus::Forfeited);
+		}
+
+		let tip = self.bitcoind.get_block_count()? as u32;
+		if let Some(round_id) = self.db.get_vtxo_round(id)? {
+			return self.round_vtxo_status(round_id, tip);
+		}
+		match vtxo {
+			Some(vtxo) => {
+				ensure!(vtxo.id() == id, "vtxo doesn't match vtxo id {}", id);
+				self.vtxo_origin_status(vtxo, tip)
+			},
+			None => Ok(VtxoStatus::Unknown),
+		}
+	}
+
+	fn round_vtxo_status(&self, round_id: Txid, tip: u32) -> anyhow::Result<VtxoStatus> {
+		// We only remove rounds once we swept them.
+		let round = match self.db.get_round(round_id)? {
+			Some(r) => r,
+			None => return Ok(VtxoStatus::Swept),
+		};
+		if round.signed_tree.spec.expiry_height <= tip {
+			Ok(VtxoStatus::Expired)
+		} else {
+			Ok(VtxoStatus::Active)
+		}
+	}
+
+	/// The status of an unspent vtxo that is not part of a round.
+	///
+	/// Onboard vtxos live as long as their onboard utxo. The outputs of an
+	/// OOR tx we cosigned live as long as the inputs it spent, so they are
+	/// resolved through those, down to the rounds or onboards they came from.
+	fn vtxo_origin_status(&self, vtxo: &Vtxo, tip: u32) -> anyhow::Result<VtxoStatus> {
+		match vtxo {
+			Vtxo::Round { .. } => match self.db.get_vtxo_round(vtxo.id())? {
+				Some(round_id) => self.round_vtxo_status(round_id, tip),
+				None => Ok(VtxoStatus::Unknown),
+			},
+			Vtxo::Onboard { base, .. } => {
+				let utxo = base.utxo;
+				if self.bitcoind.get_tx_out(&utxo.txid, utxo.vout, Some(true))?.is_none() {
+					Ok(VtxoStatus::Unknown)
+				} else if base.spec.expiry_height <= tip {
+					Ok(VtxoStatus::Expired)
+				} else {
+					Ok(VtxoStatus::Active)
+				}
+			},
+			Vtxo::Oor { inputs, oor_tx: tx, final_point, .. }
+				| Vtxo::Bolt11Change { inputs, htlc_tx: tx, final_point, .. } =>
+			{
+				if tx.compute_txid() != final_point.txid {
+					return Ok(VtxoStatus::Unknown);
+				}
+				let mut ret = VtxoStatus::Active;
+				for input in inputs {
+					let spent_in_tx = tx.input.iter().any(|i| i.previous_output == input.point());
+					// If we didn't see the input being spent, we never cosigned the tx.
+					if !spent_in_tx || !self.db.is_vtxo_spent(input.id())? {
+						return Ok(VtxoStatus::Unknown);
+					}
+					match self.vtxo_origin_status(input, tip)? {
+						VtxoStatus::Unknown => return Ok(VtxoStatus::Unknown),
+						VtxoStatus::Swept => ret = VtxoStatus::Swept,
+						VtxoStatus::Expired if ret == VtxoStatus::Active => ret = VtxoStatus::Expired,
+						_ => {},
+					}
+				}
+				Ok(ret)
+			},
+		}
+	}
+
+	/// All unspent vtxos of the given pubkey in the rounds we still have.
+	///
+	/// Rounds stored before we started indexing them by pubkey are not found.
+	pub fn round_vtxos_for_pubkey(&self, pubkey: PublicKey) -> anyhow::Result<Vec<Vtxo>> {
+		let mut ret = Vec::new();
+		for round_id in self.db.get_pubkey_rounds(pubkey)? {
+			let round = match self.db.get_round(round_id)? {
+				Some(r) => r,
+				None => continue,
+			};
+			let tree = &round.signed_tree;
+			for (leaf_idx, dest) in tree.spec.vtxos.iter().enumerate() {
+				if dest.pubkey != pubkey {
+					continue;
+				}
+				let vtxo = tree.vtxo(leaf_idx).expect("leaf in tree");
+				if !self.db.is_vtxo_spent(vtxo.id())? {
+					ret.push(vtxo);
+				}
+			}
+		}
+		Ok(ret)
+	}
+
+	/// Check that the onboard txs the vtxo builds on have at least
+	/// [Config::onboard_confirmations] confirmations.
+	pub fn check_onboard_confirmations(&self, vtxo: &Vtxo) -> anyhow::Result<()> {
+		let required = self.config.onboard_confirmations;
+		if required == 0 {
+			return Ok(());
+		}
+
+		match vtxo {
+			Vtxo::Onboard { base, .. } => {
+				let utxo = base.utxo;
+				let confirmations = self.bitcoind.get_tx_out(&utxo.txid, utxo.vout, Some(false))?
+					.map(|o| o.confirmations).unwrap_or(0);
+				ensure!(confirmations >= required,
+					"onboard utxo {} has {} confirmations, need {}", utxo, confirmations, required,
+				);
+			},
+			Vtxo::Round { .. } => {},
+			Vtxo::Oor { inputs, .. } | Vtxo::Bolt11Change { inputs, .. } => {
+				for input in inputs {
+					self.check_onboard_confirmations(input)?;
+				}
+			},
+		}
+		Ok(())
+	}
+
+	/// The height at which the chain of refreshes leading to this vtxo started.
+	///
+	/// Onboard vtxos start when their onboard tx confirms, OOR and lightning
+	/// vtxos are as old as their oldest input.
+	pub fn vtxo_origin_height(&self, vtxo: &Vtxo) -> anyhow::Result<u32> {
+		// If we have nothing better, assume the vtxo was created
+		// a full expiry delta before it expires.
+		let fallback = vtxo.spec().expiry_height
+			.saturating_sub(self.config.vtxo_expiry_delta as u32);
+		match vtxo {
+			Vtxo::Onboard { base, .. } => {
+				// An onboard vtxo is created when its onboard tx confirms.
+				let utxo = base.utxo;
+				let confirmations = self.bitcoind.get_tx_out(&utxo.txid, utxo.vout, Some(false))?
+					.map(|o| o.confirmations)
+					.filter(|c| *c > 0);
+				match confirmations {
+					Some(confs) => {
+						let tip = self.bitcoind.get_block_count()? as u32;
+						Ok((tip + 1).saturating_sub(confs))
+					},
+					None => Ok(fallback),
+				}
+			},
+			Vtxo::Round { leaf_idx, .. } => {
+				let round = match self.db.get_vtxo_round(vtxo.id())? {
+					Some(id) => self.db.get_round(id)?,
+					None => None,
+				};
+				Ok(round.and_then(|r| r.vtxo_origin_heights.get(*leaf_idx).copied())
+					.unwrap_or(fallback))
+			},
+			Vtxo::Oor { inputs, .. } | Vtxo::Bolt11Change { inputs, .. } => {
+				let mut ret = fallback;
+				for input in inputs {
+					ret = cmp::min(ret, self.vtxo_origin_height(input)?);
+				}
+				Ok(ret)
+			},
+		}
+	}
+
+	/// Check that a vtxo can be refreshed into a new vtxo expiring at
+	/// `new_expiry` without exceeding [Config::max_vtxo_lifetime_blocks].
+	///
+	/// Returns the origin height of the vtxo.
+	pub fn check_vtxo_lifetime(&self, vtxo: &Vtxo, new_expiry: u32) -> anyhow::Result<u32> {
+		let origin = self.vtxo_origin_height(vtxo)?;
+		if let Some(max) = self.config.max_vtxo_lifetime_blocks {
+			// If the limit doesn't fit a block height, there is no limit.
+			if let Some(limit) = origin.checked_add(max) {
+				ensure!(new_expiry <= limit,
+					"vtxo created at height {} can't live beyond height {}", origin, limit,
+				);
+			}
+		}
+		Ok(origin)
+	}
+
+	pub fn cosign_onboard(
+		&self,
+		user_part: ark::onboard::UserPart,
+	) -> anyhow::Result<ark::onboard::AspPart> {
+		let signer = self.signer().context("can't cosign onboard")?;
+		if self.config.onboard_max_confirmations.is_some() {
+			let utxo = user_part.utxo;
+			let confirmations = self.bitcoind.get_tx_out(&utxo.txid, utxo.vout, Some(false))?
+				.map(|o| o.confirmations);
+			self.config.check_onboard_utxo_depth(utxo, confirmations)?;
+		}
 		info!("Cosigning onboard request for utxo {}", user_part.utxo);
-		ark::onboard::new_asp(&user_part, &self.master_key)
+		let nonce = match self.onboard_nonces {
+			Some(ref pool) => Some(pool.take()?),
+			None => None,
+		};
+		let ret = signer::cosign_onboard(signer, &user_part, nonce)?;
+		self.emit_event(Event::OnboardCosigned { utxo: user_part.utxo });
+		Ok(ret)
 	}
 
 	pub fn cosign_oor(
@@ -453,12 +2364,19 @@ impl App {
 		payment: &ark::oor::OorPayment,
 		user_nonces: &[musig::MusigPubNonce],
 	) -> anyhow::Result<(Vec<musig::MusigPubNonce>, Vec<musig::MusigPartialSignature>)> {
+		if payment.exit_timelock_type != self.config.vtxo_exit_timelock {
+			bail!("OOR outputs should have {} exit timelocks", self.config.vtxo_exit_timelock);
+		}
+		if payment.script_type != self.config.vtxo_script_type {
+			bail!("OOR outputs should have {} scripts", self.config.vtxo_script_type);
+		}
+
 		let ids = payment.inputs.iter().map(|v| v.id()).collect::<Vec<_>>();
 		if let Some(dup) = self.db.atomic_check_mark_oors_cosigned(ids.iter().copied())? {
 			bail!("attempted to double sign OOR for vtxo {}", dup)
 		} else {
 			info!("Cosigning OOR tx {} with inputs: {:?}", payment.txid(), ids);
-			let (nonces, sigs) = payment.sign_asp(&self.master_key, &user_nonces);
+			let (nonces, sigs) = payment.sign_asp(self.master_key()?, &user_nonces);
 			Ok((nonces, sigs))
 		}
 	}
@@ -489,7 +2407,7 @@ impl App {
 		let details = Bolt11Payment {
 			invoice,
 			inputs: input_vtxos,
-			asp_pubkey: self.master_key.public_key(),
+			asp_pubkey: self.asp_pubkey,
 			user_pubkey: user_pk,
 			payment_amount: amount,
 			forwarding_fee: Amount::from_sat(350), //TODO(stevenroose) set fee schedule
@@ -497,6 +2415,8 @@ impl App {
 			htlc_expiry_delta: self.config.htlc_expiry_delta,
 			htlc_expiry: expiry,
 			exit_delta: self.config.vtxo_exit_delta,
+			exit_timelock_type: self.config.vtxo_exit_timelock,
+			script_type: self.config.vtxo_script_type,
 		};
 		if !details.check_amounts() {
 			bail!("invalid amounts");
@@ -504,7 +2424,7 @@ impl App {
 
 		// let's sign the tx
 		let (nonces, part_sigs) = details.sign_asp(
-			&self.master_key,
+			self.master_key()?,
 			user_nonces,
 		);
 
@@ -548,104 +2468,248 @@ impl App {
 		heartbeat_stream.merge(event_stream)
 	}
 
-	/// Returns a set of UTXOs from previous rounds that can be spent.
+	/// Returns the UTXOs from previous rounds that can be spent, in batches
+	/// of at most [Config::sweep_batch_max_inputs] UTXOs.
+	///
+	/// Rounds are only returned once [Config::sweep_grace_blocks] passed
+	/// after their expiry.
 	///
 	/// It fills in the PSBT inputs with the fields required to sign,
 	/// for signing use [sign_round_utxo_inputs].
-	fn spendable_expired_vtxos(&self, height: u32) -> anyhow::Result<Vec<SpendableUtxo>> {
-		let pubkey = self.master_key.public_key();
-
+	fn spendable_expired_vtxos(&self, height: u32) -> anyhow::Result<Vec<Vec<SpendableUtxo>>> {
+		let height = height.saturating_sub(self.config.sweep_grace_blocks);
 		let expired_rounds = self.db.get_expired_rounds(height)?;
-		let mut ret = Vec::with_capacity(2 * expired_rounds.len());
+		let mut rounds = Vec::with_capacity(expired_rounds.len());
 		for round_txid in expired_rounds {
-			let round = self.db.get_round(round_txid)?.expect("db has round");
-
-			// First add the vtxo tree utxo.
-			let (
-				spend_cb, spend_script, spend_lv, spend_merkle,
-			) = round.signed_tree.spec.expiry_scriptspend();
-			let mut psbt_in = psbt::Input {
-				witness_utxo: Some(round.tx.output[0].clone()),
-				sighash_type: Some(sighash::TapSighashType::Default.into()),
-				tap_internal_key: Some(round.signed_tree.spec.cosign_agg_pk),
-				tap_scripts: [(spend_cb, (spend_script, spend_lv))].into_iter().collect(),
-				tap_merkle_root: Some(spend_merkle),
-				non_witness_utxo: None,
-				..Default::default()
+			let round = match self.db.get_round(round_txid)? {
+				Some(r) => r,
+				None => {
+					trace!("Expired round {} was removed in the meantime", round_txid);
+					continue;
+				},
 			};
-			psbt_in.set_round_meta(round_txid, RoundMeta::Vtxo);
-			ret.push(SpendableUtxo {
-				point: OutPoint::new(round_txid, 0),
-				psbt: psbt_in,
-				weight: ark::tree::signed::NODE_SPEND_WEIGHT,
+			let utxos = self.round_sweep_utxos(round_txid, &round).and_then(|utxos| {
+				self.check_sweep_utxos_signable(&utxos)?;
+				Ok(utxos)
 			});
+			match utxos {
+				Ok(utxos) => rounds.push(utxos),
+				Err(e) => {
+					// We check all expired rounds on every sweep, only warn the first time.
+					if self.unsweepable_rounds.lock().unwrap().insert(round_txid) {
+						warn!("Leaving round {} out of the sweeps: {:#}", round_txid, e);
+					} else {
+						trace!("Skipping unsweepable round {}", round_txid);
+					}
+				},
+			}
+		}
 
-			// Then add the connector output.
-			// NB this is safe because we will use SIGHASH_ALL.
-			let mut psbt_in = psbt::Input {
-				witness_utxo: Some(round.tx.output[1].clone()),
-				sighash_type: Some(sighash::TapSighashType::Default.into()),
-				tap_internal_key: Some(pubkey.x_only_public_key().0),
-				non_witness_utxo: None,
+		Ok(sweep_batches(rounds, self.config.sweep_batch_max_inputs))
+	}
+
+	/// The utxos of the round that we can sweep once it expired.
+	///
+	/// Returns nothing if the vtxo tree of the round can't be swept.
+	/// Errors if the utxos don't match the stored round.
+	fn round_sweep_utxos(
+		&self,
+		round_txid: Txid,
+		round: &StoredRound,
+	) -> anyhow::Result<Vec<SpendableUtxo>> {
+		// First add the vtxo tree utxo.
+		let spec = &round.signed_tree.spec;
+		let psbt_in = match vtxo_tree_sweep_input(round_txid, spec, round.tx.output[0].clone()) {
+			Some(i) => i,
+			None => {
+				// We check all expired rounds on every sweep, only warn the first time.
+				if self.unsweepable_rounds.lock().unwrap().insert(round_txid) {
+					warn!("Can't sweep vtxo tree of round {} without expiry clause", round_txid);
+				} else {
+					trace!("Skipping unsweepable round {}", round_txid);
+				}
+				return Ok(Vec::new());
+			},
+		};
+		let vtxo_utxo = SpendableUtxo {
+			point: OutPoint::new(round_txid, 0),
+			psbt: psbt_in,
+			weight: ark::tree::signed::NODE_SPEND_WEIGHT,
+		};
+
+		// Then add the connector output.
+		let psbt_in = connector_sweep_input(round_txid, self.asp_pubkey, round.tx.output[1].clone());
+		let connector_utxo = SpendableUtxo {
+			point: OutPoint::new(round_txid, 1),
+			psbt: psbt_in,
+			weight: ark::connectors::INPUT_WEIGHT,
+		};
+
+		let ret = vec![vtxo_utxo, connector_utxo];
+		check_round_sweep_utxos(
+			round_txid, &round.tx, round.signed_tree.spec.total_required_value(), &ret,
+		).with_context(|| format!("sweep utxos of round {} don't match the round", round_txid))?;
+		Ok(ret)
+	}
+
+	/// Check that the round utxo inputs of the PSBT are well-formed,
+	/// without signing them.
+	///
+	/// Returns every issue found together with the index of its input,
+	/// so nothing is returned when the inputs can be signed.
+	pub fn verify_round_utxo_inputs(&self, psbt: &psbt::Psbt) -> Vec<(usize, RoundInputIssue)> {
+		verify_round_utxo_inputs(psbt, self.asp_pubkey)
+	}
+
+	fn sign_round_utxo_inputs(&self, psbt: &mut psbt::Psbt) -> anyhow::Result<RoundInputSigning> {
+		let signer = self.signer().context("can't sign round utxo inputs")?;
+		sign_round_utxo_inputs(psbt, signer)
+	}
+
+	/// Check that we can sign the given sweep utxos, by signing them in a
+	/// throw-away tx that only spends them.
+	fn check_sweep_utxos_signable(&self, utxos: &[SpendableUtxo]) -> anyhow::Result<()> {
+		let tx = Transaction {
+			version: bitcoin::transaction::Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: utxos.iter().map(|u| TxIn {
+				previous_output: u.point,
+				sequence: Sequence::ZERO,
 				..Default::default()
-			};
-			psbt_in.set_round_meta(round_txid, RoundMeta::Connector);
-			ret.push(SpendableUtxo {
-				point: OutPoint::new(round_txid, 1),
-				psbt: psbt_in,
-				weight: ark::connectors::INPUT_WEIGHT,
-			});
+			}).collect(),
+			output: Vec::new(),
+		};
+		let mut psbt = psbt::Psbt::from_unsigned_tx(tx).expect("tx is unsigned");
+		for (input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+			*input = utxo.psbt.clone();
 		}
+		self.sign_round_utxo_inputs(&mut psbt)?.check()
+	}
 
-		Ok(ret)
+	// ** SOME ADMIN COMMANDS **
+
+	/// Sweep the outputs of the given expired round right away, instead of
+	/// waiting for the next round to include them.
+	///
+	/// The swept funds go to our onchain wallet. Returns the sweep txid.
+	pub async fn sweep_round(&self, round_txid: Txid, fee_rate: FeeRate) -> anyhow::Result<Txid> {
+		let _sweep_lock = self.sweep_lock.lock().await;
+		let round = self.db.get_round(round_txid)?.ok_or_else(|| SweepRejected(format!(
+			"no unswept round with txid {}", round_txid,
+		)))?;
+		let expiry = round.signed_tree.spec.expiry_height;
+		let tip = self.bitcoind.get_block_count()? as u32;
+		if tip < expiry {
+			return Err(SweepRejected(format!(
+				"round {} only expires at height {}, current height is {}", round_txid, expiry, tip,
+			)).into());
+		}
+		let sweepable = expiry.saturating_add(self.config.sweep_grace_blocks);
+		if tip < sweepable {
+			return Err(SweepRejected(format!(
+				"round {} is in its sweep grace period until height {}, current height is {}",
+				round_txid, sweepable, tip,
+			)).into());
+		}
+		let utxos = self.round_sweep_utxos(round_txid, &round)?;
+		if utxos.is_empty() {
+			return Err(SweepRejected(format!("round {} can't be swept", round_txid)).into());
+		}
+
+		self.sync_onchain_wallet().await.context("error syncing wallet")?;
+		self.broadcast_sweep(&utxos, fee_rate, tip).await
 	}
 
-	fn sign_round_utxo_inputs(&self, psbt: &mut psbt::Psbt) -> anyhow::Result<()> {
-		let mut shc = sighash::SighashCache::new(&psbt.unsigned_tx);
-		let prevouts = psbt.inputs.iter()
-			.map(|i| i.witness_utxo.clone().unwrap())
-			.collect::<Vec<_>>();
+	/// Sweep the outputs of all expired rounds right away.
+	///
+	/// The utxos are spread over sweep txs of at most
+	/// [Config::sweep_batch_max_inputs] inputs. Returns the sweep txids.
+	pub async fn sweep_expired_rounds(&self, fee_rate: FeeRate) -> anyhow::Result<Vec<Txid>> {
+		let _sweep_lock = self.sweep_lock.lock().await;
+		let tip = self.bitcoind.get_block_count()? as u32;
+		let batches = self.spendable_expired_vtxos(tip)?;
+		if batches.is_empty() {
+			return Ok(Vec::new());
+		}
 
-		let connector_keypair = self.master_key.for_keyspend();
-		for (idx, input) in psbt.inputs.iter_mut().enumerate() {
-			if let Some((_round, meta)) = input.get_round_meta().context("corrupt psbt")? {
-				match meta {
-					RoundMeta::Vtxo => {
-						let (control, (script, lv)) = input.tap_scripts.iter().next()
-							.context("corrupt psbt: missing tap_scripts")?;
-						let leaf_hash = taproot::TapLeafHash::from_script(script, *lv);
-						let sighash = shc.taproot_script_spend_signature_hash(
-							idx,
-							&sighash::Prevouts::All(&prevouts),
-							leaf_hash,
-							sighash::TapSighashType::Default,
-						).expect("all prevouts provided");
-						trace!("Signing expired VTXO input for sighash {}", sighash);
-						let sig = SECP.sign_schnorr(&sighash.into(), &self.master_key);
-						let wit = Witness::from_slice(
-							&[&sig[..], script.as_bytes(), &control.serialize()],
-						);
-						debug_assert_eq!(wit.size(), ark::tree::signed::NODE_SPEND_WEIGHT.to_wu() as usize);
-						input.final_script_witness = Some(wit);
-					},
-					RoundMeta::Connector => {
-						let sighash = shc.taproot_key_spend_signature_hash(
-							idx,
-							&sighash::Prevouts::All(&prevouts),
-							sighash::TapSighashType::Default,
-						).expect("all prevouts provided");
-						trace!("Signing expired connector input for sighash {}", sighash);
-						let sig = SECP.sign_schnorr(&sighash.into(), &connector_keypair);
-						input.final_script_witness = Some(Witness::from_slice(&[sig[..].to_vec()]));
-					},
+		self.sync_onchain_wallet().await.context("error syncing wallet")?;
+		let mut ret = Vec::with_capacity(batches.len());
+		for utxos in batches {
+			ret.push(self.broadcast_sweep(&utxos, fee_rate, tip).await?);
+		}
+		Ok(ret)
+	}
+
+	/// Build, sign and broadcast a tx sweeping the given round utxos to our
+	/// onchain wallet, and forget about the swept rounds.
+	///
+	/// Rounds with an input we fail to sign are left out of the sweep, so
+	/// that they don't hold back the others. They can be swept once fixed.
+	async fn broadcast_sweep(
+		&self,
+		utxos: &[SpendableUtxo],
+		fee_rate: FeeRate,
+		tip: u32,
+	) -> anyhow::Result<Txid> {
+		let fee_rate = self.floor_feerate(fee_rate);
+		let mut wallet = self.wallet.lock().await;
+		let drain_spk = wallet.next_unused_address(bdk_wallet::KeychainKind::Internal)
+			.address.script_pubkey();
+		let mut excluded = HashSet::new();
+		let (psbt, utxos) = loop {
+			let utxos = utxos.iter().filter(|u| !excluded.contains(&u.point.txid))
+				.collect::<Vec<_>>();
+			ensure!(!utxos.is_empty(), "failed to sign the inputs of all {} rounds", excluded.len());
+			let mut psbt = {
+				let mut b = wallet.build_tx();
+				b.nlocktime(LockTime::from_height(tip).expect("actual height"));
+				for utxo in &utxos {
+					b.add_foreign_utxo_with_sequence(
+						utxo.point, utxo.psbt.clone(), utxo.weight, Sequence::ZERO,
+					).expect("bdk rejected foreign utxo");
 				}
+				// Only spend the round utxos, don't add any of our own.
+				b.manually_selected_only();
+				b.drain_to(drain_spk.clone());
+				b.fee_rate(fee_rate);
+				let mut psbt = b.finish().context("error building sweep tx")?;
+				psbt.set_ext_version();
+				psbt
+			};
+			let signing = self.sign_round_utxo_inputs(&mut psbt).context("signing round inputs")?;
+			if signing.failed.is_empty() {
+				break (psbt, utxos);
+			}
+			for (idx, err) in signing.failed {
+				let round_txid = psbt.unsigned_tx.input[idx].previous_output.txid;
+				error!("Leaving round {} out of the sweep, failed to sign its input: {:#}",
+					round_txid, err,
+				);
+				excluded.insert(round_txid);
 			}
+		};
+		let tx = psbt.extract_tx()?;
+		if let Some(change) = wallet.take_staged() {
+			self.db.store_changeset(&change).await?;
 		}
+		drop(wallet);
 
-		Ok(())
-	}
+		let txid = tx.compute_txid();
+		let rounds = utxos.iter().map(|u| u.point.txid).collect::<HashSet<_>>();
+		info!("Broadcasting sweep tx {} for {} expired rounds", txid, rounds.len());
+		self.bitcoind.send_raw_transaction(&tx).context("failed to broadcast sweep tx")?;
+		for round_txid in &rounds {
+			self.db.remove_round(*round_txid)?;
+		}
 
-	// ** SOME ADMIN COMMANDS **
+		self.emit_event(Event::ExpiredRoundsSwept {
+			sweep_txid: txid,
+			round_txids: rounds.into_iter().collect(),
+			nb_utxos: utxos.len(),
+			total_value: utxos.iter().map(|u| u.amount()).sum(),
+		});
+		Ok(txid)
+	}
 
 	pub fn get_master_mnemonic(&self) -> anyhow::Result<String> {
 		Ok(self.db.get_master_mnemonic()?.expect("app running"))
@@ -656,6 +2720,16 @@ impl App {
 	}
 }
 
+/// The funds of our onchain wallet that are confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalletFunds {
+	/// The funds we can use to fund txs.
+	pub spendable: Amount,
+	/// The funds in coinbase outputs that didn't reach
+	/// [Config::coinbase_maturity] yet.
+	pub immature: Amount,
+}
+
 pub(crate) struct SpendableUtxo {
 	pub point: OutPoint,
 	pub psbt: psbt::Input,
@@ -667,3 +2741,933 @@ impl SpendableUtxo {
 		self.psbt.witness_utxo.as_ref().unwrap().value
 	}
 }
+
+/// Group the sweep utxos of the given rounds in batches of at most
+/// `max_inputs` utxos, keeping the utxos of each round together.
+///
+/// Rounds with more than `max_inputs` utxos are split over multiple batches.
+fn sweep_batches(
+	rounds: impl IntoIterator<Item = Vec<SpendableUtxo>>,
+	max_inputs: usize,
+) -> Vec<Vec<SpendableUtxo>> {
+	assert_ne!(max_inputs, 0);
+	let mut ret = Vec::<Vec<SpendableUtxo>>::new();
+	for utxos in rounds {
+		if utxos.len() > max_inputs {
+			let mut utxos = utxos.into_iter().peekable();
+			while utxos.peek().is_some() {
+				ret.push(utxos.by_ref().take(max_inputs).collect());
+			}
+			continue;
+		}
+		if utxos.is_empty() {
+			continue;
+		}
+		match ret.last_mut() {
+			Some(last) if last.len() + utxos.len() <= max_inputs => last.extend(utxos),
+			_ => ret.push(utxos),
+		}
+	}
+	ret
+}
+
+/// The PSBT input to sweep the vtxo tree output of an expired round.
+///
+/// Returns [None] if the tree has no expiry clause to sweep it with.
+fn vtxo_tree_sweep_input(
+	round_txid: Txid,
+	spec: &ark::tree::signed::VtxoTreeSpec,
+	output: TxOut,
+) -> Option<psbt::Input> {
+	let (spend_cb, spend_script, spend_lv, spend_merkle) = spec.expiry_scriptspend()?;
+	let mut ret = psbt::Input {
+		witness_utxo: Some(output),
+		sighash_type: Some(sighash::TapSighashType::Default.into()),
+		tap_internal_key: Some(spec.cosign_agg_pk),
+		tap_scripts: [(spend_cb, (spend_script, spend_lv))].into_iter().collect(),
+		tap_merkle_root: Some(spend_merkle),
+		non_witness_utxo: None,
+		..Default::default()
+	};
+	ret.set_round_meta(round_txid, RoundMeta::Vtxo);
+	Some(ret)
+}
+
+/// The PSBT input to sweep the connector output of an expired round.
+fn connector_sweep_input(round_txid: Txid, asp_pubkey: PublicKey, output: TxOut) -> psbt::Input {
+	// NB this is safe because we will use SIGHASH_ALL.
+	let mut ret = psbt::Input {
+		witness_utxo: Some(output),
+		sighash_type: Some(sighash::TapSighashType::Default.into()),
+		tap_internal_key: Some(asp_pubkey.x_only_public_key().0),
+		non_witness_utxo: None,
+		..Default::default()
+	};
+	ret.set_round_meta(round_txid, RoundMeta::Connector);
+	ret
+}
+
+/// An inconsistency in a PSBT input spending a round utxo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoundInputIssue {
+	/// Our round meta field doesn't decode.
+	InvalidRoundMeta(String),
+	/// The input doesn't spend an output of the round in its round meta.
+	NotFromRound(Txid),
+	MissingWitnessUtxo,
+	/// The non-witness utxo doesn't contain the witness utxo.
+	NonWitnessUtxoMismatch,
+	MissingInternalKey,
+	/// A vtxo tree input should have exactly one tap script.
+	TapScriptCount(usize),
+	/// The tap script doesn't commit to the output key of the utxo.
+	InvalidTapScript,
+	/// The connector isn't a keyspend output for its internal key.
+	OutputScriptMismatch,
+	/// The connector internal key isn't the ASP key.
+	ForeignConnectorKey,
+	/// The witness we would sign has a different weight than we account for.
+	WitnessWeight {
+		expected: Weight,
+		actual: Weight,
+	},
+}
+
+impl fmt::Display for RoundInputIssue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RoundInputIssue::InvalidRoundMeta(e) => write!(f, "invalid round meta: {}", e),
+			RoundInputIssue::NotFromRound(txid) => {
+				write!(f, "input doesn't spend an output of round {}", txid)
+			},
+			RoundInputIssue::MissingWitnessUtxo => f.write_str("missing witness_utxo"),
+			RoundInputIssue::NonWitnessUtxoMismatch => {
+				f.write_str("non_witness_utxo doesn't match witness_utxo")
+			},
+			RoundInputIssue::MissingInternalKey => f.write_str("missing tap_internal_key"),
+			RoundInputIssue::TapScriptCount(n) => write!(f, "expected one tap script, got {}", n),
+			RoundInputIssue::InvalidTapScript => {
+				f.write_str("tap script doesn't commit to the output key")
+			},
+			RoundInputIssue::OutputScriptMismatch => {
+				f.write_str("output script doesn't match the internal key")
+			},
+			RoundInputIssue::ForeignConnectorKey => f.write_str("connector key isn't ours"),
+			RoundInputIssue::WitnessWeight { expected, actual } => {
+				write!(f, "witness weight is {}, expected {}", actual, expected)
+			},
+		}
+	}
+}
+
+/// The outcome of signing the round utxo inputs of a PSBT.
+#[derive(Debug, Default)]
+pub struct RoundInputSigning {
+	/// The indices of the inputs we signed.
+	pub signed: Vec<usize>,
+	/// The indices of the inputs we failed to sign, with the reason.
+	pub failed: Vec<(usize, anyhow::Error)>,
+}
+
+impl RoundInputSigning {
+	/// Error if any of the round inputs couldn't be signed.
+	pub fn check(&self) -> anyhow::Result<()> {
+		if let Some((idx, err)) = self.failed.first() {
+			bail!("failed to sign {} round inputs, the first one is input {}: {:#}",
+				self.failed.len(), idx, err,
+			);
+		}
+		Ok(())
+	}
+}
+
+/// Sign the inputs of the PSBT that spend round utxos with the signer.
+/// Inputs without round meta are ignored.
+///
+/// A malformed input doesn't stop us from signing the others, the inputs
+/// we couldn't sign are returned with the reason. This only errors if
+/// the PSBT itself is unusable.
+fn sign_round_utxo_inputs(
+	psbt: &mut psbt::Psbt,
+	signer: &dyn Signer,
+) -> anyhow::Result<RoundInputSigning> {
+	let mut shc = sighash::SighashCache::new(&psbt.unsigned_tx);
+	let prevouts = psbt.inputs.iter().enumerate()
+		.map(|(idx, i)| i.witness_utxo.clone().with_context(|| {
+			format!("corrupt psbt: input {} is missing witness_utxo", idx)
+		}))
+		.collect::<anyhow::Result<Vec<_>>>()?;
+
+	let mut ret = RoundInputSigning::default();
+	for (idx, input) in psbt.inputs.iter_mut().enumerate() {
+		let meta = match input.get_round_meta().context("corrupt round meta") {
+			Ok(Some((_round, meta))) => meta,
+			Ok(None) => continue,
+			Err(e) => {
+				ret.failed.push((idx, e));
+				continue;
+			},
+		};
+		match sign_round_utxo_input(&mut shc, &prevouts, idx, input, meta, signer) {
+			Ok(wit) => {
+				input.final_script_witness = Some(wit);
+				ret.signed.push(idx);
+			},
+			Err(e) => ret.failed.push((idx, e)),
+		}
+	}
+
+	Ok(ret)
+}
+
+/// Sign a single round utxo input, returning its final witness.
+fn sign_round_utxo_input(
+	shc: &mut sighash::SighashCache<&Transaction>,
+	prevouts: &[TxOut],
+	idx: usize,
+	input: &psbt::Input,
+	meta: RoundMeta,
+	signer: &dyn Signer,
+) -> anyhow::Result<Witness> {
+	match meta {
+		RoundMeta::Vtxo => {
+			let (control, (script, lv)) = input.tap_scripts.iter().next()
+				.context("corrupt psbt: missing tap_scripts")?;
+			let leaf_hash = taproot::TapLeafHash::from_script(script, *lv);
+			let sighash = shc.taproot_script_spend_signature_hash(
+				idx,
+				&sighash::Prevouts::All(prevouts),
+				leaf_hash,
+				sighash::TapSighashType::Default,
+			).expect("all prevouts provided");
+			trace!("Signing expired VTXO input for sighash {}", sighash);
+			let sig = signer.sign_schnorr(&sighash.into())?;
+			let wit = Witness::from_slice(
+				&[&sig[..], script.as_bytes(), &control.serialize()],
+			);
+			debug_assert_eq!(wit.size(), ark::tree::signed::NODE_SPEND_WEIGHT.to_wu() as usize);
+			Ok(wit)
+		},
+		RoundMeta::Connector => {
+			let sighash = shc.taproot_key_spend_signature_hash(
+				idx,
+				&sighash::Prevouts::All(prevouts),
+				sighash::TapSighashType::Default,
+			).expect("all prevouts provided");
+			trace!("Signing expired connector input for sighash {}", sighash);
+			let sig = signer.sign_schnorr_keyspend(&sighash.into())?;
+			Ok(Witness::from_slice(&[sig[..].to_vec()]))
+		},
+	}
+}
+
+/// Check the inputs of the PSBT that spend round utxos, see
+/// [App::verify_round_utxo_inputs]. Inputs without round meta are ignored.
+fn verify_round_utxo_inputs(
+	psbt: &psbt::Psbt,
+	asp_pubkey: PublicKey,
+) -> Vec<(usize, RoundInputIssue)> {
+	let mut ret = Vec::new();
+	for (idx, (input, txin)) in psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate() {
+		let (round_txid, meta) = match input.get_round_meta() {
+			Ok(Some(m)) => m,
+			Ok(None) => continue,
+			Err(e) => {
+				ret.push((idx, RoundInputIssue::InvalidRoundMeta(e.to_string())));
+				continue;
+			},
+		};
+		let issues = verify_round_utxo_input(input, txin.previous_output, round_txid, meta, asp_pubkey);
+		ret.extend(issues.into_iter().map(|i| (idx, i)));
+	}
+	ret
+}
+
+fn verify_round_utxo_input(
+	input: &psbt::Input,
+	prevout: OutPoint,
+	round_txid: Txid,
+	meta: RoundMeta,
+	asp_pubkey: PublicKey,
+) -> Vec<RoundInputIssue> {
+	let mut ret = Vec::new();
+	if prevout.txid != round_txid {
+		ret.push(RoundInputIssue::NotFromRound(round_txid));
+	}
+	let utxo = match input.witness_utxo {
+		Some(ref u) => u,
+		None => {
+			ret.push(RoundInputIssue::MissingWitnessUtxo);
+			return ret;
+		},
+	};
+	if let Some(ref tx) = input.non_witness_utxo {
+		if tx.compute_txid() != prevout.txid || tx.output.get(prevout.vout as usize) != Some(utxo) {
+			ret.push(RoundInputIssue::NonWitnessUtxoMismatch);
+		}
+	}
+	let internal_key = match input.tap_internal_key {
+		Some(k) => k,
+		None => {
+			ret.push(RoundInputIssue::MissingInternalKey);
+			return ret;
+		},
+	};
+
+	// We fill in a dummy signature to check the witness size.
+	let dummy_sig = [0u8; 64];
+	let (witness, expected_weight) = match meta {
+		RoundMeta::Vtxo => {
+			if input.tap_scripts.len() != 1 {
+				ret.push(RoundInputIssue::TapScriptCount(input.tap_scripts.len()));
+			}
+			let (control, (script, _lv)) = match input.tap_scripts.iter().next() {
+				Some(s) => s,
+				None => return ret,
+			};
+			let output_key = if utxo.script_pubkey.is_p2tr() {
+				secp256k1::XOnlyPublicKey::from_slice(&utxo.script_pubkey.as_bytes()[2..]).ok()
+			} else {
+				None
+			};
+			let committed = output_key.map(|k| control.verify_taproot_commitment(&SECP, k, script));
+			if control.internal_key != internal_key || committed != Some(true) {
+				ret.push(RoundInputIssue::InvalidTapScript);
+			}
+			let witness = Witness::from_slice(
+				&[&dummy_sig[..], script.as_bytes(), &control.serialize()],
+			);
+			(witness, ark::tree::signed::NODE_SPEND_WEIGHT)
+		},
+		RoundMeta::Connector => {
+			if internal_key != asp_pubkey.x_only_public_key().0 {
+				ret.push(RoundInputIssue::ForeignConnectorKey);
+			}
+			if utxo.script_pubkey != ScriptBuf::new_p2tr(&SECP, internal_key, None) {
+				ret.push(RoundInputIssue::OutputScriptMismatch);
+			}
+			(Witness::from_slice(&[&dummy_sig[..]]), ark::connectors::INPUT_WEIGHT)
+		},
+	};
+	let weight = Weight::from_wu(witness.size() as u64);
+	if weight != expected_weight {
+		ret.push(RoundInputIssue::WitnessWeight { expected: expected_weight, actual: weight });
+	}
+	ret
+}
+
+/// Check that the sweep utxos of a round spend exactly the vtxo tree and
+/// connector outputs of its round tx.
+fn check_round_sweep_utxos(
+	round_txid: Txid,
+	round_tx: &Transaction,
+	vtxo_tree_value: Amount,
+	utxos: &[SpendableUtxo],
+) -> anyhow::Result<()> {
+	ensure!(round_tx.compute_txid() == round_txid,
+		"stored round tx has txid {}", round_tx.compute_txid(),
+	);
+	let tree_output = round_tx.output.first().context("round tx has no outputs")?;
+	ensure!(tree_output.value == vtxo_tree_value,
+		"vtxo tree output has value {}, but the tree requires {}", tree_output.value, vtxo_tree_value,
+	);
+
+	let mut total = Amount::ZERO;
+	for utxo in utxos {
+		ensure!(utxo.point.txid == round_txid, "utxo {} is not from the round tx", utxo.point);
+		let output = round_tx.output.get(utxo.point.vout as usize)
+			.with_context(|| format!("round tx has no output {}", utxo.point.vout))?;
+		ensure!(utxo.psbt.witness_utxo.as_ref() == Some(output),
+			"utxo {} has value {}, but the round tx output has {}",
+			utxo.point, utxo.amount(), output.value,
+		);
+		total += utxo.amount();
+	}
+	let expected = round_tx.output.iter().take(2).map(|o| o.value).sum::<Amount>();
+	ensure!(total == expected,
+		"sweep utxos have total value {}, but the round tree and connectors hold {}", total, expected,
+	);
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+		vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+	}
+
+	#[tokio::test]
+	async fn bump_feerate_follows_estimate() {
+		let configured = FeeRate::from_sat_per_vb(25).unwrap();
+		let mut chain = chain::test::MockChainSource::default();
+		assert_eq!(round_bump_feerate(&chain, configured).await, configured);
+
+		chain.fee_estimates.insert(1, FeeRate::from_sat_per_vb(10).unwrap());
+		assert_eq!(round_bump_feerate(&chain, configured).await, configured);
+
+		let high = FeeRate::from_sat_per_vb(60).unwrap();
+		chain.fee_estimates.insert(1, high);
+		assert_eq!(round_bump_feerate(&chain, configured).await, high);
+	}
+
+	#[tokio::test]
+	async fn failed_create_can_be_retried() {
+		let dir = std::env::temp_dir().join(format!("aspd-create-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+
+		// Fail after we wrote the config, but before we stored the seed.
+		let err = create_datadir(&dir, |d| async move {
+			fs::write(d.join("config.json"), "{}")?;
+			bail!("failed to store mnemonic");
+		}).await.unwrap_err();
+		assert_eq!(err.to_string(), "failed to store mnemonic");
+		assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+		assert!(!tmp_datadir(&dir).unwrap().exists());
+
+		create_datadir(&dir, |d| async move {
+			fs::write(d.join("config.json"), "{}")?;
+			Ok::<_, anyhow::Error>(())
+		}).await.unwrap();
+		assert!(dir.join("config.json").exists());
+		assert!(!tmp_datadir(&dir).unwrap().exists());
+
+		// We never touch an existing datadir.
+		let err = create_datadir(&dir, |_| async { Ok(()) }).await.unwrap_err();
+		assert!(err.to_string().starts_with("dir is not empty"), "{}", err);
+		assert!(dir.join("config.json").exists());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn config_env_overrides() {
+		let mut cfg = Config::default();
+		cfg.apply_overrides(vars(&[
+			("ARKD_BITCOIND_URL", "http://bitcoind:8332"),
+			("ARKD_ESPLORA_URL", "http://esplora:3000"),
+			("ARKD_PUBLIC_RPC_ADDRESS", "127.0.0.1:4000"),
+			("ARKD_ADMIN_RPC_ADDRESS", ""),
+			("ARKD_ROUND_INTERVAL", "5000"),
+			("ARKD_ROUND_SIGN_TIME", "2s"),
+			("ARKD_SWEEP_INTERVAL", "1h"),
+			("ARKD_MAX_ONBOARD_VALUE", "100000"),
+			("OTHER_VAR", "ignored"),
+		])).unwrap();
+		assert_eq!(cfg.bitcoind_url, "http://bitcoind:8332");
+		assert_eq!(cfg.esplora_url.as_deref(), Some("http://esplora:3000"));
+		assert_eq!(cfg.public_rpc_address, "127.0.0.1:4000".parse().unwrap());
+		assert_eq!(cfg.admin_rpc_address, None);
+		assert_eq!(cfg.round_interval, Duration::from_secs(5));
+		assert_eq!(cfg.round_sign_time, Duration::from_secs(2));
+		assert_eq!(cfg.sweep_interval, Duration::from_secs(60 * 60));
+		assert_eq!(cfg.max_onboard_value, Some(Amount::from_sat(100_000)));
+	}
+
+	#[test]
+	fn config_env_overrides_invalid() {
+		let mut cfg = Config::default();
+		cfg.apply_overrides(vars(&[("ARKD_UNKNOWN_FIELD", "1")])).unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_VTXO_EXIT_DELTA", "-1")])).unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_SUBMIT_TIME", "10 days")])).unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_CLN_GRPC_URI", "http://localhost:1313")])).unwrap_err();
+	}
+
+	#[test]
+	fn config_round_tx_version() {
+		let mut cfg = Config::default();
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_TX_VERSION", "1")])).unwrap();
+		cfg.validate().unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_TX_VERSION", "3")])).unwrap();
+		cfg.validate().unwrap();
+	}
+
+	#[test]
+	fn config_fee_scheme() {
+		let mut cfg = Config::default();
+		assert_eq!(cfg.fee_scheme, RoundFeeScheme::KeylessAnchor);
+		cfg.apply_overrides(vars(&[("ARKD_FEE_SCHEME", "p2a_anchor")])).unwrap();
+		assert_eq!(cfg.fee_scheme, RoundFeeScheme::P2aAnchor);
+		// The old name is still accepted.
+		cfg.apply_overrides(vars(&[("ARKD_FEE_SCHEME", "ephemeral_anchor")])).unwrap();
+		assert_eq!(cfg.fee_scheme, RoundFeeScheme::P2aAnchor);
+		// P2A anchors need v3 round txs.
+		cfg.validate().unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_TX_VERSION", "3")])).unwrap();
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_FEE_SCHEME", "cpfp_change")])).unwrap();
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_FEE_SCHEME", "magic")])).unwrap_err();
+	}
+
+	#[test]
+	fn config_round_change() {
+		let mut cfg = Config::default();
+		assert_eq!(cfg.round_change, RoundChange::Onchain);
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_CHANGE", "vtxo")])).unwrap();
+		assert_eq!(cfg.round_change, RoundChange::Vtxo);
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_CHANGE_VTXO_MAX", "100")])).unwrap();
+		assert_eq!(cfg.round_change_vtxo_max, Amount::from_sat(100));
+		cfg.validate().unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_CHANGE_VTXO_MAX", "50000")])).unwrap();
+		cfg.validate().unwrap();
+		// Without change outputs, there's nothing to bump with.
+		cfg.apply_overrides(vars(&[("ARKD_FEE_SCHEME", "cpfp_change")])).unwrap();
+		cfg.validate().unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_CHANGE", "onchain")])).unwrap();
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_CHANGE", "lightning")])).unwrap_err();
+	}
+
+	#[test]
+	fn config_connector_value() {
+		let mut cfg = Config::default();
+		cfg.validate().unwrap();
+		// Dust connectors can't pay for their own input.
+		cfg.apply_overrides(vars(&[("ARKD_CONNECTOR_VALUE", "330")])).unwrap();
+		assert_eq!(cfg.connector_value, ark::fee::DUST);
+		cfg.validate().unwrap_err();
+		// 66 WU at 25 sat/vb is 412 sat
+		cfg.apply_overrides(vars(&[("ARKD_CONNECTOR_VALUE", "742")])).unwrap();
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_CONNECTOR_VALUE", "741")])).unwrap();
+		cfg.validate().unwrap_err();
+		// A lower bump feerate allows for smaller connectors.
+		cfg.apply_overrides(vars(&[("ARKD_ROUND_TX_BUMP_FEERATE", "10000")])).unwrap();
+		cfg.validate().unwrap();
+	}
+
+	#[test]
+	fn config_sweep_grace_blocks() {
+		let mut cfg = Config::default();
+		cfg.apply_overrides(vars(&[("ARKD_SWEEP_GRACE_BLOCKS", "52560")])).unwrap();
+		cfg.validate().unwrap();
+		cfg.apply_overrides(vars(&[("ARKD_SWEEP_GRACE_BLOCKS", "4294967295")])).unwrap();
+		cfg.validate().unwrap_err();
+	}
+
+	#[test]
+	fn config_onboard_max_confirmations() {
+		let utxo = OutPoint::null();
+		let mut cfg = Config::default();
+		// Without a maximum, any depth goes.
+		cfg.check_onboard_utxo_depth(utxo, Some(100_000)).unwrap();
+
+		cfg.apply_overrides(vars(&[
+			("ARKD_ONBOARD_CONFIRMATIONS", "3"),
+			("ARKD_ONBOARD_MAX_CONFIRMATIONS", "10"),
+		])).unwrap();
+		cfg.validate().unwrap();
+		// Not yet broadcast or still shallow is fine.
+		cfg.check_onboard_utxo_depth(utxo, None).unwrap();
+		cfg.check_onboard_utxo_depth(utxo, Some(1)).unwrap();
+		cfg.check_onboard_utxo_depth(utxo, Some(10)).unwrap();
+		// A utxo that was confirmed long ago is not.
+		let err = cfg.check_onboard_utxo_depth(utxo, Some(500)).unwrap_err();
+		assert!(err.to_string().contains("already has 500 confirmations"), "{}", err);
+
+		// The band can't be empty.
+		cfg.apply_overrides(vars(&[("ARKD_ONBOARD_MAX_CONFIRMATIONS", "2")])).unwrap();
+		cfg.validate().unwrap_err();
+		cfg.apply_overrides(vars(&[("ARKD_ONBOARD_MAX_CONFIRMATIONS", "")])).unwrap();
+		assert_eq!(cfg.onboard_max_confirmations, None);
+		cfg.validate().unwrap();
+	}
+
+	#[test]
+	fn config_oor_min_amount() {
+		let pubkey = PublicKey::from_str(
+			"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+		).unwrap();
+		let output = |sat| ark::VtxoRequest { pubkey, amount: Amount::from_sat(sat) };
+
+		let mut cfg = Config::default();
+		// The default covers the cost of exiting.
+		assert!(cfg.oor_min_amount > ark::P2TR_DUST);
+		assert_eq!(cfg.oor_min_amount, ark::oor::min_exitable_amount());
+		cfg.check_oor_outputs(&[output(cfg.oor_min_amount.to_sat())]).unwrap();
+
+		cfg.apply_overrides(vars(&[("ARKD_OOR_MIN_AMOUNT", "5000")])).unwrap();
+		cfg.validate().unwrap();
+		cfg.check_oor_outputs(&[output(20_000), output(5_000)]).unwrap();
+		let err = cfg.check_oor_outputs(&[output(20_000), output(4_999)]).unwrap_err();
+		assert!(err.to_string().contains("below the minimum"), "{}", err);
+
+		// Dust is never allowed.
+		cfg.apply_overrides(vars(&[("ARKD_OOR_MIN_AMOUNT", "100")])).unwrap();
+		cfg.validate().unwrap_err();
+	}
+
+	#[test]
+	fn config_upgrade_old_format() {
+		// A config file from before some fields existed.
+		let mut old = serde_json::to_value(Config::default()).unwrap();
+		let fields = old.as_object_mut().unwrap();
+		fields.remove("connector_value").unwrap();
+		fields.remove("round_change").unwrap();
+		fields.insert("network".into(), "signet".into());
+
+		let dir = std::env::temp_dir().join(format!("aspd-upgrade-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("config.json"), serde_json::to_vec(&old).unwrap()).unwrap();
+
+		let (_, mut added) = Config::upgrade_json(&fs::read(dir.join("config.json")).unwrap()).unwrap();
+		added.sort();
+		assert_eq!(added, vec!["connector_value", "round_change"]);
+
+		let cfg = Config::upgrade_in_datadir(&dir).unwrap();
+		assert_eq!(cfg.network, Network::Signet);
+		assert_eq!(cfg.connector_value, default_connector_value());
+		assert_eq!(cfg.round_change, default_round_change());
+		cfg.validate().unwrap();
+		assert!(dir.join("config.backup.json.v0").exists());
+
+		// The rewritten file is complete.
+		let (_, added) = Config::upgrade_json(&fs::read(dir.join("config.json")).unwrap()).unwrap();
+		assert!(added.is_empty());
+		Config::upgrade_in_datadir(&dir).unwrap();
+		assert!(!dir.join("config.backup.json.v1").exists());
+
+		// We never guess the network.
+		old.as_object_mut().unwrap().remove("network").unwrap();
+		Config::upgrade_json(&serde_json::to_vec(&old).unwrap()).unwrap_err();
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn config_custom_signet() {
+		let challenge = "51210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179851ae";
+
+		let mut cfg = Config::default();
+		cfg.apply_overrides(vars(&[("ARKD_SIGNET_CHALLENGE", challenge)])).unwrap();
+		// Only signet can have a challenge.
+		cfg.validate().unwrap_err();
+		cfg.network = Network::Signet;
+		cfg.validate().unwrap();
+		assert_ne!(cfg.network_magic(), Network::Signet.magic());
+
+		// The default challenge gives the default signet magic.
+		let mut default = Config { network: Network::Signet, ..Default::default() };
+		assert_eq!(default.network_magic(), Network::Signet.magic());
+		default.signet_challenge = Some(ScriptBuf::from_hex(DEFAULT_SIGNET_CHALLENGE).unwrap());
+		assert_eq!(default.network_magic(), Network::Signet.magic());
+
+		// Addresses encode and parse like on the default signet.
+		let (_, _, wallet) = App::wallet_from_seed(cfg.network, &[42u8; 64], 10, None).unwrap();
+		let addr = wallet.peek_address(bdk_wallet::KeychainKind::External, 0).address;
+		assert!(addr.to_string().starts_with("tb1p"), "{}", addr);
+		let parsed = Address::from_str(&addr.to_string()).unwrap()
+			.require_network(cfg.network).unwrap();
+		assert_eq!(parsed, addr);
+
+		let info = |chain: &str, challenge: Option<&str>| match challenge {
+			Some(c) => serde_json::json!({ "chain": chain, "signet_challenge": c }),
+			None => serde_json::json!({ "chain": chain }),
+		};
+		cfg.check_chain_info(&info("signet", Some(challenge))).unwrap();
+		cfg.check_chain_info(&info("signet", None)).unwrap();
+		cfg.check_chain_info(&info("signet", Some(DEFAULT_SIGNET_CHALLENGE))).unwrap_err();
+		cfg.check_chain_info(&info("regtest", None)).unwrap_err();
+		default.signet_challenge = None;
+		default.check_chain_info(&info("signet", Some(DEFAULT_SIGNET_CHALLENGE))).unwrap();
+		default.check_chain_info(&info("signet", Some(challenge))).unwrap_err();
+	}
+
+	#[test]
+	fn round_sweep_utxo_amounts() {
+		let output = |v: u64| TxOut {
+			script_pubkey: ScriptBuf::new_op_return(&[v as u8]),
+			value: Amount::from_sat(v),
+		};
+		let round_tx = Transaction {
+			version: bitcoin::transaction::Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: vec![],
+			output: vec![output(10_000), output(1_000), output(240)],
+		};
+		let txid = round_tx.compute_txid();
+		let utxo = |vout: u32, txout: TxOut| SpendableUtxo {
+			point: OutPoint::new(txid, vout),
+			psbt: psbt::Input { witness_utxo: Some(txout), ..Default::default() },
+			weight: Weight::ZERO,
+		};
+		let tree_value = Amount::from_sat(10_000);
+
+		let good = vec![utxo(0, output(10_000)), utxo(1, output(1_000))];
+		check_round_sweep_utxos(txid, &round_tx, tree_value, &good).unwrap();
+
+		// A utxo claiming more than the round tx output holds.
+		let inflated = vec![utxo(0, output(20_000)), utxo(1, output(1_000))];
+		check_round_sweep_utxos(txid, &round_tx, tree_value, &inflated).unwrap_err();
+		// Missing the connector output.
+		let missing = vec![utxo(0, output(10_000))];
+		check_round_sweep_utxos(txid, &round_tx, tree_value, &missing).unwrap_err();
+		// The same output twice.
+		let double = vec![utxo(0, output(10_000)), utxo(0, output(10_000))];
+		check_round_sweep_utxos(txid, &round_tx, tree_value, &double).unwrap_err();
+		// The tree needs more than the round tx provides.
+		check_round_sweep_utxos(txid, &round_tx, Amount::from_sat(11_000), &good).unwrap_err();
+		// The stored tx doesn't match the round id.
+		check_round_sweep_utxos(Txid::all_zeros(), &round_tx, tree_value, &good).unwrap_err();
+	}
+
+	#[test]
+	fn sweep_batches_keep_rounds_together() {
+		let round = |tag: u8| (0..2).map(|vout| SpendableUtxo {
+			point: OutPoint::new(Txid::from_byte_array([tag; 32]), vout),
+			psbt: psbt::Input::default(),
+			weight: Weight::ZERO,
+		}).collect::<Vec<_>>();
+		let txids = |batch: &Vec<SpendableUtxo>| batch.iter().map(|u| u.point.txid.to_byte_array()[0])
+			.collect::<Vec<_>>();
+
+		let batches = sweep_batches((1..=5).map(round), 4);
+		assert_eq!(batches.iter().map(txids).collect::<Vec<_>>(), vec![
+			vec![1, 1, 2, 2], vec![3, 3, 4, 4], vec![5, 5],
+		]);
+		// A round never gets split, even if the batches could be fuller.
+		let batches = sweep_batches((1..=3).map(round), 5);
+		assert_eq!(batches.iter().map(|b| b.len()).collect::<Vec<_>>(), vec![4, 2]);
+		// Unsweepable rounds don't produce empty batches.
+		let batches = sweep_batches(vec![Vec::new(), round(1), Vec::new()], 2);
+		assert_eq!(batches.len(), 1);
+		assert!(sweep_batches(Vec::new(), 2).is_empty());
+		// Rounds that don't fit in a single batch are split up.
+		let batches = sweep_batches((1..=2).map(round), 1);
+		assert_eq!(batches.iter().map(txids).collect::<Vec<_>>(), vec![
+			vec![1], vec![1], vec![2], vec![2],
+		]);
+		let big = || (0..5).map(|vout| SpendableUtxo {
+			point: OutPoint::new(Txid::from_byte_array([9; 32]), vout),
+			psbt: psbt::Input::default(),
+			weight: Weight::ZERO,
+		}).collect::<Vec<_>>();
+		let batches = sweep_batches(vec![round(1), big(), round(2)], 3);
+		assert_eq!(batches.iter().map(|b| b.len()).collect::<Vec<_>>(), vec![2, 3, 2, 2]);
+		assert!(batches.iter().all(|b| b.len() <= 3));
+	}
+
+	/// A psbt sweeping a dummy round, with a wallet input at the end.
+	fn round_sweep_psbt(asp: &Keypair) -> psbt::Psbt {
+		let user = Keypair::from_seckey_slice(&SECP, &[2; 32]).unwrap();
+		let spec = ark::tree::signed::VtxoTreeSpec::new(
+			vec![ark::VtxoRequest { pubkey: user.public_key(), amount: Amount::from_sat(10_000) }],
+			musig::combine_keys([asp.public_key(), user.public_key()]),
+			asp.public_key(),
+			1_000,
+			2016,
+			true,
+			OutputKeyPolicy::MerkleRootTweak,
+			ExitTimelockType::Relative,
+			VtxoScriptType::Taproot,
+		);
+		let round_tx = Transaction {
+			version: bitcoin::transaction::Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: Vec::new(),
+			output: vec![
+				TxOut {
+					value: spec.total_required_value(),
+					script_pubkey: ScriptBuf::new_p2tr_tweaked(spec.cosign_output_key()),
+				},
+				TxOut {
+					value: Amount::from_sat(1_000),
+					script_pubkey: ConnectorChain::output_script(asp.public_key()),
+				},
+			],
+		};
+		let round_txid = round_tx.compute_txid();
+		let sweep_tx = Transaction {
+			version: bitcoin::transaction::Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: [
+				OutPoint::new(round_txid, 0),
+				OutPoint::new(round_txid, 1),
+				OutPoint::new(Txid::all_zeros(), 0),
+			].into_iter().map(|p| bitcoin::TxIn { previous_output: p, ..Default::default() }).collect(),
+			output: Vec::new(),
+		};
+		let mut psbt = psbt::Psbt::from_unsigned_tx(sweep_tx).unwrap();
+		psbt.inputs[0] = vtxo_tree_sweep_input(round_txid, &spec, round_tx.output[0].clone())
+			.unwrap();
+		psbt.inputs[1] = connector_sweep_input(round_txid, asp.public_key(), round_tx.output[1].clone());
+		psbt.inputs[0].non_witness_utxo = Some(round_tx.clone());
+		psbt
+	}
+
+	#[test]
+	fn sign_round_utxo_inputs_with_signer() {
+		let asp = Keypair::from_seckey_slice(&SECP, &[1; 32]).unwrap();
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[2].witness_utxo = Some(TxOut {
+			value: Amount::from_sat(5_000),
+			script_pubkey: ScriptBuf::new_p2tr(&SECP, asp.x_only_public_key().0, None),
+		});
+
+		let signer = signer::test::MockSigner::new(asp);
+		let signing = sign_round_utxo_inputs(&mut psbt, &signer).unwrap();
+		assert_eq!(signing.signed, vec![0, 1]);
+		signing.check().unwrap();
+		assert_eq!(signer.nb_signatures.load(std::sync::atomic::Ordering::SeqCst), 2);
+		// The wallet input is left to the wallet.
+		assert!(psbt.inputs[2].final_script_witness.is_none());
+
+		let prevouts = psbt.inputs.iter().map(|i| i.witness_utxo.clone().unwrap()).collect::<Vec<_>>();
+		let mut shc = sighash::SighashCache::new(&psbt.unsigned_tx);
+		let (_, (script, lv)) = psbt.inputs[0].tap_scripts.iter().next().unwrap();
+		let vtxo_sighash = shc.taproot_script_spend_signature_hash(
+			0,
+			&sighash::Prevouts::All(&prevouts),
+			taproot::TapLeafHash::from_script(script, *lv),
+			sighash::TapSighashType::Default,
+		).unwrap();
+		let connector_sighash = shc.taproot_key_spend_signature_hash(
+			1, &sighash::Prevouts::All(&prevouts), sighash::TapSighashType::Default,
+		).unwrap();
+
+		let vtxo_wit = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+		let sig = secp256k1::schnorr::Signature::from_slice(vtxo_wit.nth(0).unwrap()).unwrap();
+		SECP.verify_schnorr(&sig, &vtxo_sighash.into(), &asp.x_only_public_key().0).unwrap();
+		let connector_wit = psbt.inputs[1].final_script_witness.as_ref().unwrap();
+		let sig = secp256k1::schnorr::Signature::from_slice(connector_wit.nth(0).unwrap()).unwrap();
+		SECP.verify_schnorr(&sig, &connector_sighash.into(), &signer.keyspend_pubkey()).unwrap();
+		assert!(verify_round_utxo_inputs(&psbt, asp.public_key()).is_empty());
+	}
+
+	#[test]
+	fn sign_round_utxo_inputs_partially() {
+		let asp = Keypair::from_seckey_slice(&SECP, &[1; 32]).unwrap();
+		let signer = KeypairSigner::new(asp);
+		let wallet_utxo = TxOut {
+			value: Amount::from_sat(5_000),
+			script_pubkey: ScriptBuf::new_p2tr(&SECP, asp.x_only_public_key().0, None),
+		};
+
+		// A vtxo input without tap scripts doesn't stop us signing the connector.
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[2].witness_utxo = Some(wallet_utxo.clone());
+		psbt.inputs[0].tap_scripts.clear();
+		let signing = sign_round_utxo_inputs(&mut psbt, &signer).unwrap();
+		assert_eq!(signing.signed, vec![1]);
+		assert_eq!(signing.failed.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![0]);
+		signing.check().unwrap_err();
+		assert!(psbt.inputs[0].final_script_witness.is_none());
+		assert!(psbt.inputs[1].final_script_witness.is_some());
+
+		// Neither does a corrupt round meta.
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[2].witness_utxo = Some(wallet_utxo);
+		psbt.inputs[1].proprietary.values_mut().for_each(|v| *v = vec![0xff, 0x00]);
+		let signing = sign_round_utxo_inputs(&mut psbt, &signer).unwrap();
+		assert_eq!(signing.signed, vec![0]);
+		assert_eq!(signing.failed.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1]);
+		assert!(psbt.inputs[0].final_script_witness.is_some());
+
+		// Without all prevouts, we can't sign anything.
+		let mut psbt = round_sweep_psbt(&asp);
+		sign_round_utxo_inputs(&mut psbt, &signer).unwrap_err();
+	}
+
+	#[test]
+	fn verify_round_utxo_inputs_malformed() {
+		let asp = Keypair::from_seckey_slice(&SECP, &[1; 32]).unwrap();
+		let other = Keypair::from_seckey_slice(&SECP, &[3; 32]).unwrap();
+		let verify = |psbt: &psbt::Psbt| verify_round_utxo_inputs(psbt, asp.public_key());
+		assert_eq!(verify(&round_sweep_psbt(&asp)), vec![]);
+
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[0].witness_utxo = None;
+		assert_eq!(verify(&psbt), vec![(0, RoundInputIssue::MissingWitnessUtxo)]);
+
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[0].witness_utxo.as_mut().unwrap().value = Amount::from_sat(1);
+		assert_eq!(verify(&psbt), vec![(0, RoundInputIssue::NonWitnessUtxoMismatch)]);
+
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[1].tap_internal_key = None;
+		assert_eq!(verify(&psbt), vec![(1, RoundInputIssue::MissingInternalKey)]);
+
+		// A valid input, but for another round.
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[1].set_round_meta(Txid::all_zeros(), RoundMeta::Connector);
+		psbt.inputs[1].proprietary.retain(|k, _| k.key == Txid::all_zeros()[..]);
+		assert_eq!(verify(&psbt), vec![(1, RoundInputIssue::NotFromRound(Txid::all_zeros()))]);
+
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[0].proprietary.values_mut().for_each(|v| *v = vec![0xff, 0x00]);
+		assert!(matches!(verify(&psbt)[..], [(0, RoundInputIssue::InvalidRoundMeta(_))]));
+
+		// A tap script that isn't in the output's taproot.
+		let mut psbt = round_sweep_psbt(&asp);
+		let (_, (script, _)) = psbt.inputs[0].tap_scripts.iter_mut().next().unwrap();
+		*script = ScriptBuf::from_bytes(script.as_bytes()[1..].to_vec());
+		assert_eq!(verify(&psbt), vec![
+			(0, RoundInputIssue::InvalidTapScript),
+			(0, RoundInputIssue::WitnessWeight {
+				expected: ark::tree::signed::NODE_SPEND_WEIGHT,
+				actual: ark::tree::signed::NODE_SPEND_WEIGHT - Weight::from_wu(1),
+			}),
+		]);
+
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[0].tap_scripts.clear();
+		assert_eq!(verify(&psbt), vec![(0, RoundInputIssue::TapScriptCount(0))]);
+
+		// A connector of someone else.
+		let mut psbt = round_sweep_psbt(&asp);
+		psbt.inputs[1].tap_internal_key = Some(other.x_only_public_key().0);
+		assert_eq!(verify(&psbt), vec![
+			(1, RoundInputIssue::ForeignConnectorKey),
+			(1, RoundInputIssue::OutputScriptMismatch),
+		]);
+		let psbt = round_sweep_psbt(&other);
+		assert_eq!(verify(&psbt), vec![(1, RoundInputIssue::ForeignConnectorKey)]);
+	}
+
+	#[test]
+	fn descriptor_backup_matches_seed_wallet() {
+		let seed = [42u8; 64];
+		let (key, _, mut seed_wallet) = App::wallet_from_seed(Network::Regtest, &seed, 10, None)
+			.unwrap();
+		let backup = App::descriptor_backup_from_seed(Network::Regtest, &seed);
+		assert_eq!(backup.asp_pubkey, key.public_key());
+
+		let desc_wallet = App::wallet_from_descriptor(
+			Network::Regtest, &backup.descriptor, 10, None,
+		).unwrap();
+		for i in 0..3 {
+			assert_eq!(
+				seed_wallet.peek_address(bdk_wallet::KeychainKind::External, i).address,
+				desc_wallet.peek_address(bdk_wallet::KeychainKind::External, i).address,
+			);
+		}
+
+		// The public wallet can be loaded from the seed wallet's state.
+		seed_wallet.reveal_next_address(bdk_wallet::KeychainKind::External);
+		let cs = seed_wallet.take_staged().unwrap();
+		App::wallet_from_descriptor(Network::Regtest, &backup.descriptor, 10, Some(cs)).unwrap();
+	}
+
+	#[test]
+	fn wallet_birthday_checkpoint() {
+		let seed = [42u8; 64];
+		let birthday = bdk_wallet::chain::BlockId {
+			height: 100,
+			hash: bitcoin::BlockHash::from_byte_array([1; 32]),
+		};
+		let cs = App::wallet_birthday_changeset(Network::Regtest, &seed, 10, Some(birthday))
+			.unwrap();
+		let (_, _, wallet) = App::wallet_from_seed(Network::Regtest, &seed, 10, Some(cs)).unwrap();
+		assert_eq!(wallet.latest_checkpoint().block_id(), birthday);
+
+		// Without a birthday, we start at genesis.
+		let cs = App::wallet_birthday_changeset(Network::Regtest, &seed, 10, None).unwrap();
+		let (_, _, wallet) = App::wallet_from_seed(Network::Regtest, &seed, 10, Some(cs)).unwrap();
+		assert_eq!(wallet.latest_checkpoint().height(), 0);
+	}
+}
@@ -8,11 +8,16 @@ use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::Context;
-use bitcoin::{Address, Amount, FeeRate, Network};
+use bitcoin::{Address, Amount, FeeRate, Network, ScriptBuf, Txid};
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::PublicKey;
 use clap::Parser;
 use tonic::transport::Uri;
 
-use aspd::{App, Config, ClnConfig};
+use ark::{ExitTimelockType, VtxoId, VtxoScriptType};
+use ark::tree::signed::OutputKeyPolicy;
+use aspd::{App, Config, ClnConfig, EventSinkConfig, RoundChange, RoundFeeScheme,
+	RoundOutputOrdering, SweepMode};
 use aspd_rpc_client as rpc;
 
 /// Defaults to our default port on localhost.
@@ -24,10 +29,38 @@ struct Cli {
 	/// the data directory for aspd, mandatory field for most commands
 	#[arg(long, global = true)]
 	datadir: Option<PathBuf>,
+	/// Log levels per target, like `info,aspd::round=trace,aspd::rpcserver=debug`.
+	///
+	/// An entry without target sets the default level.
+	#[arg(long, global = true, value_parser = parse_log_filter)]
+	log_filter: Option<LogFilter>,
 	#[command(subcommand)]
 	command: Command,
 }
 
+/// A default log level with overrides for specific targets.
+#[derive(Debug, Clone)]
+struct LogFilter {
+	default: Option<log::LevelFilter>,
+	targets: Vec<(String, log::LevelFilter)>,
+}
+
+fn parse_log_filter(s: &str) -> anyhow::Result<LogFilter> {
+	let mut ret = LogFilter { default: None, targets: Vec::new() };
+	for entry in s.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+		if let Some((target, level)) = entry.split_once('=') {
+			let level = log::LevelFilter::from_str(level)
+				.with_context(|| format!("invalid log level for target {}: {}", target, level))?;
+			ret.targets.push((target.to_owned(), level));
+		} else {
+			let level = log::LevelFilter::from_str(entry)
+				.with_context(|| format!("invalid log level: {}", entry))?;
+			ret.default = Some(level);
+		}
+	}
+	Ok(ret)
+}
+
 #[derive(clap::Subcommand)]
 enum Command {
 	#[command()]
@@ -35,7 +68,15 @@ enum Command {
 	#[command()]
 	SetConfig(ConfigOpts),
 	#[command()]
-	Start,
+	Start {
+		/// Start without the seed, using a descriptor backup file as
+		/// created by `get-descriptor`.
+		///
+		/// In this mode aspd can sync and serve existing rounds, but it
+		/// can't run new rounds, cosign or sweep.
+		#[arg(long)]
+		descriptor_only: Option<PathBuf>,
+	},
 	#[command()]
 	Drain {
 		/// the address to send all the wallet funds to
@@ -43,12 +84,28 @@ enum Command {
 	},
 	#[command()]
 	GetMnemonic,
+	/// Print a backup of the public key material to use with `start --descriptor-only`.
+	#[command()]
+	GetDescriptor,
 	#[command()]
 	DropOorConflicts,
+	/// Go through the full round lifecycle on regtest to check the setup.
+	///
+	/// This starts aspd, mines blocks to fund the wallets and uses a
+	/// throwaway bark wallet to onboard, refresh in a round and sweep
+	/// the round once it expired. Refuses to run on any other network.
+	///
+	/// Only available when built with the `selftest` feature.
+	#[cfg(feature = "selftest")]
+	#[command()]
+	SelfTest,
 	#[command()]
 	Rpc {
 		#[arg(long, default_value = DEFAULT_ADMIN_RPC_ADDR)]
 		addr: String,
+		/// The admin token, required for some commands.
+		#[arg(long)]
+		token: Option<String>,
 		#[command(subcommand)]
 		cmd: RpcCommand,
 	},
@@ -60,11 +117,52 @@ enum RpcCommand {
 	Balance,
 	#[command()]
 	GetAddress,
+	/// Print the public descriptor of the onchain wallet.
+	#[command()]
+	GetDescriptor,
+	/// Start a round right away.
+	#[command()]
+	TriggerRound {
+		/// The absolute expiry height of the round's vtxos, instead of the
+		/// configured expiry delta.
+		#[arg(long)]
+		expiry_height: Option<u32>,
+	},
+	/// Sweep the outputs of an expired round right away.
+	#[command()]
+	SweepRound {
+		round_txid: Txid,
+		/// The feerate (in sats per kvb) to use for the sweep tx.
+		#[arg(long)]
+		feerate_sat_per_kvb: u64,
+	},
+	/// Sweep the outputs of all expired rounds right away.
 	#[command()]
-	TriggerRound,
+	SweepExpiredRounds {
+		/// The feerate (in sats per kvb) to use for the sweep txs.
+		#[arg(long)]
+		feerate_sat_per_kvb: u64,
+	},
+	/// Bump the fee of an unconfirmed round tx, replacing an earlier bump tx.
+	#[command()]
+	BumpRoundTx {
+		round_txid: Txid,
+		/// The feerate (in sats per kvb) the round tx and its bump tx should pay together.
+		#[arg(long)]
+		feerate_sat_per_kvb: u64,
+	},
 	/// Stop aspd.
 	#[command()]
 	Stop,
+	/// Shut aspd down gracefully, after the round in progress.
+	#[command()]
+	Shutdown,
+	/// Print the timing histograms of the round phases.
+	#[command()]
+	RoundMetrics,
+	/// Print the payments submitted to the round that is collecting payments.
+	#[command()]
+	PendingPayments,
 }
 
 #[tokio::main]
@@ -78,11 +176,17 @@ async fn main() {
 	}
 }
 
-fn init_logging() {
-	fern::Dispatch::new()
-		.level(log::LevelFilter::Trace)
+fn init_logging(filter: Option<LogFilter>) {
+	let filter = filter.unwrap_or(LogFilter { default: None, targets: Vec::new() });
+	let mut dispatch = fern::Dispatch::new()
+		.level(filter.default.unwrap_or(log::LevelFilter::Trace))
 		.level_for("rustls", log::LevelFilter::Warn)
-		.level_for("bitcoincore_rpc", log::LevelFilter::Warn)
+		.level_for("bitcoincore_rpc", log::LevelFilter::Warn);
+	// Apply the user's targets last so they can override our defaults.
+	for (target, level) in filter.targets {
+		dispatch = dispatch.level_for(target, level);
+	}
+	dispatch
 		.format(|out, msg, rec| {
 			let now = chrono::Local::now();
 			let stamp = now.format("%Y-%m-%d %H:%M:%S.%3f");
@@ -98,11 +202,11 @@ fn init_logging() {
 async fn inner_main() -> anyhow::Result<()> {
 	let cli = Cli::parse();
 
-	if let Command::Rpc { cmd, addr } = cli.command {
-		return run_rpc(&addr, cmd).await;
+	if let Command::Rpc { cmd, addr, token } = cli.command {
+		return run_rpc(&addr, token, cmd).await;
 	}
 
-	init_logging();
+	init_logging(cli.log_filter);
 
 	match cli.command {
 		Command::Rpc { .. } => unreachable!(),
@@ -119,10 +223,16 @@ async fn inner_main() -> anyhow::Result<()> {
 
 			let mut cfg = Config {
 				network: opts.network,
+				signet_challenge: opts.signet_challenge.map(|c| ScriptBuf::from_hex(&c))
+					.transpose().context("invalid signet challenge")?,
 				..Default::default()
 			};
 			opts.config.merge_into(&mut cfg)?;
-			App::create(&datadir, cfg).await?;
+			if let Some(mnemonic) = opts.mnemonic {
+				App::create_with_mnemonic(&datadir, cfg, mnemonic, opts.birthday).await?;
+			} else {
+				App::create(&datadir, cfg).await?;
+			}
 		},
 		Command::SetConfig(updates) => {
 			let datadir = PathBuf::from(cli.datadir.context("need datadir")?);
@@ -138,8 +248,16 @@ async fn inner_main() -> anyhow::Result<()> {
 			println!("You should restart `arkd` to ensure the new configuration takes effect");
 			println!("Current config: {:#?}", cfg);
 		},
-		Command::Start => {
-			let mut app = App::open(&cli.datadir.context("need datadir")?).await.context("server init")?;
+		Command::Start { descriptor_only } => {
+			let datadir = cli.datadir.context("need datadir")?;
+			let mut app = if let Some(path) = descriptor_only {
+				let backup = fs::read_to_string(&path)
+					.with_context(|| format!("failed to read descriptor backup {}", path.display()))?;
+				let backup = serde_json::from_str(&backup).context("invalid descriptor backup")?;
+				App::open_descriptor_only(&datadir, backup).await.context("server init")?
+			} else {
+				App::open(&datadir).await.context("server init")?
+			};
 			if let Err(e) = app.start().await {
 				error!("Shutdown error from aspd {:?}", e);
 				process::exit(1);
@@ -153,10 +271,25 @@ async fn inner_main() -> anyhow::Result<()> {
 			let app = App::open(&cli.datadir.context("need datadir")?).await.context("server init")?;
 			println!("{}", app.get_master_mnemonic()?);
 		},
+		Command::GetDescriptor => {
+			let app = App::open(&cli.datadir.context("need datadir")?).await.context("server init")?;
+			println!("{}", serde_json::to_string_pretty(&app.descriptor_backup()?)?);
+		},
 		Command::DropOorConflicts => {
 			let app = App::open(&cli.datadir.context("need datadir")?).await.context("server init")?;
 			app.drop_all_oor_conflicts()?;
 		},
+		#[cfg(feature = "selftest")]
+		Command::SelfTest => {
+			let datadir = cli.datadir.context("need datadir")?;
+			let passed = aspd::run_self_test(&datadir, |step, res| match res {
+				Ok(()) => println!("{}: PASS", step),
+				Err(e) => println!("{}: FAIL: {:#}", step, e),
+			}).await?;
+			if !passed {
+				process::exit(1);
+			}
+		},
 	}
 
 	Ok(())
@@ -180,7 +313,7 @@ fn init_logging_rpc() {
 		.apply().expect("error setting up logging");
 }
 
-async fn run_rpc(addr: &str, cmd: RpcCommand) -> anyhow::Result<()> {
+async fn run_rpc(addr: &str, token: Option<String>, cmd: RpcCommand) -> anyhow::Result<()> {
 	init_logging_rpc();
 
 	let addr = if addr.starts_with("http") {
@@ -196,15 +329,93 @@ async fn run_rpc(addr: &str, cmd: RpcCommand) -> anyhow::Result<()> {
 		RpcCommand::Balance => {
 			let res = asp.wallet_status(rpc::Empty {}).await?.into_inner();
 			println!("{}", Amount::from_sat(res.balance));
+			if res.immature_balance > 0 {
+				println!("of which immature coinbase: {}", Amount::from_sat(res.immature_balance));
+			}
 		},
 		RpcCommand::GetAddress => {
 			let res = asp.wallet_status(rpc::Empty {}).await?.into_inner();
 			println!("{}", res.address);
 		},
-		RpcCommand::TriggerRound => {
-			asp.trigger_round(rpc::Empty {}).await?.into_inner();
+		RpcCommand::GetDescriptor => {
+			let res = asp.wallet_descriptor(rpc::Empty {}).await?.into_inner();
+			println!("{}", res.descriptor);
+		},
+		RpcCommand::TriggerRound { expiry_height } => {
+			asp.trigger_round(rpc::TriggerRoundRequest { expiry_height }).await?.into_inner();
+		}
+		RpcCommand::SweepRound { round_txid, feerate_sat_per_kvb } => {
+			let fee_rate = (feerate_sat_per_kvb.checked_sub(1).context("feerate can't be 0")? / 4) + 1;
+			let res = asp.sweep_round(rpc::SweepRoundRequest {
+				round_txid: round_txid.to_byte_array().to_vec(),
+				fee_rate,
+			}).await?.into_inner();
+			println!("{}", Txid::from_slice(&res.sweep_txid).context("invalid txid")?);
+		}
+		RpcCommand::SweepExpiredRounds { feerate_sat_per_kvb } => {
+			let fee_rate = (feerate_sat_per_kvb.checked_sub(1).context("feerate can't be 0")? / 4) + 1;
+			let res = asp.sweep_expired_rounds(rpc::SweepExpiredRoundsRequest {
+				fee_rate,
+			}).await?.into_inner();
+			for txid in res.sweep_txids {
+				println!("{}", Txid::from_slice(&txid).context("invalid txid")?);
+			}
+		}
+		RpcCommand::BumpRoundTx { round_txid, feerate_sat_per_kvb } => {
+			let fee_rate = (feerate_sat_per_kvb.checked_sub(1).context("feerate can't be 0")? / 4) + 1;
+			let res = asp.bump_round_tx(rpc::BumpRoundTxRequest {
+				round_txid: round_txid.to_byte_array().to_vec(),
+				fee_rate,
+			}).await?.into_inner();
+			println!("{}", Txid::from_slice(&res.bump_txid).context("invalid txid")?);
 		}
 		RpcCommand::Stop => unimplemented!(),
+		RpcCommand::Shutdown => {
+			let token = token.context("the shutdown command requires --token")?;
+			let mut req = tonic::Request::new(rpc::Empty {});
+			req.metadata_mut().insert(
+				rpc::ADMIN_TOKEN_HEADER, token.parse().context("invalid admin token")?,
+			);
+			asp.shutdown(req).await?;
+			println!("aspd is shutting down");
+		},
+		RpcCommand::RoundMetrics => {
+			let res = asp.round_metrics(rpc::Empty {}).await?.into_inner();
+			for phase in res.phases {
+				let avg = phase.sum_ms.checked_div(phase.count).unwrap_or(0);
+				println!("{}: {} rounds, avg {}ms", phase.phase, phase.count, avg);
+				for (i, count) in phase.bucket_counts.iter().enumerate() {
+					match phase.bucket_bounds_ms.get(i) {
+						Some(bound) => println!("  <= {}ms: {}", bound, count),
+						None => println!("  >  {}ms: {}", phase.bucket_bounds_ms.last().unwrap_or(&0), count),
+					}
+				}
+			}
+			println!("rounds skipped for insufficient funds: {}", res.insufficient_funds_rounds);
+			if res.round_backoff_level > 0 {
+				println!("backing off after {} failed rounds, next round in at most {}ms",
+					res.round_backoff_level, res.round_backoff_interval_ms,
+				);
+			}
+			if let Some(size) = res.onboard_nonce_pool_size {
+				println!("onboard nonce pool: {}/{} nonces, exhausted {} times",
+					res.onboard_nonce_pool_occupancy, size, res.onboard_nonce_pool_exhausted,
+				);
+			}
+		},
+		RpcCommand::PendingPayments => {
+			let res = asp.pending_payments(rpc::Empty {}).await?.into_inner();
+			for payment in res.payments {
+				let pubkey = PublicKey::from_slice(&payment.cosign_pubkey)
+					.context("invalid cosign pubkey")?;
+				let inputs = payment.input_vtxo_ids.iter()
+					.map(|id| Ok(VtxoId::from_slice(id).map_err(|e| anyhow!(e))?.to_string()))
+					.collect::<anyhow::Result<Vec<_>>>()?;
+				println!("{}: inputs {}, outputs {} sat, offboards {} sat",
+					pubkey, inputs.join(","), payment.output_amount, payment.offboard_amount,
+				);
+			}
+		},
 	}
 	Ok(())
 }
@@ -213,6 +424,18 @@ async fn run_rpc(addr: &str, cmd: RpcCommand) -> anyhow::Result<()> {
 struct CreateOpts {
 	#[arg(long, default_value = "regtest")]
 	network: Network,
+	/// The challenge script of a custom signet, in hex.
+	#[arg(long)]
+	signet_challenge: Option<String>,
+	/// Restore from an existing mnemonic instead of generating a new one.
+	#[arg(long)]
+	mnemonic: Option<bip39::Mnemonic>,
+	/// The block height at which the restored mnemonic was first used.
+	///
+	/// The onchain wallet is scanned from this height instead of from
+	/// genesis, which can save a lot of time on mainnet.
+	#[arg(long, requires = "mnemonic")]
+	birthday: Option<u32>,
 
 	#[command(flatten)]
 	config: ConfigOpts,
@@ -226,15 +449,33 @@ struct ConfigOpts {
 	/// the path of the cookie file for the bitcoind RPC (mandatory on create)
 	#[arg(long)]
 	bitcoind_cookie: Option<String>,
+	/// sync the onchain wallet from this esplora server instead of bitcoind
+	#[arg(long)]
+	esplora_url: Option<Option<String>>,
 
 	#[arg(long)]
 	public_rpc_address: Option<String>,
+	/// The PEM certificate to serve the public gRPC service over TLS with.
+	#[arg(long)]
+	public_rpc_tls_cert_path: Option<Option<PathBuf>>,
+	/// The PEM private key of the public gRPC TLS certificate.
+	#[arg(long)]
+	public_rpc_tls_key_path: Option<Option<PathBuf>>,
+	/// Whether to gzip public gRPC responses for clients that accept it.
+	#[arg(long)]
+	public_rpc_compression: Option<bool>,
 	#[arg(long)]
 	admin_rpc_address: Option<Option<String>>,
+	/// The token required for sensitive admin RPCs, like shutdown.
+	#[arg(long)]
+	admin_rpc_token: Option<Option<String>>,
 
 	/// Round interval, in ms.
 	#[arg(long)]
 	round_interval: Option<u64>,
+	/// Maximum time between rounds when backing off after failed rounds, in ms.
+	#[arg(long)]
+	max_round_interval: Option<u64>,
 	/// Time for users to submit payments in rounds, in ms.
 	#[arg(long)]
 	round_submit_time: Option<u64>,
@@ -243,14 +484,111 @@ struct ConfigOpts {
 	round_sign_time: Option<u64>,
 	#[arg(long)]
 	nb_round_nonces: Option<usize>,
+	/// Number of one-time ASP keys in the cosign group of the vtxo tree.
+	#[arg(long)]
+	nb_round_asp_cosigners: Option<usize>,
+	/// Maximum number of input vtxos in a round.
+	#[arg(long)]
+	max_round_inputs: Option<usize>,
+	/// Number of vtxo tree nodes whose signatures are aggregated per batch.
+	#[arg(long)]
+	round_cosign_batch_size: Option<usize>,
 	#[arg(long)]
 	vtxo_expiry_delta: Option<u16>,
 	#[arg(long)]
 	vtxo_exit_delta: Option<u16>,
+	/// How the output key of vtxo tree outputs is constructed,
+	/// either "merkle_root_tweak" or "plain_aggregate".
+	#[arg(long)]
+	vtxo_output_key_policy: Option<OutputKeyPolicy>,
+	/// The type of timelock in the exit clause of vtxos,
+	/// either "relative" or "absolute".
+	#[arg(long)]
+	vtxo_exit_timelock: Option<ExitTimelockType>,
+
+	/// The type of the output scripts of vtxos, currently only "taproot".
+	#[arg(long)]
+	vtxo_script_type: Option<VtxoScriptType>,
 
 	/// The feerate (in sats per kvb) to use for round txs.
 	#[arg(long)]
 	round_tx_feerate_sat_per_kvb: Option<u64>,
+	/// Number of blocks a round tx can stay unconfirmed before we bump its fee.
+	#[arg(long)]
+	round_tx_bump_after: Option<u32>,
+	/// The feerate (in sats per kvb) to bump stuck round txs to.
+	#[arg(long)]
+	round_tx_bump_feerate_sat_per_kvb: Option<u64>,
+	/// The lowest feerate (in sats per kvb) to build any tx at.
+	#[arg(long)]
+	min_feerate_sat_per_kvb: Option<u64>,
+	/// The number of confirmations our coinbase outputs need before we spend them.
+	#[arg(long)]
+	coinbase_maturity: Option<u32>,
+	/// Whether to check round txs with testmempoolaccept before broadcasting.
+	#[arg(long)]
+	round_tx_precheck: Option<bool>,
+	/// The version of round txs, either 2 or 3.
+	#[arg(long)]
+	round_tx_version: Option<i32>,
+	/// Whether to set the round tx locktime to the current height.
+	#[arg(long)]
+	round_tx_anti_fee_sniping: Option<bool>,
+	/// How to bump round tx fees: keyless_anchor, p2a_anchor or cpfp_change.
+	#[arg(long)]
+	fee_scheme: Option<RoundFeeScheme>,
+	/// Where our round tx change goes: onchain or vtxo.
+	#[arg(long)]
+	round_change: Option<RoundChange>,
+	/// The maximum value (in sats) of the vtxo that holds our round change.
+	#[arg(long)]
+	round_change_vtxo_max_sat: Option<u64>,
+	/// How round tx outputs are ordered: submission, bip69 or shuffle.
+	#[arg(long)]
+	round_output_ordering: Option<RoundOutputOrdering>,
+	/// The value (in sats) of each connector output of round txs.
+	#[arg(long)]
+	connector_value_sat: Option<u64>,
+	/// The maximum number of expired round utxos spent by a single tx.
+	#[arg(long)]
+	sweep_batch_max_inputs: Option<usize>,
+	/// Whether expired rounds are swept automatically or only through the
+	/// admin RPC: auto or manual.
+	#[arg(long)]
+	sweep_mode: Option<SweepMode>,
+	/// How often expired rounds are swept in auto sweep mode, in ms.
+	#[arg(long)]
+	sweep_interval: Option<u64>,
+	/// The number of blocks to wait after a round expired before sweeping it.
+	#[arg(long)]
+	sweep_grace_blocks: Option<u32>,
+	/// The maximum weight of round txs.
+	#[arg(long)]
+	round_tx_max_weight: Option<u64>,
+
+	/// Number of confirmations an onboard tx needs before it can be used in a round.
+	#[arg(long)]
+	onboard_confirmations: Option<u32>,
+	/// Maximum number of confirmations an onboard utxo can already have
+	/// when its onboard is cosigned.
+	#[arg(long)]
+	onboard_max_confirmations: Option<u32>,
+	/// Number of nonces generated ahead of time for cosigning onboards.
+	#[arg(long)]
+	onboard_nonce_pool_size: Option<usize>,
+	/// Maximum number of blocks a vtxo can live across refreshes.
+	#[arg(long)]
+	max_vtxo_lifetime_blocks: Option<u32>,
+	/// The minimum amount (in sats) of each output of an OOR payment.
+	#[arg(long)]
+	oor_min_amount_sat: Option<u64>,
+
+	/// Whether to hand out a new wallet address on every funding request.
+	#[arg(long)]
+	wallet_rotate_addresses: Option<bool>,
+	/// Number of unused addresses to watch when syncing the wallet.
+	#[arg(long)]
+	wallet_gap_limit: Option<u32>,
 
 	#[arg(long)]
 	cln_grpc_uri: Option<Option<Uri>>,
@@ -260,6 +598,12 @@ struct ConfigOpts {
 	cln_grpc_client_cert_path: Option<Option<PathBuf>>,
 	#[arg(long)]
 	cln_grpc_client_key_path: Option<Option<PathBuf>>,
+
+	/// Where to write the machine-readable event log.
+	///
+	/// Either a file path or `unix:<path>` for a unix socket.
+	#[arg(long)]
+	event_sink: Option<Option<EventSinkConfig>>,
 }
 
 impl ConfigOpts {
@@ -309,6 +653,10 @@ impl ConfigOpts {
 			cfg.bitcoind_cookie = v;
 		}
 
+		if let Some(v) = self.esplora_url {
+			cfg.esplora_url = v;
+		}
+
 		if let Some(v) = self.public_rpc_address {
 			cfg.public_rpc_address = v.parse().context("public_rpc_address is invalid")?;
 		}
@@ -321,10 +669,30 @@ impl ConfigOpts {
 			}
 		}
 
+		if let Some(v) = self.admin_rpc_token {
+			cfg.admin_rpc_token = v;
+		}
+
+		if let Some(v) = self.public_rpc_tls_cert_path {
+			cfg.public_rpc_tls_cert_path = v;
+		}
+
+		if let Some(v) = self.public_rpc_tls_key_path {
+			cfg.public_rpc_tls_key_path = v;
+		}
+
+		if let Some(v) = self.public_rpc_compression {
+			cfg.public_rpc_compression = v;
+		}
+
 		if let Some(v) = self.round_interval {
 			cfg.round_interval = Duration::from_millis(v);
 		}
 
+		if let Some(v) = self.max_round_interval {
+			cfg.max_round_interval = Duration::from_millis(v);
+		}
+
 		if let Some(v) = self.round_submit_time {
 			cfg.round_submit_time = Duration::from_millis(v);
 		}
@@ -337,6 +705,18 @@ impl ConfigOpts {
 			cfg.nb_round_nonces = v;
 		}
 
+		if let Some(v) = self.nb_round_asp_cosigners {
+			cfg.nb_round_asp_cosigners = v;
+		}
+
+		if let Some(v) = self.max_round_inputs {
+			cfg.max_round_inputs = Some(v);
+		}
+
+		if let Some(v) = self.round_cosign_batch_size {
+			cfg.round_cosign_batch_size = Some(v);
+		}
+
 		if let Some(v) = self.vtxo_expiry_delta {
 			cfg.vtxo_expiry_delta = v;
 		}
@@ -345,12 +725,127 @@ impl ConfigOpts {
 			cfg.vtxo_exit_delta = v;
 		}
 
+		if let Some(v) = self.vtxo_output_key_policy {
+			cfg.vtxo_output_key_policy = v;
+		}
+
+		if let Some(v) = self.vtxo_exit_timelock {
+			cfg.vtxo_exit_timelock = v;
+		}
+
+		if let Some(v) = self.vtxo_script_type {
+			cfg.vtxo_script_type = v;
+		}
+
 		if let Some(v) = self.round_tx_feerate_sat_per_kvb {
 			cfg.round_tx_feerate = FeeRate::from_sat_per_kwu(
 				(v.checked_sub(1).context("feerate can't be 0")? / 4) + 1
 			);
 		}
 
+		if let Some(v) = self.round_tx_bump_after {
+			cfg.round_tx_bump_after = v;
+		}
+
+		if let Some(v) = self.round_tx_bump_feerate_sat_per_kvb {
+			cfg.round_tx_bump_feerate = FeeRate::from_sat_per_kwu(
+				(v.checked_sub(1).context("feerate can't be 0")? / 4) + 1
+			);
+		}
+
+		if let Some(v) = self.min_feerate_sat_per_kvb {
+			cfg.min_feerate = FeeRate::from_sat_per_kwu(
+				(v.checked_sub(1).context("feerate can't be 0")? / 4) + 1
+			);
+		}
+
+		if let Some(v) = self.coinbase_maturity {
+			cfg.coinbase_maturity = v;
+		}
+
+		if let Some(v) = self.round_tx_precheck {
+			cfg.round_tx_precheck = v;
+		}
+
+		if let Some(v) = self.round_tx_version {
+			cfg.round_tx_version = v;
+		}
+
+		if let Some(v) = self.fee_scheme {
+			cfg.fee_scheme = v;
+		}
+
+		if let Some(v) = self.round_change {
+			cfg.round_change = v;
+		}
+
+		if let Some(v) = self.round_change_vtxo_max_sat {
+			cfg.round_change_vtxo_max = Amount::from_sat(v);
+		}
+		if let Some(v) = self.round_output_ordering {
+			cfg.round_output_ordering = v;
+		}
+
+		if let Some(v) = self.connector_value_sat {
+			cfg.connector_value = Amount::from_sat(v);
+		}
+
+		if let Some(v) = self.sweep_batch_max_inputs {
+			cfg.sweep_batch_max_inputs = v;
+		}
+
+		if let Some(v) = self.sweep_mode {
+			cfg.sweep_mode = v;
+		}
+
+		if let Some(v) = self.sweep_interval {
+			cfg.sweep_interval = Duration::from_millis(v);
+		}
+
+		if let Some(v) = self.sweep_grace_blocks {
+			cfg.sweep_grace_blocks = v;
+		}
+
+		if let Some(v) = self.round_tx_max_weight {
+			cfg.round_tx_max_weight = v;
+		}
+
+		if let Some(v) = self.round_tx_anti_fee_sniping {
+			cfg.round_tx_anti_fee_sniping = v;
+		}
+
+		if let Some(v) = self.onboard_confirmations {
+			cfg.onboard_confirmations = v;
+		}
+
+		if let Some(v) = self.onboard_max_confirmations {
+			cfg.onboard_max_confirmations = Some(v);
+		}
+
+		if let Some(v) = self.onboard_nonce_pool_size {
+			cfg.onboard_nonce_pool_size = Some(v);
+		}
+
+		if let Some(v) = self.max_vtxo_lifetime_blocks {
+			cfg.max_vtxo_lifetime_blocks = Some(v);
+		}
+
+		if let Some(v) = self.oor_min_amount_sat {
+			cfg.oor_min_amount = Amount::from_sat(v);
+		}
+
+		if let Some(v) = self.wallet_rotate_addresses {
+			cfg.wallet_rotate_addresses = v;
+		}
+
+		if let Some(v) = self.wallet_gap_limit {
+			cfg.wallet_gap_limit = v;
+		}
+
+		if let Some(v) = self.event_sink {
+			cfg.event_sink = v;
+		}
+
 		// We have the following sc
 
 		// If any of these fields is Some(Some(value)) it explcitily sets the field
@@ -0,0 +1,222 @@
+
+//! Timing metrics of the round coordinator.
+//!
+//! For every finished round, we record how long each phase of the final
+//! round attempt took in a histogram per phase. The histograms live in
+//! memory and are exposed through the admin RPC, together with a count of
+//! the rounds we skipped because our wallet couldn't fund them and the
+//! current backoff after failed rounds.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The upper bounds of the histogram buckets, in milliseconds.
+///
+/// There is an implicit last bucket for everything above the last bound.
+pub const BUCKET_BOUNDS_MS: [u64; 11] = [
+	10, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000,
+];
+
+/// The phases of a round attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RoundPhase {
+	/// Collecting payment submissions from users.
+	SubmitCollection,
+	/// Building the round tx and the vtxo tree.
+	TreeConstruction,
+	/// Aggregating the vtxo tree nonces of all cosigners.
+	NonceAggregation,
+	/// Waiting for the vtxo tree signatures and combining them.
+	VtxoSigning,
+	/// Waiting for the forfeit signatures of all inputs.
+	ForfeitSigning,
+	/// Signing, broadcasting and storing the round tx.
+	Broadcast,
+}
+
+impl RoundPhase {
+	pub const ALL: [RoundPhase; 6] = [
+		RoundPhase::SubmitCollection,
+		RoundPhase::TreeConstruction,
+		RoundPhase::NonceAggregation,
+		RoundPhase::VtxoSigning,
+		RoundPhase::ForfeitSigning,
+		RoundPhase::Broadcast,
+	];
+}
+
+impl fmt::Display for RoundPhase {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RoundPhase::SubmitCollection => f.write_str("submit_collection"),
+			RoundPhase::TreeConstruction => f.write_str("tree_construction"),
+			RoundPhase::NonceAggregation => f.write_str("nonce_aggregation"),
+			RoundPhase::VtxoSigning => f.write_str("vtxo_signing"),
+			RoundPhase::ForfeitSigning => f.write_str("forfeit_signing"),
+			RoundPhase::Broadcast => f.write_str("broadcast"),
+		}
+	}
+}
+
+/// A histogram of durations with the buckets of [BUCKET_BOUNDS_MS].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+	/// The number of observations in each bucket, not cumulative.
+	pub bucket_counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+	pub count: u64,
+	pub sum_ms: u64,
+}
+
+impl Histogram {
+	pub fn observe(&mut self, duration: Duration) {
+		let ms = duration.as_millis() as u64;
+		let bucket = BUCKET_BOUNDS_MS.iter().position(|b| ms <= *b)
+			.unwrap_or(BUCKET_BOUNDS_MS.len());
+		self.bucket_counts[bucket] += 1;
+		self.count += 1;
+		self.sum_ms += ms;
+	}
+}
+
+/// Times the phases of a single round attempt.
+pub struct PhaseTimer {
+	phase_start: Instant,
+	durations: Vec<(RoundPhase, Duration)>,
+}
+
+impl PhaseTimer {
+	/// Start timing, the first phase starts now.
+	pub fn start() -> PhaseTimer {
+		PhaseTimer {
+			phase_start: Instant::now(),
+			durations: Vec::with_capacity(RoundPhase::ALL.len()),
+		}
+	}
+
+	/// Mark the end of the given phase, the next phase starts now.
+	pub fn finish(&mut self, phase: RoundPhase) {
+		let now = Instant::now();
+		self.durations.push((phase, now - self.phase_start));
+		self.phase_start = now;
+	}
+
+	/// The duration of each finished phase, in order.
+	pub fn durations(&self) -> &[(RoundPhase, Duration)] {
+		&self.durations
+	}
+
+	/// The duration of the given phase, zero if it didn't finish.
+	pub fn duration(&self, phase: RoundPhase) -> Duration {
+		self.durations.iter().filter(|(p, _)| *p == phase).map(|(_, d)| *d).sum()
+	}
+}
+
+/// The timing histograms of all round phases.
+pub struct RoundMetrics {
+	histograms: Mutex<[Histogram; RoundPhase::ALL.len()]>,
+	insufficient_funds_rounds: AtomicU64,
+	round_backoff_level: AtomicU64,
+	round_backoff_interval_ms: AtomicU64,
+}
+
+impl RoundMetrics {
+	pub fn new() -> RoundMetrics {
+		RoundMetrics {
+			histograms: Mutex::new(Default::default()),
+			insufficient_funds_rounds: AtomicU64::new(0),
+			round_backoff_level: AtomicU64::new(0),
+			round_backoff_interval_ms: AtomicU64::new(0),
+		}
+	}
+
+	/// Record a round we skipped because our wallet couldn't fund it.
+	pub fn record_insufficient_funds(&self) {
+		self.insufficient_funds_rounds.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// The number of rounds we skipped because our wallet couldn't fund them.
+	pub fn insufficient_funds_rounds(&self) -> u64 {
+		self.insufficient_funds_rounds.load(Ordering::Relaxed)
+	}
+
+	/// Record the current backoff level and time between rounds.
+	pub fn record_round_backoff(&self, level: u32, interval: Duration) {
+		self.round_backoff_level.store(level as u64, Ordering::Relaxed);
+		self.round_backoff_interval_ms.store(interval.as_millis() as u64, Ordering::Relaxed);
+	}
+
+	/// The number of consecutive failed rounds we're backing off for.
+	pub fn round_backoff_level(&self) -> u64 {
+		self.round_backoff_level.load(Ordering::Relaxed)
+	}
+
+	/// The current time between rounds, including backoff.
+	pub fn round_backoff_interval(&self) -> Duration {
+		Duration::from_millis(self.round_backoff_interval_ms.load(Ordering::Relaxed))
+	}
+
+	/// Record the phase timings of a finished round.
+	pub fn record(&self, timer: &PhaseTimer) {
+		let mut histograms = self.histograms.lock().unwrap();
+		for (phase, duration) in timer.durations() {
+			histograms[*phase as usize].observe(*duration);
+		}
+	}
+
+	/// The current histogram of each phase.
+	pub fn histograms(&self) -> Vec<(RoundPhase, Histogram)> {
+		let histograms = self.histograms.lock().unwrap();
+		RoundPhase::ALL.iter().map(|p| (*p, histograms[*p as usize].clone())).collect()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn histogram_buckets() {
+		let mut h = Histogram::default();
+		h.observe(Duration::from_millis(0));
+		h.observe(Duration::from_millis(10));
+		h.observe(Duration::from_millis(11));
+		h.observe(Duration::from_millis(700));
+		h.observe(Duration::from_secs(120));
+		assert_eq!(h.count, 5);
+		assert_eq!(h.sum_ms, 120_721);
+		assert_eq!(h.bucket_counts[0], 2);
+		assert_eq!(h.bucket_counts[1], 1);
+		assert_eq!(h.bucket_counts[5], 1);
+		assert_eq!(h.bucket_counts[BUCKET_BOUNDS_MS.len()], 1);
+		assert_eq!(h.bucket_counts.iter().sum::<u64>(), h.count);
+	}
+
+	#[test]
+	fn record_round() {
+		let metrics = RoundMetrics::new();
+		let mut timer = PhaseTimer::start();
+		for phase in RoundPhase::ALL {
+			timer.finish(phase);
+		}
+		metrics.record(&timer);
+		metrics.record(&timer);
+
+		let histograms = metrics.histograms();
+		assert_eq!(histograms.len(), RoundPhase::ALL.len());
+		for (i, (phase, h)) in histograms.into_iter().enumerate() {
+			assert_eq!(phase, RoundPhase::ALL[i]);
+			assert_eq!(h.count, 2);
+		}
+
+		assert_eq!(metrics.insufficient_funds_rounds(), 0);
+		metrics.record_insufficient_funds();
+		assert_eq!(metrics.insufficient_funds_rounds(), 1);
+
+		assert_eq!(metrics.round_backoff_level(), 0);
+		metrics.record_round_backoff(2, Duration::from_secs(40));
+		assert_eq!(metrics.round_backoff_level(), 2);
+		assert_eq!(metrics.round_backoff_interval(), Duration::from_secs(40));
+	}
+}
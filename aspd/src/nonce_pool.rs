@@ -0,0 +1,137 @@
+
+//! A pool of musig nonces generated ahead of time.
+//!
+//! Onboards are normally cosigned with a deterministic nonce, which has to
+//! be generated when the request comes in. With a pool, that work is done
+//! in the background and a burst of onboard requests only has to sign.
+//! When the pool runs empty, requests are rejected with [NoncePoolExhausted]
+//! until the pool is refilled, instead of waiting for new nonces.
+
+use std::{cmp, fmt};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bitcoin::secp256k1::Keypair;
+use tokio::sync::Notify;
+
+use ark::musig::{self, MusigPubNonce, MusigSecNonce};
+
+/// The error returned when the nonce pool is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoncePoolExhausted;
+
+impl fmt::Display for NoncePoolExhausted {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("onboard nonce pool exhausted, try again later")
+	}
+}
+
+impl std::error::Error for NoncePoolExhausted {}
+
+pub struct NoncePool {
+	size: usize,
+	nonces: Mutex<Vec<(MusigSecNonce, MusigPubNonce)>>,
+	/// Notified every time a nonce is taken from the pool.
+	taken: Notify,
+	/// The number of times we were asked for a nonce while empty.
+	nb_exhausted: AtomicU64,
+}
+
+impl NoncePool {
+	/// Create a new empty pool holding up to `size` nonces.
+	pub fn new(size: usize) -> NoncePool {
+		NoncePool {
+			size,
+			nonces: Mutex::new(Vec::with_capacity(size)),
+			taken: Notify::new(),
+			nb_exhausted: AtomicU64::new(0),
+		}
+	}
+
+	/// The number of nonces the pool holds when full.
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	/// The number of nonces currently in the pool.
+	pub fn occupancy(&self) -> usize {
+		self.nonces.lock().unwrap().len()
+	}
+
+	/// The number of times a nonce was requested while the pool was empty.
+	pub fn nb_exhausted(&self) -> u64 {
+		self.nb_exhausted.load(Ordering::Relaxed)
+	}
+
+	/// Take a nonce pair from the pool.
+	///
+	/// The secret nonce must be used for a single signature only.
+	pub fn take(&self) -> Result<(MusigSecNonce, MusigPubNonce), NoncePoolExhausted> {
+		let ret = self.nonces.lock().unwrap().pop();
+		self.taken.notify_one();
+		ret.ok_or_else(|| {
+			self.nb_exhausted.fetch_add(1, Ordering::Relaxed);
+			NoncePoolExhausted
+		})
+	}
+
+	/// Fill the pool up to its size with new nonces.
+	///
+	/// Returns the number of nonces that were generated.
+	pub fn refill(&self, key: &Keypair) -> usize {
+		let missing = self.size.saturating_sub(self.occupancy());
+		// Don't hold the lock while generating.
+		let new = (0..missing).map(|_| musig::nonce_pair(key)).collect::<Vec<_>>();
+		let mut nonces = self.nonces.lock().unwrap();
+		let nb = cmp::min(new.len(), self.size.saturating_sub(nonces.len()));
+		nonces.extend(new.into_iter().take(nb));
+		nb
+	}
+
+	/// Refill the pool every time nonces are taken from it.
+	///
+	/// Runs forever, wrap it in a select to stop it.
+	pub async fn run_refill(&self, key: &Keypair) {
+		loop {
+			self.taken.notified().await;
+			let nb = self.refill(key);
+			trace!("Refilled onboard nonce pool with {} nonces", nb);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use ark::onboard;
+	use ark::test_util::onboard_user_part;
+
+	use super::*;
+
+	#[test]
+	fn exhaust_and_refill() {
+		let key = Keypair::new(&ark::util::SECP, &mut bitcoin::secp256k1::rand::thread_rng());
+		let pool = NoncePool::new(3);
+		assert_eq!(pool.occupancy(), 0);
+		assert_eq!(pool.take().unwrap_err(), NoncePoolExhausted);
+		assert_eq!(pool.nb_exhausted(), 1);
+
+		assert_eq!(pool.refill(&key), 3);
+		assert_eq!(pool.occupancy(), 3);
+		assert_eq!(pool.refill(&key), 0);
+
+		let user = Keypair::new(&ark::util::SECP, &mut bitcoin::secp256k1::rand::thread_rng());
+		for i in 0..3 {
+			let part = onboard_user_part(user.public_key(), key.public_key(), i);
+			let (sec, public) = pool.take().unwrap();
+			let asp = onboard::new_asp_with_nonce(&part, &key, sec, public);
+			assert!(onboard::verify_asp(&part, &asp, key.public_key()));
+		}
+		assert_eq!(pool.occupancy(), 0);
+		assert_eq!(pool.take().unwrap_err(), NoncePoolExhausted);
+		assert_eq!(pool.nb_exhausted(), 2);
+
+		assert_eq!(pool.refill(&key), 3);
+		pool.take().unwrap();
+		assert_eq!(pool.occupancy(), 2);
+	}
+}
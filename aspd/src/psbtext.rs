@@ -1,18 +1,36 @@
 
+//! Proprietary PSBT fields holding aspd metadata.
+//!
+//! External tooling, f.e. a [crate::Signer] that signs the round utxo
+//! inputs of sweep txs offline, can read these fields with [PsbtInputExt]
+//! and strip them with [PsbtExt] before sharing a PSBT with third parties.
+//!
+//! All fields are BIP-174 proprietary keys with the prefix `aspd`. In
+//! version [PSBT_EXT_VERSION] of the layout, these are:
+//!
+//! - global, subtype 0: the layout version, see [ark::psbtext].
+//! - input, subtype 1: the round meta of an input spending a round utxo.
+//!   The key is the round txid, the value the CBOR encoding of [RoundMeta].
 
 use std::borrow::BorrowMut;
+use std::fmt;
 
-use anyhow::Context;
+use ark::psbtext;
 use bitcoin::{psbt, Txid};
-use bitcoin::hashes::Hash;
+use bitcoin::hashes::{self, Hash};
+use bitcoin::hex::DisplayHex;
 
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// What kind of round utxo a PSBT input spends.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum RoundMeta {
 	Connector,
 	Vtxo,
 }
 
+/// The version of the layout of our proprietary PSBT fields.
+pub const PSBT_EXT_VERSION: u8 = 1;
+
 const PROP_KEY_PREFIX: &'static [u8] = "aspd".as_bytes();
 
 enum PropKey {
@@ -20,10 +38,38 @@ enum PropKey {
 }
 
 fn prop_key_round_meta(id: Txid) -> psbt::raw::ProprietaryKey {
-	psbt::raw::ProprietaryKey {
-		prefix: PROP_KEY_PREFIX.to_vec(),
-		subtype: PropKey::RoundMeta as u8,
-		key: id[..].to_vec(),
+	psbtext::prop_key(PROP_KEY_PREFIX, PropKey::RoundMeta as u8, id[..].to_vec())
+}
+
+/// Error when reading our proprietary fields from a PSBT.
+#[derive(Debug)]
+pub enum PsbtExtError {
+	/// The key of the round meta field doesn't hold a valid round txid.
+	InvalidRoundTxid(hashes::FromSliceError),
+	/// The value of the round meta field doesn't decode.
+	InvalidRoundMeta(String),
+	/// The value of the version field isn't a single byte.
+	InvalidVersion(Vec<u8>),
+}
+
+impl fmt::Display for PsbtExtError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PsbtExtError::InvalidRoundTxid(e) => write!(f, "invalid round txid in psbt: {}", e),
+			PsbtExtError::InvalidRoundMeta(e) => write!(f, "invalid round meta in psbt: {}", e),
+			PsbtExtError::InvalidVersion(v) => {
+				write!(f, "invalid psbt field layout version: {}", v.as_hex())
+			},
+		}
+	}
+}
+
+impl std::error::Error for PsbtExtError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			PsbtExtError::InvalidRoundTxid(e) => Some(e),
+			PsbtExtError::InvalidRoundMeta(_) | PsbtExtError::InvalidVersion(_) => None,
+		}
 	}
 }
 
@@ -34,11 +80,12 @@ pub trait PsbtInputExt: BorrowMut<psbt::Input> {
 		self.borrow_mut().proprietary.insert(prop_key_round_meta(round_id), buf);
 	}
 
-	fn get_round_meta(&self) -> anyhow::Result<Option<(Txid, RoundMeta)>> {
+	fn get_round_meta(&self) -> Result<Option<(Txid, RoundMeta)>, PsbtExtError> {
 		for (key, val) in &self.borrow().proprietary {
-			if key.prefix == PROP_KEY_PREFIX && key.subtype == PropKey::RoundMeta as u8 {
-				let txid = Txid::from_slice(&key.key).context("corrupt psbt: Txid")?;
-				let meta = ciborium::from_reader(&val[..]).context("corrupt psbt: RoundMeta")?;
+			if psbtext::is_ours(PROP_KEY_PREFIX, key) && key.subtype == PropKey::RoundMeta as u8 {
+				let txid = Txid::from_slice(&key.key).map_err(PsbtExtError::InvalidRoundTxid)?;
+				let meta = ciborium::from_reader(&val[..])
+					.map_err(|e| PsbtExtError::InvalidRoundMeta(e.to_string()))?;
 				return Ok(Some((txid, meta)));
 			}
 		}
@@ -47,3 +94,104 @@ pub trait PsbtInputExt: BorrowMut<psbt::Input> {
 }
 
 impl PsbtInputExt for psbt::Input {}
+
+pub trait PsbtExt: BorrowMut<psbt::Psbt> {
+	/// Write [PSBT_EXT_VERSION] into the PSBT.
+	fn set_ext_version(&mut self) {
+		psbtext::set_version(self.borrow_mut(), PROP_KEY_PREFIX, PSBT_EXT_VERSION);
+	}
+
+	/// The version of the layout of our fields in the PSBT, if it has one.
+	fn get_ext_version(&self) -> Result<Option<u8>, PsbtExtError> {
+		psbtext::get_version(self.borrow(), PROP_KEY_PREFIX).map_err(PsbtExtError::InvalidVersion)
+	}
+
+	/// Remove all our proprietary fields from the PSBT, returning how many
+	/// were removed. Proprietary fields of others are kept.
+	fn strip_proprietary(&mut self) -> usize {
+		psbtext::strip_proprietary(self.borrow_mut(), PROP_KEY_PREFIX)
+	}
+}
+
+impl PsbtExt for psbt::Psbt {}
+
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use bitcoin::OutPoint;
+	use bitcoin::hashes::sha256;
+
+	use ark::test_util::dummy_psbt;
+
+	/// A PSBT with the given number of inputs.
+	fn test_psbt(nb_inputs: u32) -> psbt::Psbt {
+		dummy_psbt((0..nb_inputs).map(|i| OutPoint::new(Txid::all_zeros(), i)))
+	}
+
+	/// Generate a bunch of deterministic, but arbitrary, txids.
+	fn txids() -> impl Iterator<Item = Txid> {
+		(0u32..256).map(|i| Txid::from_byte_array(
+			sha256::Hash::hash(&i.to_le_bytes()).to_byte_array(),
+		)).chain([Txid::all_zeros(), Txid::from_byte_array([0xff; 32])])
+	}
+
+	#[test]
+	fn round_meta_roundtrip() {
+		for txid in txids() {
+			let mut psbt = test_psbt(3);
+			psbt.inputs[0].set_round_meta(txid, RoundMeta::Vtxo);
+			psbt.inputs[1].set_round_meta(txid, RoundMeta::Connector);
+
+			psbt.set_ext_version();
+
+			let decoded = psbt::Psbt::deserialize(&psbt.serialize()).unwrap();
+			assert_eq!(decoded.get_ext_version().unwrap(), Some(PSBT_EXT_VERSION));
+			assert_eq!(decoded.inputs[0].get_round_meta().unwrap(), Some((txid, RoundMeta::Vtxo)));
+			assert_eq!(
+				decoded.inputs[1].get_round_meta().unwrap(), Some((txid, RoundMeta::Connector)),
+			);
+			assert_eq!(decoded.inputs[2].get_round_meta().unwrap(), None);
+		}
+	}
+
+	#[test]
+	fn strip_proprietary() {
+		let other_key = psbt::raw::ProprietaryKey {
+			prefix: "other".as_bytes().to_vec(),
+			subtype: PropKey::RoundMeta as u8,
+			key: vec![1, 2, 3],
+		};
+		let mut psbt = test_psbt(2);
+		psbt.inputs[0].set_round_meta(Txid::all_zeros(), RoundMeta::Vtxo);
+		psbt.inputs[1].set_round_meta(Txid::all_zeros(), RoundMeta::Connector);
+		psbt.inputs[1].proprietary.insert(other_key.clone(), vec![4]);
+		psbt.proprietary.insert(prop_key_round_meta(Txid::all_zeros()), vec![]);
+		psbt.set_ext_version();
+
+		let mut decoded = psbt::Psbt::deserialize(&psbt.serialize()).unwrap();
+		assert_eq!(decoded.strip_proprietary(), 4);
+		assert_eq!(decoded.strip_proprietary(), 0);
+		let decoded = psbt::Psbt::deserialize(&decoded.serialize()).unwrap();
+		assert_eq!(decoded.inputs[0].get_round_meta().unwrap(), None);
+		assert_eq!(decoded.inputs[1].get_round_meta().unwrap(), None);
+		assert!(decoded.proprietary.is_empty());
+		assert_eq!(decoded.get_ext_version().unwrap(), None);
+		assert_eq!(decoded.inputs[1].proprietary.get(&other_key), Some(&vec![4]));
+		assert_eq!(decoded.unsigned_tx, psbt.unsigned_tx);
+	}
+
+	#[test]
+	fn round_meta_invalid() {
+		let mut input = psbt::Input::default();
+		let mut key = prop_key_round_meta(Txid::all_zeros());
+		key.key.pop();
+		input.proprietary.insert(key, vec![]);
+		assert!(matches!(input.get_round_meta(), Err(PsbtExtError::InvalidRoundTxid(_))));
+
+		let mut input = psbt::Input::default();
+		input.proprietary.insert(prop_key_round_meta(Txid::all_zeros()), vec![0xff, 0x00]);
+		assert!(matches!(input.get_round_meta(), Err(PsbtExtError::InvalidRoundMeta(_))));
+	}
+}
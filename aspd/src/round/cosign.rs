@@ -0,0 +1,162 @@
+
+//! The ASP's members of the cosign group of the vtxo tree.
+//!
+//! The vtxo tree is cosigned with the musig aggregate of the keys of all
+//! users in the round and a set of ASP keys. By default that set has a
+//! single member, but the coordinator handles more than one.
+//!
+//! All members are one-time keys generated by this ASP for each round
+//! attempt, their public keys are stored with the round. Keys of other
+//! parties, like the members of a federation of ASPs, can't be configured,
+//! that would need a protocol to collect their nonces and partial
+//! signatures.
+
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{rand, Keypair, PublicKey};
+use bitcoin::sighash::TapSighash;
+
+use ark::musig;
+
+use crate::SECP;
+
+/// The ASP members of the cosign group of a round attempt.
+pub struct AspCosigners {
+	keys: Vec<Keypair>,
+}
+
+impl AspCosigners {
+	/// Generate a set of fresh one-time keys.
+	pub fn generate(nb_members: usize) -> AspCosigners {
+		assert!(nb_members > 0, "the cosign group needs at least one ASP member");
+		AspCosigners {
+			keys: (0..nb_members).map(|_| Keypair::new(&SECP, &mut rand::thread_rng())).collect(),
+		}
+	}
+
+	pub fn pubkeys(&self) -> impl Iterator<Item = PublicKey> + '_ {
+		self.keys.iter().map(|k| k.public_key())
+	}
+
+	/// Generate the nonces of every member for the given number of nodes.
+	///
+	/// The nonces are returned per member, in the order of [AspCosigners::pubkeys].
+	pub fn nonces(
+		&self,
+		nb_nodes: usize,
+	) -> (Vec<Vec<musig::MusigSecNonce>>, Vec<Vec<musig::MusigPubNonce>>) {
+		self.keys.iter().map(|key| {
+			(0..nb_nodes).map(|_| musig::nonce_pair(key)).unzip::<_, _, Vec<_>, Vec<_>>()
+		}).unzip()
+	}
+
+	/// Make the partial signatures of every member on all nodes.
+	///
+	/// The signatures are returned per member, in the order of [AspCosigners::pubkeys].
+	pub fn partial_sign(
+		&self,
+		cosigners: &[PublicKey],
+		agg_nonces: &[musig::MusigAggNonce],
+		sighashes: &[TapSighash],
+		taptweak: Option<[u8; 32]>,
+		sec_nonces: Vec<Vec<musig::MusigSecNonce>>,
+	) -> Vec<Vec<musig::MusigPartialSignature>> {
+		assert_eq!(sec_nonces.len(), self.keys.len());
+		self.keys.iter().zip(sec_nonces).map(|(key, sec_nonces)| {
+			sec_nonces.into_iter().enumerate().map(|(i, sec_nonce)| {
+				musig::partial_sign(
+					cosigners.iter().copied(),
+					agg_nonces[i],
+					key,
+					sec_nonce,
+					sighashes[i].to_byte_array(),
+					taptweak,
+					None,
+				).0
+			}).collect()
+		}).collect()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use std::str::FromStr;
+
+	use bitcoin::{Amount, OutPoint};
+	use ark::{ExitTimelockType, VtxoRequest, VtxoScriptType};
+	use ark::tree::signed::{OutputKeyPolicy, SignedVtxoTree, VtxoTreeSpec};
+
+	use crate::round::{aggregate_vtxo_sigs, validate_partial_vtxo_sigs};
+
+	#[test]
+	fn two_member_cosign_group() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let asp_cosigners = AspCosigners::generate(2);
+		let cosigners = asp_cosigners.pubkeys().chain(Some(user_key.public_key()))
+			.collect::<Vec<_>>();
+		assert_eq!(cosigners.len(), 3);
+
+		let vtxos = (0..3).map(|i| VtxoRequest {
+			pubkey: user_key.public_key(),
+			amount: Amount::from_sat(10_000 + i),
+		}).collect();
+		let spec = VtxoTreeSpec::new(
+			vtxos,
+			musig::combine_keys(cosigners.iter().copied()),
+			asp_key.public_key(),
+			1_000,
+			144,
+			false,
+			OutputKeyPolicy::MerkleRootTweak,
+			ExitTimelockType::Relative,
+			VtxoScriptType::Taproot,
+		);
+		let utxo = OutPoint::from_str(
+			"0000000000000000000000000000000000000000000000000000000000000001:0",
+		).unwrap();
+		let sighashes = spec.sighashes(utxo);
+		let nb_nodes = sighashes.len();
+		let tweak = spec.cosign_taptweak().map(|t| t.to_byte_array());
+
+		let (asp_sec_nonces, asp_pub_nonces) = asp_cosigners.nonces(nb_nodes);
+		let (user_sec_nonces, user_pub_nonces) = (0..nb_nodes)
+			.map(|_| musig::nonce_pair(&user_key))
+			.unzip::<_, _, Vec<_>, Vec<_>>();
+		let agg_nonces = (0..nb_nodes).map(|i| {
+			musig::nonce_agg(asp_pub_nonces.iter().map(|n| n[i]).chain(Some(user_pub_nonces[i])))
+		}).collect::<Vec<_>>();
+
+		let asp_sigs = asp_cosigners.partial_sign(
+			&cosigners, &agg_nonces, &sighashes, tweak, asp_sec_nonces,
+		);
+		for (pk, (nonces, sigs)) in asp_cosigners.pubkeys().zip(asp_pub_nonces.iter().zip(&asp_sigs)) {
+			assert!(validate_partial_vtxo_sigs(
+				cosigners.iter().copied(), &agg_nonces, &sighashes, tweak, pk, nonces, sigs,
+			));
+		}
+		let user_sigs = user_sec_nonces.into_iter().enumerate().map(|(i, sec)| {
+			musig::partial_sign(
+				cosigners.iter().copied(), agg_nonces[i], &user_key, sec,
+				sighashes[i].to_byte_array(), tweak, None,
+			).0
+		}).collect::<Vec<_>>();
+
+		let key_agg = musig::tweaked_key_agg(cosigners.iter().copied(), tweak.unwrap()).0;
+		let aggregate = |members: usize| {
+			let node_sigs = (0..nb_nodes).map(|i| {
+				asp_sigs.iter().take(members).map(|s| s[i].clone())
+					.chain(Some(user_sigs[i].clone()))
+					.collect()
+			}).collect::<Vec<Vec<_>>>();
+			let sigs = aggregate_vtxo_sigs(&key_agg, &agg_nonces, &sighashes, &node_sigs, nb_nodes, |_| {});
+			SignedVtxoTree::new(spec.clone(), utxo, sigs)
+		};
+
+		// With the partial signatures of both ASP members, the tree is spendable.
+		aggregate(2).validate_signatures().unwrap();
+		// Missing one of them, it's not.
+		aggregate(1).validate_signatures().unwrap_err();
+	}
+}
@@ -1,32 +1,189 @@
 
+mod cosign;
+mod scheduler;
 
+use std::{cmp, fmt};
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::iter;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{atomic, Arc};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
-use bdk_bitcoind_rpc::bitcoincore_rpc::RpcApi;
-use bitcoin::{Amount, FeeRate, OutPoint, Sequence, Transaction};
+use bdk_bitcoind_rpc::bitcoincore_rpc::{self, RpcApi};
+use bitcoin::{Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxOut, Weight};
 use bitcoin::hashes::Hash;
 use bitcoin::locktime::absolute::LockTime;
-use bitcoin::secp256k1::{rand, Keypair, PublicKey};
+use bitcoin::policy::MAX_STANDARD_TX_WEIGHT;
+use bitcoin::secp256k1::{rand, schnorr, PublicKey};
+use bitcoin::secp256k1::rand::SeedableRng;
+use bitcoin::secp256k1::rand::seq::SliceRandom;
 use bitcoin::sighash::TapSighash;
+use tokio::sync::oneshot;
 
 use ark::{musig, OffboardRequest, VtxoRequest, Vtxo, VtxoId};
 use ark::connectors::ConnectorChain;
 use ark::tree::signed::{SignedVtxoTree, VtxoTreeSpec};
 
-use crate::{SECP, App};
+use crate::{SECP, App, Config, SpendableUtxo, SweepMode};
+use crate::chain::{RPC_VERIFY_ALREADY_IN_CHAIN, RPC_VERIFY_REJECTED};
 use crate::database::ForfeitVtxo;
+use crate::events::Event;
+use crate::fee_scheme::check_truc_policy;
+use crate::metrics::{PhaseTimer, RoundPhase};
+use self::cosign::AspCosigners;
+use self::scheduler::RoundScheduler;
+
+/// The output index of the fee anchor in the round tx, for fee schemes
+/// that use one.
+///
+/// The vtxo tree output is the first output and the connector output the second.
+pub const ROUND_TX_ANCHOR_VOUT: u32 = 2;
+
+/// We don't bump a rejected round tx beyond this many times the configured
+/// round tx fee rate.
+const MAX_ROUND_FEERATE_MULTIPLIER: u64 = 16;
+
+/// Where the change of our wallet in round txs goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundChange {
+	/// A change output of the round tx to our onchain wallet.
+	Onchain,
+	/// A vtxo in the round's own vtxo tree that belongs to us.
+	///
+	/// This saves an output on the round tx, and the value comes back to
+	/// our wallet when we sweep the round after it expired. Until then,
+	/// the change can't be used to fund new rounds, so the vtxo holds at
+	/// most [Config::round_change_vtxo_max] and any change above that
+	/// still goes onchain. Round txs without
+	/// change can't be bumped from our wallet, so this doesn't go together
+	/// with the [RoundFeeScheme::CpfpChange] fee scheme.
+	///
+	/// [RoundFeeScheme::CpfpChange]: crate::RoundFeeScheme::CpfpChange
+	Vtxo,
+}
+
+impl FromStr for RoundChange {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"onchain" => Ok(RoundChange::Onchain),
+			"vtxo" => Ok(RoundChange::Vtxo),
+			_ => bail!("unknown round change destination: {}", s),
+		}
+	}
+}
+
+impl fmt::Display for RoundChange {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			RoundChange::Onchain => "onchain",
+			RoundChange::Vtxo => "vtxo",
+		})
+	}
+}
+
+/// How the vtxo tree leaves and the offboard outputs of rounds are ordered.
+///
+/// Our own outputs of the round tx stay in their fixed positions, so this
+/// only changes the order of the vtxos in the tree and of the offboards
+/// after our outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundOutputOrdering {
+	/// In the order in which the payments were submitted.
+	Submission,
+	/// Sorted by amount and then by pubkey or output script, like BIP69.
+	Bip69,
+	/// Shuffled with a fresh random seed for every round.
+	Shuffle,
+}
+
+impl FromStr for RoundOutputOrdering {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"submission" => Ok(RoundOutputOrdering::Submission),
+			"bip69" => Ok(RoundOutputOrdering::Bip69),
+			"shuffle" => Ok(RoundOutputOrdering::Shuffle),
+			_ => bail!("unknown round output ordering: {}", s),
+		}
+	}
+}
+
+impl fmt::Display for RoundOutputOrdering {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			RoundOutputOrdering::Submission => "submission",
+			RoundOutputOrdering::Bip69 => "bip69",
+			RoundOutputOrdering::Shuffle => "shuffle",
+		})
+	}
+}
+
+/// Order the vtxo requests of a round, keeping the origin height of
+/// every request next to it.
+fn order_vtxo_requests(
+	ordering: RoundOutputOrdering,
+	vtxos: &mut Vec<VtxoRequest>,
+	origins: &mut Vec<u32>,
+	rng: &mut impl rand::Rng,
+) {
+	assert_eq!(vtxos.len(), origins.len());
+	let mut pairs = vtxos.drain(..).zip(origins.drain(..)).collect::<Vec<_>>();
+	match ordering {
+		RoundOutputOrdering::Submission => {},
+		RoundOutputOrdering::Bip69 => pairs.sort_by(|(a, _), (b, _)| {
+			a.amount.cmp(&b.amount).then_with(|| a.pubkey.serialize().cmp(&b.pubkey.serialize()))
+		}),
+		RoundOutputOrdering::Shuffle => pairs.shuffle(rng),
+	}
+	let (new_vtxos, new_origins) = pairs.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+	*vtxos = new_vtxos;
+	*origins = new_origins;
+}
+
+/// Order the offboard outputs of a round.
+fn order_offboards(
+	ordering: RoundOutputOrdering,
+	offboards: &mut [OffboardRequest],
+	rng: &mut impl rand::Rng,
+) {
+	match ordering {
+		RoundOutputOrdering::Submission => {},
+		RoundOutputOrdering::Bip69 => offboards.sort_by(|a, b| {
+			a.amount.cmp(&b.amount).then_with(|| a.script_pubkey.cmp(&b.script_pubkey))
+		}),
+		RoundOutputOrdering::Shuffle => offboards.shuffle(rng),
+	}
+}
+
+/// The connector chain of the round proposal we're gathering forfeits for.
+#[derive(Debug, Clone, Copy)]
+pub struct ProposedConnectors {
+	pub round_id: u64,
+	pub len: usize,
+	pub utxo: OutPoint,
+	pub connector_value: Amount,
+}
 
 #[derive(Debug, Clone)]
 pub enum RoundEvent {
 	Start {
 		id: u64,
+		/// The sequence number of the round, see [crate::database::Db::next_round_seq].
+		seq: u64,
 		offboard_feerate: FeeRate,
+		/// Payment submissions have to carry this epoch to be accepted.
+		epoch: u64,
 	},
 	VtxoProposal {
 		id: u64,
+		seq: u64,
 		round_tx: Transaction,
 		vtxos_spec: VtxoTreeSpec,
 		cosigners: Vec<PublicKey>,
@@ -34,17 +191,153 @@ pub enum RoundEvent {
 	},
 	RoundProposal {
 		id: u64,
+		seq: u64,
 		round_tx: Transaction,
 		vtxos: SignedVtxoTree,
 		forfeit_nonces: HashMap<VtxoId, Vec<musig::MusigPubNonce>>,
 	},
 	Finished {
 		id: u64,
+		seq: u64,
 		round_tx: Transaction,
 		vtxos: SignedVtxoTree,
 	},
+	Failed {
+		id: u64,
+		seq: u64,
+		reason: RoundFailReason,
+	},
 }
 
+/// Why we gave up on a round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoundFailReason {
+	/// Our wallet doesn't have enough confirmed funds to fund the round tx.
+	InsufficientAspFunds {
+		required: Amount,
+		available: Amount,
+	},
+	/// Any other failure.
+	Other(String),
+}
+
+impl fmt::Display for RoundFailReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RoundFailReason::InsufficientAspFunds { required, available } => write!(f,
+				"insufficient ASP funds: round needs {}, only {} available", required, available,
+			),
+			RoundFailReason::Other(reason) => f.write_str(reason),
+		}
+	}
+}
+
+/// How to recover when bitcoind refuses our round tx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BroadcastRecovery {
+	/// Bitcoind already has the tx or we couldn't reach it. In the latter
+	/// case the tx will be rebroadcast on the next wallet sync.
+	Proceed,
+	/// The tx doesn't pay enough fees, retry with a higher fee rate.
+	BumpFee,
+	/// The tx is invalid, the round can't be finished.
+	Abort,
+}
+
+impl BroadcastRecovery {
+	fn from_error(err: &bitcoincore_rpc::Error) -> BroadcastRecovery {
+		let e = match err {
+			bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(e)) => e,
+			_ => return BroadcastRecovery::Proceed,
+		};
+		match e.code {
+			RPC_VERIFY_ALREADY_IN_CHAIN => BroadcastRecovery::Proceed,
+			RPC_VERIFY_REJECTED => BroadcastRecovery::from_reject_reason(&e.message),
+			_ => BroadcastRecovery::Abort,
+		}
+	}
+
+	/// Decide based on the reject reason of bitcoind's mempool policy.
+	fn from_reject_reason(reason: &str) -> BroadcastRecovery {
+		if reason.contains("txn-already-in-mempool") || reason.contains("txn-already-known") {
+			BroadcastRecovery::Proceed
+		} else if reason.contains("min relay fee not met")
+			|| reason.contains("mempool min fee not met")
+			|| reason.contains("insufficient fee")
+		{
+			BroadcastRecovery::BumpFee
+		} else {
+			// F.e. missing or already spent inputs.
+			BroadcastRecovery::Abort
+		}
+	}
+}
+
+/// Check whether bitcoind would accept the tx using testmempoolaccept.
+///
+/// Returns the reject reason if it wouldn't. If the check itself fails,
+/// we don't block the broadcast.
+fn precheck_round_tx(app: &App, tx: &Transaction) -> Option<String> {
+	match app.bitcoind.test_mempool_accept(&[tx]) {
+		Ok(res) => res.into_iter().next().filter(|r| !r.allowed).map(|r| {
+			r.reject_reason.unwrap_or_else(|| "no reason given".into())
+		}),
+		Err(e) => {
+			warn!("Failed to run testmempoolaccept on round tx: {}", e);
+			None
+		},
+	}
+}
+
+/// Why the coordinator rejected a round input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+	/// The payment was for an earlier round start.
+	StaleEpoch,
+	/// An input vtxo is already used by another payment in the round.
+	DoubleSpend,
+	/// An output is below the dust limit.
+	Dust,
+	/// The round already stopped accepting this kind of input.
+	PastDeadline,
+	/// The round has no room left for the outputs.
+	OverCapacity,
+	/// A forfeit signature is invalid.
+	BadForfeitSignature,
+	/// An input vtxo is not accepted in rounds.
+	InvalidInput,
+	/// The payment doesn't add up.
+	InvalidPayment,
+	/// The round has the maximum number of inputs.
+	RoundFull,
+	/// There is no payment with the input in the round.
+	UnknownPayment,
+}
+
+/// A rejected round input, with the reason and a message for the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputRejected {
+	pub reason: RejectReason,
+	pub message: String,
+}
+
+impl InputRejected {
+	pub fn new(reason: RejectReason, message: impl fmt::Display) -> InputRejected {
+		InputRejected { reason, message: message.to_string() }
+	}
+}
+
+impl fmt::Display for InputRejected {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:?}: {}", self.reason, self.message)
+	}
+}
+
+impl std::error::Error for InputRejected {}
+
+/// The channel the coordinator answers a round input on.
+pub type InputResponse = oneshot::Sender<Result<(), InputRejected>>;
+
 #[derive(Debug)]
 pub enum RoundInput {
 	RegisterPayment {
@@ -53,6 +346,11 @@ pub enum RoundInput {
 		offboards: Vec<OffboardRequest>,
 		cosign_pubkey: PublicKey,
 		public_nonces: Vec<musig::MusigPubNonce>,
+		/// The earliest creation height of the inputs, carried over to the outputs.
+		origin_height: u32,
+		/// The epoch of the round start the payment was submitted for.
+		epoch: u64,
+		response: InputResponse,
 	},
 	VtxoSignatures {
 		pubkey: PublicKey,
@@ -60,7 +358,111 @@ pub enum RoundInput {
 	},
 	ForfeitSignatures {
 		signatures: Vec<(VtxoId, Vec<musig::MusigPubNonce>, Vec<musig::MusigPartialSignature>)>,
+		response: InputResponse,
+	},
+	/// Withdraw the payment with the given input vtxo from the round.
+	CancelPayment {
+		input: VtxoId,
+		/// The key the cancellation was signed with.
+		user_pubkey: PublicKey,
+		/// The cosign pubkey of the payment to cancel.
+		cosign_pubkey: PublicKey,
+		/// The epoch of the round start the payment was submitted for.
+		epoch: u64,
+		response: InputResponse,
 	},
+	PendingPayments {
+		response: oneshot::Sender<Vec<PendingPayment>>,
+	},
+}
+
+impl RoundInput {
+	/// Reject an input that arrived in a round phase that doesn't take it.
+	fn reject_out_of_phase(self, round_epoch: u64) {
+		let response = match self {
+			RoundInput::RegisterPayment { epoch, response, .. }
+				| RoundInput::CancelPayment { epoch, response, .. } if epoch != round_epoch =>
+			{
+				let _ = response.send(Err(InputRejected::new(RejectReason::StaleEpoch,
+					format!("stale round epoch {}, wait for the next round start", epoch),
+				)));
+				return;
+			},
+			RoundInput::RegisterPayment { response, .. } => response,
+			RoundInput::ForfeitSignatures { response, .. } => response,
+			RoundInput::CancelPayment { response, .. } => response,
+			RoundInput::PendingPayments { response } => {
+				// Only the submit phase has pending payments.
+				let _ = response.send(Vec::new());
+				return;
+			},
+			RoundInput::VtxoSignatures { .. } => {
+				trace!("unexpected message");
+				return;
+			},
+		};
+		let _ = response.send(Err(InputRejected::new(RejectReason::PastDeadline,
+			"the round doesn't accept this input at this stage",
+		)));
+	}
+}
+
+/// Announce the start of a round (attempt) with a fresh epoch.
+///
+/// Only payments submitted for the returned epoch are accepted, so that
+/// submissions for earlier rounds can't be replayed into this one.
+fn announce_round_start(
+	app: &App,
+	round_id: u64,
+	round_seq: u64,
+	offboard_feerate: FeeRate,
+) -> u64 {
+	let epoch = rand::random::<u64>();
+	// Reset before the new epoch is visible, so that submissions for it
+	// don't see the previous round as full.
+	app.rounds().round_full.store(false, atomic::Ordering::SeqCst);
+	app.rounds().round_epoch.store(epoch, atomic::Ordering::SeqCst);
+	let _ = app.rounds().round_event_tx.send(RoundEvent::Start {
+		id: round_id, seq: round_seq, offboard_feerate, epoch,
+	});
+	epoch
+}
+
+/// Store when the next round is expected to start, as a hint for
+/// payments that don't fit in the current round.
+fn store_next_round_start(app: &App, scheduler: &RoundScheduler) {
+	let wait = scheduler.next_round_start().saturating_duration_since(tokio::time::Instant::now());
+	let start = (SystemTime::now() + wait).duration_since(UNIX_EPOCH).unwrap();
+	app.rounds().next_round_start.store(start.as_millis() as u64, atomic::Ordering::SeqCst);
+}
+
+/// A request to start a round right away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundTrigger {
+	/// The absolute expiry height of the round's vtxos, instead of
+	/// [Config::vtxo_expiry_delta] blocks after the round starts.
+	pub expiry_height: Option<u32>,
+}
+
+/// The expiry height of the vtxos of a round built at the given tip.
+///
+/// An explicit expiry height has to leave users at least the exit delta to
+/// exit their vtxos and can't be later than the expiry delta would put it,
+/// so that the ASP never locks up its funds for longer than configured.
+pub fn round_expiry_height(cfg: &Config, tip: u32, explicit: Option<u32>) -> anyhow::Result<u32> {
+	let default = tip + cfg.vtxo_expiry_delta as u32;
+	let Some(height) = explicit else {
+		return Ok(default);
+	};
+	ensure!(height > tip + cfg.vtxo_exit_delta as u32,
+		"expiry height {} is too close to the tip {}, must be more than {} blocks away",
+		height, tip, cfg.vtxo_exit_delta,
+	);
+	ensure!(height <= default,
+		"expiry height {} is beyond the maximum of {} ({} blocks after the tip {})",
+		height, default, cfg.vtxo_expiry_delta, tip,
+	);
+	Ok(height)
 }
 
 fn validate_payment(
@@ -68,16 +470,16 @@ fn validate_payment(
 	outputs: &[VtxoRequest],
 	offboards: &[OffboardRequest],
 	offboard_feerate: FeeRate,
-) -> anyhow::Result<()> {
+) -> Result<(), InputRejected> {
 	let mut in_set = HashSet::with_capacity(inputs.len());
 	let mut in_sum = Amount::ZERO;
 	for input in inputs {
 		in_sum += input.amount();
 		if in_sum > Amount::MAX_MONEY{
-			bail!("total input amount overflow");
+			return Err(InputRejected::new(RejectReason::InvalidPayment, "total input amount overflow"));
 		}
 		if !in_set.insert(input.id()) {
-			bail!("duplicate input");
+			return Err(InputRejected::new(RejectReason::DoubleSpend, "duplicate input"));
 		}
 	}
 
@@ -85,34 +487,157 @@ fn validate_payment(
 	for output in outputs {
 		out_sum += output.amount;
 		if out_sum > in_sum {
-			bail!("total output amount exceeds total input amount");
+			return Err(InputRejected::new(RejectReason::InvalidPayment,
+				"total output amount exceeds total input amount",
+			));
 		}
 	}
 	for offboard in offboards {
 		let fee = match offboard.fee(offboard_feerate) {
 			Some(v) => v,
-			None => bail!("invalid offboard address"),
+			None => {
+				return Err(InputRejected::new(RejectReason::InvalidPayment, "invalid offboard address"));
+			},
 		};
 		out_sum += offboard.amount + fee;
 		if out_sum > in_sum {
-			bail!("total output amount (with offboards) exceeds total input amount");
+			return Err(InputRejected::new(RejectReason::InvalidPayment,
+				"total output amount (with offboards) exceeds total input amount",
+			));
 		}
 	}
 
 	Ok(())
 }
 
+// Version, locktime, input and output counts and the segwit marker.
+const TX_OVERHEAD: Weight = Weight::from_wu(4 * 10 + 2);
+// Outpoint, empty script sig and sequence.
+const TXIN_BASE: Weight = Weight::from_wu(4 * 41);
+const WALLET_INPUT: Weight = Weight::from_wu(4 * 41 + 66);
+const P2TR_OUTPUT: Weight = Weight::from_wu(4 * 43);
+const CHANGE_OUTPUT: Weight = P2TR_OUTPUT;
+
+/// The estimated weight of a round tx without offboards and sweeps.
+///
+/// Like [required_round_funds], this assumes a single wallet input and
+/// a change output.
+pub fn round_tx_base_weight(cfg: &Config) -> Weight {
+	let anchor = cfg.fee_scheme.anchor_output().map(|o| o.weight()).unwrap_or(Weight::ZERO);
+	// The vtxo tree and connector outputs.
+	TX_OVERHEAD + WALLET_INPUT + CHANGE_OUTPUT + P2TR_OUTPUT + P2TR_OUTPUT + anchor
+}
+
+/// The weight the outputs of the given offboards add to the round tx.
+fn offboards_weight(offboards: &[OffboardRequest]) -> Weight {
+	offboards.iter().map(|o| {
+		TxOut { script_pubkey: o.script_pubkey.clone(), value: o.amount }.weight()
+	}).fold(Weight::ZERO, |sum, w| sum + w)
+}
+
+/// Estimate the funds our wallet has to add to the round tx.
+///
+/// This is the value of the required outputs plus the fee of the round tx,
+/// minus what the expired round utxos we sweep in it bring in. The fee
+/// estimate assumes we add a single wallet input and a change output.
+fn required_round_funds(
+	required_outputs: &[TxOut],
+	sweep_utxos: &[SpendableUtxo],
+	feerate: FeeRate,
+) -> Amount {
+	let weight = required_outputs.iter().map(|o| o.weight())
+		.chain(sweep_utxos.iter().map(|u| TXIN_BASE + u.weight))
+		.fold(TX_OVERHEAD + WALLET_INPUT + CHANGE_OUTPUT, |sum, w| sum + w);
+	let fee = feerate.fee_wu(weight).expect("no overflow");
+	let outputs = required_outputs.iter().map(|o| o.value).sum::<Amount>();
+	let swept = sweep_utxos.iter().map(|u| u.amount()).sum::<Amount>();
+	(outputs + fee).checked_sub(swept).unwrap_or(Amount::ZERO)
+}
+
+/// Check that the round tx pays exactly the outputs the round needs and
+/// that all other outputs go back to our wallet.
+///
+/// Returns the fee the round tx pays.
+fn check_round_tx_amounts(
+	psbt: &Psbt,
+	required_outputs: &[TxOut],
+	is_mine: impl Fn(&ScriptBuf) -> bool,
+	max_fee: Amount,
+) -> anyhow::Result<Amount> {
+	let tx = &psbt.unsigned_tx;
+	ensure!(psbt.inputs.len() == tx.input.len(),
+		"psbt has {} inputs, tx has {}", psbt.inputs.len(), tx.input.len(),
+	);
+
+	let mut in_sum = Amount::ZERO;
+	for (idx, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+		let value = if let Some(ref txout) = input.witness_utxo {
+			txout.value
+		} else if let Some(ref prev) = input.non_witness_utxo {
+			prev.output.get(txin.previous_output.vout as usize)
+				.with_context(|| format!("missing prevout for input {}", idx))?.value
+		} else {
+			bail!("missing utxo for input {}", idx);
+		};
+		in_sum = in_sum.checked_add(value).context("total input amount overflow")?;
+	}
+
+	ensure!(tx.output.len() >= required_outputs.len(),
+		"round tx has {} outputs, we need at least {}", tx.output.len(), required_outputs.len(),
+	);
+	for (idx, (output, required)) in tx.output.iter().zip(required_outputs).enumerate() {
+		ensure!(output == required,
+			"output {} pays {} to {}, expected {} to {}", idx,
+			output.value, output.script_pubkey, required.value, required.script_pubkey,
+		);
+	}
+	for output in &tx.output[required_outputs.len()..] {
+		ensure!(is_mine(&output.script_pubkey),
+			"round tx pays {} to foreign script {}", output.value, output.script_pubkey,
+		);
+	}
+
+	let out_sum = tx.output.iter().map(|o| o.value).sum::<Amount>();
+	let fee = in_sum.checked_sub(out_sum).with_context(|| format!(
+		"total output amount {} exceeds total input amount {}", out_sum, in_sum,
+	))?;
+	ensure!(fee <= max_fee, "round tx fee {} exceeds the maximum of {}", fee, max_fee);
+	Ok(fee)
+}
+
+/// Add a vtxo for ourselves to the tree that holds the given change, but
+/// not more than `max`.
+///
+/// The extra leaf needs more value in the tree than just the vtxo amount,
+/// this is paid for from the change as well. Returns [None] if the vtxo
+/// would be dust.
+fn change_vtxo_spec(spec: &VtxoTreeSpec, change: Amount, max: Amount) -> Option<VtxoTreeSpec> {
+	let mut ret = spec.clone();
+	ret.vtxos.push(VtxoRequest { pubkey: spec.asp_key, amount: Amount::ZERO });
+	let leaf_cost = ret.total_required_value() - spec.total_required_value();
+	let amount = cmp::min(change.checked_sub(leaf_cost)?, max);
+	if amount < ark::fee::DUST {
+		return None;
+	}
+	ret.vtxos.last_mut().unwrap().amount = amount;
+	Some(ret)
+}
+
 /// Validate the vtxo tree signatures from the given user.
 fn validate_partial_vtxo_sigs(
 	cosigners: impl IntoIterator<Item = PublicKey>,
 	agg_nonces: &[musig::MusigAggNonce],
 	sighashes: &[TapSighash],
-	taptweak: [u8; 32],
+	taptweak: Option<[u8; 32]>,
 	user_pubkey: PublicKey,
 	user_pub_nonces: &[musig::MusigPubNonce],
 	user_signatures: &[musig::MusigPartialSignature],
 ) -> bool {
-	let key_agg = musig::tweaked_key_agg(cosigners, taptweak).0;
+	let key_agg = if let Some(tweak) = taptweak {
+		musig::tweaked_key_agg(cosigners, tweak).0
+	} else {
+		musig::key_agg(cosigners)
+	};
 	for i in 0..agg_nonces.len() {
 		let session = musig::MusigSession::new(
 			&musig::SECP,
@@ -135,6 +660,38 @@ fn validate_partial_vtxo_sigs(
 	true
 }
 
+/// Aggregate the partial signatures of all cosigners into the final
+/// signatures of the vtxo tree nodes.
+///
+/// `partial_sigs` holds the partial signatures of each node. The nodes are
+/// aggregated in batches of `batch_size` and `progress` is called with the
+/// number of aggregated nodes after every batch.
+pub fn aggregate_vtxo_sigs(
+	key_agg: &musig::MusigKeyAggCache,
+	agg_nonces: &[musig::MusigAggNonce],
+	sighashes: &[TapSighash],
+	partial_sigs: &[Vec<musig::MusigPartialSignature>],
+	batch_size: usize,
+	mut progress: impl FnMut(usize),
+) -> Vec<schnorr::Signature> {
+	assert!(batch_size > 0, "zero batch size");
+	let mut ret = Vec::with_capacity(partial_sigs.len());
+	for batch in partial_sigs.chunks(batch_size) {
+		for sigs in batch {
+			let i = ret.len();
+			let session = musig::MusigSession::new(
+				&musig::SECP,
+				key_agg,
+				agg_nonces[i],
+				musig::zkp::Message::from_digest(sighashes[i].to_byte_array()),
+			);
+			ret.push(musig::sig_from(session.partial_sig_agg(sigs)));
+		}
+		progress(ret.len());
+	}
+	ret
+}
+
 fn validate_forfeit_sigs(
 	connectors: &ConnectorChain,
 	user_nonces: &[musig::MusigPubNonce],
@@ -147,17 +704,35 @@ fn validate_forfeit_sigs(
 	Ok(())
 }
 
+/// A payment submitted to the round that is collecting payments.
+#[derive(Debug, Clone)]
+pub struct PendingPayment {
+	pub cosign_pubkey: PublicKey,
+	pub inputs: Vec<VtxoId>,
+	pub outputs: Vec<VtxoRequest>,
+	pub offboards: Vec<OffboardRequest>,
+}
+
 pub struct CollectingPayments {
 	max_output_vtxos: usize,
+	max_inputs: Option<usize>,
+	/// The weight the offboard outputs can add to the round tx.
+	max_offboards_weight: Weight,
 	offboard_feerate: FeeRate,
 
 	allowed_inputs: Option<HashSet<VtxoId>>,
 	all_inputs: HashMap<VtxoId, Vtxo>,
 	all_outputs: Vec<VtxoRequest>,
+	/// The origin height for each of the outputs.
+	all_output_origins: Vec<u32>,
 	all_offboards: Vec<OffboardRequest>,
+	offboards_weight: Weight,
 	cosigners: HashSet<PublicKey>,
 	cosigner_vtxos: HashMap<PublicKey, Vec<VtxoId>>,
 	cosign_pub_nonces: HashMap<PublicKey, Vec<musig::MusigPubNonce>>,
+	/// The position of each payment's outputs and offboards, so that they
+	/// can be taken out again when the payment is cancelled.
+	cosigner_outputs: HashMap<PublicKey, (Range<usize>, Range<usize>)>,
 
 	//TODO(stevenroose) this can become a notify once we multitask
 	proceed: bool,
@@ -165,17 +740,25 @@ pub struct CollectingPayments {
 }
 
 impl CollectingPayments {
-	fn new(max_output_vtxos: usize, offboard_feerate: FeeRate) -> CollectingPayments {
+	fn new(
+		max_output_vtxos: usize,
+		max_inputs: Option<usize>,
+		max_offboards_weight: Weight,
+		offboard_feerate: FeeRate,
+	) -> CollectingPayments {
 		CollectingPayments {
-			max_output_vtxos, offboard_feerate,
+			max_output_vtxos, max_inputs, max_offboards_weight, offboard_feerate,
 
 			allowed_inputs: None,
 			all_inputs: HashMap::new(),
 			all_outputs: Vec::new(),
+			all_output_origins: Vec::new(),
 			all_offboards: Vec::new(),
+			offboards_weight: Weight::ZERO,
 			cosigners: HashSet::new(),
 			cosigner_vtxos: HashMap::new(),
 			cosign_pub_nonces: HashMap::new(),
+			cosigner_outputs: HashMap::new(),
 
 			proceed: false,
 			// proceed: tokio::sync::Notify::new(),
@@ -189,45 +772,152 @@ impl CollectingPayments {
 		offboards: Vec<OffboardRequest>,
 		cosign_pubkey: PublicKey,
 		public_nonces: Vec<musig::MusigPubNonce>,
-	) -> anyhow::Result<()> {
+		origin_height: u32,
+	) -> Result<(), InputRejected> {
 		if self.all_outputs.len() + outputs.len() > self.max_output_vtxos {
 			warn!("Got payment we don't have space for, dropping");
-			bail!("not enough outputs left in this round, try next round");
+			return Err(InputRejected::new(RejectReason::OverCapacity,
+				"not enough outputs left in this round, try next round",
+			));
+		}
+		if let Some(max) = self.max_inputs {
+			if self.all_inputs.len() + inputs.len() > max {
+				warn!("Got payment with {} inputs while the round has {} out of {}, dropping",
+					inputs.len(), self.all_inputs.len(), max,
+				);
+				return Err(InputRejected::new(RejectReason::RoundFull,
+					format!("round is full with {} inputs, try next round", self.all_inputs.len()),
+				));
+			}
 		}
 		//TODO(stevenroose) verify ownership over inputs
 
 		if let Some(ref allowed) = self.allowed_inputs {
 			// This means we're not trying first time and we filter inputs.
 			if let Some(bad) = inputs.iter().find(|i| !allowed.contains(&i.id())) {
-				bail!("input vtxo {} has been banned for this round", bad.id());
+				return Err(InputRejected::new(RejectReason::InvalidInput,
+					format!("input vtxo {} has been banned for this round", bad.id()),
+				));
 			}
 		}
+		if let Some(dup) = inputs.iter().find(|i| self.all_inputs.contains_key(&i.id())) {
+			return Err(InputRejected::new(RejectReason::DoubleSpend,
+				format!("input vtxo {} is already used in this round", dup.id()),
+			));
+		}
+		if self.cosigners.contains(&cosign_pubkey) {
+			return Err(InputRejected::new(RejectReason::InvalidPayment,
+				"cosign pubkey is already used in this round",
+			));
+		}
 
 		//TODO(stevenroose) check that vtxos exist!
 
-		validate_payment(&inputs, &outputs, &offboards, self.offboard_feerate)
-			.context("bad payment")?;
+		validate_payment(&inputs, &outputs, &offboards, self.offboard_feerate)?;
+
+		// Payments that would make the round tx too large go to the next round.
+		let weight = offboards_weight(&offboards);
+		if self.offboards_weight + weight > self.max_offboards_weight {
+			if self.offboards_weight == Weight::ZERO {
+				return Err(InputRejected::new(RejectReason::InvalidPayment,
+					"offboards don't fit in a round tx",
+				));
+			}
+			warn!("Round tx would exceed its maximum weight, proceeding with {} offboards",
+				self.all_offboards.len(),
+			);
+			self.proceed = true;
+			return Err(InputRejected::new(RejectReason::OverCapacity,
+				"round tx is full, try next round",
+			));
+		}
 
 		trace!("Received {} inputs, {} outputs and {} offboards from user",
 			inputs.len(), outputs.len(), offboards.len());
 		let vtxo_ids = inputs.iter().map(|v| v.id()).collect();
+		let output_range = self.all_outputs.len()..self.all_outputs.len() + outputs.len();
+		let offboard_range = self.all_offboards.len()..self.all_offboards.len() + offboards.len();
+		self.cosigner_outputs.insert(cosign_pubkey, (output_range, offboard_range));
 		self.all_inputs.extend(inputs.into_iter().map(|v| (v.id(), v)));
+		self.all_output_origins.extend(iter::repeat(origin_height).take(outputs.len()));
 		self.all_outputs.extend(outputs);
 		self.all_offboards.extend(offboards);
-		//TODO(stevenroose) handle duplicate cosign key
-		assert!(self.cosigners.insert(cosign_pubkey));
+		self.offboards_weight = self.offboards_weight + weight;
+		self.cosigners.insert(cosign_pubkey);
 		self.cosigner_vtxos.insert(cosign_pubkey, vtxo_ids);
 		self.cosign_pub_nonces.insert(cosign_pubkey, public_nonces);
 
-		// Check whether our round is full.
-		const REGULAR_PAYMENT_NB_OUTPUTS: usize = 2;
-		if self.all_outputs.len() + REGULAR_PAYMENT_NB_OUTPUTS >= self.max_output_vtxos {
-			warn!("Round is full, got {} outputs", self.all_outputs.len());
+		if self.is_full() {
+			warn!("Round is full, got {} inputs and {} outputs",
+				self.all_inputs.len(), self.all_outputs.len(),
+			);
 			self.proceed = true;
 			// self.proceed.notify_one();
 		}
 		Ok(())
 	}
+
+	/// Whether there is no room left for another regular payment.
+	fn is_full(&self) -> bool {
+		const REGULAR_PAYMENT_NB_OUTPUTS: usize = 2;
+		self.all_outputs.len() + REGULAR_PAYMENT_NB_OUTPUTS >= self.max_output_vtxos
+			|| self.max_inputs.is_some_and(|max| self.all_inputs.len() >= max)
+	}
+
+	/// Take the payment with the given input out of the round, which frees
+	/// all of its input vtxos.
+	///
+	/// The input must belong to the given user key and the payment must
+	/// have the given cosign pubkey.
+	fn cancel_payment(
+		&mut self,
+		input: VtxoId,
+		user_pubkey: PublicKey,
+		cosign_pubkey: PublicKey,
+	) -> Result<(), InputRejected> {
+		if self.all_inputs.get(&input).map(|v| v.spec().user_pubkey) != Some(user_pubkey)
+			|| !self.cosigner_vtxos.get(&cosign_pubkey).is_some_and(|ids| ids.contains(&input))
+		{
+			return Err(InputRejected::new(RejectReason::UnknownPayment,
+				format!("no payment with input vtxo {} in this round", input),
+			));
+		}
+
+		for id in self.cosigner_vtxos.remove(&cosign_pubkey).expect("just found") {
+			self.all_inputs.remove(&id);
+		}
+		let (outputs, offboards) = self.cosigner_outputs.remove(&cosign_pubkey)
+			.expect("every payment has outputs");
+		self.all_output_origins.drain(outputs.clone());
+		self.all_outputs.drain(outputs.clone());
+		let removed_offboards = self.all_offboards.drain(offboards.clone()).collect::<Vec<_>>();
+		self.offboards_weight = self.offboards_weight - offboards_weight(&removed_offboards);
+		// The payments submitted later move up.
+		for (other_outputs, other_offboards) in self.cosigner_outputs.values_mut() {
+			if other_outputs.start >= outputs.end {
+				*other_outputs = other_outputs.start - outputs.len()..other_outputs.end - outputs.len();
+			}
+			if other_offboards.start >= offboards.end {
+				*other_offboards = other_offboards.start - offboards.len()
+					..other_offboards.end - offboards.len();
+			}
+		}
+		self.cosigners.remove(&cosign_pubkey);
+		self.cosign_pub_nonces.remove(&cosign_pubkey);
+		// There might be room for more payments again.
+		self.proceed = self.is_full();
+		trace!("Cancelled payment of cosigner {} with input {}", cosign_pubkey, input);
+		Ok(())
+	}
+
+	fn pending_payments(&self) -> Vec<PendingPayment> {
+		self.cosigner_outputs.iter().map(|(pk, (outputs, offboards))| PendingPayment {
+			cosign_pubkey: *pk,
+			inputs: self.cosigner_vtxos.get(pk).cloned().unwrap_or_default(),
+			outputs: self.all_outputs[outputs.clone()].to_vec(),
+			offboards: self.all_offboards[offboards.clone()].to_vec(),
+		}).collect()
+	}
 }
 
 pub struct SigningVtxoTree {
@@ -265,13 +955,17 @@ impl SigningVtxoTree {
 			trace!("User with pubkey {} submitted partial vtxo sigs again", pubkey);
 			bail!("duplicate signatures for pubkey");
 		}
+		// Our own keys are cosigners too, but they don't have user nonces.
+		let Some(pub_nonces) = self.cosign_pub_nonces.get(&pubkey) else {
+			bail!("pubkey is not a user cosigner");
+		};
 		if validate_partial_vtxo_sigs(
 			self.cosigners.iter().copied(),
 			&self.cosign_agg_nonces,
 			&self.cosign_sighashes,
-			self.vtxos_spec.cosign_taptweak().to_byte_array(),
+			self.vtxos_spec.cosign_taptweak().map(|t| t.to_byte_array()),
 			pubkey,
-			self.cosign_pub_nonces.get(&pubkey).expect("user is cosigner"),
+			pub_nonces,
 			&signatures,
 		) {
 			self.cosign_part_sigs.insert(pubkey, signatures);
@@ -280,8 +974,8 @@ impl SigningVtxoTree {
 			bail!("invalid partial vtxo signatures");
 		}
 
-		// Stop the loop once we have all.
-		if self.cosign_part_sigs.len() == self.cosigners.len() - 1 {
+		// Stop the loop once we have those of all users.
+		if self.cosign_part_sigs.len() == self.cosign_pub_nonces.len() {
 			self.proceed = true;
 		}
 		Ok(())
@@ -303,13 +997,18 @@ pub struct SigningForfeits {
 }
 
 impl SigningForfeits {
+	/// Register the valid forfeit signatures.
+	///
+	/// Signatures for unknown inputs or that don't verify are dropped and
+	/// the first of such problems is returned as the rejection.
 	pub fn register_forfeits(
 		&mut self,
 		signatures: Vec<(VtxoId, Vec<musig::MusigPubNonce>, Vec<musig::MusigPartialSignature>)>,
-	) -> anyhow::Result<()> {
+	) -> Result<(), InputRejected> {
 		trace!("Received vtxo signatures for {:?}",
 			signatures.iter().map(|v| v.0).collect::<Vec<_>>(),
 		);
+		let mut rejection = None;
 		for (id, nonces, sigs) in signatures {
 			if let Some(_vtxo) = self.all_inputs.get(&id) {
 				//TODO(stevenroose) actually validate forfeit txs
@@ -322,10 +1021,18 @@ impl SigningForfeits {
 					&sigs,
 				) {
 					Ok(()) => { self.forfeit_part_sigs.insert(id, (nonces, sigs)); },
-					Err(e) => debug!("Invalid forfeit sigs for {}: {}", id, e),
+					Err(e) => {
+						debug!("Invalid forfeit sigs for {}: {}", id, e);
+						rejection.get_or_insert(InputRejected::new(RejectReason::BadForfeitSignature,
+							format!("invalid forfeit signatures for {}: {}", id, e),
+						));
+					},
 				}
 			} else {
 				debug!("User provided forfeit sigs for unknown input {}", id);
+				rejection.get_or_insert(InputRejected::new(RejectReason::InvalidInput,
+					format!("vtxo {} is not an input of this round", id),
+				));
 			}
 		}
 
@@ -334,7 +1041,7 @@ impl SigningForfeits {
 			debug!("We received all signatures, continuing round...");
 			self.proceed = true;
 		}
-		Ok(())
+		rejection.map_or(Ok(()), Err)
 	}
 }
 
@@ -345,16 +1052,42 @@ pub enum RoundState {
 	SigningForfeits(SigningForfeits),
 }
 
+/// The fee rate to retry a round tx at after it was rejected for low fees.
+///
+/// We at least double the fee rate and go at least to bitcoind's current
+/// mempool minimum fee rate, but never above [MAX_ROUND_FEERATE_MULTIPLIER]
+/// times the configured round tx fee rate. Returns [None] if the fee rate
+/// is already at that maximum.
+fn bumped_round_feerate(app: &App, feerate: FeeRate) -> Option<FeeRate> {
+	let configured = app.floor_feerate(app.config.round_tx_feerate);
+	let max = FeeRate::from_sat_per_kwu(
+		configured.to_sat_per_kwu().max(1).saturating_mul(MAX_ROUND_FEERATE_MULTIPLIER),
+	);
+	if feerate >= max {
+		return None;
+	}
+	let doubled = FeeRate::from_sat_per_kwu(feerate.to_sat_per_kwu().max(1).saturating_mul(2));
+	let bumped = match app.bitcoind.get_mempool_info() {
+		// The mempool min fee is expressed per kvB.
+		Ok(info) => cmp::max(doubled, FeeRate::from_sat_per_kwu(info.mempool_min_fee.to_sat() / 4)),
+		Err(e) => {
+			warn!("Failed to fetch mempool min fee from bitcoind: {}", e);
+			doubled
+		},
+	};
+	Some(cmp::min(bumped, max))
+}
+
 /// This method is called from a tokio thread so it can be long-lasting.
 pub async fn run_round_coordinator(
 	app: Arc<App>,
 	mut round_input_rx: tokio::sync::mpsc::UnboundedReceiver<RoundInput>,
-	mut round_trigger_rx: tokio::sync::mpsc::Receiver<()>,
+	mut round_trigger_rx: tokio::sync::mpsc::Receiver<RoundTrigger>,
 ) -> anyhow::Result<()> {
 	let cfg = &app.config;
+	let master_key = *app.master_key().context("can't run rounds")?;
 
-	let round_tx_feerate = app.config.round_tx_feerate;
-	let offboard_feerate = round_tx_feerate;
+	let offboard_feerate = app.floor_feerate(app.config.round_tx_feerate);
 
 	// The maximum number of output vtxos per round based on the max number
 	// of vtxo tree nonces we require users to provide.
@@ -363,27 +1096,74 @@ pub async fn run_round_coordinator(
 	// Whether we should sync the onchain wallet at the next round attempt.
 	let mut sync_next_attempt = true;
 
+	let mut scheduler = RoundScheduler::new(cfg.round_interval, cfg.max_round_interval);
+	let mut last_sweep = Instant::now();
 	'round: loop {
-		// Sleep for the round interval, but discard all incoming messages.
-		tokio::pin! { let timeout = tokio::time::sleep(cfg.round_interval); }
+		app.round_metrics.record_round_backoff(scheduler.backoff_level(), scheduler.current_interval());
+		if scheduler.backoff_level() > 0 {
+			warn!("{} consecutive rounds failed, backing off: next round in {:?}",
+				scheduler.backoff_level(), scheduler.current_interval(),
+			);
+		}
+
+		// Set when the round was triggered with an explicit expiry height.
+		let mut explicit_expiry = None;
+
+		// Wait for the next round interval tick, but reject all incoming messages.
 		'sleep: loop {
 			tokio::select! {
-				() = &mut timeout => break 'sleep,
-				Some(()) = round_trigger_rx.recv() => {
+				() = scheduler.tick() => break 'sleep,
+				Some(trigger) = round_trigger_rx.recv() => {
 					info!("Starting round based on admin RPC trigger");
+					explicit_expiry = trigger.expiry_height;
 					sync_next_attempt = false; // start round fast
 					break 'sleep;
 				},
-				_ = round_input_rx.recv() => {},
+				Some(input) = round_input_rx.recv() => {
+					input.reject_out_of_phase(app.rounds().round_epoch.load(atomic::Ordering::SeqCst));
+				},
+				() = app.shutdown_signal() => {
+					info!("Stopping round coordinator");
+					return Ok(());
+				},
+			}
+		}
+		scheduler.start_round();
+		store_next_round_start(&app, &scheduler);
+
+		if let Err(e) = app.sync_monitor().await {
+			warn!("Error syncing chain monitor: {}", e);
+		}
+		if let Err(e) = app.bump_stuck_round_txs().await {
+			warn!("Error trying to bump fees of stuck round txs: {}", e);
+		}
+		if let Err(e) = app.claim_exited_forfeits().await {
+			warn!("Error trying to claim exited forfeited vtxos: {}", e);
+		}
+		// We sweep in between rounds so that the sweeps never conflict with a round tx.
+		if cfg.sweep_mode == SweepMode::Auto && last_sweep.elapsed() >= cfg.sweep_interval {
+			last_sweep = Instant::now();
+			match app.sweep_expired_rounds(cfg.round_tx_feerate).await {
+				Ok(txids) if txids.is_empty() => trace!("No expired rounds to sweep"),
+				Ok(txids) => info!("Swept expired rounds in {} sweep txs", txids.len()),
+				Err(e) => warn!("Error trying to sweep expired rounds: {}", e),
 			}
 		}
 
 		let round_id = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() /
 			cfg.round_interval.as_millis()) as u64;
-		info!("Starting round {}", round_id);
+		let round_seq = app.db.next_round_seq().context("failed to take round sequence number")?;
+		info!("Starting round {} (#{})", round_id, round_seq);
+
+		// Might be increased if bitcoind rejects our round tx for low fees.
+		let mut round_tx_feerate = app.floor_feerate(app.config.round_tx_feerate);
+
+		// Used to shuffle the outputs, seeded freshly for every round.
+		let mut output_rng = rand::rngs::StdRng::from_entropy();
 
 		// Start new round, announce.
-		let _ = app.rounds().round_event_tx.send(RoundEvent::Start { id: round_id, offboard_feerate });
+		let mut round_epoch = announce_round_start(&app, round_id, round_seq, offboard_feerate);
+		app.emit_event(Event::RoundStarted { round_id });
 
 		// Allocate this data once per round so that we can keep them
 		// Perhaps we could even keep allocations between all rounds, but time
@@ -397,33 +1177,79 @@ pub async fn run_round_coordinator(
 			}
 			sync_next_attempt = true;
 
-			let mut state = CollectingPayments::new(max_output_vtxos, offboard_feerate);
+			// A previous attempt that went ahead left the round marked full.
+			app.rounds().round_full.store(false, atomic::Ordering::SeqCst);
 
-			// Generate a one-time use signing key.
-			let cosign_key = Keypair::new(&SECP, &mut rand::thread_rng());
-			state.cosigners.insert(cosign_key.public_key());
+			// Config validation makes sure the base weight fits.
+			let max_offboards_weight = Weight::from_wu(cfg.round_tx_max_weight)
+				- round_tx_base_weight(cfg);
+			let mut state = CollectingPayments::new(
+				max_output_vtxos, cfg.max_round_inputs, max_offboards_weight, offboard_feerate,
+			);
+
+			// Generate the one-time use signing keys of our side of the cosign group.
+			let asp_cosigners = AspCosigners::generate(cfg.nb_round_asp_cosigners);
+			state.cosigners.extend(asp_cosigners.pubkeys());
 
 			// Start receiving payments.
+			let mut timer = PhaseTimer::start();
 			tokio::pin! { let timeout = tokio::time::sleep(cfg.round_submit_time); }
 			'receive: loop {
 				tokio::select! {
 					() = &mut timeout => break 'receive,
 					input = round_input_rx.recv() => match input.expect("broken channel") {
 						RoundInput::RegisterPayment {
-							inputs, outputs, offboards, cosign_pubkey, public_nonces,
+							inputs, outputs, offboards, cosign_pubkey, public_nonces, origin_height,
+							epoch, response,
 						} => {
-							if let Err(e) = state.register_payment(
-								inputs, outputs, offboards, cosign_pubkey, public_nonces,
-							) {
-								trace!("Error registering payment: {}", e);
+							if epoch != round_epoch {
+								trace!("Ignoring payment for stale round epoch {}", epoch);
+								let _ = response.send(Err(InputRejected::new(RejectReason::StaleEpoch,
+									format!("stale round epoch {}, wait for the next round start", epoch),
+								)));
 								continue 'receive;
 							}
-
+							let first_input = inputs.first().map(|v| (v.id(), v.spec().user_pubkey));
+							let res = state.register_payment(
+								inputs, outputs, offboards, cosign_pubkey, public_nonces, origin_height,
+							);
+							if let Err(ref e) = res {
+								trace!("Error registering payment: {}", e);
+							}
+							let accepted = res.is_ok();
+							if response.send(res).is_err() && accepted {
+								// The client stopped waiting and doesn't know the payment is in.
+								if let Some((id, user_pubkey)) = first_input {
+									debug!("Withdrawing payment with input {} that nobody waits for", id);
+									let _ = state.cancel_payment(id, user_pubkey, cosign_pubkey);
+								}
+							}
+							// We also proceed when a payment was rejected
+							// for a full round.
 							if state.proceed {
+								// Later payments are turned away as round full.
+								app.rounds().round_full.store(true, atomic::Ordering::SeqCst);
 								break 'receive;
 							}
 						},
-						_ => trace!("unexpected message"),
+						RoundInput::CancelPayment { input, user_pubkey, cosign_pubkey, epoch, response } => {
+							if epoch != round_epoch {
+								let _ = response.send(Err(InputRejected::new(RejectReason::StaleEpoch,
+									format!("stale round epoch {}, the payment isn't in this round", epoch),
+								)));
+								continue 'receive;
+							}
+							let res = state.cancel_payment(input, user_pubkey, cosign_pubkey);
+							if let Err(ref e) = res {
+								trace!("Error cancelling payment: {}", e);
+							}
+							let _ = response.send(res);
+							app.rounds().round_full.store(state.proceed, atomic::Ordering::SeqCst);
+						},
+						RoundInput::PendingPayments { response } => {
+							let _ = response.send(state.pending_payments());
+						},
+						other => other.reject_out_of_phase(round_epoch),
 					}
 				}
 			}
@@ -431,7 +1257,24 @@ pub async fn run_round_coordinator(
 				info!("Nothing to do this round, sitting it out...");
 				continue 'round;
 			}
+			// Every payment was checked individually, but make sure the round
+			// as a whole doesn't create money either.
+			let all_inputs = state.all_inputs.values().cloned().collect::<Vec<_>>();
+			if let Err(e) = validate_payment(
+				&all_inputs, &state.all_outputs, &state.all_offboards, offboard_feerate,
+			) {
+				error!("Round payments don't add up, aborting round: {:#}", e);
+				let reason = format!("round amounts don't add up: {:#}", e);
+				let _ = app.rounds().round_event_tx.send(RoundEvent::Failed {
+					id: round_id, seq: round_seq, reason: RoundFailReason::Other(reason.clone()),
+				});
+				app.emit_event(Event::RoundFailed { round_id, reason });
+				scheduler.round_failed();
+				store_next_round_start(&app, &scheduler);
+				continue 'round;
+			}
 			info!("Received {} inputs and {} outputs for round", state.all_inputs.len(), state.all_outputs.len());
+			timer.finish(RoundPhase::SubmitCollection);
 
 			// Since it's possible in testing that we only have to do onboards,
 			// and since it's pretty annoying to deal with the case of no vtxos,
@@ -448,6 +1291,8 @@ pub async fn run_round_coordinator(
 					pubkey: *UNSPENDABLE,
 					amount: ark::fee::DUST,
 				});
+				// It's unspendable, so its lifetime doesn't matter.
+				state.all_output_origins.push(0);
 			}
 
 
@@ -459,27 +1304,64 @@ pub async fn run_round_coordinator(
 			// ****************************************************************
 
 			let tip = app.bitcoind.get_block_count()? as u32;
-			let expiry = tip + cfg.vtxo_expiry_delta as u32;
+			// The tip might have moved since the round was triggered, if the
+			// explicit expiry is no longer valid we fall back to the delta.
+			let expiry = round_expiry_height(cfg, tip, explicit_expiry).unwrap_or_else(|e| {
+				warn!("Ignoring explicit expiry height of round {}: {}", round_id, e);
+				tip + cfg.vtxo_expiry_delta as u32
+			});
 			debug!("Current tip is {}, so round vtxos will expire at {}", tip, expiry);
 
+			order_vtxo_requests(
+				cfg.round_output_ordering,
+				&mut state.all_outputs,
+				&mut state.all_output_origins,
+				&mut output_rng,
+			);
+			order_offboards(cfg.round_output_ordering, &mut state.all_offboards, &mut output_rng);
+
 			let cosign_agg_pk = musig::combine_keys(state.cosigners.iter().copied());
-			let vtxos_spec = VtxoTreeSpec::new(
+			let mut vtxos_spec = VtxoTreeSpec::new(
 				state.all_outputs.clone(),
 				cosign_agg_pk,
-				app.master_key.public_key(),
+				app.asp_pubkey,
 				expiry,
 				cfg.vtxo_exit_delta,
 				cfg.vtxo_node_anchors,
+				cfg.vtxo_output_key_policy,
+				cfg.vtxo_exit_timelock,
+				cfg.vtxo_script_type,
 			);
-			//TODO(stevenroose) this is inefficient, improve this with direct getter
-			let nb_nodes = vtxos_spec.build_unsigned_tree(OutPoint::null()).nb_nodes();
-			assert!(nb_nodes <= cfg.nb_round_nonces);
 			let connector_output = ConnectorChain::output(
-				state.all_inputs.len(), app.master_key.public_key(),
+				state.all_inputs.len(), app.asp_pubkey, cfg.connector_value,
 			);
 
 			// Build round tx.
-			let spendable_utxos = app.spendable_expired_vtxos(tip)?;
+			// We only sweep the first batch of expired utxos in the round tx,
+			// the others are left for the next rounds. Admin sweeps wait
+			// until this attempt is over, so they can't spend the same utxos.
+			let _sweep_lock = app.sweep_lock.lock().await;
+			let mut sweep_batches = match cfg.sweep_mode {
+				SweepMode::Auto => app.spendable_expired_vtxos(tip).unwrap_or_else(|e| {
+					warn!("Not sweeping in this round, failed to collect expired rounds: {:#}", e);
+					Vec::new()
+				}).into_iter(),
+				SweepMode::Manual => Vec::new().into_iter(),
+			};
+			let mut spendable_utxos = sweep_batches.next().unwrap_or_default();
+			let sweeps_weight = spendable_utxos.iter().map(|u| TXIN_BASE + u.weight)
+				.fold(Weight::ZERO, |sum, w| sum + w);
+			if state.offboards_weight + sweeps_weight > max_offboards_weight {
+				debug!("Not sweeping {} expired round utxos, it would exceed the max round tx weight",
+					spendable_utxos.len(),
+				);
+				spendable_utxos.clear();
+			}
+			if sweep_batches.len() > 0 {
+				debug!("Leaving {} batches of expired round utxos for later rounds",
+					sweep_batches.len(),
+				);
+			}
 			if !spendable_utxos.is_empty() {
 				debug!("Will be spending {} round-related UTXOs with total value of {}",
 					spendable_utxos.len(), spendable_utxos.iter().map(|v| v.amount()).sum::<Amount>(),
@@ -488,46 +1370,159 @@ pub async fn run_round_coordinator(
 					trace!("Including round-related UTXO {} with value {}", u.point, u.amount());
 				}
 			}
-			//TODO(stevenroose) think about if we can release lock sooner
-			let mut wallet = app.wallet.lock().await;
-			let mut round_tx_psbt = {
+			let required_outputs = |vtxos_spec: &VtxoTreeSpec| {
+				iter::once(TxOut {
+					script_pubkey: vtxos_spec.cosign_spk(),
+					value: vtxos_spec.total_required_value(),
+				})
+					.chain(iter::once(connector_output.clone()))
+					.chain(cfg.fee_scheme.anchor_output())
+					.chain(state.all_offboards.iter().map(|o| TxOut {
+						script_pubkey: o.script_pubkey.clone(),
+						value: o.amount,
+					}))
+					.collect::<Vec<_>>()
+			};
+
+			// Make sure our wallet can fund the round before building the round tx.
+			let required = required_round_funds(
+				&required_outputs(&vtxos_spec), &spendable_utxos, round_tx_feerate,
+			);
+			// Immature coinbase outputs can't fund the round.
+			let funds = app.wallet_funds(&*app.wallet.lock().await);
+			let available = funds.spendable;
+			if available < required {
+				error!("Our wallet can't fund round {}: it needs {}, but only has {} confirmed",
+					round_id, required, available,
+				);
+				if funds.immature > Amount::ZERO {
+					warn!("Another {} in our wallet is in immature coinbase outputs", funds.immature);
+				}
+				let reason = RoundFailReason::InsufficientAspFunds { required, available };
+				let _ = app.rounds().round_event_tx.send(RoundEvent::Failed {
+					id: round_id, seq: round_seq, reason: reason.clone(),
+				});
+				app.emit_event(Event::RoundFailed { round_id, reason: reason.to_string() });
+				app.round_metrics.record_insufficient_funds();
+				scheduler.round_failed();
+				store_next_round_start(&app, &scheduler);
+				continue 'round;
+			}
+
+			let build_round_tx = |wallet: &mut bdk_wallet::Wallet, vtxos_spec: &VtxoTreeSpec| {
+				let mut unspendable = app.immature_coinbase(wallet);
+				// TRUC txs can't have unconfirmed non-TRUC ancestors.
+				if cfg.round_tx_version == 3 {
+					unspendable.extend(app.unconfirmed_utxos(wallet));
+				}
 				let mut b = wallet.build_tx();
 				b.ordering(bdk_wallet::TxOrdering::Untouched);
-				b.nlocktime(LockTime::from_height(tip).expect("actual height"));
+				b.version(cfg.round_tx_version);
+				b.unspendable(unspendable);
+				if cfg.round_tx_anti_fee_sniping {
+					b.nlocktime(LockTime::from_height(tip).expect("actual height"));
+				} else {
+					b.nlocktime(LockTime::ZERO);
+				}
 				for utxo in &spendable_utxos {
 					b.add_foreign_utxo_with_sequence(
 						utxo.point, utxo.psbt.clone(), utxo.weight, Sequence::ZERO,
 					).expect("bdk rejected foreign utxo");
 				}
-				b.add_recipient(vtxos_spec.cosign_spk(), vtxos_spec.total_required_value());
-				b.add_recipient(connector_output.script_pubkey, connector_output.value);
-				for offb in &state.all_offboards {
-					b.add_recipient(offb.script_pubkey.clone(), offb.amount);
+				// The required outputs come first, in order, bdk adds our change after them.
+				for output in required_outputs(vtxos_spec) {
+					b.add_recipient(output.script_pubkey, output.value);
 				}
 				b.fee_rate(round_tx_feerate);
 				b.finish().expect("bdk failed to create round tx")
 			};
+			//TODO(stevenroose) think about if we can release lock sooner
+			let mut wallet = app.wallet.lock().await;
+			let mut round_tx_psbt = build_round_tx(&mut wallet, &vtxos_spec);
+			if cfg.round_change == RoundChange::Vtxo && state.all_outputs.len() < max_output_vtxos {
+				let nb_required = required_outputs(&vtxos_spec).len();
+				let tx = &round_tx_psbt.unsigned_tx;
+				let change_vouts = (nb_required..tx.output.len())
+					.filter(|vout| wallet.is_mine(tx.output[*vout].script_pubkey.clone()))
+					.collect::<Vec<_>>();
+				let change = change_vouts.iter().map(|vout| tx.output[*vout].value).sum::<Amount>();
+				// Without the change outputs, the round tx pays less fee. We
+				// leave a little extra for the fee so that bdk doesn't come
+				// short because of rounding. If the vtxo is capped, bdk keeps
+				// the rest of the change onchain.
+				let saved_fee = change_vouts.iter()
+					.map(|vout| round_tx_feerate.fee_wu(tx.output[*vout].weight()).expect("no overflow"))
+					.sum::<Amount>();
+				let leftover = cmp::min(saved_fee, ark::fee::DUST / 2);
+				let value = change + saved_fee - leftover;
+				let max = cfg.round_change_vtxo_max;
+				if let Some(mut spec) = change_vtxo_spec(&vtxos_spec, value, max) {
+					debug!("Keeping {} of our round change of {} in a vtxo",
+						spec.vtxos.last().unwrap().amount, change,
+					);
+					// Don't give away which vtxo is our change.
+					let mut origins = state.all_output_origins.clone();
+					origins.push(tip);
+					order_vtxo_requests(
+						cfg.round_output_ordering, &mut spec.vtxos, &mut origins, &mut output_rng,
+					);
+					wallet.cancel_tx(&round_tx_psbt.unsigned_tx);
+					round_tx_psbt = build_round_tx(&mut wallet, &spec);
+					state.all_outputs = spec.vtxos.clone();
+					state.all_output_origins = origins;
+					vtxos_spec = spec;
+				}
+			}
+			let vtxo_origin_heights = state.all_output_origins.clone();
+			//TODO(stevenroose) this is inefficient, improve this with direct getter
+			let nb_nodes = vtxos_spec.nb_nodes();
+			assert!(nb_nodes <= cfg.nb_round_nonces);
 			let round_tx = round_tx_psbt.clone().extract_tx()?;
+			debug_assert!(round_tx.version.0 >= 2, "round tx needs relative timelocks");
+			let required_outputs = required_outputs(&vtxos_spec);
+			// Even a maximum size tx shouldn't pay more than this.
+			let max_fee = round_tx_feerate.fee_wu(Weight::from_wu(MAX_STANDARD_TX_WEIGHT as u64))
+				.expect("no overflow");
+			let checks = check_round_tx_amounts(
+				&round_tx_psbt, &required_outputs, |spk| wallet.is_mine(spk.clone()), max_fee,
+			).and_then(|_fee| check_truc_policy(&round_tx, false));
+			if let Err(e) = checks {
+				error!("Round tx {} failed amount checks, aborting round: {:#}",
+					round_tx.compute_txid(), e,
+				);
+				wallet.cancel_tx(&round_tx);
+				let reason = format!("invalid round tx: {:#}", e);
+				let _ = app.rounds().round_event_tx.send(RoundEvent::Failed {
+					id: round_id, seq: round_seq, reason: RoundFailReason::Other(reason.clone()),
+				});
+				app.emit_event(Event::RoundFailed { round_id, reason });
+				scheduler.round_failed();
+				store_next_round_start(&app, &scheduler);
+				continue 'round;
+			}
+			let bump_output = cfg.fee_scheme.round_bump_output(&round_tx, |spk| {
+				wallet.is_mine(spk.clone())
+			});
+			if bump_output.is_none() {
+				warn!("Round tx {} has no output to bump its fee with", round_tx.compute_txid());
+			}
+			if cfg.fee_scheme.anchor_output().is_some() {
+				debug_assert_eq!(bump_output.map(|p| p.vout), Some(ROUND_TX_ANCHOR_VOUT));
+			}
+			let nb_offboards = state.all_offboards.len();
 			let vtxos_utxo = OutPoint::new(round_tx.compute_txid(), 0);
 			let conns_utxo = OutPoint::new(round_tx.compute_txid(), 1);
 
+			timer.finish(RoundPhase::TreeConstruction);
+
 			// Generate vtxo nonces and combine with user's nonces.
-			let (sec_vtxo_nonces, pub_vtxo_nonces) = {
-				let mut secs = Vec::with_capacity(nb_nodes);
-				let mut pubs = Vec::with_capacity(nb_nodes);
-				for _ in 0..nb_nodes {
-					let (s, p) = musig::nonce_pair(&cosign_key);
-					secs.push(s);
-					pubs.push(p);
-				}
-				(secs, pubs)
-			};
+			let (sec_vtxo_nonces, pub_vtxo_nonces) = asp_cosigners.nonces(nb_nodes);
 			let cosign_agg_nonces = {
 				let mut ret = Vec::with_capacity(nb_nodes);
 				let mut buf = Vec::with_capacity(state.cosigners.len());
 				for i in 0..nb_nodes {
 					buf.clear();
-					buf.push(pub_vtxo_nonces[i]);
+					buf.extend(pub_vtxo_nonces.iter().map(|n| n[i]));
 					buf.extend(state.cosign_pub_nonces.values().map(|n| n[i]));
 					ret.push(musig::MusigAggNonce::new(&musig::SECP, &buf));
 				}
@@ -536,9 +1531,12 @@ pub async fn run_round_coordinator(
 			let cosign_sighashes = vtxos_spec.sighashes(vtxos_utxo);
 			assert_eq!(cosign_sighashes.len(), cosign_agg_nonces.len());
 
+			timer.finish(RoundPhase::NonceAggregation);
+
 			// Send out vtxo proposal to signers.
 			let _ = app.rounds().round_event_tx.send(RoundEvent::VtxoProposal {
 				id: round_id,
+				seq: round_seq,
 				round_tx: round_tx.clone(),
 				vtxos_spec: vtxos_spec.clone(),
 				cosigners: state.cosigners.iter().copied().collect(),
@@ -574,6 +1572,10 @@ pub async fn run_round_coordinator(
 								}
 							}
 						}
+						app.emit_event(Event::RoundFailed {
+							round_id,
+							reason: "timed out receiving vtxo signatures".into(),
+						});
 						continue 'attempt;
 					},
 					input = round_input_rx.recv() => match input.expect("broken channel") {
@@ -587,44 +1589,59 @@ pub async fn run_round_coordinator(
 								break 'receive;
 							}
 						},
-						_ => trace!("unexpected message"),
+						other => other.reject_out_of_phase(round_epoch),
 					}
 				}
 			}
 
-			// Combine the vtxo signatures.
+			// Make our own partial signatures.
+			let cosigners = state.cosigners.iter().copied().collect::<Vec<_>>();
+			let partial_sigs = asp_cosigners.partial_sign(
+				&cosigners,
+				&state.cosign_agg_nonces,
+				&state.cosign_sighashes,
+				state.vtxos_spec.cosign_taptweak().map(|t| t.to_byte_array()),
+				sec_vtxo_nonces,
+			);
 			#[cfg(debug_assertions)]
-			let mut partial_sigs = Vec::with_capacity(nb_nodes);
-			let mut final_vtxo_sigs = Vec::with_capacity(nb_nodes);
-			for (i, sec_nonce) in sec_vtxo_nonces.into_iter().enumerate() {
-				let others = state.cosign_part_sigs.values().map(|s| s[i].clone()).collect::<Vec<_>>();
-				let (_partial, final_sig) = musig::partial_sign(
-					state.cosigners.iter().copied(),
-					state.cosign_agg_nonces[i],
-					&cosign_key,
-					sec_nonce,
-					state.cosign_sighashes[i].to_byte_array(),
-					Some(state.vtxos_spec.cosign_taptweak().to_byte_array()),
-					Some(&others),
-				);
-				final_vtxo_sigs.push(final_sig.expect("we provided others"));
-				#[cfg(debug_assertions)]
-				partial_sigs.push(_partial);
+			for (pk, (nonces, sigs)) in asp_cosigners.pubkeys()
+				.zip(pub_vtxo_nonces.iter().zip(&partial_sigs))
+			{
+				assert!(validate_partial_vtxo_sigs(
+					cosigners.iter().copied(),
+					&state.cosign_agg_nonces,
+					&state.cosign_sighashes,
+					state.vtxos_spec.cosign_taptweak().map(|t| t.to_byte_array()),
+					pk,
+					nonces,
+					sigs,
+				), "our own partial signatures were wrong");
 			}
-			#[cfg(debug_assertions)]
-			debug_assert!(validate_partial_vtxo_sigs(
-				state.cosigners.iter().copied(),
+
+			// Combine the vtxo signatures.
+			let node_sigs = (0..nb_nodes).map(|i| {
+				state.cosign_part_sigs.values().chain(&partial_sigs).map(|s| s[i].clone()).collect()
+			}).collect::<Vec<Vec<_>>>();
+			let key_agg = match state.vtxos_spec.cosign_taptweak() {
+				Some(t) => musig::tweaked_key_agg(state.cosigners.iter().copied(), t.to_byte_array()).0,
+				None => musig::key_agg(state.cosigners.iter().copied()),
+			};
+			let batch_size = app.config.round_cosign_batch_size.unwrap_or(nb_nodes).max(1);
+			let final_vtxo_sigs = aggregate_vtxo_sigs(
+				&key_agg,
 				&state.cosign_agg_nonces,
 				&state.cosign_sighashes,
-				state.vtxos_spec.cosign_taptweak().to_byte_array(),
-				cosign_key.public_key(),
-				&pub_vtxo_nonces,
-				&partial_sigs,
-			), "our own partial signatures were wrong");
+				&node_sigs,
+				batch_size,
+				|done| if batch_size < nb_nodes {
+					debug!("Round {}: aggregated {}/{} vtxo signatures", round_id, done, nb_nodes);
+				},
+			);
 
 			// Then construct the final signed vtxo tree.
 			let signed_vtxos = SignedVtxoTree::new(state.vtxos_spec, vtxos_utxo, final_vtxo_sigs);
 			debug_assert!(signed_vtxos.validate_signatures().is_ok(), "invalid signed vtxo tree");
+			timer.finish(RoundPhase::VtxoSigning);
 
 
 			// ****************************************************************
@@ -639,7 +1656,7 @@ pub async fn run_round_coordinator(
 				let mut secs = Vec::with_capacity(state.all_inputs.len());
 				let mut pubs = Vec::with_capacity(state.all_inputs.len());
 				for _ in 0..state.all_inputs.len() {
-					let (s, p) = musig::nonce_pair(&app.master_key);
+					let (s, p) = musig::nonce_pair(&master_key);
 					secs.push(s);
 					pubs.push(p);
 				}
@@ -647,16 +1664,30 @@ pub async fn run_round_coordinator(
 				forfeit_sec_nonces.insert(*id, secs);
 			}
 
+			// Make the connectors available to clients before they see the
+			// proposal. We hold the lock until the proposal is sent, so that
+			// clients never see the connectors of one attempt together with
+			// the proposal of another.
+			let mut proposed = app.rounds().proposed_connectors.lock().await;
+			*proposed = Some(ProposedConnectors {
+				round_id,
+				len: state.all_inputs.len(),
+				utxo: conns_utxo,
+				connector_value: cfg.connector_value,
+			});
+
 			// Send out round proposal to signers.
 			let _ = app.rounds().round_event_tx.send(RoundEvent::RoundProposal {
 				id: round_id,
+				seq: round_seq,
 				round_tx: round_tx.clone(),
 				vtxos: signed_vtxos.clone(),
 				forfeit_nonces: forfeit_pub_nonces.clone(),
 			});
+			drop(proposed);
 
 			let connectors = ConnectorChain::new(
-				state.all_inputs.len(), conns_utxo, app.master_key.public_key(),
+				state.all_inputs.len(), conns_utxo, app.asp_pubkey, cfg.connector_value,
 			);
 
 			let mut state = SigningForfeits {
@@ -672,34 +1703,40 @@ pub async fn run_round_coordinator(
 			'receive: loop {
 				tokio::select! {
 					_ = &mut timeout => {
-						warn!("Timed out receiving forfeit signatures.");
+						warn!("Timed out receiving forfeit signatures, missing them for {} of {} inputs",
+							state.all_inputs.len() - state.forfeit_part_sigs.len(), state.all_inputs.len(),
+						);
 						for vtxo in state.all_inputs.keys() {
 							if !state.forfeit_part_sigs.contains_key(vtxo) {
 								trace!("Dropping vtxo {}", vtxo);
 								state.allowed_inputs.remove(vtxo);
 							}
 						}
+						app.emit_event(Event::RoundFailed {
+							round_id,
+							reason: "timed out receiving forfeit signatures".into(),
+						});
 						continue 'attempt;
 					}
 					input = round_input_rx.recv() => match input.expect("broken channel") {
-						RoundInput::ForfeitSignatures { signatures } => {
-							if let Err(e) = state.register_forfeits(signatures) {
-								trace!("Error in received vtxo tree signatures: {}", e);
-								continue 'receive;
+						RoundInput::ForfeitSignatures { signatures, response } => {
+							let res = state.register_forfeits(signatures);
+							if let Err(ref e) = res {
+								trace!("Error in received forfeit signatures: {}", e);
 							}
+							let _ = response.send(res);
 
 							if state.proceed {
 								break 'receive;
 							}
 						},
-						_ => trace!("unexpected message"),
+						other => other.reject_out_of_phase(round_epoch),
 					}
 				}
 			}
 
 			// Finish the forfeit signatures.
 			let mut forfeit_sigs = HashMap::with_capacity(state.all_inputs.len());
-			let mut missing_forfeits = HashSet::new();
 			for (id, vtxo) in &state.all_inputs {
 				if let Some((user_nonces, partial_sigs)) = state.forfeit_part_sigs.get(id) {
 					let sec_nonces = forfeit_sec_nonces.remove(id).unwrap().into_iter();
@@ -707,12 +1744,14 @@ pub async fn run_round_coordinator(
 					let connectors = state.connectors.connectors();
 					let mut sigs = Vec::with_capacity(state.all_inputs.len());
 					for (i, (conn, sec)) in connectors.zip(sec_nonces.into_iter()).enumerate() {
-						let (sighash, _) = ark::forfeit::forfeit_sighash(&vtxo, conn);
+						let (sighash, _) = ark::forfeit::forfeit_sighash(
+							&vtxo, conn, state.connectors.connector_value(),
+						);
 						let agg_nonce = musig::nonce_agg([user_nonces[i], pub_nonces[i]]);
 						let (_, sig) = musig::partial_sign(
-							[app.master_key.public_key(), vtxo.spec().user_pubkey],
+							[app.asp_pubkey, vtxo.spec().user_pubkey],
 							agg_nonce,
-							&app.master_key,
+							&master_key,
 							sec,
 							sighash.to_byte_array(),
 							Some(vtxo.spec().exit_taptweak().to_byte_array()),
@@ -721,18 +1760,34 @@ pub async fn run_round_coordinator(
 						sigs.push(sig.expect("should be signed"));
 					}
 					forfeit_sigs.insert(*id, sigs);
-				} else {
-					missing_forfeits.insert(*id);
 				}
 			}
 
 
+			timer.finish(RoundPhase::ForfeitSigning);
+
 			// ****************************************************************
 			// * Finish the round
 			// ****************************************************************
 
 			// Sign the on-chain tx.
-			app.sign_round_utxo_inputs(&mut round_tx_psbt).context("signing round inputs")?;
+			// We checked the swept round utxos when collecting them, so this
+			// shouldn't fail, but a single bad round shouldn't kill us.
+			let signing = app.sign_round_utxo_inputs(&mut round_tx_psbt).and_then(|s| s.check());
+			if let Err(e) = signing {
+				error!("Failed to sign the inputs of round tx {}, aborting round: {:#}",
+					round_tx_psbt.unsigned_tx.compute_txid(), e,
+				);
+				wallet.cancel_tx(&round_tx_psbt.unsigned_tx);
+				let reason = format!("failed to sign round tx: {:#}", e);
+				let _ = app.rounds().round_event_tx.send(RoundEvent::Failed {
+					id: round_id, seq: round_seq, reason: RoundFailReason::Other(reason.clone()),
+				});
+				app.emit_event(Event::RoundFailed { round_id, reason });
+				scheduler.round_failed();
+				store_next_round_start(&app, &scheduler);
+				continue 'round;
+			}
 			let opts = bdk_wallet::SignOptions {
 				trust_witness_utxo: true,
 				..Default::default()
@@ -746,31 +1801,132 @@ pub async fn run_round_coordinator(
 			drop(wallet); // we no longer need the lock
 
 			// Broadcast over bitcoind.
-			debug!("Broadcasting round tx {}", round_tx.compute_txid());
-			let bc = app.bitcoind.send_raw_transaction(&round_tx);
-			if let Err(e) = bc {
-				warn!("Couldn't broadcast round tx: {}", e);
+			let precheck = if cfg.round_tx_precheck {
+				precheck_round_tx(&app, &round_tx)
+			} else {
+				None
+			};
+			let broadcast_err = if let Some(reason) = precheck {
+				warn!("Round tx {} failed mempool pre-check: {}", round_tx.compute_txid(), reason);
+				Some((BroadcastRecovery::from_reject_reason(&reason), reason))
+			} else {
+				debug!("Broadcasting round tx {}", round_tx.compute_txid());
+				app.bitcoind.send_raw_transaction(&round_tx).err()
+					.map(|e| (BroadcastRecovery::from_error(&e), e.to_string()))
+			};
+			if let Some((recovery, e)) = broadcast_err {
+				match recovery {
+					BroadcastRecovery::Proceed => warn!("Couldn't broadcast round tx: {}", e),
+					BroadcastRecovery::BumpFee => {
+						let new_feerate = match bumped_round_feerate(&app, round_tx_feerate) {
+							Some(f) => f,
+							None => {
+								error!("Round tx rejected for insufficient fee ({}) at our max \
+									fee rate of {} sat/kwu, aborting round",
+									e, round_tx_feerate.to_sat_per_kwu(),
+								);
+								app.wallet.lock().await.cancel_tx(&round_tx);
+								let reason = format!("round tx fee too low at the max fee rate: {}", e);
+								let _ = app.rounds().round_event_tx.send(RoundEvent::Failed {
+									id: round_id,
+									seq: round_seq,
+									reason: RoundFailReason::Other(reason.clone()),
+								});
+								app.emit_event(Event::RoundFailed { round_id, reason });
+								scheduler.round_failed();
+								store_next_round_start(&app, &scheduler);
+								continue 'round;
+							},
+						};
+						warn!("Round tx rejected for insufficient fee ({}), retrying at {} sat/kwu",
+							e, new_feerate.to_sat_per_kwu(),
+						);
+						round_tx_feerate = new_feerate;
+						app.wallet.lock().await.cancel_tx(&round_tx);
+						app.emit_event(Event::RoundFailed {
+							round_id,
+							reason: format!("round tx fee too low: {}", e),
+						});
+						// Make participants resubmit their payments for the next attempt.
+						round_epoch = announce_round_start(&app, round_id, round_seq, offboard_feerate);
+						continue 'attempt;
+					},
+					BroadcastRecovery::Abort => {
+						error!("Round tx {} rejected by bitcoind: {}", round_tx.compute_txid(), e);
+						app.wallet.lock().await.cancel_tx(&round_tx);
+						let reason = format!("round tx rejected: {}", e);
+						let _ = app.rounds().round_event_tx.send(RoundEvent::Failed {
+							id: round_id, seq: round_seq, reason: RoundFailReason::Other(reason.clone()),
+						});
+						app.emit_event(Event::RoundFailed { round_id, reason });
+						scheduler.round_failed();
+						store_next_round_start(&app, &scheduler);
+						continue 'round;
+					},
+				}
 			}
 
+			timer.finish(RoundPhase::Broadcast);
+
 			// Send out the finished round to users.
 			trace!("Sending out finish event.");
 			let _ = app.rounds().round_event_tx.send(RoundEvent::Finished {
 				id: round_id,
+				seq: round_seq,
 				vtxos: signed_vtxos.clone(),
 				round_tx: round_tx.clone(),
 			});
+			app.emit_event(Event::RoundFinished {
+				round_id,
+				round_txid: round_tx.compute_txid(),
+				nb_input_vtxos: state.all_inputs.len(),
+				nb_output_vtxos: signed_vtxos.spec.vtxos.len(),
+				nb_offboards,
+			});
+			app.round_metrics.record(&timer);
+			app.emit_event(Event::RoundTimings {
+				round_id,
+				submit_collection_ms: timer.duration(RoundPhase::SubmitCollection).as_millis() as u64,
+				tree_construction_ms: timer.duration(RoundPhase::TreeConstruction).as_millis() as u64,
+				nonce_aggregation_ms: timer.duration(RoundPhase::NonceAggregation).as_millis() as u64,
+				vtxo_signing_ms: timer.duration(RoundPhase::VtxoSigning).as_millis() as u64,
+				forfeit_signing_ms: timer.duration(RoundPhase::ForfeitSigning).as_millis() as u64,
+				broadcast_ms: timer.duration(RoundPhase::Broadcast).as_millis() as u64,
+			});
+			if !spendable_utxos.is_empty() {
+				app.emit_event(Event::ExpiredRoundsSwept {
+					sweep_txid: round_tx.compute_txid(),
+					round_txids: spendable_utxos.iter().map(|u| u.point.txid)
+						.collect::<HashSet<_>>().into_iter().collect(),
+					nb_utxos: spendable_utxos.len(),
+					total_value: spendable_utxos.iter().map(|u| u.amount()).sum(),
+				});
+			}
 
 			// Store forfeit txs and round info in database.
 			let round_id = round_tx.compute_txid();
+			let connector_value = state.connectors.connector_value();
 			for (id, vtxo) in state.all_inputs {
 				let forfeit_sigs = forfeit_sigs.remove(&id).unwrap();
 				let point = vtxo.point();
 				trace!("Storing forfeit vtxo for vtxo {}", point);
-				app.db.store_forfeit_vtxo(ForfeitVtxo { vtxo, forfeit_sigs })?;
+				app.db.store_forfeit_vtxo(ForfeitVtxo {
+					vtxo,
+					forfeit_sigs,
+					round_txid: Some(round_id),
+					connector_value,
+				})?;
 			}
 
 			trace!("Storing round result");
-			app.db.store_round(round_tx.clone(), signed_vtxos)?;
+			app.db.store_round(
+				round_seq,
+				round_tx.clone(),
+				signed_vtxos,
+				bump_output,
+				vtxo_origin_heights,
+				asp_cosigners.pubkeys().collect(),
+			)?;
 
 			//TODO(stevenroose) we should have a system that actually tracks that this tx is
 			// getting confirmed!
@@ -781,6 +1937,8 @@ pub async fn run_round_coordinator(
 			}
 
 			info!("Finished round {} with tx {}", round_id, round_tx.compute_txid());
+			scheduler.round_succeeded();
+			store_next_round_start(&app, &scheduler);
 
 			// Sync our wallet so that it sees the broadcasted tx.
 			app.sync_onchain_wallet().await.context("error syncing onchain wallet")?;
@@ -788,3 +1946,603 @@ pub async fn run_round_coordinator(
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use ark::{ExitTimelockType, VtxoScriptType};
+	use ark::tree::signed::OutputKeyPolicy;
+	use bitcoin::{transaction, TxIn};
+	use bitcoin::secp256k1::Keypair;
+
+	fn txout(tag: u8, sat: u64) -> TxOut {
+		TxOut { script_pubkey: ScriptBuf::new_op_return(&[tag]), value: Amount::from_sat(sat) }
+	}
+
+	fn round_psbt(inputs: &[u64], outputs: Vec<TxOut>) -> Psbt {
+		let tx = Transaction {
+			version: transaction::Version::TWO,
+			lock_time: LockTime::ZERO,
+			input: inputs.iter().enumerate().map(|(i, _)| TxIn {
+				previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), i as u32),
+				..Default::default()
+			}).collect(),
+			output: outputs,
+		};
+		let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+		for (input, sat) in psbt.inputs.iter_mut().zip(inputs) {
+			input.witness_utxo = Some(txout(0, *sat));
+		}
+		psbt
+	}
+
+	#[test]
+	fn change_vtxo() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let spec = VtxoTreeSpec::new(
+			vec![VtxoRequest { pubkey: user_key.public_key(), amount: Amount::from_sat(100_000) }; 5],
+			musig::combine_keys([asp_key.public_key(), user_key.public_key()]),
+			asp_key.public_key(),
+			1_000,
+			12,
+			true,
+			OutputKeyPolicy::MerkleRootTweak,
+			ExitTimelockType::Relative,
+			VtxoScriptType::Taproot,
+		);
+
+		// The tree needs exactly the change extra.
+		let change = Amount::from_sat(40_000);
+		let with_change = change_vtxo_spec(&spec, change, Amount::MAX_MONEY).unwrap();
+		assert_eq!(with_change.total_required_value(), spec.total_required_value() + change);
+		assert_eq!(with_change.vtxos.len(), 6);
+		let vtxo = with_change.vtxos.last().unwrap();
+		assert_eq!(vtxo.pubkey, asp_key.public_key());
+		assert!(vtxo.amount < change);
+		assert_eq!(&with_change.vtxos[..5], &spec.vtxos[..]);
+
+		// The vtxo is capped, the rest of the change stays onchain.
+		let max = Amount::from_sat(10_000);
+		let capped = change_vtxo_spec(&spec, change, max).unwrap();
+		assert_eq!(capped.vtxos.last().unwrap().amount, max);
+		assert!(capped.total_required_value() < spec.total_required_value() + change);
+
+		// Change that doesn't cover a vtxo is left onchain.
+		assert_eq!(change_vtxo_spec(&spec, Amount::ZERO, Amount::MAX_MONEY), None);
+		assert_eq!(change_vtxo_spec(&spec, ark::fee::DUST, Amount::MAX_MONEY), None);
+	}
+
+	#[test]
+	fn round_tx_amount_invariants() {
+		let required = vec![txout(1, 50_000), txout(2, 1_000)];
+		let change = txout(3, 40_000);
+		let is_mine = |spk: &ScriptBuf| *spk == change.script_pubkey;
+		let max_fee = Amount::from_sat(10_000);
+
+		let mut outputs = required.clone();
+		outputs.push(change.clone());
+		let psbt = round_psbt(&[60_000, 32_000], outputs.clone());
+		let fee = check_round_tx_amounts(&psbt, &required, is_mine, max_fee).unwrap();
+		assert_eq!(fee, Amount::from_sat(1_000));
+
+		// Outputs exceeding the inputs.
+		let psbt = round_psbt(&[60_000, 30_000], outputs.clone());
+		check_round_tx_amounts(&psbt, &required, is_mine, max_fee).unwrap_err();
+
+		// An input that's not accounted for would go to fees.
+		let psbt = round_psbt(&[60_000, 32_000, 100_000], outputs.clone());
+		check_round_tx_amounts(&psbt, &required, is_mine, max_fee).unwrap_err();
+
+		// Underpaying the vtxo tree.
+		let mut short = outputs.clone();
+		short[0].value = Amount::from_sat(49_000);
+		let psbt = round_psbt(&[60_000, 32_000], short);
+		check_round_tx_amounts(&psbt, &required, is_mine, max_fee).unwrap_err();
+
+		// Leaking funds to an output that's not ours.
+		let mut leak = outputs.clone();
+		leak[2].script_pubkey = ScriptBuf::new_op_return(&[4]);
+		let psbt = round_psbt(&[60_000, 32_000], leak);
+		check_round_tx_amounts(&psbt, &required, is_mine, max_fee).unwrap_err();
+
+		// Missing a required output.
+		let psbt = round_psbt(&[60_000, 32_000], vec![txout(1, 50_000), change.clone()]);
+		check_round_tx_amounts(&psbt, &required, is_mine, max_fee).unwrap_err();
+	}
+
+	#[test]
+	fn explicit_expiry_height() {
+		let cfg = Config {
+			vtxo_expiry_delta: 144,
+			vtxo_exit_delta: 12,
+			..Config::default()
+		};
+		let tip = 1_000;
+		assert_eq!(round_expiry_height(&cfg, tip, None).unwrap(), 1_144);
+		assert_eq!(round_expiry_height(&cfg, tip, Some(1_100)).unwrap(), 1_100);
+		assert_eq!(round_expiry_height(&cfg, tip, Some(1_144)).unwrap(), 1_144);
+		// In the past, too close to exit in time or beyond the delta.
+		round_expiry_height(&cfg, tip, Some(900)).unwrap_err();
+		round_expiry_height(&cfg, tip, Some(1_012)).unwrap_err();
+		round_expiry_height(&cfg, tip, Some(1_145)).unwrap_err();
+
+		// The expiry clause of the round's vtxo tree uses the explicit height.
+		let expiry = round_expiry_height(&cfg, tip, Some(1_100)).unwrap();
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let spec = VtxoTreeSpec::new(
+			vec![VtxoRequest { pubkey: user_key.public_key(), amount: Amount::from_sat(100_000) }; 3],
+			musig::combine_keys([asp_key.public_key(), user_key.public_key()]),
+			asp_key.public_key(),
+			expiry,
+			cfg.vtxo_exit_delta,
+			true,
+			OutputKeyPolicy::MerkleRootTweak,
+			ExitTimelockType::Relative,
+			VtxoScriptType::Taproot,
+		);
+		let (_, script, _, _) = spec.expiry_scriptspend().unwrap();
+		let expected = ark::util::timelock_sign(1_100, asp_key.x_only_public_key().0);
+		assert_eq!(script, expected);
+		let height = script.instructions().next().unwrap().unwrap().script_num().unwrap();
+		assert_eq!(height, 1_100);
+	}
+
+	#[test]
+	fn round_funds_estimate() {
+		let feerate = FeeRate::from_sat_per_kwu(1_000);
+		let outputs = vec![txout(1, 100_000), txout(2, 1_000)];
+		let required = required_round_funds(&outputs, &[], feerate);
+		let fee = required - Amount::from_sat(101_000);
+		// A round tx paying these outputs is a couple hundred vbytes.
+		assert!(fee > Amount::from_sat(500) && fee < Amount::from_sat(1_500), "{}", fee);
+
+		// Swept utxos fund part of the round, but their inputs cost fee.
+		let sweep = SpendableUtxo {
+			point: OutPoint::new(bitcoin::Txid::all_zeros(), 0),
+			psbt: bitcoin::psbt::Input { witness_utxo: Some(txout(0, 50_000)), ..Default::default() },
+			weight: Weight::from_wu(66),
+		};
+		let with_sweep = required_round_funds(&outputs, &[sweep], feerate);
+		assert!(with_sweep > required - Amount::from_sat(50_000));
+		assert!(with_sweep < required);
+
+		// When the swept utxos cover everything, we don't need any funds.
+		let big_sweep = SpendableUtxo {
+			point: OutPoint::new(bitcoin::Txid::all_zeros(), 1),
+			psbt: bitcoin::psbt::Input { witness_utxo: Some(txout(0, 500_000)), ..Default::default() },
+			weight: Weight::from_wu(66),
+		};
+		assert_eq!(required_round_funds(&outputs, &[big_sweep], feerate), Amount::ZERO);
+	}
+
+	fn onboard_vtxo(user_key: &Keypair, asp_key: &Keypair, tag: u8, sat: u64) -> Vtxo {
+		Vtxo::Onboard {
+			base: ark::BaseVtxo {
+				spec: ark::VtxoSpec {
+					user_pubkey: user_key.public_key(),
+					asp_pubkey: asp_key.public_key(),
+					expiry_height: 1_000,
+					exit_delta: 12,
+					amount: Amount::from_sat(sat),
+					exit_timelock_type: ExitTimelockType::Relative,
+					script_type: VtxoScriptType::Taproot,
+				},
+				utxo: OutPoint::new(bitcoin::Txid::from_byte_array([tag; 32]), 0),
+			},
+			reveal_tx_signature: bitcoin::secp256k1::schnorr::Signature::from_slice(&[1; 64]).unwrap(),
+		}
+	}
+
+	#[test]
+	fn payment_reject_reasons() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let input1 = onboard_vtxo(&user_key, &asp_key, 1, 100_000);
+		let input2 = onboard_vtxo(&user_key, &asp_key, 2, 100_000);
+		let output = |sat| VtxoRequest { pubkey: user_key.public_key(), amount: Amount::from_sat(sat) };
+		let feerate = FeeRate::from_sat_per_vb_unchecked(1);
+
+		let err = validate_payment(&[input1.clone()], &[output(100_001)], &[], feerate).unwrap_err();
+		assert_eq!(err.reason, RejectReason::InvalidPayment);
+		let err = validate_payment(
+			&[input1.clone(), input1.clone()], &[output(100_000)], &[], feerate,
+		).unwrap_err();
+		assert_eq!(err.reason, RejectReason::DoubleSpend);
+		validate_payment(&[input1.clone()], &[output(100_000)], &[], feerate).unwrap();
+
+		let mut state = CollectingPayments::new(4, None, Weight::MAX, feerate);
+		let cosign1 = Keypair::new(&SECP, &mut rand::thread_rng()).public_key();
+		let cosign2 = Keypair::new(&SECP, &mut rand::thread_rng()).public_key();
+		let err = state.register_payment(
+			vec![input1.clone()], vec![output(10_000); 5], vec![], cosign1, vec![], 0,
+		).unwrap_err();
+		assert_eq!(err.reason, RejectReason::OverCapacity);
+		state.register_payment(
+			vec![input1.clone()], vec![output(100_000)], vec![], cosign1, vec![], 0,
+		).unwrap();
+		let err = state.register_payment(
+			vec![input1.clone()], vec![output(100_000)], vec![], cosign2, vec![], 0,
+		).unwrap_err();
+		assert_eq!(err.reason, RejectReason::DoubleSpend);
+		let err = state.register_payment(
+			vec![input2.clone()], vec![output(100_000)], vec![], cosign1, vec![], 0,
+		).unwrap_err();
+		assert_eq!(err.reason, RejectReason::InvalidPayment);
+
+		// After a restart with banned inputs, only the allowed ones are accepted.
+		let mut state = CollectingPayments::new(4, None, Weight::MAX, feerate);
+		state.allowed_inputs = Some([input1.id()].into_iter().collect());
+		let err = state.register_payment(
+			vec![input2], vec![output(100_000)], vec![], cosign1, vec![], 0,
+		).unwrap_err();
+		assert_eq!(err.reason, RejectReason::InvalidInput);
+	}
+
+	#[test]
+	fn forfeit_reject_reasons() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let inputs = [1, 2].map(|tag| onboard_vtxo(&user_key, &asp_key, tag, 100_000));
+		let connectors = ConnectorChain::new(
+			inputs.len(), OutPoint::new(bitcoin::Txid::all_zeros(), 0), asp_key.public_key(),
+			Amount::from_sat(1_000),
+		);
+		let mut state = SigningForfeits {
+			forfeit_part_sigs: HashMap::new(),
+			all_inputs: inputs.iter().map(|v| (v.id(), v.clone())).collect(),
+			allowed_inputs: HashSet::new(),
+			connectors,
+			proceed: false,
+		};
+		let sigs = |n: usize| {
+			let nonces = (0..n).map(|_| musig::nonce_pair(&user_key).1).collect::<Vec<_>>();
+			let sigs = vec![musig::MusigPartialSignature::from_slice(&[1; 32]).unwrap(); n];
+			(nonces, sigs)
+		};
+
+		// Too few signatures for the connectors.
+		let (nonces, part_sigs) = sigs(1);
+		let err = state.register_forfeits(vec![(inputs[0].id(), nonces, part_sigs)]).unwrap_err();
+		assert_eq!(err.reason, RejectReason::BadForfeitSignature);
+
+		// Signatures for a vtxo that isn't in the round.
+		let other = onboard_vtxo(&user_key, &asp_key, 3, 100_000);
+		let (nonces, part_sigs) = sigs(2);
+		let err = state.register_forfeits(vec![(other.id(), nonces, part_sigs)]).unwrap_err();
+		assert_eq!(err.reason, RejectReason::InvalidInput);
+		assert!(state.forfeit_part_sigs.is_empty());
+
+		// The valid ones are kept even if others in the same request are not.
+		let (nonces1, part_sigs1) = sigs(2);
+		let (nonces2, part_sigs2) = sigs(1);
+		let err = state.register_forfeits(vec![
+			(inputs[0].id(), nonces1, part_sigs1), (inputs[1].id(), nonces2, part_sigs2),
+		]).unwrap_err();
+		assert_eq!(err.reason, RejectReason::BadForfeitSignature);
+		assert_eq!(state.forfeit_part_sigs.len(), 1);
+		assert!(!state.proceed);
+
+		let (nonces, part_sigs) = sigs(2);
+		state.register_forfeits(vec![(inputs[1].id(), nonces, part_sigs)]).unwrap();
+		assert!(state.proceed);
+	}
+
+	#[test]
+	fn reject_inputs_out_of_phase() {
+		let (response, mut rx) = oneshot::channel();
+		RoundInput::ForfeitSignatures { signatures: vec![], response }.reject_out_of_phase(1);
+		let err = rx.try_recv().unwrap().unwrap_err();
+		assert_eq!(err.reason, RejectReason::PastDeadline);
+
+		let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let cancel = |epoch, response| RoundInput::CancelPayment {
+			input: VtxoId::from_slice(&[0; 36]).unwrap(),
+			user_pubkey: user_key.public_key(),
+			cosign_pubkey: user_key.public_key(),
+			epoch,
+			response,
+		};
+		let (response, mut rx) = oneshot::channel();
+		cancel(1, response).reject_out_of_phase(1);
+		let err = rx.try_recv().unwrap().unwrap_err();
+		assert_eq!(err.reason, RejectReason::PastDeadline);
+
+		// Inputs for another epoch are stale rather than late.
+		let (response, mut rx) = oneshot::channel();
+		cancel(0, response).reject_out_of_phase(1);
+		let err = rx.try_recv().unwrap().unwrap_err();
+		assert_eq!(err.reason, RejectReason::StaleEpoch);
+	}
+
+	#[test]
+	fn cancel_payment() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let feerate = FeeRate::from_sat_per_vb_unchecked(1);
+		let offboard = |sat| OffboardRequest {
+			script_pubkey: ScriptBuf::new_p2tr(&SECP, asp_key.x_only_public_key().0, None),
+			amount: Amount::from_sat(sat),
+		};
+		let users = (0..3).map(|_| Keypair::new(&SECP, &mut rand::thread_rng())).collect::<Vec<_>>();
+		let inputs = users.iter().enumerate().map(|(i, u)| {
+			onboard_vtxo(u, &asp_key, i as u8, 100_000)
+		}).collect::<Vec<_>>();
+		let payment = |state: &mut CollectingPayments, i: usize, cosign_pubkey| {
+			let output = VtxoRequest {
+				pubkey: users[i].public_key(),
+				amount: Amount::from_sat(10_000 + i as u64),
+			};
+			state.register_payment(
+				vec![inputs[i].clone()], vec![output; 2], vec![offboard(20_000 + i as u64)],
+				cosign_pubkey, vec![], i as u32,
+			)
+		};
+
+		let mut state = CollectingPayments::new(100, None, Weight::MAX, feerate);
+		for (i, user) in users.iter().enumerate() {
+			payment(&mut state, i, user.public_key()).unwrap();
+		}
+		assert_eq!(state.pending_payments().len(), 3);
+
+		// Only the owner of the input can cancel, and only the payment with
+		// the given cosign key.
+		let cancel = |state: &mut CollectingPayments, i: usize, user: usize, cosign: usize| {
+			state.cancel_payment(inputs[i].id(), users[user].public_key(), users[cosign].public_key())
+		};
+		let err = cancel(&mut state, 1, 0, 1).unwrap_err();
+		assert_eq!(err.reason, RejectReason::UnknownPayment);
+		let err = cancel(&mut state, 1, 1, 0).unwrap_err();
+		assert_eq!(err.reason, RejectReason::UnknownPayment);
+		cancel(&mut state, 1, 1, 1).unwrap();
+		let err = cancel(&mut state, 1, 1, 1).unwrap_err();
+		assert_eq!(err.reason, RejectReason::UnknownPayment);
+
+		// Everything of the payment is taken out, the others keep their outputs.
+		assert_eq!(state.all_inputs.len(), 2);
+		assert_eq!(state.all_outputs.len(), 4);
+		assert_eq!(state.all_output_origins, vec![0, 0, 2, 2]);
+		assert_eq!(state.all_offboards.len(), 2);
+		assert_eq!(state.offboards_weight, offboards_weight(&state.all_offboards));
+		assert!(!state.cosigners.contains(&users[1].public_key()));
+		let mut pending = state.pending_payments();
+		pending.sort_by_key(|p| p.offboards[0].amount);
+		assert_eq!(pending.len(), 2);
+		assert_eq!(pending[1].inputs, vec![inputs[2].id()]);
+		assert_eq!(pending[1].outputs[0].amount, Amount::from_sat(10_002));
+		assert_eq!(pending[1].offboards[0].amount, Amount::from_sat(20_002));
+
+		// The input is free for another payment, also with a fresh cosign key.
+		// The cancellation of the earlier payment doesn't apply to it.
+		let cosign = Keypair::new(&SECP, &mut rand::thread_rng()).public_key();
+		payment(&mut state, 1, cosign).unwrap();
+		assert_eq!(state.all_inputs.len(), 3);
+		let err = cancel(&mut state, 1, 1, 1).unwrap_err();
+		assert_eq!(err.reason, RejectReason::UnknownPayment);
+		cancel(&mut state, 2, 2, 2).unwrap();
+		assert_eq!(state.all_output_origins, vec![0, 0, 1, 1]);
+		let pending = state.pending_payments();
+		let last = pending.iter().find(|p| p.cosign_pubkey == cosign).unwrap();
+		assert_eq!(last.offboards[0].amount, Amount::from_sat(20_001));
+
+		// A cancel in a full round makes room for more payments.
+		let mut state = CollectingPayments::new(6, None, Weight::MAX, feerate);
+		payment(&mut state, 0, users[0].public_key()).unwrap();
+		assert!(!state.proceed);
+		payment(&mut state, 1, users[1].public_key()).unwrap();
+		assert!(state.proceed);
+		cancel(&mut state, 1, 1, 1).unwrap();
+		assert!(!state.proceed);
+	}
+
+	#[test]
+	fn round_tx_weight_limit() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let feerate = FeeRate::from_sat_per_vb_unchecked(1);
+		let offboard = OffboardRequest {
+			script_pubkey: ScriptBuf::new_p2tr(&SECP, asp_key.x_only_public_key().0, None),
+			amount: Amount::from_sat(50_000),
+		};
+		let offboard_weight = offboards_weight(&[offboard.clone()]);
+		assert_eq!(offboard_weight, P2TR_OUTPUT);
+		let payment = |state: &mut CollectingPayments, tag: u8, nb_offboards: usize| {
+			let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+			let input = onboard_vtxo(&user_key, &asp_key, tag, 1_000_000);
+			state.register_payment(
+				vec![input], vec![], vec![offboard.clone(); nb_offboards], user_key.public_key(),
+				vec![], 0,
+			)
+		};
+
+		// Room for exactly three offboards.
+		let max_weight = offboard_weight * 3;
+		let mut state = CollectingPayments::new(100, None, max_weight, feerate);
+		payment(&mut state, 1, 2).unwrap();
+		assert!(!state.proceed);
+		// The next one would exceed the limit, so it's moved to the next
+		// round and the current round proceeds without it.
+		let err = payment(&mut state, 2, 2).unwrap_err();
+		assert_eq!(err.reason, RejectReason::OverCapacity);
+		assert!(state.proceed);
+		assert_eq!(state.all_offboards.len(), 2);
+		assert_eq!(state.offboards_weight, offboard_weight * 2);
+
+		let mut next = CollectingPayments::new(100, None, max_weight, feerate);
+		payment(&mut next, 2, 2).unwrap();
+		payment(&mut next, 3, 1).unwrap();
+		assert_eq!(next.offboards_weight, max_weight);
+
+		// A payment that never fits is invalid.
+		let mut state = CollectingPayments::new(100, None, max_weight, feerate);
+		let err = payment(&mut state, 4, 4).unwrap_err();
+		assert_eq!(err.reason, RejectReason::InvalidPayment);
+		assert!(!state.proceed);
+
+		// The default limit leaves room below the standardness limit.
+		let cfg = Config::default();
+		assert!(cfg.round_tx_max_weight < MAX_STANDARD_TX_WEIGHT as u64);
+		assert!(round_tx_base_weight(&cfg).to_wu() < cfg.round_tx_max_weight);
+	}
+
+	#[test]
+	fn round_input_limit() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let feerate = FeeRate::from_sat_per_vb_unchecked(1);
+		let payment = |state: &mut CollectingPayments, tags: &[u8]| {
+			let user_key = Keypair::new(&SECP, &mut rand::thread_rng());
+			let inputs = tags.iter().map(|t| onboard_vtxo(&user_key, &asp_key, *t, 100_000)).collect();
+			let output = VtxoRequest { pubkey: user_key.public_key(), amount: Amount::from_sat(1_000) };
+			state.register_payment(inputs, vec![output], vec![], user_key.public_key(), vec![], 0)
+		};
+
+		let mut state = CollectingPayments::new(100, Some(3), Weight::MAX, feerate);
+		payment(&mut state, &[1, 2]).unwrap();
+		assert!(!state.proceed);
+		// Too many inputs for what's left.
+		let err = payment(&mut state, &[3, 4]).unwrap_err();
+		assert_eq!(err.reason, RejectReason::RoundFull);
+		assert!(!state.proceed);
+		// The last one fills the round.
+		payment(&mut state, &[3]).unwrap();
+		assert!(state.proceed);
+		let err = payment(&mut state, &[4]).unwrap_err();
+		assert_eq!(err.reason, RejectReason::RoundFull);
+		assert_eq!(state.all_inputs.len(), 3);
+
+		// Without a limit, only the outputs count.
+		let mut state = CollectingPayments::new(100, None, Weight::MAX, feerate);
+		payment(&mut state, &[1, 2, 3, 4]).unwrap();
+		assert!(!state.proceed);
+	}
+
+	#[test]
+	fn aggregate_vtxo_sigs_batched() {
+		//! Aggregating in batches gives the same signatures as all at once.
+
+		const NB_COSIGNERS: usize = 32;
+		const NB_NODES: usize = 127;
+		let keys = (0..NB_COSIGNERS)
+			.map(|_| Keypair::new(&SECP, &mut rand::thread_rng()))
+			.collect::<Vec<_>>();
+		let key_agg = musig::key_agg(keys.iter().map(|k| k.public_key()));
+		let sighashes = (0..NB_NODES)
+			.map(|_| TapSighash::from_byte_array(rand::random()))
+			.collect::<Vec<_>>();
+
+		let mut agg_nonces = Vec::with_capacity(NB_NODES);
+		let mut partial_sigs = Vec::with_capacity(NB_NODES);
+		for sighash in &sighashes {
+			let nonces = keys.iter().map(|k| musig::nonce_pair(k)).collect::<Vec<_>>();
+			let agg_nonce = musig::nonce_agg(nonces.iter().map(|n| n.1));
+			let sigs = keys.iter().zip(nonces).map(|(key, (sec, _))| {
+				musig::partial_sign(
+					keys.iter().map(|k| k.public_key()),
+					agg_nonce,
+					key,
+					sec,
+					sighash.to_byte_array(),
+					None,
+					None,
+				).0
+			}).collect::<Vec<_>>();
+			agg_nonces.push(agg_nonce);
+			partial_sigs.push(sigs);
+		}
+
+		let mut outputs = Vec::new();
+		for batch_size in [NB_NODES, 1, 10, 32, NB_NODES + 1] {
+			let mut progress = Vec::new();
+			let sigs = aggregate_vtxo_sigs(
+				&key_agg, &agg_nonces, &sighashes, &partial_sigs, batch_size, |d| progress.push(d),
+			);
+			assert_eq!(progress.len(), NB_NODES.div_ceil(batch_size));
+			assert_eq!(progress.last(), Some(&NB_NODES));
+			outputs.push(sigs);
+		}
+
+		let agg_pk = musig::xonly_from(key_agg.agg_pk());
+		for (sig, sighash) in outputs[0].iter().zip(&sighashes) {
+			let msg = bitcoin::secp256k1::Message::from_digest(sighash.to_byte_array());
+			SECP.verify_schnorr(sig, &msg, &agg_pk).unwrap();
+		}
+		for sigs in &outputs[1..] {
+			assert_eq!(sigs, &outputs[0]);
+		}
+	}
+
+	#[test]
+	fn output_ordering_keeps_leaf_origins() {
+		let asp_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let requests = (0..8u64).map(|i| {
+			let key = Keypair::new(&SECP, &mut rand::thread_rng());
+			// Some equal amounts, so the pubkey is used to sort.
+			let amount = Amount::from_sat(10_000 + (i % 3) * 1_000);
+			VtxoRequest { pubkey: key.public_key(), amount }
+		}).collect::<Vec<_>>();
+		let origins = (0..8).map(|i| 500 + i).collect::<Vec<u32>>();
+
+		let mut rng = rand::rngs::StdRng::from_entropy();
+		for ordering in [
+			RoundOutputOrdering::Submission, RoundOutputOrdering::Bip69, RoundOutputOrdering::Shuffle,
+		] {
+			let mut vtxos = requests.clone();
+			let mut vtxo_origins = origins.clone();
+			order_vtxo_requests(ordering, &mut vtxos, &mut vtxo_origins, &mut rng);
+			let spec = VtxoTreeSpec::new(
+				vtxos.clone(),
+				asp_key.public_key(),
+				asp_key.public_key(),
+				1_000,
+				12,
+				true,
+				OutputKeyPolicy::MerkleRootTweak,
+				ExitTimelockType::Relative,
+				VtxoScriptType::Taproot,
+			);
+
+			// Every leaf still maps to the origin height of its own request.
+			for (req, origin) in requests.iter().zip(&origins) {
+				let leaf_idxs = spec.find_leaf_idxs(req).collect::<Vec<_>>();
+				assert_eq!(leaf_idxs.len(), 1, "{}", ordering);
+				assert_eq!(vtxo_origins[leaf_idxs[0]], *origin, "{}", ordering);
+			}
+
+			match ordering {
+				RoundOutputOrdering::Submission => assert_eq!(vtxos, requests),
+				RoundOutputOrdering::Bip69 => {
+					for w in vtxos.windows(2) {
+						let key = |r: &VtxoRequest| (r.amount, r.pubkey.serialize());
+						assert!(key(&w[0]) < key(&w[1]));
+					}
+					// The order doesn't depend on the order of submission.
+					let mut reversed = requests.iter().rev().cloned().collect::<Vec<_>>();
+					let mut reversed_origins = origins.iter().rev().copied().collect::<Vec<_>>();
+					order_vtxo_requests(ordering, &mut reversed, &mut reversed_origins, &mut rng);
+					assert_eq!(reversed, vtxos);
+					assert_eq!(reversed_origins, vtxo_origins);
+				},
+				RoundOutputOrdering::Shuffle => {},
+			}
+		}
+
+		// Offboards are ordered by amount and then by script.
+		let mut offboards = vec![
+			txout(2, 2_000), txout(3, 1_000), txout(1, 2_000),
+		].into_iter().map(|o| OffboardRequest { script_pubkey: o.script_pubkey, amount: o.value })
+			.collect::<Vec<_>>();
+		order_offboards(RoundOutputOrdering::Bip69, &mut offboards, &mut rng);
+		let tags = offboards.iter().map(|o| o.script_pubkey.as_bytes()[2]).collect::<Vec<_>>();
+		assert_eq!(tags, vec![3, 1, 2]);
+	}
+
+	#[test]
+	fn output_ordering_from_str() {
+		for ordering in [
+			RoundOutputOrdering::Submission, RoundOutputOrdering::Bip69, RoundOutputOrdering::Shuffle,
+		] {
+			assert_eq!(ordering.to_string().parse::<RoundOutputOrdering>().unwrap(), ordering);
+		}
+		"random".parse::<RoundOutputOrdering>().unwrap_err();
+	}
+}
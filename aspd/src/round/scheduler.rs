@@ -0,0 +1,167 @@
+
+use std::cmp;
+use std::time::Duration;
+
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// Decides when to start the next round.
+///
+/// Rounds start on the ticks of the round interval. The round coordinator
+/// runs a single round at a time: from its start until its round tx is
+/// broadcast and stored. The inputs of a round are only marked forfeited at
+/// the very end, so an overlapping round could let users spend them twice.
+///
+/// When a round takes longer than the round interval, f.e. because bitcoind
+/// is slow, the next tick only fires once that round is done. We don't
+/// start rounds back to back to catch up on the missed ticks.
+///
+/// After a failed round, the time until the next round doubles with every
+/// consecutive failure, up to the max round interval, so that we don't
+/// keep failing rounds in a tight loop when f.e. bitcoind is down.
+pub struct RoundScheduler {
+	interval: Interval,
+	round_interval: Duration,
+	max_round_interval: Duration,
+	/// The number of consecutive failed rounds.
+	nb_failures: u32,
+	/// The start time of the last round.
+	round_start: Instant,
+}
+
+impl RoundScheduler {
+	pub fn new(round_interval: Duration, max_round_interval: Duration) -> RoundScheduler {
+		let now = Instant::now();
+		let mut interval = tokio::time::interval_at(now + round_interval, round_interval);
+		interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		RoundScheduler {
+			interval,
+			round_interval,
+			max_round_interval,
+			nb_failures: 0,
+			round_start: now,
+		}
+	}
+
+	/// The number of consecutive failed rounds we're backing off for.
+	pub fn backoff_level(&self) -> u32 {
+		self.nb_failures
+	}
+
+	/// The time between the start of the last round and the next one.
+	pub fn current_interval(&self) -> Duration {
+		let factor = 1u32.checked_shl(self.nb_failures).unwrap_or(u32::MAX);
+		self.round_interval.checked_mul(factor)
+			.map(|i| cmp::min(i, self.max_round_interval))
+			.unwrap_or(self.max_round_interval)
+	}
+
+	/// When the next round is expected to start, if the current round is done by then.
+	pub fn next_round_start(&self) -> Instant {
+		self.round_start + self.current_interval()
+	}
+
+	/// Wait for the next tick of the round interval.
+	///
+	/// This is cancel safe.
+	pub async fn tick(&mut self) {
+		self.interval.tick().await;
+	}
+
+	/// Register the start of a new round.
+	///
+	/// The next tick will be a full round interval after the start of this round,
+	/// including the backoff of previous failed rounds.
+	pub fn start_round(&mut self) {
+		self.round_start = Instant::now();
+		self.interval.reset_at(self.round_start + self.current_interval());
+	}
+
+	/// Register that the last round failed and back off.
+	pub fn round_failed(&mut self) {
+		self.nb_failures = self.nb_failures.saturating_add(1);
+		self.interval.reset_at(self.round_start + self.current_interval());
+	}
+
+	/// Register that the last round succeeded and stop backing off.
+	pub fn round_succeeded(&mut self) {
+		self.nb_failures = 0;
+		self.interval.reset_at(self.round_start + self.current_interval());
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test(start_paused = true)]
+	async fn slow_round_defers_tick() {
+		let interval = Duration::from_secs(10);
+		let mut scheduler = RoundScheduler::new(interval, interval * 10);
+
+		let mut starts = Vec::new();
+		for i in 0..4 {
+			scheduler.tick().await;
+			scheduler.start_round();
+			starts.push(Instant::now());
+			// The second round has an artificially slow broadcast.
+			let round_time = if i == 1 { interval * 3 } else { interval / 10 };
+			tokio::time::sleep(round_time).await;
+		}
+
+		let gaps = starts.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+		assert_eq!(gaps[0], interval);
+		// The tick during the slow round fired once it finished...
+		assert_eq!(gaps[1], interval * 3);
+		// ...and we didn't start another round right after it to catch up.
+		assert_eq!(gaps[2], interval);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn backoff_after_failures() {
+		let interval = Duration::from_secs(10);
+		let max_interval = interval * 6;
+		let mut scheduler = RoundScheduler::new(interval, max_interval);
+
+		// Every round fails to broadcast until the sixth one succeeds.
+		let mut starts = Vec::new();
+		for i in 0..8 {
+			scheduler.tick().await;
+			scheduler.start_round();
+			starts.push(Instant::now());
+			if i < 5 {
+				scheduler.round_failed();
+			} else {
+				scheduler.round_succeeded();
+			}
+		}
+		assert_eq!(scheduler.backoff_level(), 0);
+
+		let gaps = starts.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+		// The interval doubles after every failure until it hits the max
+		// round interval. After the first success, we're back at the
+		// round interval.
+		assert_eq!(gaps, vec![
+			interval * 2,
+			interval * 4,
+			max_interval,
+			max_interval,
+			max_interval,
+			interval,
+			interval,
+		]);
+	}
+
+	#[tokio::test]
+	async fn backoff_interval() {
+		let mut scheduler = RoundScheduler::new(Duration::from_secs(10), Duration::from_secs(300));
+		assert_eq!(scheduler.current_interval(), Duration::from_secs(10));
+		scheduler.nb_failures = 1;
+		assert_eq!(scheduler.current_interval(), Duration::from_secs(20));
+		scheduler.nb_failures = 4;
+		assert_eq!(scheduler.current_interval(), Duration::from_secs(160));
+		scheduler.nb_failures = 5;
+		assert_eq!(scheduler.current_interval(), Duration::from_secs(300));
+		scheduler.nb_failures = u32::MAX;
+		assert_eq!(scheduler.current_interval(), Duration::from_secs(300));
+	}
+}
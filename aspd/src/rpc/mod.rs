@@ -6,23 +6,71 @@ pub use self::aspd::admin_service_server::{AdminService, AdminServiceServer};
 
 mod convert {
 	use crate::rpc;
-	use crate::round::RoundEvent;
+	use crate::round::{InputRejected, RejectReason, RoundEvent, RoundFailReason};
+
+	impl<'a> From<&'a RoundFailReason> for rpc::RoundFailureKind {
+		fn from(r: &'a RoundFailReason) -> Self {
+			match r {
+				RoundFailReason::InsufficientAspFunds { .. } => {
+					rpc::RoundFailureKind::InsufficientAspFunds
+				},
+				RoundFailReason::Other(_) => rpc::RoundFailureKind::RoundFailureOther,
+			}
+		}
+	}
+
+	impl From<RejectReason> for rpc::SubmitRejectReason {
+		fn from(r: RejectReason) -> Self {
+			match r {
+				RejectReason::StaleEpoch => rpc::SubmitRejectReason::StaleEpoch,
+				RejectReason::DoubleSpend => rpc::SubmitRejectReason::DoubleSpend,
+				RejectReason::Dust => rpc::SubmitRejectReason::Dust,
+				RejectReason::PastDeadline => rpc::SubmitRejectReason::PastDeadline,
+				RejectReason::OverCapacity => rpc::SubmitRejectReason::OverCapacity,
+				RejectReason::BadForfeitSignature => rpc::SubmitRejectReason::BadForfeitSignature,
+				RejectReason::InvalidInput => rpc::SubmitRejectReason::InvalidInput,
+				RejectReason::InvalidPayment => rpc::SubmitRejectReason::InvalidPayment,
+				RejectReason::RoundFull => rpc::SubmitRejectReason::RoundFull,
+				RejectReason::UnknownPayment => rpc::SubmitRejectReason::UnknownPayment,
+			}
+		}
+	}
+
+	impl From<Result<(), InputRejected>> for rpc::SubmitResponse {
+		fn from(res: Result<(), InputRejected>) -> Self {
+			match res {
+				Ok(()) => rpc::SubmitResponse {
+					reject_reason: rpc::SubmitRejectReason::Accepted.into(),
+					reject_message: String::new(),
+					next_round_start_ms: 0,
+				},
+				Err(e) => rpc::SubmitResponse {
+					reject_reason: rpc::SubmitRejectReason::from(e.reason).into(),
+					reject_message: e.message,
+					next_round_start_ms: 0,
+				},
+			}
+		}
+	}
 
 	impl From<RoundEvent> for rpc::RoundEvent {
 		fn from(e: RoundEvent) -> Self {
 			rpc::RoundEvent {
 				event: Some(match e {
-					RoundEvent::Start { id, offboard_feerate } => {
+					RoundEvent::Start { id, seq, offboard_feerate, epoch } => {
 						rpc::round_event::Event::Start(rpc::RoundStart {
 							round_id: id,
+							round_seq: seq,
 							offboard_feerate_sat_vkb: offboard_feerate.to_sat_per_kwu() * 4,
+							round_epoch: epoch,
 						})
 					},
 					RoundEvent::VtxoProposal {
-						id, vtxos_spec, round_tx, cosigners, cosign_agg_nonces,
+						id, seq, vtxos_spec, round_tx, cosigners, cosign_agg_nonces,
 					} => {
 						rpc::round_event::Event::VtxoProposal(rpc::VtxoProposal {
 							round_id: id,
+							round_seq: seq,
 							vtxos_spec: vtxos_spec.encode(),
 							round_tx: bitcoin::consensus::serialize(&round_tx),
 							vtxos_signers: cosigners.into_iter()
@@ -33,9 +81,10 @@ mod convert {
 								.collect(),
 						})
 					},
-					RoundEvent::RoundProposal { id, vtxos, round_tx, forfeit_nonces } => {
+					RoundEvent::RoundProposal { id, seq, vtxos, round_tx, forfeit_nonces } => {
 						rpc::round_event::Event::RoundProposal(rpc::RoundProposal {
 							round_id: id,
+							round_seq: seq,
 							signed_vtxos: vtxos.encode(),
 							round_tx: bitcoin::consensus::serialize(&round_tx),
 							forfeit_nonces: forfeit_nonces.into_iter().map(|(id, nonces)| {
@@ -48,13 +97,22 @@ mod convert {
 							}).collect(),
 						})
 					},
-					RoundEvent::Finished { id, vtxos, round_tx } => {
+					RoundEvent::Finished { id, seq, vtxos, round_tx } => {
 						rpc::round_event::Event::Finished(rpc::RoundFinished {
 							round_id: id,
+							round_seq: seq,
 							signed_vtxos: vtxos.encode(),
 							round_tx: bitcoin::consensus::serialize(&round_tx),
 						})
 					},
+					RoundEvent::Failed { id, seq, reason } => {
+						rpc::round_event::Event::Failed(rpc::RoundFailed {
+							round_id: id,
+							round_seq: seq,
+							kind: rpc::RoundFailureKind::from(&reason) as i32,
+							reason: reason.to_string(),
+						})
+					},
 				})
 			}
 		}
@@ -69,4 +127,46 @@ mod convert {
 			}
 		}
 	}
+
+	impl From<crate::VtxoStatus> for rpc::VtxoStatus {
+		fn from(value: crate::VtxoStatus) -> Self {
+			match value {
+				crate::VtxoStatus::Active => rpc::VtxoStatus::Active,
+				crate::VtxoStatus::Forfeited => rpc::VtxoStatus::Forfeited,
+				crate::VtxoStatus::Swept => rpc::VtxoStatus::Swept,
+				crate::VtxoStatus::Expired => rpc::VtxoStatus::Expired,
+				crate::VtxoStatus::Unknown => rpc::VtxoStatus::Unknown,
+			}
+		}
+	}
+
+	impl From<ark::tree::signed::OutputKeyPolicy> for rpc::VtxoOutputKeyPolicy {
+		fn from(value: ark::tree::signed::OutputKeyPolicy) -> Self {
+			match value {
+				ark::tree::signed::OutputKeyPolicy::MerkleRootTweak => {
+					rpc::VtxoOutputKeyPolicy::MerkleRootTweak
+				},
+				ark::tree::signed::OutputKeyPolicy::PlainAggregate => {
+					rpc::VtxoOutputKeyPolicy::PlainAggregate
+				},
+			}
+		}
+	}
+
+	impl From<ark::ExitTimelockType> for rpc::VtxoExitTimelock {
+		fn from(value: ark::ExitTimelockType) -> Self {
+			match value {
+				ark::ExitTimelockType::Relative => rpc::VtxoExitTimelock::Relative,
+				ark::ExitTimelockType::Absolute => rpc::VtxoExitTimelock::Absolute,
+			}
+		}
+	}
+
+	impl From<ark::VtxoScriptType> for rpc::VtxoScriptType {
+		fn from(value: ark::VtxoScriptType) -> Self {
+			match value {
+				ark::VtxoScriptType::Taproot => rpc::VtxoScriptType::Taproot,
+			}
+		}
+	}
 }
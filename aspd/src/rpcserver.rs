@@ -1,24 +1,43 @@
 
+use std::{cmp, fs};
+use std::collections::HashSet;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{atomic, Arc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use anyhow::Context;
 use ark::lightning::SignedBolt11Payment;
-use bitcoin::{Amount, ScriptBuf, Txid};
+use aspd_rpc_client::ADMIN_TOKEN_HEADER;
+use bdk_bitcoind_rpc::bitcoincore_rpc::RpcApi;
+use bitcoin::{Address, Amount, FeeRate, ScriptBuf, Txid};
 use bitcoin::hashes::Hash;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{schnorr, PublicKey};
 use lightning_invoice::Bolt11Invoice;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
 use tokio_stream::{Stream, StreamExt};
 use tokio_stream::wrappers::BroadcastStream;
+use tonic::codec::CompressionEncoding;
 
 use stream_until::{StreamUntilItem, StreamExt as StreamExtUntil};
 
 use ark::{musig, OffboardRequest, VtxoRequest, Vtxo, VtxoId};
+use ark::connectors::{self, ConnectorChain};
 
-use crate::App;
+use crate::{metrics, App, RoundHandle, SweepRejected};
+use crate::nonce_pool::NoncePoolExhausted;
 use crate::rpc;
-use crate::round::RoundInput;
+use crate::round::{self, RoundInput, RoundTrigger};
 use crate::lightning::pay_bolt11;
 
+/// How far the timestamp of a [rpc::VtxosForPubkeyRequest] can be from our time.
+const VTXOS_FOR_PUBKEY_MAX_TIME_DIFF: Duration = Duration::from_secs(5 * 60);
+
+/// How long a payment submission waits for the round coordinator to answer.
+const SUBMIT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 macro_rules! badarg {
 	($($arg:tt)*) => {{
 		tonic::Status::invalid_argument(format!($($arg)*))
@@ -37,6 +56,34 @@ macro_rules! not_found {
 	}};
 }
 
+/// Respond to a round submission with the given rejection reason.
+macro_rules! rejected {
+	($reason:ident, $($arg:tt)*) => {{
+		Ok(tonic::Response::new(rpc::SubmitResponse::from(Err(round::InputRejected::new(
+			round::RejectReason::$reason, format!($($arg)*),
+		)))))
+	}};
+}
+
+/// Respond to a round payment, with a hint when to retry if it
+/// didn't fit in the round.
+fn payment_response(
+	rounds: &RoundHandle,
+	res: Result<(), round::InputRejected>,
+) -> rpc::SubmitResponse {
+	let retry = match res {
+		Err(ref e) => matches!(e.reason,
+			round::RejectReason::RoundFull | round::RejectReason::OverCapacity,
+		),
+		Ok(()) => false,
+	};
+	let mut ret = rpc::SubmitResponse::from(res);
+	if retry {
+		ret.next_round_start_ms = rounds.next_round_start.load(atomic::Ordering::SeqCst);
+	}
+	ret
+}
+
 /// Just a trait to easily convert some kind of errors to tonic things.
 trait ToStatus<T> {
 	fn to_status(self) -> Result<T, tonic::Status>;
@@ -44,8 +91,71 @@ trait ToStatus<T> {
 
 impl<T> ToStatus<T> for anyhow::Result<T> {
 	fn to_status(self) -> Result<T, tonic::Status> {
-		self.map_err(|e| tonic::Status::internal(format!("internal error: {}", e)))
+		self.map_err(|e| {
+			if let Some(e) = e.downcast_ref::<NoncePoolExhausted>() {
+				tonic::Status::resource_exhausted(e.to_string())
+			} else if let Some(e) = e.downcast_ref::<SweepRejected>() {
+				tonic::Status::failed_precondition(e.to_string())
+			} else {
+				tonic::Status::internal(format!("internal error: {}", e))
+			}
+		})
+	}
+}
+
+/// Check that an admin request carries the configured admin token.
+fn check_admin_token<T>(app: &App, req: &tonic::Request<T>) -> Result<(), tonic::Status> {
+	let token = app.config.admin_rpc_token.as_ref()
+		.ok_or_else(|| tonic::Status::permission_denied("no admin token configured"))?;
+	let given = req.metadata().get(ADMIN_TOKEN_HEADER)
+		.ok_or_else(|| tonic::Status::unauthenticated("missing admin token"))?;
+	// Compare in constant time so that the token can't be guessed byte by byte.
+	if !bool::from(given.as_bytes().ct_eq(token.as_bytes())) {
+		return Err(tonic::Status::unauthenticated("invalid admin token"));
+	}
+	Ok(())
+}
+
+/// Check that an offboard has a standard script and is not dust.
+fn check_offboard(offb: &OffboardRequest) -> Result<(), round::InputRejected> {
+	match OffboardRequest::dust_limit(&offb.script_pubkey) {
+		None => Err(round::InputRejected::new(round::RejectReason::InvalidPayment,
+			"invalid offboard request: non-standard script",
+		)),
+		Some(dust) if offb.amount < dust => Err(round::InputRejected::new(round::RejectReason::Dust,
+			format!("offboard amount {} is below the dust limit of {}", offb.amount, dust),
+		)),
+		Some(_) => Ok(()),
+	}
+}
+
+/// Decode an onboard [UserPart] and check it against our policies.
+fn decode_onboard_user_part(
+	app: &App,
+	bytes: &[u8],
+) -> Result<ark::onboard::UserPart, tonic::Status> {
+	let user_part = ark::onboard::UserPart::decode(bytes)
+		.map_err(|e| badarg!("invalid user part: {}", e))?;
+	if user_part.spec.asp_pubkey != app.asp_pubkey {
+		return Err(badarg!("ASP public key is incorrect!"));
+	}
+	if user_part.spec.script_type != app.config.vtxo_script_type {
+		return Err(badarg!("onboard vtxo should have {} scripts", app.config.vtxo_script_type));
+	}
+
+	if let Some(max) = app.config.max_onboard_value {
+		if user_part.spec.amount > max {
+			return Err(badarg!("onboard amount exceeds limit of {}", max));
+		}
+	}
+	if let Some(max) = app.config.max_vtxo_lifetime_blocks {
+		let tip = app.bitcoind.get_block_count()
+			.map_err(|e| internal!("failed to get block height: {}", e))? as u32;
+		if user_part.spec.expiry_height > tip + max {
+			return Err(badarg!("onboard vtxo can't live beyond height {}", tip + max));
+		}
 	}
+	Ok(user_part)
 }
 
 #[tonic::async_trait]
@@ -56,11 +166,21 @@ impl rpc::ArkService for Arc<App> {
 	) -> Result<tonic::Response<rpc::ArkInfo>, tonic::Status> {
 		let ret = rpc::ArkInfo {
 			network: self.config.network.to_string(),
-			pubkey: self.master_key.public_key().serialize().to_vec(),
-			xonly_pubkey: self.master_key.x_only_public_key().0.serialize().to_vec(),
+			pubkey: self.asp_pubkey.serialize().to_vec(),
+			xonly_pubkey: self.asp_pubkey.x_only_public_key().0.serialize().to_vec(),
 			nb_round_nonces: self.config.nb_round_nonces as u32,
 			vtxo_exit_delta: self.config.vtxo_exit_delta as u32,
 			vtxo_expiry_delta: self.config.vtxo_expiry_delta as u32,
+			vtxo_output_key_policy: rpc::VtxoOutputKeyPolicy::from(
+				self.config.vtxo_output_key_policy,
+			) as i32,
+			onboard_confirmations: self.config.onboard_confirmations,
+			vtxo_exit_timelock: rpc::VtxoExitTimelock::from(
+				self.config.vtxo_exit_timelock,
+			) as i32,
+			vtxo_script_type: rpc::VtxoScriptType::from(self.config.vtxo_script_type) as i32,
+			oor_min_amount_sat: self.config.oor_min_amount.to_sat(),
+			timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
 		};
 		Ok(tonic::Response::new(ret))
 	}
@@ -88,6 +208,68 @@ impl rpc::ArkService for Arc<App> {
 		Ok(tonic::Response::new(rpc::RoundInfo {
 			round_tx: bitcoin::consensus::serialize(&ret.tx),
 			signed_vtxos: ret.signed_tree.encode(),
+			round_seq: ret.seq,
+		}))
+	}
+
+	async fn get_vtxo_status(
+		&self,
+		req: tonic::Request<rpc::VtxoStatusRequest>,
+	) -> Result<tonic::Response<rpc::VtxoStatusResponse>, tonic::Status> {
+		let req = req.into_inner();
+		let id = VtxoId::from_slice(&req.vtxo_id)
+			.map_err(|e| badarg!("invalid vtxo id: {}", e))?;
+		let vtxo = req.vtxo.map(|v| Vtxo::decode(&v)).transpose()
+			.map_err(|e| badarg!("invalid vtxo: {}", e))?;
+		if let Some(ref v) = vtxo {
+			if v.id() != id {
+				return Err(badarg!("vtxo doesn't match vtxo id"));
+			}
+		}
+		let status = self.vtxo_status(id, vtxo.as_ref()).map_err(|e| internal!("{}", e))?;
+		Ok(tonic::Response::new(rpc::VtxoStatusResponse {
+			status: rpc::VtxoStatus::from(status) as i32,
+		}))
+	}
+
+	async fn get_vtxos_for_pubkey(
+		&self,
+		req: tonic::Request<rpc::VtxosForPubkeyRequest>,
+	) -> Result<tonic::Response<rpc::VtxosForPubkeyResponse>, tonic::Status> {
+		let req = req.into_inner();
+		let pubkey = PublicKey::from_slice(&req.pubkey)
+			.map_err(|e| badarg!("invalid pubkey: {}", e))?;
+		let signature = schnorr::Signature::from_slice(&req.signature)
+			.map_err(|e| badarg!("invalid signature: {}", e))?;
+
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+		if now.abs_diff(req.timestamp_ms) > VTXOS_FOR_PUBKEY_MAX_TIME_DIFF.as_millis() as u64 {
+			return Err(badarg!("request timestamp is too far from the current time"));
+		}
+
+		// Watch-only wallets sign with a watch key the owner of the pubkey delegated to.
+		let signer = match req.delegation {
+			Some(delegation) => {
+				let watch_pubkey = PublicKey::from_slice(&delegation.watch_pubkey)
+					.map_err(|e| badarg!("invalid watch pubkey: {}", e))?;
+				let delegation_sig = schnorr::Signature::from_slice(&delegation.signature)
+					.map_err(|e| badarg!("invalid delegation signature: {}", e))?;
+				let msg = aspd_rpc_client::watch_delegation_message(pubkey, watch_pubkey);
+				crate::SECP.verify_schnorr(&delegation_sig, &msg, &pubkey.x_only_public_key().0)
+					.map_err(|_| tonic::Status::unauthenticated("invalid watch delegation for pubkey"))?;
+				watch_pubkey
+			},
+			None => pubkey,
+		};
+		let msg = aspd_rpc_client::vtxos_for_pubkey_message(
+			self.asp_pubkey, pubkey, req.timestamp_ms,
+		);
+		crate::SECP.verify_schnorr(&signature, &msg, &signer.x_only_public_key().0)
+			.map_err(|_| tonic::Status::unauthenticated("invalid signature for pubkey"))?;
+
+		let vtxos = self.round_vtxos_for_pubkey(pubkey).to_status()?;
+		Ok(tonic::Response::new(rpc::VtxosForPubkeyResponse {
+			vtxos: vtxos.iter().map(|v| v.encode()).collect(),
 		}))
 	}
 
@@ -98,19 +280,8 @@ impl rpc::ArkService for Arc<App> {
 		req: tonic::Request<rpc::OnboardCosignRequest>,
 	) -> Result<tonic::Response<rpc::OnboardCosignResponse>, tonic::Status> {
 		let req = req.into_inner();
-		let user_part = ciborium::from_reader::<ark::onboard::UserPart, _>(&req.user_part[..])
-			.map_err(|e| badarg!("invalid user part: {}", e))?;
-		if user_part.spec.asp_pubkey != self.master_key.public_key() {
-			return Err(badarg!("ASP public key is incorrect!"));
-		}
-
-		if let Some(max) = self.config.max_onboard_value {
-			if user_part.spec.amount > max {
-				return Err(badarg!("onboard amount exceeds limit of {}", max));
-			}
-		}
-
-		let asp_part = self.cosign_onboard(user_part);
+		let user_part = decode_onboard_user_part(self, &req.user_part)?;
+		let asp_part = self.cosign_onboard(user_part).to_status()?;
 		Ok(tonic::Response::new(rpc::OnboardCosignResponse {
 			asp_part: {
 				let mut buf = Vec::new();
@@ -120,6 +291,42 @@ impl rpc::ArkService for Arc<App> {
 		}))
 	}
 
+	async fn request_onboard_cosign_batch(
+		&self,
+		req: tonic::Request<rpc::OnboardCosignBatchRequest>,
+	) -> Result<tonic::Response<rpc::OnboardCosignBatchResponse>, tonic::Status> {
+		let req = req.into_inner();
+		if req.user_parts.is_empty() {
+			return Err(badarg!("no onboard user parts provided"));
+		}
+		if req.user_parts.len() > ark::onboard::MAX_BATCH_SIZE {
+			return Err(badarg!("can cosign at most {} onboards at once, got {}",
+				ark::onboard::MAX_BATCH_SIZE, req.user_parts.len(),
+			));
+		}
+
+		// We validate all of them before we cosign any.
+		let mut utxos = HashSet::with_capacity(req.user_parts.len());
+		let user_parts = req.user_parts.iter().map(|b| {
+			let part = decode_onboard_user_part(self, b)?;
+			if !utxos.insert(part.utxo) {
+				return Err(badarg!("duplicate onboard utxo {}", part.utxo));
+			}
+			Ok(part)
+		}).collect::<Result<Vec<_>, tonic::Status>>()?;
+
+		let asp_parts = user_parts.into_iter()
+			.map(|p| self.cosign_onboard(p))
+			.collect::<anyhow::Result<Vec<_>>>().to_status()?;
+		Ok(tonic::Response::new(rpc::OnboardCosignBatchResponse {
+			asp_parts: asp_parts.iter().map(|p| {
+				let mut buf = Vec::new();
+				ciborium::into_writer(p, &mut buf).unwrap();
+				buf
+			}).collect(),
+		}))
+	}
+
 	// oor
 
 	async fn request_oor_cosign(
@@ -137,6 +344,7 @@ impl rpc::ArkService for Arc<App> {
 		if payment.inputs.len() != user_nonces.len() {
 			return Err(badarg!("wrong number of user nonces"));
 		}
+		self.config.check_oor_outputs(&payment.outputs).map_err(|e| badarg!("{}", e))?;
 
 		let (nonces, sigs) = self.cosign_oor(&payment, &user_nonces).to_status()?;
 		Ok(tonic::Response::new(rpc::OorCosignResponse {
@@ -289,7 +497,10 @@ impl rpc::ArkService for Arc<App> {
 		_req: tonic::Request<rpc::Empty>,
 	) -> Result<tonic::Response<Self::SubscribeRoundsStream>, tonic::Status> {
 		let chan = self.try_rounds().to_status()?.round_event_tx.subscribe();
-		let stream = BroadcastStream::new(chan);
+		// End the stream on shutdown, otherwise it keeps the server from stopping.
+		let app = self.clone();
+		let shutdown = Box::pin(async move { app.shutdown_signal().await });
+		let stream = futures::StreamExt::take_until(BroadcastStream::new(chan), shutdown);
 
 		Ok(tonic::Response::new(Box::new(stream.map(|e| {
 			let e = e.map_err(|e| internal!("broken stream: {}", e))?;
@@ -300,12 +511,41 @@ impl rpc::ArkService for Arc<App> {
 	async fn submit_payment(
 		&self,
 		req: tonic::Request<rpc::SubmitPaymentRequest>,
-	) -> Result<tonic::Response<rpc::Empty>, tonic::Status> {
+	) -> Result<tonic::Response<rpc::SubmitResponse>, tonic::Status> {
 		let req = req.into_inner();
 
-		let inputs =  req.input_vtxos.into_iter().map(|vtxo| {
+		let rounds = self.try_rounds().to_status()?;
+		let epoch = rounds.round_epoch.load(atomic::Ordering::SeqCst);
+		if req.round_epoch != epoch {
+			return rejected!(StaleEpoch, "stale round epoch {}, wait for the next round start",
+				req.round_epoch,
+			);
+		}
+		if rounds.round_full.load(atomic::Ordering::SeqCst) {
+			return Ok(tonic::Response::new(payment_response(rounds, Err(round::InputRejected::new(
+				round::RejectReason::RoundFull, "round is full, try next round",
+			)))));
+		}
+
+		let inputs = req.input_vtxos.into_iter().map(|vtxo| {
 			Ok(Vtxo::decode(&vtxo).map_err(|e| badarg!("invalid vtxo: {}", e))?)
-		}).collect::<Result<_, tonic::Status>>()?;
+		}).collect::<Result<Vec<_>, tonic::Status>>()?;
+		let tip = self.bitcoind.get_block_count()
+			.map_err(|e| internal!("failed to get block height: {}", e))? as u32;
+		let new_expiry = tip + self.config.vtxo_expiry_delta as u32;
+		let mut origin_height = tip;
+		for input in &inputs {
+			if let Err(e) = self.check_onboard_confirmations(input) {
+				return rejected!(InvalidInput, "input vtxo {} not accepted: {}", input.id(), e);
+			}
+			let origin = match self.check_vtxo_lifetime(input, new_expiry) {
+				Ok(h) => h,
+				Err(e) => {
+					return rejected!(InvalidInput, "input vtxo {} not accepted: {}", input.id(), e);
+				},
+			};
+			origin_height = cmp::min(origin_height, origin);
+		}
 
 		let mut outputs = Vec::with_capacity(req.payments.len());
 		let mut offboards = Vec::with_capacity(req.payments.len() / 2);
@@ -315,12 +555,54 @@ impl rpc::ArkService for Arc<App> {
 				rpc::payment::Destination::VtxoPublicKey(pk) => {
 					let pubkey= PublicKey::from_slice(&pk)
 						.map_err(|e| badarg!("malformed pubkey {:?}: {}", pk, e))?;
-					outputs.push(VtxoRequest { amount, pubkey });
+					if payment.split_amounts.is_empty() {
+						if amount < ark::P2TR_DUST {
+							return rejected!(Dust, "vtxo amount {} is below dust", amount);
+						}
+						outputs.push(VtxoRequest { amount, pubkey });
+						continue;
+					}
+
+					let splits = payment.split_amounts.iter()
+						.map(|a| Amount::from_sat(*a)).collect::<Vec<_>>();
+					if let Some(dust) = splits.iter().find(|a| **a < ark::P2TR_DUST) {
+						return rejected!(Dust, "split amount {} is below dust", dust);
+					}
+					let sum = match splits.iter().try_fold(Amount::ZERO, |s, a| s.checked_add(*a)) {
+						Some(sum) => sum,
+						None => return rejected!(InvalidPayment, "split amounts overflow"),
+					};
+					if sum != amount {
+						return rejected!(InvalidPayment,
+							"split amounts sum to {}, payment amount is {}", sum, amount,
+						);
+					}
+					outputs.extend(splits.into_iter().map(|amount| VtxoRequest { amount, pubkey }));
+				},
+				rpc::payment::Destination::OffboardSpk(_) |
+				rpc::payment::Destination::OffboardAddress(_)
+					if !payment.split_amounts.is_empty() =>
+				{
+					return Err(badarg!("offboards can't be split"));
 				},
 				rpc::payment::Destination::OffboardSpk(s) => {
 					let script_pubkey = ScriptBuf::from_bytes(s);
 					let offb = OffboardRequest { script_pubkey, amount };
-					offb.validate().map_err(|e| badarg!("invalid offboard request: {}", e))?;
+					if let Err(e) = check_offboard(&offb) {
+						return Ok(tonic::Response::new(payment_response(rounds, Err(e))));
+					}
+					offboards.push(offb);
+				},
+				rpc::payment::Destination::OffboardAddress(addr) => {
+					let network = self.config.network;
+					let addr = Address::from_str(&addr)
+						.map_err(|e| badarg!("invalid offboard address {}: {}", addr, e))?
+						.require_network(network)
+						.map_err(|_| badarg!("offboard address is not valid for network {}", network))?;
+					let offb = OffboardRequest { script_pubkey: addr.script_pubkey(), amount };
+					if let Err(e) = check_offboard(&offb) {
+						return Ok(tonic::Response::new(payment_response(rounds, Err(e))));
+					}
 					offboards.push(offb);
 				},
 			}
@@ -343,11 +625,48 @@ impl rpc::ArkService for Arc<App> {
 				.map_err(|e| badarg!("invalid public nonce: {}", e))
 		}).collect::<Result<_, tonic::Status>>()?;
 
+		let (response, rx) = oneshot::channel();
 		let inp = RoundInput::RegisterPayment {
-			inputs, outputs, offboards, cosign_pubkey, public_nonces,
+			inputs, outputs, offboards, cosign_pubkey, public_nonces, origin_height,
+			epoch: req.round_epoch, response,
 		};
-		self.try_rounds().to_status()?.round_input_tx.send(inp).expect("input channel closed");
-		Ok(tonic::Response::new(rpc::Empty {}))
+		rounds.round_input_tx.send(inp).expect("input channel closed");
+		// The coordinator only answers while it collects payments, don't
+		// keep the client waiting while it's busy with something else. It
+		// withdraws the payment if we stopped waiting.
+		let res = match tokio::time::timeout(SUBMIT_RESPONSE_TIMEOUT, rx).await {
+			Ok(res) => res.map_err(|_| internal!("round coordinator dropped the payment"))?,
+			Err(_) => Err(round::InputRejected::new(round::RejectReason::PastDeadline,
+				"the round isn't accepting payments right now, wait for the next round start",
+			)),
+		};
+		Ok(tonic::Response::new(payment_response(rounds, res)))
+	}
+
+	async fn cancel_payment(
+		&self,
+		req: tonic::Request<rpc::CancelPaymentRequest>,
+	) -> Result<tonic::Response<rpc::SubmitResponse>, tonic::Status> {
+		let req = req.into_inner();
+		let rounds = self.try_rounds().to_status()?;
+		let vtxo = Vtxo::decode(&req.input_vtxo).map_err(|e| badarg!("invalid vtxo: {}", e))?;
+		let signature = schnorr::Signature::from_slice(&req.signature)
+			.map_err(|e| badarg!("invalid signature: {}", e))?;
+		let cosign_pubkey = PublicKey::from_slice(&req.cosign_pubkey)
+			.map_err(|e| badarg!("invalid cosign pubkey: {}", e))?;
+
+		let user_pubkey = vtxo.spec().user_pubkey;
+		let msg = aspd_rpc_client::cancel_payment_message(vtxo.id(), req.round_epoch, cosign_pubkey);
+		crate::SECP.verify_schnorr(&signature, &msg, &user_pubkey.x_only_public_key().0)
+			.map_err(|_| tonic::Status::unauthenticated("invalid signature for input vtxo"))?;
+
+		let (response, rx) = oneshot::channel();
+		let inp = RoundInput::CancelPayment {
+			input: vtxo.id(), user_pubkey, cosign_pubkey, epoch: req.round_epoch, response,
+		};
+		rounds.round_input_tx.send(inp).expect("input channel closed");
+		let res = rx.await.map_err(|_| internal!("round coordinator dropped the cancellation"))?;
+		Ok(tonic::Response::new(res.into()))
 	}
 
 	async fn provide_vtxo_signatures(
@@ -371,7 +690,8 @@ impl rpc::ArkService for Arc<App> {
 	async fn provide_forfeit_signatures(
 		&self,
 		req: tonic::Request<rpc::ForfeitSignaturesRequest>,
-	) -> Result<tonic::Response<rpc::Empty>, tonic::Status> {
+	) -> Result<tonic::Response<rpc::SubmitResponse>, tonic::Status> {
+		let (response, rx) = oneshot::channel();
 		let inp = RoundInput::ForfeitSignatures {
 			signatures: req.into_inner().signatures.into_iter().map(|ff| {
 				let id = VtxoId::from_slice(&ff.input_vtxo_id)
@@ -385,10 +705,39 @@ impl rpc::ArkService for Arc<App> {
 						.map_err(|e| badarg!("invalid forfeit sig: {}", e))
 				}).collect::<Result<_, tonic::Status>>()?;
 				Ok((id, nonces, signatures))
-			}).collect::<Result<_, tonic::Status>>()?
+			}).collect::<Result<_, tonic::Status>>()?,
+			response,
 		};
 		self.try_rounds().to_status()?.round_input_tx.send(inp).expect("input channel closed");
-		Ok(tonic::Response::new(rpc::Empty {}))
+		let res = rx.await.map_err(|_| internal!("round coordinator dropped the signatures"))?;
+		Ok(tonic::Response::new(res.into()))
+	}
+
+	async fn get_round_connectors(
+		&self,
+		req: tonic::Request<rpc::RoundConnectorsRequest>,
+	) -> Result<tonic::Response<rpc::RoundConnectors>, tonic::Status> {
+		let req = req.into_inner();
+		let proposed = *self.try_rounds().to_status()?.proposed_connectors.lock().await;
+		let proposed = match proposed {
+			Some(p) if p.round_id == req.round_id => p,
+			_ => return Err(not_found!("no round proposal for round {}", req.round_id)),
+		};
+
+		// This is exactly the chain the round coordinator uses for the forfeits.
+		let chain = ConnectorChain::new(
+			proposed.len, proposed.utxo, self.asp_pubkey, proposed.connector_value,
+		);
+		let spk = ConnectorChain::output_script(self.asp_pubkey);
+		Ok(tonic::Response::new(rpc::RoundConnectors {
+			round_id: proposed.round_id,
+			connectors: chain.connectors().map(|point| rpc::Connector {
+				outpoint: bitcoin::consensus::serialize(&point),
+				script_pubkey: spk.to_bytes(),
+				amount: chain.connector_value().to_sat(),
+			}).collect(),
+			input_weight: connectors::INPUT_WEIGHT.to_wu(),
+		}))
 	}
 }
 
@@ -398,24 +747,95 @@ impl rpc::AdminService for Arc<App> {
 		&self,
 		_req: tonic::Request<rpc::Empty>,
 	) -> Result<tonic::Response<rpc::WalletStatusResponse>, tonic::Status> {
+		let balance = self.sync_onchain_wallet().await.to_status()?;
+		let funds = self.wallet_funds(&*self.wallet.lock().await);
 		Ok(tonic::Response::new(rpc::WalletStatusResponse {
 			address: self.onchain_address().await.to_status()?.to_string(),
-			balance: self.sync_onchain_wallet().await.to_status()?.to_sat(),
+			balance: balance.to_sat(),
+			vtxo_balance: self.round_change_vtxo_balance().to_status()?.to_sat(),
+			immature_balance: funds.immature.to_sat(),
 		}))
 	}
 
-	async fn trigger_round(
+	async fn wallet_descriptor(
 		&self,
 		_req: tonic::Request<rpc::Empty>,
+	) -> Result<tonic::Response<rpc::WalletDescriptorResponse>, tonic::Status> {
+		Ok(tonic::Response::new(rpc::WalletDescriptorResponse {
+			descriptor: self.wallet_descriptor().await,
+			address: self.onchain_address().await.to_status()?.to_string(),
+		}))
+	}
+
+	async fn trigger_round(
+		&self,
+		req: tonic::Request<rpc::TriggerRoundRequest>,
 	) -> Result<tonic::Response<rpc::Empty>, tonic::Status> {
-		match self.try_rounds().to_status()?.round_trigger_tx.try_send(()) {
-			Err(tokio::sync::mpsc::error::TrySendError::Closed(())) => {
+		let expiry_height = req.into_inner().expiry_height;
+		if expiry_height.is_some() {
+			let tip = self.bitcoind.get_block_count()
+				.map_err(|e| internal!("failed to get block height: {}", e))? as u32;
+			round::round_expiry_height(&self.config, tip, expiry_height)
+				.map_err(|e| badarg!("{}", e))?;
+		}
+		let trigger = RoundTrigger { expiry_height };
+		match self.try_rounds().to_status()?.round_trigger_tx.try_send(trigger) {
+			Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
 				Err(internal!("round scheduler closed"))
 			},
 			_ => Ok(tonic::Response::new(rpc::Empty {})),
 		}
 	}
 
+	async fn sweep_round(
+		&self,
+		req: tonic::Request<rpc::SweepRoundRequest>,
+	) -> Result<tonic::Response<rpc::SweepRoundResponse>, tonic::Status> {
+		let req = req.into_inner();
+		let round_txid = Txid::from_slice(&req.round_txid)
+			.map_err(|e| badarg!("invalid txid: {}", e))?;
+		if req.fee_rate == 0 {
+			return Err(badarg!("fee rate can't be zero"));
+		}
+		let fee_rate = FeeRate::from_sat_per_kwu(req.fee_rate);
+		let txid = App::sweep_round(self, round_txid, fee_rate).await.to_status()?;
+		Ok(tonic::Response::new(rpc::SweepRoundResponse {
+			sweep_txid: txid.to_byte_array().to_vec(),
+		}))
+	}
+
+	async fn bump_round_tx(
+		&self,
+		req: tonic::Request<rpc::BumpRoundTxRequest>,
+	) -> Result<tonic::Response<rpc::BumpRoundTxResponse>, tonic::Status> {
+		let req = req.into_inner();
+		let round_txid = Txid::from_slice(&req.round_txid)
+			.map_err(|e| badarg!("invalid txid: {}", e))?;
+		if req.fee_rate == 0 {
+			return Err(badarg!("fee rate can't be zero"));
+		}
+		let fee_rate = FeeRate::from_sat_per_kwu(req.fee_rate);
+		let txid = App::bump_round_tx_fee(self, round_txid, fee_rate).await.to_status()?;
+		Ok(tonic::Response::new(rpc::BumpRoundTxResponse {
+			bump_txid: txid.to_byte_array().to_vec(),
+		}))
+	}
+
+	async fn sweep_expired_rounds(
+		&self,
+		req: tonic::Request<rpc::SweepExpiredRoundsRequest>,
+	) -> Result<tonic::Response<rpc::SweepExpiredRoundsResponse>, tonic::Status> {
+		let req = req.into_inner();
+		if req.fee_rate == 0 {
+			return Err(badarg!("fee rate can't be zero"));
+		}
+		let fee_rate = FeeRate::from_sat_per_kwu(req.fee_rate);
+		let txids = App::sweep_expired_rounds(self, fee_rate).await.to_status()?;
+		Ok(tonic::Response::new(rpc::SweepExpiredRoundsResponse {
+			sweep_txids: txids.into_iter().map(|t| t.to_byte_array().to_vec()).collect(),
+		}))
+	}
+
 	async fn stop(
 		&self,
 		_req: tonic::Request<rpc::Empty>,
@@ -423,19 +843,155 @@ impl rpc::AdminService for Arc<App> {
 		//TODO(stevenroose) implement graceful shutdown
 		std::process::exit(0);
 	}
+
+	async fn shutdown(
+		&self,
+		req: tonic::Request<rpc::Empty>,
+	) -> Result<tonic::Response<rpc::Empty>, tonic::Status> {
+		check_admin_token(self, &req)?;
+		App::shutdown(self);
+		Ok(tonic::Response::new(rpc::Empty {}))
+	}
+
+	async fn round_metrics(
+		&self,
+		_req: tonic::Request<rpc::Empty>,
+	) -> Result<tonic::Response<rpc::RoundMetricsResponse>, tonic::Status> {
+		let phases = self.round_metrics.histograms().into_iter().map(|(phase, h)| {
+			rpc::PhaseHistogram {
+				phase: phase.to_string(),
+				bucket_bounds_ms: metrics::BUCKET_BOUNDS_MS.to_vec(),
+				bucket_counts: h.bucket_counts.to_vec(),
+				count: h.count,
+				sum_ms: h.sum_ms,
+			}
+		}).collect();
+		Ok(tonic::Response::new(rpc::RoundMetricsResponse {
+			phases,
+			insufficient_funds_rounds: self.round_metrics.insufficient_funds_rounds(),
+			onboard_nonce_pool_size: self.onboard_nonces.as_ref().map(|p| p.size() as u64),
+			onboard_nonce_pool_occupancy: self.onboard_nonces.as_ref()
+				.map(|p| p.occupancy() as u64).unwrap_or(0),
+			onboard_nonce_pool_exhausted: self.onboard_nonces.as_ref()
+				.map(|p| p.nb_exhausted()).unwrap_or(0),
+			round_backoff_level: self.round_metrics.round_backoff_level(),
+			round_backoff_interval_ms: self.round_metrics.round_backoff_interval().as_millis() as u64,
+		}))
+	}
+
+	async fn pending_payments(
+		&self,
+		_req: tonic::Request<rpc::Empty>,
+	) -> Result<tonic::Response<rpc::PendingPaymentsResponse>, tonic::Status> {
+		let (response, rx) = oneshot::channel();
+		let inp = RoundInput::PendingPayments { response };
+		self.try_rounds().to_status()?.round_input_tx.send(inp).expect("input channel closed");
+		let payments = rx.await.map_err(|_| internal!("round coordinator dropped the request"))?;
+		Ok(tonic::Response::new(rpc::PendingPaymentsResponse {
+			payments: payments.into_iter().map(|p| rpc::PendingPayment {
+				cosign_pubkey: p.cosign_pubkey.serialize().to_vec(),
+				input_vtxo_ids: p.inputs.iter().map(|id| id.bytes().to_vec()).collect(),
+				output_amount: p.outputs.iter().map(|o| o.amount).sum::<Amount>().to_sat(),
+				offboard_amount: p.offboards.iter().map(|o| o.amount).sum::<Amount>().to_sat(),
+			}).collect(),
+		}))
+	}
+}
+
+/// A server builder for the public gRPC service, with the configured TLS
+/// certificate loaded and checked.
+fn public_rpc_builder(app: &App) -> anyhow::Result<tonic::transport::Server> {
+	let builder = tonic::transport::Server::builder();
+	let (cert_path, key_path) = match (
+		&app.config.public_rpc_tls_cert_path, &app.config.public_rpc_tls_key_path,
+	) {
+		(Some(c), Some(k)) => (c, k),
+		_ => return Ok(builder),
+	};
+	let cert = fs::read(cert_path)
+		.with_context(|| format!("failed to read TLS certificate {}", cert_path.display()))?;
+	let key = fs::read(key_path)
+		.with_context(|| format!("failed to read TLS key {}", key_path.display()))?;
+	let identity = tonic::transport::Identity::from_pem(cert, key);
+	// The certificate and key are only parsed here.
+	builder.tls_config(tonic::transport::ServerTlsConfig::new().identity(identity))
+		.context("invalid TLS certificate or key")
 }
 
 /// Run the public gRPC endpoint.
+///
+/// When TLS is configured, the certificate is reloaded on SIGHUP. New
+/// connections use the new certificate, while existing connections are
+/// served until they close. If the new certificate can't be loaded, we
+/// keep serving with the old one.
 pub async fn run_public_rpc_server(app: Arc<App>) -> anyhow::Result<()> {
 	let addr = app.config.public_rpc_address;
-	info!("Starting public gRPC service on address {}", addr);
-	let ark_server = rpc::ArkServiceServer::new(app.clone());
-	tonic::transport::Server::builder()
-		.add_service(ark_server)
-		.serve(addr)
-		.await?;
-	info!("Started public gRPC service on address {}", addr);
-	Ok(())
+	let tls = app.config.public_rpc_tls_cert_path.is_some();
+	let mut builder = public_rpc_builder(&app)?;
+	info!("Starting public gRPC service on address {} {}",
+		addr, if tls { "with TLS" } else { "without TLS" },
+	);
+	let listener = Arc::new(TcpListener::bind(addr).await
+		.with_context(|| format!("failed to bind to {}", addr))?);
+	// Only take over SIGHUP if there is a certificate to reload.
+	let mut hangup = if tls {
+		Some(signal(SignalKind::hangup()).context("failed to listen for SIGHUP")?)
+	} else {
+		None
+	};
+
+	let mut old_servers = Vec::new();
+	loop {
+		let incoming = futures::stream::unfold(listener.clone(), |l| async move {
+			let conn = l.accept().await.map(|(stream, _)| stream);
+			Some((conn, l))
+		});
+		let (reload_tx, reload_rx) = oneshot::channel::<()>();
+		let stop_app = app.clone();
+		let mut service = rpc::ArkServiceServer::new(app.clone());
+		if app.config.public_rpc_compression {
+			// Only used for clients that advertise gzip support.
+			service = service
+				.accept_compressed(CompressionEncoding::Gzip)
+				.send_compressed(CompressionEncoding::Gzip);
+		}
+		let mut server = tokio::spawn(builder
+			.add_service(service)
+			.serve_with_incoming_shutdown(incoming, async move {
+				tokio::select! {
+					_ = stop_app.shutdown_signal() => {},
+					_ = reload_rx => {},
+				}
+			}));
+
+		let next = loop {
+			tokio::select! {
+				res = &mut server => {
+					res.context("public gRPC server panicked")??;
+					for old in old_servers {
+						let _ = old.await;
+					}
+					info!("Stopped public gRPC service on address {}", addr);
+					return Ok(());
+				},
+				_ = async { hangup.as_mut().unwrap().recv().await }, if hangup.is_some() => {
+					match public_rpc_builder(&app) {
+						Ok(new) => {
+							info!("Reloaded TLS certificate of the public gRPC service");
+							break new;
+						},
+						Err(e) => error!("Failed to reload TLS certificate, keeping the old one: {:#}", e),
+					}
+				},
+			}
+		};
+
+		// Stop accepting connections with the old certificate and let the
+		// existing ones finish in the background.
+		let _ = reload_tx.send(());
+		old_servers.push(server);
+		builder = next;
+	}
 }
 
 /// Run the public gRPC endpoint.
@@ -445,8 +1001,8 @@ pub async fn run_admin_rpc_server(app: Arc<App>) -> anyhow::Result<()> {
 	let admin_server = rpc::AdminServiceServer::new(app.clone());
 	tonic::transport::Server::builder()
 		.add_service(admin_server)
-		.serve(addr)
+		.serve_with_shutdown(addr, app.shutdown_signal())
 		.await?;
-	info!("Started admin gRPC service on address {}", addr);
+	info!("Stopped admin gRPC service on address {}", addr);
 	Ok(())
 }
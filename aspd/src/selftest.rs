@@ -0,0 +1,268 @@
+
+//! A self-test of a regtest aspd.
+//!
+//! The self-test starts aspd and acts as a user with a throwaway bark wallet,
+//! so that it goes through the same code paths as real users do: the onboard
+//! is cosigned, the vtxo is refreshed in a round and the round is swept after
+//! it expires.
+
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use bdk_bitcoind_rpc::bitcoincore_rpc::{self, RpcApi};
+use bitcoin::{Address, Amount, Network, OutPoint};
+use tonic::transport::Channel;
+
+use ark::Vtxo;
+use aspd_rpc_client as rpc;
+
+use crate::{App, Config};
+
+/// The amount the test user onboards.
+const ONBOARD_AMOUNT: Amount = Amount::from_sat(100_000);
+
+/// How long a single step can take before we consider it failed.
+const STEP_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long we wait for the round to be swept once it can be swept.
+const SWEEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A step of the self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestStep {
+	FundWallets,
+	Onboard,
+	Round,
+	SweepExpiredRound,
+}
+
+impl SelfTestStep {
+	/// All steps, in the order in which they run.
+	pub const ALL: [SelfTestStep; 4] = [
+		SelfTestStep::FundWallets,
+		SelfTestStep::Onboard,
+		SelfTestStep::Round,
+		SelfTestStep::SweepExpiredRound,
+	];
+}
+
+impl fmt::Display for SelfTestStep {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			SelfTestStep::FundWallets => "fund wallets",
+			SelfTestStep::Onboard => "onboard",
+			SelfTestStep::Round => "round",
+			SelfTestStep::SweepExpiredRound => "sweep expired round",
+		})
+	}
+}
+
+/// Turn an unspecified listening address into one we can connect to.
+fn connect_addr(addr: SocketAddr) -> SocketAddr {
+	if addr.ip().is_unspecified() {
+		SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port())
+	} else {
+		addr
+	}
+}
+
+struct SelfTest {
+	config: Config,
+	bitcoind: bitcoincore_rpc::Client,
+	admin: rpc::AdminServiceClient<Channel>,
+	bark: bark::Wallet,
+	/// The ASP's onchain address, we mine all our blocks to it.
+	asp_address: Address,
+	/// The vtxo tree output of the round the user's vtxo was refreshed in.
+	round_output: Option<OutPoint>,
+}
+
+impl SelfTest {
+	async fn new(config: Config, bark_datadir: &Path) -> anyhow::Result<SelfTest> {
+		let bitcoind = bitcoincore_rpc::Client::new(
+			&config.bitcoind_url,
+			bitcoincore_rpc::Auth::CookieFile(config.bitcoind_cookie.as_str().into()),
+		).context("failed to create bitcoind rpc client")?;
+
+		let admin_addr = config.admin_rpc_address
+			.context("the self-test needs the admin rpc to be enabled")?;
+		let admin_url = format!("http://{}", connect_addr(admin_addr));
+		// aspd was just started, give it some time to start listening
+		let mut admin = None;
+		for _ in 0..50 {
+			match rpc::AdminServiceClient::connect(admin_url.clone()).await {
+				Ok(c) => {
+					admin = Some(c);
+					break;
+				},
+				Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+			}
+		}
+		let mut admin = admin.with_context(|| format!("can't connect to the admin rpc at {}", admin_url))?;
+
+		let status = admin.wallet_status(rpc::Empty {}).await.context("wallet status")?.into_inner();
+		let asp_address = Address::from_str(&status.address).context("invalid asp address")?
+			.require_network(config.network).context("asp address for wrong network")?;
+
+		let public_addr = connect_addr(config.public_rpc_address);
+		let bark_cfg = bark::Config {
+			network: config.network,
+			asp_address: if config.public_rpc_tls_cert_path.is_some() {
+				format!("https://localhost:{}", public_addr.port())
+			} else {
+				format!("http://{}", public_addr)
+			},
+			asp_tls_cert: config.public_rpc_tls_cert_path.clone(),
+			bitcoind_address: Some(config.bitcoind_url.clone()),
+			bitcoind_cookiefile: Some(config.bitcoind_cookie.clone().into()),
+			..Default::default()
+		};
+		let bark = bark::Wallet::create(bark_datadir, bark_cfg).await
+			.context("failed to create bark wallet")?;
+
+		Ok(SelfTest { config, bitcoind, admin, bark, asp_address, round_output: None })
+	}
+
+	fn mine(&self, blocks: u64) -> anyhow::Result<()> {
+		self.bitcoind.generate_to_address(blocks, &self.asp_address).context("failed to mine")?;
+		Ok(())
+	}
+
+	/// Fund the bark wallet and the ASP with mature coinbase outputs.
+	async fn fund_wallets(&mut self) -> anyhow::Result<()> {
+		let bark_addr = self.bark.get_new_onchain_address()?;
+		self.bitcoind.generate_to_address(1, &bark_addr).context("failed to mine")?;
+		self.mine(101)?;
+
+		let balance = self.bark.sync_onchain().await.context("bark onchain sync")?;
+		ensure!(balance > Amount::ZERO, "bark wallet didn't receive its funds");
+		let status = self.admin.wallet_status(rpc::Empty {}).await?.into_inner();
+		ensure!(status.balance > 0, "asp wallet didn't receive its funds");
+		Ok(())
+	}
+
+	/// Onboard with the user wallet and wait for the onboard to confirm.
+	async fn onboard(&mut self) -> anyhow::Result<()> {
+		self.bark.onboard(ONBOARD_AMOUNT).await.context("bark onboard")?;
+		self.mine(self.config.onboard_confirmations.max(1) as u64)?;
+
+		self.bark.sync().await.context("bark sync")?;
+		let balance = self.bark.offchain_balance().await?;
+		ensure!(balance == ONBOARD_AMOUNT,
+			"offchain balance is {} after onboarding {}", balance, ONBOARD_AMOUNT,
+		);
+		Ok(())
+	}
+
+	/// Refresh the onboarded vtxo in a round.
+	async fn round(&mut self) -> anyhow::Result<()> {
+		self.bark.refresh_vtxos(None).await.context("bark refresh")?;
+		self.mine(1)?;
+
+		let vtxos = self.bark.vtxos()?;
+		let vtxo = match vtxos.as_slice() {
+			[vtxo] => vtxo,
+			_ => bail!("expected a single vtxo after the round, got {}", vtxos.len()),
+		};
+		let Vtxo::Round { exit_branch, .. } = vtxo else {
+			bail!("vtxo {} isn't a round vtxo", vtxo.id());
+		};
+		// The root of the exit branch spends the round tx.
+		let root = exit_branch.first().context("empty exit branch")?;
+		let round_output = root.input[0].previous_output;
+		self.bitcoind.get_raw_transaction_info(&round_output.txid, None)
+			.with_context(|| format!("round tx {} not found", round_output.txid))?;
+		self.round_output = Some(round_output);
+		Ok(())
+	}
+
+	/// Let the round expire and sweep it.
+	///
+	/// The sweep goes through the same path as the sweeps of the scheduler,
+	/// which might also beat us to it, so we only check that the round's
+	/// output ends up spent.
+	async fn sweep_expired_round(&mut self) -> anyhow::Result<()> {
+		let round_output = self.round_output.context("no round")?;
+		let expiry_height = self.bark.vtxos()?.first().context("no vtxo")?.spec().expiry_height;
+		let sweepable_height = expiry_height.saturating_add(self.config.sweep_grace_blocks);
+		let tip = self.bitcoind.get_block_count()?;
+		self.mine((sweepable_height as u64).saturating_sub(tip))?;
+
+		let res = self.admin.sweep_expired_rounds(rpc::SweepExpiredRoundsRequest {
+			fee_rate: self.config.round_tx_feerate.to_sat_per_kwu(),
+		}).await.context("sweep expired rounds")?.into_inner();
+		debug!("Self-test sweep broadcast {} sweep txs", res.sweep_txids.len());
+
+		let deadline = tokio::time::Instant::now() + SWEEP_TIMEOUT;
+		while self.bitcoind.get_tx_out(&round_output.txid, round_output.vout, Some(true))?.is_some() {
+			ensure!(tokio::time::Instant::now() < deadline,
+				"round {} wasn't swept within {:?}", round_output.txid, SWEEP_TIMEOUT,
+			);
+			tokio::time::sleep(Duration::from_millis(500)).await;
+		}
+		self.mine(1)?;
+		Ok(())
+	}
+}
+
+/// Run the self-test of the aspd in the given datadir.
+///
+/// This refuses to run on anything but regtest because it mines blocks.
+/// The result of each step is passed to `report` as soon as the step is done.
+/// We stop at the first failing step, the remaining steps are not reported.
+///
+/// Returns whether all steps passed.
+pub async fn run_self_test(
+	datadir: &Path,
+	mut report: impl FnMut(SelfTestStep, &anyhow::Result<()>),
+) -> anyhow::Result<bool> {
+	let config = Config::read_from_datadir(datadir)?;
+	ensure!(config.network == Network::Regtest,
+		"the self-test only runs on regtest, this aspd runs on {}", config.network,
+	);
+
+	let bark_datadir = PathBuf::from(datadir).join(format!(
+		"selftest-{}", std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+	));
+
+	let mut app = App::open(datadir).await.context("server init")?;
+	let shutdown = app.shutdown_handle();
+	let jh_aspd = tokio::spawn(async move { app.start().await });
+
+	let res = async {
+		let mut test = SelfTest::new(config, &bark_datadir).await?;
+		for step in SelfTestStep::ALL {
+			let fut = async {
+				match step {
+					SelfTestStep::FundWallets => test.fund_wallets().await,
+					SelfTestStep::Onboard => test.onboard().await,
+					SelfTestStep::Round => test.round().await,
+					SelfTestStep::SweepExpiredRound => test.sweep_expired_round().await,
+				}
+			};
+			let res = tokio::time::timeout(STEP_TIMEOUT, fut).await
+				.unwrap_or_else(|_| Err(anyhow!("timed out after {:?}", STEP_TIMEOUT)));
+			report(step, &res);
+			if res.is_err() {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}.await;
+
+	shutdown.shutdown();
+	match jh_aspd.await {
+		Ok(Ok(())) => {},
+		Ok(Err(e)) => warn!("aspd exited with an error during the self-test: {:#}", e),
+		Err(e) => warn!("aspd task failed during the self-test: {}", e),
+	}
+	if let Err(e) = std::fs::remove_dir_all(&bark_datadir) {
+		warn!("Failed to remove self-test wallet {}: {}", bark_datadir.display(), e);
+	}
+	res
+}
@@ -31,3 +31,118 @@ pub mod uri {
 		d.deserialize_str(Visitor)
 	}
 }
+
+/// Serialize durations as human-readable strings like `10s` or `2500ms`.
+///
+/// On read we also accept a plain number of milliseconds and the default
+/// serde form of `{ "secs": 10, "nanos": 0 }`.
+pub mod duration {
+	use super::*;
+
+	use std::time::Duration;
+
+	pub fn to_string(d: &Duration) -> String {
+		if d.subsec_nanos() % 1_000_000 != 0 {
+			format!("{}ns", d.as_nanos())
+		} else if d.subsec_millis() != 0 {
+			format!("{}ms", d.as_millis())
+		} else {
+			format!("{}s", d.as_secs())
+		}
+	}
+
+	pub fn from_str(s: &str) -> Result<Duration, String> {
+		let s = s.trim();
+		let idx = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+		let (num, unit) = s.split_at(idx);
+		let num = u64::from_str(num).map_err(|_| format!("invalid duration: {}", s))?;
+		let overflow = || format!("duration too large: {}", s);
+		match unit.trim() {
+			"ns" => Ok(Duration::from_nanos(num)),
+			"us" => Ok(Duration::from_micros(num)),
+			"ms" => Ok(Duration::from_millis(num)),
+			"s" => Ok(Duration::from_secs(num)),
+			"m" => Ok(Duration::from_secs(num.checked_mul(60).ok_or_else(overflow)?)),
+			"h" => Ok(Duration::from_secs(num.checked_mul(60 * 60).ok_or_else(overflow)?)),
+			"" => Err(format!("duration without unit: {}", s)),
+			u => Err(format!("invalid duration unit: {}", u)),
+		}
+	}
+
+	pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+		s.serialize_str(&to_string(d))
+	}
+
+	pub fn deserialize<'d, D: Deserializer<'d>>(d: D) -> Result<Duration, D::Error> {
+		struct Visitor;
+
+		impl<'de> serde::de::Visitor<'de> for Visitor {
+			type Value = Duration;
+
+			fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				f.write_str("a duration like \"10s\" or a number of milliseconds")
+			}
+
+			fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+				from_str(v).map_err(E::custom)
+			}
+
+			fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+				Ok(Duration::from_millis(v))
+			}
+
+			fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+				let mut secs = None;
+				let mut nanos = None;
+				while let Some(key) = map.next_key::<String>()? {
+					match key.as_str() {
+						"secs" => secs = Some(map.next_value::<u64>()?),
+						"nanos" => nanos = Some(map.next_value::<u32>()?),
+						k => return Err(de::Error::unknown_field(k, &["secs", "nanos"])),
+					}
+				}
+				let secs = secs.ok_or_else(|| de::Error::missing_field("secs"))?;
+				let nanos = nanos.ok_or_else(|| de::Error::missing_field("nanos"))?;
+				Ok(Duration::new(secs, nanos))
+			}
+		}
+		d.deserialize_any(Visitor)
+	}
+
+	#[cfg(test)]
+	mod test {
+		use super::*;
+
+		#[derive(Debug, PartialEq, Serialize, Deserialize)]
+		struct Wrapper(#[serde(with = "super")] Duration);
+
+		#[test]
+		fn string_roundtrip() {
+			for (d, s) in [
+				(Duration::from_secs(10), "\"10s\""),
+				(Duration::from_millis(2500), "\"2500ms\""),
+				(Duration::from_nanos(1_000_001), "\"1000001ns\""),
+				(Duration::ZERO, "\"0s\""),
+			] {
+				assert_eq!(serde_json::to_string(&Wrapper(d)).unwrap(), s);
+				assert_eq!(serde_json::from_str::<Wrapper>(s).unwrap(), Wrapper(d));
+			}
+			assert_eq!(serde_json::from_str::<Wrapper>("\"2m\"").unwrap().0, Duration::from_secs(120));
+			assert_eq!(serde_json::from_str::<Wrapper>("\"1h\"").unwrap().0, Duration::from_secs(3600));
+			serde_json::from_str::<Wrapper>("\"10\"").unwrap_err();
+			serde_json::from_str::<Wrapper>("\"10 days\"").unwrap_err();
+			serde_json::from_str::<Wrapper>("\"s\"").unwrap_err();
+			serde_json::from_str::<Wrapper>(&format!("\"{}h\"", u64::MAX / 60)).unwrap_err();
+		}
+
+		#[test]
+		fn legacy_forms() {
+			assert_eq!(
+				serde_json::from_str::<Wrapper>(r#"{ "secs": 10, "nanos": 500000000 }"#).unwrap().0,
+				Duration::from_millis(10_500),
+			);
+			assert_eq!(serde_json::from_str::<Wrapper>("2500").unwrap().0, Duration::from_millis(2500));
+			serde_json::from_str::<Wrapper>(r#"{ "secs": 10 }"#).unwrap_err();
+		}
+	}
+}
@@ -0,0 +1,270 @@
+
+//! Signing with the ASP's master key.
+//!
+//! Round utxo inputs and onboards are signed through the [Signer] trait, so
+//! that an embedder of aspd can sign those with a key kept outside of the
+//! process, f.e. in an HSM, see [crate::App::set_signer]. The [KeypairSigner]
+//! holds the key in memory and is used by default.
+//!
+//! The other signatures, the musig cosigning of rounds and OOR payments, the
+//! connector signatures and the onboard nonce pool, are still made with the
+//! in-memory master key, so aspd always needs that key loaded to sign.
+
+use bitcoin::hashes::Hash;
+use bitcoin::key::TapTweak;
+use bitcoin::secp256k1::{self, schnorr, Keypair, PublicKey, XOnlyPublicKey};
+
+use ark::musig::{self, MusigAggNonce, MusigPartialSignature, MusigPubNonce, MusigSecNonce};
+use ark::onboard;
+use ark::util::KeypairExt;
+
+use crate::SECP;
+
+/// Signs with the ASP's master key.
+pub trait Signer: Send + Sync {
+	/// The public key of the master key.
+	fn pubkey(&self) -> PublicKey;
+
+	/// The public key of the master key adapted to be used in a
+	/// key-spend-only taproot.
+	fn keyspend_pubkey(&self) -> XOnlyPublicKey {
+		self.pubkey().x_only_public_key().0.tap_tweak(&SECP, None).0.to_inner()
+	}
+
+	/// Create a BIP-340 signature with the master key.
+	fn sign_schnorr(&self, msg: &secp256k1::Message) -> anyhow::Result<schnorr::Signature>;
+
+	/// Create a BIP-340 signature with the master key adapted to be used in a
+	/// key-spend-only taproot, see [Signer::keyspend_pubkey].
+	fn sign_schnorr_keyspend(
+		&self,
+		msg: &secp256k1::Message,
+	) -> anyhow::Result<schnorr::Signature>;
+
+	/// Create a musig partial signature for the aggregate of the given
+	/// pubkeys, which includes ours, with a nonce we generated before.
+	///
+	/// The secret nonce is consumed, it must never be used for another signature.
+	fn musig_partial_sign(
+		&self,
+		pubkeys: &[PublicKey],
+		agg_nonce: MusigAggNonce,
+		sec_nonce: MusigSecNonce,
+		msg: [u8; 32],
+		tweak: Option<[u8; 32]>,
+	) -> anyhow::Result<MusigPartialSignature>;
+
+	/// Create a musig partial signature with a deterministic nonce for the
+	/// aggregate of our pubkey with the given pubkeys of the other signers.
+	fn musig_deterministic_partial_sign(
+		&self,
+		their_pubkeys: &[PublicKey],
+		their_nonces: &[MusigPubNonce],
+		msg: [u8; 32],
+		tweak: Option<[u8; 32]>,
+	) -> anyhow::Result<(MusigPubNonce, MusigPartialSignature)>;
+}
+
+/// A [Signer] that holds the master key in memory.
+pub struct KeypairSigner {
+	key: Keypair,
+}
+
+impl KeypairSigner {
+	pub fn new(key: Keypair) -> KeypairSigner {
+		KeypairSigner { key }
+	}
+}
+
+impl Signer for KeypairSigner {
+	fn pubkey(&self) -> PublicKey {
+		self.key.public_key()
+	}
+
+	fn sign_schnorr(&self, msg: &secp256k1::Message) -> anyhow::Result<schnorr::Signature> {
+		Ok(SECP.sign_schnorr(msg, &self.key))
+	}
+
+	fn sign_schnorr_keyspend(
+		&self,
+		msg: &secp256k1::Message,
+	) -> anyhow::Result<schnorr::Signature> {
+		Ok(SECP.sign_schnorr(msg, &self.key.for_keyspend()))
+	}
+
+	fn musig_partial_sign(
+		&self,
+		pubkeys: &[PublicKey],
+		agg_nonce: MusigAggNonce,
+		sec_nonce: MusigSecNonce,
+		msg: [u8; 32],
+		tweak: Option<[u8; 32]>,
+	) -> anyhow::Result<MusigPartialSignature> {
+		let (sig, _) = musig::partial_sign(
+			pubkeys.iter().copied(), agg_nonce, &self.key, sec_nonce, msg, tweak, None,
+		);
+		Ok(sig)
+	}
+
+	fn musig_deterministic_partial_sign(
+		&self,
+		their_pubkeys: &[PublicKey],
+		their_nonces: &[MusigPubNonce],
+		msg: [u8; 32],
+		tweak: Option<[u8; 32]>,
+	) -> anyhow::Result<(MusigPubNonce, MusigPartialSignature)> {
+		Ok(musig::deterministic_partial_sign(
+			&self.key, their_pubkeys.iter().copied(), their_nonces.iter().copied(), msg, tweak,
+		))
+	}
+}
+
+/// Cosign the onboard of the user.
+///
+/// When a nonce pair is given, it's used instead of a deterministic nonce.
+pub fn cosign_onboard(
+	signer: &dyn Signer,
+	user: &onboard::UserPart,
+	nonce: Option<(MusigSecNonce, MusigPubNonce)>,
+) -> anyhow::Result<onboard::AspPart> {
+	let (reveal_sighash, _reveal_tx) = onboard::reveal_tx_sighash(&user.spec, user.utxo);
+	let msg = reveal_sighash.to_byte_array();
+	let tweak = onboard::onboard_taptweak(&user.spec).to_byte_array();
+	let (nonce, signature) = if let Some((sec_nonce, pub_nonce)) = nonce {
+		let agg_nonce = musig::nonce_agg([user.nonce, pub_nonce]);
+		let sig = signer.musig_partial_sign(
+			&[user.spec.user_pubkey, signer.pubkey()], agg_nonce, sec_nonce, msg, Some(tweak),
+		)?;
+		(pub_nonce, sig)
+	} else {
+		signer.musig_deterministic_partial_sign(
+			&[user.spec.user_pubkey], &[user.nonce], msg, Some(tweak),
+		)?
+	};
+	Ok(onboard::AspPart { nonce, signature })
+}
+
+#[cfg(test)]
+pub mod test {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use bitcoin::secp256k1::rand;
+	use ark::test_util::onboard_user_part;
+
+	use super::*;
+
+	/// A signer that counts its signatures, standing in for an external signer.
+	///
+	/// It doesn't use any of the signing code of [KeypairSigner].
+	pub struct MockSigner {
+		key: Keypair,
+		pub nb_signatures: AtomicUsize,
+	}
+
+	impl MockSigner {
+		pub fn new(key: Keypair) -> MockSigner {
+			MockSigner { key, nb_signatures: AtomicUsize::new(0) }
+		}
+
+		fn count(&self) {
+			self.nb_signatures.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	impl Signer for MockSigner {
+		fn pubkey(&self) -> PublicKey {
+			self.key.public_key()
+		}
+
+		fn sign_schnorr(&self, msg: &secp256k1::Message) -> anyhow::Result<schnorr::Signature> {
+			self.count();
+			Ok(SECP.sign_schnorr_no_aux_rand(msg, &self.key))
+		}
+
+		fn sign_schnorr_keyspend(
+			&self,
+			msg: &secp256k1::Message,
+		) -> anyhow::Result<schnorr::Signature> {
+			self.count();
+			let tweaked = self.key.tap_tweak(&SECP, None).to_inner();
+			Ok(SECP.sign_schnorr_no_aux_rand(msg, &tweaked))
+		}
+
+		fn musig_partial_sign(
+			&self,
+			pubkeys: &[PublicKey],
+			agg_nonce: MusigAggNonce,
+			sec_nonce: MusigSecNonce,
+			msg: [u8; 32],
+			tweak: Option<[u8; 32]>,
+		) -> anyhow::Result<MusigPartialSignature> {
+			self.count();
+			let agg = match tweak {
+				Some(t) => musig::tweaked_key_agg(pubkeys.iter().copied(), t).0,
+				None => musig::key_agg(pubkeys.iter().copied()),
+			};
+			let session = musig::MusigSession::new(
+				&musig::SECP, &agg, agg_nonce, musig::zkp::Message::from_digest(msg),
+			);
+			Ok(session.partial_sign(&musig::SECP, sec_nonce, &musig::keypair_to(&self.key), &agg)
+				.expect("nonce not reused"))
+		}
+
+		fn musig_deterministic_partial_sign(
+			&self,
+			their_pubkeys: &[PublicKey],
+			their_nonces: &[MusigPubNonce],
+			msg: [u8; 32],
+			tweak: Option<[u8; 32]>,
+		) -> anyhow::Result<(MusigPubNonce, MusigPartialSignature)> {
+			// We don't need deterministic nonces for the tests.
+			let (sec_nonce, pub_nonce) = musig::nonce_pair(&self.key);
+			let pubkeys = their_pubkeys.iter().copied().chain(Some(self.key.public_key()))
+				.collect::<Vec<_>>();
+			let agg_nonce = musig::nonce_agg(their_nonces.iter().copied().chain(Some(pub_nonce)));
+			let sig = self.musig_partial_sign(&pubkeys, agg_nonce, sec_nonce, msg, tweak)?;
+			Ok((pub_nonce, sig))
+		}
+	}
+
+	#[test]
+	fn cosign_onboard_with_signer() {
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let user = Keypair::new(&SECP, &mut rand::thread_rng());
+		let part = onboard_user_part(user.public_key(), key.public_key(), 0);
+
+		let mock = MockSigner::new(key);
+		let asp = cosign_onboard(&mock, &part, None).unwrap();
+		assert!(onboard::verify_asp(&part, &asp, key.public_key()));
+		let asp = cosign_onboard(&mock, &part, Some(musig::nonce_pair(&key))).unwrap();
+		assert!(onboard::verify_asp(&part, &asp, key.public_key()));
+		assert_eq!(mock.nb_signatures.load(Ordering::SeqCst), 2);
+
+		// The in-memory signer does the same as signing with the key directly.
+		let signer = KeypairSigner::new(key);
+		let asp = cosign_onboard(&signer, &part, None).unwrap();
+		assert!(onboard::verify_asp(&part, &asp, key.public_key()));
+		let asp = cosign_onboard(&signer, &part, Some(musig::nonce_pair(&key))).unwrap();
+		assert!(onboard::verify_asp(&part, &asp, key.public_key()));
+
+		// A signer with another key doesn't produce a valid cosignature.
+		let other = MockSigner::new(Keypair::new(&SECP, &mut rand::thread_rng()));
+		let asp = cosign_onboard(&other, &part, None).unwrap();
+		assert!(!onboard::verify_asp(&part, &asp, key.public_key()));
+	}
+
+	#[test]
+	fn keyspend_pubkey() {
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let signer = KeypairSigner::new(key);
+		assert_eq!(signer.keyspend_pubkey(), key.for_keyspend().x_only_public_key().0);
+
+		let msg = secp256k1::Message::from_digest([7; 32]);
+		for signer in [&signer as &dyn Signer, &MockSigner::new(key)] {
+			let sig = signer.sign_schnorr_keyspend(&msg).unwrap();
+			SECP.verify_schnorr(&sig, &msg, &signer.keyspend_pubkey()).unwrap();
+			let sig = signer.sign_schnorr(&msg).unwrap();
+			SECP.verify_schnorr(&sig, &msg, &signer.pubkey().x_only_public_key().0).unwrap();
+		}
+	}
+}
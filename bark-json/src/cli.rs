@@ -1,6 +1,6 @@
 
 
-use bitcoin::{Amount, OutPoint};
+use bitcoin::{Amount, OutPoint, Txid};
 use bitcoin::secp256k1::PublicKey;
 
 use ark::{VtxoId, Vtxo};
@@ -9,8 +9,25 @@ use ark::{VtxoId, Vtxo};
 pub struct Balance {
 	#[serde(with = "bitcoin::amount::serde::as_sat")]
 	pub onchain: Amount,
+	/// The part of the onchain balance we try to keep for exit fees.
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub onchain_reserve: Amount,
 	#[serde(with = "bitcoin::amount::serde::as_sat")]
 	pub offchain: Amount,
+	/// The part of the offchain balance in round VTXOs.
+	///
+	/// Watch-only wallets don't know the kind of their VTXOs.
+	#[serde(with = "bitcoin::amount::serde::as_sat::opt")]
+	pub offchain_round: Option<Amount>,
+	/// The part of the offchain balance in OOR VTXOs.
+	#[serde(with = "bitcoin::amount::serde::as_sat::opt")]
+	pub offchain_oor: Option<Amount>,
+	/// The part of the offchain balance that can be spent offchain now.
+	#[serde(default, with = "bitcoin::amount::serde::as_sat::opt")]
+	pub offchain_spendable: Option<Amount>,
+	/// The part of the offchain balance that can be exited onchain now.
+	#[serde(default, with = "bitcoin::amount::serde::as_sat::opt")]
+	pub offchain_exitable: Option<Amount>,
 	#[serde(with = "bitcoin::amount::serde::as_sat")]
 	pub pending_exit: Amount,
 }
@@ -24,18 +41,62 @@ pub enum VtxoType {
 	Bolt11Change,
 }
 
+/// How a VTXO is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VtxoKind {
+	/// The VTXO is fully cosigned by the ASP and all other participants
+	/// of its round or onboard.
+	Round,
+	/// The VTXO depends on out-of-round signatures of its previous
+	/// owners and the ASP.
+	Oor,
+}
+
+impl From<&Vtxo> for VtxoKind {
+	fn from(v: &Vtxo) -> VtxoKind {
+		if v.is_oor() {
+			VtxoKind::Oor
+		} else {
+			VtxoKind::Round
+		}
+	}
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct VtxoInfo {
 	pub id: VtxoId,
 	#[serde(with = "bitcoin::amount::serde::as_sat")]
 	pub amount: Amount,
 	pub vtxo_type: VtxoType,
+	pub kind: VtxoKind,
 	/// The offchain UTXO.
 	pub utxo: OutPoint,
 	pub user_pubkey: PublicKey,
 	pub asp_pubkey: PublicKey,
 	pub expiry_height: u32,
 	pub exit_delta: u16,
+	/// The local labels of the VTXO.
+	#[serde(default)]
+	pub labels: Vec<String>,
+	/// Whether the VTXO is on a key that received multiple OOR payments.
+	#[serde(default)]
+	pub reused_key: bool,
+	/// Whether the full exit path of the VTXO is present and consistent.
+	#[serde(default)]
+	pub exitable: bool,
+	/// The height from which the exit can be claimed, if the VTXO has an
+	/// absolute exit timelock.
+	#[serde(default)]
+	pub exit_ready_height: Option<u32>,
+	/// Whether the VTXO can be spent offchain now. Onboard VTXOs need
+	/// enough confirmations first.
+	#[serde(default)]
+	pub spendable_offchain: bool,
+	/// Whether the VTXO can be exited onchain now. The onchain txs its exit
+	/// starts from have to be confirmed first.
+	#[serde(default)]
+	pub exitable_onchain: bool,
 }
 
 impl From<Vtxo> for VtxoInfo {
@@ -43,6 +104,7 @@ impl From<Vtxo> for VtxoInfo {
 		VtxoInfo {
 			id: v.id(),
 			amount: v.amount(),
+			kind: VtxoKind::from(&v),
 			vtxo_type: match v {
 				Vtxo::Onboard { .. } => VtxoType::Onboard,
 				Vtxo::Round { .. } => VtxoType::Round,
@@ -54,6 +116,12 @@ impl From<Vtxo> for VtxoInfo {
 			asp_pubkey: v.spec().asp_pubkey,
 			expiry_height: v.spec().expiry_height,
 			exit_delta: v.spec().exit_delta,
+			labels: Vec::new(),
+			reused_key: false,
+			exitable: false,
+			exit_ready_height: None,
+			spendable_offchain: false,
+			exitable_onchain: false,
 		}
 	}
 }
@@ -62,4 +130,108 @@ impl From<Vtxo> for VtxoInfo {
 pub struct ExitStatus {
 	pub done: bool,
 	pub height: Option<u32>,
+	/// The txid of the tx claiming the exits, if they were claimed.
+	#[serde(default)]
+	pub claim_txid: Option<Txid>,
+}
+
+/// Whether the claim tx of an exit would be accepted by the mempool.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExitVerification {
+	pub claim_txid: Txid,
+	pub accepted: bool,
+	pub reject_reason: Option<String>,
+	pub vsize: Option<u64>,
+	#[serde(default, with = "bitcoin::amount::serde::as_sat::opt")]
+	pub fee: Option<Amount>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WatchtowerVtxoInfo {
+	pub id: VtxoId,
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub amount: Amount,
+	/// The offchain UTXO.
+	pub utxo: OutPoint,
+	pub expiry_height: u32,
+	pub exit_delta: u16,
+	/// The exit txs in the order they have to be broadcast.
+	pub exit_txids: Vec<Txid>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SendPreview {
+	/// The VTXOs that would be spent.
+	pub inputs: Vec<VtxoId>,
+	/// The amount of the change VTXO, if any.
+	#[serde(with = "bitcoin::amount::serde::as_sat::opt")]
+	pub change: Option<Amount>,
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub fee: Amount,
+	/// The offchain balance after the payment.
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub balance_after: Amount,
+}
+
+/// A one-shot overview of the wallet.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Status {
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub onchain_balance: Amount,
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub offchain_balance: Amount,
+	/// The part of the offchain balance in OOR VTXOs.
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub offchain_oor_balance: Amount,
+	pub nb_vtxos: usize,
+	pub nb_oor_vtxos: usize,
+	/// The number of VTXOs on a key that received multiple OOR payments.
+	pub nb_reused_key_vtxos: usize,
+	/// The expiry height of the VTXO that expires first.
+	pub nearest_expiry_height: Option<u32>,
+	/// The round txs of the rounds we are waiting on to finish.
+	pub pending_round_txids: Vec<Txid>,
+	#[serde(with = "bitcoin::amount::serde::as_sat")]
+	pub pending_round_amount: Amount,
+	pub asp_address: String,
+	pub asp_pubkey: PublicKey,
+	pub vtxo_expiry_delta: u16,
+	pub vtxo_exit_delta: u16,
+	pub onboard_confirmations: u32,
+	pub chain_tip: u32,
+	/// The height up to which the rounds of the ASP were synced.
+	pub ark_sync_height: u32,
+}
+
+/// The process exit codes of the bark CLI, one per error category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+	/// Any error that doesn't fit one of the other categories.
+	Internal = 1,
+	/// The arguments given were invalid. This matches clap's exit code
+	/// for usage errors.
+	InvalidArgument = 2,
+	/// The wallet doesn't have enough money for the requested payment.
+	InsufficientFunds = 3,
+	/// We couldn't reach the ASP.
+	AspUnreachable = 4,
+	/// The ASP failed to finish the round we participated in.
+	RoundFailed = 5,
+}
+
+impl ExitCode {
+	pub fn code(self) -> i32 {
+		self as i32
+	}
+
+	pub fn from_code(code: i32) -> Option<ExitCode> {
+		match code {
+			1 => Some(ExitCode::Internal),
+			2 => Some(ExitCode::InvalidArgument),
+			3 => Some(ExitCode::InsufficientFunds),
+			4 => Some(ExitCode::AspUnreachable),
+			5 => Some(ExitCode::RoundFailed),
+			_ => None,
+		}
+	}
 }
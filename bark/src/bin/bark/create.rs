@@ -1,10 +1,11 @@
-use std::path::{Path};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use clap::Args;
 use tokio::fs;
 
-use bark::{Config, Wallet};
+use bark::{Config, StorageBackend, Wallet, WatchOnlyWallet};
 
 use crate::ConfigOpts;
 
@@ -25,6 +26,42 @@ pub struct CreateOpts {
 	#[arg(long)]
 	bitcoin: bool,
 
+	/// Print the generated mnemonic to stdout.
+	///
+	/// Make sure the output doesn't end up in logs.
+	#[arg(long)]
+	print_mnemonic: bool,
+	/// Write the generated mnemonic to the given file.
+	///
+	/// The file must not exist yet.
+	#[arg(long)]
+	mnemonic_file: Option<PathBuf>,
+	/// Restore from an existing mnemonic instead of generating a new one.
+	///
+	/// Use `restore-vtxos` afterwards to find the VTXOs of the wallet.
+	#[arg(long, conflicts_with_all = ["print_mnemonic", "mnemonic_file"])]
+	mnemonic: Option<bip39::Mnemonic>,
+
+	/// Create a watch-only wallet for the VTXOs in this watchtower bundle.
+	///
+	/// A watch-only wallet holds no keys, it can only show its balance
+	/// and VTXOs.
+	#[arg(long, conflicts_with_all = ["print_mnemonic", "mnemonic_file", "mnemonic"])]
+	watch: Option<PathBuf>,
+	/// Create a watch-only wallet for the VTXOs of the wallet that exported
+	/// this watch key with `export-watch-key`.
+	///
+	/// Unlike a watchtower bundle, this also follows the VTXOs the wallet
+	/// receives in later rounds. It can be combined with `--watch`.
+	#[arg(long, conflicts_with_all = ["print_mnemonic", "mnemonic_file", "mnemonic"])]
+	watch_key: Option<PathBuf>,
+
+	/// The database backend to store the wallet's ark state in.
+	///
+	/// Either "sled" or "sqlite". This can't be changed later.
+	#[arg(long, default_value_t = StorageBackend::Sled)]
+	storage: StorageBackend,
+
 	#[command(flatten)]
 	config: ConfigOpts,
 }
@@ -38,6 +75,12 @@ pub async fn create_wallet(datadir: &Path, opts: CreateOpts) -> anyhow::Result<(
 		bail!("Directory {} already exists", datadir.display());
 	}
 
+	if let Some(ref path) = opts.mnemonic_file {
+		if path.exists() {
+			bail!("Mnemonic file {} already exists", path.display());
+		}
+	}
+
 	match try_create_wallet(&datadir, opts).await {
 		Ok(ok) => Ok(ok),
 		Err(err) => {
@@ -63,11 +106,46 @@ async fn try_create_wallet(datadir: &Path, opts: CreateOpts) -> anyhow::Result<(
 		network: net,
 		// required args
 		asp_address: opts.config.asp.clone().context("ASP address missing, use --asp")?,
+		storage: opts.storage,
 		..Default::default()
 	};
 	opts.config.merge_info(&mut cfg).context("invalid configuration")?;
 
-	Wallet::create(&datadir, cfg).await.context("error creating wallet")?;
+	if opts.watch.is_some() || opts.watch_key.is_some() {
+		let watch_key = opts.watch_key.as_deref().map(crate::read_watch_key).transpose()?;
+		let bundle = opts.watch.as_deref().map(crate::read_watchtower_bundle).transpose()?;
+		WatchOnlyWallet::create(&datadir, cfg, watch_key, bundle).await
+			.context("error creating watch-only wallet")?;
+		return Ok(())
+	}
+
+	let wallet = match opts.mnemonic {
+		Some(mnemonic) => Wallet::create_with_mnemonic(&datadir, cfg, mnemonic).await,
+		None => Wallet::create(&datadir, cfg).await,
+	}.context("error creating wallet")?;
+
+	if opts.print_mnemonic || opts.mnemonic_file.is_some() {
+		let mnemonic = wallet.mnemonic().context("failed to read generated mnemonic")?;
+		if let Some(ref path) = opts.mnemonic_file {
+			write_mnemonic_file(path, &mnemonic.to_string())
+				.with_context(|| format!("failed to write mnemonic file {}", path.display()))?;
+		}
+		if opts.print_mnemonic {
+			println!("{}", mnemonic);
+		}
+	}
 
 	return Ok(())
 }
+
+/// Write the mnemonic to a new file that is only readable by the current user.
+fn write_mnemonic_file(path: &Path, mnemonic: &str) -> anyhow::Result<()> {
+	let mut opts = std::fs::OpenOptions::new();
+	opts.write(true).create_new(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+	let mut file = opts.open(path)?;
+	file.write_all(mnemonic.as_bytes())?;
+	file.write_all(b"\n")?;
+	Ok(())
+}
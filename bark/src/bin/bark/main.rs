@@ -4,20 +4,23 @@
 mod create;
 mod util;
 
-use std::{env, io, process};
-use std::path::PathBuf;
+use std::{cmp, env, fmt, fs, io, process};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::Context;
-use bitcoin::hex::DisplayHex;
-use bitcoin::{address, Address, Amount};
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::{address, Address, Amount, FeeRate, Transaction, Txid};
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
 use bitcoin::secp256k1::PublicKey;
 use clap::Parser;
 use lightning_invoice::Bolt11Invoice;
 use lnurl::lightning_address::LightningAddress;
+use tokio::signal;
 
-use bark::{Wallet, Config};
+use ark::VtxoId;
+use bark::{Config, Wallet, WatchKey, WatchOnlyWallet, WatchtowerBundle, WatchtowerVtxo};
 use bark_json::cli as json;
 
 use crate::create::{CreateOpts, create_wallet};
@@ -56,6 +59,21 @@ struct Cli {
 struct ConfigOpts {
 	#[arg(long)]
 	asp: Option<String>,
+	/// A PEM certificate to verify the ASP's TLS certificate with.
+	#[arg(long)]
+	asp_cert: Option<String>,
+	/// The timeout to connect to the ASP, in seconds.
+	#[arg(long)]
+	asp_connect_timeout: Option<u64>,
+	/// The timeout of requests to the ASP, in seconds.
+	#[arg(long)]
+	asp_request_timeout: Option<u64>,
+	/// The interval of keepalive pings to the ASP, in seconds. 0 disables them.
+	#[arg(long)]
+	asp_keepalive_interval: Option<u64>,
+	/// Whether to ask the ASP to compress its responses.
+	#[arg(long)]
+	asp_compression: Option<bool>,
 
 	/// The esplora HTTP API endpoint.
 	#[arg(long)]
@@ -68,6 +86,24 @@ struct ConfigOpts {
 	bitcoind_user: Option<String>,
 	#[arg(long)]
 	bitcoind_pass: Option<String>,
+	/// The onchain balance to keep available for exit fees.
+	#[arg(long)]
+	reserve: Option<Amount>,
+	/// The number of blocks onchain txs should confirm in, used for fee estimates.
+	#[arg(long)]
+	conf_target: Option<u16>,
+	/// Refresh VTXOs that expire within this number of blocks.
+	#[arg(long)]
+	refresh_threshold: Option<u32>,
+	/// The minimum time between two checks of the daemon for VTXOs to refresh, in seconds.
+	#[arg(long)]
+	daemon_interval: Option<u64>,
+	/// Whether to derive a fresh key for every change VTXO.
+	#[arg(long)]
+	fresh_change_keys: Option<bool>,
+	/// Whether the daemon refreshes VTXOs on reused keys into a fresh key.
+	#[arg(long)]
+	refresh_reused_keys: Option<bool>,
 }
 
 impl ConfigOpts {
@@ -75,6 +111,21 @@ impl ConfigOpts {
 		if let Some(v) = self.asp {
 			cfg.asp_address = v;
 		}
+		if let Some(v) = self.asp_cert {
+			cfg.asp_tls_cert = if v == "" { None } else { Some(v.into()) };
+		}
+		if let Some(v) = self.asp_connect_timeout {
+			cfg.asp_connect_timeout_secs = v;
+		}
+		if let Some(v) = self.asp_request_timeout {
+			cfg.asp_request_timeout_secs = v;
+		}
+		if let Some(v) = self.asp_keepalive_interval {
+			cfg.asp_keepalive_interval_secs = v;
+		}
+		if let Some(v) = self.asp_compression {
+			cfg.asp_compression = v;
+		}
 		if let Some(v) = self.esplora {
 			cfg.esplora_address = if v == "" { None } else { Some(v) };
 		}
@@ -90,15 +141,65 @@ impl ConfigOpts {
 		if let Some(v) = self.bitcoind_pass {
 			cfg.bitcoind_pass = if v == "" { None } else { Some(v) };
 		}
+		if let Some(v) = self.reserve {
+			cfg.reserve_sat = v.to_sat();
+		}
+		if let Some(v) = self.conf_target {
+			if v == 0 {
+				bail!(InvalidArgument("the confirmation target can't be zero".into()));
+			}
+			cfg.conf_target = v;
+		}
+		if let Some(v) = self.refresh_threshold {
+			cfg.vtxo_refresh_threshold = v;
+		}
+		if let Some(v) = self.daemon_interval {
+			if v == 0 {
+				bail!(InvalidArgument("the daemon interval can't be zero".into()));
+			}
+			cfg.daemon_interval_secs = v;
+		}
+		if let Some(v) = self.fresh_change_keys {
+			cfg.fresh_change_keys = v;
+		}
+		if let Some(v) = self.refresh_reused_keys {
+			cfg.refresh_reused_keys = v;
+		}
 
 		if cfg.esplora_address.is_none() && cfg.bitcoind_address.is_none() {
-			bail!("Provide either an esplora or bitcoind url as chain source.");
+			bail!(InvalidArgument("Provide either an esplora or bitcoind url as chain source.".into()));
 		}
 
 		Ok(())
 	}
 }
 
+/// Default timeout for the --wait flags, in seconds.
+const DEFAULT_WAIT_TIMEOUT_SECS: u64 = 3600;
+
+#[derive(clap::Args)]
+struct WaitOpts {
+	/// Wait until the transaction is confirmed.
+	#[arg(long)]
+	wait: bool,
+	/// The number of confirmations to wait for.
+	#[arg(long, default_value_t = 1, requires = "wait")]
+	confirmations: u32,
+	/// The maximum time to wait for confirmation, in seconds.
+	#[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT_SECS, requires = "wait")]
+	wait_timeout: u64,
+}
+
+impl WaitOpts {
+	async fn wait_for(&self, w: &Wallet, txid: Txid) -> anyhow::Result<()> {
+		if self.wait {
+			let timeout = Duration::from_secs(self.wait_timeout);
+			w.wait_for_confirmations(txid, self.confirmations, timeout).await?;
+		}
+		Ok(())
+	}
+}
+
 #[derive(clap::Subcommand)]
 enum Command {
 	/// Create a new wallet.
@@ -120,12 +221,26 @@ enum Command {
 	Onchain(OnchainCommand),
 	/// The the public key used to receive vtxos.
 	#[command()]
-	VtxoPubkey,
+	VtxoPubkey {
+		/// Derive a new key, so that payments to it can't be linked to
+		/// payments to our other keys.
+		#[arg(long, default_value_t = false)]
+		fresh: bool,
+	},
 	#[command()]
 	Balance,
+	/// Print an overview of the wallet, its ASP and the chain sync
+	#[command()]
+	Status,
 	/// list the wallet's VTXOs
 	#[command()]
 	Vtxos,
+	/// fetch all unspent round VTXOs of the wallet from the ASP
+	///
+	/// Use this after restoring a wallet from its mnemonic. The ASP only
+	/// returns the VTXOs after we prove we own their keys.
+	#[command()]
+	RestoreVtxos,
 	/// refresh expiring VTXOs
 	///
 	/// By default the wallet's configured threshold is used.
@@ -140,12 +255,41 @@ enum Command {
 		/// Force refresh all VTXOs regardless of expiry height.
 		#[arg(long)]
 		all: bool,
+		/// Only refresh the VTXOs on keys that received multiple OOR payments,
+		/// into a fresh key.
+		#[arg(long)]
+		reused_keys: bool,
+		/// A local label for the refreshed VTXOs, never shared with the ASP.
+		#[arg(long)]
+		label: Option<String>,
 	},
+	/// keep running and automatically refresh VTXOs nearing expiry
+	///
+	/// VTXOs are refreshed when they expire within the configured refresh
+	/// threshold. Stop the daemon with SIGINT or SIGTERM.
+	#[command()]
+	Daemon,
 	/// onboard from the onchain wallet into the Ark
 	#[command()]
 	Onboard {
-		amount: Amount,
+		/// the amounts to onboard, each one becomes a separate VTXO
+		#[arg(required = true)]
+		amounts: Vec<Amount>,
+		/// onboard even if it leaves less than the fee reserve onchain
+		#[arg(long)]
+		allow_below_reserve: bool,
+		/// a local label for the new VTXOs, never shared with the ASP
+		#[arg(long)]
+		label: Option<String>,
+		#[command(flatten)]
+		wait: WaitOpts,
 	},
+	/// give up on an onboard of which the onboard tx can't be broadcast
+	///
+	/// The onboard tx is broadcast one last time. Only if that fails, the
+	/// VTXOs of the onboard are removed and its on-chain inputs released.
+	#[command()]
+	AbandonOnboard,
 	/// send money using an Ark (out-of-round) transaction
 	#[command()]
 	Send {
@@ -155,6 +299,21 @@ enum Command {
 		amount: Option<Amount>,
 		/// an optional comment
 		comment: Option<String>,
+		/// only show the inputs, change and fees of the payment
+		/// without sending it (only for VTXO pubkeys and on-chain addresses)
+		#[arg(long)]
+		simulate: bool,
+		/// sync with the ASP and the chain before simulating, by
+		/// default only the local wallet state is used
+		#[arg(long, requires = "simulate")]
+		sync: bool,
+		/// pay an on-chain address from your off-chain balance by
+		/// participating in an Ark round (a collaborative exit)
+		#[arg(long)]
+		onchain: bool,
+		/// a local label for the change VTXOs, never shared with the ASP
+		#[arg(long)]
+		label: Option<String>,
 	},
 	/// send money by participating in an Ark round
 	#[command()]
@@ -163,6 +322,21 @@ enum Command {
 		/// or an Ark VTXO public key.
 		destination: String,
 		amount: Amount,
+		/// only show the inputs, change and fees of the payment
+		/// without sending it
+		#[arg(long)]
+		simulate: bool,
+		/// sync with the ASP and the chain before simulating, by
+		/// default only the local wallet state is used
+		#[arg(long, requires = "simulate")]
+		sync: bool,
+		/// deliver the payment as multiple VTXOs with these amounts,
+		/// they should sum to the payment amount
+		#[arg(long)]
+		split: Vec<Amount>,
+		/// a local label for the change VTXOs, never shared with the ASP
+		#[arg(long)]
+		label: Option<String>,
 	},
 	#[command()]
 	OffboardAll,
@@ -178,8 +352,84 @@ enum Command {
 		#[arg(long)]
 		wait: bool,
 
-		//TODO(stevenroose) add a option to claim claimable exits while others are not claimable
-		//yet
+		/// After claiming the exits, wait until the claim tx has this many confirmations.
+		#[arg(long)]
+		confirmations: Option<u32>,
+		/// The maximum time to wait for the claim tx confirmations, in seconds.
+		#[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT_SECS, requires = "confirmations")]
+		wait_timeout: u64,
+
+		/// The fee rate in sat/vB for the exit and claim txs.
+		///
+		/// Defaults to our fee rate for urgent txs.
+		#[arg(long)]
+		feerate: Option<u64>,
+
+		/// Only check whether the claim tx would be accepted by the mempool
+		/// of the chain source, without broadcasting anything.
+		///
+		/// All exit txs need to be confirmed already. Needs bitcoind.
+		#[arg(long, conflicts_with_all = ["wait", "confirmations"])]
+		verify: bool,
+
+		/// Only exit and claim these VTXOs, by their id (outpoint).
+		///
+		/// The other VTXOs stay in the wallet. Pending exits of other VTXOs
+		/// still make progress, but they are not claimed.
+		#[arg(long = "vtxo", conflicts_with = "verify")]
+		vtxos: Vec<VtxoId>,
+	},
+
+	/// Export the exit data of all VTXOs for a third-party watchtower.
+	///
+	/// The bundle contains the fully signed exit txs of each VTXO, but no keys.
+	#[command()]
+	ExportWatchtower {
+		/// Write the hex-encoded bundle to this file instead of printing it.
+		#[arg(long)]
+		file: Option<PathBuf>,
+	},
+	/// Export a key that lets a watch-only wallet list the VTXOs of this
+	/// wallet with the ASP.
+	///
+	/// Create the watch-only wallet with `create --watch-key`. The key
+	/// can't spend any funds.
+	#[command()]
+	ExportWatchKey {
+		/// Write the hex-encoded key to this file instead of printing it.
+		#[arg(long)]
+		file: Option<PathBuf>,
+	},
+	/// Export the signed txs that bring a VTXO onchain, as hex, one per line.
+	///
+	/// These can be broadcast in order with `broadcast-tree` if the ASP
+	/// disappears.
+	#[command()]
+	ExportTreeTxs {
+		/// The id of the VTXO.
+		vtxo: VtxoId,
+		/// Write the txs to this file instead of printing them.
+		#[arg(long)]
+		file: Option<PathBuf>,
+	},
+	/// Broadcast the txs exported with `export-tree-txs` in order.
+	///
+	/// This only uses the chain source and works without the ASP.
+	#[command()]
+	BroadcastTree {
+		/// File containing the hex-encoded txs, one per line.
+		file: PathBuf,
+		/// The fee rate in sat/vB for the CPFP txs of the tree txs.
+		///
+		/// Defaults to our fee rate for urgent txs.
+		#[arg(long)]
+		feerate: Option<u64>,
+	},
+	/// Validate a watchtower bundle and list the VTXOs it covers.
+	#[command()]
+	ImportWatchtower {
+		/// File containing the hex-encoded bundle.
+		file: PathBuf,
 	},
 
 	/// Dev command to drop the vtxo database.
@@ -200,6 +450,11 @@ enum OnchainCommand {
 	Send {
 		destination: Address<address::NetworkUnchecked>,
 		amount: Amount,
+		/// send even if it leaves less than the fee reserve onchain
+		#[arg(long)]
+		allow_below_reserve: bool,
+		#[command(flatten)]
+		wait: WaitOpts,
 	},
 }
 
@@ -241,18 +496,65 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 		return Ok(())
 	}
 
+	// Importing a watchtower bundle doesn't require a wallet.
+	if let Command::ImportWatchtower { file } = cli.command {
+		let bundle = read_watchtower_bundle(&file)?;
+		let vtxos = bundle.vtxos.iter().map(watchtower_vtxo_info).collect::<Vec<_>>();
+		if cli.json {
+			serde_json::to_writer(io::stdout(), &vtxos).unwrap();
+		} else {
+			info!("Watchtower bundle for network {} with {} VTXO(s):", bundle.network, vtxos.len());
+			for v in vtxos {
+				info!("  {} ({}): expires at height {}, {} exit tx(s)",
+					v.id, v.amount, v.expiry_height, v.exit_txids.len(),
+				);
+			}
+		}
+		return Ok(())
+	}
+
+	// Broadcasting tree txs shouldn't need the ASP.
+	if let Command::BroadcastTree { file, feerate } = cli.command {
+		let txs = read_tree_txs(&file)?;
+		let txids = Wallet::broadcast_tree_txs(&datadir, &txs, parse_feerate(feerate)?).await?;
+		if cli.json {
+			serde_json::to_writer(io::stdout(), &txids).unwrap();
+		} else {
+			info!("Broadcast {} of {} tree tx(s)", txids.len(), txs.len());
+		}
+		return Ok(())
+	}
+
+	if WatchOnlyWallet::is_watch_only(&datadir) {
+		return run_watch_only(&datadir, cli).await;
+	}
+
+	// The daemon only opens the wallet when it needs it.
+	if let Command::Daemon = cli.command {
+		let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+			.context("failed to listen for SIGTERM")?;
+		Wallet::run_refresh_daemon(&datadir, async move {
+			tokio::select! {
+				_ = signal::ctrl_c() => {},
+				_ = sigterm.recv() => {},
+			}
+		}).await?;
+		return Ok(())
+	}
+
 	let mut w = Wallet::open(&datadir).await.context("error opening wallet")?;
 	let net = w.config().network;
 
 	match cli.command {
-		Command::Create { .. } => unreachable!(),
+		Command::Create { .. } | Command::ImportWatchtower { .. } | Command::BroadcastTree { .. }
+			| Command::Daemon => unreachable!(),
 		Command::Config { config, dangerous } => {
 			if let Some(new_cfg) = config {
 				let mut cfg = w.config().clone();
 				if !dangerous {
 					if new_cfg.asp.is_some() {
-						bail!("Changing the ASP address can lead to loss of funds. \
-							If you insist, use the --dangerous flag.");
+						bail!(InvalidArgument("Changing the ASP address can lead to loss of funds. \
+							If you insist, use the --dangerous flag.".into()));
 					}
 				}
 				new_cfg.merge_info(&mut cfg).context("invalid configuration")?;
@@ -272,64 +574,222 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 				}
 			},
 			OnchainCommand::Address => println!("{}", w.get_new_onchain_address()?),
-			OnchainCommand::Send { destination: address, amount } => {
+			OnchainCommand::Send { destination: address, amount, allow_below_reserve, wait } => {
 				let addr = address.require_network(net).with_context(|| {
 					format!("address is not valid for configured network {}", net)
 				})?;
 				w.sync_onchain().await.context("sync error")?;
-				w.send_onchain(addr, amount).await?;
+				let txid = w.send_onchain(addr, amount, allow_below_reserve).await?;
+				wait.wait_for(&w, txid).await?;
 			},
 		},
-		Command::VtxoPubkey => println!("{}", w.vtxo_pubkey()),
+		Command::VtxoPubkey { fresh } => if fresh {
+			println!("{}", w.fresh_vtxo_pubkey()?);
+		} else {
+			println!("{}", w.vtxo_pubkey());
+		},
 		Command::Balance => {
 			w.sync().await.context("sync error")?;
 			let onchain = w.onchain_balance();
 			let offchain =  w.offchain_balance().await?;
+			let offchain_oor = w.offchain_oor_balance()?;
+			let offchain_round = offchain - offchain_oor;
+			let mut offchain_spendable = Amount::ZERO;
+			let mut offchain_exitable = Amount::ZERO;
+			let vtxos = w.vtxos()?;
+			let readiness = w.vtxos_readiness(&vtxos).await?;
+			for vtxo in vtxos {
+				let ready = &readiness[&vtxo.id()];
+				if ready.spendable_offchain {
+					offchain_spendable += vtxo.amount();
+				}
+				if ready.exitable_onchain {
+					offchain_exitable += vtxo.amount();
+				}
+			}
+			let onchain_reserve = w.onchain_reserve();
 			let pending_exit = {
 				let exit = w.get_exit()?.unwrap_or_default();
 				exit.total_pending_amount()
 			};
 			if cli.json {
 				serde_json::to_writer(io::stdout(), &json::Balance {
-					onchain, offchain, pending_exit,
+					onchain,
+					onchain_reserve,
+					offchain,
+					offchain_round: Some(offchain_round),
+					offchain_oor: Some(offchain_oor),
+					offchain_spendable: Some(offchain_spendable),
+					offchain_exitable: Some(offchain_exitable),
+					pending_exit,
 				}).unwrap();
 			} else {
 				info!("Onchain balance: {}", onchain);
+				if onchain_reserve > Amount::ZERO {
+					info!("Onchain fee reserve: {}", onchain_reserve);
+				}
 				info!("Offchain balance: {}", offchain);
+				if offchain_oor > Amount::ZERO {
+					info!("  in round VTXOs: {}", offchain_round);
+					info!("  in OOR VTXOs: {}", offchain_oor);
+				}
+				if offchain_spendable != offchain || offchain_exitable != offchain {
+					info!("  spendable offchain now: {}", offchain_spendable);
+					info!("  exitable onchain now: {}", offchain_exitable);
+				}
 				if pending_exit > Amount::ZERO {
 					info!("An exit process is pending for {}", pending_exit);
 				}
 			}
+			if onchain < onchain_reserve {
+				warn!("Onchain balance is below the fee reserve of {}, you might not be able \
+					to pay the fees of a unilateral exit", onchain_reserve);
+			}
+		},
+		Command::Status => {
+			w.sync().await.context("sync error")?;
+			let status = w.status().await?;
+			if cli.json {
+				serde_json::to_writer(io::stdout(), &json::Status {
+					onchain_balance: status.onchain_balance,
+					offchain_balance: status.offchain_balance,
+					offchain_oor_balance: status.offchain_oor_balance,
+					nb_vtxos: status.nb_vtxos,
+					nb_oor_vtxos: status.nb_oor_vtxos,
+					nb_reused_key_vtxos: status.nb_reused_key_vtxos,
+					nearest_expiry_height: status.nearest_expiry_height,
+					pending_round_txids: status.pending_round_txids,
+					pending_round_amount: status.pending_round_amount,
+					asp_address: status.asp_address,
+					asp_pubkey: status.ark_info.asp_pubkey,
+					vtxo_expiry_delta: status.ark_info.vtxo_expiry_delta,
+					vtxo_exit_delta: status.ark_info.vtxo_exit_delta,
+					onboard_confirmations: status.ark_info.onboard_confirmations,
+					chain_tip: status.chain_tip,
+					ark_sync_height: status.ark_sync_height,
+				}).unwrap();
+			} else {
+				info!("Onchain balance: {}", status.onchain_balance);
+				info!("Offchain balance: {} in {} VTXO(s)", status.offchain_balance, status.nb_vtxos);
+				if status.nb_oor_vtxos > 0 {
+					info!("  of which {} in {} OOR VTXO(s)",
+						status.offchain_oor_balance, status.nb_oor_vtxos,
+					);
+				}
+				if status.nb_reused_key_vtxos > 0 {
+					warn!("{} VTXO(s) are on a key that received multiple OOR payments, \
+						these payments can be linked together. Use `bark refresh --reused-keys` \
+						to move them to a fresh key and `bark vtxo-pubkey --fresh` to receive \
+						on a new key.", status.nb_reused_key_vtxos,
+					);
+				}
+				if let Some(expiry) = status.nearest_expiry_height {
+					if let Some(diff) = expiry.checked_sub(status.chain_tip) {
+						let time_left = Duration::from_secs(60 * 10 * diff as u64);
+						info!("Nearest VTXO expiry at height {} (in about {})",
+							expiry, PrettyDuration(time_left),
+						);
+					} else {
+						info!("Nearest VTXO expiry at height {} (already expired)", expiry);
+					}
+				}
+				if !status.pending_round_txids.is_empty() {
+					info!("Waiting for {} round(s) to finish, spending {}",
+						status.pending_round_txids.len(), status.pending_round_amount,
+					);
+					for txid in &status.pending_round_txids {
+						info!("  round {}", txid);
+					}
+				}
+				info!("ASP: {} with pubkey {}", status.asp_address, status.ark_info.asp_pubkey);
+				info!("  VTXO expiry delta: {} blocks, exit delta: {} blocks",
+					status.ark_info.vtxo_expiry_delta, status.ark_info.vtxo_exit_delta,
+				);
+				info!("Chain tip at height {}, rounds synced up to height {}",
+					status.chain_tip, status.ark_sync_height,
+				);
+			}
 		},
 		Command::Vtxos => {
 			w.sync_ark().await.context("sync error")?;
 			let res = w.vtxos()?;
+			let readiness = w.vtxos_readiness(&res).await?;
 			if cli.json {
-				let json = res.into_iter().map(|v| v.into()).collect::<Vec<json::VtxoInfo>>();
+				let mut json = Vec::with_capacity(res.len());
+				for v in res {
+					let labels = w.vtxo_labels(v.id())?;
+					let reused_key = w.has_reused_key(&v)?;
+					let exit = w.vtxo_exit_status(&v)?;
+					let ready = &readiness[&v.id()];
+					json.push(json::VtxoInfo {
+						labels,
+						reused_key,
+						exitable: exit.exitable,
+						exit_ready_height: exit.exit_ready_height,
+						spendable_offchain: ready.spendable_offchain,
+						exitable_onchain: ready.exitable_onchain,
+						..json::VtxoInfo::from(v)
+					});
+				}
 				serde_json::to_writer(io::stdout(), &json).unwrap();
 			} else {
 				info!("Our wallet has {} VTXO(s):", res.len());
 				let tip = w.chain_tip_height().await.context("bitcoin chain source error")?;
 				for v in res {
 					let expiry = v.spec().expiry_height;
+					let labels = w.vtxo_labels(v.id())?;
+					let mut labels = if labels.is_empty() {
+						String::new()
+					} else {
+						format!(" [{}]", labels.join(", "))
+					};
+					if w.has_reused_key(&v)? {
+						labels.push_str(" (reused key)");
+					}
+					if !w.vtxo_exit_status(&v)?.exitable {
+						labels.push_str(" (not exitable)");
+					} else {
+						let ready = &readiness[&v.id()];
+						if !ready.spendable_offchain && expiry > tip {
+							labels.push_str(" (not spendable offchain yet)");
+						}
+						if !ready.exitable_onchain {
+							labels.push_str(" (not exitable onchain yet)");
+						}
+					}
 					if let Some(diff) = expiry.checked_sub(tip) {
 						let time_left = Duration::from_secs(60 * 10 * diff as u64);
-						info!("  {} ({}): {}; expires at height {} (in about {})",
-							v.id(), v.vtxo_type(), v.amount(), expiry, PrettyDuration(time_left),
+						info!("  {} ({}): {}; expires at height {} (in about {}){}",
+							v.id(), v.vtxo_type(), v.amount(), expiry, PrettyDuration(time_left), labels,
 						);
 					} else {
-						info!("  {} ({}): {}; already expired", v.id(), v.vtxo_type(), v.amount());
+						info!("  {} ({}): {}; already expired{}",
+							v.id(), v.vtxo_type(), v.amount(), labels,
+						);
 					}
 				}
 			}
 		},
-		Command::Refresh { threshold_blocks, threshold_hours, all } => {
+		Command::RestoreVtxos => {
+			let nb_new = w.restore_round_vtxos().await.context("error restoring vtxos")?;
+			info!("Restored {} new VTXO(s) from the ASP", nb_new);
+		},
+		Command::Refresh { threshold_blocks, threshold_hours, all, reused_keys, label } => {
+			w.set_label(label);
+			if reused_keys {
+				if threshold_blocks.is_some() || threshold_hours.is_some() || all {
+					bail!(InvalidArgument("--reused-keys can't be combined with a threshold".into()));
+				}
+				w.sync_ark().await.context("sync error")?;
+				w.refresh_reused_key_vtxos().await?;
+				return Ok(());
+			}
 			let threshold = match (threshold_blocks, threshold_hours, all) {
 				(None, None, false) => Some(w.config().vtxo_refresh_threshold),
 				(Some(b), None, false) => Some(b),
 				(None, Some(h), false) => Some(h * 6),
 				(None, None, true) => None,
-				_ => bail!("please provide either threshold blocks, hour or all"),
+				_ => bail!(InvalidArgument("please provide either threshold blocks, hour or all".into())),
 			};
 
 			if let Some(th) = threshold {
@@ -339,27 +799,75 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 			}
 			w.refresh_vtxos(threshold).await?;
 		},
-		Command::Onboard { amount } => w.onboard(amount).await?,
-		Command::Send { destination, amount, comment } => {
-			if let Ok(pk) = PublicKey::from_str(&destination) {
-				let amount = amount.context("amount missing")?;
+		Command::Onboard { amounts, allow_below_reserve, label, mut wait } => {
+			w.set_label(label);
+			let txid = w.onboard_many(&amounts, allow_below_reserve).await?;
+			// The onboard is only usable once the ASP considers it confirmed.
+			wait.confirmations = cmp::max(wait.confirmations, w.ark_info().onboard_confirmations);
+			wait.wait_for(&w, txid).await?;
+		},
+		Command::AbandonOnboard => {
+			match w.abandon_pending_onboard().await? {
+				Some(txid) => info!("Abandoned onboard {}", txid),
+				None => info!("There is no unfinished onboard"),
+			}
+		},
+		Command::Send { destination, amount, comment, simulate, sync, onchain, label } => {
+			w.set_label(label);
+			if onchain {
+				let addr = Address::from_str(&destination)
+					.map_err(|_| InvalidArgument("--onchain needs an on-chain address".into()))?
+					.require_network(net).map_err(|_| InvalidArgument(
+						format!("address is not valid for configured network {}", net),
+					))?;
+				let amount = amount.ok_or_else(|| InvalidArgument("amount missing".into()))?;
 				if comment.is_some() {
-					bail!("comment not supported for VTXO pubkey");
+					bail!(InvalidArgument("comment not supported for on-chain address".into()));
+				}
+
+				if !simulate || sync {
+					w.sync_ark().await.context("sync error")?;
+				}
+				if simulate {
+					print_send_preview(w.simulate_round_onchain_payment(addr, amount)?, cli.json);
+					return Ok(());
+				}
+				info!("Sending {} to on-chain address {} in a round", amount, addr);
+				w.send_round_onchain_payment(addr, amount).await?;
+			} else if let Ok(pk) = PublicKey::from_str(&destination) {
+				let amount = amount.ok_or_else(|| InvalidArgument("amount missing".into()))?;
+				if comment.is_some() {
+					bail!(InvalidArgument("comment not supported for VTXO pubkey".into()));
+				}
+
+				if simulate {
+					if sync {
+						w.sync_ark().await.context("sync error")?;
+					}
+					let preview = w.simulate_oor_payment(pk, amount)?;
+					print_send_preview(preview, cli.json);
+					return Ok(());
 				}
 
 				info!("Sending arkoor payment of {} to pubkey {}", amount, pk);
 				w.sync_ark().await.context("sync error")?;
 				w.send_oor_payment(pk, amount).await?;
+			} else if simulate {
+				bail!(InvalidArgument(
+					"--simulate is only supported for VTXO pubkeys and on-chain addresses".into(),
+				));
 			} else if let Ok(inv) = Bolt11Invoice::from_str(&destination) {
 				let inv_amount = inv.amount_milli_satoshis()
 					.map(|v| Amount::from_sat(v.div_ceil(1000)));
 				if let (Some(_), Some(inv)) = (amount, inv_amount) {
-					bail!("Invoice has amount of {} encoded. Please omit amount argument", inv);
+					bail!(InvalidArgument(format!(
+						"Invoice has amount of {} encoded. Please omit amount argument", inv,
+					)));
 				}
 				let final_amount = amount.or(inv_amount)
-					.context("amount required on invoice without amount")?;
+					.ok_or_else(|| InvalidArgument("amount required on invoice without amount".into()))?;
 				if comment.is_some() {
-					bail!("comment not supported for bolt11 invoice");
+					bail!(InvalidArgument("comment not supported for bolt11 invoice".into()));
 				}
 
 				info!("Sending bolt11 payment to invoice {}", inv);
@@ -368,7 +876,7 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 				let preimage = w.send_bolt11_payment(&inv, amount).await?;
 				info!("Payment preimage received: {}", preimage.as_hex());
 			} else if let Ok(addr) = LightningAddress::from_str(&destination) {
-				let amount = amount.context("amount missing")?;
+				let amount = amount.ok_or_else(|| InvalidArgument("amount missing".into()))?;
 
 				info!("Sending {} to lightning address {}", amount, addr);
 				w.sync_ark().await.context("sync error")?;
@@ -377,49 +885,116 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 				info!("Paid invoice {}", inv);
 				info!("Payment preimage received: {}", preimage.as_hex());
 			} else {
-				bail!("Argument is not a valid destination. Supported are: \
-					VTXO pubkeys, bolt11 invoices, lightning addresses",
-				);
+				bail!(InvalidArgument("Argument is not a valid destination. Supported are: \
+					VTXO pubkeys, bolt11 invoices, lightning addresses and on-chain \
+					addresses with --onchain".into(),
+				));
 			}
 			info!("Success");
 		},
-		Command::SendRound { destination, amount } => {
+		Command::SendRound { destination, amount, simulate, sync, split, label } => {
+			w.set_label(label);
 			if let Ok(pk) = PublicKey::from_str(&destination) {
+				if !split.is_empty() && split.iter().copied().sum::<Amount>() != amount {
+					bail!(InvalidArgument("split amounts should sum to the payment amount".into()));
+				}
+
 				debug!("Sending to Ark public key {}", pk);
-				w.sync_ark().await.context("sync error")?;
-				w.send_round_payment(pk, amount).await?;
+				if !simulate || sync {
+					w.sync_ark().await.context("sync error")?;
+				}
+				if simulate {
+					print_send_preview(w.simulate_round_payment(amount)?, cli.json);
+					return Ok(());
+				}
+				if split.is_empty() {
+					w.send_round_payment(pk, amount).await?;
+				} else {
+					w.send_round_payment_split(pk, &split).await?;
+				}
+			} else if !split.is_empty() {
+				bail!(InvalidArgument("--split is only supported for VTXO pubkeys".into()));
 			} else if let Ok(addr) = Address::from_str(&destination) {
-				let addr = addr.require_network(net).with_context(|| {
-					format!("address is not valid for configured network {}", net)
-				})?;
+				let addr = addr.require_network(net).map_err(|_| InvalidArgument(
+					format!("address is not valid for configured network {}", net),
+				))?;
 				debug!("Sending to on-chain address {}", addr);
-				w.sync_ark().await.context("sync error")?;
+				if !simulate || sync {
+					w.sync_ark().await.context("sync error")?;
+				}
+				if simulate {
+					print_send_preview(w.simulate_round_onchain_payment(addr, amount)?, cli.json);
+					return Ok(());
+				}
 				w.send_round_onchain_payment(addr, amount).await?;
 			} else {
-				bail!("Invalid destination");
+				bail!(InvalidArgument("Invalid destination".into()));
 			}
 		},
 		Command::OffboardAll => w.offboard_all().await?,
-		Command::Exit { only_progress, wait } => {
+		Command::Exit {
+			only_progress, wait, confirmations, wait_timeout, feerate, verify, vtxos,
+		} => {
+			let fee_rate = parse_feerate(feerate)?;
+			if verify {
+				let res = w.verify_exit(fee_rate).await.context("error verifying exit")?;
+				if cli.json {
+					let ret = json::ExitVerification {
+						claim_txid: res.txid,
+						accepted: res.allowed,
+						reject_reason: res.reject_reason,
+						vsize: res.vsize,
+						fee: res.fee,
+					};
+					serde_json::to_writer(io::stdout(), &ret).unwrap();
+				} else if res.allowed {
+					info!("Claim tx {} would be accepted by the mempool", res.txid);
+					if let (Some(fee), Some(vsize)) = (res.fee, res.vsize) {
+						info!("It pays a fee of {} for {} vbytes", fee, vsize);
+					}
+				} else {
+					info!("Claim tx {} would be rejected by the mempool: {}",
+						res.txid, res.reject_reason.as_deref().unwrap_or("unknown reason"),
+					);
+				}
+				return Ok(());
+			}
 			if !only_progress {
-				w.start_exit_for_entire_wallet().await
-					.context("error starting exit process for existing vtxos")?;
+				if vtxos.is_empty() {
+					w.start_exit_for_entire_wallet().await
+						.context("error starting exit process for existing vtxos")?;
+				} else {
+					// Already exited vtxos are no longer in the wallet.
+					let pending = w.get_exit()?.unwrap_or_default();
+					let new = vtxos.iter().copied()
+						.filter(|id| !pending.vtxos().any(|v| v.id() == *id))
+						.collect::<Vec<_>>();
+					w.start_exit_selected(&new).await
+						.context("error starting exit process for selected vtxos")?;
+				}
 			}
 
 			let mut wallet = Some(w);
 			loop {
-				let res = wallet.as_mut().unwrap().progress_exit().await
-					.context("error making progress on exit process")?;
+				let w = wallet.as_mut().unwrap();
+				let res = if vtxos.is_empty() {
+					w.progress_exit(fee_rate).await
+				} else {
+					w.progress_exit_selected(fee_rate, &vtxos).await
+				}.context("error making progress on exit process")?;
 				if cli.json {
 					let ret = match res {
 						bark::ExitStatus::Done => {
-							json::ExitStatus { done: true, height: None }
+							json::ExitStatus { done: true, height: None, claim_txid: None }
+						},
+						bark::ExitStatus::Claimed(txid) => {
+							json::ExitStatus { done: true, height: None, claim_txid: Some(txid) }
 						},
 						bark::ExitStatus::NeedMoreTxs => {
-							json::ExitStatus { done: false, height: None }
+							json::ExitStatus { done: false, height: None, claim_txid: None }
 						},
 						bark::ExitStatus::WaitingForHeight(h) => {
-							json::ExitStatus { done: false, height: Some(h) }
+							json::ExitStatus { done: false, height: Some(h), claim_txid: None }
 						},
 					};
 					serde_json::to_writer(io::stdout(), &ret).unwrap();
@@ -428,6 +1003,9 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 						bark::ExitStatus::Done => {
 							info!("Exit succesful!");
 						}
+						bark::ExitStatus::Claimed(txid) => {
+							info!("Exit succesful! Claimed all exits in tx {}", txid);
+						}
 						bark::ExitStatus::NeedMoreTxs => {
 							if wait {
 								info!("More transactions need to be confirmed.");
@@ -448,6 +1026,13 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 					}
 				}
 
+				if let bark::ExitStatus::Claimed(txid) = res {
+					if let Some(confs) = confirmations {
+						let timeout = Duration::from_secs(wait_timeout);
+						wallet.as_ref().unwrap().wait_for_confirmations(txid, confs, timeout).await?;
+					}
+					break;
+				}
 				if res == bark::ExitStatus::Done {
 					break;
 				}
@@ -477,6 +1062,43 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 
 		// dev commands
 
+		Command::ExportWatchtower { file } => {
+			let bundle = w.export_watchtower().context("error creating watchtower bundle")?;
+			let hex = bundle.encode().as_hex().to_string();
+			if let Some(path) = file {
+				fs::write(&path, hex)
+					.with_context(|| format!("failed to write bundle to {}", path.display()))?;
+				info!("Wrote watchtower bundle for {} VTXO(s) to {}",
+					bundle.vtxos.len(), path.display(),
+				);
+			} else {
+				println!("{}", hex);
+			}
+		},
+		Command::ExportWatchKey { file } => {
+			let key = w.export_watch_key().context("error creating watch key")?;
+			let hex = key.encode().as_hex().to_string();
+			if let Some(path) = file {
+				fs::write(&path, hex)
+					.with_context(|| format!("failed to write watch key to {}", path.display()))?;
+				info!("Wrote watch key for {} VTXO pubkey(s) to {}",
+					key.pubkeys.len(), path.display(),
+				);
+			} else {
+				println!("{}", hex);
+			}
+		},
+		Command::ExportTreeTxs { vtxo, file } => {
+			let txs = w.export_tree_txs(vtxo)?;
+			let lines = txs.iter().map(|tx| serialize_hex(tx)).collect::<Vec<_>>().join("\n");
+			if let Some(path) = file {
+				fs::write(&path, lines)
+					.with_context(|| format!("failed to write txs to {}", path.display()))?;
+				info!("Wrote {} tx(s) for VTXO {} to {}", txs.len(), vtxo, path.display());
+			} else {
+				println!("{}", lines);
+			}
+		},
 		Command::DropVtxos => {
 			w.drop_vtxos().await?;
 			info!("Dropped all vtxos");
@@ -485,6 +1107,146 @@ async fn inner_main(cli: Cli) -> anyhow::Result<()> {
 	Ok(())
 }
 
+fn print_send_preview(preview: bark::SendPreview, json: bool) {
+	if json {
+		serde_json::to_writer(io::stdout(), &json::SendPreview {
+			inputs: preview.inputs.iter().map(|v| v.id()).collect(),
+			change: preview.change,
+			fee: preview.fee,
+			balance_after: preview.balance_after,
+		}).unwrap();
+	} else {
+		for vtxo in &preview.inputs {
+			info!("Input: {} ({})", vtxo.id(), vtxo.amount());
+		}
+		if let Some(change) = preview.change {
+			info!("Change VTXO: {}", change);
+		} else {
+			info!("No change VTXO");
+		}
+		info!("Fee: {}", preview.fee);
+		info!("Balance after payment: {}", preview.balance_after);
+	}
+}
+
+/// Read a hex-encoded watchtower bundle from a file and validate it.
+fn read_watchtower_bundle(file: &Path) -> anyhow::Result<WatchtowerBundle> {
+	let hex = fs::read_to_string(&file)
+		.with_context(|| format!("failed to read bundle file {}", file.display()))?;
+	let bytes = Vec::<u8>::from_hex(hex.trim()).context("bundle is not valid hex")?;
+	WatchtowerBundle::import(&bytes)
+}
+
+/// Read a hex-encoded watch key from a file and validate it.
+fn read_watch_key(file: &Path) -> anyhow::Result<WatchKey> {
+	let hex = fs::read_to_string(&file)
+		.with_context(|| format!("failed to read watch key file {}", file.display()))?;
+	let bytes = Vec::<u8>::from_hex(hex.trim()).context("watch key is not valid hex")?;
+	WatchKey::import(&bytes)
+}
+
+/// Parse a fee rate argument in sat/vB.
+fn parse_feerate(feerate: Option<u64>) -> anyhow::Result<Option<FeeRate>> {
+	Ok(match feerate {
+		Some(0) => bail!(InvalidArgument("feerate can't be zero".into())),
+		Some(v) => Some(FeeRate::from_sat_per_vb(v)
+			.ok_or_else(|| InvalidArgument("feerate is too high".into()))?),
+		None => None,
+	})
+}
+
+fn read_tree_txs(file: &Path) -> anyhow::Result<Vec<Transaction>> {
+	let content = fs::read_to_string(&file)
+		.with_context(|| format!("failed to read tx file {}", file.display()))?;
+	content.lines().map(str::trim).filter(|l| !l.is_empty()).enumerate().map(|(i, line)| {
+		deserialize_hex(line).with_context(|| format!("invalid tx on line {}", i + 1))
+	}).collect()
+}
+
+fn watchtower_vtxo_info(v: &WatchtowerVtxo) -> json::WatchtowerVtxoInfo {
+	json::WatchtowerVtxoInfo {
+		id: v.id,
+		amount: v.claim.spec.amount,
+		utxo: v.claim.utxo,
+		expiry_height: v.expiry_height(),
+		exit_delta: v.claim.spec.exit_delta,
+		exit_txids: v.exit_txs.iter().map(|t| t.compute_txid()).collect(),
+	}
+}
+
+/// Run a command on a watch-only wallet, which can only show its vtxos.
+async fn run_watch_only(datadir: &Path, cli: Cli) -> anyhow::Result<()> {
+	let mut w = WatchOnlyWallet::open(datadir).await.context("error opening watch-only wallet")?;
+	match cli.command {
+		Command::Balance => {
+			let offchain = w.balance().await?;
+			if cli.json {
+				serde_json::to_writer(io::stdout(), &json::Balance {
+					onchain: Amount::ZERO,
+					onchain_reserve: Amount::ZERO,
+					offchain,
+					offchain_round: None,
+					offchain_oor: None,
+					offchain_spendable: None,
+					offchain_exitable: None,
+					pending_exit: Amount::ZERO,
+				}).unwrap();
+			} else {
+				info!("Offchain balance: {}", offchain);
+			}
+		},
+		Command::Vtxos => {
+			let vtxos = w.vtxos().await?.iter().map(watchtower_vtxo_info).collect::<Vec<_>>();
+			if cli.json {
+				serde_json::to_writer(io::stdout(), &vtxos).unwrap();
+			} else {
+				info!("Watching {} VTXO(s):", vtxos.len());
+				for v in vtxos {
+					info!("  {}: {}; expires at height {}", v.id, v.amount, v.expiry_height);
+				}
+			}
+		},
+		Command::Config { config: None, .. } => println!("{:#?}", w.config()),
+		_ => bail!(InvalidArgument(
+			"This is a watch-only wallet, it can only show its balance and VTXOs.".into(),
+		)),
+	}
+	Ok(())
+}
+
+/// An error in the arguments the user provided.
+#[derive(Debug)]
+struct InvalidArgument(String);
+
+impl fmt::Display for InvalidArgument {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.0)
+	}
+}
+
+impl std::error::Error for InvalidArgument {}
+
+/// Pick the process exit code for an error by looking for a known
+/// error type anywhere in its chain.
+fn exit_code(e: &anyhow::Error) -> json::ExitCode {
+	for cause in e.chain() {
+		if cause.is::<InvalidArgument>() {
+			return json::ExitCode::InvalidArgument;
+		} else if cause.is::<bark::InsufficientFunds>() || cause.is::<bark::BelowReserve>() {
+			return json::ExitCode::InsufficientFunds;
+		} else if cause.is::<bark::RoundFailed>() {
+			return json::ExitCode::RoundFailed;
+		} else if cause.is::<tonic::transport::Error>() {
+			return json::ExitCode::AspUnreachable;
+		} else if let Some(status) = cause.downcast_ref::<tonic::Status>() {
+			if status.code() == tonic::Code::Unavailable {
+				return json::ExitCode::AspUnreachable;
+			}
+		}
+	}
+	json::ExitCode::Internal
+}
+
 #[tokio::main]
 async fn main() {
 	let cli = Cli::parse();
@@ -507,6 +1269,6 @@ async fn main() {
 			eprintln!("Stack backtrace:");
 			eprintln!("{}", e.backtrace());
 		}
-		process::exit(1);
+		process::exit(exit_code(&e).code());
 	}
 }
@@ -0,0 +1,387 @@
+
+//! The storage of the wallet's ark state.
+//!
+//! The state can be stored in a sled database or in a sqlite database.
+//! The backend is picked when the wallet is created and can't be changed
+//! afterwards.
+
+mod sled_db;
+mod sqlite;
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::Amount;
+use bitcoin::secp256k1::PublicKey;
+
+use ark::{ExitTimelock, Vtxo, VtxoId};
+
+use crate::{PendingOnboard, PendingRound};
+use crate::exit::Exit;
+
+/// The name of the sled database in the datadir.
+const SLED_DB: &str = "db";
+/// The name of the sqlite database in the datadir.
+const SQLITE_DB: &str = "db.sqlite";
+
+/// The exit status of a vtxo, computed when the vtxo is stored so that
+/// it can be looked up without walking its exit path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VtxoExitStatus {
+	/// Whether the full exit path of the vtxo is present and consistent,
+	/// see [Vtxo::validate_exit_path].
+	pub exitable: bool,
+	/// The height from which the exit can be claimed, if the vtxo has an
+	/// absolute exit timelock. With a relative timelock, it depends on when
+	/// the exit confirms.
+	pub exit_ready_height: Option<u32>,
+}
+
+impl VtxoExitStatus {
+	pub fn of(vtxo: &Vtxo) -> VtxoExitStatus {
+		let exitable = vtxo.validate_exit_path().is_ok();
+		let exit_ready_height = match vtxo.spec().exit_timelock() {
+			ExitTimelock::Absolute(height) if exitable => Some(height),
+			_ => None,
+		};
+		VtxoExitStatus { exitable, exit_ready_height }
+	}
+}
+
+/// The database backend used to store the wallet's ark state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+	/// A sled database.
+	#[default]
+	Sled,
+	/// A sqlite database.
+	///
+	/// Can be read by other tools while the wallet is running.
+	Sqlite,
+}
+
+impl fmt::Display for StorageBackend {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			StorageBackend::Sled => "sled",
+			StorageBackend::Sqlite => "sqlite",
+		})
+	}
+}
+
+impl FromStr for StorageBackend {
+	type Err = anyhow::Error;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"sled" => Ok(StorageBackend::Sled),
+			"sqlite" => Ok(StorageBackend::Sqlite),
+			_ => bail!("unknown storage backend '{}', use 'sled' or 'sqlite'", s),
+		}
+	}
+}
+
+/// Open the database of the given backend in the datadir.
+pub fn open(datadir: &Path, backend: StorageBackend) -> anyhow::Result<Box<dyn Storage>> {
+	Ok(match backend {
+		StorageBackend::Sled => Box::new(sled_db::SledDb::open(&datadir.join(SLED_DB))?),
+		StorageBackend::Sqlite => Box::new(sqlite::SqliteDb::open(&datadir.join(SQLITE_DB))?),
+	})
+}
+
+/// The wallet's ark state.
+pub trait Storage: Send + Sync {
+	/// Store a vtxo, together with its [VtxoExitStatus].
+	fn store_vtxo(&self, vtxo: &Vtxo) -> anyhow::Result<()>;
+
+	fn get_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<Vtxo>>;
+
+	fn get_all_vtxos(&self) -> anyhow::Result<Vec<Vtxo>>;
+
+	/// Get the soonest-expiring vtxos with total value at least `min_value`.
+	fn get_expiring_vtxos(&self, min_value: Amount) -> anyhow::Result<Vec<Vtxo>>;
+
+	/// Remove a vtxo, together with its [VtxoExitStatus].
+	fn remove_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<Vtxo>>;
+
+	/// Move the vtxo from our spendable vtxos to the lost vtxos.
+	fn mark_vtxo_lost(&self, vtxo: &Vtxo) -> anyhow::Result<()>;
+
+	fn get_lost_vtxos(&self) -> anyhow::Result<Vec<Vtxo>>;
+
+	/// Store the ongoing exit process.
+	fn store_exit(&self, exit: &Exit) -> anyhow::Result<()>;
+
+	/// Fetch the ongoing exit process.
+	fn fetch_exit(&self) -> anyhow::Result<Option<Exit>>;
+
+	/// Store a round we're about to forfeit our vtxos in, replacing the
+	/// stored round with the same id.
+	fn store_pending_round(&self, round: &PendingRound) -> anyhow::Result<()>;
+
+	/// All rounds we forfeited vtxos in, but don't know the outcome of.
+	fn get_pending_rounds(&self) -> anyhow::Result<Vec<PendingRound>>;
+
+	fn remove_pending_round(&self, id: u64) -> anyhow::Result<()>;
+
+	/// Store the onboard we signed, but didn't broadcast yet.
+	fn store_pending_onboard(&self, onboard: &PendingOnboard) -> anyhow::Result<()>;
+
+	fn fetch_pending_onboard(&self) -> anyhow::Result<Option<PendingOnboard>>;
+
+	fn clear_pending_onboard(&self) -> anyhow::Result<()>;
+
+	fn get_last_ark_sync_height(&self) -> anyhow::Result<u32>;
+
+	fn store_last_ark_sync_height(&self, height: u32) -> anyhow::Result<()>;
+
+	fn store_spent_vtxo(&self, id: VtxoId, height: u32) -> anyhow::Result<()>;
+
+	fn has_spent_vtxo(&self, id: VtxoId) -> anyhow::Result<bool>;
+
+	/// Store the labels of a vtxo, replacing its previous labels.
+	fn store_vtxo_labels(&self, id: VtxoId, labels: &[String]) -> anyhow::Result<()>;
+
+	/// The labels of a vtxo, also for vtxos we already spent.
+	fn get_vtxo_labels(&self, id: VtxoId) -> anyhow::Result<Vec<String>>;
+
+	/// The exit status computed when the vtxo was stored.
+	///
+	/// Returns [None] for vtxos stored before we kept their exit status.
+	fn get_vtxo_exit_status(&self, id: VtxoId) -> anyhow::Result<Option<VtxoExitStatus>>;
+
+	/// Store the exit status of a vtxo.
+	fn store_vtxo_exit_status(&self, id: VtxoId, status: &VtxoExitStatus) -> anyhow::Result<()>;
+
+	/// Store the derivation index of a vtxo key we derived.
+	fn store_vtxo_key_index(&self, idx: u32, pubkey: PublicKey) -> anyhow::Result<()>;
+
+	/// The derivation index of the vtxo key with the given pubkey, if we derived it.
+	fn get_vtxo_key_index(&self, pubkey: PublicKey) -> anyhow::Result<Option<u32>>;
+
+	/// Register that we received an OOR vtxo on the given pubkey.
+	///
+	/// Returns the number of OOR vtxos we received on the pubkey so far.
+	fn register_received_key(&self, pubkey: PublicKey) -> anyhow::Result<u32>;
+
+	/// The number of OOR vtxos we received on the given pubkey.
+	fn get_received_key_count(&self, pubkey: PublicKey) -> anyhow::Result<u32>;
+
+	/// The derivation index of the next vtxo key to derive.
+	///
+	/// Keys are derived in order, so this is the number of keys we derived.
+	fn next_vtxo_key_index(&self) -> anyhow::Result<u32>;
+}
+
+#[cfg(test)]
+mod test {
+	use std::fs;
+
+	use bitcoin::{absolute, transaction, OutPoint, Transaction, Txid};
+	use bitcoin::hashes::Hash;
+	use bitcoin::secp256k1::{rand, schnorr, Keypair};
+
+	use ark::{BaseVtxo, ExitTimelockType, VtxoScriptType, VtxoSpec};
+
+	use crate::{InsufficientFunds, SECP};
+	use super::*;
+
+	fn onboard_vtxo(key: &Keypair, tag: u8, expiry_height: u32, sat: u64) -> Vtxo {
+		Vtxo::Onboard {
+			base: BaseVtxo {
+				spec: VtxoSpec {
+					user_pubkey: key.public_key(),
+					asp_pubkey: key.public_key(),
+					expiry_height,
+					exit_delta: 12,
+					amount: Amount::from_sat(sat),
+					exit_timelock_type: ExitTimelockType::Relative,
+					script_type: VtxoScriptType::Taproot,
+				},
+				utxo: OutPoint::new(Txid::from_byte_array([tag; 32]), 0),
+			},
+			reveal_tx_signature: schnorr::Signature::from_slice(&[1; 64]).unwrap(),
+		}
+	}
+
+	fn cbor<T: serde::Serialize>(v: &T) -> Vec<u8> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(v, &mut buf).unwrap();
+		buf
+	}
+
+	/// Write some wallet state, reopen the db and check we read it back.
+	fn roundtrip(backend: StorageBackend) {
+		let datadir = std::env::temp_dir()
+			.join(format!("bark-db-test-{}-{}", backend, std::process::id()));
+		let _ = fs::remove_dir_all(&datadir);
+		fs::create_dir_all(&datadir).unwrap();
+
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let other_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let vtxo1 = onboard_vtxo(&key, 1, 250, 10_000);
+		let vtxo2 = onboard_vtxo(&key, 2, 100, 20_000);
+		let vtxo3 = onboard_vtxo(&key, 3, 200, 30_000);
+		let lost = onboard_vtxo(&key, 4, 200, 40_000);
+		let spent = onboard_vtxo(&key, 5, 200, 50_000);
+		let pending_round = PendingRound {
+			id: 1,
+			round_txid: Txid::from_byte_array([6; 32]),
+			inputs: vec![vtxo1.id()],
+			input_vtxos: vec![vtxo1.clone()],
+			attempts: Vec::new(),
+			label: Some("round".into()),
+		};
+		let pending_onboard = PendingOnboard {
+			tx: Transaction {
+				version: transaction::Version::TWO,
+				lock_time: absolute::LockTime::ZERO,
+				input: Vec::new(),
+				output: Vec::new(),
+			},
+			vtxos: vec![vtxo2.clone()],
+			label: None,
+		};
+
+		{
+			let db = open(&datadir, backend).unwrap();
+			for v in [&vtxo1, &vtxo2, &vtxo3, &lost, &spent] {
+				db.store_vtxo(v).unwrap();
+			}
+			db.mark_vtxo_lost(&lost).unwrap();
+			assert_eq!(db.remove_vtxo(spent.id()).unwrap().unwrap().id(), spent.id());
+			assert!(db.remove_vtxo(spent.id()).unwrap().is_none());
+			db.store_spent_vtxo(spent.id(), 150).unwrap();
+			db.store_exit(&Exit::default()).unwrap();
+			db.store_pending_round(&pending_round).unwrap();
+			db.store_pending_round(&PendingRound { id: 2, ..pending_round.clone() }).unwrap();
+			db.store_pending_onboard(&pending_onboard).unwrap();
+			db.store_last_ark_sync_height(1234).unwrap();
+			db.store_vtxo_labels(vtxo3.id(), &["a".into(), "b".into()]).unwrap();
+			db.store_vtxo_key_index(0, key.public_key()).unwrap();
+			db.store_vtxo_key_index(1, other_key.public_key()).unwrap();
+			assert_eq!(db.register_received_key(key.public_key()).unwrap(), 1);
+			assert_eq!(db.register_received_key(key.public_key()).unwrap(), 2);
+		}
+
+		let db = open(&datadir, backend).unwrap();
+		let mut ids = db.get_all_vtxos().unwrap().iter().map(|v| v.id()).collect::<Vec<_>>();
+		ids.sort();
+		let mut expected = vec![vtxo1.id(), vtxo2.id(), vtxo3.id()];
+		expected.sort();
+		assert_eq!(ids, expected);
+		assert_eq!(db.get_vtxo(vtxo3.id()).unwrap().unwrap().encode(), vtxo3.encode());
+		assert!(db.get_vtxo(lost.id()).unwrap().is_none());
+		assert_eq!(
+			db.get_lost_vtxos().unwrap().iter().map(|v| v.id()).collect::<Vec<_>>(),
+			vec![lost.id()],
+		);
+		assert!(db.has_spent_vtxo(spent.id()).unwrap());
+		assert!(!db.has_spent_vtxo(vtxo1.id()).unwrap());
+
+		let expiring = db.get_expiring_vtxos(Amount::from_sat(25_000)).unwrap();
+		assert_eq!(expiring.iter().map(|v| v.id()).collect::<Vec<_>>(), vec![vtxo2.id(), vtxo3.id()]);
+		let err = db.get_expiring_vtxos(Amount::from_sat(100_000)).unwrap_err();
+		assert_eq!(
+			err.downcast_ref::<InsufficientFunds>().unwrap().available,
+			Amount::from_sat(60_000),
+		);
+
+		assert_eq!(cbor(&db.fetch_exit().unwrap().unwrap()), cbor(&Exit::default()));
+		let mut pending = db.get_pending_rounds().unwrap();
+		pending.sort_by_key(|p| p.id);
+		assert_eq!(pending.iter().map(|p| p.id).collect::<Vec<_>>(), vec![1, 2]);
+		assert_eq!(cbor(&pending[0]), cbor(&pending_round));
+		assert_eq!(cbor(&db.fetch_pending_onboard().unwrap().unwrap()), cbor(&pending_onboard));
+		db.remove_pending_round(pending_round.id).unwrap();
+		db.clear_pending_onboard().unwrap();
+		let pending = db.get_pending_rounds().unwrap();
+		assert_eq!(pending.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2]);
+		assert!(db.fetch_pending_onboard().unwrap().is_none());
+		assert_eq!(db.get_last_ark_sync_height().unwrap(), 1234);
+
+		assert_eq!(db.get_vtxo_labels(vtxo3.id()).unwrap(), vec!["a".to_owned(), "b".to_owned()]);
+		assert!(db.get_vtxo_labels(vtxo1.id()).unwrap().is_empty());
+		assert_eq!(db.get_vtxo_key_index(other_key.public_key()).unwrap(), Some(1));
+		assert_eq!(db.next_vtxo_key_index().unwrap(), 2);
+		assert_eq!(db.get_received_key_count(key.public_key()).unwrap(), 2);
+		assert_eq!(db.get_received_key_count(other_key.public_key()).unwrap(), 0);
+
+		drop(db);
+		fs::remove_dir_all(&datadir).unwrap();
+	}
+
+	#[test]
+	fn roundtrip_sled() {
+		roundtrip(StorageBackend::Sled);
+	}
+
+	#[test]
+	fn roundtrip_sqlite() {
+		roundtrip(StorageBackend::Sqlite);
+	}
+
+	/// Check that we store the exit status with the vtxo and remove it with it.
+	fn exit_status(backend: StorageBackend) {
+		let datadir = std::env::temp_dir()
+			.join(format!("bark-db-exit-status-test-{}-{}", backend, std::process::id()));
+		let _ = fs::remove_dir_all(&datadir);
+		fs::create_dir_all(&datadir).unwrap();
+
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let vtxo = onboard_vtxo(&key, 1, 250, 10_000);
+		// A round vtxo that's missing the root of its exit branch.
+		let broken = Vtxo::Round {
+			base: match onboard_vtxo(&key, 2, 300, 60_000) {
+				Vtxo::Onboard { base, .. } => base,
+				_ => unreachable!(),
+			},
+			leaf_idx: 0,
+			exit_branch: vec![Transaction {
+				version: transaction::Version::TWO,
+				lock_time: absolute::LockTime::ZERO,
+				input: vec![bitcoin::TxIn {
+					previous_output: OutPoint::new(Txid::from_byte_array([3; 32]), 0),
+					..Default::default()
+				}],
+				output: Vec::new(),
+			}],
+		};
+
+		let db = open(&datadir, backend).unwrap();
+		db.store_vtxo(&vtxo).unwrap();
+		db.store_vtxo(&broken).unwrap();
+
+		let exitable = VtxoExitStatus { exitable: true, exit_ready_height: None };
+		assert_eq!(db.get_vtxo_exit_status(vtxo.id()).unwrap(), Some(exitable));
+		let status = db.get_vtxo_exit_status(broken.id()).unwrap().unwrap();
+		assert!(!status.exitable);
+		assert_eq!(status, VtxoExitStatus::of(&broken));
+
+		db.remove_vtxo(broken.id()).unwrap().unwrap();
+		assert!(db.get_vtxo_exit_status(broken.id()).unwrap().is_none());
+		assert_eq!(db.get_vtxo_exit_status(vtxo.id()).unwrap(), Some(exitable));
+
+		drop(db);
+		fs::remove_dir_all(&datadir).unwrap();
+	}
+
+	#[test]
+	fn exit_status_sled() {
+		exit_status(StorageBackend::Sled);
+	}
+
+	#[test]
+	fn exit_status_sqlite() {
+		exit_status(StorageBackend::Sqlite);
+	}
+
+	#[test]
+	fn storage_backend_from_str() {
+		for backend in [StorageBackend::Sled, StorageBackend::Sqlite] {
+			assert_eq!(backend.to_string().parse::<StorageBackend>().unwrap(), backend);
+		}
+		"postgres".parse::<StorageBackend>().unwrap_err();
+	}
+}
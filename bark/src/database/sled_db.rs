@@ -0,0 +1,293 @@
+
+//! The default storage backend, using sled.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use bitcoin::Amount;
+use bitcoin::secp256k1::PublicKey;
+use sled::transaction::{self as tx, Transactional};
+
+use ark::{Vtxo, VtxoId};
+use sled_utils::BucketTree;
+
+use crate::{InsufficientFunds, PendingOnboard, PendingRound};
+use crate::exit::Exit;
+use super::{Storage, VtxoExitStatus};
+
+// Trees
+
+const VTXO_TREE: &str = "bark_vtxos";
+const VTXO_EXPIRY_TREE: &str = "bark_vtxo_by_expiry";
+const SPENT_VTXO_TREE: &str = "bark_spent_vtxos";
+const LOST_VTXO_TREE: &str = "bark_lost_vtxos";
+/// pubkey -> derivation index of the vtxo keys we derived
+const VTXO_KEY_TREE: &str = "bark_vtxo_keys";
+/// vtxo id -> the labels the user gave the vtxo
+const VTXO_LABEL_TREE: &str = "bark_vtxo_labels";
+/// vtxo id -> exit status computed when the vtxo was stored
+const VTXO_EXIT_STATUS_TREE: &str = "bark_vtxo_exit_status";
+/// pubkey -> number of OOR vtxos we received on the pubkey
+const RECEIVED_KEY_TREE: &str = "bark_received_keys";
+/// pending round id -> the pending round
+const PENDING_ROUND_TREE: &str = "bark_pending_rounds";
+
+// Top-level entries
+
+const ONGOING_EXIT: &str = "exit";
+const PENDING_ONBOARD: &str = "pending_onboard";
+const LAST_ARK_SYNC_HEIGHT: &str = "last_round_sync_height";
+
+pub struct SledDb {
+	db: sled::Db,
+}
+
+impl SledDb {
+	pub fn open(path: &Path) -> anyhow::Result<SledDb> {
+		Ok(SledDb {
+			db: sled::open(path).context("failed to open db")?,
+		})
+	}
+}
+
+impl Storage for SledDb {
+
+	fn store_vtxo(&self, vtxo: &Vtxo) -> anyhow::Result<()> {
+		let vtxo_tree = self.db.open_tree(VTXO_TREE)?;
+		let expiry_tree = self.db.open_tree(VTXO_EXPIRY_TREE)?;
+		let status_tree = self.db.open_tree(VTXO_EXIT_STATUS_TREE)?;
+		let mut status = Vec::new();
+		ciborium::into_writer(&VtxoExitStatus::of(vtxo), &mut status).unwrap();
+		(&vtxo_tree, &expiry_tree, &status_tree).transaction(|(vtxo_tree, expiry_tree, status_tree)| {
+			vtxo_tree.insert(vtxo.id().to_ivec(), vtxo.encode())?;
+			BucketTree::new(expiry_tree)
+				.insert(vtxo.spec().expiry_height.to_le_bytes(), &vtxo.id())?;
+			status_tree.insert(vtxo.id().to_ivec(), status.clone())?;
+			Ok::<(), tx::ConflictableTransactionError>(())
+		})?;
+		Ok(())
+	}
+
+	fn get_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<Vtxo>> {
+		Ok(self
+			.db
+			.open_tree(VTXO_TREE)?
+			.get(id)?
+			.map(|b| Vtxo::decode(&b).expect("corrupt db: invalid vtxo")))
+	}
+
+	fn get_all_vtxos(&self) -> anyhow::Result<Vec<Vtxo>> {
+		self.db
+			.open_tree(VTXO_TREE)?
+			.iter()
+			.map(|v| {
+				let (_key, val) = v?;
+				Ok(Vtxo::decode(&val).expect("corrupt db: invalid vtxo"))
+			})
+			.collect()
+	}
+
+	fn get_expiring_vtxos(&self, min_value: Amount) -> anyhow::Result<Vec<Vtxo>> {
+		let mut ret = Vec::new();
+		let mut total_amount = Amount::ZERO;
+		for res in self.db.open_tree(VTXO_EXPIRY_TREE)?.iter().values() {
+			let vsb = res?;
+			let vs = ciborium::from_reader::<HashSet<VtxoId>, _>(&vsb[..])
+				.expect("corrupt db: invalid vtxo list");
+			for id in vs {
+				let vtxo = self.get_vtxo(id)?.expect("corrupt db: missing vtxo from expiry");
+				total_amount += vtxo.spec().amount;
+				ret.push(vtxo);
+				if total_amount >= min_value {
+					return Ok(ret);
+				}
+			}
+		}
+		bail!(InsufficientFunds { available: total_amount });
+	}
+
+	fn remove_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<Vtxo>> {
+		let vtxo_tree = self.db.open_tree(VTXO_TREE)?;
+		let expiry_tree = self.db.open_tree(VTXO_EXPIRY_TREE)?;
+		let status_tree = self.db.open_tree(VTXO_EXIT_STATUS_TREE)?;
+		Ok((&vtxo_tree, &expiry_tree, &status_tree).transaction(|(vtxo_tree, expiry_tree, status_tree)| {
+			if let Some(v) = vtxo_tree.remove(&id.to_ivec())? {
+				let ret = Vtxo::decode(&v).expect("corrupt db: invalid vtxo");
+				BucketTree::new(expiry_tree).remove(ret.spec().expiry_height.to_le_bytes(), &id)?;
+				status_tree.remove(&id.to_ivec())?;
+				Ok::<_, tx::ConflictableTransactionError>(Some(ret))
+			} else {
+				Ok(None)
+			}
+		})?)
+	}
+
+	fn mark_vtxo_lost(&self, vtxo: &Vtxo) -> anyhow::Result<()> {
+		let vtxo_tree = self.db.open_tree(VTXO_TREE)?;
+		let expiry_tree = self.db.open_tree(VTXO_EXPIRY_TREE)?;
+		let lost_tree = self.db.open_tree(LOST_VTXO_TREE)?;
+		let id = vtxo.id();
+		(&vtxo_tree, &expiry_tree, &lost_tree).transaction(|(vtxo_tree, expiry_tree, lost_tree)| {
+			vtxo_tree.remove(&id.to_ivec())?;
+			BucketTree::new(expiry_tree).remove(vtxo.spec().expiry_height.to_le_bytes(), &id)?;
+			lost_tree.insert(id.to_ivec(), vtxo.encode())?;
+			Ok::<(), tx::ConflictableTransactionError>(())
+		})?;
+		Ok(())
+	}
+
+	fn get_lost_vtxos(&self) -> anyhow::Result<Vec<Vtxo>> {
+		self.db
+			.open_tree(LOST_VTXO_TREE)?
+			.iter()
+			.map(|v| {
+				let (_key, val) = v?;
+				Ok(Vtxo::decode(&val).expect("corrupt db: invalid vtxo"))
+			})
+			.collect()
+	}
+
+	fn store_exit(&self, exit: &Exit) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(exit, &mut buf).unwrap();
+		self.db.insert(ONGOING_EXIT, buf)?;
+		Ok(())
+	}
+
+	fn fetch_exit(&self) -> anyhow::Result<Option<Exit>> {
+		Ok(self.db.get(ONGOING_EXIT)?.map(|b| {
+			ciborium::from_reader(&b[..]).expect("corrupt db: exit")
+		}))
+	}
+
+	fn store_pending_round(&self, round: &PendingRound) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(round, &mut buf).unwrap();
+		self.db.open_tree(PENDING_ROUND_TREE)?.insert(round.id.to_be_bytes(), buf)?;
+		Ok(())
+	}
+
+	fn get_pending_rounds(&self) -> anyhow::Result<Vec<PendingRound>> {
+		self.db
+			.open_tree(PENDING_ROUND_TREE)?
+			.iter()
+			.map(|v| {
+				let (_key, val) = v?;
+				Ok(ciborium::from_reader(&val[..]).expect("corrupt db: pending round"))
+			})
+			.collect()
+	}
+
+	fn remove_pending_round(&self, id: u64) -> anyhow::Result<()> {
+		self.db.open_tree(PENDING_ROUND_TREE)?.remove(id.to_be_bytes())?;
+		Ok(())
+	}
+
+	fn store_pending_onboard(&self, onboard: &PendingOnboard) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(onboard, &mut buf).unwrap();
+		self.db.insert(PENDING_ONBOARD, buf)?;
+		Ok(())
+	}
+
+	fn fetch_pending_onboard(&self) -> anyhow::Result<Option<PendingOnboard>> {
+		Ok(self.db.get(PENDING_ONBOARD)?.map(|b| {
+			ciborium::from_reader(&b[..]).expect("corrupt db: pending onboard")
+		}))
+	}
+
+	fn clear_pending_onboard(&self) -> anyhow::Result<()> {
+		self.db.remove(PENDING_ONBOARD)?;
+		Ok(())
+	}
+
+	fn get_last_ark_sync_height(&self) -> anyhow::Result<u32> {
+		if let Some(b) = self.db.get(LAST_ARK_SYNC_HEIGHT)? {
+			assert_eq!(4, b.len());
+			Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+		} else {
+			Ok(0)
+		}
+	}
+
+	fn store_last_ark_sync_height(&self, height: u32) -> anyhow::Result<()> {
+		self.db.insert(LAST_ARK_SYNC_HEIGHT, height.to_le_bytes().to_vec())?;
+		Ok(())
+	}
+
+	fn store_spent_vtxo(&self, id: VtxoId, height: u32) -> anyhow::Result<()> {
+		self.db.open_tree(SPENT_VTXO_TREE)?.insert(id, height.to_le_bytes().to_vec())?;
+		Ok(())
+	}
+
+	fn has_spent_vtxo(&self, id: VtxoId) -> anyhow::Result<bool> {
+		Ok(self.db.open_tree(SPENT_VTXO_TREE)?.get(id)?.is_some())
+	}
+	//TODO(stevenroose) regularly prune spent vtxos based on height
+
+	fn store_vtxo_labels(&self, id: VtxoId, labels: &[String]) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(labels, &mut buf).unwrap();
+		self.db.open_tree(VTXO_LABEL_TREE)?.insert(id.to_ivec(), buf)?;
+		Ok(())
+	}
+
+	fn get_vtxo_labels(&self, id: VtxoId) -> anyhow::Result<Vec<String>> {
+		Ok(match self.db.open_tree(VTXO_LABEL_TREE)?.get(id)? {
+			Some(b) => ciborium::from_reader(&b[..]).expect("corrupt db: invalid vtxo labels"),
+			None => Vec::new(),
+		})
+	}
+
+	fn get_vtxo_exit_status(&self, id: VtxoId) -> anyhow::Result<Option<VtxoExitStatus>> {
+		Ok(self.db.open_tree(VTXO_EXIT_STATUS_TREE)?.get(id)?.map(|b| {
+			ciborium::from_reader(&b[..]).expect("corrupt db: invalid vtxo exit status")
+		}))
+	}
+
+	fn store_vtxo_exit_status(&self, id: VtxoId, status: &VtxoExitStatus) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(status, &mut buf).unwrap();
+		self.db.open_tree(VTXO_EXIT_STATUS_TREE)?.insert(id.to_ivec(), buf)?;
+		Ok(())
+	}
+
+	fn store_vtxo_key_index(&self, idx: u32, pubkey: PublicKey) -> anyhow::Result<()> {
+		self.db.open_tree(VTXO_KEY_TREE)?.insert(pubkey.serialize(), idx.to_le_bytes().to_vec())?;
+		Ok(())
+	}
+
+	fn get_vtxo_key_index(&self, pubkey: PublicKey) -> anyhow::Result<Option<u32>> {
+		Ok(self.db.open_tree(VTXO_KEY_TREE)?.get(pubkey.serialize())?.map(|b| {
+			u32::from_le_bytes(b[..].try_into().expect("corrupt db: invalid vtxo key index"))
+		}))
+	}
+
+	fn register_received_key(&self, pubkey: PublicKey) -> anyhow::Result<u32> {
+		let tree = self.db.open_tree(RECEIVED_KEY_TREE)?;
+		let count = self.get_received_key_count(pubkey)? + 1;
+		tree.insert(pubkey.serialize(), count.to_le_bytes().to_vec())?;
+		Ok(count)
+	}
+
+	fn get_received_key_count(&self, pubkey: PublicKey) -> anyhow::Result<u32> {
+		Ok(self.db.open_tree(RECEIVED_KEY_TREE)?.get(pubkey.serialize())?.map(|b| {
+			u32::from_le_bytes(b[..].try_into().expect("corrupt db: invalid received key count"))
+		}).unwrap_or(0))
+	}
+
+	fn next_vtxo_key_index(&self) -> anyhow::Result<u32> {
+		Ok(self.db.open_tree(VTXO_KEY_TREE)?.len() as u32)
+	}
+}
+
+trait ToIVec {
+	fn to_ivec(&self) -> sled::IVec;
+}
+
+impl<T: AsRef<[u8]>> ToIVec for T {
+	fn to_ivec(&self) -> sled::IVec {
+		self.as_ref().into()
+	}
+}
@@ -0,0 +1,341 @@
+
+//! A storage backend using sqlite.
+//!
+//! The database is opened in WAL mode, so other tools can read it while
+//! the wallet is running.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use bitcoin::Amount;
+use bitcoin::secp256k1::PublicKey;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use ark::{Vtxo, VtxoId};
+
+use crate::{InsufficientFunds, PendingOnboard, PendingRound};
+use crate::exit::Exit;
+use super::{Storage, VtxoExitStatus};
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS vtxos (
+		id BLOB PRIMARY KEY,
+		expiry_height INTEGER NOT NULL,
+		data BLOB NOT NULL
+	);
+	CREATE INDEX IF NOT EXISTS vtxos_by_expiry ON vtxos (expiry_height);
+	CREATE TABLE IF NOT EXISTS lost_vtxos (
+		id BLOB PRIMARY KEY,
+		data BLOB NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS spent_vtxos (
+		id BLOB PRIMARY KEY,
+		height INTEGER NOT NULL
+	);
+	-- pubkey -> derivation index of the vtxo keys we derived
+	CREATE TABLE IF NOT EXISTS vtxo_keys (
+		pubkey BLOB PRIMARY KEY,
+		idx INTEGER NOT NULL
+	);
+	-- vtxo id -> the labels the user gave the vtxo
+	CREATE TABLE IF NOT EXISTS vtxo_labels (
+		id BLOB PRIMARY KEY,
+		labels BLOB NOT NULL
+	);
+	-- vtxo id -> exit status computed when the vtxo was stored
+	CREATE TABLE IF NOT EXISTS vtxo_exit_status (
+		id BLOB PRIMARY KEY,
+		status BLOB NOT NULL
+	);
+	-- pubkey -> number of OOR vtxos we received on the pubkey
+	CREATE TABLE IF NOT EXISTS received_keys (
+		pubkey BLOB PRIMARY KEY,
+		count INTEGER NOT NULL
+	);
+	-- pending round id -> the pending round
+	CREATE TABLE IF NOT EXISTS pending_rounds (
+		id BLOB PRIMARY KEY,
+		data BLOB NOT NULL
+	);
+	-- top-level entries
+	CREATE TABLE IF NOT EXISTS entries (
+		key TEXT PRIMARY KEY,
+		value BLOB NOT NULL
+	);
+";
+
+// Top-level entries
+
+const ONGOING_EXIT: &str = "exit";
+const PENDING_ONBOARD: &str = "pending_onboard";
+const LAST_ARK_SYNC_HEIGHT: &str = "last_round_sync_height";
+
+pub struct SqliteDb {
+	conn: Mutex<Connection>,
+}
+
+impl SqliteDb {
+	pub fn open(path: &Path) -> anyhow::Result<SqliteDb> {
+		let conn = Connection::open(path).context("failed to open db")?;
+		conn.pragma_update(None, "journal_mode", "WAL").context("failed to enable WAL mode")?;
+		conn.execute_batch(SCHEMA).context("failed to create db schema")?;
+		Ok(SqliteDb { conn: Mutex::new(conn) })
+	}
+
+	fn get_entry(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+		Ok(self.conn.lock().unwrap().query_row(
+			"SELECT value FROM entries WHERE key = ?1", params![key], |r| r.get(0),
+		).optional()?)
+	}
+
+	fn store_entry(&self, key: &str, value: &[u8]) -> anyhow::Result<()> {
+		self.conn.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO entries (key, value) VALUES (?1, ?2)", params![key, value],
+		)?;
+		Ok(())
+	}
+
+	fn remove_entry(&self, key: &str) -> anyhow::Result<()> {
+		self.conn.lock().unwrap().execute("DELETE FROM entries WHERE key = ?1", params![key])?;
+		Ok(())
+	}
+
+	fn query_vtxos(&self, sql: &str) -> anyhow::Result<Vec<Vtxo>> {
+		let conn = self.conn.lock().unwrap();
+		let mut stmt = conn.prepare(sql)?;
+		let rows = stmt.query_map([], |r| r.get::<_, Vec<u8>>(0))?;
+		rows.map(|b| Ok(Vtxo::decode(&b?).expect("corrupt db: invalid vtxo"))).collect()
+	}
+}
+
+impl Storage for SqliteDb {
+	fn store_vtxo(&self, vtxo: &Vtxo) -> anyhow::Result<()> {
+		let mut conn = self.conn.lock().unwrap();
+		let tx = conn.transaction()?;
+		tx.execute(
+			"INSERT OR REPLACE INTO vtxos (id, expiry_height, data) VALUES (?1, ?2, ?3)",
+			params![vtxo.id().as_ref(), vtxo.spec().expiry_height, vtxo.encode()],
+		)?;
+		let mut status = Vec::new();
+		ciborium::into_writer(&VtxoExitStatus::of(vtxo), &mut status).unwrap();
+		tx.execute(
+			"INSERT OR REPLACE INTO vtxo_exit_status (id, status) VALUES (?1, ?2)",
+			params![vtxo.id().as_ref(), status],
+		)?;
+		tx.commit()?;
+		Ok(())
+	}
+
+	fn get_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<Vtxo>> {
+		let data = self.conn.lock().unwrap().query_row(
+			"SELECT data FROM vtxos WHERE id = ?1", params![id.as_ref()], |r| r.get::<_, Vec<u8>>(0),
+		).optional()?;
+		Ok(data.map(|b| Vtxo::decode(&b).expect("corrupt db: invalid vtxo")))
+	}
+
+	fn get_all_vtxos(&self) -> anyhow::Result<Vec<Vtxo>> {
+		self.query_vtxos("SELECT data FROM vtxos")
+	}
+
+	fn get_expiring_vtxos(&self, min_value: Amount) -> anyhow::Result<Vec<Vtxo>> {
+		let mut ret = Vec::new();
+		let mut total_amount = Amount::ZERO;
+		for vtxo in self.query_vtxos("SELECT data FROM vtxos ORDER BY expiry_height")? {
+			total_amount += vtxo.spec().amount;
+			ret.push(vtxo);
+			if total_amount >= min_value {
+				return Ok(ret);
+			}
+		}
+		bail!(InsufficientFunds { available: total_amount });
+	}
+
+	fn remove_vtxo(&self, id: VtxoId) -> anyhow::Result<Option<Vtxo>> {
+		let mut conn = self.conn.lock().unwrap();
+		let tx = conn.transaction()?;
+		let data = tx.query_row(
+			"DELETE FROM vtxos WHERE id = ?1 RETURNING data", params![id.as_ref()],
+			|r| r.get::<_, Vec<u8>>(0),
+		).optional()?;
+		tx.execute("DELETE FROM vtxo_exit_status WHERE id = ?1", params![id.as_ref()])?;
+		tx.commit()?;
+		Ok(data.map(|b| Vtxo::decode(&b).expect("corrupt db: invalid vtxo")))
+	}
+
+	fn mark_vtxo_lost(&self, vtxo: &Vtxo) -> anyhow::Result<()> {
+		let mut conn = self.conn.lock().unwrap();
+		let tx = conn.transaction()?;
+		let id = vtxo.id();
+		tx.execute("DELETE FROM vtxos WHERE id = ?1", params![id.as_ref()])?;
+		tx.execute(
+			"INSERT OR REPLACE INTO lost_vtxos (id, data) VALUES (?1, ?2)",
+			params![id.as_ref(), vtxo.encode()],
+		)?;
+		tx.commit()?;
+		Ok(())
+	}
+
+	fn get_lost_vtxos(&self) -> anyhow::Result<Vec<Vtxo>> {
+		self.query_vtxos("SELECT data FROM lost_vtxos")
+	}
+
+	fn store_exit(&self, exit: &Exit) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(exit, &mut buf).unwrap();
+		self.store_entry(ONGOING_EXIT, &buf)
+	}
+
+	fn fetch_exit(&self) -> anyhow::Result<Option<Exit>> {
+		Ok(self.get_entry(ONGOING_EXIT)?.map(|b| {
+			ciborium::from_reader(&b[..]).expect("corrupt db: exit")
+		}))
+	}
+
+	fn store_pending_round(&self, round: &PendingRound) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(round, &mut buf).unwrap();
+		self.conn.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO pending_rounds (id, data) VALUES (?1, ?2)",
+			params![&round.id.to_be_bytes()[..], buf],
+		)?;
+		Ok(())
+	}
+
+	fn get_pending_rounds(&self) -> anyhow::Result<Vec<PendingRound>> {
+		let conn = self.conn.lock().unwrap();
+		let mut stmt = conn.prepare("SELECT data FROM pending_rounds")?;
+		let rows = stmt.query_map([], |r| r.get::<_, Vec<u8>>(0))?;
+		rows.map(|r| {
+			Ok(ciborium::from_reader(&r?[..]).expect("corrupt db: pending round"))
+		}).collect()
+	}
+
+	fn remove_pending_round(&self, id: u64) -> anyhow::Result<()> {
+		self.conn.lock().unwrap().execute(
+			"DELETE FROM pending_rounds WHERE id = ?1", params![&id.to_be_bytes()[..]],
+		)?;
+		Ok(())
+	}
+
+	fn store_pending_onboard(&self, onboard: &PendingOnboard) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(onboard, &mut buf).unwrap();
+		self.store_entry(PENDING_ONBOARD, &buf)
+	}
+
+	fn fetch_pending_onboard(&self) -> anyhow::Result<Option<PendingOnboard>> {
+		Ok(self.get_entry(PENDING_ONBOARD)?.map(|b| {
+			ciborium::from_reader(&b[..]).expect("corrupt db: pending onboard")
+		}))
+	}
+
+	fn clear_pending_onboard(&self) -> anyhow::Result<()> {
+		self.remove_entry(PENDING_ONBOARD)
+	}
+
+	fn get_last_ark_sync_height(&self) -> anyhow::Result<u32> {
+		if let Some(b) = self.get_entry(LAST_ARK_SYNC_HEIGHT)? {
+			Ok(u32::from_le_bytes(b[..].try_into().expect("corrupt db: invalid sync height")))
+		} else {
+			Ok(0)
+		}
+	}
+
+	fn store_last_ark_sync_height(&self, height: u32) -> anyhow::Result<()> {
+		self.store_entry(LAST_ARK_SYNC_HEIGHT, &height.to_le_bytes())
+	}
+
+	fn store_spent_vtxo(&self, id: VtxoId, height: u32) -> anyhow::Result<()> {
+		self.conn.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO spent_vtxos (id, height) VALUES (?1, ?2)",
+			params![id.as_ref(), height],
+		)?;
+		Ok(())
+	}
+
+	fn has_spent_vtxo(&self, id: VtxoId) -> anyhow::Result<bool> {
+		Ok(self.conn.lock().unwrap().query_row(
+			"SELECT 1 FROM spent_vtxos WHERE id = ?1", params![id.as_ref()], |_| Ok(()),
+		).optional()?.is_some())
+	}
+
+	fn store_vtxo_labels(&self, id: VtxoId, labels: &[String]) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(labels, &mut buf).unwrap();
+		self.conn.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO vtxo_labels (id, labels) VALUES (?1, ?2)",
+			params![id.as_ref(), buf],
+		)?;
+		Ok(())
+	}
+
+	fn get_vtxo_labels(&self, id: VtxoId) -> anyhow::Result<Vec<String>> {
+		let labels = self.conn.lock().unwrap().query_row(
+			"SELECT labels FROM vtxo_labels WHERE id = ?1", params![id.as_ref()],
+			|r| r.get::<_, Vec<u8>>(0),
+		).optional()?;
+		Ok(match labels {
+			Some(b) => ciborium::from_reader(&b[..]).expect("corrupt db: invalid vtxo labels"),
+			None => Vec::new(),
+		})
+	}
+
+	fn get_vtxo_exit_status(&self, id: VtxoId) -> anyhow::Result<Option<VtxoExitStatus>> {
+		let status = self.conn.lock().unwrap().query_row(
+			"SELECT status FROM vtxo_exit_status WHERE id = ?1", params![id.as_ref()],
+			|r| r.get::<_, Vec<u8>>(0),
+		).optional()?;
+		Ok(status.map(|b| {
+			ciborium::from_reader(&b[..]).expect("corrupt db: invalid vtxo exit status")
+		}))
+	}
+
+	fn store_vtxo_exit_status(&self, id: VtxoId, status: &VtxoExitStatus) -> anyhow::Result<()> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(status, &mut buf).unwrap();
+		self.conn.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO vtxo_exit_status (id, status) VALUES (?1, ?2)",
+			params![id.as_ref(), buf],
+		)?;
+		Ok(())
+	}
+
+	fn store_vtxo_key_index(&self, idx: u32, pubkey: PublicKey) -> anyhow::Result<()> {
+		self.conn.lock().unwrap().execute(
+			"INSERT OR REPLACE INTO vtxo_keys (pubkey, idx) VALUES (?1, ?2)",
+			params![&pubkey.serialize()[..], idx],
+		)?;
+		Ok(())
+	}
+
+	fn get_vtxo_key_index(&self, pubkey: PublicKey) -> anyhow::Result<Option<u32>> {
+		Ok(self.conn.lock().unwrap().query_row(
+			"SELECT idx FROM vtxo_keys WHERE pubkey = ?1", params![&pubkey.serialize()[..]],
+			|r| r.get(0),
+		).optional()?)
+	}
+
+	fn register_received_key(&self, pubkey: PublicKey) -> anyhow::Result<u32> {
+		Ok(self.conn.lock().unwrap().query_row(
+			"INSERT INTO received_keys (pubkey, count) VALUES (?1, 1) \
+				ON CONFLICT (pubkey) DO UPDATE SET count = count + 1 \
+				RETURNING count",
+			params![&pubkey.serialize()[..]],
+			|r| r.get(0),
+		)?)
+	}
+
+	fn get_received_key_count(&self, pubkey: PublicKey) -> anyhow::Result<u32> {
+		Ok(self.conn.lock().unwrap().query_row(
+			"SELECT count FROM received_keys WHERE pubkey = ?1", params![&pubkey.serialize()[..]],
+			|r| r.get(0),
+		).optional()?.unwrap_or(0))
+	}
+
+	fn next_vtxo_key_index(&self) -> anyhow::Result<u32> {
+		Ok(self.conn.lock().unwrap().query_row(
+			"SELECT COUNT(*) FROM vtxo_keys", [], |r| r.get(0),
+		)?)
+	}
+}
@@ -1,20 +1,25 @@
 
 use std::{cmp, io};
 use std::collections::HashMap;
+use std::path::Path;
 
 use anyhow::Context;
-use bitcoin::{sighash, Amount, OutPoint, Transaction, Txid};
+use bitcoin::{sighash, Amount, FeeRate, OutPoint, Transaction, Txid};
 
-use ark::{Vtxo, VtxoSpec};
+use ark::{Vtxo, VtxoId, VtxoSpec};
+use ark::util::TransactionExt;
 
-use crate::{SECP, Wallet};
+use crate::{onchain, SECP, Wallet};
+use crate::onchain::MempoolAcceptance;
 use crate::psbtext::PsbtInputExt;
 
 
 
-const VTXO_CLAIM_INPUT_WEIGHT: usize = 138;
+/// The size of the control block of the exit clause, which is the only leaf.
+const VTXO_CLAIM_CONTROL_BLOCK_SIZE: usize = 33;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// An input of an exit claim tx, spending the output of an exited vtxo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClaimInput {
 	pub utxo: OutPoint,
 	//TODO(stevenroose) check how this is used because for OOR a pseudo spec is stored hre
@@ -33,8 +38,11 @@ impl ClaimInput {
 	}
 
 	pub fn satisfaction_weight(&self) -> bitcoin::Weight {
-		// NB might be vtxo-dependent in the future.
-		bitcoin::Weight::from_wu(VTXO_CLAIM_INPUT_WEIGHT as u64)
+		// The exit script size depends on the size of its timelock.
+		let script_len = self.spec.exit_clause().len();
+		// nb witness items, signature, exit script and control block
+		let weight = 1 + (1 + 64) + (1 + script_len) + (1 + VTXO_CLAIM_CONTROL_BLOCK_SIZE);
+		bitcoin::Weight::from_wu(weight as u64)
 	}
 }
 
@@ -100,6 +108,37 @@ impl Exit {
 		self.vtxos.iter().map(|v| &v.vtxo)
 	}
 
+	/// The exits of the given vtxos, or all exits if [None].
+	fn selected<'a>(&'a self, only: Option<&'a [VtxoId]>) -> impl Iterator<Item = &'a VtxoExit> {
+		self.vtxos.iter().filter(move |v| only.map(|o| o.contains(&v.vtxo.id())).unwrap_or(true))
+	}
+
+	/// Check that all the given vtxos are being exited.
+	fn check_selected(&self, ids: &[VtxoId]) -> anyhow::Result<()> {
+		for id in ids {
+			ensure!(self.vtxos.iter().any(|v| v.vtxo.id() == *id), "vtxo {} is not being exited", id);
+		}
+		Ok(())
+	}
+
+	/// The height at which all selected exits can be claimed.
+	///
+	/// Returns [None] if not all exit txs are confirmed yet.
+	fn claimable_height(&self, only: Option<&[VtxoId]>) -> Option<u32> {
+		// nb we wait until we can sweep all of them
+		let mut highest_height = 0;
+		for vtxo in self.selected(only) {
+			let status = vtxo.exit_tx_status.get(&vtxo.vtxo.vtxo_tx().compute_txid());
+			if let Some(ExitTxStatus::ConfirmedIn(h)) = status {
+				let height = vtxo.vtxo.spec().exit_timelock().claimable_height(*h);
+				highest_height = cmp::max(highest_height, height);
+			} else {
+				return None;
+			}
+		}
+		Some(highest_height)
+	}
+
 	pub fn total_pending_amount(&self) -> Amount {
 		self.vtxos.iter().map(|v| v.vtxo.spec().amount).sum()
 	}
@@ -107,8 +146,10 @@ impl Exit {
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ExitStatus {
-	/// All txs were broadcast and we claimed all exits.
+	/// There are no pending exits.
 	Done,
+	/// All txs were broadcast and we claimed all exits in the given claim tx.
+	Claimed(Txid),
 	/// Not all txs were able to be broadcast and confirmed.
 	NeedMoreTxs,
 	/// All txs are broadcast and confirmed, but we need more confirmations.
@@ -122,9 +163,28 @@ impl Wallet {
 		if let Err(e) = self.sync_ark().await {
 			warn!("Failed to sync incoming Ark payments, still doing exit: {}", e);
 		}
-		let current_height = self.onchain.tip().await?;
-
 		let vtxos = self.db.get_all_vtxos()?;
+		self.start_exit_for_vtxos(vtxos).await
+	}
+
+	/// Add only the given vtxos to the exit process.
+	///
+	/// The other vtxos stay in the wallet and can still be refreshed or
+	/// spent, even if they share part of their tree branch with the exited
+	/// vtxos.
+	pub async fn start_exit_selected(&mut self, ids: &[VtxoId]) -> anyhow::Result<()> {
+		self.onchain.sync().await.context("onchain sync error")?;
+		if let Err(e) = self.sync_ark().await {
+			warn!("Failed to sync incoming Ark payments, still doing exit: {}", e);
+		}
+		let vtxos = ids.iter().map(|id| {
+			self.db.get_vtxo(*id)?.with_context(|| format!("vtxo {} not found", id))
+		}).collect::<anyhow::Result<Vec<_>>>()?;
+		self.start_exit_for_vtxos(vtxos).await
+	}
+
+	async fn start_exit_for_vtxos(&mut self, vtxos: Vec<Vtxo>) -> anyhow::Result<()> {
+		let current_height = self.onchain.tip().await?;
 		let ids = vtxos.iter().map(|v| v.id()).collect::<Vec<_>>();
 
 		// The idea is to convert all our vtxos into an exit process structure,
@@ -145,6 +205,64 @@ impl Wallet {
 		Ok(())
 	}
 
+	/// The signed txs that bring the given vtxo onchain, in broadcast order.
+	///
+	/// These are the tree branch txs down to the vtxo's leaf, followed by
+	/// any OOR txs the vtxo builds on.
+	pub fn export_tree_txs(&self, id: VtxoId) -> anyhow::Result<Vec<Transaction>> {
+		let vtxo = self.db.get_vtxo(id)?.with_context(|| format!("vtxo {} not found", id))?;
+		let mut txs = Vec::new();
+		vtxo.collect_exit_txs(&mut txs);
+		Ok(txs)
+	}
+
+	/// Broadcast the given txs in order through the chain source of the wallet.
+	///
+	/// This doesn't need the ASP, so it can be used when the ASP is gone.
+	/// Txs with a fee anchor are broadcast in a package with a CPFP from our
+	/// onchain wallet, paying the given fee rate or our urgent fee rate.
+	/// Txs that are already confirmed or in the mempool are skipped. Returns
+	/// the txids that were broadcast.
+	pub async fn broadcast_tree_txs(
+		datadir: &Path,
+		txs: &[Transaction],
+		fee_rate: Option<FeeRate>,
+	) -> anyhow::Result<Vec<Txid>> {
+		let config = Self::read_config(datadir)?;
+		let seed = Self::read_mnemonic(datadir)?.to_seed("");
+		let mut onchain = onchain::Wallet::create(
+			config.network, seed, datadir, Self::chain_source(&config)?, config.conf_target,
+		).context("failed to create onchain wallet")?;
+		let fee_rate = match fee_rate {
+			Some(r) => r,
+			None => onchain.fee_rate().await,
+		};
+
+		let mut ret = Vec::with_capacity(txs.len());
+		for (i, tx) in txs.iter().enumerate() {
+			let txid = tx.compute_txid();
+			if let Ok(Some(height)) = onchain.tx_confirmed(txid).await {
+				debug!("Tree tx {} is already confirmed at height {}", txid, height);
+				continue;
+			}
+			if let Ok(true) = onchain.tx_in_mempool(txid).await {
+				debug!("Tree tx {} is already in the mempool", txid);
+				continue;
+			}
+			let ctx = || format!("failed to broadcast tree tx {} ({} of {})", txid, i + 1, txs.len());
+			if tx.fee_anchor().is_some() {
+				let cpfp = onchain.make_cpfp(&[tx], fee_rate).await.with_context(ctx)?;
+				onchain.broadcast_package(&[tx, &cpfp]).await.with_context(ctx)?;
+				info!("Broadcast tree tx {} with CPFP tx {}", txid, cpfp.compute_txid());
+			} else {
+				onchain.broadcast_tx(tx).await.with_context(ctx)?;
+				info!("Broadcast tree tx {}", txid);
+			}
+			ret.push(txid);
+		}
+		Ok(ret)
+	}
+
 	/// Get the pending exit tracking struct.
 	//TODO(stevenroose) consider not exposing this and only expose a overview struct
 	pub fn get_exit(&self) -> anyhow::Result<Option<Exit>> {
@@ -152,12 +270,50 @@ impl Wallet {
 	}
 
 	/// Progress a unilateral exit progress.
-	pub async fn progress_exit(&mut self) -> anyhow::Result<ExitStatus> {
+	///
+	/// The exit txs and the claim tx pay the given fee rate, or our urgent
+	/// fee rate if none is given.
+	pub async fn progress_exit(&mut self, fee_rate: Option<FeeRate>) -> anyhow::Result<ExitStatus> {
+		self.progress_exit_inner(fee_rate, None).await
+	}
+
+	/// Progress a unilateral exit progress, but only claim the given vtxos.
+	///
+	/// The exits of the other vtxos still make progress, but they stay in
+	/// the exit process until they are claimed later.
+	pub async fn progress_exit_selected(
+		&mut self,
+		fee_rate: Option<FeeRate>,
+		ids: &[VtxoId],
+	) -> anyhow::Result<ExitStatus> {
+		self.progress_exit_inner(fee_rate, Some(ids)).await
+	}
+
+	async fn progress_exit_inner(
+		&mut self,
+		fee_rate: Option<FeeRate>,
+		only: Option<&[VtxoId]>,
+	) -> anyhow::Result<ExitStatus> {
+		let fee_rate = match fee_rate {
+			Some(r) => r,
+			None => self.onchain.fee_rate().await,
+		};
 		self.onchain.sync().await.context("onchain sync error")?;
 		let mut exit = self.db.fetch_exit()?.unwrap_or_default();
 		if exit.is_empty() {
 			return Ok(ExitStatus::Done);
 		}
+		if let Some(ids) = only {
+			exit.check_selected(ids)?;
+		}
+
+		// Vtxos from the same tree share the txs of their common branch, we
+		// should only broadcast and CPFP each of those once.
+		let mut broadcast = exit.vtxos.iter()
+			.flat_map(|v| v.exit_tx_status.iter())
+			.filter(|(_, s)| matches!(s, ExitTxStatus::BroadcastWithCpfp(_)))
+			.map(|(txid, s)| (*txid, s.clone()))
+			.collect::<HashMap<_, _>>();
 
 		// Go over each tx and see if we can make progress on it.
 		//
@@ -165,6 +321,11 @@ impl Wallet {
 		for vtxo in exit.vtxos.iter_mut() {
 			'tx: for tx in vtxo.exit_txs() {
 				let txid = tx.compute_txid();
+				if !vtxo.exit_tx_status.contains_key(&txid) {
+					if let Some(status) = broadcast.get(&txid) {
+						vtxo.exit_tx_status.insert(txid, status.clone());
+					}
+				}
 				match vtxo.exit_tx_status.get(&txid) {
 					Some(ExitTxStatus::ConfirmedIn(_)) => {}, // nothing to do
 					Some(ExitTxStatus::BroadcastWithCpfp(_tx)) => {
@@ -193,7 +354,7 @@ impl Wallet {
 						}
 
 						// Ok let's confirm this bastard.
-						let cpfp = self.onchain.make_cpfp(&[&tx]).await?;
+						let cpfp = self.onchain.make_cpfp(&[&tx], fee_rate).await?;
 						if let Err(e) = self.onchain.broadcast_tx(&tx).await {
 							warn!("Error broadcasting an exit tx, \
 								hopefully means it already got broadcast before: {}", e);
@@ -204,7 +365,9 @@ impl Wallet {
 						} else {
 							info!("Broadcast CPFP tx {} to confirm tx {}", cpfp.compute_txid(), txid);
 						}
-						vtxo.exit_tx_status.insert(txid, ExitTxStatus::BroadcastWithCpfp(cpfp));
+						let status = ExitTxStatus::BroadcastWithCpfp(cpfp);
+						broadcast.insert(txid, status.clone());
+						vtxo.exit_tx_status.insert(txid, status);
 					},
 				}
 			}
@@ -213,60 +376,94 @@ impl Wallet {
 		// Save the updated exit state.
 		self.db.store_exit(&exit)?;
 
-		// nb we wait until we can sweep all of them
-		let mut all_confirmed = true;
-		let mut highest_height = 0;
-		for vtxo in exit.vtxos.iter_mut() {
-			let status = vtxo.exit_tx_status.get(&vtxo.vtxo.vtxo_tx().compute_txid());
-			if let Some(ExitTxStatus::ConfirmedIn(h)) = status {
-				let height = h + vtxo.vtxo.spec().exit_delta as u32;
-				highest_height = cmp::max(highest_height, height);
-			} else {
-				all_confirmed = false;
-				break;
-			}
-		}
-		let ret = if all_confirmed {
-			let current_height = self.onchain.tip().await?;
-			if highest_height <= current_height {
-				let inputs = exit.vtxos.iter().map(|vtxo| {
-					vtxo.claim()
-				}).collect::<Vec<_>>();
-
-				let total_amount = inputs.iter().map(|i| i.spec.amount).sum::<Amount>();
-				debug!("Claiming the following exits with total value of {}: {:?}",
-					total_amount, inputs.iter().map(|i| i.utxo.to_string()).collect::<Vec<_>>(),
-				);
-
-				let mut psbt = self.onchain.create_exit_claim_tx(&inputs).await?;
-
-				// Sign all the claim inputs.
-				let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
-				let prevouts = psbt.inputs.iter()
-					.map(|i| i.witness_utxo.clone().unwrap())
-					.collect::<Vec<_>>();
-				let prevouts = sighash::Prevouts::All(&prevouts);
-				let mut shc = sighash::SighashCache::new(&psbt.unsigned_tx);
-				for (i, input) in psbt.inputs.iter_mut().enumerate() {
-					input.try_sign_claim_input(&SECP, &mut shc, &prevouts, i, &vtxo_key);
-				}
+		let ret = match exit.claimable_height(only) {
+			Some(height) if height <= self.onchain.tip().await? => {
+				let tx = self.build_exit_claim_tx(&exit, only, fee_rate).await?;
 
-				// Then sign the wallet's funding inputs.
-				let tx = self.onchain.finish_tx(psbt).context("finishing claim psbt")?;
+				// Don't waste the claim on a tx the mempool won't take.
+				match self.onchain.test_mempool_accept(&tx).await {
+					Ok(Some(res)) if !res.allowed => {
+						bail!("claim tx {} would be rejected by the mempool: {}",
+							res.txid, res.reject_reason.as_deref().unwrap_or("unknown reason"),
+						);
+					},
+					Ok(_) => {},
+					Err(e) => warn!("Failed to test mempool acceptance of claim tx: {}", e),
+				}
 				if let Err(e) = self.onchain.broadcast_tx(&tx).await {
 					bail!("Error broadcasting claim tx: {}", e);
 				}
 
-				// Remove the exit record from the db.
-				self.db.store_exit(&Exit::default())?;
+				// Remove the claimed exits from the db.
+				match only {
+					Some(ids) => exit.vtxos.retain(|v| !ids.contains(&v.vtxo.id())),
+					None => exit = Exit::default(),
+				}
+				self.db.store_exit(&exit)?;
 
-				ExitStatus::Done
-			} else {
-				ExitStatus::WaitingForHeight(highest_height)
-			}
-		} else {
-			ExitStatus::NeedMoreTxs
+				ExitStatus::Claimed(tx.compute_txid())
+			},
+			Some(height) => ExitStatus::WaitingForHeight(height),
+			None => ExitStatus::NeedMoreTxs,
 		};
 		Ok(ret)
 	}
+
+	/// Check whether the claim tx of our exits would be accepted by the mempool.
+	///
+	/// This builds and signs the claim tx like [Wallet::progress_exit] would,
+	/// but doesn't broadcast anything. Fails if the exits are not claimable yet
+	/// or if the chain source can't test mempool acceptance.
+	pub async fn verify_exit(&mut self, fee_rate: Option<FeeRate>) -> anyhow::Result<MempoolAcceptance> {
+		let fee_rate = match fee_rate {
+			Some(r) => r,
+			None => self.onchain.fee_rate().await,
+		};
+		let exit = self.db.fetch_exit()?.unwrap_or_default();
+		ensure!(!exit.is_empty(), "there are no pending exits");
+		let height = exit.claimable_height(None)
+			.context("not all exit txs are confirmed yet, progress the exit first")?;
+		let tip = self.onchain.tip().await?;
+		ensure!(height <= tip, "exits are only claimable at block height {}", height);
+
+		let tx = self.build_exit_claim_tx(&exit, None, fee_rate).await?;
+		self.onchain.test_mempool_accept(&tx).await?
+			.context("the chain source can't test mempool acceptance")
+	}
+
+	/// Build and sign the tx claiming all selected exits.
+	async fn build_exit_claim_tx(
+		&mut self,
+		exit: &Exit,
+		only: Option<&[VtxoId]>,
+		fee_rate: FeeRate,
+	) -> anyhow::Result<Transaction> {
+		let inputs = exit.selected(only).map(|vtxo| {
+			vtxo.claim()
+		}).collect::<Vec<_>>();
+
+		let total_amount = inputs.iter().map(|i| i.spec.amount).sum::<Amount>();
+		debug!("Claiming the following exits with total value of {}: {:?}",
+			total_amount, inputs.iter().map(|i| i.utxo.to_string()).collect::<Vec<_>>(),
+		);
+
+		let mut psbt = self.onchain.create_exit_claim_tx(&inputs, fee_rate).await?;
+
+		// Sign all the claim inputs.
+		let prevouts = psbt.inputs.iter()
+			.map(|i| i.witness_utxo.clone().unwrap())
+			.collect::<Vec<_>>();
+		let prevouts = sighash::Prevouts::All(&prevouts);
+		let mut shc = sighash::SighashCache::new(&psbt.unsigned_tx);
+		for (i, input) in psbt.inputs.iter_mut().enumerate() {
+			if let Some(claim) = input.get_claim_input().context("corrupt claim psbt")? {
+				let vtxo_key = self.vtxo_keypair(claim.spec.user_pubkey)?;
+				input.try_sign_claim_input(&SECP, &mut shc, &prevouts, i, &vtxo_key)
+					.context("corrupt claim psbt")?;
+			}
+		}
+
+		// Then sign the wallet's funding inputs.
+		self.onchain.finish_tx(psbt).context("finishing claim psbt")
+	}
 }
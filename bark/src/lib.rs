@@ -5,46 +5,303 @@
 extern crate lnurl as lnurllib;
 
 mod database;
+pub use database::{StorageBackend, VtxoExitStatus};
 mod exit;
-pub use exit::ExitStatus;
+pub use exit::{ClaimInput, ExitStatus};
 mod lnurl;
 mod onchain;
+pub use onchain::MempoolAcceptance;
 mod psbtext;
+pub use psbtext::{PsbtExt, PsbtExtError, PsbtInputExt, PSBT_EXT_VERSION};
+mod watch;
+pub use watch::{WatchKey, WatchOnlyWallet};
+mod watchtower;
+pub use watchtower::{WatchtowerBundle, WatchtowerVtxo};
 
 
-use std::time::Duration;
-use std::{fs, iter};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{cmp, fmt, fs, iter};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{bail, Context};
-use bitcoin::{bip32, secp256k1, Address, Amount, FeeRate, Network, OutPoint, Transaction, Txid, Weight};
+use bitcoin::{
+	bip32, secp256k1, Address, Amount, FeeRate, Network, OutPoint, Psbt, Transaction, Txid, Weight,
+};
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{rand, Keypair, PublicKey};
 use lnurllib::lightning_address::LightningAddress;
 use lightning_invoice::Bolt11Invoice;
 use serde::Serialize;
 use tokio_stream::StreamExt;
+use tonic::codec::CompressionEncoding;
 
-use ark::{musig, BaseVtxo, OffboardRequest, VtxoRequest, Vtxo, VtxoId, VtxoSpec};
+use ark::{
+	musig, ExitTimelockType, OffboardRequest, VtxoRequest, VtxoScriptType, Vtxo, VtxoId,
+};
 use ark::connectors::ConnectorChain;
-use ark::tree::signed::{SignedVtxoTree, VtxoTreeSpec};
+use ark::tree::signed::{OutputKeyPolicy, SignedVtxoTree, VtxoTreeSpec};
 use aspd_rpc_client as rpc;
 
 /// The file name of the config file.
 const CONFIG_FILE: &str = "config.json";
+const MNEMONIC_FILE: &str = "mnemonic";
+
+/// Environment variable that makes bark abort right before it broadcasts
+/// an onboard tx. Only meant to test resuming interrupted onboards, so
+/// only available with the `test_hooks` feature.
+#[cfg(feature = "test_hooks")]
+#[doc(hidden)]
+pub const CRASH_BEFORE_ONBOARD_BROADCAST_ENV: &str = "BARK_CRASH_BEFORE_ONBOARD_BROADCAST";
+
+/// The interval at which we poll the chain source when waiting for confirmations.
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The bounds of the backoff between attempts of `bark daemon` to
+/// re-subscribe to the round events of the ASP.
+const ASP_RECONNECT_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const ASP_RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The number of derived vtxo keys in a row without vtxos after which
+/// [Wallet::restore_round_vtxos] stops looking for more.
+pub const RESTORE_GAP_LIMIT: u32 = 20;
+
+/// The difference between our clock and the ASP's above which we warn
+/// that we might miss round deadlines.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(10);
 
 lazy_static::lazy_static! {
 	/// Global secp context.
 	static ref SECP: secp256k1::Secp256k1<secp256k1::All> = secp256k1::Secp256k1::new();
 }
 
+#[derive(Debug, Clone)]
 pub struct ArkInfo {
 	pub asp_pubkey: PublicKey,
 	pub nb_round_nonces: usize,
 	pub vtxo_expiry_delta: u16,
 	pub vtxo_exit_delta: u16,
+	pub vtxo_output_key_policy: OutputKeyPolicy,
+	pub vtxo_exit_timelock: ExitTimelockType,
+	pub vtxo_script_type: VtxoScriptType,
+	/// Number of confirmations an onboard tx needs before the ASP accepts
+	/// its vtxo in a round.
+	pub onboard_confirmations: u32,
+	/// The minimum amount of each output of an OOR payment.
+	pub oor_min_amount: Amount,
+}
+
+/// A one-shot overview of the state of the wallet.
+#[derive(Debug, Clone)]
+pub struct Status {
+	pub onchain_balance: Amount,
+	pub offchain_balance: Amount,
+	/// The part of the offchain balance in OOR vtxos.
+	pub offchain_oor_balance: Amount,
+	pub nb_vtxos: usize,
+	pub nb_oor_vtxos: usize,
+	/// The number of vtxos on a key that received multiple OOR payments.
+	pub nb_reused_key_vtxos: usize,
+	/// The expiry height of the vtxo that expires first.
+	pub nearest_expiry_height: Option<u32>,
+	/// The round txs of the rounds we provided forfeits for, but didn't see
+	/// finish yet.
+	pub pending_round_txids: Vec<Txid>,
+	/// The amount of the input vtxos of the pending rounds.
+	pub pending_round_amount: Amount,
+	pub asp_address: String,
+	pub ark_info: ArkInfo,
+	pub chain_tip: u32,
+	/// The height up to which we synced the rounds of the ASP.
+	pub ark_sync_height: u32,
+}
+
+/// Whether a vtxo can be used right now, either offchain or in a
+/// unilateral exit.
+///
+/// A vtxo can be spendable offchain before it can be exited, f.e. when the
+/// round tx it's in is not confirmed yet, and the other way around, f.e.
+/// when its onboard tx doesn't have the confirmations the ASP requires yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VtxoReadiness {
+	/// The vtxo hasn't expired and the onboard txs it builds on have enough
+	/// confirmations for the ASP to accept it.
+	pub spendable_offchain: bool,
+	/// The exit path of the vtxo is complete and the onchain txs it starts
+	/// from are confirmed.
+	pub exitable_onchain: bool,
+}
+
+impl VtxoReadiness {
+	/// The onchain txs the exit of the vtxo starts from, and whether each
+	/// is an onboard tx.
+	fn anchor_txids(vtxo: &Vtxo) -> Vec<(Txid, bool)> {
+		let mut ret = Vec::new();
+		let mut todo = vec![vtxo];
+		while let Some(vtxo) = todo.pop() {
+			match vtxo {
+				Vtxo::Onboard { base, .. } => ret.push((base.utxo.txid, true)),
+				Vtxo::Round { base, .. } => ret.push((base.utxo.txid, false)),
+				Vtxo::Oor { inputs, .. } | Vtxo::Bolt11Change { inputs, .. } => {
+					todo.extend(inputs.iter().map(|i| i.as_ref()));
+				},
+			}
+		}
+		ret
+	}
+
+	/// Compute the readiness of the vtxo given the confirmation heights of
+	/// its anchor txs.
+	fn of(
+		vtxo: &Vtxo,
+		exit: &VtxoExitStatus,
+		tip: u32,
+		onboard_confirmations: u32,
+		confirmed_heights: &HashMap<Txid, u32>,
+	) -> VtxoReadiness {
+		let confirmations = |txid: &Txid| match confirmed_heights.get(txid) {
+			Some(height) => (tip + 1).saturating_sub(*height),
+			None => 0,
+		};
+		let anchors = VtxoReadiness::anchor_txids(vtxo);
+		let spendable_offchain = tip < vtxo.spec().expiry_height && anchors.iter()
+			.filter(|(_, onboard)| *onboard)
+			.all(|(txid, _)| confirmations(txid) >= onboard_confirmations);
+		let exitable_onchain = exit.exitable && anchors.iter()
+			.all(|(txid, _)| confirmations(txid) > 0);
+		VtxoReadiness { spendable_offchain, exitable_onchain }
+	}
+}
+
+/// We don't have enough money to make the requested payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsufficientFunds {
+	/// The total value of the vtxos we could have used.
+	pub available: Amount,
+}
+
+impl fmt::Display for InsufficientFunds {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Balance too low: {}", self.available)
+	}
+}
+
+impl std::error::Error for InsufficientFunds {}
+
+/// An onchain payment would leave less than the configured fee reserve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BelowReserve {
+	/// Our onchain balance after the payment.
+	pub balance_after: Amount,
+	/// The configured fee reserve.
+	pub reserve: Amount,
+}
+
+impl fmt::Display for BelowReserve {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Payment would leave an onchain balance of {}, below the fee reserve of {}",
+			self.balance_after, self.reserve,
+		)
+	}
+}
+
+impl std::error::Error for BelowReserve {}
+
+/// The ASP failed to finish a round we participated in.
+#[derive(Debug, Clone)]
+pub struct RoundFailed {
+	pub round_id: u64,
+	pub reason: String,
+}
+
+impl fmt::Display for RoundFailed {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "ASP failed to finish round {}: {}", self.round_id, self.reason)
+	}
+}
+
+impl std::error::Error for RoundFailed {}
+
+/// A preview of a payment, made by running input selection and fee
+/// calculation without actually sending anything.
+#[derive(Debug, Clone)]
+pub struct SendPreview {
+	/// The vtxos that would be spent.
+	pub inputs: Vec<Vtxo>,
+	/// The amount of our change vtxo, if any.
+	pub change: Option<Amount>,
+	/// Everything spent that doesn't go to the destination or our change.
+	pub fee: Amount,
+	/// Our offchain balance after the payment.
+	pub balance_after: Amount,
+}
+
+/// A payment to a single public key, delivered as multiple vtxos.
+#[derive(Debug, Clone)]
+struct SplitPayment {
+	pubkey: PublicKey,
+	amounts: Vec<Amount>,
+}
+
+impl SplitPayment {
+	fn vtxo_requests(&self) -> impl Iterator<Item = VtxoRequest> + '_ {
+		self.amounts.iter().map(|a| VtxoRequest { pubkey: self.pubkey, amount: *a })
+	}
+}
+
+/// A round in which we provided our forfeit signatures, but didn't
+/// see finish yet.
+///
+/// The input vtxos are taken out of our spendable vtxos until we know the
+/// outcome of the round. Our other vtxos can be used in other rounds in
+/// the meantime.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct PendingRound {
+	/// A local id to tell our pending rounds apart, the round txid changes
+	/// with every attempt.
+	pub id: u64,
+	/// The round tx of the latest attempt.
+	pub round_txid: Txid,
+	pub inputs: Vec<VtxoId>,
+	/// Pending rounds stored by older versions don't have these.
+	#[serde(default)]
+	pub input_vtxos: Vec<Vtxo>,
+	/// All attempts of the round we provided forfeit signatures for.
+	#[serde(default)]
+	pub attempts: Vec<RoundAttempt>,
+	/// The label for our new vtxos of the round.
+	#[serde(default)]
+	pub label: Option<String>,
+}
+
+/// A round attempt we provided forfeit signatures for.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct RoundAttempt {
+	pub round_tx: Transaction,
+	pub vtxos: SignedVtxoTree,
+}
+
+/// The outcome of a [PendingRound].
+enum PendingRoundOutcome {
+	/// The round finished, with its vtxo tree if we know it.
+	Finished(Option<SignedVtxoTree>),
+	/// None of the round txs can confirm anymore.
+	Failed,
+	/// The ASP could still finish the round.
+	Unknown,
+}
+
+/// An onboard of which we signed the onboard tx, but didn't see it
+/// broadcast yet.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct PendingOnboard {
+	pub tx: Transaction,
+	pub vtxos: Vec<Vtxo>,
+	/// The label for the onboard vtxos.
+	#[serde(default)]
+	pub label: Option<String>,
 }
 
 /// Configuration of the Bark wallet.
@@ -59,6 +316,41 @@ pub struct Config {
 	/// The address of your ASP.
 	pub asp_address: String,
 
+	/// A PEM certificate to verify the TLS certificate of the ASP with.
+	///
+	/// This can be a custom CA or the ASP's own certificate to pin it.
+	/// If unset, the usual root certificates are used. Only valid with an
+	/// `https://` ASP address.
+	pub asp_tls_cert: Option<PathBuf>,
+
+	/// The timeout to connect to the ASP, in seconds.
+	///
+	/// Default value: 30
+	pub asp_connect_timeout_secs: u64,
+
+	/// The timeout of requests to the ASP, in seconds.
+	///
+	/// Default value: 600
+	pub asp_request_timeout_secs: u64,
+
+	/// The interval of the keepalive pings on the connection to the ASP,
+	/// in seconds.
+	///
+	/// A connection that doesn't answer a ping within the same interval is
+	/// considered broken and is re-established. Set to 0 to disable pings.
+	///
+	/// Default value: 30
+	pub asp_keepalive_interval_secs: u64,
+
+	/// Ask the ASP to gzip its responses, most notably the round events,
+	/// which carry the vtxo trees of the rounds.
+	///
+	/// This saves bandwidth on constrained links at the cost of some CPU
+	/// time. The ASP only compresses if it's configured to do so.
+	///
+	/// Default value: false
+	pub asp_compression: bool,
+
 	/// The address of the Esplora HTTP server to use.
 	///
 	/// Either this or the `bitcoind_address` field has to be provided.
@@ -87,7 +379,56 @@ pub struct Config {
 	/// The number of blocks before expiration to refresh vtxos.
 	///
 	/// Default value: 288 (48 hrs)
-	pub vtxo_refresh_threshold: u32
+	pub vtxo_refresh_threshold: u32,
+
+	/// The minimum time between two checks of `bark daemon` for vtxos to
+	/// refresh, in seconds. The daemon checks when a round starts.
+	///
+	/// Default value: 60
+	pub daemon_interval_secs: u64,
+
+	/// The onchain balance, in sats, to keep available for paying the fees
+	/// of unilateral exits and CPFP bumps.
+	///
+	/// Onchain sends and onboards that would leave less than this are
+	/// refused unless explicitly allowed.
+	///
+	/// Default value: 0 (no reserve)
+	pub reserve_sat: u64,
+
+	/// The number of blocks we want our onchain txs to confirm in.
+	///
+	/// This is the target for the fee estimates of onboards, onchain sends,
+	/// exits and CPFP bumps. Fee rates given for a single command override it.
+	///
+	/// Default value: 6
+	pub conf_target: u16,
+
+	/// Derive a fresh key for every change vtxo instead of using our
+	/// vtxo pubkey.
+	///
+	/// This prevents linking our vtxos together through their pubkey.
+	///
+	/// Default value: false
+	pub fresh_change_keys: bool,
+
+	/// Refresh vtxos on a vtxo key that received multiple OOR payments
+	/// into a fresh key.
+	///
+	/// OOR payments received on the same key can be linked together.
+	/// We always warn about them, with this option `bark daemon` also
+	/// moves them to a fresh key in the next round.
+	///
+	/// Default value: false
+	pub refresh_reused_keys: bool,
+
+	/// The database backend to store the wallet's ark state in.
+	///
+	/// This is picked when the wallet is created and can't be changed
+	/// afterwards.
+	///
+	/// Default value: sled
+	pub storage: StorageBackend,
 }
 
 impl Default for Config {
@@ -95,12 +436,23 @@ impl Default for Config {
 		Config {
 			network: Network::Signet,
 			asp_address: "http://127.0.0.1:3535".to_owned(),
+			asp_tls_cert: None,
+			asp_connect_timeout_secs: 30,
+			asp_request_timeout_secs: 600,
+			asp_keepalive_interval_secs: 30,
+			asp_compression: false,
 			esplora_address: None,
 			bitcoind_address: None,
 			bitcoind_cookiefile: None,
 			bitcoind_user: None,
 			bitcoind_pass: None,
 			vtxo_refresh_threshold: 288,
+			daemon_interval_secs: 60,
+			reserve_sat: 0,
+			conf_target: 6,
+			fresh_change_keys: false,
+			refresh_reused_keys: false,
+			storage: StorageBackend::Sled,
 		}
 	}
 }
@@ -108,12 +460,101 @@ impl Default for Config {
 pub struct Wallet {
 	config: Config,
 	datadir: PathBuf,
-	db: database::Db,
+	db: Box<dyn database::Storage>,
 	onchain: onchain::Wallet,
 	vtxo_seed: bip32::Xpriv,
 	// ASP stuff
 	asp: rpc::ArkServiceClient<tonic::transport::Channel>,
 	ark_info: ArkInfo,
+	/// The label for the vtxos created by the next operations.
+	label: Option<String>,
+}
+
+/// Create the offboard request and change vtxo request for sending `amount`
+/// on-chain to `addr` using inputs worth `in_sum`.
+fn offboard_outputs(
+	addr: &Address,
+	amount: Amount,
+	in_sum: Amount,
+	offb_fr: FeeRate,
+	change_pubkey: PublicKey,
+) -> anyhow::Result<(OffboardRequest, Option<VtxoRequest>)> {
+	let offb = OffboardRequest {
+		script_pubkey: addr.script_pubkey(),
+		amount: amount,
+	};
+	offb.validate().map_err(|e| anyhow!("can't send {} to {}: {}", amount, addr, e))?;
+	let out_value = amount + offb.fee(offb_fr).expect("script from address");
+	let change = {
+		if in_sum < out_value {
+			bail!(InsufficientFunds { available: in_sum });
+		} else if in_sum <= out_value + ark::P2TR_DUST {
+			info!("No change, emptying wallet.");
+			None
+		} else {
+			let amount = in_sum - out_value;
+			info!("Adding change vtxo for {}", amount);
+			Some(VtxoRequest {
+				pubkey: change_pubkey,
+				amount: amount,
+			})
+		}
+	};
+	Ok((offb, change))
+}
+
+/// Compare our clock to the time the ASP reported in response to a request
+/// we sent and got the answer to at the given times.
+///
+/// Returns a warning if our clock is off by more than [MAX_CLOCK_SKEW].
+fn clock_skew_warning(asp_time_ms: u64, sent: SystemTime, received: SystemTime) -> Option<String> {
+	// Older ASPs don't tell us their time.
+	if asp_time_ms == 0 {
+		return None;
+	}
+	let asp_time = UNIX_EPOCH + Duration::from_millis(asp_time_ms);
+	// The ASP answered somewhere in between, so allow for the round trip.
+	let rtt = received.duration_since(sent).unwrap_or_default();
+	let (ahead, skew) = match sent.duration_since(asp_time) {
+		Ok(d) => (true, d),
+		Err(_) => (false, asp_time.duration_since(received).unwrap_or_default()),
+	};
+	if skew <= MAX_CLOCK_SKEW {
+		return None;
+	}
+	Some(format!("Our clock is {:?} {} of the ASP's (round trip {:?}), \
+		rounds might fail because we miss their deadlines, check the system time",
+		skew, if ahead { "ahead" } else { "behind" }, rtt,
+	))
+}
+
+/// Check the ASP's response to one of our round submissions.
+///
+/// Returns whether we should try again in the next round, and fails if
+/// retrying wouldn't help.
+fn check_submit_response(res: rpc::SubmitResponse) -> anyhow::Result<bool> {
+	let reason = rpc::SubmitRejectReason::try_from(res.reject_reason)
+		.context("unknown reject reason from asp")?;
+	match reason {
+		rpc::SubmitRejectReason::Accepted => Ok(false),
+		rpc::SubmitRejectReason::StaleEpoch
+			| rpc::SubmitRejectReason::PastDeadline
+			| rpc::SubmitRejectReason::OverCapacity
+			| rpc::SubmitRejectReason::RoundFull =>
+		{
+			warn!("ASP can't take our submission in this round ({}), trying the next one: {}",
+				reason.as_str_name(), res.reject_message,
+			);
+			if res.next_round_start_ms != 0 {
+				let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+				info!("Next round is expected in {:?}",
+					Duration::from_millis(res.next_round_start_ms.saturating_sub(now)),
+				);
+			}
+			Ok(true)
+		},
+		_ => bail!("{}: {}", reason.as_str_name(), res.reject_message),
+	}
 }
 
 impl Wallet {
@@ -131,6 +572,19 @@ impl Wallet {
 	pub async fn create(
 		datadir: &Path,
 		config: Config,
+	) -> anyhow::Result<Wallet> {
+		let mnemonic = bip39::Mnemonic::generate(12).expect("12 is valid");
+		Self::create_with_mnemonic(datadir, config, mnemonic).await
+	}
+
+	/// Create a new wallet with an existing mnemonic.
+	///
+	/// Use [Wallet::restore_round_vtxos] afterwards to find the round vtxos
+	/// of the mnemonic's keys.
+	pub async fn create_with_mnemonic(
+		datadir: &Path,
+		config: Config,
+		mnemonic: bip39::Mnemonic,
 	) -> anyhow::Result<Wallet> {
 		info!("Creating new bark Wallet at {}", datadir.display());
 		trace!("Config: {:?}", config);
@@ -144,98 +598,90 @@ impl Wallet {
 		// write the config to disk
 		Self::write_config(&config, datadir).context("failed to write config file")?;
 
-		// generate seed
-		let mnemonic = bip39::Mnemonic::generate(12).expect("12 is valid");
-
-		// write it to file
-		fs::write(datadir.join("mnemonic"), mnemonic.to_string().as_bytes())
+		// write the seed to file
+		fs::write(datadir.join(MNEMONIC_FILE), mnemonic.to_string().as_bytes())
 			.context("failed to write mnemonic")?;
 
 		// from then on we can open the wallet
 		Ok(Wallet::open(&datadir).await.context("failed to open")?)
 	}
 
-	/// Open existing wallet.
-	pub async fn open(datadir: &Path) -> anyhow::Result<Wallet> {
-		info!("Opening bark Wallet at {}", datadir.display());
-
-		let config = {
-			let path = datadir.join(CONFIG_FILE);
-			let bytes = fs::read(&path)
-				.with_context(|| format!("failed to read config file: {}", path.display()))?;
-			serde_json::from_slice::<Config>(&bytes).context("invalid config file")?
-		};
-		trace!("Config: {:?}", config);
-
-		// read mnemonic file
-		let mnemonic_path = datadir.join("mnemonic");
+	fn read_mnemonic(datadir: &Path) -> anyhow::Result<bip39::Mnemonic> {
+		let mnemonic_path = datadir.join(MNEMONIC_FILE);
 		let mnemonic_str = fs::read_to_string(&mnemonic_path)
 			.with_context(|| format!("failed to read mnemonic file at {}", mnemonic_path.display()))?;
-		let mnemonic = bip39::Mnemonic::from_str(&mnemonic_str).context("broken mnemonic")?;
-		let seed = mnemonic.to_seed("");
-
-		//TODO(stevenroose) check if bitcoind has txindex enabled
-
-		// create on-chain wallet
-		let chain_source = if let Some(ref url) = config.esplora_address {
-			onchain::ChainSource::Esplora {
-				url: url.clone(),
-			}
-		} else if let Some(ref url) = config.bitcoind_address {
-			let auth = if let Some(ref c) = config.bitcoind_cookiefile {
-				bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(c.clone())
-			} else {
-				bdk_bitcoind_rpc::bitcoincore_rpc::Auth::UserPass(
-					config.bitcoind_user.clone().context("need bitcoind auth config")?,
-					config.bitcoind_pass.clone().context("need bitcoind auth config")?,
-				)
-			};
-			onchain::ChainSource::Bitcoind {
-				url: url.clone(),
-				auth: auth,
-			}
-		} else {
-			bail!("Need to either provide esplora or bitcoind info");
-		};
-		let onchain = onchain::Wallet::create(config.network, seed, &datadir, chain_source)
-			.context("failed to create onchain wallet")?;
-
-		let db = database::Db::open(&datadir.join("db")).context("failed to open db")?;
+		Ok(bip39::Mnemonic::from_str(&mnemonic_str).context("broken mnemonic")?)
+	}
 
-		let vtxo_seed = {
-			let master = bip32::Xpriv::new_master(config.network, &seed).unwrap();
-			master.derive_priv(&SECP, &[350.into()]).unwrap()
-		};
+	/// Read the config file from the data directory.
+	fn read_config(datadir: &Path) -> anyhow::Result<Config> {
+		let path = datadir.join(CONFIG_FILE);
+		let bytes = fs::read(&path)
+			.with_context(|| format!("failed to read config file: {}", path.display()))?;
+		Ok(serde_json::from_slice::<Config>(&bytes).context("invalid config file")?)
+	}
 
+	/// The endpoint to connect to the ASP with, as configured.
+	fn asp_endpoint(config: &Config) -> anyhow::Result<tonic::transport::Endpoint> {
 		let asp_uri = tonic::transport::Uri::from_str(&config.asp_address)
 			.context("invalid asp addr")?;
-		let scheme = asp_uri.scheme_str().expect("no scheme?");
+		let scheme = asp_uri.scheme_str().context("ASP address needs a scheme")?;
 		if scheme != "http" && scheme != "https" {
 			bail!("ASP scheme must be either http or https");
 		}
+		if scheme == "http" && config.asp_tls_cert.is_some() {
+			bail!("an ASP TLS certificate is configured, but the ASP address is not https");
+		}
 
 		let mut endpoint = tonic::transport::Channel::builder(asp_uri.clone())
-			.keep_alive_timeout(Duration::from_secs(600))
-			.timeout(Duration::from_secs(600));
+			.connect_timeout(Duration::from_secs(config.asp_connect_timeout_secs))
+			.timeout(Duration::from_secs(config.asp_request_timeout_secs));
+		if config.asp_keepalive_interval_secs > 0 {
+			let interval = Duration::from_secs(config.asp_keepalive_interval_secs);
+			endpoint = endpoint
+				.http2_keep_alive_interval(interval)
+				.keep_alive_timeout(interval)
+				.keep_alive_while_idle(true);
+		}
 
 		if scheme == "https" {
 			info!("Connecting to ASP using SSL...");
-			let uri_auth = asp_uri.clone().into_parts().authority.expect("need authority");
+			let uri_auth = asp_uri.clone().into_parts().authority.context("need authority")?;
 			let domain = uri_auth.host();
 
-			let tls_config = tonic::transport::ClientTlsConfig::new()
+			let mut tls_config = tonic::transport::ClientTlsConfig::new()
 				.domain_name(domain);
+			if let Some(ref path) = config.asp_tls_cert {
+				let pem = fs::read(path).with_context(|| {
+					format!("failed to read ASP TLS certificate {}", path.display())
+				})?;
+				tls_config = tls_config.ca_certificate(tonic::transport::Certificate::from_pem(pem));
+			}
 			endpoint = endpoint.tls_config(tls_config)?
 		} else {
 			info!("Connecting to ASP without TLS...");
 		};
+		Ok(endpoint)
+	}
 
+	/// Connect to the ASP and fetch its ark info.
+	async fn connect_asp(
+		config: &Config,
+	) -> anyhow::Result<(rpc::ArkServiceClient<tonic::transport::Channel>, ArkInfo)> {
+		let endpoint = Self::asp_endpoint(config)?;
 		let mut asp = rpc::ArkServiceClient::connect(endpoint)
 			.await.context("failed to connect to asp")?;
+		if config.asp_compression {
+			asp = asp.accept_compressed(CompressionEncoding::Gzip);
+		}
 
 		let ark_info = {
+			let sent = SystemTime::now();
 			let res = asp.get_ark_info(rpc::Empty{})
 				.await.context("ark info request failed")?.into_inner();
+			if let Some(msg) = clock_skew_warning(res.timestamp_ms, sent, SystemTime::now()) {
+				warn!("{}", msg);
+			}
 			if config.network != res.network.parse().context("invalid network from asp")? {
 				bail!("ASP is for net {} while we are on net {}", res.network, config.network);
 			}
@@ -244,17 +690,209 @@ impl Wallet {
 				nb_round_nonces: res.nb_round_nonces as usize,
 				vtxo_expiry_delta: res.vtxo_expiry_delta as u16,
 				vtxo_exit_delta: res.vtxo_exit_delta as u16,
+				vtxo_output_key_policy: rpc::VtxoOutputKeyPolicy::try_from(res.vtxo_output_key_policy)
+					.context("unknown vtxo output key policy from asp")?.into(),
+				onboard_confirmations: res.onboard_confirmations,
+				vtxo_exit_timelock: rpc::VtxoExitTimelock::try_from(res.vtxo_exit_timelock)
+					.context("unknown vtxo exit timelock from asp")?.into(),
+				vtxo_script_type: rpc::VtxoScriptType::try_from(res.vtxo_script_type)
+					.context("unsupported vtxo script type from asp")?.into(),
+				oor_min_amount: Amount::from_sat(res.oor_min_amount_sat),
 			}
 		};
+		Ok((asp, ark_info))
+	}
+
+	/// The chain source configured for the wallet.
+	fn chain_source(config: &Config) -> anyhow::Result<onchain::ChainSource> {
+		if let Some(ref url) = config.esplora_address {
+			Ok(onchain::ChainSource::Esplora {
+				url: url.clone(),
+			})
+		} else if let Some(ref url) = config.bitcoind_address {
+			let auth = if let Some(ref c) = config.bitcoind_cookiefile {
+				bdk_bitcoind_rpc::bitcoincore_rpc::Auth::CookieFile(c.clone())
+			} else {
+				bdk_bitcoind_rpc::bitcoincore_rpc::Auth::UserPass(
+					config.bitcoind_user.clone().context("need bitcoind auth config")?,
+					config.bitcoind_pass.clone().context("need bitcoind auth config")?,
+				)
+			};
+			Ok(onchain::ChainSource::Bitcoind {
+				url: url.clone(),
+				auth: auth,
+			})
+		} else {
+			bail!("Need to either provide esplora or bitcoind info");
+		}
+	}
+
+	/// Open existing wallet.
+	pub async fn open(datadir: &Path) -> anyhow::Result<Wallet> {
+		info!("Opening bark Wallet at {}", datadir.display());
+		if WatchOnlyWallet::is_watch_only(datadir) {
+			bail!("{} is a watch-only wallet without keys", datadir.display());
+		}
+
+		let config = Self::read_config(datadir)?;
+		trace!("Config: {:?}", config);
+
+		let seed = Self::read_mnemonic(datadir)?.to_seed("");
+
+		//TODO(stevenroose) check if bitcoind has txindex enabled
+
+		// create on-chain wallet
+		let chain_source = Self::chain_source(&config)?;
+		let onchain = onchain::Wallet::create(
+			config.network, seed, &datadir, chain_source, config.conf_target,
+		).context("failed to create onchain wallet")?;
+
+		let db = database::open(datadir, config.storage).context("failed to open db")?;
+
+		let vtxo_seed = {
+			let master = bip32::Xpriv::new_master(config.network, &seed).unwrap();
+			master.derive_priv(&SECP, &[350.into()]).unwrap()
+		};
+
+		let (asp, ark_info) = Self::connect_asp(&config).await?;
 
 		let datadir = datadir.to_path_buf();
-		Ok(Wallet { config, datadir, db, onchain, vtxo_seed, asp, ark_info })
+		let mut wallet = Wallet {
+			config, datadir, db, onchain, vtxo_seed, asp, ark_info, label: None,
+		};
+		if let Err(e) = wallet.reconcile_pending_rounds().await {
+			warn!("Failed to check the outcome of our pending rounds: {:#}", e);
+		}
+		if let Err(e) = wallet.resume_pending_onboard().await {
+			warn!("Failed to finish our last onboard: {:#}", e);
+		}
+		Ok(wallet)
+	}
+
+	/// Check the outcome of the rounds we provided forfeit signatures for,
+	/// but didn't see finish, and update our vtxos accordingly.
+	async fn reconcile_pending_rounds(&mut self) -> anyhow::Result<()> {
+		for pending in self.db.get_pending_rounds()? {
+			self.reconcile_pending_round(pending).await?;
+		}
+		Ok(())
+	}
+
+	/// Check the outcome of a round we provided forfeit signatures for,
+	/// but didn't see finish, and update our vtxos accordingly.
+	///
+	/// The ASP can use our forfeit signatures as soon as the round tx of
+	/// any of the attempts of the round confirms, so we only release our
+	/// input vtxos once none of them can confirm anymore.
+	async fn reconcile_pending_round(&mut self, pending: PendingRound) -> anyhow::Result<()> {
+		info!("Checking outcome of round {} we didn't see finish", pending.round_txid);
+
+		match self.pending_round_outcome(&pending).await? {
+			PendingRoundOutcome::Finished(tree) => {
+				info!("Round {} finished, dropping our forfeited inputs", pending.round_txid);
+				if let Some(tree) = tree {
+					for (idx, dest) in tree.spec.vtxos.iter().enumerate() {
+						if self.is_own_vtxo_pubkey(dest.pubkey)? {
+							if let Some(id) = self.add_new_vtxo(&tree, idx)? {
+								self.label_new_vtxo(
+									id, pending.inputs.iter().copied(), pending.label.as_deref(),
+								)?;
+							}
+						}
+					}
+				}
+				let current_height = self.onchain.tip().await?;
+				for id in pending.inputs {
+					self.db.store_spent_vtxo(id, current_height)
+						.context("failed to store forfeited vtxo")?;
+					self.db.remove_vtxo(id).context("failed to drop input vtxo")?;
+				}
+			},
+			PendingRoundOutcome::Failed => {
+				info!("Round {} didn't finish, restoring our input vtxos", pending.round_txid);
+				for vtxo in &pending.input_vtxos {
+					self.db.store_vtxo(vtxo).context("failed to restore input vtxo")?;
+				}
+			},
+			PendingRoundOutcome::Unknown => {
+				info!("Round {} can still finish, keeping our input vtxos locked",
+					pending.round_txid,
+				);
+				return Ok(());
+			},
+		}
+		self.db.remove_pending_round(pending.id)?;
+		Ok(())
+	}
+
+	async fn pending_round_outcome(
+		&self,
+		pending: &PendingRound,
+	) -> anyhow::Result<PendingRoundOutcome> {
+		let round_txids = if pending.attempts.is_empty() {
+			vec![pending.round_txid]
+		} else {
+			pending.attempts.iter().map(|a| a.round_tx.compute_txid()).collect()
+		};
+		for txid in round_txids {
+			let req = rpc::RoundId { txid: txid.to_byte_array().to_vec() };
+			match self.asp.get_round(req).await {
+				Ok(round) => {
+					let tree = SignedVtxoTree::decode(&round.into_inner().signed_vtxos)
+						.context("invalid signed vtxo tree from asp")?;
+					return Ok(PendingRoundOutcome::Finished(Some(tree)));
+				},
+				Err(s) if s.code() == tonic::Code::NotFound => {},
+				Err(e) => return Err(e).context("round request failed"),
+			}
+		}
+
+		// The ASP might not tell us about a round tx that confirmed.
+		for attempt in &pending.attempts {
+			if let Ok(Some(_)) = self.onchain.tx_confirmed(attempt.round_tx.compute_txid()).await {
+				return Ok(PendingRoundOutcome::Finished(Some(attempt.vtxos.clone())));
+			}
+		}
+
+		// The round might have been swept already, in which case our
+		// inputs will have been forfeited.
+		for id in &pending.inputs {
+			let req = rpc::VtxoStatusRequest { vtxo_id: id.bytes().to_vec(), vtxo: None };
+			let status = self.asp.get_vtxo_status(req).await
+				.context("vtxo status request failed")?.into_inner().status;
+			if status == rpc::VtxoStatus::Forfeited as i32 {
+				return Ok(PendingRoundOutcome::Finished(None));
+			}
+		}
+
+		// Without the round txs, all we can do is trust the ASP that the
+		// round failed.
+		if pending.attempts.is_empty() {
+			return Ok(PendingRoundOutcome::Failed);
+		}
+		for attempt in &pending.attempts {
+			if !self.is_tx_conflicted(&attempt.round_tx).await? {
+				return Ok(PendingRoundOutcome::Unknown);
+			}
+		}
+		Ok(PendingRoundOutcome::Failed)
 	}
 
 	pub fn config(&self) -> &Config {
 		&self.config
 	}
 
+	pub fn ark_info(&self) -> &ArkInfo {
+		&self.ark_info
+	}
+
+	/// The mnemonic this wallet's keys are derived from.
+	///
+	/// Handle with care, anyone with the mnemonic has access to the funds.
+	pub fn mnemonic(&self) -> anyhow::Result<bip39::Mnemonic> {
+		Self::read_mnemonic(&self.datadir)
+	}
+
 	/// Change the config of this wallet.
 	///
 	/// In order for these changes to be persistent, call [Wallet::persist_config].
@@ -289,8 +927,72 @@ impl Wallet {
 		self.onchain.balance()
 	}
 
-	pub async fn send_onchain(&mut self, addr: Address, amount: Amount) -> anyhow::Result<Txid> {
-		Ok(self.onchain.send_money(addr, amount).await?)
+	/// The onchain balance we keep available for exit and CPFP fees.
+	pub fn onchain_reserve(&self) -> Amount {
+		Amount::from_sat(self.config.reserve_sat)
+	}
+
+	/// Check that the tx doesn't take our onchain balance below the reserve.
+	///
+	/// If `allow_below_reserve` is set, we only warn.
+	fn check_onchain_reserve(&self, psbt: &Psbt, allow_below_reserve: bool) -> anyhow::Result<()> {
+		let reserve = self.onchain_reserve();
+		let balance_after = self.onchain.balance_after(&psbt.unsigned_tx);
+		if balance_after < reserve {
+			let err = BelowReserve { balance_after, reserve };
+			if !allow_below_reserve {
+				bail!(err);
+			}
+			warn!("{}", err);
+		}
+		Ok(())
+	}
+
+	pub async fn send_onchain(
+		&mut self,
+		addr: Address,
+		amount: Amount,
+		allow_below_reserve: bool,
+	) -> anyhow::Result<Txid> {
+		let psbt = self.onchain.prepare_tx(addr, amount).await?;
+		self.check_onchain_reserve(&psbt, allow_below_reserve)?;
+		let tx = self.onchain.finish_tx(psbt)?;
+		self.onchain.broadcast_tx(&tx).await?;
+		Ok(tx.compute_txid())
+	}
+
+	/// Wait until the given tx has at least the given number of confirmations.
+	///
+	/// Returns the height of the block the tx confirmed in. Errors if the tx
+	/// didn't reach the given number of confirmations before the timeout.
+	pub async fn wait_for_confirmations(
+		&self,
+		txid: Txid,
+		confirmations: u32,
+		timeout: Duration,
+	) -> anyhow::Result<u32> {
+		ensure!(confirmations > 0, "need to wait for at least one confirmation");
+		info!("Waiting for tx {} to reach {} confirmation(s)...", txid, confirmations);
+
+		let poll = async {
+			loop {
+				match self.onchain.tx_confirmed(txid).await {
+					Ok(Some(height)) => {
+						let tip = self.onchain.tip().await?;
+						if tip + 1 >= height + confirmations {
+							info!("Tx {} confirmed at height {}", txid, height);
+							return Ok::<_, anyhow::Error>(height);
+						}
+					},
+					Ok(None) => {},
+					Err(e) => trace!("Error checking confirmation status of tx {}: {}", txid, e),
+				}
+				tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+			}
+		};
+		tokio::time::timeout(timeout, poll).await.with_context(|| format!(
+			"timed out waiting for {} confirmation(s) of tx {}", confirmations, txid,
+		))?
 	}
 
 	/// Retrieve the off-chain balance of the wallet.
@@ -305,6 +1007,14 @@ impl Wallet {
 		Ok(sum)
 	}
 
+	/// The part of our offchain balance held in OOR vtxos.
+	///
+	/// Unlike round vtxos, these depend on the signatures of their previous
+	/// owners and the ASP.
+	pub fn offchain_oor_balance(&self) -> anyhow::Result<Amount> {
+		Ok(self.db.get_all_vtxos()?.iter().filter(|v| v.is_oor()).map(|v| v.amount()).sum())
+	}
+
 	pub fn vtxos(&mut self) -> anyhow::Result<Vec<Vtxo>> {
 		Ok(self.db.get_all_vtxos()?)
 	}
@@ -313,6 +1023,88 @@ impl Wallet {
 	pub async fn sync(&mut self) -> anyhow::Result<()> {
 		self.onchain.sync().await?;
 		self.sync_ark().await?;
+		self.sync_swept_vtxos().await?;
+		self.reconcile_pending_rounds().await?;
+		Ok(())
+	}
+
+	/// An overview of the state of the wallet.
+	///
+	/// Make sure you sync before calling this method.
+	pub async fn status(&mut self) -> anyhow::Result<Status> {
+		let vtxos = self.db.get_all_vtxos()?;
+		let pending = self.db.get_pending_rounds()?;
+		Ok(Status {
+			onchain_balance: self.onchain.balance(),
+			offchain_balance: vtxos.iter().map(|v| v.amount()).sum(),
+			offchain_oor_balance: vtxos.iter().filter(|v| v.is_oor()).map(|v| v.amount()).sum(),
+			nb_vtxos: vtxos.len(),
+			nb_oor_vtxos: vtxos.iter().filter(|v| v.is_oor()).count(),
+			nb_reused_key_vtxos: self.reused_key_vtxos()?.len(),
+			nearest_expiry_height: vtxos.iter().map(|v| v.spec().expiry_height).min(),
+			pending_round_txids: pending.iter().map(|p| p.round_txid).collect(),
+			pending_round_amount: pending.iter().flat_map(|p| &p.input_vtxos)
+				.map(|v| v.amount()).sum(),
+			asp_address: self.config.asp_address.clone(),
+			ark_info: self.ark_info.clone(),
+			chain_tip: self.onchain.tip().await.context("chain source error")?,
+			ark_sync_height: self.db.get_last_ark_sync_height()?,
+		})
+	}
+
+	/// Vtxos that were lost, f.e. because the ASP swept them after expiry.
+	pub fn lost_vtxos(&self) -> anyhow::Result<Vec<Vtxo>> {
+		Ok(self.db.get_lost_vtxos()?)
+	}
+
+	/// Check whether any of the exit txs of the vtxo has been conflicted.
+	///
+	/// An output spent by one of our exit txs being spent in a confirmed tx,
+	/// while our exit tx is not confirmed, means the output was spent by
+	/// another tx. For expired vtxos, this is the ASP sweeping the output.
+	async fn is_vtxo_swept(&self, vtxo: &Vtxo) -> anyhow::Result<bool> {
+		let mut exit_txs = Vec::new();
+		vtxo.collect_exit_txs(&mut exit_txs);
+		for tx in exit_txs {
+			if self.is_tx_conflicted(&tx).await? {
+				return Ok(true);
+			}
+		}
+		Ok(false)
+	}
+
+	/// Whether the tx is unconfirmed and one of its inputs has been spent
+	/// in a confirmed tx, so that the tx can never confirm.
+	async fn is_tx_conflicted(&self, tx: &Transaction) -> anyhow::Result<bool> {
+		if let Ok(Some(_)) = self.onchain.tx_confirmed(tx.compute_txid()).await {
+			return Ok(false);
+		}
+		for input in &tx.input {
+			// The output can only be spent if the tx creating it is confirmed.
+			let prev = input.previous_output;
+			if let Ok(Some(_)) = self.onchain.tx_confirmed(prev.txid).await {
+				if self.onchain.txout_spent(prev).await? {
+					return Ok(true);
+				}
+			}
+		}
+		Ok(false)
+	}
+
+	/// Look for expired vtxos that were swept by the ASP and mark them as lost.
+	pub async fn sync_swept_vtxos(&mut self) -> anyhow::Result<()> {
+		let tip = self.onchain.tip().await?;
+		for vtxo in self.db.get_all_vtxos()? {
+			if vtxo.spec().expiry_height > tip {
+				continue;
+			}
+			if self.is_vtxo_swept(&vtxo).await.context("chain source error")? {
+				warn!("Vtxo {} of {} expired at height {} and was swept by the ASP, it is lost",
+					vtxo.id(), vtxo.amount(), vtxo.spec().expiry_height,
+				);
+				self.db.mark_vtxo_lost(&vtxo)?;
+			}
+		}
 		Ok(())
 	}
 
@@ -326,95 +1118,351 @@ impl Wallet {
 		Ok(())
 	}
 
-	pub fn vtxo_pubkey(&self) -> PublicKey {
-		self.vtxo_seed.to_keypair(&SECP).public_key()
+	pub fn vtxo_pubkey(&self) -> PublicKey {
+		self.vtxo_seed.to_keypair(&SECP).public_key()
+	}
+
+	/// Derive the vtxo key with the given index from our vtxo seed.
+	fn derive_vtxo_keypair(&self, idx: u32) -> Keypair {
+		let child = bip32::ChildNumber::from_normal_idx(idx).expect("index in normal range");
+		self.vtxo_seed.derive_priv(&SECP, &[child]).expect("valid derivation")
+			.to_keypair(&SECP)
+	}
+
+	/// The keypair for vtxos with the given user pubkey.
+	///
+	/// This is our static vtxo key or one of the change keys we derived.
+	fn vtxo_keypair(&self, pubkey: PublicKey) -> anyhow::Result<Keypair> {
+		let key = self.vtxo_seed.to_keypair(&SECP);
+		if pubkey == key.public_key() {
+			return Ok(key);
+		}
+		let idx = self.db.get_vtxo_key_index(pubkey)?
+			.with_context(|| format!("no vtxo key for pubkey {}", pubkey))?;
+		Ok(self.derive_vtxo_keypair(idx))
+	}
+
+	/// The keypairs for the given vtxos, in the same order.
+	fn vtxo_keypairs(&self, vtxos: &[Vtxo]) -> anyhow::Result<Vec<Keypair>> {
+		vtxos.iter().map(|v| self.vtxo_keypair(v.spec().user_pubkey)).collect()
+	}
+
+	/// Whether the given vtxo is on a key that received multiple OOR payments.
+	pub fn has_reused_key(&self, vtxo: &Vtxo) -> anyhow::Result<bool> {
+		Ok(self.db.get_received_key_count(vtxo.spec().user_pubkey)? > 1)
+	}
+
+	/// The exit status of the given vtxo.
+	///
+	/// It's computed when the vtxo is stored. For vtxos stored before we
+	/// kept their exit status, we compute it once and store it.
+	pub fn vtxo_exit_status(&self, vtxo: &Vtxo) -> anyhow::Result<VtxoExitStatus> {
+		if let Some(status) = self.db.get_vtxo_exit_status(vtxo.id())? {
+			return Ok(status);
+		}
+		let status = VtxoExitStatus::of(vtxo);
+		self.db.store_vtxo_exit_status(vtxo.id(), &status)?;
+		Ok(status)
+	}
+
+	/// Whether the given vtxos can be spent offchain and exited onchain
+	/// right now, see [VtxoReadiness].
+	///
+	/// We look up the tip once and every anchor tx only once, vtxos of
+	/// the same round share their anchor tx.
+	///
+	/// Make sure you sync before calling this method.
+	pub async fn vtxos_readiness(
+		&self,
+		vtxos: &[Vtxo],
+	) -> anyhow::Result<HashMap<VtxoId, VtxoReadiness>> {
+		let tip = self.onchain.tip().await?;
+		let mut looked_up = HashSet::new();
+		let mut confirmed_heights = HashMap::new();
+		for vtxo in vtxos {
+			for (txid, _) in VtxoReadiness::anchor_txids(vtxo) {
+				if !looked_up.insert(txid) {
+					continue;
+				}
+				if let Some(height) = self.onchain.tx_confirmed(txid).await? {
+					confirmed_heights.insert(txid, height);
+				}
+			}
+		}
+
+		let mut ret = HashMap::with_capacity(vtxos.len());
+		for vtxo in vtxos {
+			let exit = self.vtxo_exit_status(vtxo)?;
+			ret.insert(vtxo.id(), VtxoReadiness::of(
+				vtxo, &exit, tip, self.ark_info.onboard_confirmations, &confirmed_heights,
+			));
+		}
+		Ok(ret)
+	}
+
+	/// Our vtxos on a key that received multiple OOR payments.
+	pub fn reused_key_vtxos(&self) -> anyhow::Result<Vec<Vtxo>> {
+		let mut ret = Vec::new();
+		for vtxo in self.db.get_all_vtxos()? {
+			if self.has_reused_key(&vtxo)? {
+				ret.push(vtxo);
+			}
+		}
+		Ok(ret)
+	}
+
+	/// Our static vtxo key and all the vtxo keys we derived.
+	fn own_vtxo_keypairs(&self) -> anyhow::Result<Vec<Keypair>> {
+		let mut ret = vec![self.vtxo_seed.to_keypair(&SECP)];
+		for idx in 0..self.db.next_vtxo_key_index()? {
+			ret.push(self.derive_vtxo_keypair(idx));
+		}
+		Ok(ret)
+	}
+
+	/// Whether vtxos with the given user pubkey are ours.
+	fn is_own_vtxo_pubkey(&self, pubkey: PublicKey) -> anyhow::Result<bool> {
+		Ok(pubkey == self.vtxo_pubkey() || self.db.get_vtxo_key_index(pubkey)?.is_some())
+	}
+
+	/// The pubkey to send our change to.
+	///
+	/// With [Config::fresh_change_keys], a new key is derived and stored
+	/// so that we recognize the change vtxo as ours.
+	fn change_pubkey(&self) -> anyhow::Result<PublicKey> {
+		if !self.config.fresh_change_keys {
+			return Ok(self.vtxo_pubkey());
+		}
+		self.fresh_vtxo_pubkey()
+	}
+
+	/// Derive and store a new vtxo key.
+	///
+	/// Payments received on a fresh key can't be linked to the payments
+	/// received on our other keys.
+	pub fn fresh_vtxo_pubkey(&self) -> anyhow::Result<PublicKey> {
+		let idx = self.db.next_vtxo_key_index()?;
+		let pubkey = self.derive_vtxo_keypair(idx).public_key();
+		self.db.store_vtxo_key_index(idx, pubkey).context("failed to store vtxo key")?;
+		debug!("Derived vtxo key {} with index {}", pubkey, idx);
+		Ok(pubkey)
 	}
 
 	// Onboard a vtxo with the given vtxo amount.
 	//
 	// NB we will spend a little more on-chain to cover minrelayfee.
-	pub async fn onboard(&mut self, amount: Amount) -> anyhow::Result<()> {
+	//
+	// Returns the txid of the onboard tx.
+	pub async fn onboard(&mut self, amount: Amount) -> anyhow::Result<Txid> {
+		self.onboard_many(&[amount], false).await
+	}
+
+	/// Onboard multiple vtxos in a single onchain tx.
+	///
+	/// The ASP cosigns all of them in a single request, or none at all.
+	/// At most [ark::onboard::MAX_BATCH_SIZE] vtxos can be onboarded at once.
+	///
+	/// Unless `allow_below_reserve` is set, we refuse to onboard if it would
+	/// leave less than [Config::reserve_sat] in our onchain wallet.
+	pub async fn onboard_many(
+		&mut self,
+		amounts: &[Amount],
+		allow_below_reserve: bool,
+	) -> anyhow::Result<Txid> {
+		ensure!(!amounts.is_empty(), "no onboard amounts provided");
+		ensure!(amounts.len() <= ark::onboard::MAX_BATCH_SIZE,
+			"can onboard at most {} vtxos at once", ark::onboard::MAX_BATCH_SIZE,
+		);
+
+		// An unfinished onboard might still be holding on to our utxos,
+		// we can only start a new one after it's broadcast.
+		self.resume_pending_onboard().await.context("failed to finish previous onboard")?;
+
 		//TODO(stevenroose) impl key derivation
 		let key = self.vtxo_seed.to_keypair(&SECP);
 
 		let current_height = self.onchain.tip().await?;
-		let spec = ark::VtxoSpec {
+		let specs = amounts.iter().map(|amount| ark::VtxoSpec {
 			user_pubkey: key.public_key(),
 			asp_pubkey: self.ark_info.asp_pubkey,
 			expiry_height: current_height + self.ark_info.vtxo_expiry_delta as u32,
 			exit_delta: self.ark_info.vtxo_exit_delta,
-			amount: amount,
-		};
-		let onboard_amount = amount + ark::onboard::onboard_surplus();
-		let addr = Address::from_script(&ark::onboard::onboard_spk(&spec), self.config.network).unwrap();
+			amount: *amount,
+			exit_timelock_type: self.ark_info.vtxo_exit_timelock,
+			script_type: self.ark_info.vtxo_script_type,
+		}).collect::<Vec<_>>();
+		let dests = specs.iter().map(|spec| {
+			let spk = ark::onboard::onboard_spk(spec);
+			let addr = Address::from_script(&spk, self.config.network).unwrap();
+			(addr, spec.amount + ark::onboard::onboard_surplus())
+		}).collect::<Vec<_>>();
 
 		// We create the onboard tx template, but don't sign it yet.
 		self.onchain.sync().await.context("sync error")?;
-		let onboard_tx = self.onchain.prepare_tx(addr, onboard_amount)?;
-		let utxo = OutPoint::new(onboard_tx.unsigned_tx.compute_txid(), 0);
-
-		// We ask the ASP to cosign our onboard vtxo reveal tx.
-		let (user_part, priv_user_part) = ark::onboard::new_user(spec, utxo);
-		let asp_part = {
-			let res = self.asp.request_onboard_cosign(aspd_rpc_client::OnboardCosignRequest {
-				user_part: {
-					let mut buf = Vec::new();
-					ciborium::into_writer(&user_part, &mut buf).unwrap();
-					buf
-				},
+		let onboard_tx = self.onchain.prepare_tx_many(&dests).await?;
+		self.check_onchain_reserve(&onboard_tx, allow_below_reserve)?;
+		let txid = onboard_tx.unsigned_tx.compute_txid();
+
+		// We ask the ASP to cosign our onboard vtxo reveal txs.
+		let (user_parts, priv_user_parts) = specs.into_iter().enumerate().map(|(i, spec)| {
+			ark::onboard::new_user(spec, OutPoint::new(txid, i as u32))
+		}).unzip::<_, _, Vec<_>, Vec<_>>();
+		let asp_parts = {
+			let res = self.asp.request_onboard_cosign_batch(rpc::OnboardCosignBatchRequest {
+				user_parts: user_parts.iter().map(|p| p.encode()).collect(),
 			}).await.context("error requesting onboard cosign")?;
-			ciborium::from_reader::<ark::onboard::AspPart, _>(&res.into_inner().asp_part[..])
-				.context("invalid ASP part in response")?
+			res.into_inner().asp_parts.iter().map(|p| {
+				ciborium::from_reader::<ark::onboard::AspPart, _>(&p[..])
+					.context("invalid ASP part in response")
+			}).collect::<anyhow::Result<Vec<_>>>()?
 		};
+		if asp_parts.len() != user_parts.len() {
+			bail!("ASP cosigned {} onboards, we requested {}", asp_parts.len(), user_parts.len());
+		}
+		for (i, (user_part, asp_part)) in user_parts.iter().zip(&asp_parts).enumerate() {
+			if !ark::onboard::verify_asp(user_part, asp_part, self.ark_info.asp_pubkey) {
+				bail!("ASP provided invalid cosignature for onboard output {}", i);
+			}
+		}
 
-		// Store vtxo first before we actually make the on-chain tx.
-		let vtxo = ark::onboard::finish(user_part, asp_part, priv_user_part, &key);
-		self.db.store_vtxo(&vtxo).context("db error storing vtxo")?;
+		let vtxos = user_parts.into_iter().zip(asp_parts).zip(priv_user_parts)
+			.map(|((user_part, asp_part), priv_user_part)| {
+				ark::onboard::finish(user_part, asp_part, priv_user_part, &key)
+			}).collect();
 
+		// Up to here nothing is committed and an interrupted onboard can
+		// simply be retried. Once we sign, we persist the tx together with
+		// the vtxos so that we can finish the onboard after a restart.
 		let tx = self.onchain.finish_tx(onboard_tx)?;
+		let pending = PendingOnboard { tx, vtxos, label: self.label.clone() };
+		self.db.store_pending_onboard(&pending).context("db error storing pending onboard")?;
+		#[cfg(feature = "test_hooks")]
+		if std::env::var_os(CRASH_BEFORE_ONBOARD_BROADCAST_ENV).is_some() {
+			error!("Crashing before onboard broadcast as requested");
+			std::process::abort();
+		}
+
+		self.finish_onboard(pending).await
+	}
+
+	/// Store the vtxos of a signed onboard and broadcast its onboard tx.
+	async fn finish_onboard(&mut self, pending: PendingOnboard) -> anyhow::Result<Txid> {
+		// Store vtxos first before we actually make the on-chain tx.
+		for vtxo in &pending.vtxos {
+			self.db.store_vtxo(vtxo).context("db error storing vtxo")?;
+			self.label_new_vtxo(vtxo.id(), None, pending.label.as_deref())?;
+		}
+
+		let tx = pending.tx;
 		trace!("Broadcasting onboard tx: {}", bitcoin::consensus::encode::serialize_hex(&tx));
 		self.onchain.broadcast_tx(&tx).await?;
+		self.db.clear_pending_onboard()?;
 
 		info!("Onboard successfull");
 
+		Ok(tx.compute_txid())
+	}
+
+	/// Finish an onboard that was interrupted after we signed its onboard tx.
+	///
+	/// Broadcasting again is harmless if the tx did make it out before.
+	async fn resume_pending_onboard(&mut self) -> anyhow::Result<()> {
+		let pending = match self.db.fetch_pending_onboard()? {
+			Some(p) => p,
+			None => return Ok(()),
+		};
+		info!("Resuming onboard {} that didn't finish", pending.tx.compute_txid());
+		self.finish_onboard(pending).await?;
 		Ok(())
 	}
 
-	fn add_new_vtxo(&mut self, vtxos: &SignedVtxoTree, leaf_idx: usize) -> anyhow::Result<()> {
-		let exit_branch = vtxos.exit_branch(leaf_idx).unwrap();
-		let dest = &vtxos.spec.vtxos[leaf_idx];
-		let vtxo = Vtxo::Round {
-			base: BaseVtxo {
-				spec: VtxoSpec {
-					user_pubkey: dest.pubkey,
-					asp_pubkey: self.ark_info.asp_pubkey,
-					expiry_height: vtxos.spec.expiry_height,
-					exit_delta: vtxos.spec.exit_delta,
-					amount: dest.amount,
-				},
-				utxo: vtxos.utxo,
-			},
-			leaf_idx: leaf_idx,
-			exit_branch: exit_branch,
+	/// Give up on an onboard of which the onboard tx can't be broadcast,
+	/// for example because its inputs were spent elsewhere.
+	///
+	/// We try to broadcast the onboard tx one last time. Only if that fails,
+	/// the vtxos of the onboard are removed and its onchain inputs are
+	/// released. Returns the txid of the abandoned onboard tx, if any.
+	pub async fn abandon_pending_onboard(&mut self) -> anyhow::Result<Option<Txid>> {
+		let pending = match self.db.fetch_pending_onboard()? {
+			Some(p) => p,
+			None => return Ok(None),
 		};
+		let txid = pending.tx.compute_txid();
+		if let Err(e) = self.onchain.broadcast_tx(&pending.tx).await {
+			warn!("Abandoning onboard {}, failed to broadcast its onboard tx: {:#}", txid, e);
+		} else {
+			bail!("onboard tx {} was broadcast, the onboard can't be abandoned anymore", txid);
+		}
+
+		for vtxo in &pending.vtxos {
+			if self.db.remove_vtxo(vtxo.id()).context("db error removing vtxo")?.is_some() {
+				debug!("Removed vtxo {} of abandoned onboard {}", vtxo.id(), txid);
+			}
+		}
+		self.onchain.cancel_tx(&pending.tx)?;
+		self.db.clear_pending_onboard()?;
+		Ok(Some(txid))
+	}
+
+	/// Store the vtxo of the given leaf, returns its id if it's new to us.
+	fn add_new_vtxo(
+		&mut self,
+		vtxos: &SignedVtxoTree,
+		leaf_idx: usize,
+	) -> anyhow::Result<Option<VtxoId>> {
+		let vtxo = vtxos.vtxo(leaf_idx).expect("leaf in tree");
 
 		if self.db.has_spent_vtxo(vtxo.id())? {
 			debug!("Not adding vtxo {} because we previously forfeited it", vtxo.id());
-			return Ok(());
+			return Ok(None);
 		}
 
 		if self.db.get_vtxo(vtxo.id())?.is_none() {
 			debug!("Storing new vtxo {} with value {}", vtxo.id(), vtxo.spec().amount);
 			self.db.store_vtxo(&vtxo).context("failed to store vtxo")?;
+			Ok(Some(vtxo.id()))
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Label the vtxos that the next operations of the wallet create for us,
+	/// like onboard vtxos and the change of payments and refreshes.
+	///
+	/// Labels are local metadata and are never sent to the ASP. A vtxo that
+	/// is created from labelled vtxos also carries their labels.
+	pub fn set_label(&mut self, label: Option<String>) {
+		self.label = label;
+	}
+
+	/// The labels of the given vtxo.
+	pub fn vtxo_labels(&self, id: VtxoId) -> anyhow::Result<Vec<String>> {
+		self.db.get_vtxo_labels(id)
+	}
+
+	/// Give a new vtxo the labels of the vtxos it was created from,
+	/// followed by the given label.
+	fn label_new_vtxo(
+		&self,
+		id: VtxoId,
+		inputs: impl IntoIterator<Item = VtxoId>,
+		label: Option<&str>,
+	) -> anyhow::Result<()> {
+		let mut labels = Vec::<String>::new();
+		for input in inputs {
+			labels.extend(self.db.get_vtxo_labels(input)?);
+		}
+		labels.extend(label.map(String::from));
+		let mut seen = HashSet::new();
+		labels.retain(|l| seen.insert(l.clone()));
+		if !labels.is_empty() {
+			self.db.store_vtxo_labels(id, &labels).context("failed to store vtxo labels")?;
 		}
 		Ok(())
 	}
 
 	/// Sync with the Ark and look for received vtxos.
 	pub async fn sync_ark(&mut self) -> anyhow::Result<()> {
-		//TODO(stevenroose) impl key derivation
-		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
-
 		//TODO(stevenroose) we won't do reorg handling here
 		let current_height = self.onchain.tip().await?;
 		let last_sync_height = self.db.get_last_ark_sync_height()?;
@@ -430,7 +1478,7 @@ impl Wallet {
 				.context("invalid signed vtxo tree from asp")?;
 
 			for (idx, dest) in tree.spec.vtxos.iter().enumerate() {
-				if dest.pubkey == vtxo_key.public_key() {
+				if self.is_own_vtxo_pubkey(dest.pubkey)? {
 					self.add_new_vtxo(&tree, idx)?;
 				}
 			}
@@ -442,13 +1490,16 @@ impl Wallet {
 
 		self.db.store_last_ark_sync_height(current_height)?;
 
-		// Then sync OOR vtxos.
-		debug!("Emptying OOR mailbox at ASP...");
-		let req = rpc::OorVtxosRequest { pubkey: vtxo_key.public_key().serialize().to_vec() };
-		let resp = self.asp.empty_oor_mailbox(req).await.context("error fetching oors")?;
-		let oors = resp.into_inner().vtxos.into_iter()
-			.map(|b| Vtxo::decode(&b).context("invalid vtxo from asp"))
-			.collect::<Result<Vec<_>, _>>()?;
+		// Then sync OOR vtxos, we can receive them on any of our keys.
+		debug!("Emptying OOR mailboxes at ASP...");
+		let mut oors = Vec::new();
+		for key in self.own_vtxo_keypairs()? {
+			let req = rpc::OorVtxosRequest { pubkey: key.public_key().serialize().to_vec() };
+			let resp = self.asp.empty_oor_mailbox(req).await.context("error fetching oors")?;
+			for bytes in resp.into_inner().vtxos {
+				oors.push(Vtxo::decode(&bytes).context("invalid vtxo from asp")?);
+			}
+		}
 		debug!("ASP has {} OOR vtxos for us", oors.len());
 		for vtxo in oors {
 			//TODO(stevenroose) verify oor signatures
@@ -461,12 +1512,143 @@ impl Wallet {
 			if self.db.get_vtxo(vtxo.id())?.is_none() {
 				debug!("Storing new OOR vtxo {} with value {}", vtxo.id(), vtxo.spec().amount);
 				self.db.store_vtxo(&vtxo).context("failed to store OOR vtxo")?;
+				let pubkey = vtxo.spec().user_pubkey;
+				if self.db.register_received_key(pubkey)? > 1 {
+					warn!("Received OOR vtxo {} on reused key {}, it can be linked to \
+						other payments on this key", vtxo.id(), pubkey,
+					);
+				}
 			}
 		}
 
 		Ok(())
 	}
 
+	/// Fetch the unspent round vtxos of the given key from the ASP.
+	///
+	/// The vtxos are rebuilt from their round, which has to be signed
+	/// correctly and its round tx has to be confirmed or in the mempool.
+	async fn fetch_round_vtxos(
+		&mut self,
+		key: &Keypair,
+		rounds: &mut HashMap<Txid, Option<SignedVtxoTree>>,
+	) -> anyhow::Result<Vec<Vtxo>> {
+		let pubkey = key.public_key();
+		let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+			.as_millis() as u64;
+		let msg = rpc::vtxos_for_pubkey_message(self.ark_info.asp_pubkey, pubkey, timestamp_ms);
+		let req = rpc::VtxosForPubkeyRequest {
+			pubkey: pubkey.serialize().to_vec(),
+			timestamp_ms,
+			signature: SECP.sign_schnorr(&msg, key).serialize().to_vec(),
+			delegation: None,
+		};
+		let resp = self.asp.get_vtxos_for_pubkey(req).await
+			.context("error fetching vtxos from asp")?;
+
+		let mut ret = Vec::new();
+		for bytes in resp.into_inner().vtxos {
+			let vtxo = Vtxo::decode(&bytes).context("invalid vtxo from asp")?;
+			let leaf_idx = match vtxo {
+				Vtxo::Round { leaf_idx, .. } => leaf_idx,
+				_ => bail!("asp sent a non-round vtxo"),
+			};
+			ensure!(vtxo.spec().user_pubkey == pubkey, "asp sent a vtxo of another pubkey");
+			ensure!(vtxo.spec().asp_pubkey == self.ark_info.asp_pubkey,
+				"asp sent a vtxo with another asp pubkey",
+			);
+
+			let round_txid = vtxo.point().txid;
+			if !rounds.contains_key(&round_txid) {
+				let tree = self.fetch_restored_round(round_txid).await?;
+				rounds.insert(round_txid, tree);
+			}
+			let tree = match rounds.get(&round_txid).unwrap() {
+				Some(t) => t,
+				None => continue,
+			};
+			let rebuilt = tree.vtxo(leaf_idx).context("asp sent a vtxo that's not in its round")?;
+			ensure!(rebuilt.id() == vtxo.id(), "asp sent a vtxo that's not in its round");
+			ret.push(rebuilt);
+		}
+		Ok(ret)
+	}
+
+	/// Fetch the vtxo tree of the round with the given round txid and check it.
+	///
+	/// Returns [None] if the round tx is neither confirmed nor in the mempool.
+	async fn fetch_restored_round(&mut self, round_txid: Txid) -> anyhow::Result<Option<SignedVtxoTree>> {
+		let req = rpc::RoundId { txid: round_txid.to_byte_array().to_vec() };
+		let round = self.asp.get_round(req).await
+			.context("round request failed")?.into_inner();
+		let round_tx = bitcoin::consensus::deserialize::<Transaction>(&round.round_tx)
+			.context("invalid round tx from asp")?;
+		ensure!(round_tx.compute_txid() == round_txid, "asp sent another round tx");
+		let tree = SignedVtxoTree::decode(&round.signed_vtxos)
+			.context("invalid signed vtxo tree from asp")?;
+		ensure!(tree.utxo.txid == round_txid, "asp sent a vtxo tree of another round");
+		if let Err(e) = tree.validate_signatures() {
+			bail!("asp sent an invalid vtxo tree for round {}: {}", round_txid, e);
+		}
+
+		if !self.onchain.tx_in_mempool(round_txid).await?
+			&& !matches!(self.onchain.tx_confirmed(round_txid).await, Ok(Some(_)))
+		{
+			warn!("Not restoring vtxos of round {}, its round tx is not in the chain or mempool",
+				round_txid,
+			);
+			return Ok(None);
+		}
+		Ok(Some(tree))
+	}
+
+	/// Fetch all our unspent round vtxos from the ASP and store the new ones.
+	///
+	/// Unlike [Wallet::sync_ark], this doesn't depend on the last sync height,
+	/// so it also finds vtxos in rounds from before the wallet was restored.
+	/// After a restore from the mnemonic we don't know which vtxo keys we
+	/// derived, so we try derived keys until [RESTORE_GAP_LIMIT] keys in a
+	/// row have no unspent vtxos.
+	///
+	/// Returns the number of vtxos we didn't have yet.
+	pub async fn restore_round_vtxos(&mut self) -> anyhow::Result<usize> {
+		let mut rounds = HashMap::new();
+		let static_key = self.vtxo_seed.to_keypair(&SECP);
+		let mut vtxos = self.fetch_round_vtxos(&static_key, &mut rounds).await?;
+
+		let mut end = self.db.next_vtxo_key_index()? + RESTORE_GAP_LIMIT;
+		let mut idx = 0;
+		while idx < end {
+			let key = self.derive_vtxo_keypair(idx);
+			let key_vtxos = self.fetch_round_vtxos(&key, &mut rounds).await?;
+			if !key_vtxos.is_empty() {
+				// Store the keys up to this one, so that we recognize their
+				// vtxos and don't derive them again.
+				for i in self.db.next_vtxo_key_index()?..=idx {
+					let pubkey = self.derive_vtxo_keypair(i).public_key();
+					self.db.store_vtxo_key_index(i, pubkey).context("failed to store vtxo key")?;
+				}
+				end = cmp::max(end, idx + 1 + RESTORE_GAP_LIMIT);
+			}
+			vtxos.extend(key_vtxos);
+			idx += 1;
+		}
+
+		let mut nb_new = 0;
+		for vtxo in vtxos {
+			if self.db.has_spent_vtxo(vtxo.id())? {
+				debug!("Not restoring vtxo {} because we previously forfeited it", vtxo.id());
+				continue;
+			}
+			if self.db.get_vtxo(vtxo.id())?.is_none() {
+				debug!("Restoring vtxo {} with value {}", vtxo.id(), vtxo.spec().amount);
+				self.db.store_vtxo(&vtxo).context("failed to store restored vtxo")?;
+				nb_new += 1;
+			}
+		}
+		Ok(nb_new)
+	}
+
 	pub async fn offboard_all(&mut self) -> anyhow::Result<()> {
 		let _ = self.onchain.sync().await;
 		self.sync_ark().await.context("failed to sync with ark")?;
@@ -475,7 +1657,7 @@ impl Wallet {
 		let vtxo_sum = input_vtxos.iter().map(|v| v.amount()).sum::<Amount>();
 		let addr = self.onchain.new_address()?;
 
-		self.participate_round(move |_id, offb_fr| {
+		self.participate_round(Vec::new(), move |_id, offb_fr| {
 			let fee = OffboardRequest::calculate_fee(&addr.script_pubkey(), offb_fr)
 				.expect("bdk created invalid scriptPubkey");
 			let offb = OffboardRequest {
@@ -509,7 +1691,7 @@ impl Wallet {
 		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
 		let create = VtxoRequest { pubkey: vtxo_key.public_key(), amount: total_amount };
 
-		self.participate_round(move |_id, _offb_fr| {
+		self.participate_round(Vec::new(), move |_id, _offb_fr| {
 			Ok((expiring_vtxos.clone(), vec![create.clone()], Vec::new()))
 		}).await.context("round failed")?;
 		Ok(())
@@ -522,30 +1704,188 @@ impl Wallet {
 		self.refresh_vtxos(Some(self.config.vtxo_refresh_threshold)).await
 	}
 
-	pub async fn send_oor_payment(&mut self, destination: PublicKey, amount: Amount) -> anyhow::Result<VtxoId> {
-		let current_height = self.onchain.tip().await?;
+	/// Keep refreshing the vtxos of the wallet in `datadir` that are close
+	/// to expiration until `shutdown` resolves.
+	///
+	/// The daemon subscribes to the round events of the ASP. When a round
+	/// starts, and at most once every daemon interval, it syncs the wallet
+	/// and refreshes the vtxos within the configured refresh threshold, as
+	/// well as the vtxos on reused keys with [Config::refresh_reused_keys].
+	/// After every finished round, it syncs the Ark payments. Errors are
+	/// logged and we try again at the next round.
+	///
+	/// The wallet is only opened for these actions and closed again after,
+	/// so that other commands can use it in the meantime. A round we're
+	/// taking part in is finished before we shut down.
+	///
+	/// When the subscription breaks, f.e. because the ASP restarted, we
+	/// re-subscribe with an exponential backoff and resync, since we might
+	/// have missed rounds in the meantime.
+	pub async fn run_refresh_daemon(
+		datadir: &Path,
+		shutdown: impl Future<Output = ()>,
+	) -> anyhow::Result<()> {
+		let config = Self::read_config(datadir)?;
+		ensure!(config.daemon_interval_secs > 0, "daemon interval can't be zero");
+		let check_interval = Duration::from_secs(config.daemon_interval_secs);
+		let mut last_check = None::<tokio::time::Instant>;
+		tokio::pin!(shutdown);
+
+		let mut asp = None::<rpc::ArkServiceClient<tonic::transport::Channel>>;
+		let mut events = None::<tonic::Streaming<rpc::RoundEvent>>;
+		let mut reconnect_backoff = ASP_RECONNECT_MIN_BACKOFF;
+		let reconnect = tokio::time::sleep(Duration::ZERO);
+		tokio::pin!(reconnect);
+
+		info!("Refreshing VTXOs expiring within {} blocks at most every {} seconds",
+			config.vtxo_refresh_threshold, config.daemon_interval_secs,
+		);
+		loop {
+			tokio::select! {
+				_ = &mut reconnect, if events.is_none() => {
+					let res = async {
+						if asp.is_none() {
+							asp = Some(Self::connect_asp(&config).await?.0);
+						}
+						let res = asp.as_mut().unwrap().subscribe_rounds(rpc::Empty {}).await
+							.context("failed to subscribe to round events")?;
+						Ok::<_, anyhow::Error>(res.into_inner())
+					}.await;
+					match res {
+						Ok(stream) => {
+							info!("Subscribed to the round events of the ASP");
+							events = Some(stream);
+							reconnect_backoff = ASP_RECONNECT_MIN_BACKOFF;
+							// We might have missed rounds while we were disconnected.
+							last_check = None;
+						},
+						Err(e) => {
+							warn!("Failed to subscribe to round events, retrying in {:?}: {:#}",
+								reconnect_backoff, e,
+							);
+							reconnect.as_mut().reset(tokio::time::Instant::now() + reconnect_backoff);
+							reconnect_backoff = cmp::min(reconnect_backoff * 2, ASP_RECONNECT_MAX_BACKOFF);
+						},
+					}
+					continue;
+				},
+				event = async { events.as_mut().unwrap().next().await }, if events.is_some() => {
+					match event {
+						Some(Ok(rpc::RoundEvent {
+							event: Some(rpc::round_event::Event::Start(_)),
+						})) => {
+							if last_check.is_some_and(|t| t.elapsed() < check_interval) {
+								continue;
+							}
+						},
+						Some(Ok(rpc::RoundEvent {
+							event: Some(rpc::round_event::Event::Finished(f)),
+						})) => {
+							debug!("Round {} finished, syncing", f.round_id);
+							let res = async {
+								Self::open(datadir).await?.sync_ark().await
+							}.await;
+							if let Err(e) = res {
+								warn!("Failed to sync Ark payments: {:#}", e);
+							}
+							continue;
+						},
+						Some(Ok(_)) => continue,
+						Some(Err(e)) => {
+							warn!("Round event subscription broke, reconnecting: {}", e);
+							events = None;
+							continue;
+						},
+						None => {
+							warn!("Round event subscription was closed, reconnecting");
+							events = None;
+							continue;
+						},
+					}
+				},
+				_ = &mut shutdown => {
+					info!("Shutting down refresh daemon");
+					return Ok(());
+				},
+			}
+
+			last_check = Some(tokio::time::Instant::now());
+			let mut wallet = match Self::open(datadir).await {
+				Ok(w) => w,
+				Err(e) => {
+					warn!("Failed to open wallet: {:#}", e);
+					continue;
+				},
+			};
+			if let Err(e) = wallet.sync().await {
+				warn!("Failed to sync wallet: {:#}", e);
+				continue;
+			}
+			if let Err(e) = wallet.refresh_expiring_vtxos().await {
+				warn!("Failed to refresh VTXOs: {:#}", e);
+			}
+			if wallet.config.refresh_reused_keys {
+				if let Err(e) = wallet.refresh_reused_key_vtxos().await {
+					warn!("Failed to refresh VTXOs on reused keys: {:#}", e);
+				}
+			}
+		}
+	}
+
+	/// Refresh the vtxos on keys that received multiple OOR payments
+	/// into a single vtxo on a fresh key.
+	pub async fn refresh_reused_key_vtxos(&mut self) -> anyhow::Result<()> {
+		let vtxos = self.reused_key_vtxos()?;
+		if vtxos.is_empty() {
+			debug!("No VTXOs on reused keys to refresh.");
+			return Ok(());
+		}
+		let total_amount = vtxos.iter().map(|v| v.amount()).sum::<Amount>();
+		let create = VtxoRequest { pubkey: self.fresh_vtxo_pubkey()?, amount: total_amount };
+		info!("Refreshing {} VTXO(s) on reused keys into fresh key {}", vtxos.len(), create.pubkey);
+
+		self.participate_round(Vec::new(), move |_id, _offb_fr| {
+			Ok((vtxos.clone(), vec![create.clone()], Vec::new()))
+		}).await.context("round failed")?;
+		Ok(())
+	}
+
+	/// Select inputs and calculate the fee for an OOR payment.
+	fn prepare_oor_payment(
+		&self,
+		destination: PublicKey,
+		amount: Amount,
+		change_pubkey: PublicKey,
+	) -> anyhow::Result<ark::oor::OorPayment> {
 		let fr = self.onchain.regular_fee_rate();
-		//TODO(stevenroose) impl key derivation
-		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
+		let min_amount = self.ark_info.oor_min_amount;
+		if amount < min_amount {
+			bail!("OOR payment of {} is below the ASP's minimum of {}", amount, min_amount);
+		}
 		let output = VtxoRequest { pubkey: destination, amount };
+		// Change below the ASP's minimum is added to the fee.
+		let min_change = cmp::max(ark::P2TR_DUST, min_amount);
+		let mut dropped_change = Amount::ZERO;
 
 		// We do some kind of naive fee estimation: we try create a tx,
 		// if we don't have enough fee, we add the fee we were short to
 		// the desired input amount and try again.
 		let mut account_for_fee = ark::oor::OOR_MIN_FEE;
-		let payment = loop {
+		Ok(loop {
 			let input_vtxos = self.db.get_expiring_vtxos(amount + account_for_fee)?;
 			let change = {
 				let sum = input_vtxos.iter().map(|v| v.amount()).sum::<Amount>();
 				let avail = Amount::from_sat(sum.to_sat().saturating_sub(account_for_fee.to_sat()));
 				if avail < output.amount {
-					bail!("Balance too low: {}", sum);
-				} else if avail < output.amount + ark::P2TR_DUST {
+					bail!(InsufficientFunds { available: sum });
+				} else if avail < output.amount + min_change {
+					dropped_change = avail - output.amount;
 					None
 				} else {
+					dropped_change = Amount::ZERO;
 					let change_amount = avail - output.amount;
 					Some(VtxoRequest {
-						pubkey: vtxo_key.public_key(),
+						pubkey: change_pubkey,
 						amount: change_amount,
 					})
 				}
@@ -555,6 +1895,8 @@ impl Wallet {
 			let payment = ark::oor::OorPayment::new(
 				self.ark_info.asp_pubkey,
 				self.ark_info.vtxo_exit_delta,
+				self.ark_info.vtxo_exit_timelock,
+				self.ark_info.vtxo_script_type,
 				input_vtxos,
 				outputs,
 			);
@@ -562,9 +1904,51 @@ impl Wallet {
 			if let Err(ark::oor::InsufficientFunds { missing, .. }) = payment.check_fee(fr) {
 				account_for_fee += missing;
 			} else {
+				if dropped_change > Amount::ZERO {
+					warn!("Change of {} is below the ASP's minimum OOR amount of {}, \
+						it is added to the fee", dropped_change, min_change,
+					);
+				}
 				break payment;
 			}
-		};
+		})
+	}
+
+	/// Preview an OOR payment without sending it.
+	pub fn simulate_oor_payment(
+		&self,
+		destination: PublicKey,
+		amount: Amount,
+	) -> anyhow::Result<SendPreview> {
+		// The change key doesn't matter for the preview.
+		let payment = self.prepare_oor_payment(destination, amount, self.vtxo_pubkey())?;
+		// it's a bit fragile, but if there is a second output, it's our change
+		let change = payment.outputs.get(1).map(|o| o.amount);
+		self.send_preview(payment.inputs, amount, change)
+	}
+
+	fn send_preview(
+		&self,
+		inputs: Vec<Vtxo>,
+		amount: Amount,
+		change: Option<Amount>,
+	) -> anyhow::Result<SendPreview> {
+		let balance = self.db.get_all_vtxos()?.iter().map(|v| v.amount()).sum::<Amount>();
+		let in_sum = inputs.iter().map(|v| v.amount()).sum::<Amount>();
+		let change_amount = change.unwrap_or(Amount::ZERO);
+		Ok(SendPreview {
+			inputs,
+			change,
+			fee: in_sum - amount - change_amount,
+			balance_after: balance - in_sum + change_amount,
+		})
+	}
+
+	pub async fn send_oor_payment(&mut self, destination: PublicKey, amount: Amount) -> anyhow::Result<VtxoId> {
+		let current_height = self.onchain.tip().await?;
+
+		let payment = self.prepare_oor_payment(destination, amount, self.change_pubkey()?)?;
+		let input_keys = self.vtxo_keypairs(&payment.inputs)?;
 		// it's a bit fragile, but if there is a second output, it's our change
 		if let Some(o) = payment.outputs.get(1) {
 			info!("Added change VTXO of {}", o.amount);
@@ -573,8 +1957,8 @@ impl Wallet {
 		let (sec_nonces, pub_nonces) = {
 			let mut secs = Vec::with_capacity(payment.inputs.len());
 			let mut pubs = Vec::with_capacity(payment.inputs.len());
-			for _ in 0..payment.inputs.len() {
-				let (s, p) = musig::nonce_pair(&vtxo_key);
+			for key in &input_keys {
+				let (s, p) = musig::nonce_pair(key);
 				secs.push(s);
 				pubs.push(p);
 			}
@@ -603,7 +1987,7 @@ impl Wallet {
 		trace!("OOR prevouts: {:?}", payment.inputs.iter().map(|i| i.txout()).collect::<Vec<_>>());
 		let input_vtxos = payment.inputs.clone();
 		let tx = payment.sign_finalize_user(
-			&vtxo_key,
+			&input_keys,
 			sec_nonces,
 			&pub_nonces,
 			&asp_pub_nonces,
@@ -631,6 +2015,10 @@ impl Wallet {
 				//TODO(stevenroose) print vtxo in hex after btc fixed hex
 				error!("Failed to store change vtxo from OOR tx: {}", e);
 			}
+			let inputs = input_vtxos.iter().map(|v| v.id());
+			if let Err(e) = self.label_new_vtxo(change_vtxo.id(), inputs, self.label.as_deref()) {
+				error!("Failed to label change vtxo from OOR tx: {}", e);
+			}
 		}
 
 		for v in input_vtxos {
@@ -656,9 +2044,6 @@ impl Wallet {
 
 		let current_height = self.onchain.tip().await?;
 		let fr = self.onchain.regular_fee_rate();
-		//TODO(stevenroose) impl key derivation
-		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
-
 		// We do some kind of naive fee estimation: we try create a tx,
 		// if we don't have enough fee, we add the fee we were short to
 		// the desired input amount and try again.
@@ -677,11 +2062,12 @@ impl Wallet {
 			}
 		};
 
+		let input_keys = self.vtxo_keypairs(&inputs)?;
 		let (sec_nonces, pub_nonces) = {
 			let mut secs = Vec::with_capacity(inputs.len());
 			let mut pubs = Vec::with_capacity(inputs.len());
-			for _ in 0..inputs.len() {
-				let (s, p) = musig::nonce_pair(&vtxo_key);
+			for key in &input_keys {
+				let (s, p) = musig::nonce_pair(key);
 				secs.push(s);
 				pubs.push(p);
 			}
@@ -716,7 +2102,7 @@ impl Wallet {
 		trace!("htlc prevouts: {:?}", inputs.iter().map(|i| i.txout()).collect::<Vec<_>>());
 		let input_vtxos = payment.inputs.clone();
 		let signed = payment.sign_finalize_user(
-			&vtxo_key,
+			&input_keys,
 			sec_nonces,
 			&pub_nonces,
 			&asp_pub_nonces,
@@ -752,6 +2138,10 @@ impl Wallet {
 			//TODO(stevenroose) print vtxo in hex after btc fixed hex
 			error!("Failed to store change vtxo from Bolt11 payment: {}", e);
 		}
+		let inputs = input_vtxos.iter().map(|v| v.id());
+		if let Err(e) = self.label_new_vtxo(change_vtxo.id(), inputs, self.label.as_deref()) {
+			error!("Failed to label change vtxo from Bolt11 payment: {}", e);
+		}
 
 		// Mark the used vtxo's as spent
 		for v in input_vtxos {
@@ -781,46 +2171,74 @@ impl Wallet {
 		Ok((invoice, preimage))
 	}
 
-	/// Send a payment in an Ark round.
-	///
-	/// It is advised to sync your wallet before calling this method.
-	pub async fn send_round_payment(&mut self, destination: PublicKey, amount: Amount) -> anyhow::Result<()> {
-		//TODO(stevenroose) impl key derivation
-		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
-
-		// Prepare the payment.
-		let payment = VtxoRequest { pubkey: destination, amount };
+	/// Select the inputs and change for a round payment.
+	fn prepare_round_payment(
+		&self,
+		amount: Amount,
+		change_pubkey: PublicKey,
+	) -> anyhow::Result<(Vec<Vtxo>, Option<VtxoRequest>)> {
 		let input_vtxos = self.db.get_expiring_vtxos(amount)?;
 		let change = { //TODO(stevenroose) account dust
 			let sum = input_vtxos.iter().map(|v| v.amount()).sum::<Amount>();
-			if sum < payment.amount {
-				bail!("Balance too low: {}", sum);
-			} else if sum == payment.amount {
+			if sum < amount {
+				bail!(InsufficientFunds { available: sum });
+			} else if sum == amount {
 				info!("No change, emptying wallet.");
 				None
 			} else {
-				let amount = sum - payment.amount;
+				let amount = sum - amount;
 				info!("Adding change vtxo for {}", amount);
 				Some(VtxoRequest {
-					pubkey: vtxo_key.public_key(),
+					pubkey: change_pubkey,
 					amount: amount,
 				})
 			}
 		};
+		Ok((input_vtxos, change))
+	}
 
+	/// Preview a round payment without sending it.
+	pub fn simulate_round_payment(&self, amount: Amount) -> anyhow::Result<SendPreview> {
+		let (input_vtxos, change) = self.prepare_round_payment(amount, self.vtxo_pubkey())?;
+		self.send_preview(input_vtxos, amount, change.map(|c| c.amount))
+	}
+
+	/// Send a payment in an Ark round.
+	///
+	/// It is advised to sync your wallet before calling this method.
+	pub async fn send_round_payment(&mut self, destination: PublicKey, amount: Amount) -> anyhow::Result<()> {
+		let (input_vtxos, change) = self.prepare_round_payment(amount, self.change_pubkey()?)?;
+		let payment = VtxoRequest { pubkey: destination, amount };
 		let vtxos = Some(payment).into_iter().chain(change).collect::<Vec<_>>();
-		self.participate_round(move |_id, _offb_fr| {
+		self.participate_round(Vec::new(), move |_id, _offb_fr| {
+			Ok((input_vtxos.clone(), vtxos.clone(), Vec::new()))
+		}).await.context("round failed")?;
+		Ok(())
+	}
+
+	/// Send to a vtxo public key in an Ark round, split over multiple vtxos.
+	pub async fn send_round_payment_split(
+		&mut self,
+		destination: PublicKey,
+		amounts: &[Amount],
+	) -> anyhow::Result<()> {
+		ensure!(!amounts.is_empty(), "no split amounts provided");
+		let amount = amounts.iter().copied().sum::<Amount>();
+		let (input_vtxos, change) = self.prepare_round_payment(amount, self.change_pubkey()?)?;
+		let split = SplitPayment { pubkey: destination, amounts: amounts.to_vec() };
+		let vtxos = change.into_iter().collect::<Vec<_>>();
+		self.participate_round(vec![split], move |_id, _offb_fr| {
 			Ok((input_vtxos.clone(), vtxos.clone(), Vec::new()))
 		}).await.context("round failed")?;
 		Ok(())
 	}
 
-	/// Send to an off-chain address in an Ark round.
+	/// Send to an on-chain address in an Ark round, also called a
+	/// collaborative exit.
 	///
 	/// It is advised to sync your wallet before calling this method.
 	pub async fn send_round_onchain_payment(&mut self, addr: Address, amount: Amount) -> anyhow::Result<()> {
-		//TODO(stevenroose) impl key derivation
-		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
+		let change_pubkey = self.change_pubkey()?;
 
 		// Prepare the payment.
 		let input_vtxos = self.db.get_all_vtxos()?;
@@ -831,36 +2249,34 @@ impl Wallet {
 		).expect("script from address");
 		let in_sum = input_vtxos.iter().map(|v| v.amount()).sum::<Amount>();
 		if in_sum < amount + maybe_fee {
-			bail!("Balance too low");
+			bail!(InsufficientFunds { available: in_sum });
 		}
 
-		self.participate_round(move |_id, offb_fr| {
-			let offb = OffboardRequest {
-				script_pubkey: addr.script_pubkey(),
-				amount: amount,
-			};
-			let out_value = amount + offb.fee(offb_fr).expect("script from address");
-			let change = {
-				if in_sum < out_value {
-					bail!("Balance too low");
-				} else if in_sum <= out_value + ark::P2TR_DUST {
-					info!("No change, emptying wallet.");
-					None
-				} else {
-					let amount = in_sum - out_value;
-					info!("Adding change vtxo for {}", amount);
-					Some(VtxoRequest {
-						pubkey: vtxo_key.public_key(),
-						amount: amount,
-					})
-				}
-			};
-
+		self.participate_round(Vec::new(), move |_id, offb_fr| {
+			let (offb, change) = offboard_outputs(
+				&addr, amount, in_sum, offb_fr, change_pubkey,
+			)?;
 			Ok((input_vtxos.clone(), change.into_iter().collect(), vec![offb]))
 		}).await.context("round failed")?;
 		Ok(())
 	}
 
+	/// Preview sending to an on-chain address in an Ark round.
+	///
+	/// NB The offboard fee rate is only announced by the ASP when the round
+	/// starts, so we estimate it using our regular fee rate.
+	pub fn simulate_round_onchain_payment(
+		&self,
+		addr: Address,
+		amount: Amount,
+	) -> anyhow::Result<SendPreview> {
+		let input_vtxos = self.db.get_all_vtxos()?;
+		let in_sum = input_vtxos.iter().map(|v| v.amount()).sum::<Amount>();
+		let offb_fr = self.onchain.regular_fee_rate();
+		let (_, change) = offboard_outputs(&addr, amount, in_sum, offb_fr, self.vtxo_pubkey())?;
+		self.send_preview(input_vtxos, amount, change.map(|c| c.amount))
+	}
+
 	/// Participate in a round.
 	///
 	/// NB Instead of taking the input and output data as arguments, we take a closure that is
@@ -868,39 +2284,81 @@ impl Wallet {
 	/// offboards is only announced in the beginning of the round and can change between round
 	/// attempts. Lateron this will also be useful so we can randomize destinations between failed
 	/// round attempts for better privacy.
+	/// Check that the onboard txs the vtxo builds on have enough
+	/// confirmations for the ASP to accept it in a round.
+	async fn check_onboard_confirmations(&self, vtxo: &Vtxo) -> anyhow::Result<()> {
+		let required = self.ark_info.onboard_confirmations;
+		if required == 0 {
+			return Ok(());
+		}
+
+		let mut todo = vec![vtxo];
+		while let Some(vtxo) = todo.pop() {
+			match vtxo {
+				Vtxo::Onboard { base, .. } => {
+					let txid = base.utxo.txid;
+					let confirmations = match self.onchain.tx_confirmed(txid).await? {
+						Some(height) => self.onchain.tip().await? + 1 - height,
+						None => 0,
+					};
+					if confirmations < required {
+						bail!("onboard tx {} has {} confirmations, the ASP requires {}",
+							txid, confirmations, required,
+						);
+					}
+				},
+				Vtxo::Round { .. } => {},
+				Vtxo::Oor { inputs, .. } | Vtxo::Bolt11Change { inputs, .. } => {
+					todo.extend(inputs.iter().map(|i| i.as_ref()));
+				},
+			}
+		}
+		Ok(())
+	}
+
 	async fn participate_round(
 		&mut self,
+		splits: Vec<SplitPayment>,
 		mut round_input: impl FnMut(u64, FeeRate) -> anyhow::Result<
 			(Vec<Vtxo>, Vec<VtxoRequest>, Vec<OffboardRequest>)
 		>,
 	) -> anyhow::Result<()> {
-		let current_height = self.onchain.tip().await?;
+		// The inputs of rounds we don't know the outcome of yet aren't in our
+		// spendable vtxos, so we can join with the others in the meantime.
+		self.reconcile_pending_rounds().await?;
+		let pending_round_id = rand::random::<u64>();
 
-		//TODO(stevenroose) impl key derivation
-		let vtxo_key = self.vtxo_seed.to_keypair(&SECP);
+		let current_height = self.onchain.tip().await?;
 
 		info!("Waiting for a round start...");
 		let mut events = self.asp.subscribe_rounds(rpc::Empty {}).await?.into_inner();
 
 		// Wait for the next round start.
-		let (mut round_id, offboard_feerate) = loop {
+		let (mut round_id, mut round_epoch, offboard_feerate) = loop {
 			match events.next().await.context("events stream broke")??.event.unwrap() {
 				rpc::round_event::Event::Start(rpc::RoundStart {
-					round_id, offboard_feerate_sat_vkb,
+					round_id, offboard_feerate_sat_vkb, round_epoch, round_seq,
 				}) => {
+					info!("Round #{} started", round_seq);
 					let offb_fr = FeeRate::from_sat_per_kwu(offboard_feerate_sat_vkb / 4);
-					break (round_id, offb_fr);
+					break (round_id, round_epoch, offb_fr);
 				},
 				_ => {},
 			}
 		};
-		info!("Round started");
 
 		let (input_vtxos, vtxo_reqs, offb_reqs) = round_input(round_id, offboard_feerate)
 			.context("error providing round input")?;
+		for vtxo in &input_vtxos {
+			self.check_onboard_confirmations(vtxo).await
+				.with_context(|| format!("can't use vtxo {} yet", vtxo.id()))?;
+		}
 		let vtxo_ids = input_vtxos.iter().map(|v| v.id()).collect::<HashSet<_>>();
 		debug!("Spending vtxos: {:?}", vtxo_ids);
+		let input_keys = self.vtxo_keypairs(&input_vtxos)?;
 
+		// The attempts we provided forfeit signatures for.
+		let mut attempts = Vec::new();
 
 		'round: loop {
 			let cosign_key = Keypair::new(&SECP, &mut rand::thread_rng());
@@ -921,9 +2379,10 @@ impl Wallet {
 			};
 
 			// The round has now started. We can submit our payment.
+			let network = self.config.network;
 			trace!("Submitting payment request with {} inputs, {} vtxo outputs and {} offboard outputs",
 				input_vtxos.len(), vtxo_reqs.len(), offb_reqs.len());
-			self.asp.submit_payment(rpc::SubmitPaymentRequest {
+			let res = self.asp.submit_payment(rpc::SubmitPaymentRequest {
 				cosign_pubkey: cosign_key.public_key().serialize().to_vec(),
 				input_vtxos: input_vtxos.iter().map(|v| v.encode()).collect(),
 				payments: vtxo_reqs.iter().map(|r| {
@@ -932,17 +2391,44 @@ impl Wallet {
 						destination: Some(rpc::payment::Destination::VtxoPublicKey(
 							r.pubkey.serialize().to_vec(),
 						)),
+						split_amounts: Vec::new(),
 					}
-				}).chain(offb_reqs.iter().map(|r| {
+				}).chain(splits.iter().map(|s| {
 					rpc::Payment {
-						amount: r.amount.to_sat(),
-						destination: Some(rpc::payment::Destination::OffboardSpk(
-							r.script_pubkey.to_bytes(),
+						amount: s.amounts.iter().copied().sum::<Amount>().to_sat(),
+						destination: Some(rpc::payment::Destination::VtxoPublicKey(
+							s.pubkey.serialize().to_vec(),
 						)),
+						split_amounts: s.amounts.iter().map(|a| a.to_sat()).collect(),
+					}
+				})).chain(offb_reqs.iter().map(|r| {
+					// Send the address if we can, so that the ASP can
+					// check that it's for the right network.
+					let destination = match Address::from_script(&r.script_pubkey, network) {
+						Ok(addr) => rpc::payment::Destination::OffboardAddress(addr.to_string()),
+						Err(_) => rpc::payment::Destination::OffboardSpk(r.script_pubkey.to_bytes()),
+					};
+					rpc::Payment {
+						amount: r.amount.to_sat(),
+						destination: Some(destination),
+						split_amounts: Vec::new(),
 					}
 				})).collect(),
 				public_nonces: pub_nonces.iter().map(|n| n.serialize().to_vec()).collect(),
-			}).await.context("submitting payment to asp")?;
+				round_epoch,
+			}).await.context("submitting payment to asp")?.into_inner();
+			if check_submit_response(res).context("asp rejected our payment")? {
+				// Try again in the next round.
+				(round_id, round_epoch) = loop {
+					match events.next().await.context("events stream broke")??.event.unwrap() {
+						rpc::round_event::Event::Start(rpc::RoundStart {
+							round_id, round_epoch, ..
+						}) => break (round_id, round_epoch),
+						_ => {},
+					}
+				};
+				continue 'round;
+			}
 
 
 			// ****************************************************************
@@ -970,13 +2456,19 @@ impl Wallet {
 						break (vtxos, tx, cosigners, vtxo_nonces);
 					},
 					// If a new round started meanwhile, pick up on that one.
-					rpc::round_event::Event::Start(rpc::RoundStart { round_id: id, .. }) => {
+					rpc::round_event::Event::Start(rpc::RoundStart {
+						round_id: id, round_epoch: epoch, ..
+					}) => {
 						warn!("Unexpected new round started...");
 						round_id = id;
+						round_epoch = epoch;
 						continue 'round;
 					},
-					//TODO(stevenroose) make this robust
-					other => panic!("Unexpected message: {:?}", other),
+					// We didn't sign any forfeits yet, so our inputs are still ours.
+					rpc::round_event::Event::Failed(f) if f.round_id == round_id => {
+						bail!(RoundFailed { round_id, reason: f.reason });
+					},
+					other => bail!("unexpected message from ASP: {:?}", other),
 				}
 			};
 
@@ -985,6 +2477,7 @@ impl Wallet {
 
 			// Check that the proposal contains our inputs.
 			let mut my_vtxos = vtxo_reqs.clone();
+			my_vtxos.extend(splits.iter().flat_map(|s| s.vtxo_requests()));
 			for vtxo_req in vtxo_tree.iter_vtxos() {
 				if let Some(i) = my_vtxos.iter().position(|v| v == vtxo_req) {
 					my_vtxos.swap_remove(i);
@@ -1012,6 +2505,26 @@ impl Wallet {
 				bail!("ASP provided incorrect aggregated cosign pubkey");
 			}
 
+			// Check that the vtxo tree outputs will actually be spendable by us.
+			if vtxo_tree.output_key_policy != self.ark_info.vtxo_output_key_policy {
+				bail!("ASP used vtxo output key policy {} while it advertises {}",
+					vtxo_tree.output_key_policy, self.ark_info.vtxo_output_key_policy,
+				);
+			}
+			if vtxo_tree.exit_timelock_type != self.ark_info.vtxo_exit_timelock {
+				bail!("ASP used {} exit timelocks while it advertises {}",
+					vtxo_tree.exit_timelock_type, self.ark_info.vtxo_exit_timelock,
+				);
+			}
+			if vtxo_tree.script_type != self.ark_info.vtxo_script_type {
+				bail!("ASP used {} vtxo scripts while it advertises {}",
+					vtxo_tree.script_type, self.ark_info.vtxo_script_type,
+				);
+			}
+			if round_tx.output.get(0).map(|o| &o.script_pubkey) != Some(&vtxo_tree.cosign_spk()) {
+				bail!("round tx vtxo output doesn't match the vtxo tree");
+			}
+
 			// Make vtxo signatures from top to bottom, just like sighashes are returned.
 			let sighashes = vtxo_tree.sighashes(vtxos_utxo);
 			assert_eq!(sighashes.len(), vtxo_agg_nonces.len());
@@ -1023,7 +2536,7 @@ impl Wallet {
 						&cosign_key,
 						sec_nonce,
 						sighash.to_byte_array(),
-						Some(vtxo_tree.cosign_taptweak().to_byte_array()),
+						vtxo_tree.cosign_taptweak().map(|t| t.to_byte_array()),
 						None,
 					).0
 				}).collect::<Vec<_>>();
@@ -1068,13 +2581,19 @@ impl Wallet {
 						break (vtxos, tx, forfeit_nonces);
 					},
 					// If a new round started meanwhile, pick up on that one.
-					rpc::round_event::Event::Start(rpc::RoundStart { round_id: id, .. }) => {
+					rpc::round_event::Event::Start(rpc::RoundStart {
+						round_id: id, round_epoch: epoch, ..
+					}) => {
 						warn!("Unexpected new round started...");
 						round_id = id;
+						round_epoch = epoch;
 						continue 'round;
 					},
-					//TODO(stevenroose) make this robust
-					other => panic!("Unexpected message: {:?}", other),
+					// We didn't sign any forfeits yet, so our inputs are still ours.
+					rpc::round_event::Event::Failed(f) if f.round_id == round_id => {
+						bail!(RoundFailed { round_id, reason: f.reason });
+					},
+					other => bail!("unexpected message from ASP: {:?}", other),
 				}
 			};
 
@@ -1087,22 +2606,56 @@ impl Wallet {
 				bail!("Received incorrect signed vtxo tree from asp: {}", e);
 			}
 
-			// Make forfeit signatures.
-			let connectors = ConnectorChain::new(
+			// Fetch the connectors to sign our forfeits with and check they
+			// are the ones created by the round tx.
+			let connectors = self.asp.get_round_connectors(rpc::RoundConnectorsRequest { round_id })
+				.await.context("error fetching round connectors")?.into_inner();
+			if connectors.input_weight != ark::connectors::INPUT_WEIGHT.to_wu() {
+				bail!("ASP reported connector input weight of {} WU, expected {}",
+					connectors.input_weight, ark::connectors::INPUT_WEIGHT.to_wu(),
+				);
+			}
+			let connector_value = Amount::from_sat(
+				connectors.connectors.first().context("ASP provided no connectors")?.amount,
+			);
+			if connector_value < ark::fee::DUST {
+				bail!("ASP provided connectors of {}, below the dust limit", connector_value);
+			}
+			let expected = ConnectorChain::new(
 				forfeit_nonces.values().next().unwrap().len(),
 				conns_utxo,
 				self.ark_info.asp_pubkey,
+				connector_value,
 			);
-			let forfeit_signatures = input_vtxos.iter().map(|v| {
-				let sigs = connectors.connectors().enumerate().map(|(i, conn)| {
-					let (sighash, _tx) = ark::forfeit::forfeit_sighash(v, conn);
+			let connector_spk = ConnectorChain::output_script(self.ark_info.asp_pubkey);
+			let connectors = connectors.connectors.into_iter().zip(expected.connectors())
+				.map(|(conn, expected)| {
+					let point = bitcoin::consensus::deserialize::<OutPoint>(&conn.outpoint)
+						.context("invalid connector outpoint")?;
+					ensure!(point == expected, "ASP provided connector {} not in the round tx", point);
+					ensure!(conn.script_pubkey == connector_spk.as_bytes(),
+						"ASP provided connector {} with wrong scriptPubkey", point,
+					);
+					ensure!(conn.amount == connector_value.to_sat(),
+						"ASP provided connector {} with a different value", point,
+					);
+					Ok(point)
+				}).collect::<anyhow::Result<Vec<_>>>()?;
+			if connectors.len() != expected.len() {
+				bail!("ASP provided {} connectors, expected {}", connectors.len(), expected.len());
+			}
+
+			// Make forfeit signatures.
+			let forfeit_signatures = input_vtxos.iter().zip(&input_keys).map(|(v, vtxo_key)| {
+				let sigs = connectors.iter().copied().enumerate().map(|(i, conn)| {
+					let (sighash, _tx) = ark::forfeit::forfeit_sighash(v, conn, connector_value);
 					let asp_nonce = forfeit_nonces.get(&v.id())
 						.with_context(|| format!("missing asp forfeit nonce for {}", v.id()))?
 						.get(i)
 						.context("asp didn't provide enough forfeit nonces")?;
 
 					let (nonce, sig) = musig::deterministic_partial_sign(
-						&vtxo_key,
+						vtxo_key,
 						[vtxo_key.public_key(), self.ark_info.asp_pubkey],
 						[asp_nonce.clone()],
 						sighash.to_byte_array(),
@@ -1112,7 +2665,22 @@ impl Wallet {
 				}).collect::<anyhow::Result<Vec<_>>>()?;
 				Ok((v.id(), sigs))
 			}).collect::<anyhow::Result<HashMap<_, _>>>()?;
-			self.asp.provide_forfeit_signatures(rpc::ForfeitSignaturesRequest {
+			// Once we hand out our forfeits, we have to find out what
+			// happened to the round, even if we crash. Until then, our
+			// inputs can't be spent.
+			attempts.push(RoundAttempt { round_tx: round_tx.clone(), vtxos: vtxos.clone() });
+			self.db.store_pending_round(&PendingRound {
+				id: pending_round_id,
+				round_txid: round_tx.compute_txid(),
+				inputs: input_vtxos.iter().map(|v| v.id()).collect(),
+				input_vtxos: input_vtxos.clone(),
+				attempts: attempts.clone(),
+				label: self.label.clone(),
+			}).context("failed to store pending round")?;
+			for v in &input_vtxos {
+				self.db.remove_vtxo(v.id()).context("failed to lock input vtxo")?;
+			}
+			let res = self.asp.provide_forfeit_signatures(rpc::ForfeitSignaturesRequest {
 				signatures: forfeit_signatures.into_iter().map(|(id, sigs)| {
 					rpc::ForfeitSignatures {
 						input_vtxo_id: id.bytes().to_vec(),
@@ -1120,7 +2688,9 @@ impl Wallet {
 						signatures: sigs.iter().map(|s| s.1.serialize().to_vec()).collect(),
 					}
 				}).collect(),
-			}).await.context("providing signatures to asp")?;
+			}).await.context("providing signatures to asp")?.into_inner();
+			// If we were too late, the ASP starts a new attempt that we pick up below.
+			check_submit_response(res).context("asp rejected our forfeit signatures")?;
 
 
 			// ****************************************************************
@@ -1140,14 +2710,32 @@ impl Wallet {
 						.context("invalid round tx from asp")?;
 					(vtxos, tx)
 				},
+				rpc::round_event::Event::Failed(f) => {
+					if f.round_id != round_id {
+						bail!("Unexpected round ID from round failed event: {} != {}",
+							f.round_id, round_id);
+					}
+					// The ASP has our forfeit signatures, so we only get our
+					// inputs back once it can't finish the round anymore.
+					if let Some(pending) = self.db.get_pending_rounds()?.into_iter()
+						.find(|p| p.id == pending_round_id)
+					{
+						if let Err(e) = self.reconcile_pending_round(pending).await {
+							warn!("Failed to check the outcome of the failed round: {:#}", e);
+						}
+					}
+					bail!(RoundFailed { round_id, reason: f.reason });
+				},
 				// If a new round started meanwhile, pick up on that one.
-				rpc::round_event::Event::Start(rpc::RoundStart { round_id: id, .. }) => {
+				rpc::round_event::Event::Start(rpc::RoundStart {
+					round_id: id, round_epoch: epoch, ..
+				}) => {
 					warn!("Unexpected new round started...");
 					round_id = id;
+					round_epoch = epoch;
 					continue 'round;
 				},
-				//TODO(stevenroose) make this robust
-				other => panic!("Unexpected message: {:?}", other),
+				other => bail!("unexpected message from ASP: {:?}", other),
 			};
 
 			if vtxos != new_vtxos {
@@ -1162,8 +2750,11 @@ impl Wallet {
 
 			// Then add our change vtxo(s) by just checking all vtxos that might be ours.
 			for (idx, dest) in vtxos.spec.vtxos.iter().enumerate() {
-				if dest.pubkey == vtxo_key.public_key() {
-					self.add_new_vtxo(&vtxos, idx)?;
+				if self.is_own_vtxo_pubkey(dest.pubkey)? {
+					if let Some(id) = self.add_new_vtxo(&vtxos, idx)? {
+						let inputs = input_vtxos.iter().map(|v| v.id());
+						self.label_new_vtxo(id, inputs, self.label.as_deref())?;
+					}
 				}
 			}
 
@@ -1173,6 +2764,7 @@ impl Wallet {
 					.context("failed to store forfeited vtxo")?;
 				self.db.remove_vtxo(v.id()).context("failed to drop input vtxo")?;
 			}
+			self.db.remove_pending_round(pending_round_id)?;
 
 			info!("Round finished");
 			break;
@@ -1181,3 +2773,119 @@ impl Wallet {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use ark::{BaseVtxo, VtxoSpec};
+
+	#[test]
+	fn asp_endpoint_tls() {
+		let mut cfg = Config {
+			asp_address: "http://127.0.0.1:3535".into(),
+			..Default::default()
+		};
+		Wallet::asp_endpoint(&cfg).unwrap();
+		cfg.asp_address = "https://asp.example.com".into();
+		Wallet::asp_endpoint(&cfg).unwrap();
+
+		// A pinned certificate needs TLS.
+		cfg.asp_tls_cert = Some("/nonexistent/asp.pem".into());
+		let err = Wallet::asp_endpoint(&cfg).unwrap_err();
+		assert!(err.to_string().contains("failed to read ASP TLS certificate"), "{}", err);
+		cfg.asp_address = "http://127.0.0.1:3535".into();
+		let err = Wallet::asp_endpoint(&cfg).unwrap_err();
+		assert!(err.to_string().contains("not https"), "{}", err);
+
+		cfg.asp_address = "grpc://127.0.0.1:3535".into();
+		Wallet::asp_endpoint(&cfg).unwrap_err();
+	}
+
+	#[test]
+	fn clock_skew() {
+		let asp_time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+		let asp_ms = asp_time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+		let rtt = Duration::from_millis(300);
+
+		// In sync, or within the margin.
+		assert_eq!(clock_skew_warning(asp_ms, asp_time - rtt / 2, asp_time + rtt / 2), None);
+		let ahead = asp_time + Duration::from_secs(5);
+		assert_eq!(clock_skew_warning(asp_ms, ahead, ahead + rtt), None);
+		// A slow answer doesn't count as skew.
+		let slow = Duration::from_secs(60);
+		assert_eq!(clock_skew_warning(asp_ms, asp_time - slow, asp_time + slow), None);
+
+		let ahead = asp_time + Duration::from_secs(120);
+		let msg = clock_skew_warning(asp_ms, ahead, ahead + rtt).unwrap();
+		assert!(msg.contains("120s ahead"), "{}", msg);
+		let behind = asp_time - Duration::from_secs(120);
+		let msg = clock_skew_warning(asp_ms, behind - rtt, behind).unwrap();
+		assert!(msg.contains("120s behind"), "{}", msg);
+
+		// Older ASPs don't report their time.
+		assert_eq!(clock_skew_warning(0, ahead, ahead + rtt), None);
+	}
+
+	#[test]
+	fn vtxo_readiness() {
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let spec = VtxoSpec {
+			user_pubkey: key.public_key(),
+			asp_pubkey: key.public_key(),
+			expiry_height: 1_000,
+			exit_delta: 12,
+			amount: Amount::from_sat(10_000),
+			exit_timelock_type: ExitTimelockType::Relative,
+			script_type: VtxoScriptType::Taproot,
+		};
+		let onboard_txid = Txid::from_byte_array([1; 32]);
+		let round_txid = Txid::from_byte_array([2; 32]);
+		let onboard = Vtxo::Onboard {
+			base: BaseVtxo { spec: spec.clone(), utxo: OutPoint::new(onboard_txid, 0) },
+			reveal_tx_signature: bitcoin::secp256k1::schnorr::Signature::from_slice(&[1; 64])
+				.unwrap(),
+		};
+		let round = Vtxo::Round {
+			base: BaseVtxo { spec, utxo: OutPoint::new(round_txid, 0) },
+			leaf_idx: 0,
+			exit_branch: Vec::new(),
+		};
+		let exitable = VtxoExitStatus { exitable: true, exit_ready_height: None };
+		let tip = 500;
+		let ready = |vtxo: &Vtxo, heights: &[(Txid, u32)]| {
+			let heights = heights.iter().copied().collect::<HashMap<_, _>>();
+			VtxoReadiness::of(vtxo, &exitable, tip, 3, &heights)
+		};
+
+		// An unconfirmed round can be spent offchain but not exited.
+		assert_eq!(ready(&round, &[]), VtxoReadiness {
+			spendable_offchain: true, exitable_onchain: false,
+		});
+		assert_eq!(ready(&round, &[(round_txid, tip)]), VtxoReadiness {
+			spendable_offchain: true, exitable_onchain: true,
+		});
+		// An onboard that's confirmed but not deep enough for the ASP can
+		// only be exited.
+		assert_eq!(ready(&onboard, &[(onboard_txid, tip)]), VtxoReadiness {
+			spendable_offchain: false, exitable_onchain: true,
+		});
+		assert_eq!(ready(&onboard, &[]), VtxoReadiness {
+			spendable_offchain: false, exitable_onchain: false,
+		});
+		assert_eq!(ready(&onboard, &[(onboard_txid, tip - 2)]), VtxoReadiness {
+			spendable_offchain: true, exitable_onchain: true,
+		});
+
+		// A vtxo with a broken exit path can't be exited.
+		let broken = VtxoExitStatus { exitable: false, exit_ready_height: None };
+		let heights = [(round_txid, tip)].into_iter().collect::<HashMap<_, _>>();
+		assert_eq!(VtxoReadiness::of(&round, &broken, tip, 3, &heights), VtxoReadiness {
+			spendable_offchain: true, exitable_onchain: false,
+		});
+		// Nor can an expired vtxo be spent offchain.
+		assert_eq!(VtxoReadiness::of(&round, &exitable, 1_000, 3, &heights), VtxoReadiness {
+			spendable_offchain: false, exitable_onchain: true,
+		});
+	}
+}
@@ -1,12 +1,25 @@
 
+use std::collections::BTreeMap;
 
 use anyhow::Context;
 use bdk_bitcoind_rpc::bitcoincore_rpc::{self, RpcApi};
 use bdk_esplora::esplora_client;
-use bitcoin::{Amount, OutPoint, Transaction, Txid};
+use bitcoin::{Amount, FeeRate, OutPoint, Transaction, Txid};
 
 const TX_ALREADY_IN_CHAIN_ERROR: i32 = -27;
 
+/// Whether a tx would be accepted into the mempool of our chain source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolAcceptance {
+	pub txid: Txid,
+	pub allowed: bool,
+	/// Why the tx would be rejected, if it would be.
+	pub reject_reason: Option<String>,
+	pub vsize: Option<u64>,
+	/// The fee the tx pays, only reported for accepted txs.
+	pub fee: Option<Amount>,
+}
+
 pub enum ChainSource {
 	Bitcoind {
 		url: String,
@@ -65,6 +78,81 @@ impl ChainSourceClient {
 		}
 	}
 
+	/// Broadcast the txs as a package, parents first.
+	///
+	/// This lets a child pay for a parent that doesn't meet the minimum
+	/// relay fee on its own. Esplora has no package relay, there the txs
+	/// are broadcast one by one.
+	pub async fn broadcast_package(&self, txs: &[&Transaction]) -> anyhow::Result<()> {
+		match self {
+			ChainSourceClient::Bitcoind(ref bitcoind) => {
+				let hex = txs.iter().map(|t| bitcoin::consensus::encode::serialize_hex(*t))
+					.collect::<Vec<_>>();
+				let res = bitcoind.call::<serde_json::Value>("submitpackage", &[hex.into()])
+					.context("submitpackage failed")?;
+				let msg = res.get("package_msg").and_then(|m| m.as_str())
+					.context("invalid submitpackage response")?;
+				ensure!(msg == "success", "package rejected: {}: {}", msg, res["tx-results"]);
+				Ok(())
+			},
+			ChainSourceClient::Esplora(_) => {
+				for tx in txs {
+					self.broadcast_tx(tx).await?;
+				}
+				Ok(())
+			},
+		}
+	}
+
+	/// Fee rate estimates of the chain source, keyed by confirmation target.
+	///
+	/// Bitcoind is only asked for the given target, esplora returns
+	/// estimates for a range of targets.
+	pub async fn fee_estimates(&self, conf_target: u16) -> anyhow::Result<BTreeMap<u16, FeeRate>> {
+		match self {
+			ChainSourceClient::Bitcoind(ref bitcoind) => {
+				let res = bitcoind.estimate_smart_fee(conf_target, None)?;
+				let mut ret = BTreeMap::new();
+				if let Some(per_kvb) = res.fee_rate {
+					ret.insert(conf_target, FeeRate::from_sat_per_kwu(per_kvb.to_sat() / 4));
+				} else if let Some(errors) = res.errors {
+					debug!("bitcoind has no fee estimate for target {}: {:?}", conf_target, errors);
+				}
+				Ok(ret)
+			},
+			ChainSourceClient::Esplora(ref client) => {
+				let estimates = client.get_fee_estimates().await?;
+				Ok(estimates.into_iter().map(|(target, sat_per_vb)| {
+					(target, FeeRate::from_sat_per_kwu((sat_per_vb * 250.0).ceil() as u64))
+				}).collect())
+			},
+		}
+	}
+
+	/// Test whether the tx would be accepted into the mempool, without broadcasting it.
+	///
+	/// Returns [None] if the chain source can't test mempool acceptance,
+	/// which is the case for esplora.
+	pub async fn test_mempool_accept(
+		&self,
+		tx: &Transaction,
+	) -> anyhow::Result<Option<MempoolAcceptance>> {
+		match self {
+			ChainSourceClient::Bitcoind(ref bitcoind) => {
+				let res = bitcoind.test_mempool_accept(&[tx])?.into_iter().next()
+					.context("empty testmempoolaccept response")?;
+				Ok(Some(MempoolAcceptance {
+					txid: res.txid,
+					allowed: res.allowed,
+					reject_reason: res.reject_reason,
+					vsize: res.vsize,
+					fee: res.fees.map(|f| f.base),
+				}))
+			},
+			ChainSourceClient::Esplora(_) => Ok(None),
+		}
+	}
+
 	/// Returns the block height the tx is confirmed in, if any.
 	pub async fn tx_confirmed(&self, txid: Txid) -> anyhow::Result<Option<u32>> {
 		let ret = match self {
@@ -90,6 +178,39 @@ impl ChainSourceClient {
 		Ok(ret)
 	}
 
+	/// Whether the tx is in the mempool of the chain source.
+	pub async fn tx_in_mempool(&self, txid: Txid) -> anyhow::Result<bool> {
+		match self {
+			ChainSourceClient::Bitcoind(ref bitcoind) => {
+				Ok(bitcoind.get_mempool_entry(&txid).is_ok())
+			},
+			ChainSourceClient::Esplora(ref client) => {
+				if client.get_tx(&txid).await?.is_none() {
+					return Ok(false);
+				}
+				Ok(!client.get_tx_status(&txid).await?.confirmed)
+			},
+		}
+	}
+
+	/// Whether the given output has been spent by a confirmed tx.
+	///
+	/// NB For bitcoind, this also returns true if the tx of the output is not known.
+	pub async fn txout_spent(&self, outpoint: OutPoint) -> anyhow::Result<bool> {
+		match self {
+			ChainSourceClient::Bitcoind(ref bitcoind) => {
+				let txout = bitcoind.get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?;
+				Ok(txout.is_none())
+			},
+			ChainSourceClient::Esplora(ref client) => {
+				let status = client.get_output_status(&outpoint.txid, outpoint.vout as u64).await?;
+				Ok(status.map_or(false, |s| {
+					s.spent && s.status.map_or(false, |s| s.confirmed)
+				}))
+			},
+		}
+	}
+
 	pub async fn txout_value(&self, outpoint: OutPoint) -> anyhow::Result<Amount> {
 		let tx = match self {
 			ChainSourceClient::Bitcoind(ref bitcoind) => {
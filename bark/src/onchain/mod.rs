@@ -1,10 +1,12 @@
 
 mod chain;
-pub use self::chain::ChainSource;
+pub use self::chain::{ChainSource, ChainSourceClient, MempoolAcceptance};
 
+use std::collections::BTreeMap;
 use std::path::Path;
 
 use anyhow::Context;
+use ark::ExitTimelock;
 use ark::util::TransactionExt;
 use bdk_wallet::SignOptions;
 use bdk_file_store::Store;
@@ -13,18 +15,34 @@ use bitcoin::{
 	bip32, psbt, Address, Amount, FeeRate, Network, OutPoint, Psbt, Sequence, Transaction, TxOut,
 	Txid, Weight,
 };
+use bitcoin::absolute::LockTime;
 
 use crate::exit;
-use crate::psbtext::PsbtInputExt;
-use self::chain::ChainSourceClient;
+use crate::psbtext::{PsbtExt, PsbtInputExt};
 
 const DB_MAGIC: &str = "onchain_bdk";
 
+/// The fee rate we use when our chain source has no estimate for us.
+const FALLBACK_FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(2500);
+
+/// The estimate for the given confirmation target.
+///
+/// When there is none for the exact target, we take the one for the closest
+/// lower target, which is a bit more expensive.
+fn fee_rate_for_target(estimates: &BTreeMap<u16, FeeRate>, conf_target: u16) -> Option<FeeRate> {
+	estimates.range(..=conf_target).next_back().map(|(_, r)| *r)
+}
+
 pub struct Wallet {
 	wallet: bdk_wallet::Wallet,
 	//TODO(stevenroose) integrate into our own db
 	wallet_db: Store<bdk_wallet::ChangeSet>,
 	chain_source: ChainSourceClient,
+	/// The confirmation target for our fee estimates.
+	conf_target: u16,
+	/// Fee estimates to use instead of those of the chain source.
+	#[cfg(test)]
+	mock_fee_estimates: Option<BTreeMap<u16, FeeRate>>,
 }
 
 impl Wallet {
@@ -33,6 +51,7 @@ impl Wallet {
 		seed: [u8; 64],
 		dir: &Path,
 		chain_source: ChainSource,
+		conf_target: u16,
 	) -> anyhow::Result<Wallet> {
 		let db_path = dir.join("bdkwallet.db");
 		let mut db = Store::<bdk_wallet::ChangeSet>::open_or_create_new(DB_MAGIC.as_bytes(), db_path)?;
@@ -60,7 +79,14 @@ impl Wallet {
 			},
 		};
 		let chain_source = ChainSourceClient::new(chain_source)?;
-		Ok(Wallet { wallet, wallet_db: db, chain_source })
+		Ok(Wallet {
+			wallet,
+			wallet_db: db,
+			chain_source,
+			conf_target,
+			#[cfg(test)]
+			mock_fee_estimates: None,
+		})
 	}
 
 	pub async fn tip(&self) -> anyhow::Result<u32> {
@@ -71,15 +97,37 @@ impl Wallet {
 		self.chain_source.broadcast_tx(tx).await
 	}
 
+	/// Broadcast the txs as a package, parents first.
+	pub async fn broadcast_package(&self, txs: &[&Transaction]) -> anyhow::Result<()> {
+		self.chain_source.broadcast_package(txs).await
+	}
+
+	/// Test whether the tx would be accepted into the mempool, without broadcasting it.
+	pub async fn test_mempool_accept(
+		&self,
+		tx: &Transaction,
+	) -> anyhow::Result<Option<MempoolAcceptance>> {
+		self.chain_source.test_mempool_accept(tx).await
+	}
+
 	/// Returns the block height the tx is confirmed in, if any.
 	pub async fn tx_confirmed(&self, txid: Txid) -> anyhow::Result<Option<u32>> {
 		self.chain_source.tx_confirmed(txid).await
 	}
 
+	pub async fn tx_in_mempool(&self, txid: Txid) -> anyhow::Result<bool> {
+		self.chain_source.tx_in_mempool(txid).await
+	}
+
 	pub async fn txout_value(&self, outpoint: OutPoint) -> anyhow::Result<Amount> {
 		self.chain_source.txout_value(outpoint).await
 	}
 
+	/// Whether the given output has been spent by a confirmed tx.
+	pub async fn txout_spent(&self, outpoint: OutPoint) -> anyhow::Result<bool> {
+		self.chain_source.txout_spent(outpoint).await
+	}
+
 	pub async fn sync(&mut self) -> anyhow::Result<Amount> {
 		debug!("Starting wallet sync...");
 
@@ -126,22 +174,53 @@ impl Wallet {
 		self.wallet.balance().total()
 	}
 
+	/// Our balance after the given tx would be confirmed.
+	pub fn balance_after(&self, tx: &Transaction) -> Amount {
+		let (sent, received) = self.wallet.sent_and_received(tx);
+		(self.balance() + received).checked_sub(sent).unwrap_or(Amount::ZERO)
+	}
+
 	/// Fee rate to use for regular txs like onboards.
 	pub fn regular_fee_rate(&self) -> FeeRate {
 		FeeRate::from_sat_per_vb(10).unwrap()
 	}
 
-	/// Fee rate to use for urgent txs like exits.
-	fn urgent_fee_rate(&self) -> FeeRate {
-		//TODO(stevenroose) get from somewhere
-		FeeRate::from_sat_per_vb(15).unwrap()
+	/// Fee rate to use for our onchain txs, estimated for our confirmation target.
+	///
+	/// Falls back to a fixed fee rate if the chain source has no estimate.
+	pub async fn fee_rate(&self) -> FeeRate {
+		match self.fee_estimates().await {
+			Ok(estimates) => fee_rate_for_target(&estimates, self.conf_target).unwrap_or_else(|| {
+				debug!("No fee estimate for target {}, using fallback fee rate", self.conf_target);
+				FALLBACK_FEE_RATE
+			}),
+			Err(e) => {
+				warn!("Error getting fee estimates, using fallback fee rate: {}", e);
+				FALLBACK_FEE_RATE
+			},
+		}
+	}
+
+	async fn fee_estimates(&self) -> anyhow::Result<BTreeMap<u16, FeeRate>> {
+		#[cfg(test)]
+		if let Some(ref estimates) = self.mock_fee_estimates {
+			return Ok(estimates.clone());
+		}
+		self.chain_source.fee_estimates(self.conf_target).await
+	}
+
+	pub async fn prepare_tx(&mut self, dest: Address, amount: Amount) -> anyhow::Result<Psbt> {
+		self.prepare_tx_many(&[(dest, amount)]).await
 	}
 
-	pub fn prepare_tx(&mut self, dest: Address, amount: Amount) -> anyhow::Result<Psbt> {
-		let fee_rate = self.regular_fee_rate();
+	/// Prepare a tx paying to all destinations, in the order given.
+	pub async fn prepare_tx_many(&mut self, dests: &[(Address, Amount)]) -> anyhow::Result<Psbt> {
+		let fee_rate = self.fee_rate().await;
 		let mut b = self.wallet.build_tx();
 		b.ordering(bdk_wallet::tx_builder::TxOrdering::Untouched);
-		b.add_recipient(dest.script_pubkey(), amount);
+		for (dest, amount) in dests {
+			b.add_recipient(dest.script_pubkey(), *amount);
+		}
 		b.fee_rate(fee_rate);
 		b.enable_rbf();
 		Ok(b.finish()?)
@@ -161,8 +240,18 @@ impl Wallet {
 		Ok(psbt.extract_tx()?)
 	}
 
+	/// Forget about a tx we created but that will never be broadcast,
+	/// so that its inputs can be spent again.
+	pub fn cancel_tx(&mut self, tx: &Transaction) -> anyhow::Result<()> {
+		self.wallet.cancel_tx(tx);
+		if let Some(change) = self.wallet.take_staged() {
+			self.wallet_db.append_changeset(&change)?;
+		}
+		Ok(())
+	}
+
 	pub async fn send_money(&mut self, dest: Address, amount: Amount) -> anyhow::Result<Txid> {
-		let psbt = self.prepare_tx(dest, amount)?;
+		let psbt = self.prepare_tx(dest, amount).await?;
 		let tx = self.finish_tx(psbt)?;
 		self.broadcast_tx(&tx).await?;
 		Ok(tx.compute_txid())
@@ -190,16 +279,18 @@ impl Wallet {
 				}),
 				..Default::default()
 			};
-			b.add_foreign_utxo(*utxo, psbt_in, Weight::from_wu(33)).expect("adding foreign utxo");
+			b.add_foreign_utxo(*utxo, psbt_in, ark::fee::DUST_ANCHOR_SATISFACTION_WEIGHT).expect("adding foreign utxo");
 		}
 	}
 
-	/// Create a cpfp spend that spends the fee anchors in the given txs.
+	/// Create a cpfp spend that spends the fee anchors in the given txs,
+	/// so that the whole package pays the given fee rate.
 	///
 	/// This method doesn't broadcast any txs.
 	pub async fn make_cpfp(
 		&mut self,
 		txs: &[&Transaction],
+		fee_rate: FeeRate,
 	) -> anyhow::Result<Transaction> {
 		let anchors = txs.iter().map(|tx| {
 			tx.fee_anchor().with_context(|| format!("tx {} has no fee anchor", tx.compute_txid()))
@@ -230,8 +321,7 @@ impl Wallet {
 		};
 		let package_weight = txs.iter().map(|t| t.weight()).sum::<Weight>();
 
-		let urgent_fee_rate = self.urgent_fee_rate();
-		let extra_fee_needed = (urgent_fee_rate * package_weight) - existing_fee;
+		let extra_fee_needed = (fee_rate * package_weight) - existing_fee;
 
 		// Since BDK doesn't allow tx without recipients, we add a drain output.
 		let change_addr = self.wallet.next_unused_address(bdk_wallet::KeychainKind::Internal);
@@ -240,7 +330,7 @@ impl Wallet {
 			let mut b = self.wallet.build_tx();
 			Wallet::add_anchors(&mut b, &anchors);
 			b.add_recipient(change_addr.address.script_pubkey(), extra_fee_needed + ark::P2TR_DUST);
-			b.fee_rate(urgent_fee_rate);
+			b.fee_rate(fee_rate);
 			let mut psbt = b.finish().expect("failed to craft anchor spend template");
 			let opts = SignOptions {
 				trust_witness_utxo: true,
@@ -253,7 +343,7 @@ impl Wallet {
 		};
 
 		let total_weight = template_weight + package_weight;
-		let total_fee = self.urgent_fee_rate() * total_weight;
+		let total_fee = fee_rate * total_weight;
 		let extra_fee_needed = total_fee - existing_fee;
 
 		// Then build actual tx.
@@ -268,17 +358,35 @@ impl Wallet {
 		Ok(tx)
 	}
 
-	pub async fn create_exit_claim_tx(&mut self, inputs: &[exit::ClaimInput]) -> anyhow::Result<Psbt> {
+	pub async fn create_exit_claim_tx(
+		&mut self,
+		inputs: &[exit::ClaimInput],
+		fee_rate: FeeRate,
+	) -> anyhow::Result<Psbt> {
 		assert!(!inputs.is_empty());
 		self.sync().await.context("sync error")?;
+		self.build_exit_claim_tx(inputs, fee_rate)
+	}
 
-		let urgent_fee_rate = self.urgent_fee_rate();
-
+	fn build_exit_claim_tx(
+		&mut self,
+		inputs: &[exit::ClaimInput],
+		fee_rate: FeeRate,
+	) -> anyhow::Result<Psbt> {
 		// Since BDK doesn't allow tx without recipients, we add a drain output.
 		let change_addr = self.wallet.next_unused_address(bdk_wallet::KeychainKind::Internal);
 
+		// Absolute exit timelocks need a tx locktime at or after their height.
+		let locktime = inputs.iter().filter_map(|i| match i.spec.exit_timelock() {
+			ExitTimelock::Absolute(h) => Some(h),
+			ExitTimelock::Relative(_) => None,
+		}).max();
+
 		let mut b = self.wallet.build_tx();
 		b.version(2);
+		if let Some(h) = locktime {
+			b.nlocktime(LockTime::from_height(h).expect("valid block height"));
+		}
 		for input in inputs {
 			let mut psbt_in = psbt::Input::default();
 			psbt_in.set_claim_input(input);
@@ -290,12 +398,90 @@ impl Wallet {
 				input.utxo,
 				psbt_in,
 				input.satisfaction_weight(),
-				Sequence::from_height(input.spec.exit_delta),
+				match input.spec.exit_timelock() {
+					ExitTimelock::Relative(delta) => Sequence::from_height(delta),
+					ExitTimelock::Absolute(_) => Sequence::ENABLE_LOCKTIME_NO_RBF,
+				},
 			).expect("error adding foreign utxo for claim input");
 		}
 		b.drain_to(change_addr.address.script_pubkey());
-		b.fee_rate(urgent_fee_rate);
+		b.fee_rate(fee_rate);
+
+		let mut psbt = b.finish().context("failed to craft claim tx")?;
+		psbt.set_ext_version();
+		Ok(psbt)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::fs;
+	use std::str::FromStr;
+
+	use bitcoin::secp256k1::{rand, Keypair};
+	use ark::{ExitTimelockType, VtxoScriptType, VtxoSpec};
+
+	use crate::SECP;
+	use super::*;
+
+	/// The fee rate and the fee of a claim tx of a wallet with the given
+	/// confirmation target, that uses the given fee estimates.
+	async fn claim_fee(
+		input: &exit::ClaimInput,
+		estimates: &BTreeMap<u16, FeeRate>,
+		conf_target: u16,
+	) -> (FeeRate, Amount) {
+		let datadir = std::env::temp_dir().join(format!(
+			"bark-test-conf-target-{}-{}", conf_target, std::process::id(),
+		));
+		let _ = fs::remove_dir_all(&datadir);
+		fs::create_dir_all(&datadir).unwrap();
+
+		// The chain source is never contacted.
+		let chain_source = ChainSource::Esplora { url: "http://127.0.0.1:1".into() };
+		let mut wallet = Wallet::create(
+			Network::Regtest, [7; 64], &datadir, chain_source, conf_target,
+		).unwrap();
+		wallet.mock_fee_estimates = Some(estimates.clone());
+		let fee_rate = wallet.fee_rate().await;
+		let psbt = wallet.build_exit_claim_tx(std::slice::from_ref(input), fee_rate).unwrap();
+
+		drop(wallet);
+		fs::remove_dir_all(&datadir).unwrap();
+		(fee_rate, psbt.fee().unwrap())
+	}
+
+	#[tokio::test]
+	async fn conf_target_changes_exit_fee() {
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let input = exit::ClaimInput {
+			utxo: OutPoint::from_str(
+				"0000000000000000000000000000000000000000000000000000000000000001:0",
+			).unwrap(),
+			spec: VtxoSpec {
+				user_pubkey: key.public_key(),
+				asp_pubkey: key.public_key(),
+				expiry_height: 1_000,
+				exit_delta: 12,
+				amount: Amount::from_sat(100_000),
+				exit_timelock_type: ExitTimelockType::Relative,
+				script_type: VtxoScriptType::Taproot,
+			},
+		};
 
-		Ok(b.finish().context("failed to craft claim tx")?)
+		// A mock estimator, with an estimate for the next block and for 6 blocks.
+		let estimates = [
+			(1, FeeRate::from_sat_per_vb(20).unwrap()),
+			(6, FeeRate::from_sat_per_vb(5).unwrap()),
+		].into_iter().collect::<BTreeMap<_, _>>();
+		assert_eq!(fee_rate_for_target(&estimates, 3), Some(FeeRate::from_sat_per_vb(20).unwrap()));
+		assert_eq!(fee_rate_for_target(&estimates, 144), Some(FeeRate::from_sat_per_vb(5).unwrap()));
+		assert_eq!(fee_rate_for_target(&BTreeMap::new(), 6), None);
+
+		let (urgent_rate, urgent) = claim_fee(&input, &estimates, 1).await;
+		let (relaxed_rate, relaxed) = claim_fee(&input, &estimates, 6).await;
+		assert_eq!(urgent_rate, FeeRate::from_sat_per_vb(20).unwrap());
+		assert_eq!(relaxed_rate, FeeRate::from_sat_per_vb(5).unwrap());
+		assert!(urgent > relaxed, "{} <= {}", urgent, relaxed);
 	}
 }
@@ -1,13 +1,32 @@
 
+//! Proprietary PSBT fields holding bark metadata.
+//!
+//! External tooling, f.e. to sign exit claims offline or to hand them to
+//! a watchtower, can read these fields with [PsbtInputExt] and strip them
+//! with [PsbtExt] before sharing a PSBT with third parties.
+//!
+//! All fields are BIP-174 proprietary keys with the prefix `bark`. In
+//! version [PSBT_EXT_VERSION] of the layout, these are:
+//!
+//! - global, subtype 0: the layout version, see [ark::psbtext].
+//! - input, subtype 1: the claim input of an input spending the output of
+//!   an exited vtxo. The key is empty, the value the CBOR encoding of
+//!   [ClaimInput].
 
 use std::borrow::{Borrow, BorrowMut};
+use std::fmt;
 
+use ark::psbtext;
 use bitcoin::{psbt, sighash, taproot, Transaction, TxOut, Witness};
+use bitcoin::hex::DisplayHex;
 use bitcoin::secp256k1::{self, Keypair};
 
-use crate::exit;
+use crate::exit::ClaimInput;
 
 
+/// The version of the layout of our proprietary PSBT fields.
+pub const PSBT_EXT_VERSION: u8 = 1;
+
 const PROP_KEY_PREFIX: &'static [u8] = "bark".as_bytes();
 
 enum PropKey {
@@ -15,26 +34,45 @@ enum PropKey {
 }
 
 lazy_static::lazy_static! {
-	static ref PROP_KEY_CLAIM_INPUT: psbt::raw::ProprietaryKey = psbt::raw::ProprietaryKey {
-		prefix: PROP_KEY_PREFIX.to_vec(),
-		subtype: PropKey::ClaimInput as u8,
-		key: Vec::new(),
-	};
+	static ref PROP_KEY_CLAIM_INPUT: psbt::raw::ProprietaryKey =
+		psbtext::prop_key(PROP_KEY_PREFIX, PropKey::ClaimInput as u8, Vec::new());
+}
+
+/// Error when reading our proprietary fields from a PSBT.
+#[derive(Debug)]
+pub enum PsbtExtError {
+	/// The value of the claim input field doesn't decode.
+	InvalidClaimInput(String),
+	/// The value of the version field isn't a single byte.
+	InvalidVersion(Vec<u8>),
 }
 
-//TODO(stevenroose) the "corrupt psbt" expects are only safe if all psbts stay
-// within internal use, if we ever share them for communication or in a db,
-// they need to return errors
+impl fmt::Display for PsbtExtError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PsbtExtError::InvalidClaimInput(e) => write!(f, "invalid claim input in psbt: {}", e),
+			PsbtExtError::InvalidVersion(v) => {
+				write!(f, "invalid psbt field layout version: {}", v.as_hex())
+			},
+		}
+	}
+}
+
+impl std::error::Error for PsbtExtError {}
+
 pub trait PsbtInputExt: BorrowMut<psbt::Input> {
-	fn set_claim_input(&mut self, input: &exit::ClaimInput) {
+	fn set_claim_input(&mut self, input: &ClaimInput) {
 		self.borrow_mut().proprietary.insert(PROP_KEY_CLAIM_INPUT.clone(), input.encode());
 	}
 
-	fn get_claim_input(&self) -> Option<exit::ClaimInput> {
+	fn get_claim_input(&self) -> Result<Option<ClaimInput>, PsbtExtError> {
 		self.borrow().proprietary.get(&*PROP_KEY_CLAIM_INPUT)
-			.map(|e| exit::ClaimInput::decode(&e).expect("corrupt psbt"))
+			.map(|e| ClaimInput::decode(&e))
+			.transpose()
+			.map_err(|e| PsbtExtError::InvalidClaimInput(e.to_string()))
 	}
 
+	/// Sign the input if it has a claim input field.
 	fn try_sign_claim_input(
 		&mut self,
 		secp: &secp256k1::Secp256k1<impl secp256k1::Signing>,
@@ -42,11 +80,12 @@ pub trait PsbtInputExt: BorrowMut<psbt::Input> {
 		prevouts: &sighash::Prevouts<impl Borrow<TxOut>>,
 		input_idx: usize,
 		vtxo_key: &Keypair,
-	) {
-		let claim = if let Some(c) = self.get_claim_input() {
+	) -> Result<(), PsbtExtError> {
+		// We only sign psbts we created ourselves.
+		let claim = if let Some(c) = self.get_claim_input()? {
 			c
 		} else {
-			return;
+			return Ok(());
 		};
 
 		// Now we need to sign for this.
@@ -71,8 +110,122 @@ pub trait PsbtInputExt: BorrowMut<psbt::Input> {
 		);
 		debug_assert_eq!(bitcoin::Weight::from_wu(wit.size() as u64), claim.satisfaction_weight());
 		self.borrow_mut().final_script_witness = Some(wit);
-
+		Ok(())
 	}
 }
 
 impl PsbtInputExt for psbt::Input {}
+
+pub trait PsbtExt: BorrowMut<psbt::Psbt> {
+	/// Write [PSBT_EXT_VERSION] into the PSBT.
+	fn set_ext_version(&mut self) {
+		psbtext::set_version(self.borrow_mut(), PROP_KEY_PREFIX, PSBT_EXT_VERSION);
+	}
+
+	/// The version of the layout of our fields in the PSBT, if it has one.
+	fn get_ext_version(&self) -> Result<Option<u8>, PsbtExtError> {
+		psbtext::get_version(self.borrow(), PROP_KEY_PREFIX).map_err(PsbtExtError::InvalidVersion)
+	}
+
+	/// Remove all our proprietary fields from the PSBT, returning how many
+	/// were removed. Proprietary fields of others are kept.
+	fn strip_proprietary(&mut self) -> usize {
+		psbtext::strip_proprietary(self.borrow_mut(), PROP_KEY_PREFIX)
+	}
+}
+
+impl PsbtExt for psbt::Psbt {}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	use std::str::FromStr;
+
+	use bitcoin::{Amount, OutPoint};
+	use bitcoin::secp256k1::rand;
+
+	use ark::{ExitTimelockType, VtxoScriptType, VtxoSpec};
+	use ark::test_util::dummy_psbt;
+
+	use crate::SECP;
+
+	fn claim_input() -> ClaimInput {
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		ClaimInput {
+			utxo: OutPoint::from_str(
+				"0000000000000000000000000000000000000000000000000000000000000001:0",
+			).unwrap(),
+			spec: VtxoSpec {
+				user_pubkey: key.public_key(),
+				asp_pubkey: key.public_key(),
+				expiry_height: 1_000,
+				exit_delta: 12,
+				amount: Amount::from_sat(10_000),
+				exit_timelock_type: ExitTimelockType::Relative,
+				script_type: VtxoScriptType::Taproot,
+			},
+		}
+	}
+
+	/// A PSBT whose first input is the claim input, and the second isn't.
+	fn claim_psbt(claim: &ClaimInput) -> psbt::Psbt {
+		let mut psbt = dummy_psbt([claim.utxo, OutPoint::null()]);
+		psbt.inputs[0].set_claim_input(claim);
+		psbt.set_ext_version();
+		psbt
+	}
+
+	#[test]
+	fn claim_input_roundtrip() {
+		let claim = claim_input();
+		let psbt = claim_psbt(&claim);
+
+		let decoded = psbt::Psbt::deserialize(&psbt.serialize()).unwrap();
+		assert_eq!(decoded.get_ext_version().unwrap(), Some(PSBT_EXT_VERSION));
+		let read = decoded.inputs[0].get_claim_input().unwrap().unwrap();
+		assert_eq!(read.encode(), claim.encode());
+		assert!(decoded.inputs[1].get_claim_input().unwrap().is_none());
+
+		let mut input = psbt::Input::default();
+		input.proprietary.insert(PROP_KEY_CLAIM_INPUT.clone(), vec![0xff, 0x00]);
+		assert!(matches!(input.get_claim_input(), Err(PsbtExtError::InvalidClaimInput(_))));
+
+		// Signing a psbt with a corrupt claim input fails instead of panicking.
+		let mut psbt = claim_psbt(&claim);
+		psbt.inputs[0].proprietary.insert(PROP_KEY_CLAIM_INPUT.clone(), vec![0xff, 0x00]);
+		let prevouts = vec![TxOut {
+			value: Amount::from_sat(10_000),
+			script_pubkey: bitcoin::ScriptBuf::new(),
+		}; 2];
+		let mut shc = sighash::SighashCache::new(&psbt.unsigned_tx);
+		let key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let res = psbt.inputs[0].try_sign_claim_input(
+			&SECP, &mut shc, &sighash::Prevouts::All(&prevouts), 0, &key,
+		);
+		assert!(matches!(res, Err(PsbtExtError::InvalidClaimInput(_))));
+	}
+
+	#[test]
+	fn strip_proprietary() {
+		let claim = claim_input();
+		let mut psbt = claim_psbt(&claim);
+		let other_key = psbt::raw::ProprietaryKey {
+			prefix: "other".as_bytes().to_vec(),
+			subtype: PropKey::ClaimInput as u8,
+			key: Vec::new(),
+		};
+		psbt.inputs[0].proprietary.insert(other_key.clone(), vec![1]);
+		psbt.outputs[0].proprietary.insert(PROP_KEY_CLAIM_INPUT.clone(), claim.encode());
+
+		let mut decoded = psbt::Psbt::deserialize(&psbt.serialize()).unwrap();
+		assert_eq!(decoded.strip_proprietary(), 3);
+		assert_eq!(decoded.strip_proprietary(), 0);
+		let decoded = psbt::Psbt::deserialize(&decoded.serialize()).unwrap();
+		assert!(decoded.inputs[0].get_claim_input().unwrap().is_none());
+		assert!(decoded.outputs[0].proprietary.is_empty());
+		assert_eq!(decoded.get_ext_version().unwrap(), None);
+		assert_eq!(decoded.inputs[0].proprietary.get(&other_key), Some(&vec![1]));
+		assert_eq!(decoded.unsigned_tx, psbt.unsigned_tx);
+	}
+}
@@ -0,0 +1,271 @@
+//! Watch-only wallets.
+//!
+//! A watch-only wallet holds none of the keys of the wallet it watches. It
+//! is created from a watch key, a watchtower bundle or both, and can show
+//! the balance and the expiry of the vtxos, but it can't spend them.
+//!
+//! With a watch key, it lists the round vtxos of the watched vtxo pubkeys
+//! with the ASP, so it also sees the vtxos the wallet receives in later
+//! rounds. The ASP doesn't keep the vtxos of OOR payments, those are only
+//! seen if they are in the bundle. Vtxos in the bundle are dropped once
+//! the ASP knows them to be spent.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use bitcoin::{Amount, Network};
+use bitcoin::secp256k1::{rand, schnorr, Keypair, PublicKey, SecretKey};
+
+use ark::Vtxo;
+use aspd_rpc_client as rpc;
+
+use crate::{Config, Wallet, WatchtowerBundle, WatchtowerVtxo, SECP};
+
+
+/// The file in the datadir of a watch-only wallet that holds its bundle.
+const WATCH_BUNDLE_FILE: &str = "watch_bundle";
+
+/// The file in the datadir of a watch-only wallet that holds its watch key.
+const WATCH_KEY_FILE: &str = "watch_key";
+
+/// The current version of the watch key format.
+pub const WATCH_KEY_VERSION: u8 = 1;
+
+/// The number of vtxo keys beyond the ones already derived that a watch
+/// key covers, so that it keeps working while the wallet derives new keys.
+pub const WATCH_KEY_LOOKAHEAD: u32 = 20;
+
+/// A vtxo pubkey of the watched wallet and its delegation to the watch key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchedPubkey {
+	pub pubkey: PublicKey,
+	/// Signature with the pubkey over [rpc::watch_delegation_message].
+	pub delegation: schnorr::Signature,
+}
+
+/// A key that allows a watch-only wallet to list the round vtxos of the
+/// vtxo pubkeys of a wallet with the ASP.
+///
+/// It covers our static vtxo key and the first derived vtxo keys, up to
+/// [WATCH_KEY_LOOKAHEAD] past the ones we derived so far. It gives no
+/// access to any funds.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchKey {
+	pub version: u8,
+	pub network: Network,
+	/// The key the watch-only wallet signs its requests to the ASP with.
+	pub watch_key: SecretKey,
+	pub pubkeys: Vec<WatchedPubkey>,
+}
+
+impl WatchKey {
+	pub fn encode(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(self, &mut buf).unwrap();
+		buf
+	}
+
+	pub fn decode(bytes: &[u8]) -> Result<Self, ciborium::de::Error<io::Error>> {
+		ciborium::from_reader(bytes)
+	}
+
+	/// Decode a watch key and check all the delegations in it.
+	pub fn import(bytes: &[u8]) -> anyhow::Result<Self> {
+		let ret = WatchKey::decode(bytes).context("invalid watch key")?;
+		ensure!(ret.version == WATCH_KEY_VERSION,
+			"unsupported watch key version: {}", ret.version,
+		);
+		let watch_pubkey = ret.watch_keypair().public_key();
+		for p in &ret.pubkeys {
+			let msg = rpc::watch_delegation_message(p.pubkey, watch_pubkey);
+			SECP.verify_schnorr(&p.delegation, &msg, &p.pubkey.x_only_public_key().0)
+				.with_context(|| format!("invalid delegation for pubkey {}", p.pubkey))?;
+		}
+		Ok(ret)
+	}
+
+	fn watch_keypair(&self) -> Keypair {
+		Keypair::from_secret_key(&SECP, &self.watch_key)
+	}
+}
+
+impl Wallet {
+	/// Create a watch key for our vtxo pubkeys.
+	pub fn export_watch_key(&self) -> anyhow::Result<WatchKey> {
+		let watch_key = Keypair::new(&SECP, &mut rand::thread_rng());
+		let mut keys = vec![self.vtxo_seed.to_keypair(&SECP)];
+		for idx in 0..self.db.next_vtxo_key_index()? + WATCH_KEY_LOOKAHEAD {
+			keys.push(self.derive_vtxo_keypair(idx));
+		}
+		let pubkeys = keys.iter().map(|key| {
+			let msg = rpc::watch_delegation_message(key.public_key(), watch_key.public_key());
+			WatchedPubkey {
+				pubkey: key.public_key(),
+				delegation: SECP.sign_schnorr(&msg, key),
+			}
+		}).collect();
+		Ok(WatchKey {
+			version: WATCH_KEY_VERSION,
+			network: self.config.network,
+			watch_key: watch_key.secret_key(),
+			pubkeys,
+		})
+	}
+}
+
+pub struct WatchOnlyWallet {
+	config: Config,
+	bundle: Option<WatchtowerBundle>,
+	watch_key: Option<WatchKey>,
+	asp: rpc::ArkServiceClient<tonic::transport::Channel>,
+	asp_pubkey: PublicKey,
+}
+
+impl WatchOnlyWallet {
+	/// Whether the wallet in the given datadir is watch-only.
+	pub fn is_watch_only(datadir: &Path) -> bool {
+		datadir.join(WATCH_BUNDLE_FILE).exists() || datadir.join(WATCH_KEY_FILE).exists()
+	}
+
+	/// Create a new watch-only wallet for the vtxos of the watch key and
+	/// the vtxos in the bundle.
+	pub async fn create(
+		datadir: &Path,
+		config: Config,
+		watch_key: Option<WatchKey>,
+		bundle: Option<WatchtowerBundle>,
+	) -> anyhow::Result<WatchOnlyWallet> {
+		info!("Creating new watch-only bark wallet at {}", datadir.display());
+		ensure!(watch_key.is_some() || bundle.is_some(), "need a watch key or a bundle to watch");
+		if let Some(ref key) = watch_key {
+			ensure!(key.network == config.network,
+				"watch key is for network {}, but the wallet is for {}", key.network, config.network,
+			);
+		}
+		if let Some(ref bundle) = bundle {
+			ensure!(bundle.network == config.network,
+				"bundle is for network {}, but the wallet is for {}", bundle.network, config.network,
+			);
+		}
+
+		// create dir if not exit, but check that it's empty
+		fs::create_dir_all(&datadir).context("can't create dir")?;
+		if fs::read_dir(&datadir).context("can't read dir")?.next().is_some() {
+			bail!("dir is not empty");
+		}
+
+		Wallet::write_config(&config, datadir).context("failed to write config file")?;
+		if let Some(key) = watch_key {
+			fs::write(datadir.join(WATCH_KEY_FILE), key.encode())
+				.context("failed to write watch key")?;
+		}
+		if let Some(bundle) = bundle {
+			fs::write(datadir.join(WATCH_BUNDLE_FILE), bundle.encode())
+				.context("failed to write watchtower bundle")?;
+		}
+
+		WatchOnlyWallet::open(datadir).await
+	}
+
+	/// Open an existing watch-only wallet.
+	pub async fn open(datadir: &Path) -> anyhow::Result<WatchOnlyWallet> {
+		info!("Opening watch-only bark wallet at {}", datadir.display());
+		let config = Wallet::read_config(datadir)?;
+		let bundle_path = datadir.join(WATCH_BUNDLE_FILE);
+		let bundle = if bundle_path.exists() {
+			let bytes = fs::read(bundle_path).context("failed to read watchtower bundle")?;
+			Some(WatchtowerBundle::import(&bytes)?)
+		} else {
+			None
+		};
+		let key_path = datadir.join(WATCH_KEY_FILE);
+		let watch_key = if key_path.exists() {
+			let bytes = fs::read(key_path).context("failed to read watch key")?;
+			Some(WatchKey::import(&bytes)?)
+		} else {
+			None
+		};
+		let (asp, info) = Wallet::connect_asp(&config).await?;
+		Ok(WatchOnlyWallet { config, bundle, watch_key, asp, asp_pubkey: info.asp_pubkey })
+	}
+
+	pub fn config(&self) -> &Config {
+		&self.config
+	}
+
+	/// The unspent round vtxos of the pubkeys of the watch key.
+	async fn watch_key_vtxos(&mut self) -> anyhow::Result<Vec<Vtxo>> {
+		let key = match self.watch_key {
+			Some(ref k) => k,
+			None => return Ok(Vec::new()),
+		};
+		let watch_keypair = key.watch_keypair();
+
+		let mut ret = Vec::new();
+		for p in &key.pubkeys {
+			let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+				.as_millis() as u64;
+			let msg = rpc::vtxos_for_pubkey_message(self.asp_pubkey, p.pubkey, timestamp_ms);
+			let req = rpc::VtxosForPubkeyRequest {
+				pubkey: p.pubkey.serialize().to_vec(),
+				timestamp_ms,
+				signature: SECP.sign_schnorr(&msg, &watch_keypair).serialize().to_vec(),
+				delegation: Some(rpc::WatchDelegation {
+					watch_pubkey: watch_keypair.public_key().serialize().to_vec(),
+					signature: p.delegation.serialize().to_vec(),
+				}),
+			};
+			let resp = self.asp.get_vtxos_for_pubkey(req).await
+				.context("error fetching vtxos from asp")?;
+			for bytes in resp.into_inner().vtxos {
+				let vtxo = Vtxo::decode(&bytes).context("invalid vtxo from asp")?;
+				ensure!(matches!(vtxo, Vtxo::Round { .. }), "asp sent a non-round vtxo");
+				ensure!(vtxo.spec().user_pubkey == p.pubkey, "asp sent a vtxo of another pubkey");
+				ensure!(vtxo.spec().asp_pubkey == self.asp_pubkey,
+					"asp sent a vtxo with another asp pubkey",
+				);
+				ret.push(vtxo);
+			}
+		}
+		Ok(ret)
+	}
+
+	/// The watched vtxos that the ASP doesn't know to be spent.
+	pub async fn vtxos(&mut self) -> anyhow::Result<Vec<WatchtowerVtxo>> {
+		let mut ret = self.watch_key_vtxos().await?.iter()
+			.map(WatchtowerVtxo::new)
+			.collect::<Vec<_>>();
+		let seen = ret.iter().map(|v| v.id).collect::<HashSet<_>>();
+
+		let bundle_vtxos: &[WatchtowerVtxo] = match self.bundle {
+			Some(ref b) => &b.vtxos[..],
+			None => &[],
+		};
+		for vtxo in bundle_vtxos {
+			if seen.contains(&vtxo.id) {
+				continue;
+			}
+			let res = self.asp.get_vtxo_status(rpc::VtxoStatusRequest {
+				vtxo_id: vtxo.id.bytes().to_vec(),
+				vtxo: None,
+			}).await.context("vtxo status request failed")?.into_inner();
+			match rpc::VtxoStatus::try_from(res.status) {
+				Ok(rpc::VtxoStatus::Forfeited) | Ok(rpc::VtxoStatus::Swept) => {
+					trace!("Watched vtxo {} is spent", vtxo.id);
+				},
+				// Vtxos that didn't come from a round are unknown to the ASP.
+				_ => ret.push(vtxo.clone()),
+			}
+		}
+		Ok(ret)
+	}
+
+	/// The total amount of the unspent vtxos.
+	pub async fn balance(&mut self) -> anyhow::Result<Amount> {
+		Ok(self.vtxos().await?.iter().map(|v| v.claim.spec.amount).sum())
+	}
+}
@@ -0,0 +1,132 @@
+
+//! Export of exit data for third-party watchtowers.
+//!
+//! A watchtower bundle contains everything needed to monitor our vtxos and
+//! to broadcast their exit txs should the ASP misbehave. All exit txs are
+//! already fully signed, so the watchtower doesn't need any of our keys.
+//! Claiming the exited funds after the exit delta remains our own job.
+
+use std::io;
+
+use anyhow::Context;
+use bitcoin::{Network, ScriptBuf, Transaction};
+use bitcoin::taproot::{ControlBlock, LeafVersion};
+
+use ark::{Vtxo, VtxoId};
+
+use crate::Wallet;
+use crate::exit::ClaimInput;
+
+
+/// The current version of the watchtower bundle format.
+pub const WATCHTOWER_BUNDLE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchtowerVtxo {
+	pub id: VtxoId,
+	/// The vtxo output and its spec.
+	pub claim: ClaimInput,
+	/// The exit clause script of the vtxo output.
+	pub exit_script: ScriptBuf,
+	/// The serialized taproot control block for the exit clause.
+	pub control_block: Vec<u8>,
+	/// The exit txs to broadcast, in order, to put the vtxo onchain.
+	pub exit_txs: Vec<Transaction>,
+}
+
+impl WatchtowerVtxo {
+	pub(crate) fn new(vtxo: &Vtxo) -> WatchtowerVtxo {
+		let spec = vtxo.spec();
+		let exit_script = spec.exit_clause();
+		let control_block = spec.exit_taproot()
+			.control_block(&(exit_script.clone(), LeafVersion::TapScript))
+			.expect("exit clause is in the taproot");
+		let mut exit_txs = Vec::new();
+		vtxo.collect_exit_txs(&mut exit_txs);
+		WatchtowerVtxo {
+			id: vtxo.id(),
+			claim: ClaimInput {
+				utxo: vtxo.point(),
+				spec: spec.clone(),
+			},
+			exit_script,
+			control_block: control_block.serialize(),
+			exit_txs,
+		}
+	}
+
+	/// The height at which the ASP can sweep the vtxo.
+	pub fn expiry_height(&self) -> u32 {
+		self.claim.spec.expiry_height
+	}
+
+	/// Check that the exit data is internally consistent.
+	pub fn validate(&self) -> anyhow::Result<()> {
+		let spec = &self.claim.spec;
+		ensure!(self.exit_script == spec.exit_clause(), "exit script doesn't match vtxo spec");
+
+		let cb = ControlBlock::decode(&self.control_block).context("invalid control block")?;
+		let taproot = spec.exit_taproot();
+		ensure!(cb.verify_taproot_commitment(
+			&crate::SECP, taproot.output_key().to_inner(), &self.exit_script,
+		), "control block doesn't commit to exit script");
+
+		let last = self.exit_txs.last().context("no exit txs")?;
+		ensure!(last.compute_txid() == self.claim.utxo.txid, "last exit tx doesn't create vtxo");
+		let txout = last.output.get(self.claim.utxo.vout as usize)
+			.context("vtxo output missing from exit tx")?;
+		ensure!(txout.script_pubkey == spec.exit_spk(), "vtxo output has wrong scriptPubkey");
+		ensure!(txout.value == spec.amount, "vtxo output has wrong amount");
+
+		for pair in self.exit_txs.windows(2) {
+			let parent = pair[0].compute_txid();
+			ensure!(pair[1].input.iter().any(|i| i.previous_output.txid == parent),
+				"exit tx {} doesn't spend its predecessor {}", pair[1].compute_txid(), parent,
+			);
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchtowerBundle {
+	pub version: u8,
+	pub network: Network,
+	pub vtxos: Vec<WatchtowerVtxo>,
+}
+
+impl WatchtowerBundle {
+	pub fn encode(&self) -> Vec<u8> {
+		let mut buf = Vec::new();
+		ciborium::into_writer(self, &mut buf).unwrap();
+		buf
+	}
+
+	pub fn decode(bytes: &[u8]) -> Result<Self, ciborium::de::Error<io::Error>> {
+		ciborium::from_reader(bytes)
+	}
+
+	/// Decode a bundle and check all the vtxo exit data in it.
+	pub fn import(bytes: &[u8]) -> anyhow::Result<Self> {
+		let ret = WatchtowerBundle::decode(bytes).context("invalid watchtower bundle")?;
+		ensure!(ret.version == WATCHTOWER_BUNDLE_VERSION,
+			"unsupported watchtower bundle version: {}", ret.version,
+		);
+		for vtxo in &ret.vtxos {
+			vtxo.validate().with_context(|| format!("invalid exit data for vtxo {}", vtxo.id))?;
+		}
+		Ok(ret)
+	}
+}
+
+impl Wallet {
+	/// Create a watchtower bundle for all vtxos in the wallet.
+	pub fn export_watchtower(&self) -> anyhow::Result<WatchtowerBundle> {
+		let vtxos = self.db.get_all_vtxos()?;
+		Ok(WatchtowerBundle {
+			version: WATCHTOWER_BUNDLE_VERSION,
+			network: self.config.network,
+			vtxos: vtxos.iter().map(WatchtowerVtxo::new).collect(),
+		})
+	}
+}